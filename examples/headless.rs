@@ -0,0 +1,57 @@
+// plays a chart for 10 seconds without a window or audio: the clock is stepped by hand and the notes'
+// screen positions are printed once a second
+//
+//     cargo run --example headless [chart.qua]
+
+use std::path::PathBuf;
+use vsrg_renderer::{
+    initialize_map,
+    map::Map,
+    render::{set_reference_positions, update_frame, FrameState},
+    trace::visible_notes,
+};
+
+const FRAME_RATE: f64 = 60.0;
+const DURATION: f64 = 10_000.0; // ms
+const VIEW_HEIGHT: f64 = 1080.0;
+
+fn main() -> anyhow::Result<()> {
+    let path = std::env::args_os()
+        .nth(1)
+        .map_or_else(|| PathBuf::from("golden/charts/sv_reversal.qua"), PathBuf::from);
+    let mut map = Map::from_file(&path)?;
+    map.rate = 1.0;
+    map.length = DURATION; // there's no audio to take the length from
+    map.mods.autoplay = true; // so notes are hit and LNs held on the way
+    // no receptor texture, nothing here needs macroquad running
+    let field_positions = set_reference_positions(None);
+    initialize_map(&mut map, &field_positions)?;
+
+    let frame_length = 1000.0 / FRAME_RATE;
+    let mut notes = Vec::new();
+    for frame in 0..=(DURATION / frame_length) as u32 {
+        // counted from the frame index, so the clock doesn't drift from adding up frame lengths
+        map.time = f64::from(frame) * frame_length;
+        update_frame(&mut FrameState {
+            map: &mut map,
+            compare_map: None,
+            chart_diff: &[],
+            field_positions: &field_positions,
+            alpha: 1.0,
+            view_height: VIEW_HEIGHT,
+            background: None,
+        })?;
+        if frame % FRAME_RATE as u32 != 0 {
+            continue;
+        }
+        visible_notes(&map, field_positions.hold_hit_position_y, VIEW_HEIGHT, &mut notes);
+        let positions: Vec<String> = notes.iter().map(|note| format!("lane {} at {:.0}", note.lane + 1, note.y)).collect();
+        println!(
+            "{:>5.0} ms  combo {:>4}  {}",
+            map.time,
+            map.combo,
+            if positions.is_empty() { "no notes on screen".to_string() } else { positions.join(", ") }
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,91 @@
+#ifndef VSRG_RENDERER_H
+#define VSRG_RENDERER_H
+
+/* Generated from src/ffi.rs with cbindgen, don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * A loaded chart and its play state.
+ */
+typedef struct VsrgMap VsrgMap;
+
+/**
+ * A note on screen.
+ */
+typedef struct VsrgNote {
+  /**
+   * Index in the chart's hit objects.
+   */
+  uint32_t index;
+  /**
+   * Screen y of the note's head, from the top of a 1080 px view.
+   */
+  float y;
+  /**
+   * 0-indexed column it's drawn in (after mirror).
+   */
+  uint8_t lane;
+  /**
+   * 1 while it's an LN being held.
+   */
+  uint8_t held;
+  /**
+   * Beat snap color, an index into 1/1, 1/2, 1/3, 1/4, 1/6, 1/8, 1/12, 1/16, 1/48.
+   */
+  uint8_t color_index;
+} VsrgNote;
+
+/**
+ * Loads a .qua and gets it ready to play at 1x. Returns null if it can't be loaded.
+ *
+ * # Safety
+ *
+ * `path` has to be a valid, nul-terminated string.
+ */
+VsrgMap *vsrg_load_map(const char *path);
+
+/**
+ * Moves the chart to `time_ms`: positions are updated and notes passed unpressed are missed.
+ * Returns 0, or -1 if the handle is null or the update failed (the reason is logged).
+ *
+ * # Safety
+ *
+ * `handle` has to come from `vsrg_load_map` and not be freed yet.
+ */
+int32_t vsrg_update(VsrgMap *handle, double time_ms);
+
+/**
+ * Writes up to `capacity` of the notes on screen to `out`, returns how many there are in all
+ * (which can be more than were written).
+ *
+ * # Safety
+ *
+ * `handle` has to come from `vsrg_load_map`, `out` has to have room for `capacity` notes.
+ */
+size_t vsrg_visible_notes(VsrgMap *handle, VsrgNote *out, size_t capacity);
+
+/**
+ * Presses or releases a 0-indexed key at `time_ms`. Returns the judgement it made
+ * (0 marvelous, 1 perfect, 2 great, 3 good, 4 okay, 5 miss) or -1 for none.
+ *
+ * # Safety
+ *
+ * `handle` has to come from `vsrg_load_map` and not be freed yet.
+ */
+int32_t vsrg_key_event(VsrgMap *handle, int32_t lane, bool pressed, double time_ms);
+
+/**
+ * Frees a map from `vsrg_load_map`. Null is ignored.
+ *
+ * # Safety
+ *
+ * `handle` has to come from `vsrg_load_map` and not be used again afterwards.
+ */
+void vsrg_free(VsrgMap *handle);
+
+#endif  /* VSRG_RENDERER_H */
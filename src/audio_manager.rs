@@ -1,15 +1,110 @@
+use crate::ducking::{Ducker, DuckingSettings};
 use crate::logger;
-use rodio::{source::Source as _, Decoder, OutputStream, OutputStreamHandle, Sink};
-use std::{fs::File, io::BufReader, path::PathBuf, time::Instant};
+use crate::memory_budget::{MemoryBudget, BYTES_PER_MB, DEFAULT_MEMORY_CAP_MB};
+use rodio::{
+    cpal::{traits::HostTrait as _, SupportedBufferSize},
+    source::{Buffered, Source as _},
+    Decoder, DeviceTrait as _, OutputStream, OutputStreamHandle, Sink,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 const INITIAL_AUDIO_VOLUME: f64 = 0.03;
 const INITIAL_AUDIO_RATE: f64 = 1.0;
+const DEFAULT_OUTPUT_LATENCY: f64 = 0.0; // ms, when the device doesn't report it and none is configured
+const FADE_DURATION: f64 = 15.0; // ms, volume ramp when starting, stopping or replacing the sink
+// how far (ms) the wall clock position may be from the sink's before it's pulled back
+// the sink only reports in audio buffer steps, so it's too jumpy to use directly
+const SINK_SYNC_TOLERANCE: f64 = 20.0;
+
+// what the audio output is doing, for showing without reaching into the sink
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+    Stopped, // nothing loaded, or played to the end
+}
+
+// a linear volume change over a fixed time, so sinks fade in and out instead of popping
+#[derive(Debug, Clone, Copy)]
+pub struct VolumeRamp {
+    pub from: f64,
+    pub to: f64,
+    pub duration: f64, // ms
+}
+
+impl VolumeRamp {
+    pub fn volume_at(&self, elapsed: f64) -> f64 {
+        // volume after elapsed ms, holding the target once the ramp is over
+        if elapsed >= self.duration || self.duration <= 0.0 {
+            return self.to;
+        }
+        self.from + (self.to - self.from) * (elapsed / self.duration).max(0.0)
+    }
+
+    pub fn is_done(&self, elapsed: f64) -> bool {
+        elapsed >= self.duration
+    }
+}
+
+type Sample = Buffered<Decoder<BufReader<File>>>;
+
+// a decoded one-shot sample, with how loud and long it is for ducking
+struct LoadedSample {
+    source: Sample,
+    peak: f64,          // largest sample, 1 for full scale
+    duration: Duration, // real time it plays for
+    bytes: usize,       // size of the decoded buffer
+}
+
+fn load_sample(path: &Path) -> Result<LoadedSample, String> {
+    // decodes the whole sample up front, it's measured and then played from the buffer
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let source = Decoder::new(BufReader::new(file)).map_err(|e| e.to_string())?.buffered();
+    let (channels, sample_rate) = (source.channels(), source.sample_rate());
+    let (peak, count) = source
+        .clone()
+        .fold((0u16, 0u64), |(peak, count), sample| (peak.max(sample.unsigned_abs()), count + 1));
+    let frames = count / u64::from(channels.max(1));
+    Ok(LoadedSample {
+        source,
+        peak: f64::from(peak) / 32768.0,
+        duration: Duration::from_secs_f64(frames as f64 / f64::from(sample_rate.max(1))),
+        bytes: usize::try_from(count).unwrap_or(usize::MAX).saturating_mul(size_of::<i16>()),
+    })
+}
+
+fn output_buffer_latency() -> Option<f64> {
+    // length of one output buffer of the default device in ms
+    // rodio opens the stream with the host's default buffer, so it's only known when the host allows a single size
+    let device = rodio::cpal::default_host().default_output_device()?;
+    let config = device.default_output_config().ok()?;
+    match config.buffer_size() {
+        SupportedBufferSize::Range { min, max } if min == max => {
+            Some(f64::from(*min) / f64::from(config.sample_rate().0) * 1000.0)
+        }
+        _ => None,
+    }
+}
+
+pub fn estimate_output_latency(buffer_latency: Option<f64>, configured: Option<f64>) -> f64 {
+    // the device's buffer latency when known, else the configured value
+    buffer_latency
+        .or(configured)
+        .unwrap_or(DEFAULT_OUTPUT_LATENCY)
+        .max(0.0)
+}
 
 pub struct AudioManager {
     _stream: OutputStream,
     stream_handle: OutputStreamHandle,
-    pub sink: Option<Sink>,
-    pub audio_source_path: Option<PathBuf>,
+    sink: Option<Sink>,
+    audio_source_path: Option<PathBuf>,
     current_error: Option<String>,
 
     // timing related fields
@@ -21,6 +116,24 @@ pub struct AudioManager {
     length: Option<f64>, // length of audio
     rate: f64,           // playback rate
     volume: f64,
+
+    output_latency: f64,                             // estimated ms between mixing a sound and hearing it
+    samples: HashMap<PathBuf, Option<LoadedSample>>, // decoded one-shot samples, None if it failed to load
+    memory: MemoryBudget<PathBuf>,                   // the samples' decoded size, least recently played dropped over the cap
+
+    ducker: Option<Ducker>,      // lowers the music under loud keysounds, None when off
+    voices: Vec<(f64, Instant)>, // amplitude of each sample playing and when it ends
+    duck_gain: f64,              // what the ducker multiplies the music volume by right now
+    last_update: Option<Instant>,
+
+    sink_ramp: Option<(VolumeRamp, Instant)>, // fade on the current sink, applied by update()
+    pause_after_ramp: bool,                   // pause the current sink once its fade out is over
+    fading_sinks: Vec<(Sink, VolumeRamp, Instant)>, // replaced sinks fading out before they're dropped
+
+    // the sink's own position, which doesn't drift from the audio like the wall clock does
+    sink_base_ms: f64,                // song time at sink_base_pos
+    sink_base_pos: Duration,          // sink position at the last seek/restart/rate change
+    sink_position_used: Option<bool>, // whether the sink reports its position, once known
 }
 
 impl AudioManager {
@@ -52,9 +165,268 @@ impl AudioManager {
             length: None,
             rate: INITIAL_AUDIO_RATE,
             volume: INITIAL_AUDIO_VOLUME,
+            output_latency: DEFAULT_OUTPUT_LATENCY,
+            samples: HashMap::new(),
+            memory: MemoryBudget::from_mb(DEFAULT_MEMORY_CAP_MB),
+            ducker: None,
+            voices: Vec::new(),
+            duck_gain: 1.0,
+            last_update: None,
+            sink_ramp: None,
+            pause_after_ramp: false,
+            fading_sinks: Vec::new(),
+            sink_base_ms: 0.0,
+            sink_base_pos: Duration::ZERO,
+            sink_position_used: None,
         })
     }
 
+    // advances the volume fades and keeps the wall clock in sync with the sink, call once per frame
+    pub fn update(&mut self) {
+        self.sync_to_sink();
+        self.update_ducking();
+
+        if let Some((ramp, start)) = self.sink_ramp {
+            let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+            if let Some(s) = self.sink.as_ref() {
+                s.set_volume(ramp.volume_at(elapsed) as f32);
+                if ramp.is_done(elapsed) && self.pause_after_ramp {
+                    s.pause();
+                }
+            }
+            if ramp.is_done(elapsed) {
+                if self.pause_after_ramp {
+                    // the audio played on through the fade, so the clock stops only now
+                    self.bank_play_time();
+                    logger::info(&format!(
+                        "Audiomanager: Audio paused. Accumulated time: {} ms",
+                        self.accumulated_play_time_ms
+                    ));
+                }
+                self.sink_ramp = None;
+                self.pause_after_ramp = false;
+            }
+        }
+
+        self.fading_sinks.retain(|(sink, ramp, start)| {
+            let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+            sink.set_volume(ramp.volume_at(elapsed) as f32);
+            // dropping a sink stops it
+            !ramp.is_done(elapsed)
+        });
+    }
+
+    fn music_volume(&self) -> f64 {
+        // the user's volume, lowered by the ducker
+        self.volume * self.duck_gain
+    }
+
+    fn update_ducking(&mut self) {
+        let now = Instant::now();
+        let elapsed = self.last_update.map_or(0.0, |last| (now - last).as_secs_f64() * 1000.0);
+        self.last_update = Some(now);
+        let Some(ducker) = self.ducker.as_mut() else {
+            return;
+        };
+        self.voices.retain(|&(_, end)| end > now);
+        let amplitude = self.voices.iter().map(|&(amplitude, _)| amplitude).sum();
+        self.duck_gain = ducker.update(amplitude, elapsed);
+        // fades set the volume themselves and head for the ducked one
+        if self.sink_ramp.is_none() {
+            if let Some(s) = self.sink.as_ref().filter(|s| !s.is_paused()) {
+                s.set_volume(self.music_volume() as f32);
+            }
+        }
+    }
+
+    // turns ducking of the music under loud keysounds on with these settings, or off
+    pub fn set_ducking(&mut self, settings: Option<DuckingSettings>) {
+        self.ducker = settings.map(Ducker::new);
+        self.voices.clear();
+        self.duck_gain = 1.0;
+        if let Some(settings) = settings {
+            logger::info(&format!(
+                "Audiomanager: Ducking the music by up to {} dB above {} keysound amplitude",
+                settings.max_reduction, settings.threshold
+            ));
+        }
+    }
+
+    fn rebase_sink_position(&mut self, song_time_ms: f64) {
+        // song_time_ms is where the sink's current position is in the song
+        self.sink_base_ms = song_time_ms;
+        self.sink_base_pos = self.sink.as_ref().map_or(Duration::ZERO, Sink::get_pos);
+    }
+
+    fn sink_song_time_ms(&self) -> Option<f64> {
+        // song time from the sink's position; the sink plays sped up, so its position is in real time
+        let pos = self.sink.as_ref()?.get_pos();
+        let played = pos.checked_sub(self.sink_base_pos)?;
+        Some(self.sink_base_ms + played.as_secs_f64() * 1000.0 * self.rate)
+    }
+
+    fn sink_position_ms(&self) -> Option<f64> {
+        // only once the sink is known to report its position, and while it still has audio
+        // (charts can run past the end of their audio, the wall clock keeps going there)
+        if self.sink_position_used != Some(true) || self.sink.as_ref().is_none_or(Sink::empty) {
+            return None;
+        }
+        self.sink_song_time_ms()
+    }
+
+    const fn clock_running(&self) -> bool {
+        // playing, or fading out to pause with the audio still going
+        !self.is_audio_engine_paused || self.pause_after_ramp
+    }
+
+    fn bank_play_time(&mut self) {
+        // adds the current play segment to the accumulated time and ends it
+        if let Some(start_instant) = self.playback_start_instant.take() {
+            self.accumulated_play_time_ms +=
+                start_instant.elapsed().as_secs_f64() * 1000f64 * self.playback_start_rate;
+        }
+    }
+
+    fn wall_clock_position_ms(&self) -> f64 {
+        let mut current_time = self.accumulated_play_time_ms;
+        if self.clock_running() {
+            if let Some(start_instant) = self.playback_start_instant {
+                current_time = self.accumulated_play_time_ms
+                    + (start_instant.elapsed().as_secs_f64() * 1000f64 * self.playback_start_rate);
+            }
+        }
+        current_time
+    }
+
+    fn sync_to_sink(&mut self) {
+        // pulls the wall clock model back to the sink's position when they drift apart
+        if !self.is_playing() {
+            return;
+        }
+        let Some(start_instant) = self.playback_start_instant else {
+            return;
+        };
+        let pos = self.sink.as_ref().map_or(Duration::ZERO, Sink::get_pos);
+        if self.sink_position_used.is_none() {
+            // a sink that has played for a while without moving its position doesn't report it
+            if pos > self.sink_base_pos {
+                self.sink_position_used = Some(true);
+                logger::info("Audiomanager: Using the sink's playback position.");
+            } else if start_instant.elapsed().as_secs_f64() * 1000.0 > FADE_DURATION * 20.0 {
+                self.sink_position_used = Some(false);
+                logger::warning("Audiomanager: Sink doesn't report its position, using the wall clock.");
+            }
+        }
+        let Some(sink_position) = self.sink_position_ms() else {
+            return;
+        };
+        let drift = self.wall_clock_position_ms() - sink_position;
+        if drift.abs() > SINK_SYNC_TOLERANCE {
+            self.accumulated_play_time_ms -= drift;
+        }
+    }
+
+    fn fade_sink(&mut self, to: f64) {
+        // ramps the current sink from its current volume
+        let from = self.sink.as_ref().map_or(0.0, |s| f64::from(s.volume()));
+        self.sink_ramp = Some((VolumeRamp { from, to, duration: FADE_DURATION }, Instant::now()));
+    }
+
+    fn retire_sink(&mut self) {
+        // fades the current sink out instead of cutting it off
+        self.sink_ramp = None;
+        self.pause_after_ramp = false;
+        if let Some(old) = self.sink.take() {
+            if !old.is_paused() {
+                let ramp = VolumeRamp {
+                    from: f64::from(old.volume()),
+                    to: 0.0,
+                    duration: FADE_DURATION,
+                };
+                self.fading_sinks.push((old, ramp, Instant::now()));
+            }
+        }
+    }
+
+    // estimates the output latency, falling back to the configured one (ms)
+    pub fn set_output_latency(&mut self, configured: Option<f64>) {
+        let buffer_latency = output_buffer_latency();
+        self.output_latency = estimate_output_latency(buffer_latency, configured);
+        logger::info(&format!(
+            "Audiomanager: Output latency {} ms ({})",
+            self.output_latency,
+            if buffer_latency.is_some() { "from device" } else { "configured" }
+        ));
+    }
+
+    // returns the estimated output latency in ms of real time
+    pub const fn output_latency_ms(&self) -> f64 {
+        self.output_latency
+    }
+
+    pub fn set_memory_cap(&mut self, cap_mb: f64) {
+        self.memory.set_cap_mb(cap_mb);
+        self.evict_samples();
+    }
+
+    pub fn memory_usage(&self) -> (usize, usize, usize) {
+        // bytes of decoded samples held, the cap, and how many samples that is
+        (self.memory.used(), self.memory.cap(), self.memory.len())
+    }
+
+    fn evict_samples(&mut self) {
+        // drops the least recently played samples while over the memory cap, they're decoded again if played
+        let evicted = self.memory.evict();
+        if evicted.is_empty() {
+            return;
+        }
+        for path in &evicted {
+            self.samples.remove(path);
+        }
+        logger::info(&format!(
+            "Dropped {} decoded samples to stay under the {:.0} MB audio memory cap",
+            evicted.len(),
+            self.memory.cap() as f64 / BYTES_PER_MB
+        ));
+    }
+
+    // plays a one-shot sample on top of the music, decoded once and cached (up to the memory cap)
+    pub fn play_sample(&mut self, path: &Path, volume: f64) {
+        if self.samples.contains_key(path) {
+            self.memory.touch(&path.to_path_buf());
+        } else {
+            let sample = match load_sample(path) {
+                Ok(sample) => {
+                    self.memory.track(path.to_path_buf(), sample.bytes);
+                    Some(sample)
+                }
+                Err(e) => {
+                    logger::error(&format!(
+                        "Audiomanager: Failed to load sample {:?}: {e}",
+                        path.display()
+                    ));
+                    None
+                }
+            };
+            self.samples.insert(path.to_path_buf(), sample);
+            self.evict_samples();
+        }
+        let Some(Some(sample)) = self.samples.get(path) else {
+            return;
+        };
+        if self.ducker.is_some() {
+            self.voices.push((sample.peak * volume, Instant::now() + sample.duration));
+        }
+        let source = sample
+            .source
+            .clone()
+            .amplify((self.volume * volume) as f32)
+            .convert_samples();
+        if let Err(e) = self.stream_handle.play_raw(source) {
+            logger::error(&format!("Audiomanager: Failed to play sample: {e}"));
+        }
+    }
+
     // sets the audio source path and verifies if the audio file is decodable
     pub fn set_audio_path(&mut self, path: Option<PathBuf>) {
         self.audio_source_path = path;
@@ -108,6 +480,7 @@ impl AudioManager {
                             }
                             s.append(source);
                             self.current_error = None;
+                            self.rebase_sink_position(0.0);
                             logger::info("Audiomanager: Audio loaded and appended to sink.");
                             return true;
                         }
@@ -141,8 +514,11 @@ impl AudioManager {
         let need_load = self.sink.as_ref().is_some_and(rodio::Sink::empty);
 
         if let Some(s) = self.sink.as_mut() {
-            if s.is_paused() || need_load {
-                // play if paused or if empty (needs loading)
+            if s.is_paused() || need_load || self.pause_after_ramp {
+                // play if paused, fading out to pause, or empty (needs loading)
+                if s.is_paused() {
+                    s.set_volume(0.0);
+                }
                 if need_load && !self.load_and_append_to_sink() {
                     self.is_audio_engine_paused = true; // ensure state reflects failure
                     return;
@@ -151,8 +527,13 @@ impl AudioManager {
                 if let Some(sink_ref) = self.sink.as_mut() {
                     sink_ref.play();
                 }
-                self.playback_start_instant = Some(Instant::now());
-                self.playback_start_rate = self.rate;
+                self.pause_after_ramp = false;
+                self.fade_sink(self.music_volume());
+                // resuming during a fade out carries on with the segment that's still running
+                if self.playback_start_instant.is_none() {
+                    self.playback_start_instant = Some(Instant::now());
+                    self.playback_start_rate = self.rate;
+                }
                 self.is_audio_engine_paused = false;
                 logger::info("Audiomanager: Audio playing/resumed.");
             }
@@ -163,20 +544,14 @@ impl AudioManager {
         }
     }
 
-    // pauses playback and records the elapsed time
+    // pauses playback, the elapsed time is recorded once the fade out is over
     pub fn pause(&mut self) {
         if let Some(s) = self.sink.as_mut() {
-            if !s.is_paused() {
-                s.pause();
-                if let Some(start_instant) = self.playback_start_instant.take() {
-                    self.accumulated_play_time_ms +=
-                        start_instant.elapsed().as_secs_f64() * 1000f64 * self.playback_start_rate;
-                }
+            if !s.is_paused() && !self.pause_after_ramp {
+                // fades out first, update() pauses the sink when it's silent
+                self.pause_after_ramp = true;
+                self.fade_sink(0.0);
                 self.is_audio_engine_paused = true;
-                logger::info(&format!(
-                    "Audiomanager: Audio paused. Accumulated time: {} ms",
-                    self.accumulated_play_time_ms
-                ));
             }
         }
     }
@@ -188,24 +563,21 @@ impl AudioManager {
         self.playback_start_rate = self.rate;
         self.is_audio_engine_paused = true; // will be set to false by play() if successful
 
-        if let Some(s) = self.sink.as_mut() {
-            s.stop();
-            s.clear();
-            logger::info("Audiomanager: Sink stopped and cleared for restart.");
-        } else {
-            match Sink::try_new(&self.stream_handle) {
-                Ok(new_sink) => {
-                    new_sink.set_volume(self.volume as f32);
-                    new_sink.set_speed(self.rate as f32);
-                    new_sink.pause();
-                    self.sink = Some(new_sink);
-                    logger::info("Audiomanager: New sink created on restart.");
-                }
-                Err(e) => {
-                    let err_msg = format!("Audiomanager: Failed to create sink on restart: {e}");
-                    logger::error(&err_msg);
-                    self.current_error = Some(err_msg);
-                }
+        // the old sink fades out while a fresh one is created
+        self.retire_sink();
+        match Sink::try_new(&self.stream_handle) {
+            Ok(new_sink) => {
+                new_sink.set_volume(0.0); // faded in by play()
+                new_sink.set_speed(self.rate as f32);
+                new_sink.pause();
+                self.sink = Some(new_sink);
+                self.rebase_sink_position(0.0);
+                logger::info("Audiomanager: New sink created on restart.");
+            }
+            Err(e) => {
+                let err_msg = format!("Audiomanager: Failed to create sink on restart: {e}");
+                logger::error(&err_msg);
+                self.current_error = Some(err_msg);
             }
         }
         // after restart, play() will handle loading and starting
@@ -227,13 +599,12 @@ impl AudioManager {
         };
         self.playback_start_rate = self.rate;
 
-        if let Some(old) = self.sink.take() {
-            old.stop();
-        }
+        self.retire_sink();
 
         match Sink::try_new(&self.stream_handle) {
             Ok(new_sink) => {
-                new_sink.set_volume(self.volume as f32);
+                // silent until faded in, so the new decoder doesn't start with a pop
+                new_sink.set_volume(0.0);
                 new_sink.set_speed(self.rate as f32);
 
                 if let Some(path) = &self.audio_source_path {
@@ -252,6 +623,12 @@ impl AudioManager {
                                     self.is_audio_engine_paused = true;
                                 }
                                 self.sink = Some(new_sink);
+                                self.rebase_sink_position(target_ms);
+                                if was_playing {
+                                    // opening the decoder takes a while, the audio only starts now
+                                    self.playback_start_instant = Some(Instant::now());
+                                    self.fade_sink(self.music_volume());
+                                }
                                 self.current_error = None;
                             }
                             Err(e) => {
@@ -286,19 +663,18 @@ impl AudioManager {
         }
     }
 
-    // returns the current playback time in milliseconds
-    pub fn get_current_song_time_ms(&self) -> f64 {
-        let mut current_time = self.accumulated_play_time_ms;
-        if !self.is_audio_engine_paused {
-            if let Some(start_instant) = self.playback_start_instant {
-                current_time = self.accumulated_play_time_ms
-                    + (start_instant.elapsed().as_secs_f64() * 1000f64 * self.playback_start_rate);
-            }
+    // returns the current playback time in milliseconds: the wall clock model, kept within
+    // SINK_SYNC_TOLERANCE of the sink's own position when it reports one
+    // not clamped to the audio length, so charts that run past the end of their audio keep going
+    pub fn current_position_ms(&self) -> f64 {
+        let wall_clock = self.wall_clock_position_ms();
+        match self.sink_position_ms() {
+            Some(sink_position) if self.is_playing() => wall_clock.clamp(
+                sink_position - SINK_SYNC_TOLERANCE,
+                sink_position + SINK_SYNC_TOLERANCE,
+            ),
+            _ => wall_clock,
         }
-        // clamp time to total duration if available
-        self.length.map_or(current_time, |total_duration| {
-            current_time.min(total_duration)
-        })
     }
 
     // returns whether the audio is currently playing
@@ -310,6 +686,21 @@ impl AudioManager {
                 .is_some_and(|s| !s.empty() && !s.is_paused())
     }
 
+    pub fn state(&self) -> PlaybackState {
+        if self.is_playing() {
+            PlaybackState::Playing
+        } else if self.sink.as_ref().is_some_and(Sink::is_paused) {
+            PlaybackState::Paused
+        } else {
+            PlaybackState::Stopped
+        }
+    }
+
+    // returns the audio file being played, if one was found
+    pub fn audio_path(&self) -> Option<&Path> {
+        self.audio_source_path.as_deref()
+    }
+
     // returns the duration of the audio file in milliseconds
     pub const fn get_total_duration_ms(&self) -> Option<f64> {
         self.length
@@ -318,8 +709,16 @@ impl AudioManager {
     // sets the volume of the audio playback
     pub fn set_volume(&mut self, volume: f64) {
         self.volume = volume.clamp(0.0, 1.5); // clamp volume
-        if let Some(s) = self.sink.as_mut() {
-            s.set_volume(self.volume as f32);
+        let music_volume = self.music_volume();
+        match self.sink_ramp.as_mut() {
+            // fading out to pause stays at 0, fading in heads for the new volume
+            Some((ramp, _)) if !self.pause_after_ramp => ramp.to = music_volume,
+            Some(_) => {}
+            None => {
+                if let Some(s) = self.sink.as_mut() {
+                    s.set_volume(music_volume as f32);
+                }
+            }
         }
         logger::info(&format!(
             "Audiomanager: Volume set to {}",
@@ -334,15 +733,16 @@ impl AudioManager {
 
     // sets the playback rate of the audio
     pub fn set_rate(&mut self, rate: f64) {
+        // the sink's position so far was played at the old rate
+        if let Some(sink_song_time) = self.sink_song_time_ms() {
+            self.rebase_sink_position(sink_song_time);
+        }
         self.rate = rate.max(0.1); // prevent rate from being too low or zero
         if let Some(s) = self.sink.as_mut() {
             s.set_speed(self.rate as f32);
         }
-        if !self.is_audio_engine_paused {
-            if let Some(start_instant) = self.playback_start_instant.take() {
-                self.accumulated_play_time_ms +=
-                    start_instant.elapsed().as_secs_f64() * 1000f64 * self.playback_start_rate;
-            }
+        if self.clock_running() {
+            self.bank_play_time();
             self.playback_start_instant = Some(Instant::now());
             self.playback_start_rate = self.rate;
         }
@@ -362,3 +762,156 @@ impl AudioManager {
         self.current_error.as_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync_test::write_click_track;
+    use std::thread;
+
+    const CLOCK_TOLERANCE: f64 = 40.0; // ms, sleeping and the sink's buffering aren't exact
+
+    fn playing_manager(name: &str, use_sink_position: bool) -> Option<AudioManager> {
+        // a manager on the real output with a generated 10 s click track, None without an output device
+        // devices that don't play in real time (a null one) run the sink ahead, so the clock tests use the
+        // wall clock alone and the sink's position is tested on its own
+        let path = std::env::temp_dir().join(format!("vsrg_audio_{name}_{}.wav", std::process::id()));
+        write_click_track(&path, 120.0, 10_000.0).unwrap();
+        let mut audio = match AudioManager::new() {
+            Ok(audio) => audio,
+            Err(e) => {
+                logger::warning(&format!("Skipping the {name} audio test: {e}"));
+                return None;
+            }
+        };
+        audio.set_audio_path(Some(path));
+        assert_eq!(audio.get_total_duration_ms(), Some(10_000.0));
+        if !use_sink_position {
+            audio.sink_position_used = Some(false);
+        }
+        audio.play();
+        Some(audio)
+    }
+
+    fn run_for(audio: &mut AudioManager, ms: u64) {
+        // updates the manager every few ms like the frame loop does
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(ms) {
+            audio.update();
+            thread::sleep(Duration::from_millis(4));
+        }
+        audio.update();
+    }
+
+    fn assert_near(position: f64, expected: f64) {
+        assert!((position - expected).abs() < CLOCK_TOLERANCE, "position {position} ms, expected {expected} ms");
+    }
+
+    #[test]
+    fn position_stops_while_paused() {
+        let Some(mut audio) = playing_manager("pause", false) else { return };
+        run_for(&mut audio, 300);
+        audio.pause();
+        // the clock keeps going through the fade out, then stops
+        run_for(&mut audio, 100);
+        let paused_at = audio.current_position_ms();
+        assert_near(paused_at, 300.0 + FADE_DURATION);
+        run_for(&mut audio, 200);
+        assert_eq!(audio.current_position_ms(), paused_at);
+        assert!(!audio.is_playing());
+
+        audio.play();
+        run_for(&mut audio, 200);
+        assert_near(audio.current_position_ms(), paused_at + 200.0);
+    }
+
+    #[test]
+    fn seek_moves_the_position_within_the_audio() {
+        let Some(mut audio) = playing_manager("seek", false) else { return };
+        run_for(&mut audio, 100);
+        audio.seek_ms(4_000.0);
+        assert_near(audio.current_position_ms(), 4_000.0);
+        run_for(&mut audio, 200);
+        assert_near(audio.current_position_ms(), 4_200.0);
+
+        audio.seek_ms(-500.0);
+        assert_near(audio.current_position_ms(), 0.0);
+        audio.seek_ms(60_000.0);
+        assert_near(audio.current_position_ms(), 10_000.0);
+
+        // seeking while paused moves the position without starting playback
+        let Some(mut paused) = playing_manager("seek_paused", false) else { return };
+        paused.pause();
+        run_for(&mut paused, 100);
+        paused.seek_ms(2_500.0);
+        run_for(&mut paused, 100);
+        assert_eq!(paused.current_position_ms(), 2_500.0);
+        assert!(!paused.is_playing());
+    }
+
+    #[test]
+    fn position_stays_with_the_sink() {
+        let Some(mut audio) = playing_manager("sink", true) else { return };
+        for _ in 0..3 {
+            run_for(&mut audio, 150);
+            if let Some(sink_position) = audio.sink_position_ms() {
+                let drift = audio.current_position_ms() - sink_position;
+                assert!(drift.abs() <= SINK_SYNC_TOLERANCE, "{drift} ms from the sink");
+            }
+            audio.set_rate(1.5);
+        }
+        audio.seek_ms(3_000.0);
+        assert_eq!(audio.sink_song_time_ms(), Some(3_000.0));
+    }
+
+    #[test]
+    fn rate_change_keeps_the_time_played_at_the_old_rate() {
+        let Some(mut audio) = playing_manager("rate", false) else { return };
+        run_for(&mut audio, 300);
+        let before = audio.current_position_ms();
+        audio.set_rate(2.0);
+        assert_near(audio.current_position_ms(), before);
+        run_for(&mut audio, 300);
+        assert_near(audio.current_position_ms(), before + 600.0);
+
+        audio.set_rate(0.5);
+        let slowed = audio.current_position_ms();
+        run_for(&mut audio, 400);
+        assert_near(audio.current_position_ms(), slowed + 200.0);
+    }
+
+    #[test]
+    fn output_latency_prefers_the_device_then_the_config() {
+        assert_eq!(estimate_output_latency(Some(21.3), Some(40.0)), 21.3);
+        assert_eq!(estimate_output_latency(None, Some(40.0)), 40.0);
+        assert_eq!(estimate_output_latency(None, None), DEFAULT_OUTPUT_LATENCY);
+        assert_eq!(estimate_output_latency(None, Some(-5.0)), 0.0);
+    }
+
+    #[test]
+    fn volume_ramp_goes_linearly_to_its_target() {
+        let ramp = VolumeRamp { from: 0.2, to: 1.0, duration: FADE_DURATION };
+        assert_eq!(ramp.volume_at(0.0), 0.2);
+        assert!((ramp.volume_at(FADE_DURATION / 2.0) - 0.6).abs() < 1e-9);
+        assert_eq!(ramp.volume_at(FADE_DURATION), 1.0);
+        assert_eq!(ramp.volume_at(FADE_DURATION * 10.0), 1.0);
+        // a clock that reads slightly before the start holds the starting volume
+        assert_eq!(ramp.volume_at(-1.0), 0.2);
+    }
+
+    #[test]
+    fn volume_ramp_is_done_once_its_duration_has_passed() {
+        let fade_out = VolumeRamp { from: 1.0, to: 0.0, duration: FADE_DURATION };
+        assert!(!fade_out.is_done(FADE_DURATION - 0.1));
+        assert!(fade_out.volume_at(FADE_DURATION - 0.1) > 0.0);
+        assert!(fade_out.is_done(FADE_DURATION));
+        assert_eq!(fade_out.volume_at(FADE_DURATION), 0.0);
+    }
+
+    #[test]
+    fn instant_volume_ramp_jumps_to_its_target() {
+        let ramp = VolumeRamp { from: 1.0, to: 0.5, duration: 0.0 };
+        assert_eq!(ramp.volume_at(0.0), 0.5);
+        assert!(ramp.is_done(0.0));
+    }
+}
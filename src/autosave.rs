@@ -0,0 +1,281 @@
+use crate::logger;
+use crate::map::Mods;
+use crate::replay::{Replay, ReplayEvent};
+use crate::utils::Time;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Sender},
+    thread::JoinHandle,
+};
+
+// song time (ms) between autosaves
+pub const AUTOSAVE_INTERVAL: Time = 10000.0;
+
+// the play's score when it was last autosaved
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScoreSnapshot {
+    pub time: Time, // song time of the snapshot
+    pub accuracy: f64,
+    pub combo: usize,
+    pub judged: usize, // judgements so far
+    pub mods: Mods,    // mods at the time, they can be toggled while playing
+}
+
+// one line of the autosave file, which is only ever appended to
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AutosaveLine {
+    Header { checksum: u64, mods: Mods }, // always the first line
+    Event(ReplayEvent),
+    Score(ScoreSnapshot),
+    Restart, // the play started over, everything before is dropped
+}
+
+pub fn chart_checksum(contents: &[u8]) -> u64 {
+    // fnv-1a, so an autosave can be matched to its chart across builds
+    contents.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+enum Message {
+    Lines(Vec<AutosaveLine>),
+    Discard, // the play ended normally, the file isn't needed
+}
+
+// appends to the autosave file on its own thread, so saving never holds up a frame
+pub struct Autosave {
+    sender: Sender<Message>,
+    thread: JoinHandle<()>,
+}
+
+impl Autosave {
+    pub fn start(path: PathBuf, checksum: u64, mods: Mods) -> Self {
+        let (sender, receiver) = channel();
+        let thread = std::thread::spawn(move || {
+            let write = |out: &mut BufWriter<File>, lines: &[AutosaveLine]| -> Result<()> {
+                for line in lines {
+                    serde_json::to_writer(&mut *out, line)?;
+                    out.write_all(b"\n")?;
+                }
+                // each batch reaches the disk before the next one, so a crash loses at most one interval
+                out.flush()?;
+                Ok(())
+            };
+            let mut out = match File::create(&path) {
+                Ok(file) => BufWriter::new(file),
+                Err(e) => {
+                    logger::warning(&format!("Autosave disabled, couldn't create '{}': {}", path.display(), e));
+                    return;
+                }
+            };
+            if let Err(e) = write(&mut out, &[AutosaveLine::Header { checksum, mods }]) {
+                logger::warning(&format!("Autosave disabled: {e}"));
+                return;
+            }
+            while let Ok(message) = receiver.recv() {
+                match message {
+                    Message::Lines(lines) => {
+                        if let Err(e) = write(&mut out, &lines) {
+                            logger::warning(&format!("Autosave disabled: {e}"));
+                            return;
+                        }
+                    }
+                    Message::Discard => {
+                        drop(out);
+                        if let Err(e) = fs::remove_file(&path) {
+                            logger::warning(&format!("Failed to remove autosave '{}': {}", path.display(), e));
+                        }
+                        return;
+                    }
+                }
+            }
+        });
+        Self { sender, thread }
+    }
+
+    pub fn append(&self, lines: Vec<AutosaveLine>) {
+        // a stopped writer already logged why, so a failed send is ignored
+        let _ = self.sender.send(Message::Lines(lines));
+    }
+
+    pub fn finish(self, discard: bool) {
+        // waits for pending lines; discarding removes the file once they're done
+        if discard {
+            let _ = self.sender.send(Message::Discard);
+        }
+        drop(self.sender);
+        if self.thread.join().is_err() {
+            logger::warning("Autosave thread panicked");
+        }
+    }
+}
+
+// what was saved of a play that never finished
+#[derive(Debug, Clone)]
+pub struct RecoveredPlay {
+    pub checksum: u64,
+    pub mods: Mods,
+    pub events: Vec<ReplayEvent>,
+    pub score: Option<ScoreSnapshot>,
+}
+
+impl RecoveredPlay {
+    pub fn parse(contents: &str) -> Result<Self> {
+        // a crash can leave the last line half written, so a bad line at the end is ignored
+        let mut lines = contents.lines().filter(|line| !line.trim().is_empty()).peekable();
+        let Some(first) = lines.next() else {
+            bail!("Autosave is empty");
+        };
+        let AutosaveLine::Header { checksum, mods } = serde_json::from_str(first)? else {
+            bail!("Autosave doesn't start with a header");
+        };
+        let mut play = Self { checksum, mods, events: Vec::new(), score: None };
+        while let Some(line) = lines.next() {
+            let line = match serde_json::from_str(line) {
+                Ok(line) => line,
+                Err(_) if lines.peek().is_none() => break,
+                Err(e) => return Err(e.into()),
+            };
+            match line {
+                AutosaveLine::Header { .. } => bail!("Autosave has a second header"),
+                AutosaveLine::Event(event) => play.events.push(event),
+                AutosaveLine::Score(score) => {
+                    play.mods = score.mods.clone();
+                    play.score = Some(score);
+                }
+                AutosaveLine::Restart => {
+                    play.events.clear();
+                    play.score = None;
+                }
+            }
+        }
+        Ok(play)
+    }
+
+    pub fn find(path: &Path, checksum: u64) -> Option<Self> {
+        // the leftover autosave, if there is one and it's for this chart
+        let contents = fs::read_to_string(path).ok()?;
+        match Self::parse(&contents) {
+            Ok(play) if play.checksum == checksum => Some(play),
+            Ok(_) => {
+                logger::warning(&format!("Ignoring autosave '{}', it's for another chart", path.display()));
+                None
+            }
+            Err(e) => {
+                logger::warning(&format!("Ignoring unreadable autosave '{}': {}", path.display(), e));
+                None
+            }
+        }
+    }
+
+    pub fn export(&self, path: &Path) -> Result<()> {
+        // saves what was played as a replay of its own
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| anyhow!("Failed to create '{}': {}", parent.display(), e))?;
+        }
+        Replay {
+            mods: self.mods.clone(),
+            windows: None, // autosaves don't keep them
+            events: self.events.clone(),
+        }
+        .save(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("vsrg_autosave_{}_{name}", std::process::id()))
+    }
+
+    fn event(time: Time, key: i64, pressed: bool) -> ReplayEvent {
+        ReplayEvent { time, key, pressed }
+    }
+
+    fn snapshot(time: Time, mirror: bool) -> ScoreSnapshot {
+        ScoreSnapshot { time, accuracy: 97.5, combo: 12, judged: 14, mods: Mods { mirror, ..Mods::default() } }
+    }
+
+    fn event_times(play: &RecoveredPlay) -> Vec<Time> {
+        play.events.iter().map(|event| event.time).collect()
+    }
+
+    #[test]
+    fn appended_batches_are_read_back_in_order() {
+        let path = temp_path("batches.vsr");
+        let autosave = Autosave::start(path.clone(), 42, Mods::default());
+        autosave.append(vec![AutosaveLine::Event(event(100.0, 0, true)), AutosaveLine::Event(event(180.0, 0, false))]);
+        autosave.append(vec![AutosaveLine::Event(event(250.0, 3, true)), AutosaveLine::Score(snapshot(10000.0, true))]);
+        autosave.finish(false);
+
+        let play = RecoveredPlay::find(&path, 42).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(event_times(&play), [100.0, 180.0, 250.0]);
+        assert_eq!((play.events[2].key, play.events[2].pressed), (3, true));
+        assert_eq!(play.score.map(|score| (score.time, score.combo)), Some((10000.0, 12)));
+        // the mods from the latest snapshot, they were toggled after the header was written
+        assert!(play.mods.mirror);
+    }
+
+    #[test]
+    fn discarded_autosave_is_removed() {
+        let path = temp_path("discarded.vsr");
+        let autosave = Autosave::start(path.clone(), 42, Mods::default());
+        autosave.append(vec![AutosaveLine::Event(event(100.0, 0, true))]);
+        autosave.finish(true);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn restart_drops_what_came_before() {
+        let lines = [
+            AutosaveLine::Header { checksum: 7, mods: Mods::default() },
+            AutosaveLine::Event(event(100.0, 1, true)),
+            AutosaveLine::Score(snapshot(100.0, false)),
+            AutosaveLine::Restart,
+            AutosaveLine::Event(event(40.0, 2, true)),
+        ];
+        let contents: String = lines.iter().map(|line| serde_json::to_string(line).unwrap() + "\n").collect();
+        let play = RecoveredPlay::parse(&contents).unwrap();
+        assert_eq!(event_times(&play), [40.0]);
+        assert!(play.score.is_none());
+    }
+
+    #[test]
+    fn half_written_last_line_is_ignored() {
+        let header = serde_json::to_string(&AutosaveLine::Header { checksum: 7, mods: Mods::default() }).unwrap();
+        let event_line = serde_json::to_string(&AutosaveLine::Event(event(100.0, 1, true))).unwrap();
+        let torn = format!("{header}\n{event_line}\n{}", &event_line[..10]);
+        assert_eq!(event_times(&RecoveredPlay::parse(&torn).unwrap()), [100.0]);
+        // but a bad line in the middle means the file isn't an autosave
+        let broken = format!("{header}\n{}\n{event_line}\n", &event_line[..10]);
+        assert!(RecoveredPlay::parse(&broken).is_err());
+        assert!(RecoveredPlay::parse("").is_err());
+        assert!(RecoveredPlay::parse(&format!("{event_line}\n")).is_err());
+    }
+
+    #[test]
+    fn leftover_is_only_found_for_its_own_chart() {
+        let path = temp_path("other_chart.vsr");
+        let autosave = Autosave::start(path.clone(), 42, Mods::default());
+        autosave.append(vec![AutosaveLine::Event(event(100.0, 0, true))]);
+        autosave.finish(false);
+        assert!(RecoveredPlay::find(&path, 43).is_none());
+        assert!(RecoveredPlay::find(&path, 42).is_some());
+        fs::remove_file(&path).unwrap();
+        assert!(RecoveredPlay::find(&path, 42).is_none());
+    }
+
+    #[test]
+    fn checksum_is_fnv1a() {
+        assert_eq!(chart_checksum(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(chart_checksum(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(chart_checksum(b"ab"), chart_checksum(b"ba"));
+    }
+}
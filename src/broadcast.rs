@@ -0,0 +1,259 @@
+use crate::logger;
+use crate::map::Map;
+use crate::results::ResultsSummary;
+use crate::utils::{JudgementType, Time};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::{
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    thread::JoinHandle,
+    time::Duration,
+};
+use tungstenite::{Message, WebSocket};
+
+// seconds between states sent to overlays
+pub const BROADCAST_INTERVAL: f64 = 0.25;
+// how long the server waits for a state before checking for new connections again
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+// a client that can't take a message this fast is dropped, so a stuck overlay can't hold up the others
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LastJudgement {
+    pub judgement: JudgementType,
+    pub offset: f64, // ms, positive = early
+}
+
+// the live play, as sent to overlays (one json message per state)
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BroadcastState {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub difficulty: Option<String>,
+    pub time: Time,   // song time (ms)
+    pub length: Time, // song length (ms)
+    pub rate: f64,
+    pub accuracy: f64,
+    pub combo: usize,
+    pub judgement_counts: Vec<(JudgementType, usize)>, // in display order
+    pub last_judgement: Option<LastJudgement>,
+    pub unstable_rate: Option<f64>,
+}
+
+impl BroadcastState {
+    pub fn from_map(map: &Map) -> Self {
+        // scored like the results screen, so overlays show the same numbers
+        let summary = ResultsSummary::from_map(map);
+        Self {
+            title: map.title.clone(),
+            artist: map.artist.clone(),
+            difficulty: map.difficulty_name.clone(),
+            time: map.time,
+            length: map.length,
+            rate: map.rate,
+            accuracy: summary.accuracy,
+            combo: map.combo,
+            judgement_counts: summary.judgement_counts,
+            last_judgement: map
+                .last_judgement
+                .map(|(judgement, _, offset)| LastJudgement { judgement, offset }),
+            unstable_rate: summary.unstable_rate,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    state: Option<BroadcastState>, // newest state not sent yet, replaced by a newer one rather than queued
+    closed: bool,
+}
+
+// hands states to the server thread; sending never blocks on the network
+pub struct Broadcaster {
+    slot: Arc<(Mutex<Slot>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Broadcaster {
+    pub fn start(port: u16) -> Result<Self> {
+        // overlays run on the same machine, so only local connections are accepted
+        let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| anyhow!("Failed to listen on port {port}: {e}"))?;
+        listener.set_nonblocking(true)?;
+        logger::info(&format!("Broadcasting state on ws://127.0.0.1:{port}"));
+        let slot = Arc::new((Mutex::new(Slot::default()), Condvar::new()));
+        let thread = {
+            let slot = Arc::clone(&slot);
+            std::thread::spawn(move || serve(&listener, &slot))
+        };
+        Ok(Self { slot, thread: Some(thread) })
+    }
+
+    pub fn send(&self, state: BroadcastState) {
+        // an unsent older state is dropped, overlays only care about the latest
+        let (slot, wake) = &*self.slot;
+        slot.lock().unwrap_or_else(PoisonError::into_inner).state = Some(state);
+        wake.notify_one();
+    }
+}
+
+impl Drop for Broadcaster {
+    fn drop(&mut self) {
+        let (slot, wake) = &*self.slot;
+        slot.lock().unwrap_or_else(PoisonError::into_inner).closed = true;
+        wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                logger::warning("Broadcast thread panicked");
+            }
+        }
+    }
+}
+
+fn serve(listener: &TcpListener, slot: &(Mutex<Slot>, Condvar)) {
+    let mut clients: Vec<WebSocket<TcpStream>> = Vec::new();
+    loop {
+        accept_clients(listener, &mut clients);
+
+        let (slot, wake) = slot;
+        let state = {
+            let guard = slot.lock().unwrap_or_else(PoisonError::into_inner);
+            let (mut guard, _) = wake
+                .wait_timeout_while(guard, ACCEPT_POLL, |slot| slot.state.is_none() && !slot.closed)
+                .unwrap_or_else(PoisonError::into_inner);
+            if guard.closed {
+                break;
+            }
+            guard.state.take()
+        };
+        let Some(state) = state else {
+            continue;
+        };
+        let json = match serde_json::to_string(&state) {
+            Ok(json) => json,
+            Err(e) => {
+                logger::warning(&format!("Failed to serialize broadcast state: {e}"));
+                continue;
+            }
+        };
+        // clients that closed or can't keep up are dropped
+        clients.retain_mut(|client| client.send(Message::text(json.clone())).is_ok());
+    }
+    for mut client in clients {
+        let _ = client.close(None);
+        let _ = client.flush();
+    }
+}
+
+fn accept_clients(listener: &TcpListener, clients: &mut Vec<WebSocket<TcpStream>>) {
+    // takes every waiting connection; a failed handshake only loses that client
+    while let Ok((stream, address)) = listener.accept() {
+        let setup = stream
+            .set_nonblocking(false)
+            .and_then(|()| stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT)))
+            .and_then(|()| stream.set_read_timeout(Some(CLIENT_WRITE_TIMEOUT)));
+        if let Err(e) = setup {
+            logger::warning(&format!("Broadcast client {address} dropped: {e}"));
+            continue;
+        }
+        match tungstenite::accept(stream) {
+            Ok(client) => {
+                logger::info(&format!("Broadcast client connected from {address}"));
+                clients.push(client);
+            }
+            Err(e) => logger::warning(&format!("Broadcast client {address} failed the handshake: {e}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::initialize_map;
+    use crate::map::{HitObject, TimingPoint};
+    use crate::render::set_reference_positions;
+    use std::time::Instant;
+
+    fn state(time: Time) -> BroadcastState {
+        BroadcastState {
+            title: Some("Title".to_string()),
+            artist: None,
+            difficulty: Some("Hard".to_string()),
+            time,
+            length: 90000.0,
+            rate: 1.1,
+            accuracy: 98.5,
+            combo: 42,
+            judgement_counts: vec![(JudgementType::Marvelous, 40), (JudgementType::Miss, 1)],
+            last_judgement: Some(LastJudgement { judgement: JudgementType::Perfect, offset: -12.5 }),
+            unstable_rate: Some(80.0),
+        }
+    }
+
+    #[test]
+    fn state_serializes_every_field() {
+        let json: serde_json::Value = serde_json::to_value(state(1234.0)).unwrap();
+        assert_eq!(json["title"], "Title");
+        assert!(json["artist"].is_null());
+        assert_eq!(json["time"], 1234.0);
+        assert_eq!(json["rate"], 1.1);
+        assert_eq!(json["combo"], 42);
+        assert_eq!(json["judgement_counts"][0], serde_json::json!(["Marvelous", 40]));
+        assert_eq!(json["last_judgement"], serde_json::json!({ "judgement": "Perfect", "offset": -12.5 }));
+        assert_eq!(json["unstable_rate"], 80.0);
+    }
+
+    #[test]
+    fn state_from_a_map_matches_its_play() {
+        let mut map = Map::default();
+        map.hit_objects = vec![HitObject { start_time: 1000.0, lane: 1, ..HitObject::default() }];
+        map.timing_points.push(TimingPoint { start_time: 0.0, bpm: 60.0, time_signature: None, hidden: false });
+        map.title = Some("Chart".to_string());
+        map.rate = 1.0;
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        map.time = 1010.0;
+        map.handle_gameplay_key_press(1010.0, 0);
+        let state = BroadcastState::from_map(&map);
+        assert_eq!((state.title.as_deref(), state.time, state.combo), (Some("Chart"), 1010.0, 1));
+        assert_eq!(state.last_judgement, Some(LastJudgement { judgement: JudgementType::Marvelous, offset: -10.0 }));
+        assert_eq!(state.accuracy, ResultsSummary::from_map(&map).accuracy);
+    }
+
+    #[test]
+    fn unsent_states_are_replaced_not_queued() {
+        // no server thread, so nothing takes the states out
+        let broadcaster = Broadcaster { slot: Arc::new((Mutex::new(Slot::default()), Condvar::new())), thread: None };
+        for time in [1.0, 2.0, 3.0] {
+            broadcaster.send(state(time));
+        }
+        let slot = broadcaster.slot.0.lock().unwrap();
+        assert_eq!(slot.state.as_ref().map(|state| state.time), Some(3.0));
+    }
+
+    #[test]
+    fn clients_receive_the_latest_state() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let broadcaster = Broadcaster::start(port).unwrap();
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let (mut client, _) = tungstenite::client(format!("ws://127.0.0.1:{port}"), stream).unwrap();
+
+        // the server only takes the client on its next poll, so states are sent until one arrives
+        let started = Instant::now();
+        let mut time = 0.0;
+        let json = loop {
+            time += 1.0;
+            broadcaster.send(state(time));
+            std::thread::sleep(ACCEPT_POLL);
+            assert!(started.elapsed() < Duration::from_secs(5), "no state arrived");
+            if client.get_ref().peek(&mut [0]).is_ok_and(|read| read > 0) {
+                break client.read().unwrap().into_text().unwrap();
+            }
+        };
+        let received: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(received["time"].as_f64().unwrap() <= time);
+        drop(broadcaster);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::map::BeatPosition;
+use crate::utils::{format_time, Time, TimeStyle};
+use anyhow::{anyhow, Result};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+pub fn clip_line(time: Time, position: Option<BeatPosition>) -> String {
+    // one clips.txt line: timestamp, then measure and beat when the chart has timing points
+    match position {
+        Some(position) => format!(
+            "{} measure {} beat {} ({:.2})",
+            format_time(time, TimeStyle::Precise),
+            position.measure,
+            position.beat,
+            position.phase
+        ),
+        None => format_time(time, TimeStyle::Precise),
+    }
+}
+
+pub fn append_clip(path: &Path, line: &str) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow!("Failed to open '{}': {}", path.display(), e))?;
+    writeln!(file, "{line}")?;
+    Ok(())
+}
+
+pub fn screenshot_path(dir: &Path, map_name: &str, time: Time) -> PathBuf {
+    // <map>_<ms>.png, with anything that can't go in a file name replaced
+    let map_name: String = map_name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    dir.join(format!("{map_name}_{}.png", time.round() as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Map, TimeSignature, TimingPoint};
+
+    fn two_bpm_chart() -> Map {
+        // 120 BPM 4/4 from 0, then 180 BPM 3/4 from 3000 ms (half way through measure 2)
+        let mut map = Map::default();
+        map.timing_points = vec![
+            TimingPoint { start_time: 0.0, bpm: 120.0, time_signature: None, hidden: false },
+            TimingPoint { start_time: 3000.0, bpm: 180.0, time_signature: Some(TimeSignature::Triple), hidden: false },
+        ];
+        map
+    }
+
+    #[test]
+    fn clip_lines_have_the_time_then_the_measure_and_beat() {
+        let map = two_bpm_chart();
+        let line = |time: Time| clip_line(time, map.beat_phase(time));
+        assert_eq!(line(0.0), "0:00.000 measure 1 beat 1 (0.00)");
+        assert_eq!(line(1250.0), "0:01.250 measure 1 beat 3 (0.50)");
+        assert_eq!(line(2750.0), "0:02.750 measure 2 beat 2 (0.50)");
+        // the timing point starts measure 3, in 3/4
+        assert_eq!(line(3000.0), "0:03.000 measure 3 beat 1 (0.00)");
+        assert_eq!(line(4500.0), "0:04.500 measure 4 beat 2 (0.50)");
+        // beats at 180 BPM fall between whole ms, charts round them to 3 decimals
+        assert_eq!(line(3333.333), "0:03.333 measure 3 beat 2 (0.00)");
+        assert_eq!(line(63_000.0), "1:03.000 measure 63 beat 1 (0.00)");
+        // the lead-in counts back from the first measure
+        assert_eq!(line(-500.0), "-0:00.500 measure 0 beat 4 (0.00)");
+    }
+
+    #[test]
+    fn clip_lines_are_just_the_time_without_timing_points() {
+        let map = Map::default();
+        assert_eq!(map.beat_phase(1234.0), None);
+        assert_eq!(clip_line(1234.0, None), "0:01.234");
+        assert_eq!(clip_line(3_723_456.4, None), "1:02:03.456");
+    }
+
+    #[test]
+    fn clips_are_appended_a_line_each() {
+        let path = std::env::temp_dir().join(format!("vsrg_clips_{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        append_clip(&path, "0:01.000").unwrap();
+        append_clip(&path, "0:02.000").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "0:01.000\n0:02.000\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn screenshot_names_only_keep_safe_characters() {
+        let path = screenshot_path(Path::new("shots"), "Artist - Title [Hard]/x", 1234.6);
+        assert_eq!(path, Path::new("shots").join("Artist_-_Title__Hard__x_1235.png"));
+    }
+}
@@ -0,0 +1,331 @@
+use crate::draw::RenderFilter;
+use crate::ducking::DuckingSettings;
+use crate::input::{Binding, KeyBindings};
+use crate::input_gate::DEFAULT_RESUME_GRACE;
+use crate::memory_budget::DEFAULT_MEMORY_CAP_MB;
+use crate::logger;
+use crate::mash::{DEFAULT_MASH_PRESSES, DEFAULT_MASH_WINDOW};
+use crate::splash::SplashFilter;
+use crate::utils::{JudgementType, Skin, DEFAULT_SKIN, MAX_LANES};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+// version written by this build, older files are migrated up to it when loaded
+pub const CONFIG_VERSION: i64 = 2;
+
+// settings kept between runs; command line options override them for one run
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    pub version: i64,
+    pub offset: f64,                // audio offset in milliseconds
+    pub scroll_speed: f64,          // scroll speed of the notes
+    pub volume: f64,                // audio volume, 0-1.5
+    pub audio_latency: Option<f64>, // output latency (ms) to assume when the device doesn't report one
+    pub fullscreen: bool,           // start in fullscreen
+    pub lang: String,               // ui language
+    pub safe_mode: bool,            // photosensitivity-safe mode: limit how fast notes move on screen, no flashing effects
+    pub safe_mode_max_speed: f64,   // fastest (px/s) anything moves on screen in safe mode
+    pub render_scale: f64,          // resolution the scene is drawn at, as a fraction of the window's (0.5-2)
+    pub render_filter: RenderFilter, // how the scene is filtered when scaled to the window
+    pub native_ui_text: bool,       // draw the ui on top at the window's resolution, so text stays sharp
+    pub playfield_x_offset: f64,    // how far the playfield is moved right of the center (negative for left)
+    pub lane_gap_after: Vec<usize>, // lanes (1 is leftmost) followed by an extra gap, e.g. [2] between the hands in 4K
+    pub lane_gap_px: f64,           // width of that gap
+    pub lane_widths: Vec<f64>,      // width of each lane from the left, 0 (or left out) for the skin's
+    pub hide_splash_for: Vec<JudgementType>, // judgements shown without the center splash or lane judgement, e.g. ["Marvelous"]
+    pub splash_only_on_combo_break: bool,    // only show the splash for misses and okays
+    pub frozen_sv_warning_ms: f64,  // 0x SV stops at least this long (ms) are warned about on load, 0 for none
+    pub keysound_ducking_db: f64,   // most the music is lowered (dB) while loud keysounds stack up, 0 for never
+    pub keysound_ducking_threshold: f64, // summed keysound amplitude (1 = one at full scale) the music starts going down at
+    pub keysound_ducking_release_ms: f64, // how fast the music comes back afterwards
+    pub lane_offsets_ms: Vec<f64>,  // ms each key (1 is leftmost) registers late by, judged as if pressed that much earlier
+    pub rate_step: f64,             // how much - and + change the rate by (shift changes it by 0.01)
+    pub show_pace: bool,            // show the notes left and projected accuracy while playing
+    pub lookahead_ms: f64,          // how far ahead notes are drawn, 0 to work it out from the screen, scroll speed and SV
+    pub background_dim: f64,        // how much the map's background is darkened, 0 (as it is) to 1 (black)
+    pub resume_grace_ms: f64,       // gameplay presses in the first ms after playing again are ignored
+    pub audio_memory_cap_mb: f64,   // most decoded keysounds kept in memory, the least recently played are dropped past it
+    pub fixed_timestep_hz: f64,     // run the simulation at this tick rate and interpolate rendering, 0 for once a frame
+    pub mash_presses: usize,        // presses in one lane within mash_window_ms that count as mashing
+    pub mash_window_ms: f64,        // window (ms) for mash detection
+    pub keys: Vec<String>,          // gameplay keys, one "key,key,..." list (lane 1 first) per key count, e.g. ["s,d,f,space,j,k,l"]
+    #[serde(flatten)]
+    pub unknown: toml::Table, // fields this version doesn't know, kept so saving doesn't drop them
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            offset: DEFAULT_SKIN.offset,
+            scroll_speed: DEFAULT_SKIN.scroll_speed,
+            volume: 0.03,
+            audio_latency: None,
+            fullscreen: false,
+            lang: "en".to_string(),
+            safe_mode: false,
+            safe_mode_max_speed: 3000.0,
+            render_scale: 1.0,
+            render_filter: RenderFilter::Linear,
+            native_ui_text: true,
+            playfield_x_offset: DEFAULT_SKIN.playfield_x_offset,
+            lane_gap_after: Vec::new(),
+            lane_gap_px: DEFAULT_SKIN.lane_gap_px,
+            lane_widths: Vec::new(),
+            hide_splash_for: Vec::new(),
+            splash_only_on_combo_break: false,
+            frozen_sv_warning_ms: DEFAULT_SKIN.frozen_sv_warning,
+            keysound_ducking_db: 0.0,
+            keysound_ducking_threshold: 1.0,
+            keysound_ducking_release_ms: 300.0,
+            lane_offsets_ms: Vec::new(),
+            rate_step: 0.05,
+            show_pace: true,
+            lookahead_ms: DEFAULT_SKIN.lookahead,
+            background_dim: DEFAULT_SKIN.background_dim,
+            resume_grace_ms: DEFAULT_RESUME_GRACE,
+            audio_memory_cap_mb: DEFAULT_MEMORY_CAP_MB,
+            fixed_timestep_hz: 0.0,
+            mash_presses: DEFAULT_MASH_PRESSES,
+            mash_window_ms: DEFAULT_MASH_WINDOW,
+            keys: Vec::new(),
+            unknown: toml::Table::new(),
+        }
+    }
+}
+
+fn migrate(table: &mut toml::Table) -> Result<()> {
+    // upgrades an older config in place, one version at a time
+    // files from before versioning count as version 1
+    let mut version = table.get("version").and_then(toml::Value::as_integer).unwrap_or(1);
+    if version > CONFIG_VERSION {
+        logger::warning(&format!(
+            "Config version {version} is newer than this build's ({CONFIG_VERSION}), unknown fields are kept as they are"
+        ));
+        return Ok(());
+    }
+    if version < 1 {
+        return Err(anyhow!("config version {version} doesn't exist, versions start at 1"));
+    }
+    while version < CONFIG_VERSION {
+        match version {
+            1 => {
+                // audio_offset was renamed to offset, volume went from a percentage to 0-1
+                if let Some(offset) = table.remove("audio_offset") {
+                    table.entry("offset").or_insert(offset);
+                }
+                if let Some(volume) = table.get_mut("volume") {
+                    let percent = volume
+                        .as_float()
+                        .or_else(|| volume.as_integer().map(|volume| volume as f64))
+                        .ok_or_else(|| anyhow!("volume isn't a number"))?;
+                    *volume = toml::Value::Float(percent / 100.0);
+                }
+            }
+            _ => return Err(anyhow!("no migration from config version {version}")),
+        }
+        version += 1;
+        logger::info(&format!("Migrated config to version {version}"));
+    }
+    table.insert("version".to_string(), toml::Value::Integer(version));
+    Ok(())
+}
+
+impl Config {
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut table: toml::Table = toml::from_str(contents)?;
+        migrate(&mut table)?;
+        Ok(table.try_into()?)
+    }
+
+    pub fn load_or_default(path: &Path) -> Self {
+        // a missing config gives the defaults; a broken one is moved aside (so saving can't lose it) first
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                logger::warning(&format!("Using the default config, couldn't read '{}': {}", path.display(), e));
+                return Self::default();
+            }
+        };
+        match Self::parse(&contents) {
+            Ok(config) => {
+                logger::info(&format!("Loaded config from {}", path.display()));
+                config
+            }
+            Err(e) => {
+                let backup = path.with_extension("toml.broken");
+                logger::warning(&format!(
+                    "Using the default config, couldn't parse '{}' (moved to '{}'): {}",
+                    path.display(),
+                    backup.display(),
+                    e
+                ));
+                if let Err(e) = fs::rename(path, &backup) {
+                    logger::warning(&format!("Failed to move the broken config: {e}"));
+                }
+                Self::default()
+            }
+        }
+    }
+
+    pub fn apply_to_skin(&self, skin: Skin) -> Skin {
+        // the skin with this config's settings in it; lanes a chart can't have are left out
+        let mut lane_gap_after = [false; MAX_LANES];
+        for &lane in &self.lane_gap_after {
+            match lane.checked_sub(1).and_then(|index| lane_gap_after.get_mut(index)) {
+                Some(gap) => *gap = true,
+                None => logger::warning(&format!("Ignoring lane_gap_after lane {lane}, lanes are 1-{MAX_LANES}")),
+            }
+        }
+        if self.lane_widths.len() > MAX_LANES {
+            logger::warning(&format!("Ignoring lane_widths past the first {MAX_LANES}"));
+        }
+        let mut lane_widths = [None; MAX_LANES];
+        for (lane_width, &width) in lane_widths.iter_mut().zip(&self.lane_widths) {
+            *lane_width = (width > 0.0).then_some(width);
+        }
+        Skin {
+            offset: self.offset,
+            scroll_speed: self.scroll_speed,
+            playfield_x_offset: self.playfield_x_offset,
+            lane_gap_after,
+            lane_gap_px: self.lane_gap_px,
+            lane_widths,
+            splash_filter: SplashFilter::new(&self.hide_splash_for, self.splash_only_on_combo_break),
+            frozen_sv_warning: self.frozen_sv_warning_ms.max(0.0),
+            lookahead: self.lookahead_ms.max(0.0),
+            background_dim: self.background_dim.clamp(0.0, 1.0),
+            ..skin
+        }
+    }
+
+    pub fn lane_offsets(&self, key_count: usize) -> Vec<f64> {
+        // the offset of each of a chart's keys, 0 for the ones left out
+        if self.lane_offsets_ms.len() > key_count {
+            logger::warning(&format!("Ignoring lane_offsets_ms past the first {key_count}, the chart has {key_count} keys"));
+        }
+        let mut lane_offsets = self.lane_offsets_ms.clone();
+        lane_offsets.resize(key_count, 0.0);
+        lane_offsets
+    }
+
+    pub fn key_bindings(&self) -> KeyBindings {
+        // the defaults with the configured ones in place of theirs, broken ones left out
+        let mut key_bindings = KeyBindings::default();
+        for keys in &self.keys {
+            match keys.parse::<Binding>() {
+                Ok(binding) => key_bindings.set(binding),
+                Err(e) => logger::warning(&format!("Ignoring keys '{keys}': {e}")),
+            }
+        }
+        key_bindings
+    }
+
+    pub fn ducking_settings(&self) -> Option<DuckingSettings> {
+        // None when ducking is off
+        (self.keysound_ducking_db > 0.0).then(|| DuckingSettings {
+            threshold: self.keysound_ducking_threshold.max(0.01),
+            max_reduction: self.keysound_ducking_db,
+            release: self.keysound_ducking_release_ms.max(0.0),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = toml::to_string_pretty(self)?;
+        fs::write(path, contents).map_err(|e| anyhow!("Failed to write config '{}': {}", path.display(), e))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_get_their_defaults() {
+        let config = Config::parse("version = 2\nscroll_speed = 25.0\n").unwrap();
+        assert_eq!(config, Config { scroll_speed: 25.0, ..Config::default() });
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn version_1_is_migrated_to_version_2() {
+        let config = Config::parse("audio_offset = -12.0\nvolume = 50\nfullscreen = true\n").unwrap();
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.offset, -12.0);
+        assert_eq!(config.volume, 0.5);
+        assert!(config.fullscreen);
+        assert!(config.unknown.is_empty());
+        // an offset that was already renamed wins over the old one
+        let config = Config::parse("version = 1\naudio_offset = -12.0\noffset = 4.0\nvolume = 2.5\n").unwrap();
+        assert_eq!((config.offset, config.volume), (4.0, 0.025));
+    }
+
+    #[test]
+    fn versions_that_never_existed_are_errors() {
+        assert!(Config::parse("version = 0\n").is_err());
+        assert!(Config::parse("version = -3\n").is_err());
+        assert!(Config::parse("version = 1\nvolume = \"loud\"\n").is_err());
+    }
+
+    #[test]
+    fn broken_config_is_moved_aside() {
+        let path = std::env::temp_dir().join(format!("vsrg_config_{}.toml", std::process::id()));
+        fs::write(&path, "version = 0\n").unwrap();
+        assert_eq!(Config::load_or_default(&path), Config::default());
+        let backup = path.with_extension("toml.broken");
+        assert!(!path.exists());
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "version = 0\n");
+        fs::remove_file(&backup).unwrap();
+    }
+
+    #[test]
+    fn unknown_keys_survive_a_round_trip() {
+        let contents = "version = 2\nvolume = 0.2\nfuture_option = \"kept\"\n\n[future_table]\nvalue = 3\n";
+        let config = Config::parse(contents).unwrap();
+        assert_eq!(config.unknown.get("future_option").and_then(toml::Value::as_str), Some("kept"));
+        let saved = toml::to_string_pretty(&config).unwrap();
+        let reloaded = Config::parse(&saved).unwrap();
+        assert_eq!(reloaded, config);
+        assert_eq!(reloaded.unknown["future_table"]["value"].as_integer(), Some(3));
+    }
+
+    #[test]
+    fn newer_versions_load_as_they_are() {
+        let config = Config::parse("version = 9\nvolume = 0.2\n").unwrap();
+        assert_eq!((config.version, config.volume), (9, 0.2));
+    }
+
+    #[test]
+    fn broken_key_bindings_are_skipped() {
+        use macroquad::input::KeyCode;
+        let config = Config {
+            keys: vec![
+                "s,d,f,space,j,k,l".to_string(),
+                "q,w,nope,r".to_string(),
+                "a,a,b,c".to_string(),
+                "".to_string(),
+                "z,x,c,v,b".to_string(),
+            ],
+            ..Config::default()
+        };
+        let key_bindings = config.key_bindings();
+        let defaults = KeyBindings::default();
+        assert_eq!(key_bindings.keys(7)[3], KeyCode::Space);
+        assert!(key_bindings.binds(7, KeyCode::Space));
+        assert_eq!(key_bindings.keys(4), defaults.keys(4));
+        assert_eq!(key_bindings.keys(5), [KeyCode::Z, KeyCode::X, KeyCode::C, KeyCode::V, KeyCode::B]);
+        assert_eq!(key_bindings.keys(8), defaults.keys(8));
+        assert!(key_bindings.keys(1).is_empty());
+    }
+
+    #[test]
+    fn pause_key_cant_be_bound() {
+        // so it pauses even when Space plays a lane
+        assert!("s,d,f8,j".parse::<Binding>().is_err());
+        assert!(!KeyBindings::default().binds(4, crate::input::PAUSE_KEY));
+    }
+}
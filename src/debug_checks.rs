@@ -0,0 +1,327 @@
+use crate::logger;
+use crate::map::{Map, Position};
+use crate::utils::{HitKind, HoldEventKind, JudgementType, Rng, Time, TRACK_ROUNDING};
+use clap::ValueEnum;
+use std::collections::HashMap;
+
+// frames between the regular checks; seeks, restarts and mod toggles are checked right away
+pub const CHECK_INTERVAL: u64 = 60;
+// SV points per timing group whose cumulative position is recomputed on each check
+const SAMPLED_POSITIONS: usize = 4;
+
+// what a broken invariant does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum InvariantMode {
+    Warn,   // log it and carry on
+    Strict, // log it and panic, so the state it broke in can be looked at
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub check: &'static str,
+    pub detail: String,
+}
+
+// runs the checks over the map and play state, and keeps what they need between frames
+pub struct InvariantChecker {
+    mode: InvariantMode,
+    rng: Rng,
+    frames: u64,
+    furthest_time: Time, // latest map time seen; seeking back leaves later notes judged, they aren't replayed
+}
+
+impl InvariantChecker {
+    pub fn new(mode: InvariantMode, seed: u64) -> Self {
+        Self { mode, rng: Rng::new(seed), frames: 0, furthest_time: f64::NEG_INFINITY }
+    }
+
+    pub fn after_frame(&mut self, map: &Map, event: Option<&str>) {
+        // checks after an event, or every CHECK_INTERVAL frames otherwise
+        self.furthest_time = self.furthest_time.max(map.time);
+        self.frames += 1;
+        if let Some(event) = event {
+            self.check(map, event);
+        } else if self.frames.is_multiple_of(CHECK_INTERVAL) {
+            self.check(map, "frame");
+        }
+    }
+
+    pub fn check(&mut self, map: &Map, context: &str) {
+        self.furthest_time = self.furthest_time.max(map.time);
+        let violations = check_map(map, self.furthest_time, &mut self.rng);
+        if violations.is_empty() {
+            return;
+        }
+        for violation in &violations {
+            logger::error(&format!(
+                "Invariant '{}' broken after {context} at {:.0} ms: {}",
+                violation.check, map.time, violation.detail
+            ));
+        }
+        if self.mode == InvariantMode::Strict {
+            panic!("{} invariants broken after {context} (--check-invariants=strict)", violations.len());
+        }
+    }
+}
+
+pub fn check_map(map: &Map, furthest_time: Time, rng: &mut Rng) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    check_sorted(map, &mut violations);
+    check_cumulative_positions(map, rng, &mut violations);
+    check_hit_flags(map, furthest_time, &mut violations);
+    check_held_notes(map, &mut violations);
+    check_judgement_counts(map, &mut violations);
+    violations
+}
+
+fn check_sorted_list(name: &str, times: impl Iterator<Item = Time>, violations: &mut Vec<Violation>) {
+    // reports the first item earlier than the one before it
+    let mut previous: Option<Time> = None;
+    for (index, time) in times.enumerate() {
+        if let Some(previous) = previous.filter(|&previous| time < previous) {
+            violations.push(Violation {
+                check: "sorted",
+                detail: format!("{name} out of order at index {index} ({time} ms after {previous} ms)"),
+            });
+            return;
+        }
+        previous = Some(time);
+    }
+}
+
+fn check_sorted(map: &Map, violations: &mut Vec<Violation>) {
+    check_sorted_list("hit objects", map.hit_objects.iter().map(|note| note.start_time), violations);
+    check_sorted_list("timing points", map.timing_points.iter().map(|point| point.start_time), violations);
+    check_sorted_list("timing lines", map.timing_lines.iter().map(|line| line.start_time), violations);
+    for (id, group) in map.timing_groups.iter() {
+        check_sorted_list(&format!("SVs of '{id}'"), group.scroll_velocities.iter().map(|point| point.start_time), violations);
+        check_sorted_list(&format!("SSFs of '{id}'"), group.scroll_speed_factors.iter().map(|point| point.start_time), violations);
+    }
+}
+
+fn check_cumulative_positions(map: &Map, rng: &mut Rng, violations: &mut Vec<Violation>) {
+    // recomputes a few points from the one before them, the same way initialize_control_points does
+    for (id, group) in map.timing_groups.iter() {
+        let points = &group.scroll_velocities;
+        if points.is_empty() {
+            continue;
+        }
+        let mut sampled: Vec<usize> = (0..SAMPLED_POSITIONS.min(points.len()))
+            .map(|_| rng.below(points.len() as u64) as usize)
+            .collect();
+        sampled.sort_unstable();
+        sampled.dedup();
+        for index in sampled {
+            let expected = if index == 0 {
+                (points[0].start_time * group.initial_scroll_velocity * TRACK_ROUNDING) as Position
+            } else {
+                let previous = &points[index - 1];
+                let distance = (points[index].start_time - previous.start_time) * previous.multiplier;
+                previous.cumulative_position.saturating_add((distance * TRACK_ROUNDING) as Position)
+            };
+            if points[index].cumulative_position != expected {
+                violations.push(Violation {
+                    check: "cumulative position",
+                    detail: format!(
+                        "SV {index} of '{id}' is at {}, recomputed {expected}",
+                        points[index].cumulative_position
+                    ),
+                });
+            }
+        }
+        // positions only go back on the track after a negative SV
+        if let Some(index) = (1..points.len()).find(|&index| {
+            points[index - 1].multiplier >= 0.0 && points[index].cumulative_position < points[index - 1].cumulative_position
+        }) {
+            violations.push(Violation {
+                check: "cumulative position",
+                detail: format!("SV {index} of '{id}' goes back on the track after a positive SV"),
+            });
+        }
+    }
+}
+
+fn check_hit_flags(map: &Map, furthest_time: Time, violations: &mut Vec<Violation>) {
+    // nothing can be pressed before its early miss window, so a note past it was never reachable
+    let reachable = furthest_time + map.judgement_windows.early(JudgementType::Miss);
+    if let Some(index) = map.hit_objects.iter().position(|note| note.hit && note.start_time > reachable) {
+        violations.push(Violation {
+            check: "hit flags",
+            detail: format!(
+                "note {index} at {} ms is hit, but the play has only reached {furthest_time:.0} ms",
+                map.hit_objects[index].start_time
+            ),
+        });
+    }
+    if let Some(index) = map.hit_objects.iter().position(|note| note.tail_hit && !note.hit) {
+        violations.push(Violation { check: "hit flags", detail: format!("note {index} has its end judged but not its head") });
+    }
+}
+
+fn check_held_notes(map: &Map, violations: &mut Vec<Violation>) {
+    // a held note has to be an unfinished LN in its lane, last grabbed (or regrabbed) rather than let go
+    let mut last_events: HashMap<usize, HoldEventKind> = HashMap::new();
+    for event in &map.hold_events {
+        last_events.insert(event.note, event.kind);
+    }
+    let mut held: Vec<(&i64, &usize)> = map.held_notes.iter().collect();
+    held.sort_unstable();
+    for (&lane, &index) in held {
+        let problem = match map.hit_objects.get(index) {
+            None => Some("doesn't exist".to_string()),
+            Some(note) if note.end_time.is_none() => Some("isn't an LN".to_string()),
+            Some(note) if note.lane != lane => Some(format!("is in lane {}", note.lane)),
+            Some(note) if !note.hit || note.tail_hit => Some("isn't being held (head unjudged or end judged)".to_string()),
+            Some(_) => match last_events.get(&index) {
+                Some(HoldEventKind::Grab | HoldEventKind::Regrab) => None,
+                Some(kind) => Some(format!("was last {kind:?}, not pressed")),
+                None => Some("was never pressed".to_string()),
+            },
+        };
+        if let Some(problem) = problem {
+            violations.push(Violation {
+                check: "held notes",
+                detail: format!("note {index} held in lane {lane} {problem}"),
+            });
+        }
+    }
+}
+
+fn check_judgement_counts(map: &Map, violations: &mut Vec<Violation>) {
+    // counts match the judgements, and there are no more judgements than notes judged
+    // (notes can be done without a judgement, by resuming or autoplay skipping them)
+    for (&judgement, &count) in &map.judgement_counts {
+        let judged = map.hit_stats.iter().filter(|hit_stat| hit_stat.judgement == judgement).count();
+        if judged != count {
+            violations.push(Violation {
+                check: "judgement counts",
+                detail: format!("{count} {judgement} counted, {judged} judged"),
+            });
+        }
+    }
+    let heads = map.hit_stats.iter().filter(|hit_stat| hit_stat.kind != HitKind::LongNoteEnd).count();
+    let ends = map.hit_stats.len() - heads;
+    let hit = map.hit_objects.iter().filter(|note| note.hit).count();
+    let tails_hit = map.hit_objects.iter().filter(|note| note.end_time.is_some() && note.tail_hit).count();
+    if heads > hit {
+        violations.push(Violation {
+            check: "judgement counts",
+            detail: format!("{heads} notes judged, but only {hit} are hit"),
+        });
+    }
+    if ends > tails_hit {
+        violations.push(Violation {
+            check: "judgement counts",
+            detail: format!("{ends} LN ends judged, but only {tails_hit} are done"),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::initialize_map;
+    use crate::map::HitObject;
+    use crate::render::set_reference_positions;
+    use std::path::Path;
+
+    fn played() -> Map {
+        // a chart with a negative SV, with its first two notes played
+        let mut map = Map::from_file(Path::new("golden/charts/sv_reversal.qua")).unwrap();
+        map.rate = 1.0;
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        map.handle_gameplay_key_press(1000.0, 0);
+        map.handle_gameplay_key_press(1260.0, 1);
+        map.time = 1300.0;
+        map
+    }
+
+    fn checks(map: &Map, furthest_time: Time) -> Vec<&'static str> {
+        check_map(map, furthest_time, &mut Rng::new(1)).iter().map(|violation| violation.check).collect()
+    }
+
+    #[test]
+    fn played_map_breaks_nothing() {
+        let map = played();
+        assert_eq!(map.hit_stats.len(), 2);
+        assert!(checks(&map, map.time).is_empty(), "{:?}", check_map(&map, map.time, &mut Rng::new(1)));
+    }
+
+    #[test]
+    fn unsorted_notes_are_found() {
+        let mut map = played();
+        map.hit_objects.swap(2, 3);
+        assert_eq!(checks(&map, map.time), ["sorted"]);
+    }
+
+    #[test]
+    fn wrong_cumulative_positions_are_found() {
+        // each point off by a different amount, so recomputing any one from the one before catches it
+        let mut map = played();
+        let group = map.timing_groups.get_mut("$Default").unwrap();
+        for (index, point) in group.scroll_velocities.iter_mut().enumerate() {
+            point.cumulative_position += index as Position * 7 + 1;
+        }
+        assert!(checks(&map, map.time).contains(&"cumulative position"));
+    }
+
+    #[test]
+    fn notes_hit_ahead_of_the_play_are_found() {
+        let mut map = played();
+        map.hit_objects[3].hit = true;
+        assert_eq!(checks(&map, map.time), ["hit flags"]);
+        // unless the play got there before seeking back
+        assert!(!checks(&map, 1800.0).contains(&"hit flags"));
+
+        let mut map = played();
+        map.hit_objects[3].tail_hit = true;
+        assert_eq!(checks(&map, map.time), ["hit flags"]);
+    }
+
+    #[test]
+    fn held_notes_without_a_press_are_found() {
+        let mut map = played();
+        map.held_notes.insert(1, 0);
+        assert_eq!(checks(&map, map.time), ["held notes"]);
+
+        let mut map = played();
+        map.hit_objects.push(HitObject { start_time: 2000.0, end_time: Some(2500.0), lane: 3, hit: true, ..HitObject::default() });
+        map.held_notes.insert(3, 4);
+        let violations = check_map(&map, 2000.0, &mut Rng::new(1));
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].detail.contains("never pressed"), "{}", violations[0].detail);
+    }
+
+    #[test]
+    fn judgement_counts_that_dont_add_up_are_found() {
+        let mut map = played();
+        *map.judgement_counts.entry(JudgementType::Miss).or_insert(0) += 1;
+        assert_eq!(checks(&map, map.time), ["judgement counts"]);
+
+        // a judgement for a note that isn't hit
+        let mut map = played();
+        map.hit_objects[1].hit = false;
+        assert_eq!(checks(&map, map.time), ["judgement counts"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "invariants broken after seek")]
+    fn strict_mode_panics() {
+        let mut map = played();
+        map.hit_objects.swap(2, 3);
+        InvariantChecker::new(InvariantMode::Strict, 1).check(&map, "seek");
+    }
+
+    #[test]
+    fn warn_mode_only_checks_every_interval_or_after_events() {
+        let mut map = played();
+        map.hit_objects.swap(2, 3);
+        let mut checker = InvariantChecker::new(InvariantMode::Warn, 1);
+        // logs and carries on
+        checker.after_frame(&map, Some("transform"));
+        for _ in 0..CHECK_INTERVAL {
+            checker.after_frame(&map, None);
+        }
+        assert_eq!(checker.frames, CHECK_INTERVAL + 1);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::logger;
+use crate::map::parsers::is_chart_path;
+use crate::map::Map;
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// the charts (.qua, .osu, .sm or .ssc) next to the one being played, each parsed the first time it's switched to and kept
+// after; a chart saved again since (e.g. from an editor while practicing) is parsed again
+pub struct DifficultyCache {
+    paths: Vec<PathBuf>, // sorted by path, like the picker lists them
+    current: usize,
+    charts: HashMap<PathBuf, (Map, Option<SystemTime>)>, // chart data as parsed, before any mods, and when its file was modified
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+impl DifficultyCache {
+    pub fn new(chart: &Map) -> Result<Self> {
+        // starts out with the loaded chart, so switching back to it doesn't read it again
+        let path = PathBuf::from(&chart.file_path);
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+            .map_err(|e| anyhow!("Failed to read map directory {}: {}", dir.display(), e))?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && is_chart_path(path))
+            .collect();
+        paths.sort();
+        let current = paths
+            .iter()
+            .position(|candidate| *candidate == path)
+            .ok_or_else(|| anyhow!("'{}' isn't in its own directory", path.display()))?;
+        let charts = HashMap::from([(path.clone(), (chart.chart_clone(), modified(&path)))]);
+        Ok(Self { paths, current, charts })
+    }
+
+    pub fn neighbor(&self, step: isize) -> Option<usize> {
+        // index of the difficulty step places away, wrapping around; none if there's only the one
+        (self.paths.len() > 1).then(|| (self.current as isize + step).rem_euclid(self.paths.len() as isize) as usize)
+    }
+
+    pub fn switch_to(&mut self, index: usize) -> Result<Map> {
+        // a fresh copy of the chart to play, parsing it first if it hasn't been yet
+        let path = self
+            .paths
+            .get(index)
+            .ok_or_else(|| anyhow!("No difficulty {index}, there are {}", self.paths.len()))?;
+        // a file that can't be looked at any more keeps its cached chart
+        let modified = modified(path);
+        if self.charts.get(path).is_none_or(|(_, cached)| modified.is_some() && *cached != modified) {
+            logger::info(&format!("Loading map: {}", path.display()));
+            let chart = Map::from_file(path)?;
+            self.charts.insert(path.clone(), (chart, modified));
+        }
+        self.current = index;
+        Ok(self.charts[path].0.chart_clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn chart(difficulty: &str, audio: &str, first_note: u32) -> String {
+        format!(
+            "AudioFile: {audio}\nMode: Keys4\nDifficultyName: {difficulty}\nTimingPoints:\n- StartTime: 0\n  Bpm: 120\n\
+             HitObjects:\n- StartTime: {first_note}\n  Lane: 1\n  KeySounds: []\n"
+        )
+    }
+
+    fn mapset(name: &str) -> PathBuf {
+        // two difficulties on the same audio and one with its own, plus a file that isn't a chart
+        let dir = std::env::temp_dir().join(format!("vsrg_difficulties_{}_{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a_easy.qua"), chart("Easy", "audio.mp3", 1000)).unwrap();
+        fs::write(dir.join("b_hard.qua"), chart("Hard", "audio.mp3", 500)).unwrap();
+        fs::write(dir.join("c_extra.qua"), chart("Extra", "extra.mp3", 250)).unwrap();
+        fs::write(dir.join("notes.txt"), "not a chart").unwrap();
+        dir
+    }
+
+    fn difficulty(map: &Map) -> &str {
+        map.difficulty_name.as_deref().unwrap_or_default()
+    }
+
+    #[test]
+    fn neighbors_wrap_around_in_path_order() {
+        let dir = mapset("neighbors");
+        let cache = DifficultyCache::new(&Map::from_file(&dir.join("b_hard.qua")).unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(cache.paths.len(), 3);
+        assert_eq!((cache.neighbor(-1), cache.neighbor(1), cache.neighbor(2)), (Some(0), Some(2), Some(0)));
+    }
+
+    #[test]
+    fn charts_are_parsed_once_and_handed_out_fresh() {
+        let dir = mapset("cached");
+        let mut cache = DifficultyCache::new(&Map::from_file(&dir.join("a_easy.qua")).unwrap()).unwrap();
+        assert_eq!(cache.charts.len(), 1);
+        let hard = cache.switch_to(1).unwrap();
+        assert_eq!((difficulty(&hard), cache.charts.len(), cache.current), ("Hard", 2, 1));
+
+        // a cached chart is used even when its file is gone
+        fs::remove_file(dir.join("a_easy.qua")).unwrap();
+        let mut easy = cache.switch_to(0).unwrap();
+        assert_eq!(difficulty(&easy), "Easy");
+        // and what's played doesn't change the cached copy
+        easy.hit_objects.clear();
+        assert_eq!(cache.switch_to(0).unwrap().hit_objects.len(), 1);
+        assert!(cache.switch_to(5).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn charts_saved_again_are_parsed_again() {
+        let dir = mapset("invalidated");
+        let path = dir.join("b_hard.qua");
+        let mut cache = DifficultyCache::new(&Map::from_file(&dir.join("a_easy.qua")).unwrap()).unwrap();
+        assert_eq!(cache.switch_to(1).unwrap().hit_objects[0].start_time, 500.0);
+
+        fs::write(&path, chart("Hard", "audio.mp3", 750)).unwrap();
+        // set apart from the first write explicitly, file systems can keep coarse times
+        let later = SystemTime::now() + Duration::from_secs(10);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        assert_eq!(cache.switch_to(1).unwrap().hit_objects[0].start_time, 750.0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn difficulties_on_the_same_audio_share_it() {
+        // the audio is only reloaded when the file name differs
+        let dir = mapset("audio");
+        let mut cache = DifficultyCache::new(&Map::from_file(&dir.join("a_easy.qua")).unwrap()).unwrap();
+        let audio = |map: Map| map.audio_file;
+        let easy = audio(cache.switch_to(0).unwrap());
+        assert_eq!(audio(cache.switch_to(1).unwrap()), easy);
+        assert_ne!(audio(cache.switch_to(2).unwrap()), easy);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
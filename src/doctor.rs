@@ -0,0 +1,267 @@
+use crate::autosave::chart_checksum;
+use crate::config::Config;
+use crate::logger;
+use crate::map::Map;
+use crate::render::set_reference_positions;
+use anyhow::Result;
+use rodio::{OutputStream, Sink, Source};
+use std::{fs, path::Path, time::Duration};
+
+// tiny chart with every kind of timing data, initialized like a real one to check the position math
+// (its SVs are already normalized, so they're used as written rather than scaled by the bpms)
+const REFERENCE_MAP: &str = "\
+Mode: Keys4
+BPMDoesNotAffectScrollVelocity: true
+TimingPoints:
+- StartTime: 0
+  Bpm: 120
+- StartTime: 2000
+  Bpm: 180
+  Signature: 3
+SliderVelocities:
+- StartTime: 500
+  Multiplier: 2
+- StartTime: 1000
+  Multiplier: -0.5
+- StartTime: 1500
+  Multiplier: 1
+ScrollSpeedFactors:
+- StartTime: 1200
+  Multiplier: 0.5
+TimingGroups:
+  slow:
+    InitialScrollVelocity: 0.5
+    ScrollVelocities:
+    - StartTime: 800
+      Multiplier: 1.5
+HitObjects:
+- StartTime: 250
+  Lane: 1
+  KeySounds: []
+- StartTime: 750
+  Lane: 2
+  EndTime: 1750
+  KeySounds: []
+- StartTime: 1250
+  Lane: 3
+  KeySounds: []
+  TimingGroup: slow
+- StartTime: 2500
+  Lane: 4
+  KeySounds: []
+";
+
+// hash of the reference map's track positions when this build's math is right
+const REFERENCE_POSITIONS_HASH: u64 = 0x6c8c_c84b_1b7e_14ef;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn, // works, but not as it should
+    Fail,
+}
+
+// outcome of one doctor check
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self { name, status, detail: detail.into() }
+    }
+}
+
+pub fn check_textures(paths: &[&Path]) -> CheckResult {
+    // every texture has to exist, there are no fallbacks for them
+    let missing: Vec<String> = paths
+        .iter()
+        .filter(|path| !path.is_file())
+        .map(|path| path.display().to_string())
+        .collect();
+    if missing.is_empty() {
+        CheckResult::new("textures", CheckStatus::Pass, format!("{} found", paths.len()))
+    } else {
+        CheckResult::new("textures", CheckStatus::Fail, format!("missing {}", missing.join(", ")))
+    }
+}
+
+pub fn check_audio(play_tone: bool) -> CheckResult {
+    // opens the default output and plays a short quiet tone on it
+    let (_stream, handle) = match OutputStream::try_default() {
+        Ok(output) => output,
+        Err(e) => return CheckResult::new("audio", CheckStatus::Fail, format!("no output stream: {e}")),
+    };
+    let sink = match Sink::try_new(&handle) {
+        Ok(sink) => sink,
+        Err(e) => return CheckResult::new("audio", CheckStatus::Fail, format!("can't play on the output: {e}")),
+    };
+    if play_tone {
+        let tone = rodio::source::SineWave::new(440.0)
+            .take_duration(Duration::from_millis(100))
+            .amplify(0.05);
+        sink.append(tone);
+        sink.sleep_until_end();
+    }
+    CheckResult::new("audio", CheckStatus::Pass, "output stream opened")
+}
+
+pub fn check_songs_dir(dir: &Path) -> CheckResult {
+    // not there yet only matters once a map is opened from it
+    match fs::read_dir(dir) {
+        Ok(entries) => CheckResult::new("songs", CheckStatus::Pass, format!("{} entries in {}", entries.count(), dir.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            CheckResult::new("songs", CheckStatus::Warn, format!("{} doesn't exist", dir.display()))
+        }
+        Err(e) => CheckResult::new("songs", CheckStatus::Fail, format!("can't read {}: {}", dir.display(), e)),
+    }
+}
+
+pub fn check_config(path: &Path) -> CheckResult {
+    // a missing config is fine, the defaults are used
+    match fs::read_to_string(path) {
+        Ok(contents) => match Config::parse(&contents) {
+            Ok(_) => CheckResult::new("config", CheckStatus::Pass, format!("{} parses", path.display())),
+            Err(e) => CheckResult::new("config", CheckStatus::Fail, format!("{} doesn't parse: {}", path.display(), e)),
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            CheckResult::new("config", CheckStatus::Pass, "no config file, using the defaults")
+        }
+        Err(e) => CheckResult::new("config", CheckStatus::Fail, format!("can't read {}: {}", path.display(), e)),
+    }
+}
+
+fn reference_positions_hash(contents: &str) -> Result<u64> {
+    // initializes a chart and hashes the track positions of its notes, timing lines and groups at a few times
+    let mut map: Map = serde_yaml::from_str(contents)?;
+    map.length = 3000.0;
+    crate::initialize_map(&mut map, &set_reference_positions(None))?;
+    let mut positions: Vec<i64> = Vec::new();
+    for hit_object in &map.hit_objects {
+        positions.extend([hit_object.start_position, hit_object.start_position_tail]);
+    }
+    positions.extend(map.timing_lines.iter().map(|timing_line| timing_line.start_position));
+    for time in [0.0, 600.0, 1100.0, 1300.0, 2600.0] {
+        map.update_track_position(time);
+        positions.extend(map.timing_groups.values().map(|group| group.current_track_position));
+    }
+    let bytes: Vec<u8> = positions.iter().flat_map(|position| position.to_le_bytes()).collect();
+    Ok(chart_checksum(&bytes))
+}
+
+pub fn check_reference_map() -> CheckResult {
+    match reference_positions_hash(REFERENCE_MAP) {
+        Ok(REFERENCE_POSITIONS_HASH) => CheckResult::new("reference map", CheckStatus::Pass, "positions match"),
+        Ok(hash) => CheckResult::new(
+            "reference map",
+            CheckStatus::Fail,
+            format!("positions hash {hash:016x}, expected {REFERENCE_POSITIONS_HASH:016x}"),
+        ),
+        Err(e) => CheckResult::new("reference map", CheckStatus::Fail, format!("didn't initialize: {e}")),
+    }
+}
+
+pub fn report(results: &[CheckResult]) -> Result<()> {
+    // logs every check, and fails if any of them did
+    for result in results {
+        let line = format!("{}: {}", result.name, result.detail);
+        match result.status {
+            CheckStatus::Pass => logger::info(&line),
+            CheckStatus::Warn => logger::warning(&line),
+            CheckStatus::Fail => logger::error(&line),
+        }
+    }
+    let failed = results.iter().filter(|result| result.status == CheckStatus::Fail).count();
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} checks failed", results.len());
+    }
+    logger::info(&format!("All {} checks passed", results.len()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reference_map_positions_match() {
+        assert_eq!(reference_positions_hash(REFERENCE_MAP).unwrap(), REFERENCE_POSITIONS_HASH);
+        assert_eq!(check_reference_map().status, CheckStatus::Pass);
+    }
+
+    #[test]
+    fn reference_map_notices_changed_positions() {
+        // the same chart with its SVs scaled by the bpms places things elsewhere
+        let legacy = REFERENCE_MAP.replace("BPMDoesNotAffectScrollVelocity: true\n", "");
+        assert_ne!(reference_positions_hash(&legacy).unwrap(), REFERENCE_POSITIONS_HASH);
+    }
+
+    fn scratch_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("vsrg_doctor_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn missing_or_unreadable_songs_dirs_are_reported() {
+        let dir = scratch_dir("songs");
+        fs::write(dir.join("a.qua"), "").unwrap();
+        fs::create_dir(dir.join("mapset")).unwrap();
+        let found = check_songs_dir(&dir);
+        assert_eq!(found.status, CheckStatus::Pass);
+        assert!(found.detail.starts_with("2 entries"), "{}", found.detail);
+        assert_eq!(check_songs_dir(&dir.join("missing")).status, CheckStatus::Warn);
+        // a file where the directory should be
+        let not_a_dir = check_songs_dir(&dir.join("a.qua"));
+        assert_eq!(not_a_dir.status, CheckStatus::Fail);
+        assert!(not_a_dir.detail.starts_with("can't read"), "{}", not_a_dir.detail);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn broken_configs_fail_and_missing_ones_pass() {
+        let dir = scratch_dir("config");
+        let path = dir.join("config.toml");
+        assert_eq!(check_config(&path).status, CheckStatus::Pass);
+        fs::write(&path, "scroll_speed = 25.0\n").unwrap();
+        assert_eq!(check_config(&path).status, CheckStatus::Pass);
+        fs::write(&path, "scroll_speed = \"fast\"\n").unwrap();
+        let broken = check_config(&path);
+        assert_eq!(broken.status, CheckStatus::Fail);
+        assert!(broken.detail.contains("doesn't parse"), "{}", broken.detail);
+        assert_eq!(check_config(&dir).status, CheckStatus::Fail);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_missing_textures_are_listed() {
+        let dir = scratch_dir("textures");
+        let (found, missing) = (dir.join("receptor.png"), dir.join("note.png"));
+        fs::write(&found, "").unwrap();
+        assert_eq!(check_textures(&[&found]).status, CheckStatus::Pass);
+        let result = check_textures(&[&found, &missing]);
+        assert_eq!(result.status, CheckStatus::Fail);
+        assert_eq!(result.detail, format!("missing {}", missing.display()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn any_failed_check_fails_the_report() {
+        // the error is what main exits with, so a failed check is a non-zero exit code
+        let dir = scratch_dir("report");
+        fs::write(dir.join("config.toml"), "[broken").unwrap();
+        let passing = [check_songs_dir(&dir), check_reference_map()];
+        assert!(report(&passing).is_ok());
+        // warnings don't fail it
+        let warned = [check_songs_dir(&dir.join("missing")), check_reference_map()];
+        assert_eq!(warned[0].status, CheckStatus::Warn);
+        assert!(report(&warned).is_ok());
+        let failing = [check_songs_dir(&dir.join("missing")), check_config(&dir.join("config.toml")), check_reference_map()];
+        assert_eq!(report(&failing).unwrap_err().to_string(), "1 of 3 checks failed");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
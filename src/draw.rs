@@ -1,12 +1,23 @@
+use anyhow::{anyhow, Result};
+use image::{Rgba, RgbaImage};
 use macroquad::{color::Color, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// range of the render scale setting (offscreen resolution as a fraction of the window's)
+pub const MIN_RENDER_SCALE: f64 = 0.5;
+pub const MAX_RENDER_SCALE: f64 = 2.0;
 
 pub trait Draw {
     fn draw_rectangle(&mut self, x: f64, y: f64, w: f64, h: f64, color: Color);
+    fn draw_rectangle_outline(&mut self, x: f64, y: f64, w: f64, h: f64, thickness: f64, color: Color);
+    fn draw_quad(&mut self, corners: [(f64, f64); 4], color: Color); // filled, corners in order around it
     fn draw_line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, thickness: f64, color: Color);
     fn draw_circle(&mut self, x: f64, y: f64, radius: f64, color: Color);
     fn draw_circle_outline(&mut self, x: f64, y: f64, radius: f64, thickness: f64, color: Color);
-    // fn draw_text(&mut self, text: &str, x: f64, y: f64, size: f64, color: macroquad::color::Color);
-    fn draw_texture(&mut self, texture: &Texture2D, x: f64, y: f64, color: Color);
+    fn draw_text(&mut self, text: &str, x: f64, y: f64, size: f64, color: Color);
+    fn draw_texture_flipped(&mut self, texture: &Texture2D, x: f64, y: f64, color: Color, flip_y: bool); // upside down if flip_y
+    fn draw_texture_scaled(&mut self, texture: &Texture2D, x: f64, y: f64, w: f64, h: f64, color: Color); // stretched to w by h
     fn screen_height(&self) -> f64;
     fn screen_width(&self) -> f64;
 }
@@ -17,6 +28,14 @@ impl Draw for MacroquadDraw {
     fn draw_rectangle(&mut self, x: f64, y: f64, w: f64, h: f64, color: Color) {
         draw_rectangle(x as f32, y as f32, w as f32, h as f32, color);
     }
+    fn draw_rectangle_outline(&mut self, x: f64, y: f64, w: f64, h: f64, thickness: f64, color: Color) {
+        draw_rectangle_lines(x as f32, y as f32, w as f32, h as f32, thickness as f32, color);
+    }
+    fn draw_quad(&mut self, corners: [(f64, f64); 4], color: Color) {
+        let [a, b, c, d] = corners.map(|(x, y)| vec2(x as f32, y as f32));
+        draw_triangle(a, b, c, color);
+        draw_triangle(a, c, d, color);
+    }
     fn draw_line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, thickness: f64, color: Color) {
         draw_line(x1 as f32, y1 as f32, x2 as f32, y2 as f32, thickness as f32, color);
     }
@@ -26,11 +45,125 @@ impl Draw for MacroquadDraw {
     fn draw_circle_outline(&mut self, x: f64, y: f64, radius: f64, thickness: f64, color: Color) {
         draw_circle_lines(x as f32, y as f32, radius as f32, thickness as f32, color);
     }
-    // fn draw_text(&mut self, text: &str, x: f64, y: f64, size: f64, color: macroquad::color::Color) {
-    //     macroquad::text::draw_text(text, x as f32, y as f32, size as f32, color);
-    // }
-    fn draw_texture(&mut self, texture: &Texture2D, x: f64, y: f64, color: Color) {
-        draw_texture(texture, x as f32, y as f32, color);
+    fn draw_text(&mut self, text: &str, x: f64, y: f64, size: f64, color: Color) {
+        macroquad::text::draw_text(text, x as f32, y as f32, size as f32, color);
+    }
+    fn draw_texture_flipped(&mut self, texture: &Texture2D, x: f64, y: f64, color: Color, flip_y: bool) {
+        draw_texture_ex(texture, x as f32, y as f32, color, DrawTextureParams { flip_y, ..Default::default() });
+    }
+    fn draw_texture_scaled(&mut self, texture: &Texture2D, x: f64, y: f64, w: f64, h: f64, color: Color) {
+        let dest_size = Some(vec2(w as f32, h as f32));
+        draw_texture_ex(texture, x as f32, y as f32, color, DrawTextureParams { dest_size, ..Default::default() });
+    }
+    fn screen_height(&self) -> f64 {
+        f64::from(screen_height())
+    }
+    fn screen_width(&self) -> f64 {
+        f64::from(screen_width())
+    }
+}
+
+// how the offscreen scene is filtered when it's stretched over the window
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderFilter {
+    #[default]
+    Linear, // smooth
+    Nearest, // sharp pixels
+}
+
+impl From<RenderFilter> for FilterMode {
+    fn from(filter: RenderFilter) -> Self {
+        match filter {
+            RenderFilter::Linear => Self::Linear,
+            RenderFilter::Nearest => Self::Nearest,
+        }
+    }
+}
+
+pub fn render_target_size(window_width: f64, window_height: f64, scale: f64) -> (u32, u32) {
+    // pixels of the offscreen target for a window, at least one each way
+    let size = |length: f64| ((length * scale).round() as u32).max(1);
+    (size(window_width), size(window_height))
+}
+
+// draws into an offscreen target sized to the window times a scale, shown stretched over the window;
+// everything is still drawn in window coordinates (the camera does the scaling), so input needs no mapping
+pub struct OffscreenDraw {
+    target: RenderTarget,
+    size: (u32, u32),
+    scale: f64,
+    filter: RenderFilter,
+}
+
+impl OffscreenDraw {
+    pub fn new(scale: f64, filter: RenderFilter) -> Self {
+        let size = render_target_size(f64::from(screen_width()), f64::from(screen_height()), scale);
+        let target = render_target(size.0, size.1);
+        target.texture.set_filter(filter.into());
+        Self { target, size, scale, filter }
+    }
+
+    pub fn begin(&mut self) {
+        // points drawing at the target, made again first if the window was resized
+        let size = render_target_size(f64::from(screen_width()), f64::from(screen_height()), self.scale);
+        if size != self.size {
+            self.target = render_target(size.0, size.1);
+            self.target.texture.set_filter(self.filter.into());
+            self.size = size;
+        }
+        set_camera(&Camera2D {
+            render_target: Some(self.target.clone()),
+            ..Camera2D::from_display_rect(Rect::new(0.0, 0.0, screen_width(), screen_height()))
+        });
+        clear_background(BLACK);
+    }
+
+    pub fn present(&self) {
+        // back to drawing on the window, with the target stretched over it
+        set_default_camera();
+        draw_texture_ex(
+            &self.target.texture,
+            0.0,
+            0.0,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(vec2(screen_width(), screen_height())),
+                flip_y: true, // render targets are stored bottom row first
+                ..Default::default()
+            },
+        );
+    }
+}
+
+impl Draw for OffscreenDraw {
+    // the camera set by begin sends macroquad's own drawing to the target
+    fn draw_rectangle(&mut self, x: f64, y: f64, w: f64, h: f64, color: Color) {
+        MacroquadDraw.draw_rectangle(x, y, w, h, color);
+    }
+    fn draw_rectangle_outline(&mut self, x: f64, y: f64, w: f64, h: f64, thickness: f64, color: Color) {
+        MacroquadDraw.draw_rectangle_outline(x, y, w, h, thickness, color);
+    }
+    fn draw_quad(&mut self, corners: [(f64, f64); 4], color: Color) {
+        MacroquadDraw.draw_quad(corners, color);
+    }
+    fn draw_line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, thickness: f64, color: Color) {
+        MacroquadDraw.draw_line(x1, y1, x2, y2, thickness, color);
+    }
+    fn draw_circle(&mut self, x: f64, y: f64, radius: f64, color: Color) {
+        MacroquadDraw.draw_circle(x, y, radius, color);
+    }
+    fn draw_circle_outline(&mut self, x: f64, y: f64, radius: f64, thickness: f64, color: Color) {
+        MacroquadDraw.draw_circle_outline(x, y, radius, thickness, color);
+    }
+    fn draw_text(&mut self, text: &str, x: f64, y: f64, size: f64, color: Color) {
+        MacroquadDraw.draw_text(text, x, y, size, color);
+    }
+    fn draw_texture_flipped(&mut self, texture: &Texture2D, x: f64, y: f64, color: Color, flip_y: bool) {
+        MacroquadDraw.draw_texture_flipped(texture, x, y, color, flip_y);
+    }
+    fn draw_texture_scaled(&mut self, texture: &Texture2D, x: f64, y: f64, w: f64, h: f64, color: Color) {
+        MacroquadDraw.draw_texture_scaled(texture, x, y, w, h, color);
     }
     fn screen_height(&self) -> f64 {
         f64::from(screen_height())
@@ -39,3 +172,528 @@ impl Draw for MacroquadDraw {
         f64::from(screen_width())
     }
 }
+
+pub fn save_screenshot(path: &Path) -> Result<()> {
+    // saves what has been drawn to the screen so far this frame
+    let screen = get_screen_data();
+    let image = RgbaImage::from_raw(u32::from(screen.width), u32::from(screen.height), screen.bytes)
+        .ok_or_else(|| anyhow!("Screen data doesn't match its size"))?;
+    // gl reads the bottom row first
+    image::imageops::flip_vertical(&image)
+        .save(path)
+        .map_err(|e| anyhow!("Failed to save screenshot '{}': {}", path.display(), e))
+}
+
+// a vertical slice of another target that reports itself as the whole screen, so anything drawn
+// relative to the screen (like the playfield) can be drawn side by side
+pub struct Viewport<'a, D: Draw> {
+    pub target: &'a mut D,
+    pub x: f64,     // left edge of the slice on the target
+    pub width: f64, // width of the slice
+}
+
+impl<D: Draw> Draw for Viewport<'_, D> {
+    fn draw_rectangle(&mut self, x: f64, y: f64, w: f64, h: f64, color: Color) {
+        self.target.draw_rectangle(self.x + x, y, w, h, color);
+    }
+    fn draw_rectangle_outline(&mut self, x: f64, y: f64, w: f64, h: f64, thickness: f64, color: Color) {
+        self.target.draw_rectangle_outline(self.x + x, y, w, h, thickness, color);
+    }
+    fn draw_quad(&mut self, corners: [(f64, f64); 4], color: Color) {
+        self.target.draw_quad(corners.map(|(x, y)| (self.x + x, y)), color);
+    }
+    fn draw_line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, thickness: f64, color: Color) {
+        self.target.draw_line(self.x + x1, y1, self.x + x2, y2, thickness, color);
+    }
+    fn draw_circle(&mut self, x: f64, y: f64, radius: f64, color: Color) {
+        self.target.draw_circle(self.x + x, y, radius, color);
+    }
+    fn draw_circle_outline(&mut self, x: f64, y: f64, radius: f64, thickness: f64, color: Color) {
+        self.target.draw_circle_outline(self.x + x, y, radius, thickness, color);
+    }
+    fn draw_text(&mut self, text: &str, x: f64, y: f64, size: f64, color: Color) {
+        self.target.draw_text(text, self.x + x, y, size, color);
+    }
+    fn draw_texture_flipped(&mut self, texture: &Texture2D, x: f64, y: f64, color: Color, flip_y: bool) {
+        self.target.draw_texture_flipped(texture, self.x + x, y, color, flip_y);
+    }
+    fn draw_texture_scaled(&mut self, texture: &Texture2D, x: f64, y: f64, w: f64, h: f64, color: Color) {
+        self.target.draw_texture_scaled(texture, self.x + x, y, w, h, color);
+    }
+    fn screen_height(&self) -> f64 {
+        self.target.screen_height()
+    }
+    fn screen_width(&self) -> f64 {
+        self.width
+    }
+}
+
+// moves, rotates and zooms points around a pivot: zoomed and rotated about the pivot first, then offset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform2D {
+    pub pivot: (f64, f64),
+    pub offset: (f64, f64),
+    pub rotation: f64, // degrees, clockwise on screen
+    pub zoom: f64,
+}
+
+impl Transform2D {
+    pub const fn identity(pivot: (f64, f64)) -> Self {
+        Self { pivot, offset: (0.0, 0.0), rotation: 0.0, zoom: 1.0 }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.offset == (0.0, 0.0) && self.rotation % 360.0 == 0.0 && self.zoom == 1.0
+    }
+
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        // y points down, so this rotation matrix turns things clockwise on screen
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let (dx, dy) = ((x - self.pivot.0) * self.zoom, (y - self.pivot.1) * self.zoom);
+        (
+            self.pivot.0 + dx * cos - dy * sin + self.offset.0,
+            self.pivot.1 + dx * sin + dy * cos + self.offset.1,
+        )
+    }
+}
+
+// another target with a transform applied to everything drawn on it; rectangles turn into quads once
+// rotated, text and textures are only moved (and text zoomed), never turned
+pub struct Transformed<'a, D: Draw> {
+    pub target: &'a mut D,
+    pub transform: Transform2D,
+}
+
+impl<D: Draw> Transformed<'_, D> {
+    fn is_rotated(&self) -> bool {
+        self.transform.rotation % 360.0 != 0.0
+    }
+
+    fn corners(&self, x: f64, y: f64, w: f64, h: f64) -> [(f64, f64); 4] {
+        [(x, y), (x + w, y), (x + w, y + h), (x, y + h)].map(|(x, y)| self.transform.apply(x, y))
+    }
+}
+
+impl<D: Draw> Draw for Transformed<'_, D> {
+    fn draw_rectangle(&mut self, x: f64, y: f64, w: f64, h: f64, color: Color) {
+        if self.is_rotated() {
+            let corners = self.corners(x, y, w, h);
+            self.target.draw_quad(corners, color);
+        } else {
+            let (x, y) = self.transform.apply(x, y);
+            self.target.draw_rectangle(x, y, w * self.transform.zoom, h * self.transform.zoom, color);
+        }
+    }
+    fn draw_rectangle_outline(&mut self, x: f64, y: f64, w: f64, h: f64, thickness: f64, color: Color) {
+        let thickness = thickness * self.transform.zoom;
+        if self.is_rotated() {
+            let corners = self.corners(x, y, w, h);
+            for index in 0..4 {
+                let ((x1, y1), (x2, y2)) = (corners[index], corners[(index + 1) % 4]);
+                self.target.draw_line(x1, y1, x2, y2, thickness, color);
+            }
+        } else {
+            let (x, y) = self.transform.apply(x, y);
+            self.target.draw_rectangle_outline(x, y, w * self.transform.zoom, h * self.transform.zoom, thickness, color);
+        }
+    }
+    fn draw_quad(&mut self, corners: [(f64, f64); 4], color: Color) {
+        self.target.draw_quad(corners.map(|(x, y)| self.transform.apply(x, y)), color);
+    }
+    fn draw_line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, thickness: f64, color: Color) {
+        let ((x1, y1), (x2, y2)) = (self.transform.apply(x1, y1), self.transform.apply(x2, y2));
+        self.target.draw_line(x1, y1, x2, y2, thickness * self.transform.zoom, color);
+    }
+    fn draw_circle(&mut self, x: f64, y: f64, radius: f64, color: Color) {
+        let (x, y) = self.transform.apply(x, y);
+        self.target.draw_circle(x, y, radius * self.transform.zoom, color);
+    }
+    fn draw_circle_outline(&mut self, x: f64, y: f64, radius: f64, thickness: f64, color: Color) {
+        let (x, y) = self.transform.apply(x, y);
+        let zoom = self.transform.zoom;
+        self.target.draw_circle_outline(x, y, radius * zoom, thickness * zoom, color);
+    }
+    fn draw_text(&mut self, text: &str, x: f64, y: f64, size: f64, color: Color) {
+        let (x, y) = self.transform.apply(x, y);
+        self.target.draw_text(text, x, y, size * self.transform.zoom, color);
+    }
+    fn draw_texture_flipped(&mut self, texture: &Texture2D, x: f64, y: f64, color: Color, flip_y: bool) {
+        let (x, y) = self.transform.apply(x, y);
+        self.target.draw_texture_flipped(texture, x, y, color, flip_y);
+    }
+    fn draw_texture_scaled(&mut self, texture: &Texture2D, x: f64, y: f64, w: f64, h: f64, color: Color) {
+        // moved and zoomed, but not rotated
+        let (x, y) = self.transform.apply(x, y);
+        self.target.draw_texture_scaled(texture, x, y, w * self.transform.zoom, h * self.transform.zoom, color);
+    }
+    fn screen_height(&self) -> f64 {
+        self.target.screen_height()
+    }
+    fn screen_width(&self) -> f64 {
+        self.target.screen_width()
+    }
+}
+
+// one call made to a RecordingDraw, with positions rounded to 0.1 px and colors to 0.001, so tiny
+// float differences between runs (or platforms) don't count as a change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DrawCommand {
+    Rectangle { x: f64, y: f64, w: f64, h: f64, color: [f64; 4] },
+    RectangleOutline { x: f64, y: f64, w: f64, h: f64, thickness: f64, color: [f64; 4] },
+    Quad { corners: [[f64; 2]; 4], color: [f64; 4] },
+    Line { x1: f64, y1: f64, x2: f64, y2: f64, thickness: f64, color: [f64; 4] },
+    Circle { x: f64, y: f64, radius: f64, color: [f64; 4] },
+    CircleOutline { x: f64, y: f64, radius: f64, thickness: f64, color: [f64; 4] },
+    Text { text: String, x: f64, y: f64, size: f64, color: [f64; 4] },
+    Texture { x: f64, y: f64, color: [f64; 4], flip_y: bool }, // only where and which way up, not the texture itself
+    TextureScaled { x: f64, y: f64, w: f64, h: f64, color: [f64; 4] },
+}
+
+fn px(value: f64) -> f64 {
+    // + 0.0 turns -0.0 into 0.0
+    (value * 10.0).round() / 10.0 + 0.0
+}
+
+fn rgba(color: Color) -> [f64; 4] {
+    [color.r, color.g, color.b, color.a].map(|channel| (f64::from(channel) * 1000.0).round() / 1000.0 + 0.0)
+}
+
+// draws nothing, only records what it was asked to draw in order, for comparing frames without a window
+pub struct RecordingDraw {
+    pub width: f64,
+    pub height: f64,
+    pub commands: Vec<DrawCommand>,
+}
+
+impl RecordingDraw {
+    pub fn new(width: f64, height: f64) -> Self {
+        Self { width, height, commands: Vec::new() }
+    }
+}
+
+impl Draw for RecordingDraw {
+    fn draw_rectangle(&mut self, x: f64, y: f64, w: f64, h: f64, color: Color) {
+        self.commands.push(DrawCommand::Rectangle { x: px(x), y: px(y), w: px(w), h: px(h), color: rgba(color) });
+    }
+    fn draw_rectangle_outline(&mut self, x: f64, y: f64, w: f64, h: f64, thickness: f64, color: Color) {
+        self.commands.push(DrawCommand::RectangleOutline {
+            x: px(x),
+            y: px(y),
+            w: px(w),
+            h: px(h),
+            thickness: px(thickness),
+            color: rgba(color),
+        });
+    }
+    fn draw_quad(&mut self, corners: [(f64, f64); 4], color: Color) {
+        self.commands.push(DrawCommand::Quad { corners: corners.map(|(x, y)| [px(x), px(y)]), color: rgba(color) });
+    }
+    fn draw_line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, thickness: f64, color: Color) {
+        self.commands.push(DrawCommand::Line {
+            x1: px(x1),
+            y1: px(y1),
+            x2: px(x2),
+            y2: px(y2),
+            thickness: px(thickness),
+            color: rgba(color),
+        });
+    }
+    fn draw_circle(&mut self, x: f64, y: f64, radius: f64, color: Color) {
+        self.commands.push(DrawCommand::Circle { x: px(x), y: px(y), radius: px(radius), color: rgba(color) });
+    }
+    fn draw_circle_outline(&mut self, x: f64, y: f64, radius: f64, thickness: f64, color: Color) {
+        self.commands.push(DrawCommand::CircleOutline {
+            x: px(x),
+            y: px(y),
+            radius: px(radius),
+            thickness: px(thickness),
+            color: rgba(color),
+        });
+    }
+    fn draw_text(&mut self, text: &str, x: f64, y: f64, size: f64, color: Color) {
+        self.commands.push(DrawCommand::Text { text: text.to_string(), x: px(x), y: px(y), size: px(size), color: rgba(color) });
+    }
+    fn draw_texture_flipped(&mut self, _texture: &Texture2D, x: f64, y: f64, color: Color, flip_y: bool) {
+        self.commands.push(DrawCommand::Texture { x: px(x), y: px(y), color: rgba(color), flip_y });
+    }
+    fn draw_texture_scaled(&mut self, _texture: &Texture2D, x: f64, y: f64, w: f64, h: f64, color: Color) {
+        self.commands.push(DrawCommand::TextureScaled { x: px(x), y: px(y), w: px(w), h: px(h), color: rgba(color) });
+    }
+    fn screen_height(&self) -> f64 {
+        self.height
+    }
+    fn screen_width(&self) -> f64 {
+        self.width
+    }
+}
+
+// 3x5 pixel glyphs for text drawn without a gpu, rows top to bottom (lowercase draws as uppercase)
+const GLYPHS: &[(char, u16)] = &[
+    ('0', 0b111_101_101_101_111),
+    ('1', 0b010_110_010_010_111),
+    ('2', 0b111_001_111_100_111),
+    ('3', 0b111_001_111_001_111),
+    ('4', 0b101_101_111_001_001),
+    ('5', 0b111_100_111_001_111),
+    ('6', 0b111_100_111_101_111),
+    ('7', 0b111_001_001_001_001),
+    ('8', 0b111_101_111_101_111),
+    ('9', 0b111_101_111_001_111),
+    ('A', 0b010_101_111_101_101),
+    ('B', 0b110_101_110_101_110),
+    ('C', 0b011_100_100_100_011),
+    ('D', 0b110_101_101_101_110),
+    ('E', 0b111_100_110_100_111),
+    ('F', 0b111_100_110_100_100),
+    ('G', 0b011_100_101_101_011),
+    ('H', 0b101_101_111_101_101),
+    ('I', 0b111_010_010_010_111),
+    ('J', 0b001_001_001_101_010),
+    ('K', 0b101_101_110_101_101),
+    ('L', 0b100_100_100_100_111),
+    ('M', 0b101_111_111_101_101),
+    ('N', 0b110_101_101_101_101),
+    ('O', 0b010_101_101_101_010),
+    ('P', 0b110_101_110_100_100),
+    ('Q', 0b010_101_101_110_011),
+    ('R', 0b110_101_110_101_101),
+    ('S', 0b011_100_010_001_110),
+    ('T', 0b111_010_010_010_010),
+    ('U', 0b101_101_101_101_111),
+    ('V', 0b101_101_101_101_010),
+    ('W', 0b101_101_111_111_101),
+    ('X', 0b101_101_010_101_101),
+    ('Y', 0b101_101_010_010_010),
+    ('Z', 0b111_001_010_100_111),
+    ('.', 0b000_000_000_000_010),
+    (',', 0b000_000_000_010_100),
+    (':', 0b000_010_000_010_000),
+    ('-', 0b000_000_111_000_000),
+    ('+', 0b000_010_111_010_000),
+    ('[', 0b110_100_100_100_110),
+    (']', 0b011_001_001_001_011),
+    ('(', 0b010_100_100_100_010),
+    (')', 0b010_001_001_001_010),
+    ('/', 0b001_001_010_100_100),
+    ('%', 0b101_001_010_100_101),
+    ('_', 0b000_000_000_000_111),
+    ('\'', 0b010_010_000_000_000),
+    ('!', 0b010_010_010_000_010),
+    ('?', 0b110_001_010_000_010),
+    ('|', 0b010_010_010_010_010),
+    ('=', 0b000_111_000_111_000),
+    ('>', 0b100_010_001_010_100),
+    ('<', 0b001_010_100_010_001),
+];
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+// draws into an image on the cpu, for output that doesn't need a window (thumbnails, exports)
+pub struct SoftwareDraw {
+    pub image: RgbaImage,
+}
+
+impl SoftwareDraw {
+    pub fn new(width: u32, height: u32, background: Color) -> Self {
+        Self {
+            image: RgbaImage::from_pixel(width, height, to_rgba(background)),
+        }
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<()> {
+        self.image
+            .save(path)
+            .map_err(|e| anyhow!("Failed to save image '{}': {}", path.display(), e))
+    }
+
+    fn blend_pixel(&mut self, x: i64, y: i64, color: Color) {
+        // alpha-blends a color over one pixel, ignoring anything off the image
+        if x < 0 || y < 0 || x >= i64::from(self.image.width()) || y >= i64::from(self.image.height()) {
+            return;
+        }
+        let pixel = self.image.get_pixel_mut(x as u32, y as u32);
+        let alpha = color.a.clamp(0.0, 1.0);
+        let source = [color.r, color.g, color.b];
+        for (channel, value) in pixel.0.iter_mut().take(3).zip(source) {
+            let blended = f32::from(*channel) / 255.0 * (1.0 - alpha) + value.clamp(0.0, 1.0) * alpha;
+            *channel = (blended * 255.0).round() as u8;
+        }
+        pixel.0[3] = pixel.0[3].max((alpha * 255.0).round() as u8);
+    }
+}
+
+fn to_rgba(color: Color) -> Rgba<u8> {
+    Rgba([
+        (color.r.clamp(0.0, 1.0) * 255.0).round() as u8,
+        (color.g.clamp(0.0, 1.0) * 255.0).round() as u8,
+        (color.b.clamp(0.0, 1.0) * 255.0).round() as u8,
+        (color.a.clamp(0.0, 1.0) * 255.0).round() as u8,
+    ])
+}
+
+impl Draw for SoftwareDraw {
+    fn draw_rectangle(&mut self, x: f64, y: f64, w: f64, h: f64, color: Color) {
+        // normalize negative sizes (LN bodies are drawn upwards)
+        let (left, right) = (x.min(x + w), x.max(x + w));
+        let (top, bottom) = (y.min(y + h), y.max(y + h));
+        let width = f64::from(self.image.width());
+        let height = f64::from(self.image.height());
+        let (left, right) = (left.round().max(0.0) as i64, right.round().min(width) as i64);
+        let (top, bottom) = (top.round().max(0.0) as i64, bottom.round().min(height) as i64);
+        for py in top..bottom {
+            for px in left..right {
+                self.blend_pixel(px, py, color);
+            }
+        }
+    }
+    fn draw_rectangle_outline(&mut self, x: f64, y: f64, w: f64, h: f64, thickness: f64, color: Color) {
+        self.draw_rectangle(x, y, w, thickness, color);
+        self.draw_rectangle(x, y + h - thickness, w, thickness, color);
+        self.draw_rectangle(x, y + thickness, thickness, h - thickness * 2.0, color);
+        self.draw_rectangle(x + w - thickness, y + thickness, thickness, h - thickness * 2.0, color);
+    }
+    fn draw_quad(&mut self, corners: [(f64, f64); 4], color: Color) {
+        // fills the pixels whose centers are inside the (convex) quad, wound either way
+        let (xs, ys) = (corners.map(|(x, _)| x), corners.map(|(_, y)| y));
+        let (left, right) = (xs.iter().copied().fold(f64::INFINITY, f64::min), xs.iter().copied().fold(f64::NEG_INFINITY, f64::max));
+        let (top, bottom) = (ys.iter().copied().fold(f64::INFINITY, f64::min), ys.iter().copied().fold(f64::NEG_INFINITY, f64::max));
+        let width = f64::from(self.image.width());
+        let height = f64::from(self.image.height());
+        for py in top.floor().max(0.0) as i64..bottom.ceil().min(height) as i64 {
+            for px in left.floor().max(0.0) as i64..right.ceil().min(width) as i64 {
+                let (x, y) = (px as f64 + 0.5, py as f64 + 0.5);
+                let sides = (0..4).map(|index| {
+                    let ((x1, y1), (x2, y2)) = (corners[index], corners[(index + 1) % 4]);
+                    (x2 - x1) * (y - y1) - (y2 - y1) * (x - x1)
+                });
+                let (mut inside_left, mut inside_right) = (true, true);
+                for side in sides {
+                    inside_left &= side <= 0.0;
+                    inside_right &= side >= 0.0;
+                }
+                if inside_left || inside_right {
+                    self.blend_pixel(px, py, color);
+                }
+            }
+        }
+    }
+    fn draw_line(&mut self, x1: f64, y1: f64, x2: f64, y2: f64, thickness: f64, color: Color) {
+        // stamps squares along the line; good enough for the mostly axis-aligned lines we draw
+        let half = (thickness / 2.0).max(0.5);
+        let steps = (x2 - x1).abs().max((y2 - y1).abs()).ceil().max(1.0) as i64;
+        let mut stamped = std::collections::HashSet::new();
+        for step in 0..=steps {
+            let t = step as f64 / steps as f64;
+            let cx = x1 + (x2 - x1) * t;
+            let cy = y1 + (y2 - y1) * t;
+            for py in (cy - half).round() as i64..(cy + half).round() as i64 {
+                for px in (cx - half).round() as i64..(cx + half).round() as i64 {
+                    // don't blend the same pixel twice where stamps overlap
+                    if stamped.insert((px, py)) {
+                        self.blend_pixel(px, py, color);
+                    }
+                }
+            }
+        }
+    }
+    fn draw_circle(&mut self, x: f64, y: f64, radius: f64, color: Color) {
+        for py in (y - radius).floor() as i64..=(y + radius).ceil() as i64 {
+            for px in (x - radius).floor() as i64..=(x + radius).ceil() as i64 {
+                let (dx, dy) = (px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+                if dx * dx + dy * dy <= radius * radius {
+                    self.blend_pixel(px, py, color);
+                }
+            }
+        }
+    }
+    fn draw_circle_outline(&mut self, x: f64, y: f64, radius: f64, thickness: f64, color: Color) {
+        let inner = (radius - thickness).max(0.0);
+        for py in (y - radius).floor() as i64..=(y + radius).ceil() as i64 {
+            for px in (x - radius).floor() as i64..=(x + radius).ceil() as i64 {
+                let (dx, dy) = (px as f64 + 0.5 - x, py as f64 + 0.5 - y);
+                let distance = dx * dx + dy * dy;
+                if distance <= radius * radius && distance >= inner * inner {
+                    self.blend_pixel(px, py, color);
+                }
+            }
+        }
+    }
+    fn draw_text(&mut self, text: &str, x: f64, y: f64, size: f64, color: Color) {
+        // y is the baseline, like macroquad's draw_text
+        let scale = ((size / 7.0).round() as i64).max(1);
+        let top = y.round() as i64 - i64::from(GLYPH_HEIGHT) * scale;
+        let mut left = x.round() as i64;
+        for character in text.chars() {
+            let character = character.to_ascii_uppercase();
+            if let Some(&(_, bits)) = GLYPHS.iter().find(|(glyph, _)| *glyph == character) {
+                for row in 0..GLYPH_HEIGHT {
+                    for column in 0..GLYPH_WIDTH {
+                        let bit = (GLYPH_HEIGHT - 1 - row) * GLYPH_WIDTH + (GLYPH_WIDTH - 1 - column);
+                        if bits & (1 << bit) == 0 {
+                            continue;
+                        }
+                        let px = left + i64::from(column) * scale;
+                        let py = top + i64::from(row) * scale;
+                        self.draw_rectangle(px as f64, py as f64, scale as f64, scale as f64, color);
+                    }
+                }
+            }
+            left += i64::from(GLYPH_WIDTH + 1) * scale;
+        }
+    }
+    fn draw_texture_flipped(&mut self, _texture: &Texture2D, _x: f64, _y: f64, _color: Color, _flip_y: bool) {
+        // textures only exist on the gpu, so they're skipped here
+    }
+    fn draw_texture_scaled(&mut self, _texture: &Texture2D, _x: f64, _y: f64, _w: f64, _h: f64, _color: Color) {}
+    fn screen_height(&self) -> f64 {
+        f64::from(self.image.height())
+    }
+    fn screen_width(&self) -> f64 {
+        f64::from(self.image.width())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_target_is_the_window_times_the_scale() {
+        assert_eq!(render_target_size(1920.0, 1080.0, 1.0), (1920, 1080));
+        assert_eq!(render_target_size(1920.0, 1080.0, MIN_RENDER_SCALE), (960, 540));
+        assert_eq!(render_target_size(1920.0, 1080.0, 0.75), (1440, 810));
+        assert_eq!(render_target_size(3840.0, 2160.0, MAX_RENDER_SCALE), (7680, 4320));
+        // odd sizes round to the nearest pixel, and a minimized window still gets a target
+        assert_eq!(render_target_size(1001.0, 601.0, 0.5), (501, 301));
+        assert_eq!(render_target_size(0.0, 1.0, 0.5), (1, 1));
+    }
+
+    #[test]
+    fn window_points_land_on_the_same_spot_of_the_target() {
+        // the target is stretched over the whole window, so a point (like the mouse on the progress bar) is at the
+        // same fraction of both; rounding the target's size moves it by less than a target pixel
+        for (width, height) in [(1280.0, 720.0), (1001.0, 601.0), (3840.0, 2160.0)] {
+            for scale in [MIN_RENDER_SCALE, 0.75, 1.0, 1.5, MAX_RENDER_SCALE] {
+                let (target_width, target_height) = render_target_size(width, height, scale);
+                for (x, y) in [(0.0, 0.0), (width / 3.0, height / 2.0), (width, height)] {
+                    let target_x = x / width * f64::from(target_width);
+                    let target_y = y / height * f64::from(target_height);
+                    assert!((target_x - x * scale).abs() < 1.0, "{width}x{height} at {scale}: x {x} -> {target_x}");
+                    assert!((target_y - y * scale).abs() < 1.0, "{width}x{height} at {scale}: y {y} -> {target_y}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn render_filter_is_written_by_name() {
+        #[derive(Serialize, Deserialize)]
+        struct Settings {
+            render_filter: RenderFilter,
+        }
+        let settings: Settings = toml::from_str("render_filter = \"nearest\"").unwrap();
+        assert_eq!(settings.render_filter, RenderFilter::Nearest);
+        assert_eq!(FilterMode::from(settings.render_filter), FilterMode::Nearest);
+    }
+}
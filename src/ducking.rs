@@ -0,0 +1,114 @@
+// lowers the music while loud keysounds stack up, so the two together don't clip
+
+// ms for the envelope to rise most of the way to a louder level, short so the music is down before it clips
+const ATTACK: f64 = 5.0;
+
+// when and how far the music is lowered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DuckingSettings {
+    pub threshold: f64,     // summed keysound amplitude (1 = one sample at full scale) the music starts going down at
+    pub max_reduction: f64, // dB the music is lowered by at most
+    pub release: f64,       // ms for the music to come most of the way back once it's quieter
+}
+
+pub fn db_to_gain(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+pub fn follow_envelope(envelope: f64, amplitude: f64, elapsed: f64, attack: f64, release: f64) -> f64 {
+    // moves the envelope toward this frame's amplitude, within `attack` ms when it's louder and
+    // `release` ms when it's quieter, the same however long the frame was
+    let time_constant = if amplitude > envelope { attack } else { release };
+    if time_constant <= 0.0 {
+        return amplitude;
+    }
+    let coefficient = 1.0 - (-elapsed.max(0.0) / time_constant).exp();
+    envelope + (amplitude - envelope) * coefficient
+}
+
+pub fn ducking_gain(envelope: f64, threshold: f64, max_reduction: f64) -> f64 {
+    // music gain (linear): down by as many dB as the envelope is over the threshold, up to max_reduction
+    if envelope <= threshold || threshold <= 0.0 || max_reduction <= 0.0 {
+        return 1.0;
+    }
+    let over = 20.0 * (envelope / threshold).log10();
+    db_to_gain(-over.min(max_reduction))
+}
+
+// the envelope of the keysounds playing, and the gain it gives the music
+#[derive(Debug, Clone, Copy)]
+pub struct Ducker {
+    pub settings: DuckingSettings,
+    envelope: f64,
+}
+
+impl Ducker {
+    pub const fn new(settings: DuckingSettings) -> Self {
+        Self { settings, envelope: 0.0 }
+    }
+
+    pub fn update(&mut self, amplitude: f64, elapsed: f64) -> f64 {
+        // takes this frame's summed keysound amplitude and the ms since the last one, returns the music gain
+        self.envelope = follow_envelope(self.envelope, amplitude, elapsed, ATTACK, self.settings.release);
+        ducking_gain(self.envelope, self.settings.threshold, self.settings.max_reduction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: DuckingSettings = DuckingSettings { threshold: 1.0, max_reduction: 12.0, release: 300.0 };
+
+    fn gain_to_db(gain: f64) -> f64 {
+        20.0 * gain.log10()
+    }
+
+    fn run(ducker: &mut Ducker, amplitude: f64, ms: usize) -> f64 {
+        // a ms at a time, the music gain after the last one
+        (0..ms).map(|_| ducker.update(amplitude, 1.0)).last().unwrap_or(1.0)
+    }
+
+    fn ducker_after(amplitude: f64, ms: usize) -> f64 {
+        run(&mut Ducker::new(SETTINGS), amplitude, ms)
+    }
+
+    #[test]
+    fn music_goes_down_within_the_attack() {
+        // twice the threshold is 6 dB over it: the envelope is 1 - 1/e of the way there after 5 ms
+        // (2 dB down), and all of it after 25
+        assert!((gain_to_db(ducker_after(2.0, 5)) + 2.04).abs() < 0.05);
+        assert!((gain_to_db(ducker_after(2.0, 25)) + 6.02).abs() < 0.1);
+        // the same in one long frame as in many short ones
+        assert!((Ducker::new(SETTINGS).update(2.0, 25.0) - ducker_after(2.0, 25)).abs() < 1e-9);
+        // quiet keysounds leave the music alone
+        assert_eq!(ducker_after(0.9, 100), 1.0);
+    }
+
+    #[test]
+    fn music_comes_back_over_the_release() {
+        let mut ducker = Ducker::new(SETTINGS);
+        run(&mut ducker, 2.0, 50);
+        // a third of the release in, the envelope is still over the threshold
+        let after_100 = run(&mut ducker, 0.0, 100);
+        assert!(after_100 < 0.9, "{after_100}");
+        // a whole release after it went quiet the envelope is down to 1/e of twice the threshold, so it's back up
+        assert_eq!(run(&mut ducker, 0.0, 200), 1.0);
+
+        // a longer release keeps it down for longer
+        let mut slow = Ducker::new(DuckingSettings { release: 1000.0, ..SETTINGS });
+        run(&mut slow, 2.0, 50);
+        assert!(run(&mut slow, 0.0, 300) < 1.0);
+    }
+
+    #[test]
+    fn music_is_lowered_by_the_max_reduction_at_most() {
+        // 40 dB over the threshold, held to 12
+        let gain = ducker_after(100.0, 100);
+        assert!((gain - db_to_gain(-12.0)).abs() < 1e-9, "{gain}");
+        assert!((gain_to_db(gain) + 12.0).abs() < 1e-9);
+        // none at all turns it off, as does no threshold
+        assert_eq!(ducking_gain(100.0, 1.0, 0.0), 1.0);
+        assert_eq!(ducking_gain(100.0, 0.0, 12.0), 1.0);
+    }
+}
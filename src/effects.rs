@@ -0,0 +1,239 @@
+use crate::draw::Transform2D;
+use crate::utils::{index_at_time, lerp, sort_by_start_time, HasStartTime, Time};
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+// optional file in a map's directory with keyframes that move, turn and zoom the playfield (for SV
+// showcases); only what's drawn changes, judging and input go by time like always
+pub const EFFECTS_FILE: &str = "effects.yaml";
+
+fn one() -> f64 {
+    1.0
+}
+
+// how a keyframe is reached from the one before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum Easing {
+    #[default]
+    Linear,
+    Step, // stays at the previous keyframe, then jumps
+    InQuad,
+    OutQuad,
+    InOutQuad,
+    InCubic,
+    OutCubic,
+    InOutCubic,
+    InOutSine,
+}
+
+impl Easing {
+    pub fn apply(self, progress: f64) -> f64 {
+        // progress (0-1) between two keyframes to how far along the values are
+        let t = progress.clamp(0.0, 1.0);
+        match self {
+            Self::Linear => t,
+            Self::Step => {
+                if t < 1.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            Self::InQuad => t * t,
+            Self::OutQuad => 1.0 - (1.0 - t) * (1.0 - t),
+            Self::InOutQuad => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(2) / 2.0
+                }
+            }
+            Self::InCubic => t * t * t,
+            Self::OutCubic => 1.0 - (1.0 - t).powi(3),
+            Self::InOutCubic => {
+                if t < 0.5 {
+                    4.0 * t * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+            Self::InOutSine => -((std::f64::consts::PI * t).cos() - 1.0) / 2.0,
+        }
+    }
+}
+
+// where the playfield is from a time on
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct PlayfieldKeyframe {
+    pub start_time: Time,
+    #[serde(default)]
+    pub x: f64, // px to the right
+    #[serde(default)]
+    pub y: f64, // px down
+    #[serde(default)]
+    pub rotation: f64, // degrees clockwise, around the middle of the playfield
+    #[serde(default = "one")]
+    pub zoom: f64,
+    #[serde(default)]
+    pub easing: Easing, // how it's reached from the keyframe before
+}
+
+impl HasStartTime for PlayfieldKeyframe {
+    fn start_time(&self) -> Time {
+        self.start_time
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct Effects {
+    #[serde(default)]
+    pub playfield: Vec<PlayfieldKeyframe>,
+}
+
+pub fn parse_effects(contents: &str) -> Result<Effects> {
+    let mut effects: Effects = serde_yaml::from_str(contents)?;
+    for keyframe in &effects.playfield {
+        let values = [keyframe.start_time, keyframe.x, keyframe.y, keyframe.rotation, keyframe.zoom];
+        if values.iter().any(|value| !value.is_finite()) {
+            bail!("Keyframe at {} ms has a value that isn't a number", keyframe.start_time);
+        }
+        if keyframe.zoom <= 0.0 {
+            bail!("Keyframe at {} ms has zoom {}, it has to be above 0", keyframe.start_time, keyframe.zoom);
+        }
+    }
+    sort_by_start_time(&mut effects.playfield);
+    Ok(effects)
+}
+
+pub fn load_effects(map_dir: &Path) -> Result<Option<Effects>> {
+    // reads the effects file from a map directory, if it has one
+    let path = map_dir.join(EFFECTS_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| anyhow!("Failed to read '{}': {}", path.display(), e))?;
+    parse_effects(&contents)
+        .map(Some)
+        .map_err(|e| anyhow!("Failed to parse '{}': {}", path.display(), e))
+}
+
+pub fn playfield_at(keyframes: &[PlayfieldKeyframe], time: Time) -> Option<PlayfieldKeyframe> {
+    // the playfield's values at a time, eased between the keyframes around it; None before the first
+    // keyframe, where the playfield isn't moved (like SSFs, nothing eases into the first one)
+    let index = index_at_time(keyframes, time)?;
+    let keyframe = keyframes[index];
+    let Some(next) = keyframes.get(index + 1) else {
+        return Some(keyframe);
+    };
+    let eased = next.easing.apply((time - keyframe.start_time) / (next.start_time - keyframe.start_time));
+    Some(PlayfieldKeyframe {
+        start_time: time,
+        x: lerp(keyframe.x, next.x, eased),
+        y: lerp(keyframe.y, next.y, eased),
+        rotation: lerp(keyframe.rotation, next.rotation, eased),
+        zoom: lerp(keyframe.zoom, next.zoom, eased),
+        easing: next.easing,
+    })
+}
+
+pub fn playfield_transform(keyframes: &[PlayfieldKeyframe], time: Time, pivot: (f64, f64)) -> Option<Transform2D> {
+    // the transform to draw the playfield with, None when it isn't moved at all
+    let playfield = playfield_at(keyframes, time)?;
+    let transform = Transform2D {
+        pivot,
+        offset: (playfield.x, playfield.y),
+        rotation: playfield.rotation,
+        zoom: playfield.zoom,
+    };
+    (!transform.is_identity()).then_some(transform)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYFRAMES: &str = "\
+Playfield:
+- StartTime: 2000
+  X: 100
+  Rotation: 90
+  Zoom: 2
+- StartTime: 1000
+- StartTime: 3000
+  X: 100
+  Y: -50
+  Rotation: 90
+  Zoom: 2
+  Easing: InQuad
+- StartTime: 4000
+  Easing: Step
+";
+
+    fn at(effects: &Effects, time: Time) -> (f64, f64, f64, f64) {
+        let playfield = playfield_at(&effects.playfield, time).unwrap();
+        (playfield.x, playfield.y, playfield.rotation, playfield.zoom)
+    }
+
+    #[test]
+    fn keyframes_are_parsed_in_time_order_with_defaults() {
+        let effects = parse_effects(KEYFRAMES).unwrap();
+        let times: Vec<Time> = effects.playfield.iter().map(|keyframe| keyframe.start_time).collect();
+        assert_eq!(times, [1000.0, 2000.0, 3000.0, 4000.0]);
+        // left out, a keyframe is the playfield where it always is, reached linearly
+        assert_eq!(
+            effects.playfield[0],
+            PlayfieldKeyframe { start_time: 1000.0, x: 0.0, y: 0.0, rotation: 0.0, zoom: 1.0, easing: Easing::Linear }
+        );
+        assert_eq!(effects.playfield[2].easing, Easing::InQuad);
+        assert_eq!(parse_effects("").unwrap(), Effects::default());
+    }
+
+    #[test]
+    fn broken_keyframes_are_errors() {
+        assert!(parse_effects("Playfield:\n- StartTime: 0\n  Spin: 3\n").is_err());
+        assert!(parse_effects("Playfield:\n- StartTime: 0\n  Easing: Bounce\n").is_err());
+        let error = parse_effects("Playfield:\n- StartTime: 500\n  Zoom: 0\n").unwrap_err();
+        assert_eq!(error.to_string(), "Keyframe at 500 ms has zoom 0, it has to be above 0");
+        let error = parse_effects("Playfield:\n- StartTime: 500\n  X: .nan\n").unwrap_err();
+        assert_eq!(error.to_string(), "Keyframe at 500 ms has a value that isn't a number");
+    }
+
+    #[test]
+    fn playfield_is_eased_between_keyframes() {
+        let effects = parse_effects(KEYFRAMES).unwrap();
+        // not moved before the first keyframe, and held after the last
+        assert_eq!(playfield_at(&effects.playfield, 999.0), None);
+        assert_eq!(at(&effects, 1000.0), (0.0, 0.0, 0.0, 1.0));
+        assert_eq!(at(&effects, 5000.0), (0.0, 0.0, 0.0, 1.0));
+        // linear halfway, then in-quad a quarter of the way at the halfway point
+        assert_eq!(at(&effects, 1500.0), (50.0, 0.0, 45.0, 1.5));
+        assert_eq!(at(&effects, 2500.0), (100.0, -12.5, 90.0, 2.0));
+        // a step holds until the keyframe is reached
+        assert_eq!(at(&effects, 3999.0), (100.0, -50.0, 90.0, 2.0));
+        assert_eq!(at(&effects, 4000.0), (0.0, 0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn easings_start_at_0_and_end_at_1() {
+        use Easing::*;
+        for easing in [Linear, Step, InQuad, OutQuad, InOutQuad, InCubic, OutCubic, InOutCubic, InOutSine] {
+            assert_eq!(easing.apply(0.0), 0.0, "{easing:?}");
+            assert!((easing.apply(1.0) - 1.0).abs() < 1e-12, "{easing:?}");
+            // and stay there past either end
+            assert_eq!((easing.apply(-1.0), easing.apply(2.0)), (easing.apply(0.0), easing.apply(1.0)), "{easing:?}");
+        }
+        assert_eq!((OutQuad.apply(0.5), InOutCubic.apply(0.25)), (0.75, 0.0625));
+        assert!((InOutSine.apply(0.5) - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn unmoved_playfield_has_no_transform() {
+        let effects = parse_effects(KEYFRAMES).unwrap();
+        assert_eq!(playfield_transform(&effects.playfield, 1000.0, (500.0, 600.0)), None);
+        let Transform2D { pivot, offset, rotation, zoom } = playfield_transform(&effects.playfield, 2000.0, (500.0, 600.0)).unwrap();
+        assert_eq!((pivot, offset, rotation, zoom), ((500.0, 600.0), (100.0, 0.0), 90.0, 2.0));
+    }
+}
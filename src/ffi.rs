@@ -0,0 +1,210 @@
+// a C interface to the chart, position and judgement code, for embedding it in other engines;
+// nothing here draws or plays audio, so it works without a window (include/vsrg_renderer.h declares it).
+// A panic can't unwind into the host (it would abort it), so each function catches its own and
+// returns what it returns on failure instead
+
+use crate::logger;
+use crate::map::Map;
+use crate::render::{set_reference_positions, update_frame, FrameState};
+use crate::trace::{visible_notes, TraceNote};
+use std::ffi::{c_char, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+// screen height (px) notes are placed in, y is from its top
+const VIEW_HEIGHT: f64 = 1080.0;
+// returned by vsrg_key_event when nothing was judged (a ghost tap, or a release with nothing held)
+const NO_JUDGEMENT: i32 = -1;
+// returned by vsrg_update
+const UPDATED: i32 = 0;
+const UPDATE_FAILED: i32 = -1;
+
+fn guarded<T>(function: &str, on_panic: T, body: impl FnOnce() -> T) -> T {
+    // runs a function's body, logging a panic and returning on_panic instead of unwinding into the
+    // host; the handle may be left partway through an update, but can still be freed
+    catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| (*message).to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        logger::error(&format!("{function} panicked: {message}"));
+        on_panic
+    })
+}
+
+/// A loaded chart and its play state.
+pub struct VsrgMap {
+    map: Map,
+    notes: Vec<TraceNote>, // scratch for vsrg_visible_notes
+}
+
+/// A note on screen.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VsrgNote {
+    /// Index in the chart's hit objects.
+    pub index: u32,
+    /// Screen y of the note's head, from the top of a 1080 px view.
+    pub y: f32,
+    /// 0-indexed column it's drawn in (after mirror).
+    pub lane: u8,
+    /// 1 while it's an LN being held.
+    pub held: u8,
+    /// Beat snap color, an index into 1/1, 1/2, 1/3, 1/4, 1/6, 1/8, 1/12, 1/16, 1/48.
+    pub color_index: u8,
+}
+
+/// Loads a .qua and gets it ready to play at 1x. Returns null if it can't be loaded.
+///
+/// # Safety
+///
+/// `path` has to be a valid, nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn vsrg_load_map(path: *const c_char) -> *mut VsrgMap {
+    if path.is_null() {
+        return std::ptr::null_mut();
+    }
+    let Ok(path) = unsafe { CStr::from_ptr(path) }.to_str() else {
+        return std::ptr::null_mut();
+    };
+    guarded("vsrg_load_map", std::ptr::null_mut(), || {
+        let load = || -> anyhow::Result<Map> {
+            let mut map = Map::from_file(Path::new(path))?;
+            map.rate = 1.0;
+            crate::initialize_map(&mut map, &set_reference_positions(None))?;
+            Ok(map)
+        };
+        match load() {
+            Ok(map) => Box::into_raw(Box::new(VsrgMap { map, notes: Vec::new() })),
+            Err(e) => {
+                logger::error(&format!("vsrg_load_map: {e}"));
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Moves the chart to `time_ms`: positions are updated and notes passed unpressed are missed.
+/// Returns 0, or -1 if the handle is null or the update failed (the reason is logged).
+///
+/// # Safety
+///
+/// `handle` has to come from `vsrg_load_map` and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn vsrg_update(handle: *mut VsrgMap, time_ms: f64) -> i32 {
+    let Some(handle) = (unsafe { handle.as_mut() }) else {
+        return UPDATE_FAILED;
+    };
+    guarded("vsrg_update", UPDATE_FAILED, || {
+        handle.map.time = time_ms;
+        let field_positions = set_reference_positions(None);
+        let updated = update_frame(&mut FrameState {
+            map: &mut handle.map,
+            compare_map: None,
+            chart_diff: &[],
+            field_positions: &field_positions,
+            alpha: 1.0,
+            view_height: VIEW_HEIGHT,
+            background: None,
+        });
+        match updated {
+            Ok(()) => UPDATED,
+            Err(e) => {
+                logger::error(&format!("vsrg_update: {e}"));
+                UPDATE_FAILED
+            }
+        }
+    })
+}
+
+/// Writes up to `capacity` of the notes on screen to `out`, returns how many there are in all
+/// (which can be more than were written).
+///
+/// # Safety
+///
+/// `handle` has to come from `vsrg_load_map`, `out` has to have room for `capacity` notes.
+#[no_mangle]
+pub unsafe extern "C" fn vsrg_visible_notes(handle: *mut VsrgMap, out: *mut VsrgNote, capacity: usize) -> usize {
+    let Some(handle) = (unsafe { handle.as_mut() }) else {
+        return 0;
+    };
+    let out = if out.is_null() { &mut [][..] } else { unsafe { std::slice::from_raw_parts_mut(out, capacity) } };
+    guarded("vsrg_visible_notes", 0, || {
+        let field_positions = set_reference_positions(None);
+        visible_notes(&handle.map, field_positions.hold_hit_position_y, VIEW_HEIGHT, &mut handle.notes);
+        for (slot, note) in out.iter_mut().zip(&handle.notes) {
+            *slot = VsrgNote {
+                index: note.index,
+                y: note.y,
+                lane: note.lane,
+                held: u8::from(note.held),
+                color_index: handle.map.hit_objects[note.index as usize].snap_index as u8,
+            };
+        }
+        handle.notes.len()
+    })
+}
+
+/// Presses or releases a 0-indexed key at `time_ms`. Returns the judgement it made
+/// (0 marvelous, 1 perfect, 2 great, 3 good, 4 okay, 5 miss) or -1 for none.
+///
+/// # Safety
+///
+/// `handle` has to come from `vsrg_load_map` and not be freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn vsrg_key_event(handle: *mut VsrgMap, lane: i32, pressed: bool, time_ms: f64) -> i32 {
+    let Some(handle) = (unsafe { handle.as_mut() }) else {
+        return NO_JUDGEMENT;
+    };
+    guarded("vsrg_key_event", NO_JUDGEMENT, || {
+        let map = &mut handle.map;
+        if lane < 0 || i64::from(lane) >= map.get_key_count(true) {
+            return NO_JUDGEMENT;
+        }
+        let judged = map.hit_stats.len();
+        if pressed {
+            map.handle_gameplay_key_press(time_ms, i64::from(lane));
+        } else {
+            map.handle_gameplay_key_release(time_ms, i64::from(lane));
+        }
+        // the last judgement made, a press can set off a mine as well as hit a note
+        map.hit_stats[judged..].last().map_or(NO_JUDGEMENT, |hit_stat| hit_stat.judgement as i32)
+    })
+}
+
+/// Frees a map from `vsrg_load_map`. Null is ignored.
+///
+/// # Safety
+///
+/// `handle` has to come from `vsrg_load_map` and not be used again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn vsrg_free(handle: *mut VsrgMap) {
+    if !handle.is_null() {
+        let handle = unsafe { Box::from_raw(handle) };
+        guarded("vsrg_free", (), || drop(handle));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panics_are_caught_and_give_the_fallback() {
+        assert_eq!(guarded("test", -1, || panic!("broken chart")), -1);
+        assert_eq!(guarded("test", 0, || panic!("{} notes", 3)), 0);
+        assert_eq!(guarded("test", -1, || 2), 2);
+    }
+
+    #[test]
+    fn null_handles_are_ignored() {
+        unsafe {
+            assert!(vsrg_load_map(std::ptr::null()).is_null());
+            assert_eq!(vsrg_update(std::ptr::null_mut(), 0.0), UPDATE_FAILED);
+            assert_eq!(vsrg_visible_notes(std::ptr::null_mut(), std::ptr::null_mut(), 4), 0);
+            assert_eq!(vsrg_key_event(std::ptr::null_mut(), 0, true, 0.0), NO_JUDGEMENT);
+            vsrg_free(std::ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::draw::Draw;
+use macroquad::color::Color;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+// frames kept for the graph and the figures
+pub const FRAME_HISTORY: usize = 240;
+// a frame this many times the median is a spike
+const SPIKE_FACTOR: f64 = 3.0;
+// frames needed before the median means enough to call anything a spike
+const MIN_SPIKE_HISTORY: usize = 60;
+// graph bars are this many px per ms, up to GRAPH_HEIGHT
+const GRAPH_SCALE: f64 = 1.5;
+pub const GRAPH_HEIGHT: f64 = 60.0;
+const GRAPH_BAR_WIDTH: f64 = 1.0;
+
+// how long each part of a frame took (ms), in the order they ran
+pub type FramePhases = Vec<(&'static str, f64)>;
+
+// times the parts of a frame, each mark ends the part since the one before
+pub struct PhaseTimer {
+    last: Instant,
+    phases: FramePhases,
+}
+
+impl PhaseTimer {
+    pub fn new() -> Self {
+        Self { last: Instant::now(), phases: Vec::new() }
+    }
+
+    pub fn mark(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        self.phases.push((phase, (now - self.last).as_secs_f64() * 1000.0));
+        self.last = now;
+    }
+
+    pub fn finish(mut self, phase: &'static str) -> FramePhases {
+        self.mark(phase);
+        self.phases
+    }
+}
+
+impl Default for PhaseTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// a frame that took much longer than the ones before it
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameSpike {
+    pub frame_time: f64, // ms
+    pub median: f64,     // ms, of the frames before it
+    pub phases: FramePhases,
+}
+
+impl FrameSpike {
+    pub fn describe(&self) -> String {
+        // e.g. "52.1 ms (3.1x the 16.7 ms median): audio 48.0, input 0.3, ..."
+        let phases: Vec<String> = self.phases.iter().map(|(phase, ms)| format!("{phase} {ms:.1}")).collect();
+        format!(
+            "{:.1} ms ({:.1}x the {:.1} ms median): {}",
+            self.frame_time,
+            self.frame_time / self.median,
+            self.median,
+            phases.join(", ")
+        )
+    }
+}
+
+// the last frame times, for the fps figures, the graph and telling stutters apart from a low frame rate
+#[derive(Debug, Default)]
+pub struct FramePacing {
+    frame_times: VecDeque<f64>, // ms, oldest first
+}
+
+impl FramePacing {
+    pub fn new() -> Self {
+        Self { frame_times: VecDeque::with_capacity(FRAME_HISTORY) }
+    }
+
+    pub fn push(&mut self, frame_time: f64, phases: FramePhases) -> Option<FrameSpike> {
+        // adds a frame, returned as a spike if it's well over the median of the ones before it
+        let spike = (self.frame_times.len() >= MIN_SPIKE_HISTORY)
+            .then(|| self.median())
+            .flatten()
+            .filter(|&median| median > 0.0 && frame_time > median * SPIKE_FACTOR)
+            .map(|median| FrameSpike { frame_time, median, phases });
+        if self.frame_times.len() == FRAME_HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+        spike
+    }
+
+    pub fn frame_times(&self) -> impl Iterator<Item = f64> + '_ {
+        self.frame_times.iter().copied()
+    }
+
+    fn sorted(&self) -> Vec<f64> {
+        let mut sorted: Vec<f64> = self.frame_times.iter().copied().collect();
+        sorted.sort_unstable_by(f64::total_cmp);
+        sorted
+    }
+
+    pub fn median(&self) -> Option<f64> {
+        let sorted = self.sorted();
+        let middle = sorted.len() / 2;
+        match sorted.len() {
+            0 => None,
+            len if len % 2 == 0 => Some((sorted[middle - 1] + sorted[middle]) / 2.0),
+            _ => Some(sorted[middle]),
+        }
+    }
+
+    pub fn average_fps(&self) -> Option<f64> {
+        let total: f64 = self.frame_times.iter().sum();
+        (total > 0.0).then(|| self.frame_times.len() as f64 * 1000.0 / total)
+    }
+
+    pub fn low_fps(&self, fraction: f64) -> Option<f64> {
+        // the "1% low" for 0.01: the fps of the frame that's slower than all but that fraction of them
+        let sorted = self.sorted();
+        let rank = (sorted.len() as f64 * fraction).floor() as usize;
+        let frame_time = *sorted.iter().rev().nth(rank.min(sorted.len().checked_sub(1)?))?;
+        (frame_time > 0.0).then(|| 1000.0 / frame_time)
+    }
+}
+
+pub fn draw_frame_pacing(pacing: &FramePacing, x: f64, y: f64, draw: &mut impl Draw) {
+    // one bar per frame from the bottom of the graph, newest on the right; spikes are red, frames
+    // over 1.5x the median yellow
+    let median = pacing.median().unwrap_or(0.0);
+    let width = FRAME_HISTORY as f64 * GRAPH_BAR_WIDTH;
+    draw.draw_rectangle(x, y, width, GRAPH_HEIGHT, Color::new(0.0, 0.0, 0.0, 0.5));
+    let start = x + width - pacing.frame_times.len() as f64 * GRAPH_BAR_WIDTH;
+    for (index, frame_time) in pacing.frame_times().enumerate() {
+        let height = (frame_time * GRAPH_SCALE).min(GRAPH_HEIGHT);
+        let color = if frame_time > median * SPIKE_FACTOR {
+            Color::new(1.0, 0.3, 0.3, 0.9)
+        } else if frame_time > median * 1.5 {
+            Color::new(1.0, 0.85, 0.3, 0.9)
+        } else {
+            Color::new(0.4, 1.0, 0.5, 0.9)
+        };
+        let bar_x = start + index as f64 * GRAPH_BAR_WIDTH;
+        draw.draw_rectangle(bar_x, y + GRAPH_HEIGHT - height, GRAPH_BAR_WIDTH, height, color);
+    }
+    // the median as a line across
+    let median_y = y + GRAPH_HEIGHT - (median * GRAPH_SCALE).min(GRAPH_HEIGHT);
+    draw.draw_line(x, median_y, x + width, median_y, 1.0, Color::new(1.0, 1.0, 1.0, 0.4));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pacing(frame_times: &[f64]) -> FramePacing {
+        let mut pacing = FramePacing::new();
+        for &frame_time in frame_times {
+            pacing.push(frame_time, Vec::new());
+        }
+        pacing
+    }
+
+    #[test]
+    fn lows_are_the_slow_end_of_the_frames() {
+        // 200 frames: three slow ones among 10 ms
+        let mut frame_times = vec![10.0; 197];
+        frame_times.extend([20.0, 40.0, 50.0]);
+        let pacing = pacing(&frame_times);
+        // the 1% low skips the two slowest frames, the 0.1% low is the slowest
+        assert_eq!(pacing.low_fps(0.01), Some(50.0));
+        assert_eq!(pacing.low_fps(0.001), Some(20.0));
+        assert_eq!(pacing.median(), Some(10.0));
+        let average = pacing.average_fps().unwrap();
+        assert!((average - 200_000.0 / 2080.0).abs() < 1e-9, "{average}");
+        // without frames there aren't any
+        assert_eq!((FramePacing::new().low_fps(0.01), FramePacing::new().average_fps()), (None, None));
+    }
+
+    #[test]
+    fn only_the_last_frames_are_kept() {
+        let mut frame_times = vec![100.0; 10];
+        frame_times.extend(vec![10.0; FRAME_HISTORY]);
+        let pacing = pacing(&frame_times);
+        assert_eq!(pacing.frame_times().count(), FRAME_HISTORY);
+        assert_eq!(pacing.low_fps(0.001), Some(100.0));
+    }
+
+    #[test]
+    fn spikes_are_frames_over_three_times_the_median() {
+        let mut pacing = pacing(&[16.0; MIN_SPIKE_HISTORY]);
+        assert_eq!(pacing.push(48.0, Vec::new()), None);
+        let spike = pacing.push(49.0, vec![("audio", 45.5), ("render", 3.5)]).unwrap();
+        assert_eq!((spike.frame_time, spike.median), (49.0, 16.0));
+        assert_eq!(spike.describe(), "49.0 ms (3.1x the 16.0 ms median): audio 45.5, render 3.5");
+        // a spike doesn't move the median much, so the next one is caught too
+        assert!(pacing.push(60.0, Vec::new()).is_some());
+    }
+
+    #[test]
+    fn no_spikes_until_the_median_means_something() {
+        let mut pacing = pacing(&[16.0; MIN_SPIKE_HISTORY - 1]);
+        assert_eq!(pacing.push(200.0, Vec::new()), None);
+        assert!(pacing.push(200.0, Vec::new()).is_some());
+    }
+}
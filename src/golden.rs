@@ -0,0 +1,301 @@
+use crate::draw::{DrawCommand, RecordingDraw};
+use crate::effects::parse_effects;
+use crate::logger;
+use crate::map::Map;
+use crate::package::decode_image;
+use crate::render::{render_frame, set_reference_positions, update_frame, FrameState};
+use crate::results::ResultsSummary;
+use crate::utils::{set_skin, skin, HitStat, NoteShape, Skin, DEFAULT_SKIN};
+use anyhow::{anyhow, Result};
+use macroquad::miniquad::{RawId, TextureId};
+use macroquad::texture::Texture2D;
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+// inside the golden directory: the cases to render, the charts they use and the frames they should draw
+const CASES_FILE: &str = "cases.toml";
+const CHARTS_DIR: &str = "charts";
+const EXPECTED_DIR: &str = "expected";
+// simulated ms between updates on the way to a case's time, so judgements (and held LNs) happen like in a play
+const SIMULATION_STEP: f64 = 1000.0 / 240.0;
+
+// every case is rendered twice and both runs have to agree exactly, which catches anything that
+// depends on more than the case itself (HashMap iteration order, leftover global state, wall clock).
+// what's left that can differ between machines is the float math itself: libm's sin/cos/powf and
+// friends aren't guaranteed to round the same everywhere, and a compiler may fuse a multiply and
+// add on targets with FMA. the expected commands are rounded to 0.1 px and 0.001 of a color channel
+// so those last-bit differences don't show up, unless a value lands right on a rounding boundary
+
+fn default_length() -> f64 {
+    4000.0
+}
+
+fn default_width() -> f64 {
+    1000.0
+}
+
+fn default_height() -> f64 {
+    1200.0
+}
+
+// one frame to check: a chart at a time, with the mods and screen size it's drawn with
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GoldenCase {
+    pub name: String,  // also the name of its expected .json
+    pub chart: String, // .qua in the charts directory
+    pub time: f64,     // ms into the chart
+    #[serde(default = "default_length")]
+    pub length: f64, // stands in for the audio's length, the timing lines go up to it
+    #[serde(default)]
+    pub mirror: bool,
+    #[serde(default)]
+    pub no_sv: bool,
+    #[serde(default)]
+    pub no_ssf: bool,
+    #[serde(default)]
+    pub autoplay: bool, // hits every note on the way, so LNs are held
+    #[serde(default)]
+    pub note_shape: Option<NoteShape>, // the default skin's if not given
+    #[serde(default)]
+    pub effects: Option<String>, // effects file in the charts directory the chart is played with
+    #[serde(default)]
+    pub background: Option<String>, // image in the charts directory drawn behind the playfield
+    #[serde(default = "default_width")]
+    pub width: f64,
+    #[serde(default = "default_height")]
+    pub height: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CasesFile {
+    #[serde(rename = "case")]
+    cases: Vec<GoldenCase>,
+}
+
+pub fn load_cases(dir: &Path) -> Result<Vec<GoldenCase>> {
+    let path = dir.join(CASES_FILE);
+    let content =
+        fs::read_to_string(&path).map_err(|e| anyhow!("Failed to read golden cases '{}': {}", path.display(), e))?;
+    let file: CasesFile =
+        toml::from_str(&content).map_err(|e| anyhow!("Failed to parse golden cases '{}': {}", path.display(), e))?;
+    Ok(file.cases)
+}
+
+// what a case left behind: the frame's draw commands, and the judgements made on the way to it
+// along with the results JSON they'd be exported as
+#[derive(Debug, PartialEq)]
+pub struct RenderedCase {
+    pub commands: Vec<DrawCommand>,
+    pub hit_stats: Vec<HitStat>,
+    pub results_json: String,
+}
+
+pub fn render_case(dir: &Path, case: &GoldenCase) -> Result<RenderedCase> {
+    // draws the case's frame with the default skin, so nothing from the user's config ends up in it
+    let mut map = Map::from_file(&dir.join(CHARTS_DIR).join(&case.chart))?;
+    map.rate = 1.0;
+    map.length = case.length;
+    map.mods.mirror = case.mirror;
+    map.mods.no_sv = case.no_sv;
+    map.mods.no_ssf = case.no_ssf;
+    map.mods.autoplay = case.autoplay;
+    if let Some(effects) = &case.effects {
+        let path = dir.join(CHARTS_DIR).join(effects);
+        let contents =
+            fs::read_to_string(&path).map_err(|e| anyhow!("Failed to read effects '{}': {}", path.display(), e))?;
+        map.effects = parse_effects(&contents)?;
+    }
+    set_skin(match case.note_shape {
+        Some(note_shape) => Skin { note_shape, ..DEFAULT_SKIN },
+        None => DEFAULT_SKIN,
+    });
+    let field_positions = set_reference_positions(None);
+    crate::initialize_map(&mut map, &field_positions)?;
+
+    // the image is decoded like a play's background, so one that can't be fails the case; the
+    // recorded frame only keeps where a texture is drawn, so a placeholder handle stands in for the
+    // texture, which couldn't be created without a window anyway
+    let background = match &case.background {
+        Some(file_name) => {
+            let path = dir.join(CHARTS_DIR).join(file_name);
+            let bytes = fs::read(&path).map_err(|e| anyhow!("Failed to read background '{}': {}", path.display(), e))?;
+            decode_image(&path, &bytes)?;
+            Some(Texture2D::from_miniquad_texture(TextureId::from_raw_id(RawId::OpenGl(0))))
+        }
+        None => None,
+    };
+
+    // each step's time is its index times the step rather than a running sum, so rounding error
+    // doesn't build up over long cases
+    let start = 0f64.min(case.time);
+    for step in 0u32.. {
+        let time = (start + f64::from(step) * SIMULATION_STEP).min(case.time);
+        map.time = time;
+        update_frame(&mut FrameState {
+            map: &mut map,
+            compare_map: None,
+            chart_diff: &[],
+            field_positions: &field_positions,
+            alpha: 1.0,
+            view_height: case.height,
+            background: background.as_ref(),
+        })?;
+        if time >= case.time {
+            break;
+        }
+    }
+
+    let mut draw = RecordingDraw::new(case.width, case.height);
+    render_frame(
+        &mut FrameState {
+            map: &mut map,
+            compare_map: None,
+            chart_diff: &[],
+            field_positions: &field_positions,
+            alpha: 1.0,
+            view_height: case.height,
+            background: background.as_ref(),
+        },
+        &mut draw,
+    )?;
+    let results_json = ResultsSummary::from_map(&map).to_json()?;
+    Ok(RenderedCase { commands: draw.commands, hit_stats: map.hit_stats, results_json })
+}
+
+fn to_json(commands: &[DrawCommand]) -> Result<String> {
+    // one command per line, so a changed frame shows up in a diff as the commands that changed
+    let lines = commands.iter().map(serde_json::to_string).collect::<Result<Vec<_>, _>>()?;
+    Ok(format!("[\n{}\n]\n", lines.join(",\n")))
+}
+
+fn first_difference(expected: &[DrawCommand], actual: &[DrawCommand]) -> Option<String> {
+    // the first command that isn't the same, or the extra ones if one list is a prefix of the other
+    let index = expected.iter().zip(actual).position(|(expected, actual)| expected != actual);
+    match index {
+        Some(index) => Some(format!(
+            "command {index} is {}, expected {}",
+            serde_json::to_string(&actual[index]).unwrap_or_default(),
+            serde_json::to_string(&expected[index]).unwrap_or_default()
+        )),
+        None if expected.len() != actual.len() => {
+            Some(format!("{} commands drawn, expected {}", actual.len(), expected.len()))
+        }
+        None => None,
+    }
+}
+
+pub fn run(dir: &Path, bless: bool) -> Result<()> {
+    // renders every case and compares it with its expected commands, or writes them when blessing
+    let cases = load_cases(dir)?;
+    let expected_dir = dir.join(EXPECTED_DIR);
+    if bless {
+        fs::create_dir_all(&expected_dir)
+            .map_err(|e| anyhow!("Failed to create '{}': {}", expected_dir.display(), e))?;
+    }
+    let previous_skin = skin();
+    let mut failed = 0;
+    for case in &cases {
+        let path = expected_dir.join(format!("{}.json", case.name));
+        let result = render_case(dir, case).and_then(|rendered| {
+            if render_case(dir, case)? != rendered {
+                anyhow::bail!("rendering it again gave a different frame or judgements, so it isn't deterministic");
+            }
+            let actual = rendered.commands;
+            if bless {
+                fs::write(&path, to_json(&actual)?).map_err(|e| anyhow!("Failed to write '{}': {}", path.display(), e))?;
+                return Ok(None);
+            }
+            let content = fs::read_to_string(&path)
+                .map_err(|e| anyhow!("No expected frame '{}' (bless to write it): {}", path.display(), e))?;
+            let expected: Vec<DrawCommand> = serde_json::from_str(&content)
+                .map_err(|e| anyhow!("Failed to parse '{}': {}", path.display(), e))?;
+            Ok(first_difference(&expected, &actual))
+        });
+        match result {
+            Ok(None) if bless => logger::info(&format!("{}: blessed", case.name)),
+            Ok(None) => logger::info(&format!("{}: matches", case.name)),
+            Ok(Some(difference)) => {
+                logger::error(&format!("{}: {difference}", case.name));
+                failed += 1;
+            }
+            Err(e) => {
+                logger::error(&format!("{}: {e}", case.name));
+                failed += 1;
+            }
+        }
+    }
+    set_skin(previous_skin);
+    if failed > 0 {
+        anyhow::bail!("{failed} of {} golden frames failed", cases.len());
+    }
+    logger::info(&format!("All {} golden frames {}", cases.len(), if bless { "blessed" } else { "match" }));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn golden_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("golden")
+    }
+
+    #[test]
+    fn golden_frames_match() {
+        // every case against its checked-in frame; `golden --bless` rewrites them after an intended change
+        run(&golden_dir(), false).unwrap();
+    }
+
+    #[test]
+    fn every_case_renders_the_same_twice() {
+        // same draw commands and same results JSON, for cases with judgements (autoplay, held LNs) too
+        let dir = golden_dir();
+        for case in load_cases(&dir).unwrap() {
+            let first = render_case(&dir, &case).unwrap();
+            let second = render_case(&dir, &case).unwrap();
+            assert_eq!(to_json(&first.commands).unwrap(), to_json(&second.commands).unwrap(), "{}", case.name);
+            assert_eq!(first.results_json, second.results_json, "{}", case.name);
+            assert_eq!(first.hit_stats, second.hit_stats, "{}", case.name);
+        }
+    }
+
+    fn case(background: Option<&str>) -> GoldenCase {
+        GoldenCase {
+            name: "background".to_string(),
+            chart: "plain_4k.qua".to_string(),
+            time: 300.0,
+            length: default_length(),
+            mirror: false,
+            no_sv: false,
+            no_ssf: false,
+            autoplay: false,
+            note_shape: None,
+            effects: None,
+            background: background.map(str::to_string),
+            width: default_width(),
+            height: default_height(),
+        }
+    }
+
+    #[test]
+    fn jpeg_background_is_drawn_under_the_playfield() {
+        let rendered = render_case(&golden_dir(), &case(Some("background.jpg"))).unwrap();
+        let plain = render_case(&golden_dir(), &case(None)).unwrap();
+        assert!(matches!(rendered.commands[0], DrawCommand::TextureScaled { .. }));
+        assert!(matches!(rendered.commands[1], DrawCommand::Rectangle { .. }));
+        assert_eq!(rendered.commands[2..], plain.commands[..]);
+    }
+
+    #[test]
+    fn background_that_isnt_an_image_fails_the_case() {
+        // a chart isn't an image, and a missing file can't be read
+        let dir = golden_dir();
+        let error = render_case(&dir, &case(Some("plain_4k.qua"))).unwrap_err();
+        assert!(error.to_string().contains("Failed to decode"), "{error}");
+        let error = render_case(&dir, &case(Some("missing.jpg"))).unwrap_err();
+        assert!(error.to_string().contains("Failed to read background"), "{error}");
+    }
+}
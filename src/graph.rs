@@ -0,0 +1,138 @@
+use crate::draw::Draw;
+use macroquad::color::Color;
+
+// maps data values into a rectangle on screen, keeping padding between the frame and the data
+#[derive(Debug, Clone)]
+pub struct Graph {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    pub padding: f64,
+    pub x_range: (f64, f64), // data values at the left and right edges
+    pub y_range: (f64, f64), // data values at the bottom and top edges
+}
+
+fn range_progress(value: f64, (min, max): (f64, f64)) -> f64 {
+    // 0-1 position of a value within a range, an empty range puts everything in the middle
+    let span = max - min;
+    if span.abs() < f64::EPSILON {
+        return 0.5;
+    }
+    ((value - min) / span).clamp(0.0, 1.0)
+}
+
+impl Graph {
+    pub fn map_x(&self, value: f64) -> f64 {
+        self.x + self.padding + range_progress(value, self.x_range) * (self.width - self.padding * 2.0)
+    }
+
+    pub fn map_y(&self, value: f64) -> f64 {
+        // larger values are higher up
+        self.y + self.height - self.padding - range_progress(value, self.y_range) * (self.height - self.padding * 2.0)
+    }
+
+    pub fn draw_frame(&self, draw: &mut impl Draw, color: Color) {
+        draw.draw_rectangle_outline(self.x, self.y, self.width, self.height, 1.0, color);
+    }
+
+    pub fn draw_horizontal_line(&self, draw: &mut impl Draw, value: f64, color: Color) {
+        // reference line across the data area
+        let y = self.map_y(value);
+        draw.draw_line(self.x + self.padding, y, self.x + self.width - self.padding, y, 1.0, color);
+    }
+
+    pub fn draw_polyline(&self, draw: &mut impl Draw, points: &[(f64, f64)], thickness: f64, color: Color) {
+        for pair in points.windows(2) {
+            let (x1, y1) = (self.map_x(pair[0].0), self.map_y(pair[0].1));
+            let (x2, y2) = (self.map_x(pair[1].0), self.map_y(pair[1].1));
+            draw.draw_line(x1, y1, x2, y2, thickness, color);
+        }
+    }
+
+    pub fn draw_points(&self, draw: &mut impl Draw, points: &[(f64, f64, Color)], radius: f64) {
+        for &(x, y, color) in points {
+            draw.draw_circle(self.map_x(x), self.map_y(y), radius, color);
+        }
+    }
+}
+
+pub fn decimate<T: Clone>(points: &[T], max_points: usize) -> Vec<T> {
+    // keeps at most max_points evenly spread points, always including the first and last
+    // (just the first when only one is allowed)
+    if points.len() <= max_points || max_points < 2 {
+        return points.iter().take(max_points).cloned().collect();
+    }
+    let step = (points.len() - 1) as f64 / (max_points - 1) as f64;
+    (0..max_points)
+        .map(|index| points[(index as f64 * step).round() as usize].clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> Graph {
+        Graph { x: 100.0, y: 50.0, width: 200.0, height: 120.0, padding: 10.0, x_range: (0.0, 10.0), y_range: (-1.0, 1.0) }
+    }
+
+    #[test]
+    fn values_are_mapped_inside_the_padding() {
+        let graph = graph();
+        assert_eq!(graph.map_x(0.0), 110.0);
+        assert_eq!(graph.map_x(5.0), 200.0);
+        assert_eq!(graph.map_x(10.0), 290.0);
+        // y grows downwards on screen, so the smallest value is at the bottom
+        assert_eq!(graph.map_y(-1.0), 160.0);
+        assert_eq!(graph.map_y(0.0), 110.0);
+        assert_eq!(graph.map_y(1.0), 60.0);
+    }
+
+    #[test]
+    fn values_outside_the_range_are_clamped_to_the_edges() {
+        let graph = graph();
+        assert_eq!(graph.map_x(-5.0), graph.map_x(0.0));
+        assert_eq!(graph.map_x(50.0), graph.map_x(10.0));
+        assert_eq!(graph.map_y(-3.0), graph.map_y(-1.0));
+        assert_eq!(graph.map_y(3.0), graph.map_y(1.0));
+    }
+
+    #[test]
+    fn empty_range_puts_everything_in_the_middle() {
+        let graph = Graph { x_range: (4.0, 4.0), y_range: (0.0, 0.0), ..graph() };
+        for value in [-100.0, 0.0, 4.0, 100.0] {
+            assert_eq!(graph.map_x(value), 200.0);
+            assert_eq!(graph.map_y(value), 110.0);
+        }
+    }
+
+    #[test]
+    fn inverted_range_flips_the_axis() {
+        let graph = Graph { y_range: (1.0, -1.0), ..graph() };
+        assert_eq!(graph.map_y(1.0), 160.0);
+        assert_eq!(graph.map_y(-1.0), 60.0);
+        assert_eq!(graph.map_y(0.5), graph.map_y(-0.5) + 50.0);
+    }
+
+    #[test]
+    fn decimation_keeps_the_first_and_last_points() {
+        let points: Vec<usize> = (0..1000).collect();
+        for max_points in [2, 3, 10, 999] {
+            let kept = decimate(&points, max_points);
+            assert_eq!(kept.len(), max_points);
+            assert_eq!(kept.first(), Some(&0));
+            assert_eq!(kept.last(), Some(&999));
+            assert!(kept.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+        assert_eq!(decimate(&points[..5], 10), points[..5]);
+    }
+
+    #[test]
+    fn decimation_to_fewer_than_two_points() {
+        let points = [1, 2, 3];
+        assert_eq!(decimate(&points, 1), [1]);
+        assert!(decimate(&points, 0).is_empty());
+        assert!(decimate::<i32>(&[], 0).is_empty());
+    }
+}
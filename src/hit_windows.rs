@@ -0,0 +1,125 @@
+use crate::map::Map;
+use crate::utils::{JudgementWindows, JUDGEMENTS};
+use serde::{Deserialize, Serialize};
+
+// the hit windows a play was judged with, kept with its results and replay so plays judged with
+// different windows aren't compared (or replayed) as if they were the same
+
+// name of the windows from JUDGEMENTS, the only ones this build has; anything else is "Custom"
+pub const STANDARD_PRESET: &str = "Standard";
+pub const CUSTOM_PRESET: &str = "Custom";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActiveWindows {
+    pub preset: String,
+    pub windows: JudgementWindows, // per judgement, ms early and late
+    // windows are in chart ms: the map's clock runs at the rate, so in real time they're narrower by it
+    pub scale_with_rate: bool,
+    pub rate: f64,
+}
+
+impl ActiveWindows {
+    pub fn of(map: &Map) -> Self {
+        let standard = JudgementWindows::symmetric(JUDGEMENTS);
+        Self {
+            preset: if map.judgement_windows == standard { STANDARD_PRESET } else { CUSTOM_PRESET }.to_string(),
+            windows: map.judgement_windows.clone(),
+            scale_with_rate: true,
+            rate: map.rate,
+        }
+    }
+
+    pub fn differences(&self, other: &Self) -> Vec<String> {
+        // how other's windows differ from these, empty if a play is judged the same with both; the rate
+        // is left out, replays are judged in chart ms whatever it is
+        let mut differences = Vec::new();
+        if self.preset != other.preset {
+            differences.push(format!("preset {} instead of {}", other.preset, self.preset));
+        }
+        if self.scale_with_rate != other.scale_with_rate {
+            let scaled = |scale: bool| if scale { "scaled" } else { "not scaled" };
+            differences.push(format!(
+                "windows {} by rate instead of {}",
+                scaled(other.scale_with_rate),
+                scaled(self.scale_with_rate)
+            ));
+        }
+        for judgement in JUDGEMENTS.iter().map(|judgement| judgement.kind) {
+            for (side, ours, theirs) in [
+                ("early", self.windows.early(judgement), other.windows.early(judgement)),
+                ("late", self.windows.late(judgement), other.windows.late(judgement)),
+            ] {
+                if ours != theirs {
+                    differences.push(format!("{judgement} {side} {theirs} ms instead of {ours}"));
+                }
+            }
+        }
+        differences
+    }
+
+    pub fn describe_windows(&self) -> String {
+        // e.g. "Marvelous 18, Perfect 43, ..." with early/late for the ones that aren't symmetric
+        JUDGEMENTS
+            .iter()
+            .map(|judgement| {
+                let (early, late) = (self.windows.early(judgement.kind), self.windows.late(judgement.kind));
+                if early == late {
+                    format!("{} {early}", judgement.kind)
+                } else {
+                    format!("{} -{early}/+{late}", judgement.kind)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::Replay;
+    use crate::results::ResultsSummary;
+    use crate::utils::JudgementType;
+
+    fn custom_windows_map() -> Map {
+        let mut map = Map::default();
+        map.rate = 1.3;
+        map.judgement_windows = JudgementWindows::symmetric(JUDGEMENTS);
+        map.judgement_windows.early.insert(JudgementType::Marvelous, 12.5);
+        map.judgement_windows.late.insert(JudgementType::Miss, 180.0);
+        map
+    }
+
+    #[test]
+    fn windows_survive_the_results_json() {
+        let map = custom_windows_map();
+        let windows = ActiveWindows::of(&map);
+        assert_eq!((windows.preset.as_str(), windows.rate), (CUSTOM_PRESET, 1.3));
+        let json: serde_json::Value = serde_json::from_str(&ResultsSummary::from_map(&map).to_json().unwrap()).unwrap();
+        let exported: ActiveWindows = serde_json::from_value(json["windows"].clone()).unwrap();
+        assert_eq!(exported, windows);
+        assert_eq!(json["windows"]["windows"]["early"]["Marvelous"], 12.5);
+    }
+
+    #[test]
+    fn windows_survive_the_replay_file() {
+        let map = custom_windows_map();
+        let replay = Replay { windows: Some(ActiveWindows::of(&map)), ..Replay::default() };
+        let path = std::env::temp_dir().join(format!("vsrg_hit_windows_replay_{}.json", std::process::id()));
+        replay.save(&path).unwrap();
+        let loaded = Replay::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.windows, replay.windows);
+        // so it's played on a chart with the same windows, and not on one with the standard ones
+        loaded.check_windows(&map, false).unwrap();
+        let mut standard = Map::default();
+        standard.judgement_windows = JudgementWindows::symmetric(JUDGEMENTS);
+        let error = loaded.check_windows(&standard, false).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Replay was recorded with other hit windows: preset Standard instead of Custom, Marvelous early 18 ms \
+             instead of 12.5, Miss late 164 ms instead of 180 (--force-windows to play it anyway)"
+        );
+        loaded.check_windows(&standard, true).unwrap();
+    }
+}
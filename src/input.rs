@@ -0,0 +1,142 @@
+// which key plays which lane, for each key count. Written as comma-separated key names, lane 1
+// first, e.g. "s,d,f,space,j,k,l"; a binding replaces the default for its number of keys. A
+// hotkey that's also a gameplay key of the chart only works while paused, so PAUSE_KEY pauses when
+// Space plays a lane
+
+use macroquad::input::KeyCode;
+use std::{collections::HashMap, str::FromStr};
+
+// toggles pause like Space; function keys can't be bound, so it's never a gameplay key
+pub const PAUSE_KEY: KeyCode = KeyCode::F8;
+
+// one key per lane, lane 1 first (a scratch lane's last)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding(pub Vec<KeyCode>);
+
+fn key_code(name: &str) -> Option<KeyCode> {
+    let key = match name.to_ascii_lowercase().as_str() {
+        "a" => KeyCode::A,
+        "b" => KeyCode::B,
+        "c" => KeyCode::C,
+        "d" => KeyCode::D,
+        "e" => KeyCode::E,
+        "f" => KeyCode::F,
+        "g" => KeyCode::G,
+        "h" => KeyCode::H,
+        "i" => KeyCode::I,
+        "j" => KeyCode::J,
+        "k" => KeyCode::K,
+        "l" => KeyCode::L,
+        "m" => KeyCode::M,
+        "n" => KeyCode::N,
+        "o" => KeyCode::O,
+        "p" => KeyCode::P,
+        "q" => KeyCode::Q,
+        "r" => KeyCode::R,
+        "s" => KeyCode::S,
+        "t" => KeyCode::T,
+        "u" => KeyCode::U,
+        "v" => KeyCode::V,
+        "w" => KeyCode::W,
+        "x" => KeyCode::X,
+        "y" => KeyCode::Y,
+        "z" => KeyCode::Z,
+        "0" => KeyCode::Key0,
+        "1" => KeyCode::Key1,
+        "2" => KeyCode::Key2,
+        "3" => KeyCode::Key3,
+        "4" => KeyCode::Key4,
+        "5" => KeyCode::Key5,
+        "6" => KeyCode::Key6,
+        "7" => KeyCode::Key7,
+        "8" => KeyCode::Key8,
+        "9" => KeyCode::Key9,
+        "space" => KeyCode::Space,
+        ";" | "semicolon" => KeyCode::Semicolon,
+        "'" | "apostrophe" => KeyCode::Apostrophe,
+        "." | "period" => KeyCode::Period,
+        "/" | "slash" => KeyCode::Slash,
+        "[" | "leftbracket" => KeyCode::LeftBracket,
+        "]" | "rightbracket" => KeyCode::RightBracket,
+        "\\" | "backslash" => KeyCode::Backslash,
+        "`" | "grave" => KeyCode::GraveAccent,
+        "-" | "minus" => KeyCode::Minus,
+        "=" | "equal" => KeyCode::Equal,
+        "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "lshift" => KeyCode::LeftShift,
+        "rshift" => KeyCode::RightShift,
+        "lctrl" => KeyCode::LeftControl,
+        "rctrl" => KeyCode::RightControl,
+        "lalt" => KeyCode::LeftAlt,
+        "ralt" => KeyCode::RightAlt,
+        _ => return None,
+    };
+    Some(key)
+}
+
+impl FromStr for Binding {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // "," can't be bound, it separates the keys
+        let mut keys = Vec::new();
+        for name in value.split(',').map(str::trim) {
+            let key = key_code(name).ok_or_else(|| format!("unknown key '{name}'"))?;
+            if keys.contains(&key) {
+                return Err(format!("'{name}' is bound twice"));
+            }
+            keys.push(key);
+        }
+        Ok(Self(keys))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyBindings {
+    bindings: HashMap<usize, Vec<KeyCode>>, // by key count, the scratch lane counted
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        // 4K and 7K, and both with the scratch lane (+1 key) on the next key over
+        use KeyCode::*;
+        let bindings = [
+            vec![A, S, Semicolon, Apostrophe],
+            vec![A, S, Semicolon, Apostrophe, Slash],
+            vec![S, D, F, J, K, L, Semicolon],
+            vec![S, D, F, J, K, L, Semicolon, Apostrophe],
+        ];
+        Self { bindings: bindings.into_iter().map(|keys| (keys.len(), keys)).collect() }
+    }
+}
+
+impl KeyBindings {
+    pub fn set(&mut self, binding: Binding) {
+        // replaces the binding for as many keys as it has
+        self.bindings.insert(binding.0.len(), binding.0);
+    }
+
+    /// ```
+    /// use macroquad::input::KeyCode;
+    /// use vsrg_renderer::input::{Binding, KeyBindings};
+    ///
+    /// let mut bindings = KeyBindings::default();
+    /// assert_eq!(bindings.keys(7)[3], KeyCode::J);
+    /// bindings.set("s,d,f,space,j,k,l".parse::<Binding>().unwrap());
+    /// assert_eq!(bindings.keys(7)[3], KeyCode::Space);
+    /// assert_eq!(bindings.keys(4), [KeyCode::A, KeyCode::S, KeyCode::Semicolon, KeyCode::Apostrophe]);
+    /// assert!(bindings.keys(6).is_empty());
+    /// assert!("s,d,s".parse::<Binding>().is_err());
+    /// assert!("s,d,,f".parse::<Binding>().is_err());
+    /// ```
+    pub fn keys(&self, key_count: usize) -> &[KeyCode] {
+        // the keys of each lane, none if that many keys aren't bound
+        self.bindings.get(&key_count).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn binds(&self, key_count: usize, key: KeyCode) -> bool {
+        // whether the key plays a lane with that many keys
+        self.keys(key_count).contains(&key)
+    }
+}
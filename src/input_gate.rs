@@ -0,0 +1,110 @@
+// decides which gameplay presses reach the map. While paused the map's time is frozen, so a press
+// would be judged against notes near the pause point that the player never saw come down; those
+// are dropped, and so are presses right after playback starts again (the key that resumed it, or
+// one already on its way down). Releases always go through, an LN let go of while paused was let go
+// of where it stopped. Seeking, volume, fullscreen and the rest aren't gameplay keys and aren't gated
+
+use crate::utils::Time;
+
+// ms after playing again that presses are still dropped
+pub const DEFAULT_RESUME_GRACE: Time = 8.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputGate {
+    grace: Time,
+    playing: bool,
+    resumed_at: Option<f64>, // wall clock (ms) playback last started
+}
+
+impl InputGate {
+    pub const fn new(grace: Time) -> Self {
+        // starts paused, like the player
+        Self { grace, playing: false, resumed_at: None }
+    }
+
+    pub fn set_playing(&mut self, playing: bool, now: f64) {
+        // called every frame with the playback state and the wall clock (ms), so it's wall time and
+        // not chart time that counts (a restart or seek doesn't make the grace run again)
+        if playing && !self.playing {
+            self.resumed_at = Some(now);
+        }
+        self.playing = playing;
+    }
+
+    pub fn accepts_press(&self, now: f64) -> bool {
+        self.playing && self.resumed_at.is_none_or(|resumed_at| now - resumed_at >= self.grace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Map;
+    use crate::scenarios::Playback;
+    use crate::utils::JudgementType;
+
+    #[test]
+    fn presses_only_count_while_playing_and_after_the_grace() {
+        let mut gate = InputGate::new(DEFAULT_RESUME_GRACE);
+        assert!(!gate.accepts_press(0.0));
+        gate.set_playing(true, 1000.0);
+        assert!(!gate.accepts_press(1000.0));
+        assert!(!gate.accepts_press(1007.9));
+        assert!(gate.accepts_press(1008.0));
+        // staying on doesn't start the grace again
+        gate.set_playing(true, 1500.0);
+        assert!(gate.accepts_press(1500.0));
+
+        gate.set_playing(false, 2000.0);
+        assert!(!gate.accepts_press(5000.0));
+        gate.set_playing(true, 5000.0);
+        assert!(!gate.accepts_press(5004.0));
+        assert!(gate.accepts_press(5010.0));
+        // no grace at all
+        let mut gate = InputGate::new(0.0);
+        gate.set_playing(true, 1000.0);
+        assert!(gate.accepts_press(1000.0));
+    }
+
+    #[test]
+    fn gated_presses_never_reach_the_chart() {
+        // a chord at 1 s, paused just before it
+        let mut map: Map = serde_yaml::from_str(
+            "\
+Mode: Keys4
+TimingPoints:
+- StartTime: 0
+  Bpm: 120
+HitObjects:
+- StartTime: 1000
+  Lane: 1
+  KeySounds: []
+- StartTime: 1000
+  Lane: 2
+  KeySounds: []
+- StartTime: 1000
+  Lane: 3
+  KeySounds: []
+",
+        )
+        .unwrap();
+        map.rate = 1.0;
+        let mut playback = Playback::new(map, None).unwrap();
+        playback.advance_to(995.0).unwrap();
+        playback.toggle_pause();
+        // lane 1 while paused, lane 2 right after playing again, lane 3 once the grace is over
+        playback.advance_to(1500.0).unwrap();
+        playback.press(1);
+        playback.toggle_pause();
+        playback.advance_to(1502.0).unwrap();
+        playback.press(2);
+        playback.advance_to(1505.0 + DEFAULT_RESUME_GRACE).unwrap();
+        playback.press(3);
+        assert_eq!(playback.map.hit_stats.len(), 1);
+        assert_eq!((playback.map.hit_stats[0].lane, playback.map.hit_stats[0].judgement), (3, JudgementType::Marvelous));
+        // the other two are missed once they're past
+        playback.advance_to(2000.0).unwrap();
+        let judged: Vec<_> = playback.map.hit_stats.iter().map(|stat| (stat.lane, stat.judgement)).collect();
+        assert_eq!(judged, [(3, JudgementType::Marvelous), (1, JudgementType::Miss), (2, JudgementType::Miss)]);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::logger;
+use crate::map::Map;
+use crate::package::find_asset;
+use crate::utils::Time;
+use std::path::{Path, PathBuf};
+
+// a sample that plays at a chart time
+#[derive(Debug, Clone)]
+pub struct ScheduledSound {
+    pub time: Time,
+    pub path: PathBuf,
+    pub volume: f64, // 0-1
+}
+
+pub fn trigger_time(time: Time, latency: Time) -> Time {
+    // when a sound has to be started to be heard at its time
+    time - latency
+}
+
+// plays sounds known ahead of time early by the output latency, so they're heard on time
+// times and latency are in chart ms, so real time latency has to be scaled by the rate
+#[derive(Debug, Default)]
+pub struct SoundScheduler {
+    sounds: Vec<ScheduledSound>, // sorted by time
+    cursor: usize,               // first sound not triggered yet
+}
+
+impl SoundScheduler {
+    pub fn new(mut sounds: Vec<ScheduledSound>) -> Self {
+        sounds.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { sounds, cursor: 0 }
+    }
+
+    pub fn due(&mut self, now: Time, latency: Time) -> &[ScheduledSound] {
+        // sounds that have to start by now, each one is only returned once
+        let start = self.cursor;
+        while self
+            .sounds
+            .get(self.cursor)
+            .is_some_and(|sound| trigger_time(sound.time, latency) <= now)
+        {
+            self.cursor += 1;
+        }
+        &self.sounds[start..self.cursor]
+    }
+
+    pub fn seek(&mut self, time: Time, latency: Time) {
+        // moves the cursor after a seek or restart, sounds that should've started before time are skipped
+        self.cursor = self
+            .sounds
+            .partition_point(|sound| trigger_time(sound.time, latency) < time);
+    }
+}
+
+pub fn resolve_samples(map: &Map, map_dir: &Path, root: &Path) -> Vec<Option<PathBuf>> {
+    // paths of the CustomAudioSamples entries, None for ones that can't be used
+    map.custom_audio_samples
+        .iter()
+        .map(|sample| {
+            find_asset(map_dir, root, &sample.path)
+                .inspect_err(|e| logger::warning(&format!("Skipping audio sample: {e}")))
+                .ok()
+        })
+        .collect()
+}
+
+fn sample_path(samples: &[Option<PathBuf>], sample: i32) -> Option<PathBuf> {
+    // samples are one-based
+    samples.get(usize::try_from(sample - 1).ok()?)?.clone()
+}
+
+pub fn note_key_sounds(map: &Map, samples: &[Option<PathBuf>], index: usize) -> Vec<ScheduledSound> {
+    // the key sounds of a note, at its start time
+    let Some(hit_object) = map.hit_objects.get(index) else {
+        return Vec::new();
+    };
+    hit_object
+        .key_sounds
+        .iter()
+        .filter_map(|key_sound| {
+            let path = sample_path(samples, key_sound.sample)?;
+            Some(ScheduledSound {
+                time: hit_object.start_time,
+                path,
+                volume: f64::from(key_sound.volume) / 100.0,
+            })
+        })
+        .collect()
+}
+
+pub fn autoplay_key_sounds(map: &Map, samples: &[Option<PathBuf>]) -> Vec<ScheduledSound> {
+    // with autoplay every note is hit on time, so all key sounds are known ahead
+    // (mines and fakes are never hit by it)
+    (0..map.hit_objects.len())
+        .filter(|&index| map.hit_objects[index].object_kind.is_normal())
+        .flat_map(|index| note_key_sounds(map, samples, index))
+        .collect()
+}
+
+pub fn sound_effects(map: &Map, samples: &[Option<PathBuf>]) -> Vec<ScheduledSound> {
+    // the chart's SoundEffects, which play at their times however it's played
+    map.sound_effects
+        .iter()
+        .filter_map(|sound_effect| {
+            Some(ScheduledSound {
+                time: sound_effect.start_time,
+                path: sample_path(samples, sound_effect.sample)?,
+                volume: f64::from(sound_effect.volume) / 100.0,
+            })
+        })
+        .collect()
+}
+
+pub fn chart_sounds(map: &Map, samples: &[Option<PathBuf>]) -> Vec<ScheduledSound> {
+    // everything the chart plays by itself: its sound effects, and with autoplay the key sounds
+    let mut sounds = sound_effects(map, samples);
+    if map.mods.autoplay {
+        sounds.extend(autoplay_key_sounds(map, samples));
+    }
+    sounds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{HitObject, KeySound, SoundEffect};
+
+    fn sound(time: Time) -> ScheduledSound {
+        ScheduledSound { time, path: PathBuf::from(format!("{time}.wav")), volume: 1.0 }
+    }
+
+    fn times(sounds: &[ScheduledSound]) -> Vec<Time> {
+        sounds.iter().map(|sound| sound.time).collect()
+    }
+
+    #[test]
+    fn sounds_are_due_the_latency_early_and_only_once() {
+        let mut scheduler = SoundScheduler::new(vec![sound(1500.0), sound(1000.0)]);
+        assert!(scheduler.due(969.0, 30.0).is_empty());
+        assert_eq!(times(scheduler.due(970.0, 30.0)), [1000.0]);
+        assert!(scheduler.due(1000.0, 30.0).is_empty());
+        // a frame can skip past several
+        assert_eq!(times(SoundScheduler::new(vec![sound(1000.0), sound(1500.0)]).due(2000.0, 30.0)), [1000.0, 1500.0]);
+    }
+
+    #[test]
+    fn seeking_skips_sounds_that_should_have_started() {
+        let mut scheduler = SoundScheduler::new(vec![sound(1000.0), sound(1500.0), sound(2000.0)]);
+        scheduler.seek(1480.0, 30.0);
+        assert!(scheduler.due(1900.0, 30.0).is_empty());
+        scheduler.seek(1460.0, 30.0);
+        assert_eq!(times(scheduler.due(1470.0, 30.0)), [1500.0]);
+        scheduler.seek(0.0, 30.0);
+        assert_eq!(times(scheduler.due(970.0, 30.0)), [1000.0]);
+    }
+
+    #[test]
+    fn chart_sounds_are_its_sound_effects_and_autoplay_key_sounds() {
+        let mut map = Map::default();
+        map.sound_effects = vec![SoundEffect { start_time: 250.0, sample: 2, volume: 50 }];
+        map.hit_objects = vec![HitObject { start_time: 500.0, lane: 1, key_sounds: vec![KeySound { sample: 1, volume: 100 }], ..HitObject::default() }];
+        let samples = [Some(PathBuf::from("hit.wav")), Some(PathBuf::from("clap.wav"))];
+        let sounds = chart_sounds(&map, &samples);
+        assert_eq!(times(&sounds), [250.0]);
+        assert_eq!((sounds[0].path.as_path(), sounds[0].volume), (Path::new("clap.wav"), 0.5));
+        map.mods.autoplay = true;
+        assert_eq!(times(&chart_sounds(&map, &samples)), [250.0, 500.0]);
+        // a missing sample is left out
+        assert!(sound_effects(&map, &[None, None]).is_empty());
+    }
+}
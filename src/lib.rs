@@ -0,0 +1,98 @@
+#![allow(clippy::eq_op)]
+#![allow(unused_imports)]
+
+// everything but the window and command line, so the chart, timing and drawing code can be used headless
+
+pub mod alloc_stats;
+pub mod audio_manager;
+pub mod autosave;
+#[cfg(feature = "net")]
+pub mod broadcast;
+pub mod clips;
+pub mod config;
+pub mod debug_checks;
+pub mod difficulties;
+pub mod doctor;
+pub mod draw;
+pub mod effects;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod ducking;
+pub mod frame_pacing;
+pub mod golden;
+pub mod graph;
+pub mod hit_windows;
+pub mod input;
+pub mod input_gate;
+pub mod keysounds;
+pub mod map;
+pub mod map_skin;
+pub mod mapset;
+pub mod mash;
+pub mod memory_budget;
+#[cfg(feature = "online")]
+pub mod net;
+pub mod pace;
+pub mod package;
+pub mod picker;
+pub mod qua_stream;
+pub mod rate;
+pub mod rate_ramp;
+pub mod render;
+pub mod regions;
+pub mod replay;
+pub mod resume;
+pub mod results;
+pub mod strings;
+pub mod scenarios;
+pub mod scoring;
+pub mod scroll_suggestion;
+pub mod seek;
+pub mod simple_notes;
+pub mod splash;
+pub mod sync_test;
+pub mod thumbnail;
+pub mod trace;
+pub mod transform;
+pub mod utils;
+pub mod logger;
+pub mod local_offset;
+pub mod lookahead;
+pub mod onset;
+
+pub use utils::{index_at_time, lerp, object_at_time, sort_by_start_time, HasStartTime, Time};
+
+use anyhow::Result;
+use map::{Map, OFF_SNAP_TOLERANCE};
+use utils::FieldPositions;
+
+pub fn initialize_map(map: &mut Map, field_positions: &FieldPositions) -> Result<()> {
+    // map processing functions / preload
+    map.initialize_default_timing_group();
+    map.sort();
+    map.normalize_svs();
+    map.initialize_control_points();
+    map.initialize_hit_objects(field_positions).map_err(|e| {
+        logger::error(&format!("Failed to initialize hit objects: {e}"));
+        e
+    })?;
+    map.initialize_timing_lines(field_positions).map_err(|e| {
+        logger::error(&format!("Failed to initialize timing lines: {e}"));
+        e
+    })?;
+    map.initialize_beat_snaps().map_err(|e| {
+        logger::error(&format!("Failed to initialize beat snaps: {e}"));
+        e
+    })?;
+    let off_snap = map.off_snap_notes(OFF_SNAP_TOLERANCE);
+    if let Some(first) = off_snap.first() {
+        logger::warning(&format!(
+            "{} notes are more than {OFF_SNAP_TOLERANCE} ms off their 1/48 snap, the first at {} ms",
+            off_snap.len(),
+            map.hit_objects[first.index].start_time
+        ));
+    }
+    map.initialize_playable_length();
+
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use crate::logger;
+use crate::utils::Time;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path};
+
+// offsets added to the global one for charts whose timing doesn't line up with their audio
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LocalOffsets {
+    offsets: HashMap<String, Time>, // chart checksum (hex) -> offset (ms)
+}
+
+impl LocalOffsets {
+    pub fn load(path: &Path) -> Self {
+        // a missing file has no offsets, an unreadable one is replaced on the next save
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                logger::warning(&format!("Failed to read '{}': {}", path.display(), e));
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            logger::warning(&format!("Ignoring unreadable local offsets '{}': {}", path.display(), e));
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).map_err(|e| anyhow!("Failed to write '{}': {}", path.display(), e))
+    }
+
+    pub fn get(&self, checksum: u64) -> Time {
+        self.offsets.get(&format!("{checksum:016x}")).copied().unwrap_or(0.0)
+    }
+
+    pub fn set(&mut self, checksum: u64, offset: Time) {
+        // 0 is the same as no local offset, so it isn't kept
+        let key = format!("{checksum:016x}");
+        if offset == 0.0 {
+            self.offsets.remove(&key);
+        } else {
+            self.offsets.insert(key, offset);
+        }
+    }
+}
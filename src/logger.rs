@@ -35,9 +35,23 @@ pub fn info(msg: &str) {
 }
 
 pub fn warning(msg: &str) {
+    #[cfg(test)]
+    WARNINGS.with_borrow_mut(|warnings| warnings.push(msg.to_string()));
     log(LogLevel::Warning, msg);
 }
 
+#[cfg(test)]
+thread_local! {
+    // warnings logged on this thread, so tests can check what a load warned about
+    static WARNINGS: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+#[cfg(test)]
+pub fn take_warnings() -> Vec<String> {
+    // the warnings logged on this thread since the last call
+    WARNINGS.take()
+}
+
 pub fn error(msg: &str) {
     log(LogLevel::Error, msg);
 }
\ No newline at end of file
@@ -0,0 +1,210 @@
+use crate::map::{Map, TimingGroup};
+use crate::utils::{index_at_time, skin, Time};
+
+// how far ahead of and behind the current time a group's notes can be on screen, so the rest can be
+// skipped when drawing. The SV decides how far along the track a note is, the current SSF and scroll
+// speed how far that is on screen (an SSF scales the whole distance by its value now, so later ones
+// don't matter until they're reached)
+
+// screen distance (in view heights) past the hit position notes are still drawn within, twice the view
+// so notes a note height above their position or drawn at an earlier tick's are covered
+const REACH_VIEW_HEIGHTS: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LookWindow {
+    pub ahead: Option<Time>,  // ms after the current time notes can be on screen, None for no limit
+    pub behind: Option<Time>, // ms before it a note's end can be and still be on screen
+}
+
+impl LookWindow {
+    pub const UNLIMITED: Self = Self { ahead: None, behind: None };
+
+    pub fn last_start(&self, time: Time) -> Time {
+        // latest start time a note can have and be on screen
+        self.ahead.map_or(Time::INFINITY, |ahead| time + ahead)
+    }
+
+    pub fn first_end(&self, time: Time) -> Time {
+        // earliest end time (start for normal notes) a note can have and be on screen
+        self.behind.map_or(Time::NEG_INFINITY, |behind| time - behind)
+    }
+}
+
+pub fn time_to_travel_ahead(group: &TimingGroup, time: Time, distance: f64, ignore_sv: bool) -> Option<Time> {
+    // ms after time the track moves `distance` (ms at 1x) past where it is; None if it never does, or if
+    // it turns back at some point (notes could come back on screen however far ahead they are)
+    if ignore_sv {
+        return Some(distance);
+    }
+    let scroll_velocities = &group.scroll_velocities;
+    let current = index_at_time(scroll_velocities, time);
+    let mut velocity = current.map_or(group.initial_scroll_velocity, |index| scroll_velocities[index].multiplier);
+    let upcoming = &scroll_velocities[current.map_or(0, |index| index + 1)..];
+    if velocity < 0.0 || upcoming.iter().any(|sv| sv.multiplier < 0.0) {
+        return None;
+    }
+    let (mut from, mut left) = (time, distance);
+    for sv in upcoming {
+        let travelled = velocity * (sv.start_time - from);
+        if velocity > 0.0 && travelled >= left {
+            break;
+        }
+        left -= travelled;
+        from = sv.start_time;
+        velocity = sv.multiplier;
+    }
+    // 0x to the end never gets there
+    (velocity > 0.0).then(|| from + left / velocity - time)
+}
+
+pub fn time_to_travel_behind(group: &TimingGroup, time: Time, distance: f64, ignore_sv: bool) -> Option<Time> {
+    // the same going back from time: ms before it the track was `distance` behind where it is
+    if ignore_sv {
+        return Some(distance);
+    }
+    let scroll_velocities = &group.scroll_velocities;
+    let passed = &scroll_velocities[..index_at_time(scroll_velocities, time).map_or(0, |index| index + 1)];
+    if group.initial_scroll_velocity < 0.0 || passed.iter().any(|sv| sv.multiplier < 0.0) {
+        return None;
+    }
+    let (mut to, mut left) = (time, distance);
+    for sv in passed.iter().rev() {
+        let travelled = sv.multiplier * (to - sv.start_time);
+        if sv.multiplier > 0.0 && travelled >= left {
+            return Some(time - (to - left / sv.multiplier));
+        }
+        left -= travelled;
+        to = sv.start_time;
+    }
+    // before the first SV the initial velocity goes on forever
+    let velocity = group.initial_scroll_velocity;
+    (velocity > 0.0).then(|| time - to + left / velocity)
+}
+
+pub fn derive_look_window(map: &Map, group: &TimingGroup, view_height: f64) -> LookWindow {
+    // the window notes of a group can be on screen in at the map's current time and scroll speed
+    if map.safe_mode_speed.is_some() {
+        // safe mode draws notes where they were heading, which can be anywhere the SV had them
+        return LookWindow::UNLIMITED;
+    }
+    let ssf = if map.mods.no_ssf { 1.0 } else { group.current_ssf_factor };
+    let pixels_per_ms = (group.scroll_speed * ssf).abs();
+    if pixels_per_ms < f64::EPSILON || !pixels_per_ms.is_finite() {
+        // nothing moves, everything is at the hit position
+        return LookWindow::UNLIMITED;
+    }
+    let distance = view_height * REACH_VIEW_HEIGHTS / pixels_per_ms;
+    LookWindow {
+        ahead: time_to_travel_ahead(group, map.time, distance, map.mods.no_sv),
+        behind: time_to_travel_behind(group, map.time, distance, map.mods.no_sv),
+    }
+}
+
+pub fn look_window(map: &Map, group: &TimingGroup, view_height: f64) -> LookWindow {
+    // derived from the SV, with the skin's look-ahead in its place when it sets one
+    let window = derive_look_window(map, group, view_height);
+    let lookahead = skin().lookahead;
+    if lookahead > 0.0 {
+        LookWindow { ahead: Some(lookahead * map.rate), ..window }
+    } else {
+        window
+    }
+}
+
+pub fn notes_in_view(map: &Map, view_height: f64) -> impl DoubleEndedIterator<Item = usize> + '_ {
+    // notes_in_draw_order without the ones too far from the current time to be on screen
+    map.group_draw_order.iter().flat_map(move |&group_index| {
+        let window = look_window(map, &map.timing_groups[group_index], view_height);
+        let (last_start, first_end) = (window.last_start(map.time), window.first_end(map.time));
+        // each group's notes are in time order, so the ones after the window are cut off in one go
+        let group_notes = &map.group_notes[group_index];
+        let in_window = group_notes.partition_point(|&index| map.hit_objects[index].start_time <= last_start);
+        group_notes[..in_window].iter().copied().filter(move |&index| {
+            let note = &map.hit_objects[index];
+            note.end_time.unwrap_or(note.start_time) >= first_end
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::set_reference_positions;
+    use crate::utils::DEFAULT_SKIN;
+
+    const VIEW_HEIGHT: f64 = 1200.0;
+
+    fn sv_spike_map() -> Map {
+        // 1x, then 10x for a second from 5 s: the notes just after the spike start far up the track
+        let mut map: Map = serde_yaml::from_str(
+            "\
+Mode: Keys4
+TimingPoints:
+- StartTime: 0
+  Bpm: 120
+SliderVelocities:
+- StartTime: 5000
+  Multiplier: 10
+- StartTime: 6000
+  Multiplier: 1
+HitObjects:
+- StartTime: 2000
+  Lane: 1
+  KeySounds: []
+- StartTime: 5500
+  Lane: 2
+  KeySounds: []
+- StartTime: 5900
+  Lane: 3
+  KeySounds: []
+- StartTime: 6500
+  Lane: 4
+  KeySounds: []
+- StartTime: 8000
+  Lane: 1
+  KeySounds: []
+",
+        )
+        .unwrap();
+        map.rate = 1.0;
+        crate::initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        map.update_scroll_speed();
+        map
+    }
+
+    fn on_screen(map: &Map, index: usize) -> bool {
+        // any part of the note (drawn a note height above its position) inside the view
+        let bottom = VIEW_HEIGHT + map.hit_objects[index].position as f64;
+        bottom > 0.0 && bottom - DEFAULT_SKIN.note_height < VIEW_HEIGHT
+    }
+
+    #[test]
+    fn notes_after_an_sv_spike_are_in_view_before_they_reach_the_screen() {
+        let mut map = sv_spike_map();
+        // when each note first gets into the look window, and when it first gets on screen
+        let mut first_in_view = vec![None; map.hit_objects.len()];
+        let mut first_on_screen = vec![None; map.hit_objects.len()];
+        for step in 0..=1800 {
+            let time = f64::from(step) * 5.0;
+            map.time = time;
+            map.update_track_position(time);
+            map.update_hit_objects().unwrap();
+            let in_view: Vec<usize> = notes_in_view(&map, VIEW_HEIGHT).collect();
+            for index in 0..map.hit_objects.len() {
+                if in_view.contains(&index) {
+                    first_in_view[index].get_or_insert(time);
+                }
+                if on_screen(&map, index) {
+                    assert!(in_view.contains(&index), "note at {} ms is on screen at {time} ms but not drawn", map.hit_objects[index].start_time);
+                    first_on_screen[index].get_or_insert(time);
+                }
+            }
+        }
+        // the spike carries the 5.9 s note across the screen in about 40 ms: it's in view a little before
+        // it shows, but not from before the spike, a second early like a window by time alone would have it
+        let (in_view, on_screen) = (first_in_view[2].unwrap(), first_on_screen[2].unwrap());
+        assert!(in_view < on_screen, "in view at {in_view} ms, on screen at {on_screen} ms");
+        assert!(in_view > 5000.0, "in view at {in_view} ms");
+        assert!(first_on_screen.iter().all(Option::is_some));
+    }
+}
@@ -1,21 +1,48 @@
 #![allow(clippy::eq_op)]
 #![allow(unused_imports)]
 
-mod audio_manager;
-mod draw;
-mod map;
-mod render;
-mod utils;
-mod logger;
-
-use audio_manager::AudioManager;
-use draw::MacroquadDraw;
-use map::Map;
-use render::{render_frame, set_reference_positions, FrameState};
-use utils::{index_at_time, lerp, object_at_time, sort_by_start_time, HasStartTime, Time, JudgementType, SKIN};
+use vsrg_renderer::{
+    alloc_stats, audio_manager, autosave, clips, config, debug_checks, difficulties, doctor, draw, ducking, effects,
+    frame_pacing, golden, graph, hit_windows, initialize_map, input, input_gate, keysounds, local_offset, logger, lookahead, map, map_skin, mapset, mash, memory_budget, onset, pace, package, picker, qua_stream,
+    rate, rate_ramp, regions, render, replay, results, resume, scenarios, scoring, scroll_suggestion, seek, simple_notes, splash, strings, sync_test, thumbnail, trace,
+    transform, utils,
+};
+#[cfg(feature = "net")]
+use vsrg_renderer::broadcast;
+#[cfg(feature = "online")]
+use vsrg_renderer::net;
+
+use audio_manager::{AudioManager, PlaybackState};
+use resume::ResumeStore;
+use transform::{Mirror, NoLongNotes, Random, ScaleTimes, ShiftTimes, TransformPipeline};
+use local_offset::LocalOffsets;
+use scroll_suggestion::ScrollSuggestions;
+use autosave::{chart_checksum, Autosave, AutosaveLine, RecoveredPlay, ScoreSnapshot, AUTOSAVE_INTERVAL};
+use config::Config;
+use debug_checks::{InvariantChecker, InvariantMode};
+use difficulties::DifficultyCache;
+use pace::PaceDisplay;
+use hit_windows::ActiveWindows;
+use input::{Binding, KeyBindings, PAUSE_KEY};
+use input_gate::InputGate;
+use frame_pacing::{draw_frame_pacing, FramePacing, PhaseTimer, GRAPH_HEIGHT};
+use draw::{save_screenshot, Draw, MacroquadDraw, OffscreenDraw, SoftwareDraw, MAX_RENDER_SCALE, MIN_RENDER_SCALE};
+use keysounds::{chart_sounds, note_key_sounds, resolve_samples, SoundScheduler};
+use map::{diff_charts, Map, Mods, NoteLock, OFF_SNAP_TOLERANCE};
+use package::ChartMetadata;
+use mash::MashDetector;
+use render::{note_at_screen_position, render_hit_window_bands, render_lane_splashes, render_note_inspection, render_frame, render_hit_error_bar, render_progress_bar, set_reference_positions, update_frame, update_versus, render_versus, FixedTimestep, FrameState, FrameThrottle};
+use regions::{avoid_regions, draw_reserved_regions, ReservedRegion};
+use replay::{Replay, ReplayEvent, ReplayPlayer};
+use rate_ramp::{RampOutcome, RampSchedule, RateRamp, DEFAULT_RAMP_ACCURACY, RAMP_RETRY_DELAY};
+use results::{draw_results, ResultsSummary};
+use scoring::Ruleset;
+use strings::{tr, tr_args};
+use sync_test::{SYNC_TEST_BPM, SYNC_TEST_DURATION, SYNC_TEST_LANES};
+use utils::{format_time, judgement_color, index_at_time, lerp, object_at_time, sort_by_start_time, FieldPositions, HasStartTime, Time, TimeStyle, JudgementType, Rng, Skin, skin, set_skin};
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use core::f64;
 use macroquad::prelude::*;
 // use macroquad::miniquad::{BlendFactor, PipelineParams};
@@ -28,16 +55,29 @@ use std::{
     time::Instant,
 };
 
+// counts allocations, so map loading can log its peak memory use
+#[global_allocator]
+static ALLOCATOR: alloc_stats::CountingAllocator = alloc_stats::CountingAllocator;
+
 #[derive(Parser, Debug, Clone)]
-#[command(author, version, about = "VSRG Renderer")]
+#[command(
+    author,
+    version,
+    about = "VSRG Renderer",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct CliArgs {
-    map_dir: PathBuf, // directory containing the map (.qua) file
+    #[command(subcommand)]
+    command: Option<Command>, // headless tools; no window is opened
+    #[arg(required_unless_present_any = ["sync_test", "from_csv"])]
+    map_dir: Option<PathBuf>, // directory or .qp/.osz/.zip archive containing the map (.qua, .osu, .sm or .ssc) file
     #[arg(long)]
-    fullscreen: bool, // start in fullscreen
+    fullscreen: bool, // start in fullscreen, even if the config doesn't
     #[arg(long, default_value_t = 1.0)]
     rate: f64,        // playback rate
-    #[arg(long, default_value_t = 0.03)]
-    volume: f64,      // initial audio volume
+    #[arg(long)]
+    volume: Option<f64>, // initial audio volume, instead of the config's
     #[arg(long)]
     mirror: bool,     // mirror notes horizontally
     #[arg(long)]
@@ -47,26 +87,654 @@ struct CliArgs {
     #[arg(long)]
     autoplay: bool,   // autoplay mode
     #[arg(long)]
+    random: bool,     // shuffle lanes
+    #[arg(long)]
+    no_ln: bool,      // play every LN as a normal note
+    #[arg(long)]
+    seed: Option<u64>, // seed for everything randomized, random if not given (logged for replaying)
+    #[arg(long)]
     debug: bool,      // enable debug text
     #[arg(long)]
     no_ui: bool,      // disable UI elements
+    #[arg(long)]
+    compare: Option<PathBuf>, // directory of a second chart to overlay
+    #[arg(long)]
+    highlight_diff: bool, // mark notes that only exist in one of the compared charts
+    #[arg(long, value_name = "HZ")]
+    fixed_timestep: Option<f64>, // run the simulation at a fixed tick rate and interpolate rendering, instead of the config's (0 for off)
+    #[arg(long)]
+    lang: Option<String>, // ui language, loaded from lang/<lang>.toml, instead of the config's
+    #[arg(long)]
+    ignore_map_skin: bool, // don't apply the map's map_skin.toml overrides
+    #[arg(long)]
+    watermark: bool,  // show the rate/mods watermark even with --no-ui
+    #[arg(long)]
+    mash_presses: Option<usize>, // presses in one lane within the mash window that count as mashing, instead of the config's
+    #[arg(long, value_name = "MS")]
+    mash_window: Option<f64>, // window for mash detection, instead of the config's
+    #[arg(long)]
+    mash_toast: bool, // show a toast when mashing is detected during play
+    #[arg(long, value_name = "MS")]
+    audio_latency: Option<f64>, // output latency to assume when the audio device doesn't report one, instead of the config's
+    #[arg(long, value_name = "PNG")]
+    results_image: Option<PathBuf>, // also save the results screen to an image when the map is finished
+    #[arg(long, value_name = "JSON")]
+    results_json: Option<PathBuf>, // also save the results, with the hit windows they were judged with, as json
+    #[arg(long)]
+    difficulty: Option<String>, // difficulty to play when the map has several (part of its name)
+    #[arg(long, conflicts_with = "map_dir")]
+    sync_test: bool, // play a generated metronome chart with a click track to check offset and sync
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["map_dir", "sync_test", "difficulty"])]
+    from_csv: Option<PathBuf>, // play a .notes.csv note list (start_ms,lane[,end_ms] rows) as a chart
+    #[arg(long, num_args = 2, value_names = ["REPLAY1", "REPLAY2"], conflicts_with_all = ["compare", "autoplay", "sync_test"])]
+    versus: Option<Vec<PathBuf>>, // play two replays of the chart side by side
+    #[arg(long, requires = "versus")]
+    force_windows: bool, // play --versus replays recorded with other hit windows than the current ones (with a warning)
+    #[arg(long, value_name = "JSON", conflicts_with = "versus")]
+    record_replay: Option<PathBuf>, // save the play's key presses (since the last restart) as a replay on exit
+    #[arg(long)]
+    no_throttle: bool, // run at full frame rate even while paused or in the background
+    #[arg(long, value_enum, default_value_t = Ruleset::Quaver)]
+    ruleset: Ruleset, // how accuracy is scored, judgements are the same for every ruleset
+    #[arg(long = "reserve-region", value_name = "X,Y,W,H[,LABEL]")]
+    reserved_regions: Vec<ReservedRegion>, // screen areas to keep clear of UI and mark with a placeholder (repeatable)
+    #[arg(long, value_enum, default_value_t = NoteLock::Strict)]
+    note_lock: NoteLock, // which note a press judges when several in its lane overlap
+    #[arg(long)]
+    no_resume: bool, // don't offer to resume long maps where they were last quit, or remember where that was
+    #[arg(long, value_name = "PORT")]
+    broadcast: Option<u16>, // serve the live play's state to overlays over a websocket on this port (`net` feature)
+    #[arg(long)]
+    safe_mode: bool, // photosensitivity-safe mode, even if the config doesn't: limits how fast things move and turns off flashing effects
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "warn")]
+    check_invariants: Option<InvariantMode>, // check the map and play state after seeks and every few frames, log (or panic on) anything broken
+    #[arg(long, value_name = "SCALE")]
+    render_scale: Option<f64>, // resolution the scene is drawn at as a fraction of the window's (0.5-2), instead of the config's
+    #[arg(long, value_name = "START:MAX:STEP", conflicts_with_all = ["rate", "versus"])]
+    rate_ramp: Option<RampSchedule>, // practice from the start rate up, retrying a step faster after each clear until max
+    #[arg(long, value_name = "PERCENT", default_value_t = DEFAULT_RAMP_ACCURACY, requires = "rate_ramp")]
+    ramp_accuracy: f64, // accuracy a play needs for --rate-ramp to move up
+    #[arg(long)]
+    strict: bool, // refuse to play a chart with validation problems instead of warning about them
+    #[arg(long, value_name = "0-1")]
+    background_dim: Option<f64>, // how much the map's background is darkened, instead of the config's
+    #[arg(long = "keys", value_name = "KEY,KEY,...")]
+    key_bindings: Vec<Binding>, // gameplay keys, lane 1 first, e.g. "s,d,f,space,j,k,l" for 7K; replaces the config's for that many keys (repeatable)
 }
 
-fn window_conf() -> Conf {
-    let args = CliArgs::parse();
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    #[command(about = "Download a Quaver mapset by id into the songs folder")]
+    Get {
+        mapset_id: u64,
+    },
+    #[command(about = "Shift every time in a chart by a number of milliseconds and save it")]
+    Shift {
+        map_dir: PathBuf,     // directory or .qp/.osz/.zip archive containing the map (.qua, .osu, .sm or .ssc) file
+        #[arg(long)]
+        difficulty: Option<String>, // difficulty to use when the map has several
+        #[arg(long, allow_negative_numbers = true)]
+        ms: f64,              // milliseconds to shift by (negative = earlier)
+        #[arg(long)]
+        out: PathBuf,         // where to write the shifted .qua
+        #[arg(long, allow_negative_numbers = true)]
+        from: Option<f64>,    // only shift objects starting at or after this time
+        #[arg(long, allow_negative_numbers = true)]
+        to: Option<f64>,      // only shift objects starting at or before this time
+        #[arg(long)]
+        clamp: bool,          // clamp times shifted before 0 instead of failing
+    },
+    #[command(about = "Write a chart's data as JSON")]
+    Dump {
+        map_dir: PathBuf,     // directory or .qp/.osz/.zip archive containing the map (.qua, .osu, .sm or .ssc) file
+        #[arg(long)]
+        difficulty: Option<String>, // difficulty to use when the map has several
+        #[arg(long)]
+        out: PathBuf,         // where to write the json
+        #[arg(long)]
+        computed: bool,       // also include each note's computed snap and track positions
+        #[arg(long)]
+        rate: Option<f64>,    // write times as if played at this rate
+        #[arg(long)]
+        no_ln: bool,          // turn every LN into a normal note
+        #[arg(long)]
+        mirror: bool,         // flip the lanes
+        #[arg(long, value_name = "SEED")]
+        random: Option<u64>,  // shuffle the lanes with this seed (after mirroring)
+    },
+    #[command(about = "Print a chart's note counts and the notes that are off their beat snap")]
+    Stats {
+        map_dir: PathBuf,     // directory or .qp/.osz/.zip archive containing the map (.qua, .osu, .sm or .ssc) file
+        #[arg(long)]
+        difficulty: Option<String>, // difficulty to use when the map has several
+        #[arg(long, default_value_t = OFF_SNAP_TOLERANCE)]
+        snap_tolerance: f64,  // ms a note can be off its 1/48 snap before it's reported
+    },
+    #[command(about = "Render the whole chart to a static PNG preview")]
+    Thumbnail {
+        map_dir: PathBuf,     // directory or .qp/.osz/.zip archive containing the map (.qua, .osu, .sm or .ssc) file
+        #[arg(long)]
+        difficulty: Option<String>, // difficulty to use when the map has several
+        #[arg(long)]
+        out: PathBuf,         // where to write the png
+        #[arg(long, default_value_t = 8)]
+        columns: usize,       // number of columns the chart is split across
+        #[arg(long)]
+        sv_heat: bool,        // tint the background by scroll velocity
+    },
+    #[command(about = "Check that textures, audio, the songs folder, the config and the position math all work")]
+    Doctor {
+        #[arg(long)]
+        no_tone: bool,        // only open the audio output, without playing the test tone
+    },
+    #[command(about = "Simulate an autoplay of the chart and write every frame's note positions")]
+    Trace {
+        map_dir: PathBuf,     // directory or .qp/.osz/.zip archive containing the map (.qua, .osu, .sm or .ssc) file
+        #[arg(long)]
+        difficulty: Option<String>, // difficulty to use when the map has several
+        #[arg(long, value_name = "OUT.csv|OUT.bin")]
+        trace_positions: PathBuf, // where to write the positions, csv or binary by extension
+        #[arg(long, default_value_t = 60.0)]
+        fps: f64,             // simulated frames per second of song time
+        #[arg(long, default_value_t = 1.0)]
+        rate: f64,            // playback rate
+        #[arg(long, default_value_t = 1200.0)]
+        view_height: f64,     // height of the simulated screen
+        #[arg(long)]
+        mirror: bool,         // mirror notes horizontally
+        #[arg(long)]
+        no_sv: bool,          // ignore scroll velocities
+        #[arg(long)]
+        no_ssf: bool,         // ignore scroll speed factors
+    },
+    #[command(about = "Render the golden frames and compare their draw commands with the checked-in ones")]
+    Golden {
+        #[arg(default_value = "golden")]
+        dir: PathBuf,         // directory with cases.toml, the charts and the expected frames
+        #[arg(long)]
+        bless: bool,          // write the rendered frames as the expected ones instead of comparing
+    },
+    #[command(about = "Play the scripted scenarios headless and check how each play came out")]
+    Scenarios {
+        #[arg(default_value = "scenarios")]
+        dir: PathBuf, // directory with the scenario files (.toml or .json) and a charts directory
+    },
+}
+
+// receptor image, reloaded with the skin (F5) when it changes on disk
+const RECEPTOR_TEXTURE: &str = "skins/receptor.png";
+
+// how long (s) a toast message stays on screen
+const TOAST_DURATION: f64 = 3.0;
+
+// size of the --results-image export
+const RESULTS_IMAGE_WIDTH: u32 = 640;
+const RESULTS_IMAGE_HEIGHT: u32 = 900;
+
+// how far apart (ms) two notes in the same lane can be and still count as the same note
+const COMPARE_TOLERANCE_MS: f64 = 10.0;
+
+fn find_map_charts(map_path: &Path) -> Result<(Vec<ChartMetadata>, PathBuf)> {
+    // the charts in a map directory or .qp/.zip archive, and the mapset's root directory
+    // (the extraction directory for archives)
+    let root = if package::is_archive(map_path) {
+        package::extract_cached(map_path, &cache_dir().join("packages/"))?
+    } else {
+        map_path.to_path_buf()
+    };
+    let charts = package::find_charts(&root)?;
+    Ok((charts, root))
+}
+
+fn load_chart(chart: &ChartMetadata) -> Result<Map> {
+    logger::info(&format!(
+        "Loading map: {}",
+        chart.path.display()
+    ));
+    let map = Map::from_file(&chart.path)?;
+    logger::info(&map.parse_report);
+    report_validation_errors(&map);
+    Ok(map)
+}
+
+fn report_validation_errors(map: &Map) {
+    // problems the chart was loaded with anyway (see Map::validate)
+    for error in &map.validation_errors {
+        logger::warning(&format!("Chart problem: {error}"));
+    }
+}
+
+fn check_strict(map: &Map, strict: bool) -> Result<()> {
+    // with --strict, a chart with validation problems isn't played
+    if strict && !map.validation_errors.is_empty() {
+        anyhow::bail!(
+            "'{}' has {} validation problem(s) and --strict is set",
+            map.file_path,
+            map.validation_errors.len()
+        );
+    }
+    Ok(())
+}
+
+fn load_map(map_path: &Path, difficulty: Option<&str>) -> Result<Map> {
+    // loads a map without a window, so several difficulties need one picked by name
+    let (charts, _) = find_map_charts(map_path)?;
+    let chart = package::choose_chart(&charts, difficulty)?.ok_or_else(|| {
+        anyhow::anyhow!(
+            "Multiple difficulties ({}), pick one with --difficulty",
+            package::difficulty_names(&charts)
+        )
+    })?;
+    load_chart(chart)
+}
+
+async fn pick_map(map_path: &Path, difficulty: Option<&str>) -> Result<(Map, PathBuf)> {
+    // loads a map, showing a picker when it has several difficulties and none was given
+    let (charts, root) = find_map_charts(map_path)?;
+    Ok((pick_chart(&charts, difficulty).await?, root))
+}
+
+async fn pick_chart(charts: &[ChartMetadata], difficulty: Option<&str>) -> Result<Map> {
+    let chart = match package::choose_chart(charts, difficulty)? {
+        Some(chart) => chart,
+        None => picker::pick_difficulty(charts).await?,
+    };
+    load_chart(chart)
+}
+
+fn apply_rate(audio_manager: &mut AudioManager, map: &mut Map, rate: f64) -> String {
+    // every rate change goes through here: snapped to two decimals and kept in range; returns its toast
+    let snapped = rate::snap_rate(rate);
+    if (snapped - rate).abs() > 0.005 {
+        logger::warning(&format!(
+            "Rate {rate} is outside {}-{}, using {snapped}",
+            rate::MIN_RATE,
+            rate::MAX_RATE
+        ));
+    }
+    audio_manager.set_rate(snapped);
+    map.rate = snapped;
+    rate::rate_toast(snapped)
+}
+
+fn set_scroll_speed(scroll_speed: f64, user_skin: &mut Skin, map_skin: &mut Option<Skin>) {
+    // every scroll speed change while playing goes through here, both skins take it so switching
+    // between them (K) keeps it
+    user_skin.scroll_speed = scroll_speed;
+    if let Some(map_skin) = map_skin.as_mut() {
+        map_skin.scroll_speed = scroll_speed;
+    }
+    let mut current = skin();
+    current.scroll_speed = scroll_speed;
+    set_skin(current);
+}
+
+fn suggest_scroll_speed(map: &Map, checksum: Option<u64>, suggestions: &mut ScrollSuggestions) -> Option<f64> {
+    // a scroll speed to offer the first time a chart is loaded, if it's far enough from the current one
+    let checksum = checksum.filter(|&checksum| !suggestions.was_offered(checksum))?;
+    let skin = skin();
+    let suggested = Map::suggest_scroll_speed(
+        &map.note_density()?,
+        skin.note_height,
+        f64::from(screen_height()),
+        map.rate,
+        skin.normalize_scroll_velocity_by_rate_percentage,
+    )?;
+    // first load only, whether it's offered or not
+    suggestions.mark_offered(checksum);
+    if let Err(e) = suggestions.save(&scroll_suggestions_path()) {
+        logger::error(&format!("{e}"));
+    }
+    if !scroll_suggestion::worth_offering(suggested, skin.scroll_speed) {
+        return None;
+    }
+    logger::info(&format!("Suggesting a scroll speed of {suggested:.0} for this chart (it's {:.0})", skin.scroll_speed));
+    Some(suggested)
+}
+
+fn prepare_difficulty(mut chart: Map, previous: &Map, length: Time, field_positions: &FieldPositions, strict: bool) -> Result<Map> {
+    // another difficulty of the mapset, set up to carry on with the same mods and settings as the one before
+    report_validation_errors(&chart);
+    check_strict(&chart, strict)?;
+    chart.length = length;
+    chart.rate = previous.rate;
+    chart.mods = previous.mods.clone();
+    chart.ruleset = previous.ruleset;
+    chart.note_lock = previous.note_lock;
+    chart.safe_mode_speed = previous.safe_mode_speed;
+    chart.lane_offsets = previous.lane_offsets.clone();
+    chart.effects = previous.effects.clone(); // the effects file is the mapset's, not one difficulty's
+    chart.mash_detector = previous.mash_detector.clone();
+    chart.mash_detector.reset();
+    TransformPipeline::from_mods(&chart.mods).apply(&mut chart)?;
+    initialize_map(&mut chart, field_positions)?;
+    Ok(chart)
+}
+
+// what the loaded chart is made of, for the debug text
+struct MapCounts {
+    hit_objects: usize,
+    timing_points: usize,
+    svs: usize,
+    ssfs: usize,
+    timing_groups: usize,
+    timing_lines: usize,
+    common_bpm: f64,
+    bpm_range: (f64, f64),
+}
+
+impl MapCounts {
+    fn of(map: &Map) -> Self {
+        Self {
+            hit_objects: map.hit_objects.len(),
+            timing_points: map.timing_points.len(),
+            svs: map.timing_groups.values().map(|g| g.scroll_velocities.len()).sum(),
+            ssfs: map.timing_groups.values().map(|g| g.scroll_speed_factors.len()).sum(),
+            timing_groups: map.timing_groups.len(),
+            timing_lines: map.timing_lines.len(),
+            common_bpm: map.get_common_bpm(),
+            bpm_range: map.get_bpm_range(),
+        }
+    }
+
+    fn bpm_text(&self) -> String {
+        // the common bpm, with the range when it changes
+        let format_bpm = |bpm: f64| ((bpm * 100.0).round() / 100.0).to_string();
+        let (min, max) = self.bpm_range;
+        if min == max {
+            tr_args("debug.bpm", &[("bpm", &format_bpm(self.common_bpm))])
+        } else {
+            tr_args("debug.bpm_range", &[("bpm", &format_bpm(self.common_bpm)), ("min", &format_bpm(min)), ("max", &format_bpm(max))])
+        }
+    }
+}
+
+fn render_scene(state: &mut FrameState, players: &mut [&mut Map], window_bands: bool, draw: &mut impl Draw) -> Result<()> {
+    // the playfield(s), without the ui drawn over them
+    if players.is_empty() {
+        if window_bands {
+            render_hit_window_bands(state.map, state.field_positions, draw);
+        }
+        render_frame(state, draw)
+    } else {
+        render_versus(players, state.field_positions, state.alpha, draw)
+    }
+}
+
+fn warn_about_key_bindings(map: &Map, key_bindings: &KeyBindings) {
+    // a chart without keys for its lanes, and Space taken by a lane so it doesn't pause while playing
+    let key_count = map.get_key_count(true);
+    let lanes = usize::try_from(key_count).unwrap_or(0);
+    if !map.mods.autoplay && key_bindings.keys(lanes).is_empty() {
+        logger::warning(&format!("No keys are bound for {key_count} lanes, the chart can only be watched (bind them with --keys)"));
+    }
+    if key_bindings.binds(lanes, KeyCode::Space) {
+        logger::warning(&format!("Space plays a lane with {key_count} lanes, pause with F8 while playing"));
+    }
+}
+
+fn modified_time(path: &Path) -> Option<std::time::SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn find_background(map: &Map, map_folder_path: &Path, map_root: &Path) -> Option<PathBuf> {
+    map.background_file.as_ref().and_then(|file_name| {
+        package::find_asset(map_folder_path, map_root, file_name)
+            .inspect_err(|e| logger::warning(&format!("Can't use background image: {e}")))
+            .ok()
+    })
+}
+
+async fn load_background(path: Option<&Path>) -> Option<Texture2D> {
+    // the map's background, or None (a black one) if it can't be used; decoded here because
+    // load_texture panics on files it can't decode
+    let path = path?;
+    let loaded = match load_file(&path.to_string_lossy()).await {
+        Ok(bytes) => package::decode_image(path, &bytes),
+        Err(e) => Err(anyhow::anyhow!("Failed to read '{}': {}", path.display(), e)),
+    };
+    loaded
+        .inspect_err(|e| logger::warning(&format!("Can't use background image: {e}")))
+        .ok()
+        .map(|image| Texture2D::from_image(&image))
+}
+
+fn reposition_map(map: &mut Map, field_positions: &FieldPositions) -> Result<()> {
+    // places notes and timing lines again after the field positions changed
+    map.initialize_hit_objects(field_positions)?;
+    map.initialize_timing_lines(field_positions)
+}
+
+fn songs_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("songs/")
+}
+
+fn config_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("config.toml")
+}
+
+fn autosave_path(checksum: u64) -> PathBuf {
+    // the play in progress, left behind if the renderer doesn't exit normally
+    // one per chart, so playing another chart can't overwrite a play that wasn't recovered yet
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(format!(".inprogress-{checksum:016x}.vsr"))
+}
+
+fn recovered_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("recovered/")
+}
+
+fn resume_path() -> PathBuf {
+    // where each chart was last quit
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("resume.json")
+}
+
+fn local_offsets_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("local_offsets.json")
+}
+
+fn scroll_suggestions_path() -> PathBuf {
+    // charts a scroll speed was already suggested for
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("scroll_suggestions.json")
+}
+
+fn clips_path() -> PathBuf {
+    // moments marked with F9 while playing
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("clips.txt")
+}
+
+fn screenshots_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("screenshots/")
+}
+
+fn cache_dir() -> PathBuf {
+    // generated files: extracted mapset archives, the sync test's click track
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("cache/")
+}
+
+fn run_command(command: &Command) -> Result<()> {
+    // runs a headless subcommand
+    match command {
+        Command::Get { mapset_id } => {
+            #[cfg(feature = "online")]
+            {
+                let mapset_dir = net::download_mapset(&net::HttpFetcher, *mapset_id, &songs_dir())
+                    .inspect_err(|e| logger::error(&format!("Failed to download mapset {mapset_id}: {e}")))?;
+                logger::info(&format!("Mapset {mapset_id} saved to {}", mapset_dir.display()));
+                Ok(())
+            }
+            #[cfg(not(feature = "online"))]
+            {
+                anyhow::bail!("Cannot download mapset {mapset_id}: built without the `online` feature")
+            }
+        }
+        Command::Shift { map_dir, difficulty, ms, out, from, to, clamp } => {
+            let mut map = load_map(&songs_dir().join(map_dir), difficulty.as_deref())?;
+            let range = match (from, to) {
+                (None, None) => None,
+                (from, to) => Some((from.unwrap_or(f64::NEG_INFINITY), to.unwrap_or(f64::INFINITY))),
+            };
+            TransformPipeline::new()
+                .then(ShiftTimes { delta: *ms, range, clamp: *clamp })
+                .apply(&mut map)?;
+            fs::write(out, map.to_qua_string()?)
+                .map_err(|e| anyhow::anyhow!("Failed to write '{}': {}", out.display(), e))?;
+            logger::info(&format!("Shifted map by {ms} ms, saved to {}", out.display()));
+            Ok(())
+        }
+        Command::Dump { map_dir, difficulty, out, computed, rate, no_ln, mirror, random } => {
+            let mut map = load_map(&songs_dir().join(map_dir), difficulty.as_deref())?;
+            let mut pipeline = TransformPipeline::new();
+            if let Some(rate) = *rate {
+                pipeline = pipeline.then(ScaleTimes { rate });
+            }
+            pipeline = pipeline.then_if(*no_ln, NoLongNotes).then_if(*mirror, Mirror);
+            if let Some(seed) = *random {
+                pipeline = pipeline.then(Random { seed });
+            }
+            let transforms = pipeline.apply(&mut map)?;
+            if !transforms.is_empty() {
+                logger::info(&format!("Chart transforms: {}", transform::describe(&transforms)));
+            }
+            // chart data only; runtime fields are never serialized
+            let mut dump = serde_json::Map::new();
+            dump.insert("Chart".to_string(), serde_json::to_value(map.chart_clone())?);
+            if !transforms.is_empty() {
+                let applied: Vec<String> = transforms.iter().map(ToString::to_string).collect();
+                dump.insert("Transforms".to_string(), serde_json::to_value(applied)?);
+            }
+            if *computed {
+                map.initialize_default_timing_group();
+                map.sort();
+                map.initialize_control_points();
+                map.initialize_beat_snaps()?;
+                let notes: Vec<serde_json::Value> = map
+                    .hit_objects
+                    .iter()
+                    .map(|note| {
+                        let group = note.timing_group.as_ref().and_then(|id| map.timing_groups.get(id));
+                        let position_at = |time| group.map(|group| group.get_position_from_time(time, false));
+                        serde_json::json!({
+                            "StartTime": note.start_time,
+                            "Lane": note.lane,
+                            "Snap": 48 / utils::BEAT_SNAPS[note.snap_index].divisor,
+                            "StartPosition": position_at(note.start_time),
+                            "EndPosition": note.end_time.and_then(position_at),
+                        })
+                    })
+                    .collect();
+                dump.insert("Computed".to_string(), serde_json::Value::from(notes));
+            }
+            let json = serde_json::to_string_pretty(&dump)?;
+            fs::write(out, json).map_err(|e| anyhow::anyhow!("Failed to write '{}': {}", out.display(), e))?;
+            logger::info(&format!("Dumped map to {}", out.display()));
+            Ok(())
+        }
+        Command::Stats { map_dir, difficulty, snap_tolerance } => {
+            let mut map = load_map(&songs_dir().join(map_dir), difficulty.as_deref())?;
+            map.initialize_default_timing_group();
+            map.sort();
+            map.initialize_beat_snaps()?;
+            let long_notes = map.hit_objects.iter().filter(|note| note.end_time.is_some()).count();
+            logger::info(&format!(
+                "{} notes ({} LNs), {} timing points",
+                map.hit_objects.len(),
+                long_notes,
+                map.timing_points.len()
+            ));
+            let off_snap = map.off_snap_notes(*snap_tolerance);
+            logger::info(&format!("{} notes more than {snap_tolerance} ms off snap", off_snap.len()));
+            for (index, timing_point) in map.timing_points.iter().enumerate() {
+                let notes: Vec<_> = off_snap.iter().filter(|note| note.timing_point == index).collect();
+                let Some(worst) = notes.iter().map(|note| note.error).reduce(f64::max) else {
+                    continue;
+                };
+                logger::info(&format!(
+                    "  timing point at {} ms ({} BPM): {} off snap, worst by {:.2} ms",
+                    timing_point.start_time,
+                    timing_point.bpm,
+                    notes.len(),
+                    worst
+                ));
+            }
+            Ok(())
+        }
+        Command::Thumbnail { map_dir, difficulty, out, columns, sv_heat } => {
+            let mut map = load_map(&songs_dir().join(map_dir), difficulty.as_deref())?;
+            // only what the thumbnail needs, there's no playfield to position against
+            map.initialize_default_timing_group();
+            map.sort();
+            map.normalize_svs();
+            map.initialize_beat_snaps()?;
+            thumbnail::render_thumbnail(&map, *columns, *sv_heat).save_png(out)?;
+            logger::info(&format!("Saved thumbnail to {}", out.display()));
+            Ok(())
+        }
+        Command::Doctor { no_tone } => doctor::report(&[
+            doctor::check_textures(&[Path::new(RECEPTOR_TEXTURE)]),
+            doctor::check_audio(!no_tone),
+            doctor::check_songs_dir(&songs_dir()),
+            doctor::check_config(&config_path()),
+            doctor::check_reference_map(),
+        ]),
+        Command::Trace { map_dir, difficulty, trace_positions, fps, rate, view_height, mirror, no_sv, no_ssf } => {
+            if *fps <= 0.0 || *rate <= 0.0 {
+                anyhow::bail!("--fps and --rate must be positive");
+            }
+            let mut map = load_map(&songs_dir().join(map_dir), difficulty.as_deref())?;
+            map.rate = *rate;
+            map.mods.mirror = *mirror;
+            map.mods.no_sv = *no_sv;
+            map.mods.no_ssf = *no_ssf;
+            map.mods.autoplay = true; // so LNs are held like in a real play
+            let field_positions = set_reference_positions(None);
+            initialize_map(&mut map, &field_positions)?;
+
+            let mut writer = trace::TraceWriter::create(trace_positions)?;
+            trace::trace_map(&mut map, &field_positions, 1000.0 / fps * rate, *view_height, &mut writer)?;
+            logger::info(&format!(
+                "Traced {} frames ({} note positions) to {}",
+                writer.frames,
+                writer.notes,
+                trace_positions.display()
+            ));
+            writer.finish()
+        }
+        Command::Golden { dir, bless } => golden::run(dir, *bless),
+        Command::Scenarios { dir } => scenarios::run(dir),
+    }
+}
+
+fn window_conf(args: &CliArgs, config: &Config) -> Conf {
     Conf {
         window_title: "VSRG Renderer".to_string(),
         window_width: 1000,
         window_height: 1200,
-        fullscreen: args.fullscreen,
+        fullscreen: args.fullscreen || config.fullscreen,
         ..Default::default()
     }
 }
 
-#[macroquad::main(window_conf)]
-pub async fn main() -> anyhow::Result<()> {
+fn main() -> Result<()> {
     let args = CliArgs::parse();
-    let mut is_fullscreen = args.fullscreen;
+    let config = Config::load_or_default(&config_path());
+    strings::set_language(args.lang.as_deref().unwrap_or(&config.lang));
+    set_skin(config.apply_to_skin(skin()));
+    if let Some(command) = &args.command {
+        return run_command(command);
+    }
+
+    macroquad::Window::from_config(window_conf(&args, &config), async move {
+        if let Err(e) = run(args, config).await {
+            logger::error(&format!("Exiting: {e}"));
+            std::process::exit(1);
+        }
+    });
+    Ok(())
+}
+
+async fn run(args: CliArgs, mut config: Config) -> Result<()> {
+    let initial_fullscreen = args.fullscreen || config.fullscreen;
+    let mut is_fullscreen = initial_fullscreen;
 
     // --- audio setup ---
     let mut audio_manager = AudioManager::new().map_err(|e| {
@@ -76,104 +744,315 @@ pub async fn main() -> anyhow::Result<()> {
         Error::other(e)
     })?;
 
-    audio_manager.set_rate(args.rate);
-    audio_manager.set_volume(args.volume);
+    // practice ramp, its rate replaces --rate
+    let mut rate_ramp = args.rate_ramp.map(|schedule| RateRamp::new(schedule, args.ramp_accuracy));
+    let mut ramp_retry_at: Option<f64> = None; // when the next rate starts after a clear
+    audio_manager.set_volume(args.volume.unwrap_or(config.volume));
+    let initial_volume = audio_manager.get_volume();
+    audio_manager.set_output_latency(args.audio_latency.or(config.audio_latency));
+    audio_manager.set_ducking(config.ducking_settings());
+    audio_manager.set_memory_cap(config.audio_memory_cap_mb);
 
     // --- map loading ---
-    let song_name = args.map_dir;
-    let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
-    let map_folder_path = project_dir.join("songs/").join(song_name);
-    let map_file_name_option = fs::read_dir(&map_folder_path)
-        .map_err(|e| anyhow::anyhow!("Failed to read map directory {:?}: {}", map_folder_path, e))?
-        .filter_map(Result::ok)
-        .map(|entry| entry.path())
-        .find(|path| path.extension().is_some_and(|ext| ext == "qua"))
-        .and_then(|path| path.to_str().map(ToString::to_string));
-
-    let Some(map_file_name) = map_file_name_option else {
-        let err_msg = format!(
-            "No .qua file found in directory {}",
-            map_folder_path.display()
-        );
-        logger::error(&err_msg);
-        anyhow::bail!(err_msg);
+    let (mut map, map_root) = if args.sync_test {
+        // metronome chart and click track generated on the spot, the chart is initialized like any other
+        let sync_test_dir = cache_dir().join("sync_test/");
+        fs::create_dir_all(&sync_test_dir)?;
+        let audio_file = "click.wav";
+        sync_test::write_click_track(&sync_test_dir.join(audio_file), SYNC_TEST_BPM, SYNC_TEST_DURATION)?;
+        let mut map = Map::synthetic_metronome(SYNC_TEST_BPM, SYNC_TEST_DURATION, SYNC_TEST_LANES);
+        map.audio_file = Some(audio_file.to_string());
+        map.file_path = sync_test_dir.join("sync_test.qua").to_string_lossy().to_string();
+        (map, sync_test_dir)
+    } else if let Some(path) = &args.from_csv {
+        // the audio named in its header is looked for next to it
+        let map = simple_notes::load_simple_notes(path)?;
+        report_validation_errors(&map);
+        let root = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+        (map, root)
+    } else {
+        match &args.map_dir {
+            Some(song_name) => pick_map(&songs_dir().join(song_name), args.difficulty.as_deref()).await?,
+            None => {
+                // no map given, pick one from the songs directory
+                let mapsets = mapset::scan_songs_dir(&songs_dir());
+                if mapsets.is_empty() {
+                    anyhow::bail!("No map directory given, and no maps in '{}'", songs_dir().display());
+                }
+                let mapset = picker::pick_mapset(&mapsets).await?;
+                (pick_chart(&mapset.charts, args.difficulty.as_deref()).await?, mapset.dir.clone())
+            }
+        }
+    };
+    check_strict(&map, args.strict)?;
+    let map_folder_path = Path::new(&map.file_path)
+        .parent()
+        .map_or_else(|| map_root.clone(), Path::to_path_buf);
+    // comparing a chart with itself (e.g. to see what --random did) reuses the parsed chart
+    let self_compare = args
+        .compare
+        .as_ref()
+        .zip(args.map_dir.as_ref())
+        .is_some_and(|(compare_dir, song_name)| compare_dir == song_name)
+        .then(|| map.chart_clone());
+    // versus: every replay plays its own copy of the chart, the loaded map only keeps the clock
+    let versus_charts = match &args.versus {
+        Some(paths) => paths
+            .iter()
+            .map(|path| Ok((map.chart_clone(), Replay::load(path)?)))
+            .collect::<Result<Vec<_>>>()?,
+        None => Vec::new(),
     };
-    logger::info(&format!(
-        "Loading map: {map_file_name}"
-    ));
-    let qua_file_content = fs::read_to_string(&map_file_name)
-        .map_err(|e| anyhow::anyhow!("Failed to read map file '{}': {}", map_file_name, e))?;
-
-    let mut map: Map = serde_yaml::from_str(&qua_file_content)
-        .map_err(|e| anyhow::anyhow!("Failed to parse map data from '{}': {}", map_file_name, e))?;
 
     // set audio path in audio manager
-    if let Some(audio_filename_str) = &map.audio_file {
-        let map_dir = Path::new(&map_file_name)
-            .parent()
-            .unwrap_or_else(|| Path::new(""));
-        let current_audio_path = map_dir.join(audio_filename_str);
-        audio_manager.set_audio_path(Some(current_audio_path));
-    } else {
-        audio_manager.set_audio_path(None);
-    }
+    let mut audio_path = map.audio_file.as_ref().and_then(|audio_filename_str| {
+        package::find_asset(&map_folder_path, &map_root, audio_filename_str)
+            .inspect_err(|e| logger::warning(&format!("Can't use audio file: {e}")))
+            .ok()
+    });
+    audio_manager.set_audio_path(audio_path.clone());
 
     map.length = audio_manager.get_total_duration_ms().unwrap_or(0f64);
-    map.rate = audio_manager.get_rate();
+    apply_rate(&mut audio_manager, &mut map, rate_ramp.as_ref().map_or(args.rate, RateRamp::rate));
     map.mods.mirror = args.mirror;
     map.mods.no_sv = args.no_sv;
     map.mods.no_ssf = args.no_ssf;
     map.mods.autoplay = args.autoplay;
     map.mods.debug = args.debug;
     map.mods.no_ui = args.no_ui;
+    map.mods.random = args.random;
+    map.mods.no_ln = args.no_ln;
+    map.ruleset = args.ruleset;
+    map.note_lock = args.note_lock;
+    map.safe_mode_speed = (args.safe_mode || config.safe_mode).then_some(config.safe_mode_max_speed);
+    map.lane_offsets = config.lane_offsets(usize::try_from(map.get_key_count(true)).unwrap_or(0));
+    map.mash_detector = MashDetector::new(
+        args.mash_presses.unwrap_or(config.mash_presses),
+        args.mash_window.unwrap_or(config.mash_window_ms),
+    );
 
-    // map processing functions / preload
-    let receptor_texture: Texture2D = load_texture("skins/receptor.png").await.unwrap();
-    let field_positions = set_reference_positions(&receptor_texture);
-    map.initialize_default_timing_group();
-    map.sort();
-    map.initialize_control_points();
-    map.initialize_hit_objects(&field_positions).map_err(|e| {
-        logger::error(&format!("Failed to initialize hit objects: {e}"));
-        e
-    })?;
-    map.initialize_timing_lines(&field_positions).map_err(|e| {
-        logger::error(&format!("Failed to initialize timing lines: {e}"));
-        e
-    })?;
-    map.initialize_beat_snaps().map_err(|e| {
-        logger::error(&format!("Failed to initialize beat snaps: {e}"));
-        e
-    })?;
+    // one seeded generator for the whole run, so the same seed gives the same run
+    let seed = args.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos() as u64)
+    });
+    logger::info(&format!("Seed: {seed}"));
+    map.mods.seed = seed;
+    // the mapset's other difficulties, to switch to while practicing (the chart is kept from before the mods)
+    let mut difficulties = if args.sync_test || args.from_csv.is_some() {
+        None
+    } else {
+        DifficultyCache::new(&map)
+            .inspect_err(|e| logger::warning(&format!("Can't switch difficulties: {e}")))
+            .ok()
+    };
+    let transforms = TransformPipeline::from_mods(&map.mods).apply(&mut map)?;
+    if !transforms.is_empty() {
+        logger::info(&format!("Chart transforms: {}", transform::describe(&transforms)));
+    }
+
+    // --- skin ---
+    if let Some(background_dim) = args.background_dim {
+        let mut current = skin();
+        current.background_dim = background_dim.clamp(0.0, 1.0);
+        set_skin(current);
+    }
+    // both are kept so the map's overrides can be switched off while playing
+    let mut user_skin = skin();
+    let mut map_skin = if args.ignore_map_skin {
+        None
+    } else {
+        map_skin::reload_map_skin(&user_skin, &map_folder_path).unwrap_or_else(|e| {
+            logger::warning(&format!("Ignoring map skin: {e}"));
+            None
+        })
+    };
+    let mut inspection = None; // note inspected in debug mode, and where to show it
+    let mut use_map_skin = map_skin.is_some();
+    let mut show_window_bands = false; // hit window preview on the playfield (H)
+    let mut toast: Option<(String, f64)> = None; // message and when it was shown
+    let mut pace_display = PaceDisplay::default();
+    match effects::load_effects(&map_folder_path) {
+        Ok(Some(effects)) => {
+            logger::info(&format!("Using {} playfield keyframes", effects.playfield.len()));
+            map.effects = effects;
+        }
+        Ok(None) => {}
+        Err(e) => logger::warning(&format!("Ignoring effects: {e}")),
+    }
+    if let Some(map_skin) = map_skin {
+        logger::info("Using map skin overrides");
+        set_skin(map_skin);
+        toast = Some((tr("toast.map_skin_active").into(), get_time()));
+    }
+
+    let mut background_path = find_background(&map, &map_folder_path, &map_root);
+    let mut background_texture = load_background(background_path.as_deref()).await;
+    let mut receptor_texture: Texture2D = load_texture(RECEPTOR_TEXTURE).await.unwrap();
+    let mut receptor_modified = modified_time(Path::new(RECEPTOR_TEXTURE));
+    let mut field_positions = set_reference_positions(Some(&receptor_texture));
+    initialize_map(&mut map, &field_positions)?;
+    let mut invariant_checker = args.check_invariants.map(|mode| InvariantChecker::new(mode, seed));
+    if let Some(checker) = invariant_checker.as_mut() {
+        checker.check(&map, "load");
+    }
+    let mut check_event: Option<&str> = None; // something that happened this frame that the checker runs after
+
+    let mut versus_players = Vec::new();
+    for (mut player_map, replay) in versus_charts {
+        player_map.length = map.length;
+        player_map.rate = map.rate;
+        player_map.mods = map.mods.clone();
+        player_map.note_lock = map.note_lock;
+        player_map.safe_mode_speed = map.safe_mode_speed;
+        replay.apply_mods(&mut player_map)?;
+        initialize_map(&mut player_map, &field_positions)?;
+        replay.check_windows(&player_map, args.force_windows)?;
+        versus_players.push((player_map, ReplayPlayer::new(replay)));
+    }
+    let mut recorded_events: Vec<ReplayEvent> = Vec::new();
+    let mut screenshot_time: Option<Time> = None; // set by shift+F9, taken once the frame is drawn
+
+    // comparison chart: same song, same clock, never judged
+    let mut compare_map = match &args.compare {
+        Some(compare_dir) => {
+            let mut compare_map = match self_compare {
+                Some(chart) => chart,
+                None => pick_map(&songs_dir().join(compare_dir), None).await?.0,
+            };
+            compare_map.length = map.length;
+            compare_map.rate = map.rate;
+            compare_map.safe_mode_speed = map.safe_mode_speed;
+            compare_map.mods = Mods {
+                autoplay: false,
+                random: false,
+                ..map.mods.clone()
+            };
+            initialize_map(&mut compare_map, &field_positions)?;
+            Some(compare_map)
+        }
+        None => None,
+    };
+    let mut chart_diff = match &compare_map {
+        Some(compare_map) if args.highlight_diff => {
+            let diff = diff_charts(&map, compare_map, COMPARE_TOLERANCE_MS);
+            logger::info(&format!(
+                "Compared charts: {} notes differ (±{COMPARE_TOLERANCE_MS} ms)",
+                diff.len()
+            ));
+            diff
+        }
+        _ => Vec::new(),
+    };
 
-    let total_hit_objects = map.hit_objects.len();
-    let total_timing_points = map.timing_points.len();
-    let total_svs = map
-        .timing_groups
-        .values()
-        .map(|g| g.scroll_velocities.len())
-        .sum::<usize>();
-    let total_ssfs = map
-        .timing_groups
-        .values()
-        .map(|g| g.scroll_speed_factors.len())
-        .sum::<usize>();
-    let total_timing_groups = map.timing_groups.len();
-    let total_timing_lines = map.timing_lines.len();
+    // chart sounds are started early by the output latency, live key presses can't be
+    let mut samples = resolve_samples(&map, &map_folder_path, &map_root);
+    let mut sound_scheduler = SoundScheduler::new(chart_sounds(&map, &samples));
+    // output latency in chart ms
+    let sound_latency = |audio_manager: &AudioManager| audio_manager.output_latency_ms() * audio_manager.get_rate();
+
+    let mut map_counts = MapCounts::of(&map);
     logger::info(&format!(
-        "Map loaded successfully: {total_hit_objects} Hit Objects, {total_timing_points} Timing Points, {total_svs} SVs, {total_ssfs} SSFs, {total_timing_groups} Timing Groups, {total_timing_lines} Timing Lines"
+        "Map loaded successfully: {} Hit Objects, {} Timing Points, {} SVs, {} SSFs, {} Timing Groups, {} Timing Lines",
+        map_counts.hit_objects,
+        map_counts.timing_points,
+        map_counts.svs,
+        map_counts.ssfs,
+        map_counts.timing_groups,
+        map_counts.timing_lines
     ));
 
     // this is the visual play state, audio is handled by audio_manager
     let mut is_playing_visuals = false;
+    let mut input_gate = InputGate::new(config.resume_grace_ms.max(0.0));
+    let mut key_bindings = config.key_bindings();
+    for binding in &args.key_bindings {
+        key_bindings.set(binding.clone());
+    }
+    warn_about_key_bindings(&map, &key_bindings);
+
+    // live plays are autosaved, so one cut short by a crash can still be exported
+    let mut checksum = fs::read(&map.file_path).ok().map(|contents| chart_checksum(&contents));
+    let mut autosave = match checksum {
+        Some(checksum) if !map.mods.autoplay && versus_players.is_empty() && !args.sync_test => {
+            if let Some(play) = RecoveredPlay::find(&autosave_path(checksum), checksum) {
+                if picker::ask_recovery(&play).await {
+                    let map_name = Path::new(&map.file_path)
+                        .file_stem()
+                        .map_or_else(|| "map".into(), |stem| stem.to_string_lossy());
+                    let path = recovered_dir().join(format!("{map_name}-{}.json", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+                    match play.export(&path) {
+                        Ok(()) => logger::info(&format!("Unfinished play exported to {}", path.display())),
+                        Err(e) => logger::error(&format!("{e}")),
+                    }
+                } else {
+                    logger::info("Unfinished play discarded");
+                }
+            }
+            Some(Autosave::start(autosave_path(checksum), checksum, map.mods.clone()))
+        }
+        _ => None,
+    };
+    // charts whose first beat doesn't line up with their audio get an offset of their own
+    let mut local_offsets = LocalOffsets::load(&local_offsets_path());
+    let mut local_offset = checksum.map_or(0.0, |checksum| local_offsets.get(checksum));
+    if local_offset != 0.0 {
+        logger::info(&format!("Using a local offset of {local_offset} ms"));
+    }
+    let mut offset_suggestion = match (checksum, &audio_path, map.timing_points.first()) {
+        (Some(_), Some(audio_path), Some(timing_point)) => {
+            onset::suggest_local_offset(audio_path, timing_point.start_time, local_offset).unwrap_or_else(|e| {
+                logger::warning(&format!("Couldn't check the audio's first beat: {e}"));
+                None
+            })
+        }
+        _ => None,
+    };
+    if let Some(suggested) = offset_suggestion {
+        logger::info(&format!("The audio's first beat suggests a local offset of {suggested:.0} ms"));
+    }
+    let mut scroll_suggestions = ScrollSuggestions::load(&scroll_suggestions_path());
+    let mut scroll_speed_suggestion = suggest_scroll_speed(&map, checksum, &mut scroll_suggestions);
+    // long maps can be picked up where they were last quit, replays can't start partway in
+    let mut resume_checksum = checksum.filter(|_| {
+        !args.no_resume && versus_players.is_empty() && !args.sync_test && args.record_replay.is_none()
+    });
+    let mut resume_store = ResumeStore::load(&resume_path());
+    let mut resume_offer = resume_checksum.and_then(|checksum| resume_store.get(checksum));
+    let mut autosaved_events = 0; // recorded events already sent to the autosave
+    let mut next_autosave_time = AUTOSAVE_INTERVAL;
+    // live state for browser overlays, sent a few times a second
+    #[cfg(feature = "net")]
+    let broadcaster = args.broadcast.map(broadcast::Broadcaster::start).transpose()?;
+    #[cfg(feature = "net")]
+    let mut next_broadcast = 0.0;
+    #[cfg(not(feature = "net"))]
+    if args.broadcast.is_some() {
+        logger::warning("Not broadcasting: built without the `net` feature");
+    }
+    // set once the map is finished
+    let mut results: Option<ResultsSummary> = None;
+    let mut results_windows_open = false; // hit windows listed on the results screen (W)
 
     // let mut json_output_file = File::create("output.json")?;
     // let json_string = serde_json::to_string_pretty(&map)?;
     // write!(json_output_file, "{json_string}")?;
     // logger::info("Parsed map data written to output.json");
 
-    let start_instant = Instant::now();
-    let mut frame_count: u64 = 0;
+    let mut frame_pacing = FramePacing::new();
+    let mut phase_timer = PhaseTimer::new();
+    let mut frame_start: Option<Instant> = None;
+    let mut fixed_timestep = Some(args.fixed_timestep.unwrap_or(config.fixed_timestep_hz))
+        .filter(|&tick_rate| tick_rate > 0.0)
+        .map(FixedTimestep::new);
+    let mut frame_throttle = FrameThrottle::new(!args.no_throttle);
+    // the scene can be drawn at another resolution than the window's, for sharpness or speed
+    let render_scale = args.render_scale.unwrap_or(config.render_scale).clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE);
+    let mut offscreen = (render_scale != 1.0).then(|| {
+        logger::info(&format!("Drawing the scene at {render_scale}x the window's resolution"));
+        OffscreenDraw::new(render_scale, config.render_filter)
+    });
 
     // let vert_src = r#"#version 100
     // attribute vec3 position;
@@ -315,20 +1194,41 @@ pub async fn main() -> anyhow::Result<()> {
 
     // main render loop
     loop {
-        frame_count += 1;
+        // the frame that just ended, measured start to start so presenting it counts too
+        let now = Instant::now();
+        let phases = std::mem::take(&mut phase_timer).finish("present");
+        if let Some(last_start) = frame_start.replace(now) {
+            // frames slowed down on purpose while idle aren't stutters
+            if !frame_throttle.is_throttled() {
+                if let Some(spike) = frame_pacing.push((now - last_start).as_secs_f64() * 1000.0, phases) {
+                    logger::warning(&format!("Slow frame: {}", spike.describe()));
+                }
+            }
+        }
 
-        let time = audio_manager.get_current_song_time_ms() + SKIN.offset;
-        map.time = time;
+        if frame_throttle.begin_frame(is_playing_visuals) {
+            // back to full rate after throttled frames, nothing from those frames carries over
+            if let Some(fixed_timestep) = fixed_timestep.as_mut() {
+                fixed_timestep.reset();
+            }
+        }
+        audio_manager.update();
+        phase_timer.mark("audio");
+        let time = audio_manager.current_position_ms() + skin().offset + local_offset;
 
         // --- inputs ---
-        if is_key_pressed(KeyCode::Escape) || is_key_pressed(KeyCode::Backspace) {
+        // hotkeys on a gameplay key of the chart are left to gameplay while playing
+        let lane_count = usize::try_from(map.get_key_count(true)).unwrap_or(0);
+        let playing = is_playing_visuals;
+        let hotkey_pressed = |key_code| is_key_pressed(key_code) && !(playing && key_bindings.binds(lane_count, key_code));
+        if hotkey_pressed(KeyCode::Escape) || hotkey_pressed(KeyCode::Backspace) {
             break;
         }
-        if is_key_pressed(KeyCode::F11) || is_key_pressed(KeyCode::F) {
+        if hotkey_pressed(KeyCode::F11) || hotkey_pressed(KeyCode::F) {
             is_fullscreen = !is_fullscreen;
             set_fullscreen(is_fullscreen);
         }
-        if is_key_pressed(KeyCode::Space) {
+        if hotkey_pressed(KeyCode::Space) || hotkey_pressed(PAUSE_KEY) {
             is_playing_visuals = !is_playing_visuals;
             if is_playing_visuals {
                 audio_manager.play();
@@ -336,80 +1236,526 @@ pub async fn main() -> anyhow::Result<()> {
                 audio_manager.pause();
             }
         }
-        if is_key_pressed(KeyCode::R) {
+        // a clear's results stay up for a moment before the ramp moves on
+        let ramp_retry = results.is_some() && ramp_retry_at.is_some_and(|retry_at| get_time() >= retry_at);
+        if hotkey_pressed(KeyCode::R) || ramp_retry {
+            ramp_retry_at = None;
+            if let Some(rate_ramp) = &rate_ramp {
+                apply_rate(&mut audio_manager, &mut map, rate_ramp.rate());
+            }
             is_playing_visuals = true;
+            resume_offer = None;
+            results = None;
+            map.reset_judgements();
+            for (player_map, replay_player) in &mut versus_players {
+                replay_player.restart(player_map);
+            }
+            recorded_events.clear();
+            if let Some(autosave) = &autosave {
+                autosave.append(vec![AutosaveLine::Restart]);
+                autosaved_events = 0;
+                next_autosave_time = AUTOSAVE_INTERVAL;
+            }
             audio_manager.restart();
             audio_manager.play();
+            sound_scheduler.seek(0.0, sound_latency(&audio_manager));
+            check_event = Some("restart");
+        }
+        // the offer stands until the play is resumed, restarted, or something is judged
+        if !map.hit_stats.is_empty() {
+            resume_offer = None;
+        }
+        if let Some(position) = resume_offer {
+            let position_text = format_time(position, TimeStyle::Clock);
+            if hotkey_pressed(KeyCode::Y) {
+                resume_offer = None;
+                audio_manager.seek_ms((position - skin().offset - local_offset).max(0.0));
+                sound_scheduler.seek(audio_manager.current_position_ms(), sound_latency(&audio_manager));
+                // notes before the position are skipped, not missed
+                map.skip_to(position);
+                check_event = Some("resume");
+                results = None;
+                logger::info(&format!("Resumed at {position_text}"));
+                toast = Some((tr_args("toast.resumed", &[("time", &position_text)]), get_time()));
+            } else if toast.as_ref().is_none_or(|(_, shown_at)| get_time() - shown_at >= TOAST_DURATION) {
+                // shown again whenever another toast is done
+                toast = Some((tr_args("toast.resume_offer", &[("time", &position_text)]), get_time()));
+            }
+        }
+        if !map.hit_stats.is_empty() {
+            offset_suggestion = None;
+            scroll_speed_suggestion = None;
+        }
+        if let (Some(suggested), Some(checksum)) = (offset_suggestion, checksum) {
+            let offset_text = format!("{suggested:.0}");
+            if hotkey_pressed(KeyCode::O) {
+                offset_suggestion = None;
+                local_offset = suggested;
+                local_offsets.set(checksum, suggested);
+                match local_offsets.save(&local_offsets_path()) {
+                    Ok(()) => logger::info(&format!("Local offset set to {offset_text} ms")),
+                    Err(e) => logger::error(&format!("{e}")),
+                }
+                toast = Some((tr_args("toast.local_offset_set", &[("offset", &offset_text)]), get_time()));
+            } else if toast.as_ref().is_none_or(|(_, shown_at)| get_time() - shown_at >= TOAST_DURATION) {
+                toast = Some((tr_args("toast.local_offset_offer", &[("offset", &offset_text)]), get_time()));
+            }
+        }
+        if let Some(suggested) = scroll_speed_suggestion {
+            let speed_text = format!("{suggested:.0}");
+            if hotkey_pressed(KeyCode::T) {
+                scroll_speed_suggestion = None;
+                set_scroll_speed(suggested, &mut user_skin, &mut map_skin);
+                logger::info(&format!("Scroll speed set to {speed_text}"));
+                toast = Some((tr_args("toast.scroll_speed_set", &[("speed", &speed_text)]), get_time()));
+            } else if toast.as_ref().is_none_or(|(_, shown_at)| get_time() - shown_at >= TOAST_DURATION) {
+                toast = Some((tr_args("toast.scroll_speed_offer", &[("speed", &speed_text)]), get_time()));
+            }
+        }
+        if hotkey_pressed(KeyCode::F9) {
+            let line = clips::clip_line(time, map.beat_phase(time));
+            match clips::append_clip(&clips_path(), &line) {
+                Ok(()) => {
+                    logger::info(&format!("Clip marked at {line}"));
+                    toast = Some((tr("toast.clip_marked").into(), get_time()));
+                }
+                Err(e) => logger::error(&format!("{e}")),
+            }
+            if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+                screenshot_time = Some(time);
+            }
         }
-        if is_key_pressed(KeyCode::Up) {
+        if results.is_some() && hotkey_pressed(KeyCode::W) {
+            results_windows_open = !results_windows_open;
+        }
+        if hotkey_pressed(KeyCode::H) {
+            show_window_bands = !show_window_bands;
+            toast = Some((tr(if show_window_bands { "toast.window_bands_on" } else { "toast.window_bands_off" }).into(), get_time()));
+        }
+        if hotkey_pressed(KeyCode::K) {
+            if let Some(map_skin) = map_skin {
+                use_map_skin = !use_map_skin;
+                if use_map_skin {
+                    set_skin(map_skin);
+                    toast = Some((tr("toast.map_skin_active").into(), get_time()));
+                } else {
+                    set_skin(user_skin);
+                    toast = Some((tr("toast.map_skin_disabled").into(), get_time()));
+                }
+            }
+        }
+        if hotkey_pressed(KeyCode::M) || hotkey_pressed(KeyCode::V) || hotkey_pressed(KeyCode::F7) {
+            if !versus_players.is_empty() || args.record_replay.is_some() {
+                // replays keep one set of mods for the whole play
+                toast = Some((tr("toast.mods_locked").into(), get_time()));
+            } else if hotkey_pressed(KeyCode::M) {
+                map.toggle_mirror();
+                if let Some(compare_map) = compare_map.as_mut() {
+                    compare_map.toggle_mirror();
+                }
+                toast = Some((tr(if map.mods.mirror { "toast.mirror_on" } else { "toast.mirror_off" }).into(), get_time()));
+                check_event = Some("mirror toggle");
+            } else if hotkey_pressed(KeyCode::F7) {
+                map.toggle_autoplay(time);
+                // autoplay's key sounds are scheduled ahead (with the sound effects), manual ones play on each press
+                sound_scheduler = SoundScheduler::new(chart_sounds(&map, &samples));
+                sound_scheduler.seek(audio_manager.current_position_ms(), sound_latency(&audio_manager));
+                toast = Some((tr(if map.mods.autoplay { "toast.autoplay_on" } else { "toast.autoplay_off" }).into(), get_time()));
+                check_event = Some("autoplay toggle");
+            } else {
+                map.toggle_no_sv(&field_positions)?;
+                if let Some(compare_map) = compare_map.as_mut() {
+                    compare_map.toggle_no_sv(&field_positions)?;
+                }
+                toast = Some((tr(if map.mods.no_sv { "toast.no_sv_on" } else { "toast.no_sv_off" }).into(), get_time()));
+                check_event = Some("no SV toggle");
+            }
+        }
+        if hotkey_pressed(KeyCode::F5) {
+            // the new skin only replaces the old one once it has parsed, playback carries on either way
+            let reloaded = if args.ignore_map_skin {
+                Ok(user_skin)
+            } else {
+                map_skin::hot_reload(&user_skin, &map_folder_path, &mut map_skin, &mut use_map_skin)
+            };
+            match reloaded {
+                Ok(reloaded) => {
+                    set_skin(reloaded);
+                    let modified = modified_time(Path::new(RECEPTOR_TEXTURE));
+                    if modified != receptor_modified {
+                        match load_texture(RECEPTOR_TEXTURE).await {
+                            Ok(texture) => {
+                                receptor_texture = texture;
+                                receptor_modified = modified;
+                            }
+                            Err(e) => logger::warning(&format!("Keeping the old receptor texture: {e}")),
+                        }
+                    }
+                    field_positions = set_reference_positions(Some(&receptor_texture));
+                    reposition_map(&mut map, &field_positions)?;
+                    if let Some(compare_map) = compare_map.as_mut() {
+                        reposition_map(compare_map, &field_positions)?;
+                    }
+                    for (player_map, _) in &mut versus_players {
+                        reposition_map(player_map, &field_positions)?;
+                    }
+                    logger::info("Reloaded skin");
+                    toast = Some((tr("toast.skin_reloaded").into(), get_time()));
+                }
+                Err(e) => {
+                    logger::error(&format!("Keeping the current skin: {e}"));
+                    // toml errors start with the line and column, the rest is a snippet of the file
+                    let error = e.to_string();
+                    let summary = error.lines().next().unwrap_or_default();
+                    toast = Some((tr_args("toast.skin_reload_failed", &[("error", summary)]), get_time()));
+                }
+            }
+        }
+        if map.mods.debug && (is_mouse_button_pressed(MouseButton::Left) || hotkey_pressed(KeyCode::I)) {
+            // inspect the note under the mouse
+            let (mouse_x, mouse_y) = mouse_position();
+            let (mouse_x, mouse_y) = (f64::from(mouse_x), f64::from(mouse_y));
+            let window_width = f64::from(screen_width());
+            let window_height = f64::from(screen_height());
+            inspection = note_at_screen_position(&map, &field_positions, window_width, window_height, mouse_x, mouse_y)
+                .and_then(|index| map.inspect_note(index))
+                .map(|note| {
+                    logger::info(&format!("Inspecting {}", note.lines().join(", ")));
+                    (note, mouse_x + 16.0, mouse_y)
+                });
+        }
+        if hotkey_pressed(KeyCode::Up) {
             let new_vol = (audio_manager.get_volume() + 0.05).min(1.5);
             audio_manager.set_volume(new_vol);
         }
-        if is_key_pressed(KeyCode::Down) {
+        if hotkey_pressed(KeyCode::Down) {
             let new_vol = (audio_manager.get_volume() - 0.05).max(0.0);
             audio_manager.set_volume(new_vol);
         }
-        if is_key_pressed(KeyCode::Equal) {
-            let new_rate = (audio_manager.get_rate() + 0.1).min(2.0);
-            audio_manager.set_rate(new_rate);
-            map.rate = new_rate;
-        }
-        if is_key_pressed(KeyCode::Minus) {
-            let new_rate = (audio_manager.get_rate() - 0.1).max(0.5);
-            audio_manager.set_rate(new_rate);
-            map.rate = new_rate;
-        }
-        if is_key_pressed(KeyCode::Left) {
-            let offset = 5000.0;
-            let mut new_time = audio_manager.get_current_song_time_ms() - offset;
-            if let Some(total) = audio_manager.get_total_duration_ms() {
-                new_time = new_time.clamp(0.0, total);
-            } else {
-                new_time = new_time.max(0.0);
+        if rate_ramp.is_some() && (hotkey_pressed(KeyCode::Equal) || hotkey_pressed(KeyCode::Minus)) {
+            toast = Some((tr("toast.rate_locked").into(), get_time()));
+        } else if hotkey_pressed(KeyCode::Equal) || hotkey_pressed(KeyCode::Minus) {
+            let shift_down = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+            let step = if shift_down { rate::FINE_RATE_STEP } else { config.rate_step.abs() };
+            let direction = if hotkey_pressed(KeyCode::Equal) { 1.0 } else { -1.0 };
+            let new_rate = rate::step_rate(map.rate, step * direction);
+            let message = apply_rate(&mut audio_manager, &mut map, new_rate);
+            toast = Some((message, get_time()));
+        }
+        let control_down = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+        let difficulty_step = if !control_down {
+            None
+        } else if hotkey_pressed(KeyCode::Left) {
+            Some(-1)
+        } else if hotkey_pressed(KeyCode::Right) {
+            Some(1)
+        } else {
+            None
+        };
+        if difficulty_step.is_some() && (!versus_players.is_empty() || args.record_replay.is_some()) {
+            // a replay is of one chart
+            toast = Some((tr("toast.difficulty_locked").into(), get_time()));
+        } else if let Some(step) = difficulty_step {
+            let switched = difficulties
+                .as_mut()
+                .and_then(|difficulties| difficulties.neighbor(step).map(|index| difficulties.switch_to(index)));
+            match switched {
+                None => toast = Some((tr("toast.no_other_difficulties").into(), get_time())),
+                Some(Err(e)) => logger::error(&format!("Can't switch difficulty: {e}")),
+                Some(Ok(chart)) => {
+                    // the audio carries on where it is, it's only reloaded if this difficulty has its own
+                    let chart_audio_path = chart.audio_file.as_ref().and_then(|audio_filename_str| {
+                        package::find_asset(&map_folder_path, &map_root, audio_filename_str)
+                            .inspect_err(|e| logger::warning(&format!("Can't use audio file: {e}")))
+                            .ok()
+                    });
+                    let position = audio_manager.current_position_ms();
+                    let audio_changed = chart_audio_path != audio_path;
+                    if audio_changed {
+                        audio_manager.set_audio_path(chart_audio_path.clone());
+                        audio_manager.seek_ms(position);
+                    }
+                    let length = audio_manager.get_total_duration_ms().unwrap_or(0f64);
+                    match prepare_difficulty(chart, &map, length, &field_positions, args.strict) {
+                        Err(e) => {
+                            logger::error(&format!("Can't switch difficulty: {e}"));
+                            if audio_changed {
+                                audio_manager.set_audio_path(audio_path.clone());
+                                audio_manager.seek_ms(position);
+                            }
+                        }
+                        Ok(chart) => {
+                            // the chart left is remembered like on exit
+                            if let Some(checksum) = resume_checksum {
+                                resume_store.set(checksum, results.is_none().then_some(map.time));
+                            }
+                            if let Some(autosave) = autosave.take() {
+                                autosave.finish(true);
+                            }
+                            map = chart;
+                            audio_path = chart_audio_path;
+                            let chart_background_path = find_background(&map, &map_folder_path, &map_root);
+                            if chart_background_path != background_path {
+                                background_texture = load_background(chart_background_path.as_deref()).await;
+                                background_path = chart_background_path;
+                            }
+
+                            checksum = fs::read(&map.file_path).ok().map(|contents| chart_checksum(&contents));
+                            local_offset = checksum.map_or(0.0, |checksum| local_offsets.get(checksum));
+                            offset_suggestion = None;
+                            scroll_speed_suggestion = suggest_scroll_speed(&map, checksum, &mut scroll_suggestions);
+                            // a fresh play from here on, the notes before now are skipped rather than missed
+                            let now = audio_manager.current_position_ms() + skin().offset + local_offset;
+                            if map.hit_objects.first().is_some_and(|note| note.start_time < now) {
+                                map.skip_to(now);
+                            }
+                            samples = resolve_samples(&map, &map_folder_path, &map_root);
+                            sound_scheduler = SoundScheduler::new(chart_sounds(&map, &samples));
+                            sound_scheduler.seek(audio_manager.current_position_ms(), sound_latency(&audio_manager));
+                            resume_checksum = checksum.filter(|_| !args.no_resume);
+                            resume_offer = None;
+                            autosave = checksum
+                                .filter(|_| !map.mods.autoplay)
+                                .map(|checksum| Autosave::start(autosave_path(checksum), checksum, map.mods.clone()));
+                            autosaved_events = 0;
+                            next_autosave_time = AUTOSAVE_INTERVAL;
+                            recorded_events.clear();
+                            results = None;
+                            inspection = None;
+                            map_counts = MapCounts::of(&map);
+                            warn_about_key_bindings(&map, &key_bindings);
+                            if let Some(compare_map) = compare_map.as_ref().filter(|_| args.highlight_diff) {
+                                chart_diff = diff_charts(&map, compare_map, COMPARE_TOLERANCE_MS);
+                            }
+                            check_event = Some("difficulty switch");
+
+                            let name = map.difficulty_name.clone().unwrap_or_default();
+                            logger::info(&format!("Switched to '{name}'"));
+                            toast = Some((tr_args("toast.difficulty_switched", &[("name", &name)]), get_time()));
+                        }
+                    }
+                }
             }
-            audio_manager.seek_ms(new_time);
         }
-        if is_key_pressed(KeyCode::Right) {
-            let offset = 5000.0;
-            let mut new_time = audio_manager.get_current_song_time_ms() + offset;
-            if let Some(total) = audio_manager.get_total_duration_ms() {
-                new_time = new_time.clamp(0.0, total);
+        // left/right seek 5 seconds, with alt to the previous/next measure line, with alt and shift
+        // to the previous/next note (ctrl switches difficulties)
+        let seek_forward = if control_down {
+            None
+        } else if hotkey_pressed(KeyCode::Left) {
+            Some(false)
+        } else if hotkey_pressed(KeyCode::Right) {
+            Some(true)
+        } else {
+            None
+        };
+        if let Some(forward) = seek_forward {
+            let alt_down = is_key_down(KeyCode::LeftAlt) || is_key_down(KeyCode::RightAlt);
+            let shift_down = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+            // a chart time to seek to, and the toast saying what's there
+            let target = if alt_down && shift_down {
+                let found = if forward { seek::next_note_time(&map, time) } else { seek::previous_note_time(&map, time) };
+                found.map(|(target, index)| (target, tr_args("toast.seek_note", &[("index", &(index + 1).to_string())])))
+            } else if alt_down {
+                let found = if forward { seek::next_measure_time(&map, time) } else { seek::previous_measure_time(&map, time) };
+                found.map(|(target, measure)| (target, tr_args("toast.seek_measure", &[("measure", &measure.to_string())])))
             } else {
-                new_time = new_time.max(0.0);
+                let offset = if forward { 5000.0 } else { -5000.0 };
+                Some((time + offset, String::new()))
+            };
+            if let Some((target, message)) = target {
+                if target < time {
+                    // replays are judged again from the start to get back here
+                    for (player_map, replay_player) in &mut versus_players {
+                        replay_player.restart(player_map);
+                    }
+                }
+                let mut new_time = target - skin().offset - local_offset;
+                if let Some(total) = audio_manager.get_total_duration_ms() {
+                    new_time = new_time.clamp(0.0, total);
+                } else {
+                    new_time = new_time.max(0.0);
+                }
+                audio_manager.seek_ms(new_time);
+                sound_scheduler.seek(audio_manager.current_position_ms(), sound_latency(&audio_manager));
+                check_event = Some("seek");
+                results = None;
+                if !message.is_empty() {
+                    toast = Some((message, get_time()));
+                }
             }
-            audio_manager.seek_ms(new_time);
         }
 
-        // gameplay keybinds
-        if !map.mods.autoplay {
-            if is_key_pressed(KeyCode::A) {
-                map.handle_gameplay_key_press(map.time, 0);
+        // gameplay keybinds, presses only count while playing
+        let now = get_time() * 1000.0;
+        input_gate.set_playing(is_playing_visuals, now);
+        if !map.mods.autoplay && versus_players.is_empty() {
+            for (key, &key_code) in key_bindings.keys(usize::try_from(map.get_key_count(true)).unwrap_or(0)).iter().enumerate() {
+                // releases first, so a release and re-press in the same frame frees the lane for the press
+                if is_key_released(key_code) {
+                    map.handle_gameplay_key_release(time, key as i64);
+                    recorded_events.push(ReplayEvent { time, key: key as i64, pressed: false });
+                }
+                if is_key_pressed(key_code) && input_gate.accepts_press(now) {
+                    recorded_events.push(ReplayEvent { time, key: key as i64, pressed: true });
+                    let mash_bursts = map.mash_detector.bursts;
+                    if let Some(index) = map.handle_gameplay_key_press(time, key as i64) {
+                        for sound in note_key_sounds(&map, &samples, index) {
+                            audio_manager.play_sample(&sound.path, sound.volume);
+                        }
+                    }
+                    if args.mash_toast && map.mash_detector.bursts > mash_bursts {
+                        toast = Some((tr("toast.mashing").into(), get_time()));
+                    }
+                }
             }
-            if is_key_pressed(KeyCode::S) {
-                map.handle_gameplay_key_press(map.time, 1);
+        }
+
+        for (player_map, replay_player) in &mut versus_players {
+            replay_player.play_until(player_map, time);
+        }
+
+        if let Some(autosave) = autosave.as_ref().filter(|_| time >= next_autosave_time) {
+            let mut lines: Vec<AutosaveLine> = recorded_events[autosaved_events..]
+                .iter()
+                .map(|&event| AutosaveLine::Event(event))
+                .collect();
+            lines.push(AutosaveLine::Score(ScoreSnapshot {
+                time,
+                accuracy: map.accuracy(),
+                combo: map.combo,
+                judged: map.hit_stats.len(),
+                mods: map.mods.clone(),
+            }));
+            autosave.append(lines);
+            autosaved_events = recorded_events.len();
+            next_autosave_time = time + AUTOSAVE_INTERVAL;
+        }
+        #[cfg(feature = "net")]
+        if let Some(broadcaster) = broadcaster.as_ref().filter(|_| get_time() >= next_broadcast) {
+            broadcaster.send(broadcast::BroadcastState::from_map(&map));
+            next_broadcast = get_time() + broadcast::BROADCAST_INTERVAL;
+        }
+
+        if is_playing_visuals {
+            let latency = sound_latency(&audio_manager);
+            for sound in sound_scheduler.due(audio_manager.current_position_ms(), latency) {
+                audio_manager.play_sample(&sound.path, sound.volume);
             }
-            if is_key_pressed(KeyCode::Semicolon) {
-                map.handle_gameplay_key_press(map.time, 2);
+        }
+
+        // map is finished once the last object is past, regardless of the audio length
+        // (a chart without notes has nothing to show results for, its audio just plays)
+        let finished = !map.hit_objects.is_empty() && map.time >= map.playable_length;
+        if is_playing_visuals && results.is_none() && finished && !versus_players.is_empty() {
+            // versus has no results screen, the scores stay up
+            is_playing_visuals = false;
+            audio_manager.pause();
+            for (index, (player_map, _)) in versus_players.iter().enumerate() {
+                logger::info(&format!("Player {}:", index + 1));
+                ResultsSummary::from_map(player_map).log();
             }
-            if is_key_pressed(KeyCode::Apostrophe) {
-                map.handle_gameplay_key_press(map.time, 3);
+        }
+        if is_playing_visuals && results.is_none() && finished {
+            is_playing_visuals = false;
+            audio_manager.pause();
+            let mut summary = ResultsSummary::from_map(&map);
+            summary.log();
+            if let Some(rate_ramp) = rate_ramp.as_mut() {
+                // plays after the ramp is over are still shown with it, but don't count
+                let outcome = (!rate_ramp.is_over()).then(|| rate_ramp.after_play(&summary));
+                match outcome {
+                    None => {}
+                    Some(RampOutcome::Next(rate)) => {
+                        logger::info(&format!("Ramp: cleared {:.2}x, next {rate:.2}x", map.rate));
+                        toast = Some((tr_args("toast.ramp_next", &[("rate", &format!("{rate:.2}"))]), get_time()));
+                        ramp_retry_at = Some(get_time() + RAMP_RETRY_DELAY);
+                    }
+                    Some(RampOutcome::Complete) => logger::info(&format!("Ramp complete at {:.2}x", rate_ramp.rate())),
+                    Some(RampOutcome::Failed) => logger::info(&format!(
+                        "Ramp stopped at {:.2}x, under {}%",
+                        rate_ramp.rate(),
+                        rate_ramp.min_accuracy()
+                    )),
+                }
+                summary.rate_ramp = Some(rate_ramp.clone());
             }
+            if let Some(path) = &args.results_image {
+                let mut image = SoftwareDraw::new(RESULTS_IMAGE_WIDTH, RESULTS_IMAGE_HEIGHT, BLACK);
+                draw_results(&summary, true, &mut image);
+                match image.save_png(path) {
+                    Ok(()) => logger::info(&format!("Results image saved to {}", path.display())),
+                    Err(e) => logger::error(&format!("{e}")),
+                }
+            }
+            if let Some(path) = &args.results_json {
+                match summary.save_json(path) {
+                    Ok(()) => logger::info(&format!("Results saved to {}", path.display())),
+                    Err(e) => logger::error(&format!("{e}")),
+                }
+            }
+            results = Some(summary);
         }
 
         let mut macroquad_draw = MacroquadDraw;
         let mut frame_state = FrameState {
             map: &mut map,
+            compare_map: compare_map.as_mut(),
+            chart_diff: &chart_diff,
             field_positions: &field_positions,
+            alpha: 1.0,
+            view_height: f64::from(screen_height()),
+            background: background_texture.as_ref(),
         };
 
+        phase_timer.mark("input");
+        // --------- simulation --------
+        let simulation_times = match fixed_timestep.as_mut() {
+            Some(fixed_timestep) => {
+                let (ticks, alpha) = fixed_timestep.advance(time);
+                frame_state.alpha = alpha;
+                ticks
+            }
+            None => vec![time],
+        };
+        let mut players: Vec<&mut Map> = versus_players.iter_mut().map(|(player_map, _)| player_map).collect();
+        for simulation_time in simulation_times {
+            frame_state.map.time = simulation_time;
+            let result = if players.is_empty() {
+                update_frame(&mut frame_state)
+            } else {
+                let rate = frame_state.map.rate;
+                update_versus(&mut players, &field_positions, simulation_time, rate, frame_state.view_height)
+            };
+            result.map_err(|e| {
+                logger::error(&format!("Update error: {e}"));
+                e
+            })?;
+        }
+        if let Some(checker) = invariant_checker.as_mut() {
+            checker.after_frame(frame_state.map, check_event.take());
+        }
+
+        phase_timer.mark("simulation");
         // --------- render stuff --------
 
         clear_background(BLACK); // resets frame to all black
-        render_frame(&mut frame_state, &mut macroquad_draw).map_err(|e| {
+        let result = match offscreen.as_mut() {
+            Some(offscreen) => {
+                offscreen.begin();
+                render_scene(&mut frame_state, &mut players, show_window_bands, offscreen)
+            }
+            None => render_scene(&mut frame_state, &mut players, show_window_bands, &mut macroquad_draw),
+        };
+        result.map_err(|e| {
             logger::error(&format!("Render error: {e}"));
             e
         })?;
+        // the ui is drawn over the scaled scene at the window's resolution, or scaled with it
+        if let Some(offscreen) = offscreen.as_ref().filter(|_| config.native_ui_text) {
+            offscreen.present();
+        }
 
+        phase_timer.mark("scene");
         // -------- draw ui / debug info --------
         let line_height = 20.0;
         if map.mods.debug {
@@ -422,7 +1768,10 @@ pub async fn main() -> anyhow::Result<()> {
                 map.creator.as_ref(),
             ) {
                 draw_text(
-                    &format!("Map: {title} - {artist} [{difficulty}] by {creator}"),
+                    &tr_args(
+                        "debug.map_info",
+                        &[("title", title), ("artist", artist), ("difficulty", difficulty), ("creator", creator)],
+                    ),
                     10.0,
                     y_offset,
                     20.0,
@@ -432,8 +1781,34 @@ pub async fn main() -> anyhow::Result<()> {
             }
 
             draw_text(
-                &format!(
-                    "{total_hit_objects} Notes, {total_svs} SVs, {total_ssfs} SSFs, {total_timing_groups} Groups, {total_timing_points} Timing Points, {total_timing_lines} Timing Lines",
+                &tr_args(
+                    "debug.map_counts",
+                    &[
+                        ("notes", &map_counts.hit_objects.to_string()),
+                        ("svs", &map_counts.svs.to_string()),
+                        ("ssfs", &map_counts.ssfs.to_string()),
+                        ("groups", &map_counts.timing_groups.to_string()),
+                        ("timing_points", &map_counts.timing_points.to_string()),
+                        ("timing_lines", &map_counts.timing_lines.to_string()),
+                    ],
+                ),
+                10.0,
+                y_offset,
+                20.0,
+                WHITE,
+            );
+            y_offset += line_height;
+
+            draw_text(&map_counts.bpm_text(), 10.0, y_offset, 20.0, WHITE);
+            y_offset += line_height;
+
+            draw_text(
+                &tr_args(
+                    "debug.timing_lines",
+                    &[
+                        ("updated", &map.visible_timing_lines.len().to_string()),
+                        ("total", &map_counts.timing_lines.to_string()),
+                    ],
                 ),
                 10.0,
                 y_offset,
@@ -444,23 +1819,20 @@ pub async fn main() -> anyhow::Result<()> {
             y_offset += line_height;
 
             let visual_state_text = if is_playing_visuals {
-                "Playing"
+                tr("state.playing")
             } else {
-                "Paused"
+                tr("state.paused")
             };
-            let audio_actual_state_text = if audio_manager.is_playing() {
-                "Playing"
-            } else if audio_manager
-                .sink
-                .as_ref()
-                .is_some_and(rodio::Sink::is_paused)
-            {
-                "Paused"
-            } else {
-                "Stopped/empty"
+            let audio_actual_state_text = match audio_manager.state() {
+                PlaybackState::Playing => tr("state.playing"),
+                PlaybackState::Paused => tr("state.paused"),
+                PlaybackState::Stopped => tr("state.stopped"),
             };
             draw_text(
-                &format!("Visuals: {visual_state_text} | Audio: {audio_actual_state_text} (space, r)"),
+                &tr_args(
+                    "debug.playback",
+                    &[("visuals", visual_state_text), ("audio", audio_actual_state_text)],
+                ),
                 10.0,
                 y_offset,
                 20.0,
@@ -469,10 +1841,25 @@ pub async fn main() -> anyhow::Result<()> {
             y_offset += line_height;
 
             draw_text(
-                &format!(
-                    "Volume: {:.2} (up/down) | Rate: {:.1}x (left/right)",
-                    audio_manager.get_volume(),
-                    audio_manager.get_rate()
+                &tr_args(
+                    "debug.volume_rate",
+                    &[
+                        ("volume", &format!("{:.2}", audio_manager.get_volume())),
+                        ("rate", &rate::format_rate(audio_manager.get_rate())),
+                        ("pitch", &rate::pitch_label(audio_manager.get_rate())),
+                    ],
+                ),
+                10.0,
+                y_offset,
+                20.0,
+                WHITE,
+            );
+            y_offset += line_height;
+
+            draw_text(
+                &tr_args(
+                    "debug.audio_latency",
+                    &[("latency", &format!("{:.1}", audio_manager.output_latency_ms()))],
                 ),
                 10.0,
                 y_offset,
@@ -482,12 +1869,18 @@ pub async fn main() -> anyhow::Result<()> {
             y_offset += line_height;
 
             let total_duration_str = match audio_manager.get_total_duration_ms() {
-                Some(d) => format!("{:.2}s", d / 1000f64),
-                
-                None => "N/A".to_string(),
+                Some(d) => format_time(d, TimeStyle::Precise),
+                None => tr("debug.not_available").to_string(),
             };
             draw_text(
-                &format!("Time: {:.2}s / {}", time / 1000f64, total_duration_str),
+                &tr_args(
+                    "debug.time",
+                    &[
+                        ("time", &format_time(time, TimeStyle::Precise)),
+                        ("seconds", &format_time(time, TimeStyle::Seconds)),
+                        ("total", &total_duration_str),
+                    ],
+                ),
                 10.0,
                 y_offset,
                 20.0,
@@ -495,36 +1888,54 @@ pub async fn main() -> anyhow::Result<()> {
             );
             y_offset += line_height;
 
-            let fps = format!("{:<3}", get_fps());
-            let elapsed = start_instant.elapsed().as_secs_f64();
-            let avg_fps = if elapsed > 0f64 {
-                frame_count as f64 / elapsed
-            } else {
-                0f64
-            };
+            // rolling over the last frames, the lows show stutters an average hides
+            let fps_figure = |fps: Option<f64>| fps.map_or_else(|| "-".to_string(), |fps| format!("{fps:.0}"));
             draw_text(
-                &format!("FPS: {fps} | {avg_fps:.2}"),
+                &tr_args(
+                    "debug.fps",
+                    &[
+                        ("average", &fps_figure(frame_pacing.average_fps())),
+                        ("low", &fps_figure(frame_pacing.low_fps(0.01))),
+                        ("lowest", &fps_figure(frame_pacing.low_fps(0.001))),
+                    ],
+                ),
                 10.0,
                 y_offset,
                 20.0,
                 WHITE,
             );
+            y_offset += line_height / 2.0;
+            draw_frame_pacing(&frame_pacing, 10.0, f64::from(y_offset), &mut macroquad_draw);
+            y_offset += GRAPH_HEIGHT as f32 + line_height;
+
+            let (memory_used, memory_cap, sample_count) = audio_manager.memory_usage();
+            draw_text(
+                &tr_args(
+                    "debug.audio_memory",
+                    &[
+                        ("used", &format!("{:.1}", memory_used as f64 / memory_budget::BYTES_PER_MB)),
+                        ("cap", &format!("{:.0}", memory_cap as f64 / memory_budget::BYTES_PER_MB)),
+                        ("count", &sample_count.to_string()),
+                    ],
+                ),
+                10.0,
+                y_offset,
+                18.0,
+                WHITE,
+            );
             y_offset += line_height;
 
             if let Some(err_msg) = audio_manager.get_error() {
                 draw_text(
-                    &format!("Audio status: {err_msg}"),
+                    &tr_args("debug.audio_status", &[("status", err_msg)]),
                     10.0,
                     y_offset,
                     18.0,
                     YELLOW,
                 );
-            } else if audio_manager.audio_source_path.is_none() && map.audio_file.is_some() {
+            } else if let (None, Some(audio_file)) = (audio_manager.audio_path(), &map.audio_file) {
                 draw_text(
-                    &format!(
-                        "Audio status: no path set for '{}'",
-                        map.audio_file.as_ref().unwrap()
-                    ),
+                    &tr_args("debug.audio_no_path", &[("file", audio_file)]),
                     10.0,
                     y_offset,
                     18.0,
@@ -533,9 +1944,39 @@ pub async fn main() -> anyhow::Result<()> {
             }
         }
 
-        if !map.mods.no_ui {
+        if map.hit_objects.is_empty() && !map.mods.no_ui {
+            let text = tr("state.no_notes");
+            let font_size = 40;
+            let width = measure_text(text, None, font_size, 1.0).width;
+            draw_text(text, (screen_width() - width) / 2.0, screen_height() / 2.0, f32::from(font_size), GRAY);
+        }
+
+        if args.sync_test {
+            let text = match sync_test::mean_offset(&map.hit_stats) {
+                Some((offset, count)) => tr_args(
+                    "sync_test.mean_offset",
+                    &[("offset", &format!("{offset:+.1}")), ("count", &count.to_string())],
+                ),
+                None => tr("sync_test.hint").to_string(),
+            };
+            let font_size = 40;
+            let width = measure_text(&text, None, font_size, 1.0).width;
+            draw_text(&text, (screen_width() - width) / 2.0, 60.0, f32::from(font_size), YELLOW);
+        }
+
+        if !map.mods.no_ui && versus_players.is_empty() {
             // -------- judgements --------
-            let mut right_y = 400.0;
+            // six lines of 50px text, line_height * 2 apart
+            let (counts_width, counts_height) = (400.0, 50.0 + 5.0 * f64::from(line_height) * 2.0);
+            let counts_top = avoid_regions(
+                &args.reserved_regions,
+                f64::from(screen_width()) - counts_width,
+                400.0 - 50.0,
+                counts_width,
+                counts_height,
+                f64::from(screen_height()),
+            );
+            let mut right_y = counts_top as f32 + 50.0;
             for judgement in [
                 JudgementType::Marvelous,
                 JudgementType::Perfect,
@@ -546,7 +1987,10 @@ pub async fn main() -> anyhow::Result<()> {
             ] {
                 let count = map.judgement_counts.get(&judgement).copied().unwrap_or(0);
                 draw_text(
-                &format!("{judgement}: {count}"),
+                &tr_args(
+                    "judgement.count",
+                    &[("judgement", judgement.localized()), ("count", &count.to_string())],
+                ),
                 screen_width() - 400.0,
                 right_y,
                 50.0,
@@ -555,22 +1999,18 @@ pub async fn main() -> anyhow::Result<()> {
                 right_y += line_height * 2.0;
             }
 
+            // -------- lane judgements --------
+            render_lane_splashes(&map, &field_positions, &mut macroquad_draw);
+
             // -------- judgement splash --------
             let splash_length = 500.0; // duration of the splash effect in ms
-            if let Some((judgement, time, offset_ms)) = map.last_judgement {
-                let elapsed = audio_manager.get_current_song_time_ms() - time;
+            if let Some((judgement, time, offset_ms)) = map.splash {
+                let elapsed = audio_manager.current_position_ms() - time;
                 if elapsed < splash_length {
                     let alpha = (1.0 - (elapsed / splash_length)).clamp(0.0, 1.0);
-                    let color = match judgement {
-                        JudgementType::Marvelous => WHITE,
-                        JudgementType::Perfect => GOLD,
-                        JudgementType::Great => GREEN,
-                        JudgementType::Good => BLUE,
-                        JudgementType::Okay => DARKGRAY,
-                        JudgementType::Miss => RED,
-                    };
+                    let color = judgement_color(judgement);
                     draw_text_ex(
-                        &judgement.to_string(),
+                        judgement.localized(),
                         screen_width() / 2.0 - 100.0,
                         screen_height() / 2.0,
                         TextParams {
@@ -614,31 +2054,161 @@ pub async fn main() -> anyhow::Result<()> {
             }
 
             // -------- accuracy --------
-            let mut points = 0.0;
-            let total_judgements = map.judgement_counts.values().sum::<usize>() as f64;
-            points += map.judgement_counts.get(&JudgementType::Marvelous).copied().unwrap_or(0) as f64 * 100.0;
-            points += map.judgement_counts.get(&JudgementType::Perfect).copied().unwrap_or(0) as f64   * 98.25;
-            points += map.judgement_counts.get(&JudgementType::Great).copied().unwrap_or(0) as f64     * 65.0;
-            points += map.judgement_counts.get(&JudgementType::Good).copied().unwrap_or(0) as f64      * 25.0;
-            points += map.judgement_counts.get(&JudgementType::Okay).copied().unwrap_or(0) as f64      * -100.0;
-            points += map.judgement_counts.get(&JudgementType::Miss).copied().unwrap_or(0) as f64      * -50.0;
-            let accuracy_display = if total_judgements <= 0.0 {
-                "100.00%".to_string()
-            } else {
-                format!("{:.2}%", (points / total_judgements).max(0.0))
-            };
             draw_text(
-                &accuracy_display,
+                &format!("{:.2}%", map.accuracy()),
                 screen_width() - 300.0,
                 80.0,
                 80.0,
                 WHITE,
             );
+
+            // -------- notes left and pace --------
+            if config.show_pace && !map.hit_objects.is_empty() {
+                let pace = pace_display.text(&map);
+                let font_size = 20;
+                let width = measure_text(pace, None, font_size, 1.0).width;
+                draw_text(pace, screen_width() - width - 10.0, 110.0, f32::from(font_size), GRAY);
+            }
+
+            // -------- hit error bar --------
+            render_hit_error_bar(&map, &args.reserved_regions, &mut macroquad_draw);
+
+            // -------- progress --------
+            render_progress_bar(&map, &mut macroquad_draw);
+            let progress = tr_args(
+                "progress.time",
+                &[
+                    ("time", &format_time(map.time, TimeStyle::Clock)),
+                    ("total", &format_time(map.length.max(map.playable_length), TimeStyle::Clock)),
+                ],
+            );
+            let font_size = 20;
+            let width = measure_text(&progress, None, font_size, 1.0).width;
+            draw_text(&progress, screen_width() - width - 10.0, 26.0, f32::from(font_size), GRAY);
+            if let Some(rate_ramp) = rate_ramp.as_ref().filter(|rate_ramp| !rate_ramp.is_over()) {
+                let ramp = tr_args(
+                    "ramp.progress",
+                    &[("rate", &format!("{:.2}", rate_ramp.rate())), ("max", &format!("{:.2}", rate_ramp.max_rate()))],
+                );
+                let width = measure_text(&ramp, None, font_size, 1.0).width;
+                draw_text(&ramp, screen_width() - width - 10.0, 50.0, f32::from(font_size), GRAY);
+            }
+
+            // -------- toast --------
+            toast = toast.filter(|(_, shown_at)| get_time() - shown_at < TOAST_DURATION);
+            if let Some((message, shown_at)) = &toast {
+                let alpha = (TOAST_DURATION - (get_time() - shown_at)).clamp(0.0, 1.0) as f32;
+                draw_text(message, 20.0, screen_height() - 20.0, 30.0, Color { a: alpha, ..WHITE });
+            }
         }
 
+        // above the ui, under debug overlays
+        draw_reserved_regions(&args.reserved_regions, &mut macroquad_draw);
+
+        if let Some((note, x, y)) = &inspection {
+            render_note_inspection(note, *x, *y, &mut macroquad_draw);
+        }
 
+        // -------- rate/mods watermark --------
+        if !map.mods.no_ui || args.watermark {
+            let watermark = map.mods.watermark(map.rate);
+            let font_size = 28;
+            let width = f64::from(measure_text(&watermark, None, font_size, 1.0).width);
+            let skin = skin();
+            draw_text(
+                &watermark,
+                (f64::from(screen_width()) - skin.watermark_x - width) as f32,
+                (f64::from(screen_height()) - skin.watermark_y) as f32,
+                f32::from(font_size),
+                Color::new(1.0, 1.0, 1.0, 0.6),
+            );
+        }
+
+        if let Some(summary) = &results {
+            draw_results(summary, results_windows_open, &mut MacroquadDraw);
+        }
+        if let Some(offscreen) = offscreen.as_ref().filter(|_| !config.native_ui_text) {
+            offscreen.present();
+        }
+
+        if let Some(clip_time) = screenshot_time.take() {
+            let map_name = Path::new(&map.file_path)
+                .file_stem()
+                .map_or_else(|| "map".into(), |stem| stem.to_string_lossy());
+            let path = clips::screenshot_path(&screenshots_dir(), &map_name, clip_time);
+            match fs::create_dir_all(screenshots_dir()).map_err(anyhow::Error::from).and_then(|()| save_screenshot(&path)) {
+                Ok(()) => logger::info(&format!("Screenshot saved to {}", path.display())),
+                Err(e) => logger::error(&format!("{e}")),
+            }
+        }
+
+        phase_timer.mark("ui");
+        frame_throttle.end_frame(is_playing_visuals);
         next_frame().await;
     }
 
-    Ok(())
+    // remembered for next time, a finished map starts over
+    if let Some(checksum) = resume_checksum {
+        resume_store.set(checksum, results.is_none().then_some(map.time));
+        if let Err(e) = resume_store.save(&resume_path()) {
+            logger::error(&format!("{e}"));
+        }
+    }
+
+    // exiting normally, so there's nothing to recover
+    if let Some(autosave) = autosave.take() {
+        autosave.finish(true);
+    }
+
+    if let Some(path) = &args.record_replay {
+        let replay = Replay {
+            mods: map.mods.clone(),
+            windows: Some(ActiveWindows::of(&map)),
+            events: recorded_events,
+        };
+        match replay.save(path) {
+            Ok(()) => logger::info(&format!("Replay saved to {}", path.display())),
+            Err(e) => logger::error(&format!("{e}")),
+        }
+    }
+
+    // keep what was changed while playing for next time, options only given for this run aren't saved
+    if audio_manager.get_volume() != initial_volume {
+        config.volume = audio_manager.get_volume();
+    }
+    if is_fullscreen != initial_fullscreen {
+        config.fullscreen = is_fullscreen;
+    }
+    config.save(&config_path())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strict_refuses_a_broken_chart() {
+        let path = std::env::temp_dir().join(format!("vsrg_main_strict_{}.qua", std::process::id()));
+        // a note in lane 5 of a 4K chart
+        let chart = "\
+Mode: Keys4
+TimingPoints:
+- StartTime: 0
+  Bpm: 120
+HitObjects:
+- StartTime: 0
+  Lane: 5
+  KeySounds: []
+";
+        fs::write(&path, chart).unwrap();
+        let map = Map::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(map.validation_errors.len(), 1);
+        let error = check_strict(&map, true).unwrap_err();
+        assert_eq!(error.to_string(), format!("'{}' has 1 validation problem(s) and --strict is set", path.display()));
+        // without --strict it's played anyway, and a clean chart is played either way
+        check_strict(&map, false).unwrap();
+        let clean = Map::from_file(&Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/charts/plain_4k.qua")).unwrap();
+        check_strict(&clean, true).unwrap();
+    }
 }
@@ -1,16 +1,347 @@
-use crate::utils::{FieldPositions, BEAT_SNAPS, DEFAULT_TIMING_GROUP_ID, SKIN, TRACK_ROUNDING, JUDGEMENTS, JudgementType, Judgement};
+use crate::scoring::Ruleset;
+use crate::utils::{FieldPositions, HitKind, HitStat, HoldEvent, HoldEventKind, Rng, BEAT_SNAPS, DEFAULT_TIMING_GROUP_ID, LEAD_OUT_TIME, skin, DEFAULT_SKIN, TRACK_ROUNDING, JUDGEMENTS, JudgementType, JudgementWindows, Judgement};
 use crate::{index_at_time, lerp, object_at_time, sort_by_start_time, HasStartTime, Time};
+use crate::effects::Effects;
 use crate::logger;
-use anyhow::{bail, Result};
-use serde::{Deserialize, Serialize};
+use crate::mash::MashDetector;
+use crate::qua_stream::{self, STREAMING_PARSE_THRESHOLD};
+use crate::alloc_stats;
+use anyhow::{anyhow, bail, Result};
+use clap::ValueEnum;
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{
     collections::{HashMap, VecDeque},
+    fmt, fs,
     mem::take,
+    ops::{Index, Range},
+    path::Path,
+    time::Instant,
 };
 
+pub mod parsers;
+
 // anything representing a position on the track
 pub type Position = i64;
 
+// times (ms) this close together count as the same moment when resolving LN chains
+const CHAIN_EPSILON: Time = 1.0;
+
+// limits for chart values on load, so position math (time * multiplier * TRACK_ROUNDING) stays sane
+const MAX_MULTIPLIER: f64 = 1e5;
+const MAX_START_TIME: Time = 24.0 * 60.0 * 60.0 * 1000.0; // 24 hours
+// range bpms are counted in: "max possible sane value for timing lines" - quaver devs, and a
+// minute-long beat for 0 bpm (used for stops), so there's always a finite, positive beat to count
+const MAX_BPM: f64 = 9999.0;
+const MIN_BPM: f64 = 1.0;
+// multiplier for a 0 or infinite bpm when normalizing SVs; osu! handles infinite bpm more like an
+// arbitrarily large SV, this is the smallest power of two over its max (denormalizing uses 1/this)
+const UNUSABLE_BPM_MULTIPLIER: f64 = 128.0;
+// extra screen distance (px) above and below the view where timing lines are still updated
+const TIMING_LINE_MARGIN: f64 = 50.0;
+// how far (ms) a note can be from its 1/48 grid before it counts as off-snap
+pub const OFF_SNAP_TOLERANCE: Time = 2.0;
+
+// real time (s) between updates past which safe mode treats it as a seek, and jumps to the new positions
+const SAFE_MODE_SEEK_GAP: f64 = 0.25;
+// a press this close (ms) to a mine in its lane sets it off
+const MINE_WINDOW: Time = 40.0;
+
+fn limit_motion(previous: Position, target: Position, hit_position: f64, max_step: f64, elapsed: f64, time_left: f64) -> Position {
+    // moves toward target by at most max_step, but never slower than what still reaches hit_position by the
+    // object's time (time_left, real seconds), so it converges on the receptor exactly on time
+    if time_left <= elapsed {
+        return target;
+    }
+    let gap = (target - previous) as f64;
+    let to_hit = hit_position - previous as f64;
+    // the extra speed only helps toward the receptor, moving away from it is always limited
+    let catch_up = if to_hit.signum() == gap.signum() { to_hit.abs() * elapsed / time_left } else { 0.0 };
+    let step = max_step.max(catch_up);
+    previous + gap.clamp(-step, step) as Position
+}
+
+// a note that isn't on its timing point's 1/48 grid
+#[derive(Debug, Clone, Copy)]
+pub struct OffSnapNote {
+    pub index: usize,        // index in hit_objects
+    pub timing_point: usize, // index of its timing point
+    pub error: Time,         // distance (ms) from the nearest grid position
+}
+
+// how packed a chart is, see Map::note_density
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoteDensity {
+    pub busy_nps: f64,                   // notes per second in the chart's busy parts (95th percentile of its seconds)
+    pub shortest_lane_gap: Option<Time>, // shortest time (ms) from a note, or a hold's end, to the next in its lane
+}
+
+// scroll speeds the skin's setting is kept within (after scaling by rate)
+const MIN_SCROLL_SPEED: f64 = 50.0;
+const MAX_SCROLL_SPEED: f64 = 1000.0;
+// a suggested scroll speed keeps notes this many note heights apart at least...
+const SUGGESTED_SPACING: f64 = 1.5;
+// ...and has about this many notes of the chart's busy parts on screen at once
+const SUGGESTED_NOTES_ON_SCREEN: f64 = 12.0;
+
+fn snap_error(offset: Time, beat_length: Time) -> Time {
+    // distance from offset to the nearest multiple of a 1/48 beat, 0 when there is no grid
+    let grid = beat_length / 48.0;
+    if !grid.is_finite() || grid <= 0.0 {
+        return 0.0;
+    }
+    (offset - (offset / grid).round() * grid).abs()
+}
+
+// a chart value that was out of range and clamped on load
+#[derive(Debug, Clone)]
+pub struct ClampedValue {
+    pub field: String, // what was clamped, e.g. "SV multiplier at 1000 ms"
+    pub value: f64,
+    pub clamped: f64,
+}
+
+fn clamp_chart_value(field: impl FnOnce() -> String, value: &mut f64, limit: f64, report: &mut Vec<ClampedValue>) {
+    // clamps to ±limit, NaN becomes 0
+    let clamped = if value.is_nan() { 0.0 } else { value.clamp(-limit, limit) };
+    if clamped.to_bits() != value.to_bits() {
+        report.push(ClampedValue {
+            field: field(),
+            value: *value,
+            clamped,
+        });
+        *value = clamped;
+    }
+}
+
+// something wrong with a chart that it can still be played with, see Map::validate
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapValidationError {
+    LaneOutOfRange { index: usize, start_time: Time, lane: i64, key_count: i64 }, // index in hit_objects
+    LnEndBeforeStart { index: usize, start_time: Time, end_time: Time },
+    OverlappingNotes { lane: i64, first: Time, second: Time }, // the second starts before the first is over
+    NanSvMultiplier { group: String, start_time: Time },
+    InvalidBpm { start_time: Time, bpm: f64 }, // 0 or NaN
+}
+
+impl fmt::Display for MapValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LaneOutOfRange { index, start_time, lane, key_count } => {
+                write!(f, "note {index} at {start_time} ms is in lane {lane}, outside 1-{key_count}")
+            }
+            Self::LnEndBeforeStart { index, start_time, end_time } => {
+                write!(f, "LN {index} at {start_time} ms ends at {end_time} ms, not after it starts")
+            }
+            Self::OverlappingNotes { lane, first, second } => {
+                write!(f, "note at {second} ms in lane {lane} overlaps the one at {first} ms")
+            }
+            Self::NanSvMultiplier { group, start_time } => write!(f, "SV at {start_time} ms ({group}) has no number for a multiplier"),
+            Self::InvalidBpm { start_time, bpm } => write!(f, "timing point at {start_time} ms has a BPM of {bpm}"),
+        }
+    }
+}
+
+// a stretch of 0x SVs in a timing group, where everything in it stops moving
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrozenSvRun {
+    pub group: String,
+    pub start_time: Time,
+    pub end_time: Time, // the next moving point, or the group's last note if there isn't one
+}
+
+fn frozen_runs(group: &str, initial_scroll_velocity: f64, points: &[ControlPoint], last_note: Time) -> Vec<FrozenSvRun> {
+    // stretches where the velocity is 0 (before the first point too, when the initial one is), up
+    // to the group's last note; points must be sorted
+    let mut runs = Vec::new();
+    let mut frozen_since = (initial_scroll_velocity == 0.0).then_some(0.0);
+    for point in points {
+        match frozen_since {
+            Some(start_time) if point.multiplier != 0.0 => {
+                runs.push(FrozenSvRun { group: group.to_string(), start_time, end_time: point.start_time });
+                frozen_since = None;
+            }
+            None if point.multiplier == 0.0 => frozen_since = Some(point.start_time),
+            _ => {}
+        }
+    }
+    if let Some(start_time) = frozen_since {
+        runs.push(FrozenSvRun { group: group.to_string(), start_time, end_time: last_note });
+    }
+    // stops after the last note don't hold anything up
+    for run in &mut runs {
+        run.end_time = run.end_time.min(last_note);
+    }
+    runs.retain(|run| run.end_time > run.start_time);
+    runs
+}
+
+fn collapse_duplicate_times(points: &mut Vec<ControlPoint>) -> usize {
+    // keeps only the last point defined at each time, like quaver, returns how many were removed
+    sort_by_start_time(points);
+    let count = points.len();
+    points.reverse();
+    points.dedup_by(|point, kept| point.start_time == kept.start_time);
+    points.reverse();
+    count - points.len()
+}
+
+fn mirror_lanes<T>(lanes: &mut HashMap<i64, T>, key_count: i64) {
+    // moves per-lane state to the mirrored lanes, the scratch lane's stays
+    *lanes = take(lanes)
+        .into_iter()
+        .map(|(lane, value)| if (1..=key_count).contains(&lane) { (key_count + 1 - lane, value) } else { (lane, value) })
+        .collect();
+}
+
+fn clamp_control_points(kind: &str, group: &str, points: &mut [ControlPoint], report: &mut Vec<ClampedValue>) {
+    for point in points {
+        let time = point.start_time;
+        clamp_chart_value(|| format!("{kind} time at {time} ms ({group})"), &mut point.start_time, MAX_START_TIME, report);
+        clamp_chart_value(|| format!("{kind} multiplier at {time} ms ({group})"), &mut point.multiplier, MAX_MULTIPLIER, report);
+    }
+}
+
+fn is_unusable_bpm(bpm: f64) -> bool {
+    // bpms SV normalization can't scale by, they're clamped to a large multiplier instead
+    bpm == 0.0 || !bpm.is_finite()
+}
+
+fn sv_point(start_time: Time, multiplier: f64) -> ControlPoint {
+    ControlPoint { start_time, multiplier, length: None, cumulative_position: 0 }
+}
+
+fn normalized_scroll_velocities(timing_points: &[TimingPoint], scroll_velocities: &[ControlPoint], base_bpm: f64) -> (f64, Vec<ControlPoint>) {
+    // the initial SV and SVs that scroll like the legacy ones do with their bpms; every timing point
+    // resets the SV to 1x in the legacy format, unless there's an SV at the same time
+    // https://github.com/Quaver/Quaver.API/blob/master/Quaver.API/Maps/Qua.cs (NormalizeSVs)
+    let adjusted = |multiplier: f64, bpm: f64| if is_unusable_bpm(bpm) { UNUSABLE_BPM_MULTIPLIER } else { multiplier * bpm / base_bpm };
+    let mut normalized = Vec::new();
+    let mut current_bpm = timing_points[0].bpm;
+    let mut sv_index = 0;
+    let mut current_sv_start_time: Option<Time> = None;
+    let mut current_sv_multiplier = 1.0;
+    let mut current_adjusted: Option<f64> = None;
+    let mut initial: Option<f64> = None;
+    for (index, timing_point) in timing_points.iter().enumerate() {
+        let next_at_same_time = timing_points.get(index + 1).is_some_and(|next| next.start_time == timing_point.start_time);
+        while let Some(sv) = scroll_velocities.get(sv_index) {
+            // an SV at a timing point's time applies after it, and after the last of several at that time
+            if sv.start_time > timing_point.start_time || (next_at_same_time && sv.start_time == timing_point.start_time) {
+                break;
+            }
+            if sv.start_time < timing_point.start_time {
+                let multiplier = adjusted(sv.multiplier, current_bpm);
+                if current_adjusted.is_none() {
+                    current_adjusted = Some(multiplier);
+                    initial = Some(multiplier);
+                }
+                if current_adjusted != Some(multiplier) {
+                    normalized.push(sv_point(sv.start_time, multiplier));
+                    current_adjusted = Some(multiplier);
+                }
+            }
+            current_sv_start_time = Some(sv.start_time);
+            current_sv_multiplier = sv.multiplier;
+            sv_index += 1;
+        }
+
+        if !current_sv_start_time.is_some_and(|start_time| start_time >= timing_point.start_time) {
+            current_sv_multiplier = 1.0;
+        }
+        current_bpm = timing_point.bpm;
+        let multiplier = adjusted(current_sv_multiplier, current_bpm);
+        if current_adjusted.is_none() {
+            current_adjusted = Some(multiplier);
+            initial = Some(multiplier);
+        }
+        if current_adjusted != Some(multiplier) {
+            normalized.push(sv_point(timing_point.start_time, multiplier));
+            current_adjusted = Some(multiplier);
+        }
+    }
+
+    for sv in &scroll_velocities[sv_index..] {
+        let multiplier = adjusted(sv.multiplier, current_bpm);
+        if current_adjusted != Some(multiplier) {
+            normalized.push(sv_point(sv.start_time, multiplier));
+            current_adjusted = Some(multiplier);
+        }
+    }
+    sort_by_start_time(&mut normalized);
+    (initial.unwrap_or(1.0), normalized)
+}
+
+fn denormalized_scroll_velocities(
+    timing_points: &[TimingPoint],
+    initial_scroll_velocity: f64,
+    scroll_velocities: &[ControlPoint],
+    base_bpm: f64,
+) -> Vec<ControlPoint> {
+    // the reverse of normalized_scroll_velocities: SVs that scroll the same once every timing point
+    // scales them by its bpm (and resets them to 1x); the initial SV becomes an SV 1 ms before the
+    // first one that differs from it
+    // https://github.com/Quaver/Quaver.API/blob/master/Quaver.API/Maps/Qua.cs (DenormalizeSVs)
+    let adjusted = |multiplier: f64, bpm: f64| if is_unusable_bpm(bpm) { 1.0 / UNUSABLE_BPM_MULTIPLIER } else { multiplier / (bpm / base_bpm) };
+    let mut denormalized = Vec::new();
+    let mut current_bpm = timing_points[0].bpm;
+    let mut sv_index = 0;
+    let mut current_sv_multiplier = initial_scroll_velocity;
+    let mut current_adjusted: Option<f64> = None;
+    for (index, timing_point) in timing_points.iter().enumerate() {
+        while let Some(sv) = scroll_velocities.get(sv_index) {
+            if sv.start_time > timing_point.start_time {
+                break;
+            }
+            if sv.start_time < timing_point.start_time {
+                let multiplier = adjusted(sv.multiplier, current_bpm);
+                if current_adjusted != Some(multiplier) {
+                    if current_adjusted.is_none() && sv.multiplier != initial_scroll_velocity {
+                        denormalized.push(sv_point(sv.start_time - 1.0, adjusted(initial_scroll_velocity, current_bpm)));
+                    }
+                    denormalized.push(sv_point(sv.start_time, multiplier));
+                    current_adjusted = Some(multiplier);
+                }
+            }
+            current_sv_multiplier = sv.multiplier;
+            sv_index += 1;
+        }
+
+        current_bpm = timing_point.bpm;
+        if current_adjusted.is_none() && current_sv_multiplier != initial_scroll_velocity {
+            denormalized.push(sv_point(timing_point.start_time - 1.0, adjusted(initial_scroll_velocity, current_bpm)));
+        }
+        // timing points reset the SV, only the last of several at the same time matters
+        current_adjusted = Some(1.0);
+        if timing_points.get(index + 1).is_some_and(|next| next.start_time == timing_point.start_time) {
+            continue;
+        }
+        let multiplier = adjusted(current_sv_multiplier, current_bpm);
+        if current_adjusted != Some(multiplier) {
+            denormalized.push(sv_point(timing_point.start_time, multiplier));
+            current_adjusted = Some(multiplier);
+        }
+    }
+
+    for sv in &scroll_velocities[sv_index..] {
+        let multiplier = adjusted(sv.multiplier, current_bpm);
+        if current_adjusted != Some(multiplier) {
+            denormalized.push(sv_point(sv.start_time, multiplier));
+            current_adjusted = Some(multiplier);
+        }
+    }
+    sort_by_start_time(&mut denormalized);
+    denormalized
+}
+
+// which note a press judges when several heads in its lane are in the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum NoteLock {
+    #[default]
+    Strict, // always the earliest, so a late press on a jack can lock the next note out
+    Forgiving, // the closest, skipping earlier heads the press is already late for (they're missed later)
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Mods {
     pub mirror: bool,   // mirror notes horizontally
@@ -19,6 +350,49 @@ pub struct Mods {
     pub autoplay: bool, // autoplay mode
     pub debug: bool,    // enable debug text
     pub no_ui: bool,    // disable UI elements
+    pub random: bool,   // shuffle lanes (seeded, see --seed)
+    pub seed: u64,      // seed the run's randomness came from
+    #[serde(default)]
+    pub no_ln: bool,    // every LN played as a normal note
+}
+
+impl Mods {
+    pub fn short_codes(&self) -> Vec<String> {
+        // canonical short names of the active gameplay mods (debug/ui options aren't mods)
+        let mut codes = Vec::new();
+        if self.mirror {
+            codes.push("MR".to_string());
+        }
+        if self.no_sv {
+            codes.push("NSV".to_string());
+        }
+        if self.no_ssf {
+            codes.push("NSSF".to_string());
+        }
+        if self.autoplay {
+            codes.push("AP".to_string());
+        }
+        if self.random {
+            codes.push(format!("RD:{}", self.seed));
+        }
+        if self.no_ln {
+            codes.push("NLN".to_string());
+        }
+        codes
+    }
+
+    pub fn watermark(&self, rate: f64) -> String {
+        // "1.1x | MR NSV", or just the rate with no mods
+        let rate = format!("{rate:.2}");
+        let rate = rate.trim_end_matches('0');
+        let rate = if rate.ends_with('.') { format!("{rate}0") } else { rate.to_string() };
+        let codes = self.short_codes();
+        if codes.is_empty() {
+            format!("{rate}x")
+        } else {
+            format!("{rate}x | {}", codes.join(" "))
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -29,27 +403,49 @@ pub enum GameMode {
     Keys4,
     Keys7,
 }
- 
 
+impl GameMode {
+    pub const fn key_count(&self) -> i64 {
+        match self {
+            GameMode::Keys4 => 4,
+            GameMode::Keys7 => 7,
+        }
+    }
+}
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "PascalCase")]
 pub struct Map {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub audio_file: Option<String>,      // audio file name
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub song_preview_time: Option<f64>,  // time (ms) of the song where the preview starts
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub background_file: Option<String>, // background file name
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub banner_file: Option<String>,     // mapset banner name
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub map_id: Option<f64>,             // unique Map Identifier (-1 if not submitted)
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub map_set_id: Option<f64>,         // unique Map Set identifier (-1 if not submitted)
     #[serde(default)]
     pub mode: GameMode,                  // game mode for this map {Keys4, Keys7}
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,           // song title
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub artist: Option<String>,          // song artist
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub source: Option<String>,          // source of the song (album, mixtape, etc.)
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tags: Option<String>,            // any tags that could be used to help find the song
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub creator: Option<String>,         // map creator
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub difficulty_name: Option<String>, // map difficulty name
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,     // map description
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub genre: Option<String>,           // song genre
     #[serde(rename = "LegacyLNRendering")]
     #[serde(default)]
@@ -66,11 +462,13 @@ pub struct Map {
     #[serde(default)]
     pub bookmarks: Vec<serde_yaml::Value>,
     #[serde(default)]
-    pub custom_audio_samples: Vec<serde_yaml::Value>,
+    pub custom_audio_samples: Vec<CustomAudioSample>,
     #[serde(default)]
-    pub timing_points: Vec<TimingPoint>,
+    pub sound_effects: Vec<SoundEffect>,
     #[serde(default)]
-    pub timing_lines: Vec<TimingLine>,
+    pub timing_points: Vec<TimingPoint>,
+    #[serde(default, skip_serializing)]
+    pub timing_lines: Vec<TimingLine>, // generated from timing points, not part of the chart
     #[serde(rename = "SliderVelocities")]
     #[serde(default)]
     pub scroll_velocities: Vec<ControlPoint>,
@@ -79,8 +477,8 @@ pub struct Map {
     #[serde(default)]
     pub hit_objects: Vec<HitObject>,
     #[serde(default)]
-    pub timing_groups: HashMap<String, TimingGroup>,
-    #[serde(skip_deserializing)]
+    pub timing_groups: TimingGroups,
+    #[serde(skip)]
     pub file_path: String, // map file path
     #[serde(skip)]
     pub time: Time, // current time in the map
@@ -89,18 +487,422 @@ pub struct Map {
     #[serde(skip)]
     pub mods: Mods,
     #[serde(skip)]
+    pub ruleset: Ruleset, // how accuracy is scored
+    #[serde(skip)]
     pub length: Time, // length of the map in ms
     #[serde(skip)]
-    pub judgement_windows: HashMap<JudgementType, f64>, // hit window in ms
+    pub playable_length: Time, // time at which the map is finished (last object + miss window + lead-out, up to the audio's end)
+    #[serde(skip)]
+    pub judgement_windows: JudgementWindows, // hit windows in ms
     #[serde(skip)]
     pub judgement_counts: HashMap<JudgementType, usize>, // count for each judgement
     #[serde(skip)]
     pub last_judgement: Option<(JudgementType, f64, f64)>, // last judgement (type, time, offset)
     #[serde(skip)]
+    pub splash: Option<(JudgementType, f64, f64)>, // last judgement shown in the center splash, same fields
+    #[serde(skip)]
+    pub note_lock: NoteLock, // how a press picks between overlapping heads in its lane
+    #[serde(skip)]
+    pub lane_splashes: HashMap<i64, (JudgementType, Time)>, // lane -> its last judgement and when it happened
+    #[serde(skip)]
+    pub hit_stats: Vec<HitStat>, // every judgement so far, in order
+    #[serde(skip)]
+    pub hold_events: Vec<HoldEvent>, // LN grabs, breaks, regrabs and releases so far, in order
+    #[serde(skip)]
     pub combo: usize, // current combo
+    #[serde(skip)]
+    pub held_notes: HashMap<i64, usize>, // lane -> index of the LN being held in it
+    #[serde(skip)]
+    pub released_tails: HashMap<i64, Time>, // lane -> end time of the LN last released in it
+    #[serde(skip)]
+    pub mash_detector: MashDetector, // flags mashing from key presses, informational only
+    #[serde(skip)]
+    pub group_notes: Vec<Vec<usize>>, // timing group index -> indices of the hit objects in it
+    #[serde(skip)]
+    pub group_draw_order: Vec<usize>, // timing group indices, the group drawn first (at the bottom) first
+    #[serde(skip)]
+    pub visible_timing_lines: Range<usize>, // timing lines updated (and drawn) in the last update
+    #[serde(skip)]
+    timing_lines_sorted: bool, // whether timing line track positions only go up (no negative SVs)
+    #[serde(skip)]
+    longest_long_note: Time, // how far back a press has to look for a broken LN to regrab
+    #[serde(skip)]
+    pub mixed_mods: bool, // mods were toggled after something was judged, so the score isn't for one set of mods
+    #[serde(skip)]
+    pub resumed: bool, // the play was picked up partway in, so the notes before it were never played
+    #[serde(skip)]
+    pub autoplay_assisted: bool, // autoplay was turned on (or off after judging) partway through the play
+    #[serde(skip)]
+    pub safe_mode_speed: Option<f64>, // photosensitivity-safe mode: fastest (px/s) notes and lines move on screen
+    #[serde(skip)]
+    pub lane_offsets: Vec<f64>, // ms each gameplay key (0-indexed, as pressed) registers late by, taken off before judging
+    #[serde(skip)]
+    pub effects: Effects, // playfield keyframes from the map folder's effects file
+    #[serde(skip)]
+    pub validation_errors: Vec<MapValidationError>, // what validate found in the chart as read, before loading clamped anything
+    #[serde(skip)]
+    pub parse_report: String, // how the chart was parsed, in how long and with how much memory; logged by whoever loads it for play
+    #[serde(skip)]
+    last_position_update: Option<Time>, // map time of the last update_hit_objects, for safe mode's per-update limit
 }
 
 impl Map {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        // reads and parses a .qua file, or a chart in one of the formats in parsers
+        let content = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read map file '{}': {}", path.display(), e))?;
+
+        // big files have their long lists parsed in chunks, anything that goes wrong there is left to the full parse
+        let started = Instant::now();
+        let allocated = alloc_stats::reset_peak();
+        let format = parsers::ChartFormat::of(path).unwrap_or(parsers::ChartFormat::Qua);
+        let streamed = (format == parsers::ChartFormat::Qua && content.len() >= STREAMING_PARSE_THRESHOLD).then(|| {
+            qua_stream::parse_sections(&content).inspect_err(|e| {
+                logger::warning(&format!("Parsing '{}' in one go, it couldn't be parsed in sections: {e}", path.display()));
+            })
+        });
+        let (mut map, how): (Self, &str) = match streamed {
+            _ if format != parsers::ChartFormat::Qua => (
+                parsers::parse_converted(format, path, &content)
+                    .map_err(|e| anyhow!("Failed to parse {} chart '{}': {}", format.name(), path.display(), e))?,
+                "as a converted chart",
+            ),
+            Some(Ok(map)) => (map, "in sections"),
+            _ => (
+                serde_yaml::from_str(&content)
+                    .map_err(|e| anyhow!("Failed to parse map data from '{}': {}", path.display(), e))?,
+                "in one go",
+            ),
+        };
+        // not logged here, charts are also parsed in bulk (difficulty switching, tools)
+        map.parse_report = format!(
+            "Parsed {:.1} MB {how} in {:.0} ms, peaking at {:.1} MB more memory",
+            content.len() as f64 / 1e6,
+            started.elapsed().as_secs_f64() * 1000.0,
+            alloc_stats::peak().saturating_sub(allocated) as f64 / 1e6
+        );
+        map.file_path = path.to_string_lossy().to_string();
+        // checked before anything is fixed up, clamping turns a NaN multiplier into 0
+        map.validation_errors = map.validate().err().unwrap_or_default();
+        for clamped in map.validate_ranges() {
+            logger::warning(&format!(
+                "Clamped {} from {} to {}",
+                clamped.field, clamped.value, clamped.clamped
+            ));
+        }
+        for (points, count) in map.collapse_duplicate_points() {
+            logger::warning(&format!("Removed {count} {points} at the same time as a later one"));
+        }
+        let min_frozen_length = skin().frozen_sv_warning;
+        if min_frozen_length > 0.0 {
+            for run in map.frozen_sv_runs(min_frozen_length) {
+                logger::warning(&format!(
+                    "SVs in '{}' stop the chart from {} to {} ms (0x, which is also what a point without a Multiplier is)",
+                    run.group, run.start_time, run.end_time
+                ));
+            }
+        }
+        for timing_point in map.irregular_bpms() {
+            logger::warning(&format!(
+                "Timing point at {} ms has a BPM of {}, counting it as {:.0} ms beats",
+                timing_point.start_time,
+                timing_point.bpm,
+                timing_point.ms_per_beat()
+            ));
+        }
+
+        Ok(map)
+    }
+
+    /// Returns everything wrong with the chart that it can still be loaded with: notes outside the
+    /// lanes, LNs that don't end after they start, notes in a lane starting before the one before
+    /// them is over (or at the same time), SVs with a NaN multiplier and timing points with a BPM
+    /// of 0 or NaN. Notes starting right at an LN's end aren't overlapping.
+    ///
+    /// ```
+    /// use vsrg_renderer::map::{ControlPoint, HitObject, Map, MapValidationError, SimpleNote, TimingPoint};
+    ///
+    /// let note = |start_time, lane, end_time| SimpleNote { start_time, lane, end_time };
+    /// let mut map = Map::from_simple_notes(&[note(0.0, 1, Some(500.0)), note(500.0, 1, None), note(0.0, 2, None)], 120.0, 4);
+    /// assert_eq!(map.validate(), Ok(()));
+    ///
+    /// map.hit_objects.push(HitObject { start_time: 1000.0, lane: 5, ..HitObject::default() });
+    /// map.hit_objects.push(HitObject { start_time: 2000.0, end_time: Some(1900.0), lane: 3, ..HitObject::default() });
+    /// map.hit_objects.push(HitObject { start_time: 250.0, lane: 1, ..HitObject::default() });
+    /// map.scroll_velocities.push(ControlPoint { start_time: 300.0, multiplier: f64::NAN, length: None, cumulative_position: 0 });
+    /// map.timing_points.push(TimingPoint { start_time: 4000.0, bpm: 0.0, time_signature: None, hidden: false });
+    /// assert_eq!(
+    ///     map.validate(),
+    ///     Err(vec![
+    ///         MapValidationError::LaneOutOfRange { index: 3, start_time: 1000.0, lane: 5, key_count: 4 },
+    ///         MapValidationError::LnEndBeforeStart { index: 4, start_time: 2000.0, end_time: 1900.0 },
+    ///         MapValidationError::OverlappingNotes { lane: 1, first: 0.0, second: 250.0 },
+    ///         MapValidationError::NanSvMultiplier { group: "$Default".to_string(), start_time: 300.0 },
+    ///         MapValidationError::InvalidBpm { start_time: 4000.0, bpm: 0.0 },
+    ///     ])
+    /// );
+    ///
+    /// // two notes at the same time in a lane overlap too
+    /// let stacked = Map::from_simple_notes(&[note(100.0, 2, None), note(100.0, 2, None)], 120.0, 4);
+    /// assert_eq!(stacked.validate(), Err(vec![MapValidationError::OverlappingNotes { lane: 2, first: 100.0, second: 100.0 }]));
+    /// ```
+    pub fn validate(&self) -> std::result::Result<(), Vec<MapValidationError>> {
+        let mut errors = Vec::new();
+        let key_count = self.get_key_count(true);
+        for (index, note) in self.hit_objects.iter().enumerate() {
+            if !(1..=key_count).contains(&note.lane) {
+                errors.push(MapValidationError::LaneOutOfRange { index, start_time: note.start_time, lane: note.lane, key_count });
+            }
+            if let Some(end_time) = note.end_time.filter(|&end_time| end_time <= note.start_time) {
+                errors.push(MapValidationError::LnEndBeforeStart { index, start_time: note.start_time, end_time });
+            }
+        }
+
+        // mines and fakes can sit on other notes
+        let mut lanes: Vec<(i64, Time, Time)> = self
+            .hit_objects
+            .iter()
+            .filter(|note| note.object_kind.is_normal())
+            .map(|note| (note.lane, note.start_time, note.end_time.filter(|&end| end > note.start_time).unwrap_or(note.start_time)))
+            .collect();
+        lanes.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+        for pair in lanes.windows(2) {
+            let ((lane, first, first_end), (next_lane, second, _)) = (pair[0], pair[1]);
+            if lane == next_lane && (second < first_end || second == first) {
+                errors.push(MapValidationError::OverlappingNotes { lane, first, second });
+            }
+        }
+
+        let groups = std::iter::once((DEFAULT_TIMING_GROUP_ID, &self.scroll_velocities))
+            .chain(self.timing_groups.iter().map(|(id, group)| (id.as_str(), &group.scroll_velocities)));
+        for (group, scroll_velocities) in groups {
+            errors.extend(
+                scroll_velocities
+                    .iter()
+                    .filter(|sv| sv.multiplier.is_nan())
+                    .map(|sv| MapValidationError::NanSvMultiplier { group: group.to_string(), start_time: sv.start_time }),
+            );
+        }
+        errors.extend(
+            self.timing_points
+                .iter()
+                .filter(|timing_point| timing_point.bpm == 0.0 || timing_point.bpm.is_nan())
+                .map(|timing_point| MapValidationError::InvalidBpm { start_time: timing_point.start_time, bpm: timing_point.bpm }),
+        );
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn validate_ranges(&mut self) -> Vec<ClampedValue> {
+        // clamps absurd times and multipliers from corrupt charts, returns everything that was clamped
+        let mut report = Vec::new();
+        for hit_object in &mut self.hit_objects {
+            let time = hit_object.start_time;
+            clamp_chart_value(|| format!("note time at {time} ms"), &mut hit_object.start_time, MAX_START_TIME, &mut report);
+            if let Some(end_time) = hit_object.end_time.as_mut() {
+                clamp_chart_value(|| format!("LN end time of the note at {time} ms"), end_time, MAX_START_TIME, &mut report);
+            }
+        }
+        for timing_point in &mut self.timing_points {
+            let time = timing_point.start_time;
+            clamp_chart_value(|| format!("timing point time at {time} ms"), &mut timing_point.start_time, MAX_START_TIME, &mut report);
+        }
+
+        clamp_chart_value(|| "initial SV".to_string(), &mut self.initial_scroll_velocity, MAX_MULTIPLIER, &mut report);
+        clamp_control_points("SV", DEFAULT_TIMING_GROUP_ID, &mut self.scroll_velocities, &mut report);
+        clamp_control_points("SSF", DEFAULT_TIMING_GROUP_ID, &mut self.scroll_speed_factors, &mut report);
+        for (id, timing_group) in self.timing_groups.iter_mut() {
+            clamp_chart_value(|| format!("initial SV ({id})"), &mut timing_group.initial_scroll_velocity, MAX_MULTIPLIER, &mut report);
+            clamp_control_points("SV", id, &mut timing_group.scroll_velocities, &mut report);
+            clamp_control_points("SSF", id, &mut timing_group.scroll_speed_factors, &mut report);
+        }
+        report
+    }
+
+    pub fn collapse_duplicate_points(&mut self) -> Vec<(String, usize)> {
+        // removes SV/SSF points sharing a time with a later point in the same group, which would
+        // otherwise make zero-length segments; returns the removed count per list
+        let mut report = Vec::new();
+        let mut collapse = |kind: &str, group: &str, points: &mut Vec<ControlPoint>| {
+            let count = collapse_duplicate_times(points);
+            if count > 0 {
+                report.push((format!("{kind} points ({group})"), count));
+            }
+        };
+        collapse("SV", DEFAULT_TIMING_GROUP_ID, &mut self.scroll_velocities);
+        collapse("SSF", DEFAULT_TIMING_GROUP_ID, &mut self.scroll_speed_factors);
+        for (id, timing_group) in self.timing_groups.iter_mut() {
+            collapse("SV", id, &mut timing_group.scroll_velocities);
+            collapse("SSF", id, &mut timing_group.scroll_speed_factors);
+        }
+        report
+    }
+
+    pub fn frozen_sv_runs(&self, min_length: Time) -> Vec<FrozenSvRun> {
+        // stretches of 0x SVs at least min_length (ms) long with notes still to come, which look like
+        // the chart froze (a point without a multiplier is 0x, so they're easy to end up with by
+        // accident); for the chart as parsed, before the default group is made
+        let last_note = |group: Option<&str>| {
+            self.hit_objects
+                .iter()
+                .filter(|hit_object| {
+                    let id = hit_object.timing_group.as_deref().unwrap_or(DEFAULT_TIMING_GROUP_ID);
+                    id == group.unwrap_or(DEFAULT_TIMING_GROUP_ID)
+                })
+                .map(|hit_object| hit_object.end_time.unwrap_or(hit_object.start_time))
+                .fold(f64::NEG_INFINITY, f64::max)
+        };
+        let mut scroll_velocities = self.scroll_velocities.clone();
+        sort_by_start_time(&mut scroll_velocities);
+        let mut runs = frozen_runs(DEFAULT_TIMING_GROUP_ID, self.initial_scroll_velocity, &scroll_velocities, last_note(None));
+        for (id, timing_group) in self.timing_groups.iter() {
+            let mut scroll_velocities = timing_group.scroll_velocities.clone();
+            sort_by_start_time(&mut scroll_velocities);
+            runs.extend(frozen_runs(id, timing_group.initial_scroll_velocity, &scroll_velocities, last_note(Some(id))));
+        }
+        runs.retain(|run| run.end_time - run.start_time >= min_length);
+        runs
+    }
+
+    pub fn irregular_bpms(&self) -> Vec<&TimingPoint> {
+        // timing points whose bpm isn't used as is (see TimingPoint::ms_per_beat); they're kept
+        // in the chart, so dumps and shifts write them back unchanged
+        self.timing_points
+            .iter()
+            .filter(|timing_point| !(MIN_BPM..=MAX_BPM).contains(&timing_point.bpm))
+            .collect()
+    }
+
+    pub fn chart_clone(&self) -> Self {
+        // clones only the chart data, as if freshly parsed; runtime state starts empty (initialize again to play it)
+        let mut timing_groups: TimingGroups = self
+            .timing_groups
+            .iter()
+            .map(|(id, group)| (id.clone(), group.chart_clone()))
+            .collect();
+        // the default group is built from the map-level lists on initialization, so put them back
+        let (initial_scroll_velocity, scroll_velocities, scroll_speed_factors) =
+            match timing_groups.remove(DEFAULT_TIMING_GROUP_ID) {
+                Some(group) => (group.initial_scroll_velocity, group.scroll_velocities, group.scroll_speed_factors),
+                None => (
+                    self.initial_scroll_velocity,
+                    self.scroll_velocities.iter().map(ControlPoint::chart_clone).collect(),
+                    self.scroll_speed_factors.iter().map(ControlPoint::chart_clone).collect(),
+                ),
+            };
+
+        Self {
+            audio_file: self.audio_file.clone(),
+            song_preview_time: self.song_preview_time,
+            background_file: self.background_file.clone(),
+            banner_file: self.banner_file.clone(),
+            map_id: self.map_id,
+            map_set_id: self.map_set_id,
+            mode: self.mode.clone(),
+            title: self.title.clone(),
+            artist: self.artist.clone(),
+            source: self.source.clone(),
+            tags: self.tags.clone(),
+            creator: self.creator.clone(),
+            difficulty_name: self.difficulty_name.clone(),
+            description: self.description.clone(),
+            genre: self.genre.clone(),
+            legacy_ln_rendering: self.legacy_ln_rendering,
+            bpm_does_not_affect_scroll_velocity: self.bpm_does_not_affect_scroll_velocity,
+            initial_scroll_velocity,
+            has_scratch_key: self.has_scratch_key,
+            editor_layers: self.editor_layers.clone(),
+            bookmarks: self.bookmarks.clone(),
+            custom_audio_samples: self.custom_audio_samples.clone(),
+            sound_effects: self.sound_effects.clone(),
+            timing_points: self.timing_points.clone(),
+            scroll_velocities,
+            scroll_speed_factors,
+            hit_objects: self.hit_objects.iter().map(HitObject::chart_clone).collect(),
+            timing_groups,
+            file_path: self.file_path.clone(),
+            validation_errors: self.validation_errors.clone(),
+            ..Self::default()
+        }
+    }
+
+    pub fn from_simple_notes(rows: &[SimpleNote], bpm: f64, key_count: i64) -> Self {
+        // a chart of just these notes, with one timing point at 0 and no SVs; for generated charts,
+        // which go through the same initialization as a parsed one (rows aren't checked, lanes past
+        // the key count are left to initialization like in a .qua)
+        let key_count = key_count.clamp(1, 7);
+        Self {
+            creator: Some("VSRG Renderer".to_string()),
+            mode: if key_count <= 4 { GameMode::Keys4 } else { GameMode::Keys7 },
+            initial_scroll_velocity: 1.0,
+            timing_points: vec![TimingPoint {
+                start_time: 0.0,
+                bpm,
+                time_signature: Some(TimeSignature::Quadruple),
+                hidden: false,
+            }],
+            hit_objects: rows
+                .iter()
+                .map(|row| HitObject {
+                    start_time: row.start_time,
+                    end_time: row.end_time,
+                    lane: row.lane,
+                    ..HitObject::default()
+                })
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    pub fn synthetic_metronome(bpm: f64, duration: Time, lanes: i64) -> Self {
+        // one note per beat cycling through the lanes, for sync testing and benchmarks
+        let beat_length = 60000.0 / bpm;
+        let lanes = lanes.clamp(1, 7);
+        let beats = (duration / beat_length).ceil().max(0.0) as i64;
+        let rows: Vec<SimpleNote> = (0..beats)
+            .map(|beat| SimpleNote { start_time: beat as f64 * beat_length, lane: beat % lanes + 1, end_time: None })
+            .filter(|row| row.start_time < duration)
+            .collect();
+        Self {
+            title: Some("Sync test".to_string()),
+            artist: Some("Metronome".to_string()),
+            difficulty_name: Some(format!("{bpm} BPM")),
+            ..Self::from_simple_notes(&rows, bpm, lanes)
+        }
+    }
+
+    /// Returns the chart as a .qua, as if it had never been played: the default group's SVs and SSFs
+    /// go back to the map-level lists they were moved out of on initialization, nothing from playing
+    /// it is written, and neither are values that aren't set.
+    ///
+    /// ```
+    /// use std::path::Path;
+    /// use vsrg_renderer::{initialize_map, map::Map, render::set_reference_positions};
+    ///
+    /// let mut map = Map::from_file(Path::new("golden/charts/sv_reversal.qua"))?;
+    /// initialize_map(&mut map, &set_reference_positions(None))?;
+    /// let qua = map.to_qua_string()?;
+    /// assert!(!qua.contains("null") && !qua.contains("$Default"));
+    ///
+    /// let reparsed: Map = serde_yaml::from_str(&qua)?;
+    /// let times = |map: &Map| map.hit_objects.iter().map(|note| (note.start_time, note.end_time, note.lane)).collect::<Vec<_>>();
+    /// assert_eq!(times(&reparsed), times(&map));
+    /// let svs = map.timing_groups.get("$Default").unwrap().scroll_velocities.iter();
+    /// let sv_times: Vec<_> = svs.map(|sv| (sv.start_time, sv.multiplier)).collect();
+    /// assert!(!sv_times.is_empty());
+    /// assert_eq!(reparsed.scroll_velocities.iter().map(|sv| (sv.start_time, sv.multiplier)).collect::<Vec<_>>(), sv_times);
+    /// // and it comes out the same the second time round
+    /// assert_eq!(reparsed.to_qua_string()?, qua);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn to_qua_string(&self) -> Result<String> {
+        serde_yaml::to_string(&self.chart_clone()).map_err(|e| anyhow!("Failed to serialize map: {e}"))
+    }
+
     pub fn initialize_default_timing_group(&mut self) {
         // adds the default timing group to timing_groups
         self.timing_groups.insert(
@@ -110,17 +912,183 @@ impl Map {
                 scroll_velocities: take(&mut self.scroll_velocities),
                 scroll_speed_factors: take(&mut self.scroll_speed_factors),
                 color_rgb: None,
-                current_track_position: 0,
-                current_ssf_factor: 1.0,
-                scroll_speed: 0.0,
+                z_layer: None,
+                ..TimingGroup::default()
             },
         );
         // set every hitobject whose timing group is null to the default group
+        // notes in a group the chart doesn't define (a typo'd id) would never move, so they go there too
+        let mut unknown_groups: Vec<(String, usize)> = Vec::new();
         for hit_object in &mut self.hit_objects {
-            if hit_object.timing_group.is_none() {
-                hit_object.timing_group = Some(DEFAULT_TIMING_GROUP_ID.to_string());
+            match &hit_object.timing_group {
+                Some(group_id) if self.timing_groups.index_of(group_id).is_none() => {
+                    match unknown_groups.iter_mut().find(|(id, _)| id == group_id) {
+                        Some((_, count)) => *count += 1,
+                        None => unknown_groups.push((group_id.clone(), 1)),
+                    }
+                    hit_object.unknown_timing_group = hit_object.timing_group.take();
+                }
+                Some(_) => continue,
+                None => {}
+            }
+            hit_object.timing_group = Some(DEFAULT_TIMING_GROUP_ID.to_string());
+        }
+        if !unknown_groups.is_empty() {
+            let groups = unknown_groups
+                .iter()
+                .map(|(id, count)| format!("'{id}' ({count})"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            logger::warning(&format!("Notes in unknown timing groups moved to the default group: {groups}"));
+        }
+    }
+
+    /// Returns the BPM the most time is spent at, up to the end of the last note (0 without timing
+    /// points). Of several timing points at the same time only the last counts, and ones after the
+    /// last note don't count at all.
+    ///
+    /// ```
+    /// use vsrg_renderer::map::{Map, SimpleNote, TimingPoint};
+    ///
+    /// let timing_point = |start_time, bpm| TimingPoint { start_time, bpm, time_signature: None, hidden: false };
+    /// // 1 s of 120 bpm, then an LN to 4 s at 174 bpm (the 300 bpm point is replaced at once)
+    /// let mut map = Map::from_simple_notes(&[SimpleNote { start_time: 1500.0, lane: 1, end_time: Some(4000.0) }], 120.0, 4);
+    /// map.timing_points.extend([timing_point(1000.0, 300.0), timing_point(1000.0, 174.0), timing_point(5000.0, 348.0)]);
+    /// assert_eq!(map.get_common_bpm(), 174.0);
+    /// assert_eq!(map.get_bpm_range(), (120.0, 174.0));
+    /// ```
+    pub fn get_common_bpm(&self) -> f64 {
+        // https://github.com/Quaver/Quaver.API/blob/master/Quaver.API/Maps/Qua.cs (GetCommonBpm)
+        let Some(first) = self.timing_points.first() else {
+            return 0.0;
+        };
+        let Some(mut last_time) = self
+            .hit_objects
+            .iter()
+            .map(|hit_object| hit_object.end_time.unwrap_or(hit_object.start_time))
+            .max_by(f64::total_cmp)
+        else {
+            return first.bpm;
+        };
+
+        // bpms in the order first seen from the end, so ties go to the later one like in Quaver
+        let mut durations: Vec<(f64, i64)> = Vec::new();
+        for (index, timing_point) in self.timing_points.iter().enumerate().rev() {
+            if timing_point.start_time > last_time {
+                continue;
             }
+            let start_time = if index == 0 { 0.0 } else { timing_point.start_time };
+            let duration = (last_time - start_time) as i64;
+            last_time = timing_point.start_time;
+            match durations.iter_mut().find(|(bpm, _)| *bpm == timing_point.bpm) {
+                Some((_, total)) => *total += duration,
+                None => durations.push((timing_point.bpm, duration)),
+            }
+        }
+        durations
+            .iter()
+            .fold(None, |most: Option<(f64, i64)>, &(bpm, duration)| match most {
+                Some((_, longest)) if longest >= duration => most,
+                _ => Some((bpm, duration)),
+            })
+            .map_or(first.bpm, |(bpm, _)| bpm)
+    }
+
+    pub fn get_bpm_range(&self) -> (f64, f64) {
+        // lowest and highest bpm played, of the timing points get_common_bpm counts ((0, 0) without any)
+        let last_time = self
+            .hit_objects
+            .iter()
+            .map(|hit_object| hit_object.end_time.unwrap_or(hit_object.start_time))
+            .fold(f64::NEG_INFINITY, f64::max);
+        let first_time = self.timing_points.first().map_or(0.0, |first| first.start_time);
+        let played = |(index, timing_point): &(usize, &TimingPoint)| {
+            let next_at_same_time = self.timing_points.get(index + 1).is_some_and(|next| next.start_time == timing_point.start_time);
+            !next_at_same_time && (timing_point.start_time <= last_time || timing_point.start_time == first_time)
+        };
+        self.timing_points
+            .iter()
+            .enumerate()
+            .filter(played)
+            .map(|(_, timing_point)| timing_point.bpm)
+            .fold(None, |range: Option<(f64, f64)>, bpm| Some(range.map_or((bpm, bpm), |(min, max)| (min.min(bpm), max.max(bpm)))))
+            .unwrap_or((0.0, 0.0))
+    }
+
+    /// Rewrites a legacy chart's default group SVs (where BPM changes affect scroll, scaled against
+    /// [`get_common_bpm`](Self::get_common_bpm)) so they scroll the same without the BPM affecting
+    /// them, and marks the map as not BPM-affected. 0 and infinite BPMs scroll at 128x. Needs
+    /// the default group made and sorted; does nothing if the map isn't legacy.
+    ///
+    /// ```
+    /// use vsrg_renderer::map::{ControlPoint, Map, SimpleNote, TimingPoint};
+    /// use vsrg_renderer::utils::DEFAULT_TIMING_GROUP_ID;
+    ///
+    /// // 120 bpm for the first second, 240 for the two after it, with a 2x SV at 500 ms
+    /// let mut map = Map::from_simple_notes(&[SimpleNote { start_time: 3000.0, lane: 1, end_time: None }], 120.0, 4);
+    /// map.timing_points.push(TimingPoint { start_time: 1000.0, bpm: 240.0, time_signature: None, hidden: false });
+    /// map.scroll_velocities.push(ControlPoint { start_time: 500.0, multiplier: 2.0, length: None, cumulative_position: 0 });
+    /// map.initialize_default_timing_group();
+    /// map.sort();
+    /// assert_eq!(map.get_common_bpm(), 240.0);
+    ///
+    /// let multipliers = |map: &Map| {
+    ///     let group = map.timing_groups.get(DEFAULT_TIMING_GROUP_ID).unwrap();
+    ///     let points: Vec<(f64, f64)> = group.scroll_velocities.iter().map(|sv| (sv.start_time, sv.multiplier)).collect();
+    ///     (group.initial_scroll_velocity, points)
+    /// };
+    /// map.normalize_svs();
+    /// assert!(map.bpm_does_not_affect_scroll_velocity);
+    /// // half speed at 120 bpm, the 2x SV brings it to 1x, and the 240 bpm section stays at 1x
+    /// assert_eq!(multipliers(&map), (0.5, vec![(500.0, 1.0)]));
+    ///
+    /// map.denormalize_svs();
+    /// assert!(!map.bpm_does_not_affect_scroll_velocity);
+    /// assert_eq!(multipliers(&map), (0.0, vec![(500.0, 2.0)]));
+    ///
+    /// // an infinite bpm scrolls as fast as normalizing allows
+    /// map.timing_points.push(TimingPoint { start_time: 2500.0, bpm: f64::INFINITY, time_signature: None, hidden: false });
+    /// map.normalize_svs();
+    /// assert_eq!(multipliers(&map), (0.5, vec![(500.0, 1.0), (2500.0, 128.0)]));
+    /// ```
+    pub fn normalize_svs(&mut self) {
+        if self.bpm_does_not_affect_scroll_velocity || self.timing_points.is_empty() {
+            return;
+        }
+        let base_bpm = self.get_common_bpm();
+        if is_unusable_bpm(base_bpm) {
+            logger::warning(&format!("Can't normalize SVs against a most common bpm of {base_bpm}, leaving them as they are"));
+            return;
         }
+        let Some(group) = self.timing_groups.get_mut(DEFAULT_TIMING_GROUP_ID) else {
+            return;
+        };
+        let (initial_scroll_velocity, scroll_velocities) = normalized_scroll_velocities(&self.timing_points, &group.scroll_velocities, base_bpm);
+        group.initial_scroll_velocity = initial_scroll_velocity;
+        group.scroll_velocities = scroll_velocities;
+        self.initial_scroll_velocity = initial_scroll_velocity;
+        self.bpm_does_not_affect_scroll_velocity = true;
+    }
+
+    pub fn denormalize_svs(&mut self) {
+        // the reverse of normalize_svs, for writing a chart in the legacy format; the initial SV isn't
+        // used by it, so it's 0 like Quaver writes it
+        if !self.bpm_does_not_affect_scroll_velocity || self.timing_points.is_empty() {
+            return;
+        }
+        let base_bpm = self.get_common_bpm();
+        if is_unusable_bpm(base_bpm) {
+            logger::warning(&format!("Can't denormalize SVs against a most common bpm of {base_bpm}, leaving them as they are"));
+            return;
+        }
+        let Some(group) = self.timing_groups.get_mut(DEFAULT_TIMING_GROUP_ID) else {
+            return;
+        };
+        group.scroll_velocities =
+            denormalized_scroll_velocities(&self.timing_points, group.initial_scroll_velocity, &group.scroll_velocities, base_bpm);
+        group.initial_scroll_velocity = 0.0;
+        self.initial_scroll_velocity = 0.0;
+        self.bpm_does_not_affect_scroll_velocity = false;
     }
 
     pub fn initialize_control_points(&mut self) {
@@ -147,16 +1115,18 @@ impl Map {
                 // distance between last and current SV, times the previous SV's multiplier
                 let distance = (current_sv.start_time - previous_sv.start_time) * multiplier;
 
-                position += (distance * TRACK_ROUNDING) as Position;
+                // float to int casts saturate, the sum has to as well
+                position = position.saturating_add((distance * TRACK_ROUNDING) as Position);
                 timing_group.scroll_velocities[index].cumulative_position = position;
             }
         }
     }
 
     pub fn initialize_hit_objects(&mut self, field_positions: &FieldPositions) -> Result<()> {
-        // initialize the hit objects
+        // initialize the hit objects and bucket them by timing group, so updates don't look groups up by name
         // https://github.com/Quaver/Quaver/blob/develop/Quaver.Shared/Screens/Gameplay/Rulesets/Keys/HitObjects/GameplayHitObjectKeys.cs#L161
-        for hit_object in &mut self.hit_objects {
+        self.group_notes = vec![Vec::new(); self.timing_groups.len()];
+        for (index, hit_object) in self.hit_objects.iter_mut().enumerate() {
             let Some(group_id) = hit_object.timing_group.as_ref() else {
                 logger::warning(&format!(
                     "Hit object at time {} has no timing group",
@@ -165,37 +1135,84 @@ impl Map {
                 continue;
             };
 
-            let Some(timing_group) = self.timing_groups.get_mut(group_id) else {
+            let Some(group_index) = self.timing_groups.index_of(group_id) else {
                 logger::warning(&format!(
                     "Timing group '{}' not found for hit object at time {}",
                     group_id, hit_object.start_time
                 ));
                 continue;
             };
+            self.group_notes[group_index].push(index);
+            let timing_group = &self.timing_groups[group_index];
 
             hit_object.start_position = timing_group.get_position_from_time(hit_object.start_time, false);
-            hit_object.start_position_tail = if hit_object.end_time.is_some() {
+            hit_object.start_position_tail = if let Some(end_time) = hit_object.end_time {
                 // if this is a long note, set the end position
-                timing_group.get_position_from_time(hit_object.end_time.unwrap(), false)
+                timing_group.get_position_from_time(end_time, false)
             } else {
                 // if not a long note, set end position to start position
                 hit_object.start_position
             };
-            hit_object.hit_position = field_positions.hit_position_y;
+            // LN heads and ends can have their own hit positions, set by the skin
+            if hit_object.end_time.is_some() {
+                hit_object.hit_position = field_positions.hold_hit_position_y;
+                hit_object.hold_end_hit_position = field_positions.hold_end_hit_position_y;
+            } else {
+                hit_object.hit_position = field_positions.hit_position_y;
+                hit_object.hold_end_hit_position = field_positions.hit_position_y;
+            }
         }
 
+        // like quaver, groups are drawn in the order the chart defines them (after the default group)
+        // so later ones are on top; a group's own layer, if it has one, goes before that
+        let default_group = self.timing_groups.index_of(DEFAULT_TIMING_GROUP_ID);
+        self.group_draw_order = default_group
+            .into_iter()
+            .chain((0..self.group_notes.len()).filter(|&index| Some(index) != default_group))
+            .collect();
+        self.group_draw_order.sort_by_key(|&index| self.timing_groups[index].z_layer.unwrap_or(0));
+
         Ok(())
     }
 
+    pub fn notes_in_draw_order(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        // hit object indices one timing group at a time, bottom group first, each group's in time order
+        self.group_draw_order.iter().flat_map(|&group| self.group_notes[group].iter().copied())
+    }
+
+    pub fn beat_phase(&self, time: Time) -> Option<BeatPosition> {
+        // measure and beat at a time; like timing lines, every timing point starts a new measure
+        let index = index_at_time(&self.timing_points, time).unwrap_or(0);
+        let timing_point = self.timing_points.get(index)?;
+        let measures_before: i64 = self.timing_points[..=index]
+            .windows(2)
+            .map(|pair| {
+                let measure_length = pair[0].ms_per_beat() * pair[0].beats_per_measure() as f64;
+                ((pair[1].start_time - pair[0].start_time) / measure_length).ceil().max(0.0) as i64
+            })
+            .sum();
+
+        let beats = (time - timing_point.start_time) / timing_point.ms_per_beat();
+        // charts round times to the microsecond, so a beat that's a hair early is still on the beat
+        let beats = if (beats - beats.round()).abs() * timing_point.ms_per_beat() < 0.001 { beats.round() } else { beats };
+        let whole_beats = beats.floor() as i64;
+        let beats_per_measure = timing_point.beats_per_measure();
+        Some(BeatPosition {
+            measure: measures_before + whole_beats.div_euclid(beats_per_measure) + 1,
+            beat: whole_beats.rem_euclid(beats_per_measure) + 1,
+            phase: beats - beats.floor(),
+        })
+    }
+
     pub fn initialize_timing_lines(&mut self, field_positions: &FieldPositions) -> Result<()> {
         // creates timing lines based on timing points' signatures and BPMs
         self.timing_lines.clear();
+        self.visible_timing_lines = 0..0;
 
+        // lines are placed along the default group, so a map without one (never initialized) has none
         let Some(tg) = self.timing_groups.get(DEFAULT_TIMING_GROUP_ID) else {
-            bail!(
-                "Default timing group '{}' not found",
-                DEFAULT_TIMING_GROUP_ID
-            );
+            logger::warning(&format!("Default timing group '{DEFAULT_TIMING_GROUP_ID}' not found, no timing lines"));
+            return Ok(());
         };
 
         // loop through timing points
@@ -205,8 +1222,7 @@ impl Map {
                 continue;
             }
 
-            // the "current time" that will be incrementing)
-            let mut current_time = self.timing_points[tp_index].start_time;
+            let start_time = self.timing_points[tp_index].start_time;
             let end_time = if tp_index + 1 < self.timing_points.len() {
                 // this isn't the last timing point
                 // end 1ms earlier to avoid possible timing line overlap
@@ -223,16 +1239,16 @@ impl Map {
                     .unwrap_or(TimeSignature::Quadruple) as u32,
             );
 
-            // "max possible sane value for timing lines" - quaver devs
-            const MAX_BPM: f64 = 9999.0;
-            let ms_per_beat = 60000f64 / MAX_BPM.min(self.timing_points[tp_index].bpm.abs());
-            // how many ms between measures/timing lines
-            let ms_increment = signature * ms_per_beat;
-            if ms_increment <= 0f64 {
-                continue; // no increment, skip this timing point
-            }
+            // how many ms between measures/timing lines, always positive
+            let ms_increment = signature * self.timing_points[tp_index].ms_per_beat();
 
-            while current_time < end_time {
+            // each line's time is counted from the timing point rather than added up line by line,
+            // so long sections don't drift by rounding error
+            for line_index in 0.. {
+                let current_time = start_time + f64::from(line_index) * ms_increment;
+                if current_time >= end_time {
+                    break;
+                }
                 // position for the timing line
                 let start_position = tg.get_position_from_time(current_time, false);
 
@@ -241,20 +1257,31 @@ impl Map {
                     start_time: current_time,
                     start_position,
                     current_track_position: 0,
+                    previous_track_position: 0,
                     hit_position: field_positions.timing_line_position_y,
                 });
-
-                // increment time for next timing line
-                current_time += ms_increment;
             }
         }
+        // lines are made in time order, but negative svs can move later ones back on the track
+        self.timing_lines_sorted = self
+            .timing_lines
+            .windows(2)
+            .all(|pair| pair[0].start_position <= pair[1].start_position);
 
         Ok(())
     }
 
     pub fn initialize_beat_snaps(&mut self) -> Result<()> {
         if self.timing_points.is_empty() {
-            bail!("Cannot initialize beat snaps without timing points");
+            // no grid to snap to, so every note keeps the 1/1 color
+            if !self.hit_objects.is_empty() {
+                logger::warning("No timing points, notes are drawn without beat snap colors");
+            }
+            for hit_object in &mut self.hit_objects {
+                hit_object.snap_index = 0;
+                hit_object.snap_error = 0.0;
+            }
+            return Ok(());
         }
 
         for hit_object in &mut self.hit_objects {
@@ -262,13 +1289,15 @@ impl Map {
             let timing_point = object_at_time(&self.timing_points, hit_object.start_time)
                 .unwrap_or(&self.timing_points[0]);
 
-            // get beat length (ms per beat)
-            let beat_length = 60000f64 / timing_point.bpm;
-            // calculate offset from timing point start time
+            // get beat length (ms per beat), negative bpms snap like positive ones
+            let beat_length = timing_point.ms_per_beat();
+            // calculate offset from timing point start time, negative before the first timing point
             let offset = hit_object.start_time - timing_point.start_time;
 
-            // calculate note's snap index
-            let index = (48.0 * offset / beat_length).round() as u32;
+            hit_object.snap_error = snap_error(offset, beat_length);
+
+            // calculate note's snap index, counted within its beat so notes before the timing point work too
+            let index = ((48.0 * offset / beat_length).round() as i64).rem_euclid(48) as u32;
 
             // defualt value; will be overwritten unless
             // not snapped to 1/16 or less, snap to 1/48
@@ -276,7 +1305,7 @@ impl Map {
 
             // loop through beat snaps to find the correct one
             for (i, snap_type) in BEAT_SNAPS.iter().enumerate() {
-                if index % snap_type.divisor == 0 {
+                if index.is_multiple_of(snap_type.divisor) {
                     // snap to this color
                     hit_object.snap_index = i;
                     break;
@@ -287,27 +1316,273 @@ impl Map {
         Ok(())
     }
 
-    pub fn sort(&mut self) {
-        // sort hit objects
-        sort_by_start_time(&mut self.hit_objects);
+    pub fn off_snap_notes(&self, tolerance: Time) -> Vec<OffSnapNote> {
+        // notes further than tolerance from their grid, needs initialize_beat_snaps
+        self.hit_objects
+            .iter()
+            .enumerate()
+            .filter(|(_, hit_object)| hit_object.snap_error > tolerance)
+            .map(|(index, hit_object)| OffSnapNote {
+                index,
+                timing_point: index_at_time(&self.timing_points, hit_object.start_time).unwrap_or(0),
+                error: hit_object.snap_error,
+            })
+            .collect()
+    }
 
-        // sort timing points
-        sort_by_start_time(&mut self.timing_points);
+    pub fn initialize_playable_length(&mut self) {
+        // the map ends once its last judgeable event (including LN ends) can no longer be hit
+        let last_event_time = self
+            .hit_objects
+            .iter()
+            .filter(|hit_object| hit_object.object_kind != ObjectKind::Fake)
+            .map(|hit_object| hit_object.end_time.unwrap_or(hit_object.start_time))
+            .reduce(f64::max);
 
-        // sort scroll velocities
-        for timing_group in self.timing_groups.values_mut() {
-            sort_by_start_time(&mut timing_group.scroll_velocities);
-        }
+        self.playable_length = match last_event_time {
+            Some(time) => {
+                // the lead-out stops at the end of the audio (when its length is known), but the last
+                // event is always judged, even in charts longer than their audio
+                let judged = time + self.judgement_windows.late(JudgementType::Miss);
+                let lead_out = judged + LEAD_OUT_TIME;
+                if self.length > 0.0 {
+                    lead_out.min(self.length.max(judged))
+                } else {
+                    lead_out
+                }
+            }
+            None => self.length, // nothing to judge, fall back to the audio length
+        };
+    }
 
-        // sort scroll speed factors
+    pub fn shift_times(&mut self, delta: Time, range: Option<(Time, Time)>, clamp: bool) -> Result<()> {
+        // moves every time in the chart (or only those starting within `range`) by `delta` ms
+        // computed positions are reset, so the map has to be sorted and initialized again afterwards
+        let in_range = |time: Time| range.is_none_or(|(start, end)| time >= start && time <= end);
+
+        // check for anything ending up before 0 first, so a failed shift leaves the map untouched
+        if !clamp {
+            let mut times = self
+                .hit_objects
+                .iter()
+                .map(|hit_object| hit_object.start_time)
+                .chain(self.timing_points.iter().map(|tp| tp.start_time))
+                .chain(self.scroll_velocities.iter().map(|sv| sv.start_time))
+                .chain(self.scroll_speed_factors.iter().map(|ssf| ssf.start_time))
+                .chain(self.timing_groups.values().flat_map(|group| {
+                    group
+                        .scroll_velocities
+                        .iter()
+                        .chain(&group.scroll_speed_factors)
+                        .map(|point| point.start_time)
+                }))
+                .chain(self.sound_effects.iter().map(|sound_effect| sound_effect.start_time))
+                .chain(self.bookmarks.iter().filter_map(|bookmark| bookmark.get("StartTime")?.as_f64()))
+                .chain(self.song_preview_time);
+            if let Some(time) = times.find(|&time| in_range(time) && time + delta < 0.0) {
+                bail!("Shifting by {delta} ms would move the object at {time} ms before 0");
+            }
+        }
+        let shift = |time: &mut Time| {
+            if in_range(*time) {
+                *time = (*time + delta).max(0.0);
+            }
+        };
+
+        for hit_object in &mut self.hit_objects {
+            if in_range(hit_object.start_time) {
+                // LN ends move with their heads
+                hit_object.start_time = (hit_object.start_time + delta).max(0.0);
+                if let Some(end_time) = hit_object.end_time.as_mut() {
+                    *end_time = (*end_time + delta).max(hit_object.start_time);
+                }
+            }
+            hit_object.start_position = 0;
+            hit_object.start_position_tail = 0;
+        }
+        for timing_point in &mut self.timing_points {
+            shift(&mut timing_point.start_time);
+        }
+        for sound_effect in &mut self.sound_effects {
+            shift(&mut sound_effect.start_time);
+        }
+        if let Some(song_preview_time) = self.song_preview_time.as_mut() {
+            shift(song_preview_time);
+        }
+        let groups = self.timing_groups.values_mut().flat_map(|group| {
+            group
+                .scroll_velocities
+                .iter_mut()
+                .chain(group.scroll_speed_factors.iter_mut())
+        });
+        for control_point in self
+            .scroll_velocities
+            .iter_mut()
+            .chain(self.scroll_speed_factors.iter_mut())
+            .chain(groups)
+        {
+            shift(&mut control_point.start_time);
+            control_point.cumulative_position = 0;
+        }
+        for bookmark in &mut self.bookmarks {
+            if let Some(start_time) = bookmark.get_mut("StartTime") {
+                if let Some(mut time) = start_time.as_f64() {
+                    shift(&mut time);
+                    *start_time = serde_yaml::Value::from(time);
+                }
+            }
+        }
+
+        self.timing_lines.clear();
+        Ok(())
+    }
+
+    pub fn scale_times(&mut self, rate: f64) -> Result<()> {
+        // rewrites the chart as if played at `rate` (times divided by it, bpms multiplied), so a dump
+        // lines up with rate-changed audio; like shift_times it has to be initialized again afterwards
+        if !(rate.is_finite() && rate > 0.0) {
+            bail!("Rate must be positive, got {rate}");
+        }
+        for hit_object in &mut self.hit_objects {
+            hit_object.start_time /= rate;
+            if let Some(end_time) = hit_object.end_time.as_mut() {
+                *end_time /= rate;
+            }
+            hit_object.start_position = 0;
+            hit_object.start_position_tail = 0;
+        }
+        for timing_point in &mut self.timing_points {
+            timing_point.start_time /= rate;
+            timing_point.bpm *= rate;
+        }
+        for sound_effect in &mut self.sound_effects {
+            sound_effect.start_time /= rate;
+        }
+        let groups = self.timing_groups.values_mut().flat_map(|group| {
+            group
+                .scroll_velocities
+                .iter_mut()
+                .chain(group.scroll_speed_factors.iter_mut())
+        });
+        for control_point in self
+            .scroll_velocities
+            .iter_mut()
+            .chain(self.scroll_speed_factors.iter_mut())
+            .chain(groups)
+        {
+            control_point.start_time /= rate;
+            control_point.cumulative_position = 0;
+        }
+        for bookmark in &mut self.bookmarks {
+            if let Some(start_time) = bookmark.get_mut("StartTime") {
+                if let Some(time) = start_time.as_f64() {
+                    *start_time = serde_yaml::Value::from(time / rate);
+                }
+            }
+        }
+        self.timing_lines.clear();
+        Ok(())
+    }
+
+    pub fn mirror_chart_lanes(&mut self) {
+        // flips the chart itself (unlike the mirror mod, which only flips how it's drawn and played)
+        let key_count = self.get_key_count(false);
+        for hit_object in &mut self.hit_objects {
+            // the scratch lane (if any) stays where it is
+            if hit_object.lane >= 1 && hit_object.lane <= key_count {
+                hit_object.lane = key_count + 1 - hit_object.lane;
+            }
+        }
+    }
+
+    pub fn remove_long_notes(&mut self) -> usize {
+        // turns every LN into a normal note at its head, returns how many there were
+        let mut count = 0;
+        for hit_object in &mut self.hit_objects {
+            if hit_object.end_time.take().is_some() {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    pub fn inspect_note(&self, index: usize) -> Option<NoteInspection> {
+        // gathers a note's computed data at the current time
+        let note = self.hit_objects.get(index)?;
+        let group_id = note.timing_group.as_deref().unwrap_or(DEFAULT_TIMING_GROUP_ID);
+        let timing_group = self.timing_groups.get(group_id)?;
+
+        // same group, but as if there were no SVs
+        let mut no_sv_group = timing_group.clone();
+        no_sv_group.current_track_position = no_sv_group.get_position_from_time(self.time, true);
+        let no_sv_position = no_sv_group.get_object_position(
+            note.hit_position,
+            no_sv_group.get_position_from_time(note.start_time, true),
+            self.mods.no_ssf,
+        );
+
+        Some(NoteInspection {
+            index,
+            start_time: note.start_time,
+            end_time: note.end_time,
+            lane: note.lane,
+            timing_group: group_id.to_string(),
+            snap_index: note.snap_index,
+            snap_error: note.snap_error,
+            start_position: note.start_position,
+            position: note.position,
+            sv_multiplier: object_at_time(&timing_group.scroll_velocities, note.start_time)
+                .map_or(timing_group.initial_scroll_velocity, |sv| sv.multiplier),
+            ssf_factor: timing_group.get_scroll_speed_factor_from_time(note.start_time),
+            no_sv_delta: note.position.saturating_sub(no_sv_position),
+        })
+    }
+
+    pub fn randomize_lanes(&mut self, rng: &mut Rng) -> Vec<i64> {
+        // random mod: shuffles which lane each lane's notes go to, returns the new lane for each old one
+        let key_count = self.get_key_count(false);
+        let mut lanes: Vec<i64> = (1..=key_count).collect();
+        for index in (1..lanes.len()).rev() {
+            // fisher-yates
+            let other = rng.below(index as u64 + 1) as usize;
+            lanes.swap(index, other);
+        }
+        for hit_object in &mut self.hit_objects {
+            // the scratch lane (if any) isn't shuffled
+            if hit_object.lane >= 1 && hit_object.lane <= key_count {
+                hit_object.lane = lanes[(hit_object.lane - 1) as usize];
+            }
+        }
+        lanes
+    }
+
+    pub fn sort(&mut self) {
+        // sort hit objects
+        sort_by_start_time(&mut self.hit_objects);
+        self.longest_long_note = self
+            .hit_objects
+            .iter()
+            .filter_map(|note| note.end_time.map(|end_time| end_time - note.start_time))
+            .fold(0.0, f64::max);
+
+        // sort timing points
+        sort_by_start_time(&mut self.timing_points);
+
+        // sort sound effects
+        sort_by_start_time(&mut self.sound_effects);
+
+        // sort scroll velocities
+        for timing_group in self.timing_groups.values_mut() {
+            sort_by_start_time(&mut timing_group.scroll_velocities);
+        }
+
+        // sort scroll speed factors
         for timing_group in self.timing_groups.values_mut() {
             sort_by_start_time(&mut timing_group.scroll_speed_factors);
         }
 
         // init judgements with new values
-        self.judgement_windows = JUDGEMENTS.iter()
-            .map(|j| (j.kind, j.window))
-            .collect();
+        self.judgement_windows = JudgementWindows::symmetric(JUDGEMENTS);
         self.judgement_counts = JUDGEMENTS.iter()
             .map(|j| (j.kind, 0))
             .collect();
@@ -317,115 +1592,423 @@ impl Map {
         // update current track position of hit objects in each timing group
         self.time = time;
         for timing_group in self.timing_groups.values_mut() {
-            timing_group.current_ssf_factor = timing_group.get_scroll_speed_factor_from_time(time);
+            timing_group.current_ssf_factor = if self.mods.no_ssf {
+                1.0 // pinned, so nothing reading the factor can pick up a stale SSF
+            } else {
+                timing_group.get_scroll_speed_factor_from_time(time)
+            };
             timing_group.current_track_position = timing_group.get_position_from_time(time, self.mods.no_sv);
         }
     }
 
     pub fn update_scroll_speed(&mut self) {
         // updates the scroll speed of all timing groups
-        let speed = SKIN.scroll_speed;
-        let rate_scaling = 1f64
-            + (self.rate - 1f64)
-            * (SKIN.normalize_scroll_velocity_by_rate_percentage as f64 / 100f64);
-        let adjusted_scroll_speed = (speed * rate_scaling).clamp(50.0, 1000.0);
+        let skin = skin();
+        let scroll_speed = Self::pixels_per_ms(skin.scroll_speed, self.rate, skin.normalize_scroll_velocity_by_rate_percentage);
+        for timing_group in self.timing_groups.values_mut() {
+            timing_group.scroll_speed = scroll_speed;
+            timing_group.downscroll = skin.downscroll;
+        }
+    }
+
+    pub fn pixels_per_ms(scroll_speed: f64, rate: f64, normalize_percentage: usize) -> f64 {
+        // how far notes move on screen (px) per ms of the chart at a scroll speed setting, see
+        // update_scroll_speed; faster rates cover the same distance in less real time
+        let rate_scaling = 1f64 + (rate - 1f64) * (normalize_percentage as f64 / 100f64);
+        let adjusted_scroll_speed = (scroll_speed * rate_scaling).clamp(MIN_SCROLL_SPEED, MAX_SCROLL_SPEED);
         let scaling_factor = 1920f64 / 1366f64; // quaver's scaling
 
-        let scroll_speed = (adjusted_scroll_speed / 10f64)
-            / (20f64 * self.rate)
-            * scaling_factor; // * base_to_virtual_ratio
+        (adjusted_scroll_speed / 10f64)
+            / (20f64 * rate)
+            * scaling_factor // * base_to_virtual_ratio
+    }
+
+    /// Returns a scroll speed setting for a chart this dense, rounded up to 10: fast enough that notes
+    /// at the shortest gap in a lane are `SUGGESTED_SPACING` note heights apart, and about
+    /// `SUGGESTED_NOTES_ON_SCREEN` notes of its busy parts fit a view this tall. A lane gap shorter
+    /// than the average gap in the busy parts is taken as a one-off and the average is used instead.
+    ///
+    /// ```
+    /// use vsrg_renderer::map::{Map, NoteDensity};
+    ///
+    /// // a relaxed chart is kept readable by its density: 6 notes a second, 400 ms between notes in a lane
+    /// let relaxed = NoteDensity { busy_nps: 6.0, shortest_lane_gap: Some(400.0) };
+    /// assert_eq!(Map::suggest_scroll_speed(&relaxed, 36.0, 1200.0, 1.0, 100), Some(90.0));
+    /// // a dense one needs to be faster
+    /// let dense = NoteDensity { busy_nps: 20.0, shortest_lane_gap: Some(150.0) };
+    /// assert_eq!(Map::suggest_scroll_speed(&dense, 36.0, 1200.0, 1.0, 100), Some(290.0));
+    /// // and faster again in a taller window
+    /// assert_eq!(Map::suggest_scroll_speed(&dense, 36.0, 1600.0, 1.0, 100), Some(380.0));
+    /// // tight jacks are spaced by the note height instead
+    /// let jacks = NoteDensity { busy_nps: 8.0, shortest_lane_gap: Some(125.0) };
+    /// let suggested = Map::suggest_scroll_speed(&jacks, 36.0, 1200.0, 1.0, 100).unwrap();
+    /// assert!(125.0 * Map::pixels_per_ms(suggested, 1.0, 100) >= 1.5 * 36.0 - 1.0);
+    /// // a 10 ms lane gap in a 10 notes a second chart is spaced like 100 ms
+    /// let one_off = NoteDensity { busy_nps: 10.0, shortest_lane_gap: Some(10.0) };
+    /// assert_eq!(
+    ///     Map::suggest_scroll_speed(&one_off, 36.0, 1200.0, 1.0, 100),
+    ///     Map::suggest_scroll_speed(&NoteDensity { shortest_lane_gap: Some(100.0), ..one_off }, 36.0, 1200.0, 1.0, 100),
+    /// );
+    /// // with nothing to space there's nothing to suggest
+    /// assert_eq!(Map::suggest_scroll_speed(&NoteDensity { busy_nps: 0.0, shortest_lane_gap: None }, 36.0, 1200.0, 1.0, 100), None);
+    /// ```
+    pub fn suggest_scroll_speed(density: &NoteDensity, note_height: f64, view_height: f64, rate: f64, normalize_percentage: usize) -> Option<f64> {
+        let busy_gap = (density.busy_nps > 0.0).then(|| 1000.0 / density.busy_nps);
+        let gap = match (density.shortest_lane_gap, busy_gap) {
+            (Some(lane_gap), Some(busy_gap)) => lane_gap.max(busy_gap),
+            (Some(gap), None) | (None, Some(gap)) => gap,
+            (None, None) => return None,
+        };
+        let spaced = SUGGESTED_SPACING * note_height / gap;
+        let on_screen = density.busy_nps * view_height / (1000.0 * SUGGESTED_NOTES_ON_SCREEN);
+        // the inverse of pixels_per_ms
+        let rate_scaling = 1f64 + (rate - 1f64) * (normalize_percentage as f64 / 100f64);
+        let adjusted_scroll_speed = spaced.max(on_screen) * 20f64 * rate * 10f64 / (1920f64 / 1366f64);
+        let scroll_speed = adjusted_scroll_speed.clamp(MIN_SCROLL_SPEED, MAX_SCROLL_SPEED) / rate_scaling;
+        Some((scroll_speed / 10.0).ceil() * 10.0)
+    }
 
-        for timing_group in self.timing_groups.values_mut() {
-            timing_group.scroll_speed = scroll_speed;
+    pub fn note_density(&self) -> Option<NoteDensity> {
+        // how packed the chart is, for suggesting a scroll speed; None without two notes to space
+        if self.hit_objects.len() < 2 {
+            return None;
+        }
+        let mut starts: Vec<Time> = self.hit_objects.iter().map(|note| note.start_time).collect();
+        starts.sort_by(f64::total_cmp);
+        let first = starts[0];
+        let mut per_second = vec![0usize; ((starts[starts.len() - 1] - first) / 1000.0) as usize + 1];
+        for start in &starts {
+            per_second[((start - first) / 1000.0) as usize] += 1;
+        }
+        per_second.sort_unstable();
+        let busy_nps = per_second[(per_second.len() * 95).div_ceil(100) - 1] as f64;
+
+        // from a note (or the end of a hold) to the next one in its lane
+        let mut lanes: HashMap<i64, Vec<(Time, Time)>> = HashMap::new();
+        for note in &self.hit_objects {
+            lanes.entry(note.lane).or_default().push((note.start_time, note.end_time.unwrap_or(note.start_time)));
         }
+        let shortest_lane_gap = lanes
+            .values_mut()
+            .flat_map(|notes| {
+                notes.sort_by(|a, b| a.0.total_cmp(&b.0));
+                notes.windows(2).map(|pair| pair[1].0 - pair[0].1).collect::<Vec<_>>()
+            })
+            .filter(|&gap| gap > 0.0)
+            .min_by(f64::total_cmp);
+        Some(NoteDensity { busy_nps, shortest_lane_gap })
     }
 
-    pub fn update_timing_lines(&mut self) -> Result<()> {
-        // updates the position of all timing lines
-        let Some(timing_group) = self.timing_groups.get_mut(DEFAULT_TIMING_GROUP_ID) else {
-            bail!("Default timing group '{}' not found", DEFAULT_TIMING_GROUP_ID);
+    pub fn update_timing_lines(&mut self, view_height: f64) -> Result<()> {
+        // updates the position of the timing lines on screen (view_height tall), the rest keep their old ones
+        // without the default group no lines were made, so there's nothing to move
+        let Some(timing_group) = self.timing_groups.get(DEFAULT_TIMING_GROUP_ID) else {
+            self.visible_timing_lines = 0..0;
+            return Ok(());
         };
-        for timing_line in &mut self.timing_lines {
-            // timing_line.current_track_position = (timing_group.current_track_position - timing_line.start_position);
-            timing_line.current_track_position = timing_group.get_object_position(
+        let no_sv = self.mods.no_sv;
+        let track_position = |timing_line: &TimingLine| {
+            if no_sv {
+                (timing_line.start_time * TRACK_ROUNDING) as Position
+            } else {
+                timing_line.start_position
+            }
+        };
+
+        let visible = match self.timing_lines.first() {
+            // without svs (or with only positive ones) lines are in track order, so the visible ones are a slice
+            Some(first) if no_sv || self.timing_lines_sorted => {
+                let (low, high) = timing_group.visible_track_range(
+                    first.hit_position,
+                    (-view_height - TIMING_LINE_MARGIN, TIMING_LINE_MARGIN),
+                    self.mods.no_ssf,
+                );
+                let start = self.timing_lines.partition_point(|line| track_position(line) < low);
+                let end = self.timing_lines.partition_point(|line| track_position(line) <= high);
+                start..end.max(start)
+            }
+            _ => 0..self.timing_lines.len(),
+        };
+
+        let motion_limit = self.motion_limit();
+        let previously_visible = take(&mut self.visible_timing_lines);
+        for index in visible.clone() {
+            let timing_line = &mut self.timing_lines[index];
+            let mut position = timing_group.get_object_position(
                 timing_line.hit_position,
-                if self.mods.no_sv {
-                    (timing_line.start_time * TRACK_ROUNDING) as Position
-                } else {
-                    timing_line.start_position
-                },
+                track_position(timing_line),
                 self.mods.no_ssf,
             );
+            if let Some((max_step, elapsed)) = motion_limit.filter(|_| previously_visible.contains(&index)) {
+                let time_left = (timing_line.start_time - self.time) / self.rate / 1000.0;
+                position = limit_motion(
+                    timing_line.current_track_position,
+                    position,
+                    timing_line.hit_position,
+                    max_step,
+                    elapsed,
+                    time_left,
+                );
+            }
+            // lines coming on screen have no previous position worth interpolating from
+            timing_line.previous_track_position = if previously_visible.contains(&index) {
+                timing_line.current_track_position
+            } else {
+                position
+            };
+            timing_line.current_track_position = position;
         }
+        self.visible_timing_lines = visible;
 
         Ok(())
     }
 
     pub fn update_hit_objects(&mut self) -> Result<()> {
-        // update the position of all hit objects
+        // update the position of all hit objects, one timing group at a time
         // https://github.com/Quaver/Quaver/blob/develop/Quaver.Shared/Screens/Gameplay/Rulesets/Keys/HitObjects/GameplayHitObjectKeys.cs#L387
-        for hit_object in &mut self.hit_objects {
-            let Some(group_id) = hit_object.timing_group.as_ref() else {
-                logger::warning(&format!(
-                    "Hit object at time {} has no timing group",
-                    hit_object.start_time
-                ));
-                continue;
-            };
-
-            let Some(timing_group) = self.timing_groups.get_mut(group_id) else {
-                logger::warning(&format!(
-                    "Timing group '{}' not found for hit object at time {}",
-                    group_id, hit_object.start_time
-                ));
-                continue;
-            };
+        let motion_limit = self.motion_limit();
+        let time_left = |time: Time| (time - self.time) / self.rate / 1000.0;
+        for (group_index, note_indices) in self.group_notes.iter().enumerate() {
+            let timing_group = &self.timing_groups[group_index];
+            let screen_scroll_speed = timing_group.screen_scroll_speed(self.mods.no_ssf);
+            for &note_index in note_indices {
+                let hit_object = &mut self.hit_objects[note_index];
+                while hit_object.previous_positions.len() < 50 {
+                    // ensure we have at least 10 previous positions
+                    hit_object
+                        .previous_positions
+                        .push_front(hit_object.position);
+                }
 
-            while hit_object.previous_positions.len() < 50 {
-                // ensure we have at least 10 previous positions
                 hit_object
                     .previous_positions
                     .push_front(hit_object.position);
+                if hit_object.previous_positions.len() > 50 {
+                    hit_object.previous_positions.pop_back();
+                }
+
+                hit_object.previous_position = hit_object.position;
+                hit_object.previous_position_tail = hit_object.position_tail;
+
+                hit_object.position = timing_group.object_position(
+                    hit_object.hit_position,
+                    if self.mods.no_sv {
+                        (hit_object.start_time * TRACK_ROUNDING) as Position
+                    } else {
+                        hit_object.start_position
+                    },
+                    screen_scroll_speed,
+                );
+
+                hit_object.position_tail = timing_group.object_position(
+                    hit_object.hold_end_hit_position,
+                    if self.mods.no_sv {
+                        (hit_object.end_time.unwrap_or(hit_object.start_time) * TRACK_ROUNDING) as Position
+                    } else {
+                        hit_object.start_position_tail
+                    },
+                    screen_scroll_speed,
+                );
+
+                // only what's drawn is limited, judging goes by time
+                if let Some((max_step, elapsed)) = motion_limit {
+                    hit_object.position = limit_motion(
+                        hit_object.previous_position,
+                        hit_object.position,
+                        hit_object.hit_position,
+                        max_step,
+                        elapsed,
+                        time_left(hit_object.start_time),
+                    );
+                    hit_object.position_tail = limit_motion(
+                        hit_object.previous_position_tail,
+                        hit_object.position_tail,
+                        hit_object.hold_end_hit_position,
+                        max_step,
+                        elapsed,
+                        time_left(hit_object.end_time.unwrap_or(hit_object.start_time)),
+                    );
+                }
+            }
+        }
+        self.last_position_update = Some(self.time);
+
+        Ok(())
+    }
+
+    pub fn hit_window_edges(&self, judgement: JudgementType, hit_position: f64) -> Option<(f64, f64)> {
+        // screen positions (like a note's, from the bottom) of a note that would get exactly the early
+        // and the late edge of a judgement if pressed now; goes through the default group's svs and
+        // current ssf and scroll speed like its notes do, so it follows them live
+        let timing_group = self.timing_groups.get(DEFAULT_TIMING_GROUP_ID)?;
+        let screen_position = |time: Time| {
+            let track_position = if self.mods.no_sv {
+                (time * TRACK_ROUNDING) as Position
+            } else {
+                timing_group.get_position_from_time(time, false)
+            };
+            timing_group.get_object_position(hit_position, track_position, self.mods.no_ssf) as f64
+        };
+        // pressing now is early for a note still to come and late for one already passed
+        Some((
+            screen_position(self.time + self.judgement_windows.early(judgement)),
+            screen_position(self.time - self.judgement_windows.late(judgement)),
+        ))
+    }
+
+    fn motion_limit(&self) -> Option<(f64, f64)> {
+        // in safe mode, the farthest (px) anything may move this update and the real seconds since the last one;
+        // none right after a seek or while paused, so those jump straight to the new positions
+        let speed = self.safe_mode_speed?;
+        let elapsed = (self.time - self.last_position_update?) / self.rate / 1000.0;
+        (elapsed > 0.0 && elapsed < SAFE_MODE_SEEK_GAP).then_some((speed * elapsed, elapsed))
+    }
+
+    pub fn update_judgements(&mut self) {
+        // judges notes that were passed without being pressed or released (autoplay, misses, held LN ends)
+        for index in 0..self.hit_objects.len() {
+            let note = &self.hit_objects[index];
+            // mines are only judged when pressed, fakes never
+            if note.is_finished() || !note.object_kind.is_normal() {
+                continue;
             }
+            let lane = note.lane;
 
-            hit_object
-                .previous_positions
-                .push_front(hit_object.position);
-            if hit_object.previous_positions.len() > 50 {
-                hit_object.previous_positions.pop_back();
+            if !note.hit {
+                if self.mods.autoplay && note.start_time <= self.time {
+                    // past receptors in autoplay mode = hit note perfectly
+                    // keysounds for autoplay are scheduled ahead of time instead
+                    self.press_lane(note.start_time, lane);
+                    continue;
+                }
+                if self.time - note.start_time > self.judgement_windows.late(JudgementType::Okay) {
+                    // the tail of a missed LN is never judged separately
+                    let offset = note.start_time - self.time;
+                    let kind = if note.end_time.is_some() { HitKind::LongNoteHead } else { HitKind::Note };
+                    self.hit_objects[index].hit = true;
+                    self.hit_objects[index].tail_hit = true;
+                    self.apply_judgement(JudgementType::Miss, kind, lane, self.time, offset);
+                }
+                continue;
             }
 
-            hit_object.position = timing_group.get_object_position(
-                hit_object.hit_position,
-                if self.mods.no_sv {
-                    (hit_object.start_time * TRACK_ROUNDING) as Position
-                } else {
-                    hit_object.start_position
-                },
-                self.mods.no_ssf,
-            );
+            // LN whose head was hit but whose end hasn't been judged yet
+            let end_time = note.end_time.unwrap_or(note.start_time);
+            let late_window = self.judgement_windows.late(JudgementType::Okay);
+            if self.held_notes.get(&lane) == Some(&index) {
+                if self.mods.autoplay && end_time <= self.time {
+                    self.release_lane(end_time, lane);
+                } else if self.time - end_time > late_window {
+                    // held too long past the end, judged as the latest possible release
+                    self.release_lane(end_time + late_window, lane);
+                }
+            } else if self.time - end_time > late_window {
+                // broken LN that was never regrabbed
+                self.hit_objects[index].tail_hit = true;
+                self.apply_judgement(JudgementType::Miss, HitKind::LongNoteEnd, lane, self.time, end_time - self.time);
+            }
+        }
+    }
 
-            hit_object.position_tail = timing_group.get_object_position(
-                hit_object.hit_position,
-                if self.mods.no_sv {
-                    (hit_object.end_time.unwrap_or(hit_object.start_time) * TRACK_ROUNDING) as Position
-                } else {
-                    hit_object.start_position_tail
-                },
-                self.mods.no_ssf,
-            );
+    pub fn accuracy(&self) -> f64 {
+        // accuracy of the judgements so far, scored by the active ruleset
+        self.ruleset.backend().accuracy(&self.hit_stats)
+    }
+
+    pub fn remaining_judgements(&self) -> usize {
+        // judgements still to come: the head and (for LNs) the end of every playable note not judged yet
+        self.hit_objects
+            .iter()
+            .filter(|note| note.object_kind.is_normal())
+            .map(|note| usize::from(!note.hit) + usize::from(note.end_time.is_some() && !note.tail_hit))
+            .sum()
+    }
+
+    pub fn remaining_notes(&self) -> usize {
+        // playable notes not fully judged yet
+        self.hit_objects.iter().filter(|note| note.object_kind.is_normal() && !note.is_finished()).count()
+    }
+
+    pub fn reset_judgements(&mut self) {
+        // clears all gameplay state so the map can be played again
+        for hit_object in &mut self.hit_objects {
+            hit_object.hit = false;
+            hit_object.tail_hit = false;
+        }
+        for count in self.judgement_counts.values_mut() {
+            *count = 0;
+        }
+        self.last_judgement = None;
+        self.splash = None;
+        self.lane_splashes.clear();
+        self.hit_stats.clear();
+        self.hold_events.clear();
+        self.combo = 0;
+        self.held_notes.clear();
+        self.released_tails.clear();
+        self.mash_detector.reset();
+        self.mixed_mods = false;
+        self.resumed = false;
+        self.autoplay_assisted = false;
+    }
+
+    pub fn toggle_autoplay(&mut self, time: Time) {
+        // autoplay only takes over notes from time on, ones already overdue are skipped instead of all being
+        // judged at once; an LN being held carries on under whichever control there is now
+        self.mods.autoplay = !self.mods.autoplay;
+        self.autoplay_assisted |= self.mods.autoplay || !self.hit_stats.is_empty();
+        if self.mods.autoplay {
+            for hit_object in &mut self.hit_objects {
+                if !hit_object.hit && hit_object.start_time < time {
+                    hit_object.hit = true;
+                    hit_object.tail_hit = true;
+                }
+            }
+        }
+    }
 
+    pub fn skip_to(&mut self, time: Time) {
+        // notes starting before time are done without being judged, as if they weren't part of the play
+        // (an LN started before it is skipped whole, it can't be held from the middle)
+        for hit_object in &mut self.hit_objects {
+            if hit_object.start_time < time {
+                hit_object.hit = true;
+                hit_object.tail_hit = true;
+            }
         }
+        self.resumed = true;
+    }
+
+    pub fn toggle_mirror(&mut self) {
+        // lanes are only mirrored when drawing and judging, so nothing has to be recalculated
+        self.mods.mirror = !self.mods.mirror;
+        self.mixed_mods |= !self.hit_stats.is_empty();
+        // a key held through the toggle still releases the LN it grabbed
+        let key_count = self.get_key_count(false);
+        mirror_lanes(&mut self.held_notes, key_count);
+        mirror_lanes(&mut self.released_tails, key_count);
+    }
 
+    pub fn toggle_no_sv(&mut self, field_positions: &FieldPositions) -> Result<()> {
+        // notes switch between sv and plain track positions, so they're placed again at the current time
+        self.mods.no_sv = !self.mods.no_sv;
+        self.mixed_mods |= !self.hit_stats.is_empty();
+        self.initialize_hit_objects(field_positions)?;
+        self.update_track_position(self.time);
+        self.update_hit_objects()?;
+        // nothing is interpolated (or trailed) from where it was before the toggle
+        for hit_object in &mut self.hit_objects {
+            hit_object.previous_position = hit_object.position;
+            hit_object.previous_position_tail = hit_object.position_tail;
+            hit_object.previous_positions.clear();
+        }
+        self.visible_timing_lines = 0..0;
         Ok(())
     }
 
     pub const fn get_key_count(&self, include_scratch: bool) -> i64 {
         // returns the number of keys in the map
-        let key_count = match self.mode {
-            GameMode::Keys4 => 4,
-            GameMode::Keys7 => 7,
-        };
+        let key_count = self.mode.key_count();
 
         if self.has_scratch_key && include_scratch {
             key_count + 1
@@ -434,57 +2017,341 @@ impl Map {
         }
     }
 
-    pub fn handle_gameplay_key_press(&mut self, time: Time, mut lane: i64) {
-        // handles when one of the gameplay keys is pressed
-        if self.mods.mirror {
-            lane = self.get_key_count(false) - lane; // mirror the lane
+    pub const fn is_scratch_lane(&self, lane: i64) -> bool {
+        // the +1 lane of a chart with a scratch key, which mirror and random leave where it is
+        self.has_scratch_key && lane == self.get_key_count(true)
+    }
+
+    const fn chart_lane(&self, key: i64) -> i64 {
+        // converts a 0-indexed gameplay key to the 1-indexed chart lane it plays
+        if self.mods.mirror && !self.is_scratch_lane(key + 1) {
+            self.get_key_count(false) - key
+        } else {
+            key + 1
+        }
+    }
+
+    fn lane_offset(&self, key: i64) -> f64 {
+        // keys without one (or past the configured ones) aren't adjusted
+        usize::try_from(key).ok().and_then(|key| self.lane_offsets.get(key)).copied().unwrap_or(0.0)
+    }
+
+    fn record_raw_offsets(&mut self, from: usize, lane_offset: f64) {
+        // the judgements made since `from` were for an input moved by lane_offset, so their raw offsets are
+        // what they'd have been without it
+        for hit_stat in &mut self.hit_stats[from..] {
+            hit_stat.raw_offset = hit_stat.offset - lane_offset;
+        }
+    }
+
+    pub fn handle_gameplay_key_press(&mut self, time: Time, key: i64) -> Option<usize> {
+        // handles when one of the gameplay keys is pressed, returns the note it hit if any
+        // the key's offset only moves what the press is judged at, nothing drawn
+        let lane_offset = self.lane_offset(key);
+        let time = time - lane_offset;
+        let judged = self.hit_stats.len();
+        let lane = self.chart_lane(key);
+        let notes_in_window = self.notes_in_lane_between(
+            lane,
+            time - self.mash_detector.window(),
+            time + self.judgement_windows.early(JudgementType::Miss),
+        );
+        self.mash_detector.record_press(lane, time, notes_in_window);
+        self.set_off_mines(time, lane);
+        let hit = self.press_lane(time, lane);
+        self.record_raw_offsets(judged, lane_offset);
+        hit
+    }
+
+    fn set_off_mines(&mut self, time: Time, lane: i64) {
+        // a press near a mine in its lane is a miss; each one only goes off once
+        let first = index_at_time(&self.hit_objects, time - MINE_WINDOW).unwrap_or(0);
+        let mines: Vec<usize> = (first..self.hit_objects.len())
+            .take_while(|&index| self.hit_objects[index].start_time <= time + MINE_WINDOW)
+            .filter(|&index| {
+                let note = &self.hit_objects[index];
+                note.object_kind == ObjectKind::Mine
+                    && note.lane == lane
+                    && !note.hit
+                    && (note.start_time - time).abs() <= MINE_WINDOW
+            })
+            .collect();
+        for index in mines {
+            self.hit_objects[index].hit = true;
+            self.hit_objects[index].tail_hit = true;
+            let distance = self.hit_objects[index].start_time - time;
+            self.apply_judgement(JudgementType::Miss, HitKind::Mine, lane, time, distance);
+        }
+    }
+
+    pub fn handle_gameplay_key_release(&mut self, time: Time, key: i64) {
+        // handles when one of the gameplay keys is released
+        let lane_offset = self.lane_offset(key);
+        let judged = self.hit_stats.len();
+        self.release_lane(time - lane_offset, self.chart_lane(key));
+        self.record_raw_offsets(judged, lane_offset);
+    }
+
+    fn notes_in_lane_between(&self, lane: i64, from: Time, to: Time) -> usize {
+        // number of notes in a lane starting within [from, to]
+        let first = index_at_time(&self.hit_objects, from).unwrap_or(0);
+        self.hit_objects[first..]
+            .iter()
+            .take_while(|note| note.start_time <= to)
+            .filter(|note| note.lane == lane && note.start_time >= from && note.object_kind.is_normal())
+            .count()
+    }
+
+    fn apply_judgement(&mut self, judgement_type: JudgementType, kind: HitKind, lane: i64, time: Time, distance: Time) {
+        // records a judgement in the counts, combo, splashes and hit stats
+        if judgement_type == JudgementType::Miss {
+            self.combo = 0; // reset combo on miss
+        } else {
+            self.combo += 1;
+        }
+
+        *self.judgement_counts.get_mut(&judgement_type).unwrap() += 1;
+        let offset_decimals = 0;
+        let offset = (distance * 10f64.powi(offset_decimals)).round() / 10f64.powi(offset_decimals);
+        self.last_judgement = Some((judgement_type, time, offset)); // update last judgement
+        // the splashes are only replaced by judgements that show them
+        if skin().splash_filter.shows(judgement_type) {
+            self.splash = Some((judgement_type, time, offset));
+            self.lane_splashes.insert(lane, (judgement_type, time)); // only the latest one per lane is shown
+        }
+        self.hit_stats.push(HitStat {
+            time,
+            offset: distance,
+            raw_offset: distance,
+            judgement: judgement_type,
+            kind,
+            lane,
+        });
+    }
+
+    fn press_candidate(&self, time: Time, lane: i64) -> Option<usize> {
+        // picks the note a press in this lane should judge:
+        // 1. un-started heads in the hit window, earliest first
+        // 2. broken LNs (released early, end not reached yet) to regrab, latest first
+        // the LN whose end was just released in this lane is only picked if nothing else is
+        if self.hit_objects.is_empty() {
+            return None;
         }
 
         // hit window in ms - early up to miss, late up to okay (anything past is auto miss)
         // earliest hit object in the window
-        let start_index = index_at_time(&self.hit_objects, time - self.judgement_windows[&JudgementType::Okay])
-            .unwrap_or(self.hit_objects.len() - 1);
+        let start_index = index_at_time(&self.hit_objects, time - self.judgement_windows.late(JudgementType::Okay))
+            .unwrap_or(0);
         // last hit object in the window
-        let end_index = index_at_time(&self.hit_objects, time + self.judgement_windows[&JudgementType::Miss])
-            .unwrap_or(self.hit_objects.len() - 1);
-
-        // loop through hit objects in the window
-        for index in start_index..=end_index {
-            let hit_object = &mut self.hit_objects[index];
-            if hit_object.lane != lane || hit_object.hit {
-                // not the right lane or already hit
-                continue;
+        let end_index = index_at_time(&self.hit_objects, time + self.judgement_windows.early(JudgementType::Miss))
+            .unwrap_or(0);
+
+        let mut heads: Vec<usize> = (start_index..=end_index)
+            .filter(|&index| {
+                let note = &self.hit_objects[index];
+                note.lane == lane
+                    && note.object_kind.is_normal()
+                    && !note.hit
+                    && self.judgement_windows.judge(note.start_time - time).is_some()
+            })
+            .collect();
+        if self.note_lock == NoteLock::Forgiving {
+            // the head closest to the press goes first; only heads already passed can be skipped,
+            // never one still coming, and a tie goes to the earlier head
+            let reachable = heads
+                .iter()
+                .position(|&index| self.hit_objects[index].start_time >= time)
+                .map_or(heads.len(), |position| position + 1);
+            let distance = |index: usize| (self.hit_objects[index].start_time - time).abs();
+            let closest = heads[..reachable]
+                .iter()
+                .enumerate()
+                .min_by(|(_, &a), (_, &b)| distance(a).total_cmp(&distance(b)))
+                .map(|(position, _)| position);
+            if let Some(closest) = closest {
+                heads[..=closest].rotate_right(1);
             }
+        }
+        // a broken LN started at most the longest LN's length ago
+        let regrab_index = index_at_time(&self.hit_objects, time - self.longest_long_note).unwrap_or(0);
+        let regrabs = (regrab_index..=end_index).rev().filter(|&index| {
+            let note = &self.hit_objects[index];
+            note.lane == lane
+                && note.hit
+                && !note.tail_hit
+                && note.end_time.is_some_and(|end_time| time < end_time)
+        });
+        let candidates: Vec<usize> = heads.into_iter().chain(regrabs).collect();
 
-            // double check if the hit object is within the hit window
-            let distance = hit_object.start_time - time;
-            if distance.abs() <= self.judgement_windows[&JudgementType::Miss] {
-                // calculate judgement type based on distance from start time
-                let judgement_type = match distance.abs() {
-                    d if d <= self.judgement_windows[&JudgementType::Marvelous] => JudgementType::Marvelous,
-                    d if d <= self.judgement_windows[&JudgementType::Perfect] => JudgementType::Perfect,
-                    d if d <= self.judgement_windows[&JudgementType::Great] => JudgementType::Great,
-                    d if d <= self.judgement_windows[&JudgementType::Good] => JudgementType::Good,
-                    d if d <= self.judgement_windows[&JudgementType::Okay] => JudgementType::Okay,
-                    _ => JudgementType::Miss,
-                };
-
-                if judgement_type == JudgementType::Miss {
-                    self.combo = 0; // reset combo on miss
-                } else {
-                    self.combo += 1;
-                }
+        let released_tail = self.released_tails.get(&lane).copied();
+        let owns_released_tail = |index: &usize| {
+            released_tail
+                .zip(self.hit_objects[*index].end_time)
+                .is_some_and(|(released, end_time)| (released - end_time).abs() <= CHAIN_EPSILON)
+        };
+        candidates
+            .iter()
+            .find(|index| !owns_released_tail(index))
+            .or_else(|| candidates.first())
+            .copied()
+    }
+
+    fn press_lane(&mut self, time: Time, lane: i64) -> Option<usize> {
+        // judges a press, returns the note whose head was hit (not counting misses and regrabs)
+        if self.held_notes.contains_key(&lane) {
+            return None; // already holding something in this lane
+        }
+        let index = self.press_candidate(time, lane)?;
+
+        let hit_object = &self.hit_objects[index];
+        if hit_object.hit {
+            // regrab of a broken LN, its end is judged on release like normal
+            self.held_notes.insert(lane, index);
+            self.hold_events.push(HoldEvent { time, note: index, kind: HoldEventKind::Regrab });
+            return None;
+        }
 
-                // increment judgement count
-                *self.judgement_counts.get_mut(&judgement_type).unwrap() += 1;
-                hit_object.hit = true; // mark as hit
-                let offset_decimals = 0;
-                let offset = (distance * 10f64.powi(offset_decimals)).round() / 10f64.powi(offset_decimals);
-                self.last_judgement = Some((judgement_type, time, offset)); // update last judgement
-                return; // exit after hitting the first valid hit object
+        let distance = hit_object.start_time - time;
+        // None is a ghost tap
+        let judgement_type = self.judgement_windows.judge(distance)?;
+        self.hit_objects[index].hit = true; // mark as hit
+        let kind = if self.hit_objects[index].end_time.is_some() {
+            if judgement_type == JudgementType::Miss {
+                // pressed way too early, the whole LN is gone
+                self.hit_objects[index].tail_hit = true;
+            } else {
+                self.held_notes.insert(lane, index);
+                self.hold_events.push(HoldEvent { time, note: index, kind: HoldEventKind::Grab });
             }
+            HitKind::LongNoteHead
+        } else {
+            HitKind::Note
+        };
+        self.apply_judgement(judgement_type, kind, lane, time, distance);
+        (judgement_type != JudgementType::Miss).then_some(index)
+    }
+
+    fn release_lane(&mut self, time: Time, lane: i64) {
+        // releases only ever judge the LN currently held in that lane
+        let Some(index) = self.held_notes.remove(&lane) else {
+            return;
+        };
+        let hit_object = &self.hit_objects[index];
+        let end_time = hit_object.end_time.unwrap_or(hit_object.start_time);
+        self.released_tails.insert(lane, end_time);
+
+        let distance = end_time - time;
+        if distance > self.judgement_windows.early(JudgementType::Okay) {
+            // let go too early: the LN is broken and can still be regrabbed before its end
+            self.combo = 0;
+            self.hold_events.push(HoldEvent { time, note: index, kind: HoldEventKind::Break });
+            return;
+        }
+        self.hit_objects[index].tail_hit = true;
+        self.hold_events.push(HoldEvent { time, note: index, kind: HoldEventKind::Release });
+        let judgement_type = self.judgement_windows.judge(distance).unwrap_or(JudgementType::Okay);
+        self.apply_judgement(judgement_type, HitKind::LongNoteEnd, lane, time, distance);
+    }
+}
+
+// computed data for one note, for debugging SV maps
+#[derive(Debug, Clone)]
+pub struct NoteInspection {
+    pub index: usize,
+    pub start_time: Time,
+    pub end_time: Option<Time>,
+    pub lane: i64,
+    pub timing_group: String,
+    pub snap_index: usize,
+    pub snap_error: Time,       // distance (ms) from the 1/48 grid
+    pub start_position: Position,
+    pub position: Position,     // current screen offset
+    pub sv_multiplier: f64,     // active SV at the note's start time
+    pub ssf_factor: f64,        // SSF factor at the note's start time
+    pub no_sv_delta: Position,  // current position minus where it would be with no SVs
+}
+
+impl NoteInspection {
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("note #{} (lane {})", self.index, self.lane),
+            format!("start_time: {:.2}", self.start_time),
+        ];
+        if let Some(end_time) = self.end_time {
+            lines.push(format!("end_time: {end_time:.2}"));
+        }
+        lines.extend([
+            format!("timing group: {}", self.timing_group),
+            format!("snap index: {} (1/{}), off by {:.2} ms", self.snap_index, 48 / BEAT_SNAPS[self.snap_index].divisor, self.snap_error),
+            format!("start_position: {}", self.start_position),
+            format!("position: {}", self.position),
+            format!("sv: {:.3}x, ssf: {:.3}x", self.sv_multiplier, self.ssf_factor),
+            format!("no sv delta: {}", self.no_sv_delta),
+        ]);
+        lines
+    }
+}
+
+// which chart a diff entry belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffSide {
+    Main,    // note only exists in the played chart
+    Compare, // note only exists in the comparison chart
+}
+
+#[derive(Debug, Clone)]
+pub struct DiffEntry {
+    pub side: DiffSide,
+    pub index: usize, // index into the side's hit objects
+    pub start_time: Time,
+}
+
+// returns the indexes of notes in `a` without a note in the same lane within ±tolerance ms in `b`
+fn unmatched_notes(a: &[HitObject], b: &[HitObject], tolerance: Time) -> Vec<usize> {
+    let mut unmatched = Vec::new();
+    for (index, note) in a.iter().enumerate() {
+        // first candidate is the last note starting before the tolerance window
+        let first = index_at_time(b, note.start_time - tolerance).unwrap_or(0);
+        let matched = b[first..]
+            .iter()
+            .take_while(|other| other.start_time <= note.start_time + tolerance)
+            .any(|other| {
+                other.lane == note.lane && (other.start_time - note.start_time).abs() <= tolerance
+            });
+        if !matched {
+            unmatched.push(index);
         }
     }
+    unmatched
+}
+
+pub fn diff_charts(a: &Map, b: &Map, tolerance: Time) -> Vec<DiffEntry> {
+    // finds notes present in one chart but not the other (hit objects must be sorted)
+    let mut entries: Vec<DiffEntry> = unmatched_notes(&a.hit_objects, &b.hit_objects, tolerance)
+        .into_iter()
+        .map(|index| DiffEntry {
+            side: DiffSide::Main,
+            index,
+            start_time: a.hit_objects[index].start_time,
+        })
+        .collect();
+    entries.extend(
+        unmatched_notes(&b.hit_objects, &a.hit_objects, tolerance)
+            .into_iter()
+            .map(|index| DiffEntry {
+                side: DiffSide::Compare,
+                index,
+                start_time: b.hit_objects[index].start_time,
+            }),
+    );
+    sort_by_start_time(&mut entries);
+    entries
+}
+
+impl HasStartTime for DiffEntry {
+    fn start_time(&self) -> Time {
+        self.start_time
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -496,6 +2363,8 @@ pub struct TimingLine {
     #[serde(default)]
     pub current_track_position: Position, // track position; >0 = hasnt passed receptors
     #[serde(skip)]
+    pub previous_track_position: Position, // track position from the previous update, for interpolation
+    #[serde(skip)]
     pub hit_position: f64, // position of the timing line on the screen
 }
 
@@ -507,11 +2376,33 @@ pub struct TimingPoint {
     #[serde(default)]
     pub start_time: Time, // start time (ms)
     pub bpm: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub time_signature: Option<TimeSignature>,
     #[serde(default)]
     pub hidden: bool, // show timing lines
 }
 
+impl TimingPoint {
+    pub fn beats_per_measure(&self) -> i64 {
+        self.time_signature.map_or(4, |signature| signature as i64)
+    }
+
+    pub fn ms_per_beat(&self) -> f64 {
+        // beat length everything counts beats in: negative bpms count like positive ones, and the bpm is
+        // kept within MIN_BPM-MAX_BPM, so 0 (or nan) gives 60000 ms beats and infinite ones 6 ms
+        let bpm = if self.bpm.is_nan() { MIN_BPM } else { self.bpm.abs().clamp(MIN_BPM, MAX_BPM) };
+        60000.0 / bpm
+    }
+}
+
+// where a time falls in the chart's measures, both 1-based
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeatPosition {
+    pub measure: i64,
+    pub beat: i64,
+    pub phase: f64, // 0-1 progress through the beat
+}
+
 impl HasStartTime for TimingPoint {
     fn start_time(&self) -> Time {
         self.start_time
@@ -524,36 +2415,80 @@ pub struct ControlPoint {
     // represents either an SV or SSF point
     #[serde(default)]
     pub start_time: Time,
+    // quaver leaves out values that are their type's default when saving, so a 0x point is written
+    // without a multiplier; one that's missing has to stay 0 (not 1 like InitialScrollVelocity)
     #[serde(default)]
     pub multiplier: f64,
-    #[serde(skip_deserializing)]
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
     pub length: Option<Time>, // none if last point
-    #[serde(skip_deserializing)]
+    #[serde(skip)]
     pub cumulative_position: Position, // cumulative distance from the start of the map
 }
 
-impl HasStartTime for ControlPoint {
-    fn start_time(&self) -> Time {
-        self.start_time
+impl ControlPoint {
+    fn chart_clone(&self) -> Self {
+        Self {
+            start_time: self.start_time,
+            multiplier: self.multiplier,
+            length: None,
+            cumulative_position: 0,
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl HasStartTime for ControlPoint {
+    fn start_time(&self) -> Time {
+        self.start_time
+    }
+}
+
+// what a hit object does when played; quaver has only normal ones, the others come from converted
+// charts (or an ObjectKind field in the .qua)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ObjectKind {
+    #[default]
+    Normal,
+    Mine, // not to be pressed: a press near it is a miss, leaving it alone is nothing
+    Fake, // drawn like a note but never judged
+}
+
+impl ObjectKind {
+    pub fn is_normal(&self) -> bool {
+        *self == Self::Normal
+    }
+}
+
+// a note as generated charts give it, see Map::from_simple_notes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimpleNote {
+    pub start_time: Time,
+    pub lane: i64,              // 1-indexed
+    pub end_time: Option<Time>, // LNs end
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "PascalCase")]
 pub struct HitObject {
     // a note
     #[serde(default)]
     pub start_time: Time,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub end_time: Option<Time>, // if Some, then its an LN
     pub lane: i64,
     pub key_sounds: Vec<KeySound>, // key sounds to play when this object is hit
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub timing_group: Option<String>,
+    #[serde(default, skip_serializing_if = "ObjectKind::is_normal")]
+    pub object_kind: ObjectKind,
     #[serde(skip)]
     pub snap_index: usize, // index for snap color
     #[serde(skip)]
+    pub snap_error: Time, // distance (ms) from the nearest 1/48 grid position of its timing point
+    #[serde(skip)]
     pub hit_position: f64, // where the note is "hit", calculated from hit body height and hit position offset
     #[serde(skip)]
+    pub hold_end_hit_position: f64, // where an LN's end is "hit", same as hit_position for normal notes
+    #[serde(skip)]
     pub start_position: Position, // track position at start_time (in timing group)
     #[serde(skip)]
     pub start_position_tail: Position, // track position at start_time for LN end
@@ -562,14 +2497,55 @@ pub struct HitObject {
     #[serde(skip)]
     pub position_tail: Position, // live position of the LN end
     #[serde(skip)]
+    pub previous_position: Position, // position from the previous update, for interpolation
+    #[serde(skip)]
+    pub previous_position_tail: Position, // LN end position from the previous update
+    #[serde(skip)]
     pub previous_positions: VecDeque<Position>, // previous positions, used for rendering effects
     #[serde(skip)]
-    pub hit: bool, // whether this object has been hit
+    pub hit: bool, // whether this object has been hit (the head, for LNs)
+    #[serde(skip)]
+    pub tail_hit: bool, // whether an LN's end has been judged
+    #[serde(skip)]
+    pub unknown_timing_group: Option<String>, // group id from the chart that doesn't exist, the note uses the default group
 }
 
 // public virtual float CurrentLongNoteBodySize => (LatestHeldPosition - EarliestHeldPosition) *
 //     TimingGroupController.ScrollSpeed / HitObjectManagerKeys.TrackRounding;
 
+impl HitObject {
+    fn chart_clone(&self) -> Self {
+        // objects without a group get the default one on initialization, so undo that
+        Self {
+            start_time: self.start_time,
+            end_time: self.end_time,
+            lane: self.lane,
+            key_sounds: self.key_sounds.clone(),
+            object_kind: self.object_kind,
+            // and notes moved out of an unknown group get their original id back
+            timing_group: self
+                .unknown_timing_group
+                .clone()
+                .or_else(|| self.timing_group.clone().filter(|group| group != DEFAULT_TIMING_GROUP_ID)),
+            ..Self::default()
+        }
+    }
+
+    // whether the object needs no more judging
+    pub const fn is_finished(&self) -> bool {
+        self.hit && (self.end_time.is_none() || self.tail_hit)
+    }
+
+    // screen offset between the previous and current update (alpha 1.0 = current)
+    pub fn interpolated_position(&self, alpha: f64) -> f64 {
+        lerp(self.previous_position as f64, self.position as f64, alpha)
+    }
+
+    pub fn interpolated_position_tail(&self, alpha: f64) -> f64 {
+        lerp(self.previous_position_tail as f64, self.position_tail as f64, alpha)
+    }
+}
+
 impl HasStartTime for HitObject {
     fn start_time(&self) -> Time {
         self.start_time
@@ -583,6 +2559,30 @@ pub struct KeySound {
     pub volume: i32, // the volume of the sound sample (defaults to 100)
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct CustomAudioSample {
+    pub path: String, // file name of the sample, in the map's folder
+    #[serde(default)]
+    pub unaffected_by_rate: bool, // samples always play at their own speed here, so this is only kept
+}
+
+// a sample the chart plays at a time, whether or not there's a note there
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct SoundEffect {
+    pub start_time: Time,
+    pub sample: i32, // the one-based index of the sound sample in the CustomAudioSamples array
+    #[serde(default = "full_volume")]
+    pub volume: i32, // the volume of the sound sample, 0-100
+}
+
+impl HasStartTime for SoundEffect {
+    fn start_time(&self) -> Time {
+        self.start_time
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "PascalCase")]
 pub struct TimingGroup {
@@ -593,24 +2593,43 @@ pub struct TimingGroup {
     pub scroll_velocities: Vec<ControlPoint>,
     #[serde(default)]
     pub scroll_speed_factors: Vec<ControlPoint>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub color_rgb: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub z_layer: Option<i32>, // groups with a higher layer are drawn on top, the default group is on 0
     // info for playback
     #[serde(skip)]
     pub current_track_position: Position, // current playback position
-    #[serde(default = "one_f64")]
+    #[serde(default = "one_f64", skip_serializing)]
     pub current_ssf_factor: f64, // current SSF multiplier
     #[serde(skip)]
     pub scroll_speed: f64, // speed at which objects travel across the screen
+    #[serde(skip)]
+    pub downscroll: bool, // the skin's scroll direction, kept with scroll_speed so positions don't read the skin
 }
 
 impl TimingGroup {
+    fn chart_clone(&self) -> Self {
+        Self {
+            initial_scroll_velocity: self.initial_scroll_velocity,
+            scroll_velocities: self.scroll_velocities.iter().map(ControlPoint::chart_clone).collect(),
+            scroll_speed_factors: self.scroll_speed_factors.iter().map(ControlPoint::chart_clone).collect(),
+            color_rgb: self.color_rgb.clone(),
+            z_layer: self.z_layer,
+            ..Self::default()
+        }
+    }
+
     pub fn get_scroll_speed_factor_from_time(&self, time: Time) -> f64 {
         // gets the SSF multiplier at a time, with linear interpolation
+        if self.scroll_speed_factors.is_empty() {
+            return 1.0; // no SSFs, so no effect applied
+        }
         let ssf_index = index_at_time(&self.scroll_speed_factors, time);
 
         match ssf_index {
             None => {
-                // before first SSF point or no SSFs, so no effect applied
+                // before first SSF point, so no effect applied
                 1.0
             }
             Some(index) => {
@@ -621,6 +2640,10 @@ impl TimingGroup {
                 }
 
                 let next_ssf = &self.scroll_speed_factors[index + 1];
+                if next_ssf.start_time <= ssf.start_time {
+                    // stacked points, nothing to interpolate over
+                    return next_ssf.multiplier;
+                }
                 // lerp between this and next point based on time between
                 lerp(
                     ssf.multiplier,
@@ -631,36 +2654,57 @@ impl TimingGroup {
         }
     }
 
+    /// Returns the group's track position at `time` (ms times the SV, times `TRACK_ROUNDING`), or
+    /// just the time's when `ignore_sv`. The map has to be initialized first, which doesn't need a window.
+    ///
+    /// ```
+    /// use std::path::Path;
+    /// use vsrg_renderer::{initialize_map, map::Map, render::set_reference_positions, utils::DEFAULT_TIMING_GROUP_ID};
+    ///
+    /// // 1x, then -1.5x from 600 ms
+    /// let mut map = Map::from_file(Path::new("golden/charts/sv_reversal.qua"))?;
+    /// initialize_map(&mut map, &set_reference_positions(None))?;
+    /// let group = map.timing_groups.get(DEFAULT_TIMING_GROUP_ID).unwrap();
+    /// assert_eq!(group.get_position_from_time(600.0, false), 60_000);
+    /// // going back down the track
+    /// assert_eq!(group.get_position_from_time(700.0, false), 45_000);
+    /// assert_eq!(group.get_position_from_time(700.0, true), 70_000);
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
     pub fn get_position_from_time(&self, time: Time, ignore_sv: bool) -> Position {
-        // calculates the timing group's track position with time and SV
         if ignore_sv {
             return (time * TRACK_ROUNDING) as Position;
         }
+        if self.scroll_velocities.is_empty() {
+            // no SVs (e.g. an SSF-only group), so the whole map uses the initial scroll velocity
+            return (time * self.initial_scroll_velocity * TRACK_ROUNDING) as Position;
+        }
 
         let sv_index = index_at_time(&self.scroll_velocities, time);
 
         match sv_index {
             None => {
-                // before first SV point or no SVs, so use initial scroll velocity
+                // before first SV point, so use initial scroll velocity
                 (time * self.initial_scroll_velocity * TRACK_ROUNDING) as Position
             }
             Some(index) => {
                 // get the track position at the start of the current SV point
-                let mut current_position = self.scroll_velocities[index].cumulative_position;
+                let current_position = self.scroll_velocities[index].cumulative_position;
 
                 // add the distance between the start of the current SV point and the time
-                current_position += ((time - self.scroll_velocities[index].start_time)
-                    * self.scroll_velocities[index].multiplier
-                    * TRACK_ROUNDING) as Position;
-                current_position
+                current_position.saturating_add(
+                    ((time - self.scroll_velocities[index].start_time)
+                        * self.scroll_velocities[index].multiplier
+                        * TRACK_ROUNDING) as Position,
+                )
             }
         }
     }
 
-    pub fn get_object_position(&self, hit_position: f64, initial_position: Position, ignore_ssf: bool) -> Position {
-        // calculates the position of a hit object with a position offset
+    fn screen_scroll_speed(&self, ignore_ssf: bool) -> f64 {
+        // screen distance per track distance (times TRACK_ROUNDING), signed by scroll direction
         // note: signs were swapped in quaver?
-        let mut scroll_speed = if SKIN.downscroll {
+        let mut scroll_speed = if self.downscroll {
             -self.scroll_speed
         } else {
             self.scroll_speed
@@ -670,11 +2714,35 @@ impl TimingGroup {
             // apply SSF factor
             scroll_speed *= self.current_ssf_factor;
         }
+        scroll_speed
+    }
+
+    pub fn get_object_position(&self, hit_position: f64, initial_position: Position, ignore_ssf: bool) -> Position {
+        // calculates the position of a hit object with a position offset
+        self.object_position(hit_position, initial_position, self.screen_scroll_speed(ignore_ssf))
+    }
 
+    fn object_position(&self, hit_position: f64, initial_position: Position, screen_scroll_speed: f64) -> Position {
+        // get_object_position with the screen scroll speed worked out once for a whole group of notes
         let distance = (initial_position as f64) - (self.current_track_position as f64);
-        let position = hit_position + (distance * scroll_speed / TRACK_ROUNDING);
+        let position = hit_position + (distance * screen_scroll_speed / TRACK_ROUNDING);
         position as Position
     }
+
+    pub fn visible_track_range(&self, hit_position: f64, (top, bottom): (f64, f64), ignore_ssf: bool) -> (Position, Position) {
+        // track positions that get_object_position puts between two screen positions (inclusive, in either order)
+        let scroll_speed = self.screen_scroll_speed(ignore_ssf);
+        if scroll_speed.abs() < f64::EPSILON {
+            // nothing moves, everything is at the hit position
+            return (Position::MIN, Position::MAX);
+        }
+        let track_position = |screen_position: f64| {
+            let distance = (screen_position - hit_position) * TRACK_ROUNDING / scroll_speed;
+            (self.current_track_position as f64 + distance) as Position
+        };
+        let (a, b) = (track_position(top), track_position(bottom));
+        (a.min(b), a.max(b))
+    }
 }
 
 impl Default for TimingGroup {
@@ -684,9 +2752,11 @@ impl Default for TimingGroup {
             scroll_velocities: Vec::new(),
             scroll_speed_factors: Vec::new(),
             color_rgb: None,
+            z_layer: None,
             current_track_position: 0,
             current_ssf_factor: 1.0,
             scroll_speed: 0.0,
+            downscroll: DEFAULT_SKIN.downscroll,
         }
     }
 }
@@ -698,6 +2768,1785 @@ pub enum TimeSignature {
     Triple = 3,
 }
 
+// timing groups by id, kept in a vec so per-frame code can use indices instead of looking groups up by name
+#[derive(Debug, Clone, Default)]
+pub struct TimingGroups {
+    groups: Vec<TimingGroup>,
+    ids: Vec<String>,                // id of each group, same order as groups
+    indices: HashMap<String, usize>, // id -> index
+}
+
+impl TimingGroups {
+    pub fn index_of(&self, id: &str) -> Option<usize> {
+        self.indices.get(id).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&TimingGroup> {
+        self.index_of(id).map(|index| &self.groups[index])
+    }
+
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut TimingGroup> {
+        self.index_of(id).map(|index| &mut self.groups[index])
+    }
+
+    pub fn insert(&mut self, id: String, group: TimingGroup) {
+        // replaces the group if the id already exists, keeping its index
+        if let Some(index) = self.index_of(&id) {
+            self.groups[index] = group;
+            return;
+        }
+        self.indices.insert(id.clone(), self.groups.len());
+        self.ids.push(id);
+        self.groups.push(group);
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<TimingGroup> {
+        // later groups shift down, so indices taken before this are invalid
+        let index = self.indices.remove(id)?;
+        self.ids.remove(index);
+        for later_index in self.indices.values_mut() {
+            if *later_index > index {
+                *later_index -= 1;
+            }
+        }
+        Some(self.groups.remove(index))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &TimingGroup> {
+        self.groups.iter()
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut TimingGroup> {
+        self.groups.iter_mut()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &TimingGroup)> {
+        self.ids.iter().zip(&self.groups)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut TimingGroup)> {
+        self.ids.iter().zip(&mut self.groups)
+    }
+}
+
+impl Index<usize> for TimingGroups {
+    type Output = TimingGroup;
+
+    fn index(&self, index: usize) -> &TimingGroup {
+        &self.groups[index]
+    }
+}
+
+impl FromIterator<(String, TimingGroup)> for TimingGroups {
+    fn from_iter<I: IntoIterator<Item = (String, TimingGroup)>>(iter: I) -> Self {
+        let mut groups = Self::default();
+        for (id, group) in iter {
+            groups.insert(id, group);
+        }
+        groups
+    }
+}
+
+impl Serialize for TimingGroups {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // in the order they were defined, which is the order they're drawn in
+        serializer.collect_map(self.iter())
+    }
+}
+
+struct TimingGroupsVisitor;
+
+impl<'de> Visitor<'de> for TimingGroupsVisitor {
+    type Value = TimingGroups;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map of timing group ids to timing groups")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<TimingGroups, A::Error> {
+        let mut groups = TimingGroups::default();
+        while let Some((id, group)) = access.next_entry::<String, TimingGroup>()? {
+            if groups.index_of(&id).is_some() {
+                return Err(de::Error::custom(format!("timing group '{id}' is defined twice")));
+            }
+            groups.insert(id, group);
+        }
+        Ok(groups)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimingGroups {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // in file order, so group indices are the same every load and follow the chart's draw order
+        deserializer.deserialize_map(TimingGroupsVisitor)
+    }
+}
+
 pub const fn one_f64() -> f64 {
     1.0
 }
+
+pub const fn full_volume() -> i32 {
+    100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::initialize_map;
+    use crate::render::{set_reference_positions, PlayfieldLayout};
+    use crate::utils::{set_skin, Skin};
+
+    fn initialized(hit_objects: Vec<HitObject>) -> Map {
+        let mut map = Map { mode: GameMode::Keys4, hit_objects, ..Map::default() };
+        map.timing_points.push(TimingPoint { start_time: 0.0, bpm: 60.0, time_signature: None, hidden: false });
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        map
+    }
+
+    fn long_note(start_time: Time, end_time: Time, lane: i64) -> HitObject {
+        HitObject { start_time, end_time: Some(end_time), lane, ..HitObject::default() }
+    }
+
+    fn chain() -> Map {
+        // three 1/4 LNs in lane 1 at 60 BPM, each ending where the next starts
+        initialized(vec![long_note(1000.0, 1250.0, 1), long_note(1250.0, 1500.0, 1), long_note(1500.0, 1750.0, 1)])
+    }
+
+    fn hold_kinds(map: &Map) -> Vec<(usize, HoldEventKind)> {
+        map.hold_events.iter().map(|event| (event.note, event.kind)).collect()
+    }
+
+    #[test]
+    fn clean_chain_judges_every_head_and_tail() {
+        let mut map = chain();
+        for (start, end) in [(1000.0, 1250.0), (1250.0, 1500.0), (1500.0, 1750.0)] {
+            map.handle_gameplay_key_press(start, 0);
+            map.handle_gameplay_key_release(end, 0);
+        }
+        assert_eq!(map.hit_stats.len(), 6);
+        assert!(map.hit_stats.iter().all(|stat| stat.judgement == JudgementType::Marvelous));
+        assert!(map.hit_objects.iter().all(|note| note.hit && note.tail_hit));
+        use HoldEventKind::{Grab, Release};
+        assert_eq!(hold_kinds(&map), [(0, Grab), (0, Release), (1, Grab), (1, Release), (2, Grab), (2, Release)]);
+    }
+
+    fn note_lock_results(note_lock: NoteLock, presses: &[Time]) -> Vec<(Time, JudgementType, i64)> {
+        // two notes 30 ms apart in lane 1, tapped at the given times, then played to the end:
+        // each note's time, judgement and offset in the order they were judged
+        let mut map = initialized(vec![
+            HitObject { start_time: 1000.0, lane: 1, ..HitObject::default() },
+            HitObject { start_time: 1030.0, lane: 1, ..HitObject::default() },
+        ]);
+        map.note_lock = note_lock;
+        for &time in presses {
+            map.handle_gameplay_key_press(time, 0);
+            map.handle_gameplay_key_release(time + 20.0, 0);
+        }
+        map.time = 2000.0;
+        map.update_judgements();
+        assert!(map.hit_objects.iter().all(|note| note.hit));
+        map.hit_stats.iter().map(|stat| (stat.time, stat.judgement, stat.offset.round() as i64)).collect()
+    }
+
+    #[test]
+    fn strict_note_lock_always_judges_the_earliest_note() {
+        use JudgementType::{Marvelous, Miss, Perfect};
+        // late for the first note but right on the second: the first is hit, the second missed
+        let late_tap = note_lock_results(NoteLock::Strict, &[1025.0]);
+        assert_eq!(late_tap[0], (1025.0, Perfect, -25));
+        assert_eq!(late_tap[1].1, Miss);
+        assert_eq!(note_lock_results(NoteLock::Strict, &[1010.0])[0], (1010.0, Marvelous, -10));
+        // two taps get one each, in order
+        assert_eq!(note_lock_results(NoteLock::Strict, &[1025.0, 1035.0]), [(1025.0, Perfect, -25), (1035.0, Marvelous, -5)]);
+        assert_eq!(note_lock_results(NoteLock::Strict, &[995.0, 1028.0]), [(995.0, Marvelous, 5), (1028.0, Marvelous, 2)]);
+    }
+
+    #[test]
+    fn forgiving_note_lock_judges_the_closest_note_it_is_not_early_for() {
+        use JudgementType::{Marvelous, Miss, Perfect};
+        // the second note is closer, the first is skipped and missed once it's passed
+        let late_tap = note_lock_results(NoteLock::Forgiving, &[1025.0]);
+        assert_eq!(late_tap[0], (1025.0, Marvelous, 5));
+        assert_eq!(late_tap[1].1, Miss);
+        // closer to the first, so that one
+        assert_eq!(note_lock_results(NoteLock::Forgiving, &[1010.0])[0], (1010.0, Marvelous, -10));
+        // the skipped note is still there for the next tap
+        assert_eq!(note_lock_results(NoteLock::Forgiving, &[1025.0, 1035.0]), [(1025.0, Marvelous, 5), (1035.0, Perfect, -35)]);
+        // early for both: never the later one
+        assert_eq!(note_lock_results(NoteLock::Forgiving, &[995.0, 1028.0]), [(995.0, Marvelous, 5), (1028.0, Marvelous, 2)]);
+    }
+
+    #[test]
+    fn release_without_a_held_note_judges_nothing() {
+        let mut map = chain();
+        map.handle_gameplay_key_release(1250.0, 0);
+        assert!(map.hit_stats.is_empty());
+        assert!(map.hold_events.is_empty());
+        assert!(map.hit_objects.iter().all(|note| !note.tail_hit));
+    }
+
+    #[test]
+    fn release_judges_the_held_ln_and_not_the_next_head() {
+        let mut map = chain();
+        map.handle_gameplay_key_press(1000.0, 0);
+        map.handle_gameplay_key_release(1252.0, 0);
+        assert!(map.hit_objects[0].tail_hit);
+        assert!(!map.hit_objects[1].hit);
+        assert_eq!(map.hit_stats.len(), 2);
+    }
+
+    #[test]
+    fn press_right_after_a_release_takes_the_next_head() {
+        let mut map = chain();
+        map.handle_gameplay_key_press(1000.0, 0);
+        map.handle_gameplay_key_release(1249.0, 0);
+        assert_eq!(map.handle_gameplay_key_press(1250.5, 0), Some(1));
+        assert_eq!(map.held_notes.get(&1), Some(&1));
+    }
+
+    #[test]
+    fn unstarted_head_is_preferred_over_regrabbing_a_broken_ln() {
+        let mut map = chain();
+        map.handle_gameplay_key_press(1000.0, 0);
+        map.handle_gameplay_key_release(1050.0, 0);
+        assert_eq!(map.hold_events.last().map(|event| event.kind), Some(HoldEventKind::Break));
+        // the broken LN could still be regrabbed, but the next head is in its window
+        assert_eq!(map.handle_gameplay_key_press(1240.0, 0), Some(1));
+        assert_eq!(map.held_notes.get(&1), Some(&1));
+        assert!(!map.hit_objects[0].tail_hit);
+    }
+
+    #[test]
+    fn broken_ln_is_regrabbed_when_nothing_else_is_in_the_window() {
+        let mut map = chain();
+        map.handle_gameplay_key_press(1000.0, 0);
+        map.handle_gameplay_key_release(1050.0, 0);
+        assert_eq!(map.handle_gameplay_key_press(1060.0, 0), None);
+        assert_eq!(map.held_notes.get(&1), Some(&0));
+        map.handle_gameplay_key_release(1250.0, 0);
+        use HoldEventKind::{Break, Grab, Regrab, Release};
+        assert_eq!(hold_kinds(&map), [(0, Grab), (0, Break), (0, Regrab), (0, Release)]);
+        assert!(!map.hit_objects[1].hit);
+    }
+
+    #[test]
+    fn broken_ln_far_behind_the_press_can_be_regrabbed() {
+        // the regrab search only looks back as far as the longest LN
+        let mut notes: Vec<HitObject> = (0..40).map(|beat| HitObject { start_time: 1000.0 + f64::from(beat) * 250.0, lane: 1, ..HitObject::default() }).collect();
+        notes.push(long_note(1000.0, 11000.0, 2));
+        let mut map = initialized(notes);
+        map.handle_gameplay_key_press(1000.0, 1);
+        map.handle_gameplay_key_release(2000.0, 1);
+        map.handle_gameplay_key_press(9000.0, 1);
+        let long_note = map.hit_objects.iter().position(|note| note.lane == 2).unwrap();
+        assert_eq!(map.held_notes.get(&2), Some(&long_note));
+        assert_eq!(map.hold_events.last().map(|event| event.kind), Some(HoldEventKind::Regrab));
+    }
+
+    #[test]
+    fn press_before_the_early_miss_window_is_a_ghost_tap() {
+        let mut map = initialized(vec![HitObject { start_time: 1000.0, lane: 1, ..HitObject::default() }]);
+        let miss = map.judgement_windows.early(JudgementType::Miss);
+        assert_eq!(map.handle_gameplay_key_press(1000.0 - miss - 1.0, 0), None);
+        assert!(map.hit_stats.is_empty());
+        assert!(!map.hit_objects[0].hit);
+    }
+
+    #[test]
+    fn press_between_okay_and_miss_early_misses_the_note() {
+        let mut map = initialized(vec![HitObject { start_time: 1000.0, lane: 1, ..HitObject::default() }]);
+        let okay = map.judgement_windows.early(JudgementType::Okay);
+        map.handle_gameplay_key_press(1000.0 - okay - 1.0, 0);
+        assert_eq!(map.hit_stats.iter().map(|stat| stat.judgement).collect::<Vec<_>>(), [JudgementType::Miss]);
+        assert!(map.hit_objects[0].hit);
+    }
+
+    #[test]
+    fn press_inside_okay_judges_normally() {
+        let mut map = initialized(vec![HitObject { start_time: 1000.0, lane: 1, ..HitObject::default() }]);
+        let okay = map.judgement_windows.early(JudgementType::Okay);
+        map.handle_gameplay_key_press(1000.0 - okay + 1.0, 0);
+        assert_eq!(map.hit_stats.iter().map(|stat| stat.judgement).collect::<Vec<_>>(), [JudgementType::Okay]);
+    }
+
+    const MULTI_GROUP_CHART: &str = "\
+Mode: Keys4
+TimingPoints:
+- StartTime: 0
+  Bpm: 120
+SliderVelocities:
+- StartTime: 500
+  Multiplier: 2
+- StartTime: 1500
+  Multiplier: 0.5
+TimingGroups:
+  slow:
+    InitialScrollVelocity: 0.5
+    ScrollVelocities:
+    - StartTime: 800
+      Multiplier: 1.5
+  reverse:
+    ScrollVelocities:
+    - StartTime: 1000
+      Multiplier: -1
+    - StartTime: 1200
+      Multiplier: 1
+HitObjects:
+- StartTime: 250
+  Lane: 1
+  KeySounds: []
+- StartTime: 750
+  Lane: 2
+  EndTime: 1750
+  TimingGroup: slow
+  KeySounds: []
+- StartTime: 1250
+  Lane: 3
+  TimingGroup: reverse
+  KeySounds: []
+- StartTime: 1250
+  Lane: 4
+  TimingGroup: slow
+  KeySounds: []
+- StartTime: 2000
+  Lane: 1
+  TimingGroup: reverse
+  KeySounds: []
+";
+
+    #[test]
+    fn group_indices_place_notes_like_looking_groups_up_by_name() {
+        let mut map: Map = serde_yaml::from_str(MULTI_GROUP_CHART).unwrap();
+        map.rate = 1.0;
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        assert_eq!(map.timing_groups.len(), 3);
+        for time in [0.0, 600.0, 1100.0, 1300.0, 2500.0] {
+            map.time = time;
+            map.update_track_position(time);
+            map.update_hit_objects().unwrap();
+            for note in &map.hit_objects {
+                let group = map.timing_groups.get(note.timing_group.as_deref().unwrap()).unwrap();
+                assert_eq!(note.start_position, group.get_position_from_time(note.start_time, false));
+                assert_eq!(note.position, group.get_object_position(note.hit_position, note.start_position, false));
+                assert_eq!(
+                    note.position_tail,
+                    group.get_object_position(note.hold_end_hit_position, note.start_position_tail, false)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn metronome_has_one_note_per_beat_cycling_through_the_lanes() {
+        let map = Map::synthetic_metronome(120.0, 3000.0, 4);
+        let notes: Vec<(Time, i64)> = map.hit_objects.iter().map(|note| (note.start_time, note.lane)).collect();
+        assert_eq!(notes, [(0.0, 1), (500.0, 2), (1000.0, 3), (1500.0, 4), (2000.0, 1), (2500.0, 2)]);
+        assert!(map.hit_objects.iter().all(|note| note.end_time.is_none()));
+        assert_eq!(map.timing_points[0].bpm, 120.0);
+    }
+
+    #[test]
+    fn metronome_stops_before_the_duration_and_clamps_its_lanes() {
+        // 0.7 s beats: the last one starts before 2 s, none at or after it
+        let map = Map::synthetic_metronome(60000.0 / 700.0, 2000.0, 9);
+        let starts: Vec<Time> = map.hit_objects.iter().map(|note| note.start_time).collect();
+        assert_eq!(starts.len(), 3);
+        assert!((starts[2] - 1400.0).abs() < 1e-9);
+        assert_eq!(map.get_key_count(false), 7);
+        assert!(Map::synthetic_metronome(120.0, 0.0, 4).hit_objects.is_empty());
+    }
+
+    #[test]
+    fn metronome_initializes_like_a_real_chart() {
+        let mut map = Map::synthetic_metronome(120.0, 2000.0, 4);
+        map.rate = 1.0;
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        map.handle_gameplay_key_press(510.0, 1);
+        let stat = map.hit_stats[0];
+        assert_eq!((stat.lane, stat.offset), (2, -10.0));
+    }
+
+    #[test]
+    fn resuming_skips_earlier_notes_without_judging_them() {
+        let mut map = initialized(vec![
+            HitObject { start_time: 1000.0, lane: 1, ..HitObject::default() },
+            long_note(20000.0, 40000.0, 2),
+            HitObject { start_time: 45000.0, lane: 3, ..HitObject::default() },
+        ]);
+        map.skip_to(35000.0);
+        assert!(map.resumed);
+        map.time = 36000.0;
+        map.update_judgements();
+        assert!(map.hit_stats.is_empty());
+        // the first note after the resume point still counts
+        map.handle_gameplay_key_press(45000.0, 2);
+        assert_eq!(map.hit_stats.len(), 1);
+        assert_eq!(map.hit_stats[0].judgement, JudgementType::Marvelous);
+    }
+
+    fn note(start_time: Time, lane: i64) -> HitObject {
+        HitObject { start_time, lane, ..HitObject::default() }
+    }
+
+    fn judge_at(map: &mut Map, time: Time) {
+        map.time = time;
+        map.update_judgements();
+    }
+
+    #[test]
+    fn autoplay_turned_on_before_a_chord_hits_the_chord() {
+        let mut map = initialized(vec![note(2000.0, 1), note(2000.0, 2), note(2000.0, 3)]);
+        map.toggle_autoplay(1990.0);
+        judge_at(&mut map, 1990.0);
+        assert!(map.hit_stats.is_empty());
+        judge_at(&mut map, 2000.0);
+        judge_at(&mut map, 2200.0);
+        assert_eq!(map.hit_stats.len(), 3);
+        assert!(map.hit_stats.iter().all(|stat| stat.judgement == JudgementType::Marvelous));
+        assert!(map.autoplay_assisted);
+    }
+
+    #[test]
+    fn autoplay_turned_on_skips_overdue_notes() {
+        // all three are still inside the late window, so none has been missed yet
+        let mut map = initialized(vec![note(1000.0, 1), note(1050.0, 2), note(1100.0, 3), note(1500.0, 4)]);
+        judge_at(&mut map, 1120.0);
+        map.toggle_autoplay(1120.0);
+        judge_at(&mut map, 1120.0);
+        judge_at(&mut map, 1400.0);
+        assert!(map.hit_stats.is_empty(), "overdue notes were judged: {:?}", map.hit_stats);
+        judge_at(&mut map, 1500.0);
+        assert_eq!(map.hit_stats.len(), 1);
+        assert_eq!(map.hit_stats[0].lane, 4);
+    }
+
+    #[test]
+    fn autoplay_turned_off_mid_ln_leaves_the_hold_to_the_player() {
+        let mut map = initialized(vec![long_note(1000.0, 2000.0, 1)]);
+        map.toggle_autoplay(0.0);
+        judge_at(&mut map, 1000.0);
+        assert_eq!(map.hit_stats.len(), 1);
+        map.toggle_autoplay(1500.0);
+        assert!(!map.mods.autoplay && map.autoplay_assisted);
+        judge_at(&mut map, 1600.0);
+        map.handle_gameplay_key_release(2000.0, 0);
+        assert_eq!(map.hit_stats.len(), 2);
+        assert_eq!(map.hit_stats[1].kind, HitKind::LongNoteEnd);
+        assert_eq!(map.hit_stats[1].judgement, JudgementType::Marvelous);
+    }
+
+    fn two_bpm_chart(bpm_does_not_affect_scroll_velocity: bool) -> Map {
+        // 120 bpm for the first second and 240 after, with a 2x SV at 500 ms and a 0.5x one at 2000 ms
+        let mut map = Map { mode: GameMode::Keys4, bpm_does_not_affect_scroll_velocity, ..Map::default() };
+        map.hit_objects = vec![HitObject { start_time: 4000.0, lane: 1, ..HitObject::default() }];
+        map.timing_points = vec![
+            TimingPoint { start_time: 0.0, bpm: 120.0, time_signature: None, hidden: false },
+            TimingPoint { start_time: 1000.0, bpm: 240.0, time_signature: None, hidden: false },
+        ];
+        map.scroll_velocities = vec![sv_point(500.0, 2.0), sv_point(2000.0, 0.5)];
+        map
+    }
+
+    fn default_group_svs(map: &Map) -> (f64, Vec<(Time, f64)>) {
+        let group = map.timing_groups.get(DEFAULT_TIMING_GROUP_ID).unwrap();
+        (group.initial_scroll_velocity, group.scroll_velocities.iter().map(|sv| (sv.start_time, sv.multiplier)).collect())
+    }
+
+    #[test]
+    fn two_bpm_legacy_chart_is_normalized_on_initialization() {
+        let mut map = two_bpm_chart(false);
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        assert!(map.bpm_does_not_affect_scroll_velocity);
+        // scaled against the common 240 bpm: half speed until the 2x SV, and the 240 bpm timing point
+        // resets the SV to 1x, which is already the speed there
+        assert_eq!(default_group_svs(&map), (0.5, vec![(500.0, 1.0), (2000.0, 0.5)]));
+    }
+
+    #[test]
+    fn normalized_chart_keeps_its_svs() {
+        let mut map = two_bpm_chart(true);
+        map.initial_scroll_velocity = 1.0;
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        assert_eq!(default_group_svs(&map), (1.0, vec![(500.0, 2.0), (2000.0, 0.5)]));
+    }
+
+    #[test]
+    fn zero_bpm_section_scrolls_at_the_clamped_multiplier() {
+        let mut map = two_bpm_chart(false);
+        map.timing_points.push(TimingPoint { start_time: 3000.0, bpm: 0.0, time_signature: None, hidden: false });
+        map.scroll_velocities.pop();
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        assert_eq!(default_group_svs(&map), (0.5, vec![(500.0, 1.0), (3000.0, UNUSABLE_BPM_MULTIPLIER)]));
+    }
+
+    fn seven_key_chord(has_scratch_key: bool) -> Map {
+        // every lane at 1000 ms, the scratch lane too if there is one
+        let lanes = if has_scratch_key { 8 } else { 7 };
+        let hit_objects = (1..=lanes).map(|lane| HitObject { start_time: 1000.0, lane, ..HitObject::default() }).collect();
+        let mut map = Map { mode: GameMode::Keys7, has_scratch_key, hit_objects, ..Map::default() };
+        map.timing_points.push(TimingPoint { start_time: 0.0, bpm: 60.0, time_signature: None, hidden: false });
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        map.mods.mirror = true;
+        map
+    }
+
+    #[test]
+    fn seven_key_mirror_keeps_the_middle_lane() {
+        let mut map = seven_key_chord(false);
+        assert_eq!(map.handle_gameplay_key_press(1000.0, 3).map(|note| map.hit_objects[note].lane), Some(4));
+        assert_eq!(map.handle_gameplay_key_press(1000.0, 0).map(|note| map.hit_objects[note].lane), Some(7));
+        assert_eq!(map.handle_gameplay_key_press(1000.0, 6).map(|note| map.hit_objects[note].lane), Some(1));
+        let layout = PlayfieldLayout::new(&DEFAULT_SKIN, 7, false, true, 1000.0);
+        assert_eq!((1..=7).map(|lane| layout.column(lane)).collect::<Vec<_>>(), [6, 5, 4, 3, 2, 1, 0]);
+
+        let mut map = seven_key_chord(false);
+        map.mirror_chart_lanes();
+        assert_eq!(map.hit_objects.iter().map(|note| note.lane).collect::<Vec<_>>(), [7, 6, 5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn seven_key_mirror_leaves_the_scratch_lane_last() {
+        let mut map = seven_key_chord(true);
+        assert_eq!(map.handle_gameplay_key_press(1000.0, 7).map(|note| map.hit_objects[note].lane), Some(8));
+        assert_eq!(map.handle_gameplay_key_press(1000.0, 3).map(|note| map.hit_objects[note].lane), Some(4));
+        let layout = PlayfieldLayout::new(&DEFAULT_SKIN, 7, true, true, 1000.0);
+        assert_eq!((layout.column(4), layout.column(8)), (3, 7));
+    }
+
+    fn four_key_scratch_chord(mirror: bool) -> Map {
+        // every lane at 1000 ms in a 4K+1 chart, the scratch lane (5) too
+        let hit_objects = (1..=5).map(|lane| HitObject { start_time: 1000.0, lane, ..HitObject::default() }).collect();
+        let mut map = Map { mode: GameMode::Keys4, has_scratch_key: true, hit_objects, ..Map::default() };
+        map.timing_points.push(TimingPoint { start_time: 0.0, bpm: 60.0, time_signature: None, hidden: false });
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        map.mods.mirror = mirror;
+        map
+    }
+
+    fn lanes_pressed(map: &mut Map, keys: &[i64]) -> Vec<Option<i64>> {
+        keys.iter().map(|&key| map.handle_gameplay_key_press(1000.0, key).map(|note| map.hit_objects[note].lane)).collect()
+    }
+
+    #[test]
+    fn four_key_scratch_lane_is_played_with_the_fifth_key() {
+        use crate::input::KeyBindings;
+        use macroquad::input::KeyCode;
+        let mut map = four_key_scratch_chord(false);
+        assert_eq!(map.get_key_count(true), 5);
+        assert!(map.is_scratch_lane(5) && !map.is_scratch_lane(4));
+        // the bindings for 5 keys are the 4K ones with the scratch key after them
+        let bindings = KeyBindings::default();
+        let keys = bindings.keys(5);
+        assert_eq!((&keys[..4], keys[4]), (bindings.keys(4), KeyCode::Slash));
+        assert_eq!(lanes_pressed(&mut map, &[0, 1, 2, 3, 4]), [Some(1), Some(2), Some(3), Some(4), Some(5)]);
+    }
+
+    #[test]
+    fn four_key_mirror_leaves_the_scratch_lane_in_place() {
+        let mut map = four_key_scratch_chord(true);
+        assert_eq!(lanes_pressed(&mut map, &[0, 1, 2, 3, 4]), [Some(4), Some(3), Some(2), Some(1), Some(5)]);
+        let layout = PlayfieldLayout::new(&DEFAULT_SKIN, 4, true, true, 1000.0);
+        assert_eq!((1..=5).map(|lane| layout.column(lane)).collect::<Vec<_>>(), [3, 2, 1, 0, 4]);
+        assert_eq!(layout.lane_width(4), DEFAULT_SKIN.scratch_lane_width);
+
+        // mirroring the chart itself leaves it too
+        let mut map = four_key_scratch_chord(false);
+        map.mirror_chart_lanes();
+        assert_eq!(map.hit_objects.iter().map(|note| note.lane).collect::<Vec<_>>(), [4, 3, 2, 1, 5]);
+    }
+
+    fn snapped(bpm: f64, timing_point_time: Time, note_times: &[Time]) -> Map {
+        let hit_objects = note_times.iter().map(|&start_time| HitObject { start_time, lane: 1, ..HitObject::default() }).collect();
+        let mut map = Map { mode: GameMode::Keys4, hit_objects, ..Map::default() };
+        map.timing_points.push(TimingPoint { start_time: timing_point_time, bpm, time_signature: None, hidden: false });
+        map.initialize_beat_snaps().unwrap();
+        map
+    }
+
+    fn snaps(map: &Map) -> Vec<usize> {
+        map.hit_objects.iter().map(|note| note.snap_index).collect()
+    }
+
+    #[test]
+    fn zero_and_negative_bpms_count_beats_like_documented() {
+        // 120 BPM, then 0 BPM from 2000 ms (60000 ms beats) and -180 BPM from 4000 ms (counted as 180)
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/charts/irregular_bpm.qua");
+        let mut map = Map::from_file(&path).unwrap();
+        let irregular: Vec<_> = map.irregular_bpms().iter().map(|timing_point| (timing_point.start_time, timing_point.ms_per_beat())).collect();
+        assert_eq!(irregular, [(2000.0, 60000.0), (4000.0, 60000.0 / 180.0)]);
+        map.rate = 1.0;
+        map.length = 6000.0;
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+
+        // on the beat, 1/2, on the (60 s long) beat, 1/60 of a beat (closest to 1/48), 1/2, 1/1, 1/1
+        assert_eq!(snaps(&map), [0, 1, 0, 8, 1, 0, 0]);
+        // a line every measure: 2000 ms at 120 BPM, then one for the whole 0 BPM section, then every 1333 ms
+        let lines: Vec<_> = map.timing_lines.iter().map(|line| line.start_time.round()).collect();
+        assert_eq!(lines, [0.0, 2000.0, 4000.0, 5333.0]);
+        // and every timing point starts a measure
+        let beat = |time: Time| map.beat_phase(time).map(|position| (position.measure, position.beat));
+        assert_eq!(beat(3000.0), Some((2, 1)));
+        assert_eq!(beat(4500.0), Some((3, 2)));
+        assert_eq!(beat(5400.0), Some((4, 1)));
+        // the chart still plays through: positions keep moving and every note is judged
+        map.mods.autoplay = true;
+        let mut last_position = Position::MIN;
+        for step in 0..=70 {
+            map.time = f64::from(step) * 100.0;
+            map.update_track_position(map.time);
+            map.update_scroll_speed();
+            map.update_hit_objects().unwrap();
+            map.update_timing_lines(1200.0).unwrap();
+            map.update_judgements();
+            let position = map.timing_groups.get(DEFAULT_TIMING_GROUP_ID).unwrap().current_track_position;
+            assert!(position >= last_position, "went back at {} ms", map.time);
+            last_position = position;
+        }
+        assert!(map.hit_objects.iter().all(HitObject::is_finished));
+    }
+
+    #[test]
+    fn notes_at_120_bpm_get_their_snap_colors() {
+        // 500 ms beats: 1/1, 1/2, 1/3, 1/4, 1/6, 1/8, 1/12, 1/16, 1/48
+        let map = snapped(120.0, 0.0, &[1000.0, 250.0, 500.0 / 3.0, 125.0, 500.0 / 6.0, 62.5, 500.0 / 12.0, 31.25, 500.0 / 48.0]);
+        assert_eq!(snaps(&map), [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(map.hit_objects.iter().all(|note| note.snap_error < 1e-9));
+    }
+
+    #[test]
+    fn off_snap_notes_at_120_bpm_are_measured() {
+        // the grid is 500 / 48 = 10.417 ms, 505 is 5 ms past the beat and 1.5 ms is within the tolerance
+        let map = snapped(120.0, 0.0, &[505.0, 1001.5, 1500.0]);
+        assert!((map.hit_objects[0].snap_error - 5.0).abs() < 1e-9);
+        assert!((map.hit_objects[1].snap_error - 1.5).abs() < 1e-9);
+        assert_eq!(map.hit_objects[2].snap_error, 0.0);
+        let off_snap: Vec<(usize, usize)> =
+            map.off_snap_notes(OFF_SNAP_TOLERANCE).iter().map(|note| (note.index, note.timing_point)).collect();
+        assert_eq!(off_snap, [(0, 0)]);
+    }
+
+    #[test]
+    fn notes_at_173_333_bpm_rounded_to_whole_ms_stay_on_snap() {
+        // 346.154 ms beats; charts store the times rounded, which is well within the tolerance
+        let beat: Time = 60000.0 / 173.333;
+        let times: Vec<Time> = [1.0, 0.5, 0.25, 1.0 / 3.0, 2.0].iter().map(|beats| (1000.0 + beats * beat).round()).collect();
+        let map = snapped(173.333, 1000.0, &times);
+        assert_eq!(snaps(&map), [0, 1, 3, 2, 0]);
+        assert!(map.hit_objects.iter().all(|note| note.snap_error < 0.5), "{:?}", map.hit_objects.iter().map(|note| note.snap_error).collect::<Vec<_>>());
+        assert!(map.off_snap_notes(OFF_SNAP_TOLERANCE).is_empty());
+        // 4 ms off a 1/4 is reported
+        let map = snapped(173.333, 1000.0, &[(1000.0 + beat / 4.0).round() + 4.0]);
+        assert_eq!(map.off_snap_notes(OFF_SNAP_TOLERANCE).len(), 1);
+    }
+
+    #[test]
+    fn notes_before_the_first_timing_point_snap_backwards() {
+        // a half and a quarter beat before a 120 BPM timing point at 1000 ms, and a whole beat
+        let map = snapped(120.0, 1000.0, &[750.0, 875.0, 500.0, 0.0]);
+        assert_eq!(snaps(&map), [1, 3, 0, 0]);
+        assert!(map.hit_objects.iter().all(|note| note.snap_error < 1e-9));
+    }
+
+    #[test]
+    fn negative_bpm_snaps_like_its_positive() {
+        let times = [0.0, 250.0, 125.0, 505.0];
+        let negative = snapped(-120.0, 0.0, &times);
+        let positive = snapped(120.0, 0.0, &times);
+        assert_eq!(snaps(&negative), snaps(&positive));
+        assert_eq!(snaps(&negative), [0, 1, 3, 0]);
+        let errors = |map: &Map| map.hit_objects.iter().map(|note| note.snap_error).collect::<Vec<_>>();
+        assert_eq!(errors(&negative), errors(&positive));
+    }
+
+    #[test]
+    fn scroll_direction_is_kept_with_the_scroll_speed() {
+        // positions follow the skin the scroll speed was last updated with, not whatever is set since
+        let mut map = initialized(vec![HitObject { start_time: 1000.0, lane: 1, ..HitObject::default() }]);
+        map.rate = 1.0;
+        let position = |map: &mut Map| {
+            map.update_track_position(0.0);
+            map.update_hit_objects().unwrap();
+            map.hit_objects[0].position - map.hit_objects[0].hit_position as Position
+        };
+        map.update_scroll_speed();
+        let downscroll = position(&mut map);
+        assert!(downscroll < 0, "{downscroll}");
+        set_skin(Skin { downscroll: false, ..DEFAULT_SKIN });
+        assert_eq!(position(&mut map), downscroll);
+        map.update_scroll_speed();
+        set_skin(DEFAULT_SKIN);
+        assert_eq!(position(&mut map), -downscroll);
+    }
+
+    fn head_and_tail_positions(skin: Skin) -> Vec<(Position, Position)> {
+        // an LN and a normal note 1 s before they reach the hit line, with the skin's hit positions
+        set_skin(skin);
+        let mut map = initialized(vec![long_note(1000.0, 1500.0, 1), HitObject { start_time: 1000.0, lane: 2, ..HitObject::default() }]);
+        set_skin(DEFAULT_SKIN);
+        map.rate = 1.0;
+        map.update_track_position(0.0);
+        map.update_scroll_speed();
+        map.update_hit_objects().unwrap();
+        map.hit_objects.iter().map(|note| (note.position, note.position_tail)).collect()
+    }
+
+    #[test]
+    fn ln_heads_and_ends_are_moved_by_the_skin_offsets() {
+        let unset = head_and_tail_positions(DEFAULT_SKIN);
+        // without offsets heads and ends go to the same hit line as normal notes
+        let (ln_head, ln_tail) = unset[0];
+        let (note_head, note_tail) = unset[1];
+        assert_eq!(ln_head, note_head);
+        assert_eq!(note_tail, note_head);
+        assert!(ln_tail < ln_head);
+
+        let offset = head_and_tail_positions(Skin {
+            hold_hit_position_offset: 30.0,
+            hold_end_hit_position_offset: 12.0,
+            ..DEFAULT_SKIN
+        });
+        // downscroll: the offsets are upwards, and normal notes don't move
+        assert_eq!(offset[0], (ln_head - 30, ln_tail - 12));
+        assert_eq!(offset[1], unset[1]);
+    }
+
+    fn scrolling_group(downscroll: bool, scroll_speed: f64) -> TimingGroup {
+        // 1 s into the track, half speed from an SSF
+        TimingGroup { current_track_position: 100_000, current_ssf_factor: 0.5, scroll_speed, downscroll, ..TimingGroup::default() }
+    }
+
+    #[test]
+    fn visible_track_range_covers_the_screen_in_either_direction() {
+        // screen positions go up from the bottom of the window (negative), the hit line 200 px up
+        let hit_position = -200.0;
+        let screen = (-1000.0, 0.0);
+        for downscroll in [true, false] {
+            let group = scrolling_group(downscroll, 2.0);
+            let (low, high) = group.visible_track_range(hit_position, screen, true);
+            // 2 px per ms: 100 ms before the hit line to 400 ms after it in downscroll (later notes higher up),
+            // 400 ms before to 100 ms after in upscroll
+            let expected = if downscroll { (90_000, 140_000) } else { (60_000, 110_000) };
+            assert_eq!((low, high), expected, "downscroll {downscroll}");
+            // the ends are where the screen's edges are, in order either way
+            let edges = [group.get_object_position(hit_position, low, true), group.get_object_position(hit_position, high, true)];
+            assert!(edges.contains(&0) && edges.contains(&-1000), "{edges:?}");
+            assert_eq!(group.visible_track_range(hit_position, (screen.1, screen.0), true), (low, high));
+
+            // the SSF halves the speed, so twice as much of the track fits
+            let (ssf_low, ssf_high) = group.visible_track_range(hit_position, screen, false);
+            assert_eq!(ssf_high - ssf_low, 2 * (high - low));
+        }
+    }
+
+    #[test]
+    fn visible_track_range_is_everything_when_nothing_moves() {
+        for downscroll in [true, false] {
+            let group = scrolling_group(downscroll, 0.0);
+            assert_eq!(group.visible_track_range(-200.0, (-1000.0, 0.0), true), (Position::MIN, Position::MAX));
+            // an SSF of 0 stops it just the same
+            let stopped = TimingGroup { current_ssf_factor: 0.0, ..scrolling_group(downscroll, 2.0) };
+            assert_eq!(stopped.visible_track_range(-200.0, (-1000.0, 0.0), false), (Position::MIN, Position::MAX));
+        }
+    }
+
+    fn extreme_chart() -> Map {
+        // a 1e12x SV from the start, and a note and SV 1e15 ms in
+        let mut map = Map { mode: GameMode::Keys4, ..Map::default() };
+        map.timing_points.push(TimingPoint { start_time: 0.0, bpm: 60.0, time_signature: None, hidden: false });
+        map.scroll_velocities = vec![sv_point(0.0, 1e12), sv_point(1e15, 1.0)];
+        map.hit_objects = vec![
+            HitObject { start_time: 1000.0, lane: 1, ..HitObject::default() },
+            HitObject { start_time: 2000.0, lane: 2, ..HitObject::default() },
+            HitObject { start_time: 1e15, lane: 3, ..HitObject::default() },
+        ];
+        map
+    }
+
+    #[test]
+    fn extreme_values_saturate_instead_of_wrapping() {
+        let mut map = extreme_chart();
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        let group = map.timing_groups.get(DEFAULT_TIMING_GROUP_ID).unwrap();
+        let positions: Vec<Position> =
+            [0.0, 1000.0, 2000.0, 1e15, 2e15].iter().map(|&time| group.get_position_from_time(time, false)).collect();
+        assert_eq!(positions[0], 0);
+        assert!(positions.windows(2).all(|pair| pair[0] <= pair[1]), "{positions:?}");
+        assert_eq!(positions[4], Position::MAX);
+        let start_positions: Vec<Position> = map.hit_objects.iter().map(|note| note.start_position).collect();
+        assert!(start_positions.windows(2).all(|pair| pair[0] <= pair[1]), "{start_positions:?}");
+
+        for time in [0.0, 1500.0, 1e15] {
+            map.update_track_position(time);
+            map.update_hit_objects().unwrap();
+        }
+    }
+
+    #[test]
+    fn extreme_values_are_clamped_and_reported() {
+        let mut map = extreme_chart();
+        let report: Vec<(String, f64, f64)> =
+            map.validate_ranges().into_iter().map(|clamped| (clamped.field, clamped.value, clamped.clamped)).collect();
+        assert_eq!(
+            report,
+            [
+                ("note time at 1000000000000000 ms".to_string(), 1e15, MAX_START_TIME),
+                ("SV multiplier at 0 ms ($Default)".to_string(), 1e12, MAX_MULTIPLIER),
+                ("SV time at 1000000000000000 ms ($Default)".to_string(), 1e15, MAX_START_TIME),
+            ]
+        );
+        // clamping again has nothing left to do
+        assert!(map.validate_ranges().is_empty());
+
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        let group = map.timing_groups.get(DEFAULT_TIMING_GROUP_ID).unwrap();
+        let expected = (MAX_MULTIPLIER * 1000.0 * TRACK_ROUNDING) as Position;
+        assert_eq!(group.get_position_from_time(1000.0, false), expected);
+        assert_eq!(map.hit_objects[1].start_position, expected * 2);
+        assert!(map.hit_objects[2].start_position > expected * 2);
+    }
+
+    fn computed_fields(map: &Map) -> String {
+        // everything initialization works out, to compare two initialized maps
+        let notes: Vec<_> = map
+            .hit_objects
+            .iter()
+            .map(|note| {
+                let positions = (note.start_position, note.start_position_tail, note.position, note.position_tail);
+                (note.start_time, note.lane, note.timing_group.clone(), note.snap_index, note.hit_position, positions)
+            })
+            .collect();
+        let groups: Vec<_> = map
+            .timing_groups
+            .iter()
+            .map(|(id, group)| {
+                let svs: Vec<_> =
+                    group.scroll_velocities.iter().map(|sv| (sv.start_time, sv.multiplier, sv.cumulative_position)).collect();
+                let ssfs: Vec<_> = group.scroll_speed_factors.iter().map(|ssf| (ssf.start_time, ssf.multiplier)).collect();
+                (id.clone(), group.initial_scroll_velocity, svs, ssfs)
+            })
+            .collect();
+        format!("{notes:?}\n{groups:?}\n{:?}\n{:?}", map.group_notes, map.length)
+    }
+
+    #[test]
+    fn notes_in_unknown_groups_move_with_the_default_group() {
+        let chart = "\
+Mode: Keys4
+TimingPoints:
+- StartTime: 0
+  Bpm: 60
+SliderVelocities:
+- StartTime: 1000
+  Multiplier: 2
+TimingGroups:
+  real:
+    ScrollVelocities:
+    - StartTime: 0
+      Multiplier: 0.5
+HitObjects:
+- StartTime: 2000
+  Lane: 1
+  KeySounds: []
+- StartTime: 2000
+  Lane: 2
+  TimingGroup: rael
+  KeySounds: []
+- StartTime: 3000
+  Lane: 3
+  TimingGroup: rael
+  KeySounds: []
+- StartTime: 3000
+  Lane: 4
+  TimingGroup: ghost
+  KeySounds: []
+- StartTime: 2000
+  Lane: 4
+  TimingGroup: real
+  KeySounds: []
+";
+        let mut map: Map = serde_yaml::from_str(chart).unwrap();
+        map.rate = 1.0;
+        logger::take_warnings();
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        let warnings: Vec<_> = logger::take_warnings().into_iter().filter(|warning| warning.contains("unknown timing groups")).collect();
+        assert_eq!(warnings, vec!["Notes in unknown timing groups moved to the default group: 'rael' (2), 'ghost' (1)".to_string()]);
+        let groups: Vec<_> = map.hit_objects.iter().map(|note| (note.start_time, note.timing_group.clone().unwrap())).collect();
+        assert_eq!(groups.iter().filter(|(_, group)| group == DEFAULT_TIMING_GROUP_ID).count(), 4);
+
+        // they follow the default group's SVs, not the 0.5x of the group that does exist
+        map.update_scroll_speed();
+        for time in [0.0, 1500.0, 2500.0] {
+            map.update_track_position(time);
+            map.update_hit_objects().unwrap();
+            let position = |start_time: Time, lane: i64| {
+                map.hit_objects.iter().find(|note| note.start_time == start_time && note.lane == lane).unwrap().position
+            };
+            assert_eq!(position(2000.0, 2), position(2000.0, 1), "at {time} ms");
+            assert_eq!(position(3000.0, 3), position(3000.0, 4), "at {time} ms");
+            if time < 2000.0 {
+                assert_ne!(position(2000.0, 4), position(2000.0, 1), "at {time} ms");
+            }
+        }
+        // and keep their ids when the chart is saved
+        let saved = map.to_qua_string().unwrap();
+        assert_eq!(saved.matches("TimingGroup: rael").count(), 2);
+        assert_eq!(saved.matches("TimingGroup: ghost").count(), 1);
+    }
+
+    fn note_and_line_positions(map: &mut Map) -> String {
+        // where every note and visible timing line is at the map's time
+        map.update_timing_lines(1200.0).unwrap();
+        let notes: Vec<_> = map
+            .hit_objects
+            .iter()
+            .map(|note| (note.start_position, note.start_position_tail, note.position, note.position_tail))
+            .collect();
+        let lines: Vec<_> = map.timing_lines[map.visible_timing_lines.clone()].iter().map(|line| line.current_track_position).collect();
+        format!("{notes:?}\n{lines:?}")
+    }
+
+    fn at_time(name: &str, no_sv: bool, time: Time) -> Map {
+        let mut map = Map::from_file(&Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/charts").join(name)).unwrap();
+        map.mods.no_sv = no_sv;
+        map.rate = 1.0;
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        map.update_scroll_speed();
+        map.time = time;
+        map.update_track_position(time);
+        map.update_hit_objects().unwrap();
+        map
+    }
+
+    #[test]
+    fn toggling_no_sv_places_notes_like_loading_with_it() {
+        let field_positions = set_reference_positions(None);
+        for name in ["sv_reversal.qua", "ssf.qua", "group_layers.qua"] {
+            for time in [0.0, 1500.0, 4000.0] {
+                let mut toggled = at_time(name, false, 500.0);
+                toggled.time = time;
+                toggled.toggle_no_sv(&field_positions).unwrap();
+                assert!(toggled.mods.no_sv);
+                let mut fresh = at_time(name, true, time);
+                assert_eq!(note_and_line_positions(&mut toggled), note_and_line_positions(&mut fresh), "{name} at {time} ms");
+
+                toggled.toggle_no_sv(&field_positions).unwrap();
+                let mut fresh = at_time(name, false, time);
+                assert_eq!(note_and_line_positions(&mut toggled), note_and_line_positions(&mut fresh), "{name} back at {time} ms");
+            }
+        }
+        // the SVs do move the notes
+        let with_svs = note_and_line_positions(&mut at_time("sv_reversal.qua", false, 1500.0));
+        assert_ne!(with_svs, note_and_line_positions(&mut at_time("sv_reversal.qua", true, 1500.0)));
+    }
+
+    #[test]
+    fn chart_clone_initializes_like_a_fresh_parse() {
+        let mut charts = vec![MULTI_GROUP_CHART.to_string()];
+        for name in ["group_layers.qua", "long_notes.qua", "ssf.qua", "sv_reversal.qua"] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/charts").join(name);
+            charts.push(fs::read_to_string(path).unwrap());
+        }
+        let fresh = |chart: &str| {
+            let mut map: Map = serde_yaml::from_str(chart).unwrap();
+            map.rate = 1.0;
+            initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+            map
+        };
+        for chart in &charts {
+            // played for a bit, so there's runtime state the clone mustn't keep
+            let mut played = fresh(chart);
+            for time in [0.0, 500.0, 1000.0] {
+                played.update_track_position(time);
+                played.update_hit_objects().unwrap();
+                played.handle_gameplay_key_press(time, 0);
+            }
+            let mut cloned = played.chart_clone();
+            assert_eq!(cloned.hit_stats.len(), 0);
+            cloned.rate = 1.0;
+            initialize_map(&mut cloned, &set_reference_positions(None)).unwrap();
+            let fresh = fresh(chart);
+            assert_eq!(cloned.to_qua_string().unwrap(), fresh.to_qua_string().unwrap());
+            assert_eq!(computed_fields(&cloned), computed_fields(&fresh));
+        }
+    }
+
+    #[test]
+    fn dump_of_a_played_map_holds_only_chart_data() {
+        // 50 previous positions per note would be several hundred bytes each
+        let hit_objects =
+            (0..1000).map(|index| HitObject { start_time: index as Time * 100.0, lane: index % 4 + 1, ..HitObject::default() }).collect();
+        let mut map = initialized(hit_objects);
+        for time in [0.0, 5000.0, 50_000.0] {
+            map.update_track_position(time);
+            map.update_hit_objects().unwrap();
+        }
+        let dump = serde_json::to_string_pretty(&map.chart_clone()).unwrap();
+        assert!(dump.len() < 200 * 1000, "{} bytes", dump.len());
+    }
+
+    #[test]
+    fn inspection_shows_where_svs_moved_a_note() {
+        // a 2x SV at 1000 ms and a 0.5x SSF at 1500 ms, one note on each side
+        let mut map = Map { mode: GameMode::Keys4, ..Map::default() };
+        map.timing_points.push(TimingPoint { start_time: 0.0, bpm: 60.0, time_signature: None, hidden: false });
+        map.scroll_velocities = vec![sv_point(1000.0, 2.0)];
+        map.scroll_speed_factors = vec![sv_point(1500.0, 0.5)];
+        map.hit_objects = vec![
+            HitObject { start_time: 500.0, lane: 1, ..HitObject::default() },
+            long_note(2000.0, 2500.0, 2),
+        ];
+        map.rate = 1.0;
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        map.update_track_position(0.0);
+        map.update_scroll_speed();
+        map.update_hit_objects().unwrap();
+
+        let before = map.inspect_note(0).unwrap();
+        assert_eq!((before.index, before.lane, before.timing_group.as_str()), (0, 1, DEFAULT_TIMING_GROUP_ID));
+        assert_eq!((before.sv_multiplier, before.ssf_factor, before.no_sv_delta), (1.0, 1.0, 0));
+        assert_eq!(before.start_position, 50_000);
+        assert_eq!(before.position, map.hit_objects[0].position);
+        assert!(before.snap_error < 0.01);
+        assert!(!before.lines().iter().any(|line| line.starts_with("end_time")));
+
+        let after = map.inspect_note(1).unwrap();
+        assert_eq!((after.sv_multiplier, after.ssf_factor, after.end_time), (2.0, 0.5, Some(2500.0)));
+        // 1000 ms at 1x and 1000 ms at 2x, where it'd be at 2000 ms without SVs
+        assert_eq!(after.start_position, 300_000);
+        let group = map.timing_groups.get(DEFAULT_TIMING_GROUP_ID).unwrap();
+        let hit_position = map.hit_objects[1].hit_position;
+        let no_sv_position = group.get_object_position(hit_position, 200_000, false);
+        assert_eq!(after.no_sv_delta, after.position - no_sv_position);
+        assert_ne!(after.no_sv_delta, 0);
+        assert!(after.lines().contains(&"end_time: 2500.00".to_string()));
+
+        assert!(map.inspect_note(2).is_none());
+    }
+
+    fn chart_of(hit_objects: Vec<HitObject>) -> Map {
+        let mut map = Map { hit_objects, ..Map::default() };
+        map.sort();
+        map
+    }
+
+    fn diff(a: &Map, b: &Map) -> Vec<(DiffSide, usize, Time)> {
+        diff_charts(a, b, 2.0).into_iter().map(|entry| (entry.side, entry.index, entry.start_time)).collect()
+    }
+
+    fn base_notes() -> Vec<HitObject> {
+        vec![
+            HitObject { start_time: 1000.0, lane: 1, ..HitObject::default() },
+            long_note(1500.0, 2000.0, 2),
+            HitObject { start_time: 2000.0, lane: 3, ..HitObject::default() },
+        ]
+    }
+
+    #[test]
+    fn identical_charts_have_no_differences() {
+        let chart = chart_of(base_notes());
+        assert!(diff(&chart, &chart.clone()).is_empty());
+        // within the tolerance is the same note
+        let mut nudged = base_notes();
+        nudged[0].start_time += 2.0;
+        assert!(diff(&chart, &chart_of(nudged)).is_empty());
+    }
+
+    #[test]
+    fn moved_note_is_missing_from_one_side_and_added_to_the_other() {
+        let mut moved = base_notes();
+        moved[2].start_time = 2250.0;
+        use DiffSide::{Compare, Main};
+        assert_eq!(diff(&chart_of(base_notes()), &chart_of(moved)), [(Main, 2, 2000.0), (Compare, 2, 2250.0)]);
+    }
+
+    #[test]
+    fn added_and_removed_long_notes_are_found() {
+        let mut added = base_notes();
+        added.push(long_note(500.0, 800.0, 4));
+        let (base, added) = (chart_of(base_notes()), chart_of(added));
+        // sorted, so the new LN is the first note of its chart
+        assert_eq!(diff(&base, &added), [(DiffSide::Compare, 0, 500.0)]);
+        assert_eq!(diff(&added, &base), [(DiffSide::Main, 0, 500.0)]);
+
+        let removed = chart_of(base_notes().into_iter().filter(|note| note.end_time.is_none()).collect());
+        assert_eq!(diff(&base, &removed), [(DiffSide::Main, 1, 1500.0)]);
+    }
+
+    #[test]
+    fn note_changing_lane_is_a_difference() {
+        let mut changed = base_notes();
+        changed[0].lane = 4;
+        use DiffSide::{Compare, Main};
+        assert_eq!(diff(&chart_of(base_notes()), &chart_of(changed)), [(Main, 0, 1000.0), (Compare, 0, 1000.0)]);
+    }
+
+    #[test]
+    fn map_ends_after_its_last_note_and_the_lead_out_stops_with_the_audio() {
+        let mut map = initialized(vec![HitObject { start_time: 1000.0, lane: 1, ..HitObject::default() }, long_note(1500.0, 3000.0, 2)]);
+        let judged = 3000.0 + map.judgement_windows.late(JudgementType::Miss);
+        let playable_length = |map: &mut Map, length: Time| {
+            map.length = length;
+            map.initialize_playable_length();
+            map.playable_length
+        };
+        // a long outro isn't waited through
+        assert_eq!(playable_length(&mut map, 120_000.0), judged + LEAD_OUT_TIME);
+        // no audio length, nothing to stop at
+        assert_eq!(playable_length(&mut map, 0.0), judged + LEAD_OUT_TIME);
+        // the audio ends during the lead-out
+        assert_eq!(playable_length(&mut map, judged + 500.0), judged + 500.0);
+        // longer than its audio, the LN end is still judged
+        assert_eq!(playable_length(&mut map, 2000.0), judged);
+
+        let mut empty = initialized(Vec::new());
+        assert_eq!(playable_length(&mut empty, 60_000.0), 60_000.0);
+    }
+
+    fn shiftable_chart() -> Map {
+        // something of every kind with a time, in the default group and another one
+        let chart = "\
+Mode: Keys4
+SongPreviewTime: 1500
+Bookmarks:
+- StartTime: 1500
+  Note: drop
+SoundEffects:
+- StartTime: 1500
+  Sample: 1
+TimingPoints:
+- StartTime: 100
+  Bpm: 120
+- StartTime: 2000
+  Bpm: 180
+SliderVelocities:
+- StartTime: 500
+  Multiplier: 2
+ScrollSpeedFactors:
+- StartTime: 1500
+  Multiplier: 0.5
+TimingGroups:
+  fast:
+    ScrollVelocities:
+    - StartTime: 1500
+      Multiplier: 3
+    ScrollSpeedFactors:
+    - StartTime: 2500
+      Multiplier: 2
+HitObjects:
+- StartTime: 250
+  Lane: 1
+  KeySounds: []
+- StartTime: 1500
+  Lane: 4
+  EndTime: 2500
+  TimingGroup: fast
+  KeySounds: []
+";
+        serde_yaml::from_str(chart).unwrap()
+    }
+
+    fn duplicate_sv_chart(svs: &str, group_svs: &str) -> String {
+        format!(
+            "\
+Mode: Keys4
+TimingPoints:
+- StartTime: 0
+  Bpm: 60
+SliderVelocities:
+{svs}TimingGroups:
+  slow:
+    ScrollVelocities:
+{group_svs}HitObjects:
+- StartTime: 3000
+  Lane: 1
+  KeySounds: []
+- StartTime: 3000
+  Lane: 2
+  TimingGroup: slow
+  KeySounds: []
+"
+        )
+    }
+
+    fn loaded_track_positions(name: &str, chart: &str) -> (Vec<ControlPoint>, Vec<(Position, Position)>) {
+        // the default group's SVs after loading, and both groups' track positions every 250 ms
+        let path = std::env::temp_dir().join(format!("vsrg_map_{name}_{}.qua", std::process::id()));
+        std::fs::write(&path, chart).unwrap();
+        let mut map = Map::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        map.rate = 1.0;
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        let positions = (0..=14)
+            .map(|step| {
+                map.update_track_position(f64::from(step) * 250.0);
+                let position = |id: &str| map.timing_groups.get(id).unwrap().current_track_position;
+                (position(DEFAULT_TIMING_GROUP_ID), position("slow"))
+            })
+            .collect();
+        (map.timing_groups.get(DEFAULT_TIMING_GROUP_ID).unwrap().scroll_velocities.clone(), positions)
+    }
+
+    #[test]
+    fn last_point_at_a_time_wins_on_every_load() {
+        // out of time order, with two points at 1000 and at 2000 ms, and two at 500 ms in a group
+        let duplicates = duplicate_sv_chart(
+            "\
+- StartTime: 2000
+  Multiplier: 3
+- StartTime: 1000
+  Multiplier: 2
+- StartTime: 1000
+  Multiplier: 0.5
+- StartTime: 2000
+  Multiplier: 1
+",
+            "    - StartTime: 500\n      Multiplier: 4\n    - StartTime: 500\n      Multiplier: 0.25\n",
+        );
+        let winners = duplicate_sv_chart(
+            "\
+- StartTime: 1000
+  Multiplier: 0.5
+- StartTime: 2000
+  Multiplier: 1
+",
+            "    - StartTime: 500\n      Multiplier: 0.25\n",
+        );
+        let (svs, positions) = loaded_track_positions("duplicates", &duplicates);
+        let multipliers: Vec<_> = svs.iter().map(|sv| (sv.start_time, sv.multiplier)).collect();
+        assert_eq!(multipliers, vec![(1000.0, 0.5), (2000.0, 1.0)]);
+        let (_, expected) = loaded_track_positions("winners", &winners);
+        assert_eq!(positions, expected);
+        // 1 s at 1x, 1 s at 0.5x, then 1x again; the group is at 0.25x from 500 ms
+        assert_eq!(positions[12], (250_000, 112_500));
+        for load in 0..3 {
+            assert_eq!(loaded_track_positions("duplicates", &duplicates).1, positions, "load {load}");
+        }
+    }
+
+    fn all_times(map: &Map) -> Vec<(String, Time)> {
+        // every time in the chart, labelled with where it's from
+        let mut times = Vec::new();
+        for note in &map.hit_objects {
+            times.push(("note".to_string(), note.start_time));
+            times.extend(note.end_time.map(|end_time| ("LN end".to_string(), end_time)));
+        }
+        times.extend(map.timing_points.iter().map(|tp| ("timing point".to_string(), tp.start_time)));
+        times.extend(map.scroll_velocities.iter().map(|sv| ("SV".to_string(), sv.start_time)));
+        times.extend(map.scroll_speed_factors.iter().map(|ssf| ("SSF".to_string(), ssf.start_time)));
+        for (id, group) in map.timing_groups.iter() {
+            times.extend(group.scroll_velocities.iter().map(|sv| (format!("SV ({id})"), sv.start_time)));
+            times.extend(group.scroll_speed_factors.iter().map(|ssf| (format!("SSF ({id})"), ssf.start_time)));
+        }
+        times.extend(map.sound_effects.iter().map(|sound_effect| ("sound effect".to_string(), sound_effect.start_time)));
+        times.extend(map.bookmarks.iter().map(|bookmark| ("bookmark".to_string(), bookmark["StartTime"].as_f64().unwrap())));
+        times.extend(map.song_preview_time.map(|time| ("preview".to_string(), time)));
+        times
+    }
+
+    #[test]
+    fn shifting_moves_every_time_in_the_chart() {
+        let original = shiftable_chart();
+        let kinds: Vec<String> = all_times(&original).into_iter().map(|(kind, _)| kind).collect();
+        for kind in ["LN end", "SV", "SSF", "SV (fast)", "SSF (fast)", "timing point", "bookmark", "sound effect", "preview"] {
+            assert!(kinds.iter().any(|other| other == kind), "the chart has no {kind}");
+        }
+
+        let mut shifted = original.clone();
+        shifted.shift_times(250.0, None, false).unwrap();
+        for ((kind, before), (_, after)) in all_times(&original).into_iter().zip(all_times(&shifted)) {
+            assert_eq!(after, before + 250.0, "{kind} at {before} ms");
+        }
+        // the bookmark's other fields are kept
+        assert_eq!(shifted.bookmarks[0]["Note"].as_str(), Some("drop"));
+    }
+
+    #[test]
+    fn shifting_a_range_moves_only_what_starts_in_it() {
+        let original = shiftable_chart();
+        let mut shifted = original.clone();
+        shifted.shift_times(100.0, Some((1200.0, 1800.0)), false).unwrap();
+        for ((kind, before), (_, after)) in all_times(&original).into_iter().zip(all_times(&shifted)) {
+            let expected = if (1200.0..=1800.0).contains(&before) { before + 100.0 } else { before };
+            // LN ends go with their heads
+            if kind != "LN end" {
+                assert_eq!(after, expected, "{kind} at {before} ms");
+            }
+        }
+        assert_eq!((shifted.hit_objects[1].start_time, shifted.hit_objects[1].end_time), (1600.0, Some(2600.0)));
+    }
+
+    #[test]
+    fn shifting_before_0_is_an_error_unless_clamped() {
+        let original = shiftable_chart();
+        let earliest = all_times(&original).into_iter().map(|(_, time)| time).fold(f64::INFINITY, f64::min);
+        let mut shifted = original.clone();
+        let error = shifted.shift_times(-earliest - 1.0, None, false).unwrap_err().to_string();
+        assert!(error.contains("before 0"), "{error}");
+        assert_eq!(all_times(&shifted), all_times(&original));
+
+        // only the preview time would end up before 0
+        let mut preview_only = original.clone();
+        preview_only.song_preview_time = Some(10.0);
+        assert!(preview_only.shift_times(-20.0, Some((0.0, 100.0)), false).is_err());
+
+        shifted.shift_times(-1500.0, None, true).unwrap();
+        for ((kind, before), (_, after)) in all_times(&original).into_iter().zip(all_times(&shifted)) {
+            assert_eq!(after, (before - 1500.0).max(0.0), "{kind} at {before} ms");
+        }
+    }
+
+    fn control_point_chart(svs: bool, ssfs: bool) -> Map {
+        // 2x then 0.5x at 1000 and 2000 ms, as SVs and/or SSFs
+        let mut map = Map { mode: GameMode::Keys4, ..Map::default() };
+        map.timing_points.push(TimingPoint { start_time: 0.0, bpm: 60.0, time_signature: None, hidden: false });
+        map.hit_objects.push(HitObject { start_time: 3000.0, lane: 1, ..HitObject::default() });
+        let points = vec![sv_point(1000.0, 2.0), sv_point(2000.0, 0.5)];
+        if svs {
+            map.scroll_velocities = points.clone();
+        }
+        if ssfs {
+            map.scroll_speed_factors = points;
+        }
+        map.rate = 1.0;
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        map
+    }
+
+    fn position_and_factor(map: &mut Map, time: Time) -> (Position, f64) {
+        map.update_track_position(time);
+        let group = map.timing_groups.get(DEFAULT_TIMING_GROUP_ID).unwrap();
+        (group.current_track_position, group.current_ssf_factor)
+    }
+
+    #[test]
+    fn svs_and_ssfs_work_with_and_without_each_other() {
+        // before, between and after the points
+        let times = [500.0, 1500.0, 2500.0];
+        let plain_positions = [50_000, 150_000, 250_000];
+        let sv_positions = [50_000, 200_000, 325_000];
+        let ssf_factors = [1.0, 1.25, 0.5];
+        for (svs, ssfs) in [(false, false), (true, false), (false, true), (true, true)] {
+            let mut map = control_point_chart(svs, ssfs);
+            for (index, &time) in times.iter().enumerate() {
+                let expected_position = if svs { sv_positions[index] } else { plain_positions[index] };
+                let expected_factor = if ssfs { ssf_factors[index] } else { 1.0 };
+                assert_eq!(
+                    position_and_factor(&mut map, time),
+                    (expected_position, expected_factor),
+                    "SVs: {svs}, SSFs: {ssfs}, at {time} ms"
+                );
+                // the mods take either away
+                map.mods.no_sv = true;
+                map.mods.no_ssf = true;
+                assert_eq!(position_and_factor(&mut map, time), (plain_positions[index], 1.0));
+                map.mods.no_sv = false;
+                map.mods.no_ssf = false;
+            }
+            // and the note is placed, whichever lists there are
+            assert_eq!(map.hit_objects[0].start_position, if svs { 350_000 } else { 300_000 });
+            map.update_scroll_speed();
+            map.update_hit_objects().unwrap();
+        }
+    }
+
+    #[test]
+    fn each_mod_has_its_own_short_code() {
+        let single = |set: fn(&mut Mods)| {
+            let mut mods = Mods { seed: 42, ..Mods::default() };
+            set(&mut mods);
+            mods.short_codes()
+        };
+        assert_eq!(single(|mods| mods.mirror = true), ["MR"]);
+        assert_eq!(single(|mods| mods.no_sv = true), ["NSV"]);
+        assert_eq!(single(|mods| mods.no_ssf = true), ["NSSF"]);
+        assert_eq!(single(|mods| mods.autoplay = true), ["AP"]);
+        assert_eq!(single(|mods| mods.random = true), ["RD:42"]);
+        assert_eq!(single(|mods| mods.no_ln = true), ["NLN"]);
+        // options that aren't mods have none
+        assert!(single(|mods| mods.debug = true).is_empty());
+        assert!(single(|mods| mods.no_ui = true).is_empty());
+        assert!(single(|_| {}).is_empty());
+    }
+
+    #[test]
+    fn watermark_lists_the_rate_and_mods_in_order() {
+        let mut mods = Mods::default();
+        assert_eq!(mods.watermark(1.0), "1.0x");
+        assert_eq!(mods.watermark(1.1), "1.1x");
+        assert_eq!(mods.watermark(0.85), "0.85x");
+        assert_eq!(mods.watermark(2.0), "2.0x");
+        assert_eq!(mods.watermark(10.0), "10.0x");
+        // two decimals at most
+        assert_eq!(mods.watermark(1.234), "1.23x");
+
+        // always in the same order, whichever were turned on first
+        mods.no_ln = true;
+        mods.random = true;
+        mods.seed = 7;
+        mods.mirror = true;
+        assert_eq!(mods.watermark(1.5), "1.5x | MR RD:7 NLN");
+        mods.no_ssf = true;
+        mods.autoplay = true;
+        mods.no_sv = true;
+        mods.debug = true;
+        assert_eq!(mods.watermark(0.5), "0.5x | MR NSV NSSF AP RD:7 NLN");
+    }
+
+    #[test]
+    fn control_points_leave_out_default_values_like_quaver() {
+        let points: Vec<ControlPoint> = serde_yaml::from_str(
+            "\
+- StartTime: 1000
+- Multiplier: 2.5
+- StartTime: 2000
+  Multiplier: 0
+- StartTime: 3000
+  Multiplier: 1.5
+",
+        )
+        .unwrap();
+        let values: Vec<_> = points.iter().map(|point| (point.start_time, point.multiplier)).collect();
+        // a missing multiplier is a 0x stop, not 1x, and a missing start time is the start of the chart
+        assert_eq!(values, [(1000.0, 0.0), (0.0, 2.5), (2000.0, 0.0), (3000.0, 1.5)]);
+    }
+
+    fn frozen_sv_warnings(name: &str, frozen_sv_warning: f64) -> Vec<String> {
+        // the stop warnings from loading a chart with a 7 s stop (a point without a multiplier, then an
+        // explicit 0x) and a 1 s one in the default group, one after its last note, and a group frozen from the start
+        let chart = "\
+Mode: Keys4
+TimingPoints:
+- StartTime: 0
+  Bpm: 60
+SliderVelocities:
+- StartTime: 1000
+- StartTime: 2000
+  Multiplier: 0
+- StartTime: 8000
+  Multiplier: 1
+- StartTime: 9000
+  Multiplier: 0
+- StartTime: 10000
+  Multiplier: 1
+- StartTime: 20000
+TimingGroups:
+  still:
+    InitialScrollVelocity: 0
+HitObjects:
+- StartTime: 12000
+  Lane: 1
+  KeySounds: []
+- StartTime: 6000
+  Lane: 2
+  TimingGroup: still
+  KeySounds: []
+";
+        set_skin(Skin { frozen_sv_warning, ..DEFAULT_SKIN });
+        let path = std::env::temp_dir().join(format!("vsrg_map_{name}_{}.qua", std::process::id()));
+        std::fs::write(&path, chart).unwrap();
+        logger::take_warnings();
+        Map::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        set_skin(DEFAULT_SKIN);
+        logger::take_warnings().into_iter().filter(|warning| warning.starts_with("SVs in")).collect()
+    }
+
+    #[test]
+    fn long_sv_stops_are_warned_about_on_load() {
+        let zero = "(0x, which is also what a point without a Multiplier is)";
+        assert_eq!(
+            frozen_sv_warnings("frozen_default", DEFAULT_SKIN.frozen_sv_warning),
+            [
+                format!("SVs in '$Default' stop the chart from 1000 to 8000 ms {zero}"),
+                format!("SVs in 'still' stop the chart from 0 to 6000 ms {zero}"),
+            ]
+        );
+        // the 1 s stop counts once the threshold is lower, and 0 turns the warning off
+        let short = frozen_sv_warnings("frozen_short", 1000.0);
+        assert_eq!(short.len(), 3);
+        assert!(short[1].contains("from 9000 to 10000 ms"), "{short:?}");
+        assert!(frozen_sv_warnings("frozen_off", 0.0).is_empty());
+    }
+
+    fn mine_and_fake_play(mine_press: Option<Time>, fake_press: bool) -> Map {
+        // notes in lane 1 at 1 and 4 s tapped on time, a mine in lane 2 at 2 s and a fake in lane 1 at
+        // 3 s, pressed if asked, then played to the end
+        let mut map = initialized(vec![
+            note(1000.0, 1),
+            HitObject { start_time: 2000.0, lane: 2, object_kind: ObjectKind::Mine, ..HitObject::default() },
+            HitObject { start_time: 3000.0, lane: 1, object_kind: ObjectKind::Fake, ..HitObject::default() },
+            note(4000.0, 1),
+        ]);
+        let mut presses = vec![(1000.0, 0), (4000.0, 0)];
+        presses.extend(mine_press.map(|time| (time, 1)));
+        if fake_press {
+            presses.push((3000.0, 0));
+        }
+        presses.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (time, key) in presses {
+            judge_at(&mut map, time);
+            map.handle_gameplay_key_press(time, key);
+            map.handle_gameplay_key_release(time + 20.0, key);
+        }
+        judge_at(&mut map, 5000.0);
+        map
+    }
+
+    fn judged_kinds(map: &Map) -> Vec<(HitKind, JudgementType)> {
+        map.hit_stats.iter().map(|stat| (stat.kind, stat.judgement)).collect()
+    }
+
+    #[test]
+    fn pressing_a_mine_is_a_miss() {
+        let map = mine_and_fake_play(Some(2030.0), false);
+        assert_eq!(
+            judged_kinds(&map),
+            [(HitKind::Note, JudgementType::Marvelous), (HitKind::Mine, JudgementType::Miss), (HitKind::Note, JudgementType::Marvelous)]
+        );
+        assert_eq!(map.hit_stats[1].lane, 2);
+        assert!(map.accuracy() < 100.0);
+        assert_eq!(map.combo, 1);
+        assert!(map.hit_objects[1].hit);
+    }
+
+    #[test]
+    fn leaving_a_mine_alone_is_nothing() {
+        // pressed too far from it counts as leaving it alone too
+        for mine_press in [None, Some(1950.0), Some(2050.0)] {
+            let map = mine_and_fake_play(mine_press, false);
+            assert_eq!(judged_kinds(&map), [(HitKind::Note, JudgementType::Marvelous); 2], "{mine_press:?}");
+            assert_eq!((map.accuracy(), map.combo), (100.0, 2));
+            assert!(!map.hit_objects[1].hit);
+        }
+    }
+
+    #[test]
+    fn fake_notes_are_never_judged() {
+        // pressed or not, the fake doesn't add a judgement, and the notes around it are untouched
+        for fake_press in [false, true] {
+            let map = mine_and_fake_play(None, fake_press);
+            assert_eq!(judged_kinds(&map), [(HitKind::Note, JudgementType::Marvelous); 2]);
+            assert_eq!((map.accuracy(), map.combo), (100.0, 2));
+            assert_eq!((map.remaining_notes(), map.remaining_judgements()), (0, 0));
+        }
+        // nor by autoplay
+        let mut map = initialized(vec![
+            note(1000.0, 1),
+            HitObject { start_time: 2000.0, lane: 2, object_kind: ObjectKind::Mine, ..HitObject::default() },
+            HitObject { start_time: 3000.0, lane: 1, object_kind: ObjectKind::Fake, ..HitObject::default() },
+        ]);
+        map.toggle_autoplay(0.0);
+        for time in (0..=40).map(|step| f64::from(step) * 100.0) {
+            judge_at(&mut map, time);
+        }
+        assert_eq!(judged_kinds(&map), [(HitKind::Note, JudgementType::Marvelous)]);
+    }
+
+    #[test]
+    fn lane_offset_only_moves_its_own_lane() {
+        // the second key registers 30 ms late; both lanes tapped 30 ms after their notes
+        let mut map = initialized(vec![note(1000.0, 1), note(1000.0, 2), long_note(2000.0, 3000.0, 2)]);
+        map.lane_offsets = vec![0.0, 30.0];
+        map.handle_gameplay_key_press(1030.0, 0);
+        map.handle_gameplay_key_release(1050.0, 0);
+        map.handle_gameplay_key_press(1030.0, 1);
+        map.handle_gameplay_key_release(1050.0, 1);
+        // so is the LN's release
+        map.handle_gameplay_key_press(2030.0, 1);
+        map.handle_gameplay_key_release(3030.0, 1);
+        judge_at(&mut map, 4000.0);
+        let judged: Vec<_> = map.hit_stats.iter().map(|stat| (stat.lane, stat.judgement, stat.offset, stat.raw_offset)).collect();
+        assert_eq!(
+            judged,
+            [
+                (1, JudgementType::Perfect, -30.0, -30.0),
+                (2, JudgementType::Marvelous, 0.0, -30.0),
+                (2, JudgementType::Marvelous, 0.0, -30.0),
+                (2, JudgementType::Marvelous, 0.0, -30.0),
+            ]
+        );
+        // keys past the configured offsets aren't moved
+        let mut map = initialized(vec![note(1000.0, 3)]);
+        map.lane_offsets = vec![0.0, 30.0];
+        map.handle_gameplay_key_press(1030.0, 2);
+        assert_eq!((map.hit_stats[0].offset, map.hit_stats[0].raw_offset), (-30.0, -30.0));
+    }
+
+    fn stream_chart(every: Time, lanes: i64, count: usize) -> Map {
+        // a note every `every` ms, cycling through the lanes from 1
+        Map {
+            hit_objects: (0..count).map(|index| note(index as Time * every, index as i64 % lanes + 1)).collect(),
+            ..Map::default()
+        }
+    }
+
+    #[test]
+    fn density_of_synthetic_charts() {
+        // 10 notes a second over four lanes: 400 ms from a note to the next in its lane
+        assert_eq!(stream_chart(100.0, 4, 100).note_density(), Some(NoteDensity { busy_nps: 10.0, shortest_lane_gap: Some(400.0) }));
+        // the busy parts are the 95th percentile: a one second burst in a slow chart doesn't count
+        let mut burst = stream_chart(500.0, 1, 40);
+        burst.hit_objects.extend((1..=10).map(|index| note(5000.0 + f64::from(index) * 90.0, 2)));
+        burst.hit_objects.sort_by(|a, b| a.start_time.total_cmp(&b.start_time));
+        assert_eq!(burst.note_density().unwrap().busy_nps, 2.0);
+        // a hold's end counts as where the lane's next note is spaced from
+        let holds = Map { hit_objects: vec![long_note(0.0, 900.0, 1), note(1000.0, 1)], ..Map::default() };
+        assert_eq!(holds.note_density().unwrap().shortest_lane_gap, Some(100.0));
+        assert_eq!(stream_chart(100.0, 4, 1).note_density(), None);
+    }
+
+    #[test]
+    fn suggested_scroll_speed_spaces_and_fits_the_notes() {
+        let suggest = |density: &NoteDensity| Map::suggest_scroll_speed(density, 36.0, 1200.0, 1.0, 100).unwrap();
+        // 12 of 10 notes a second fit 1200 px at 1 px/ms, 142 in settings, rounded up to 10
+        let stream = NoteDensity { busy_nps: 10.0, shortest_lane_gap: Some(400.0) };
+        assert_eq!(suggest(&stream), 150.0);
+        // in a short view, jacks 125 ms apart are what needs it faster: 54 px (1.5 note heights) between them
+        let jacks = NoteDensity { busy_nps: 8.0, shortest_lane_gap: Some(125.0) };
+        let suggested = Map::suggest_scroll_speed(&jacks, 36.0, 400.0, 1.0, 100).unwrap();
+        assert!(125.0 * Map::pixels_per_ms(suggested, 1.0, 100) >= 54.0);
+        assert!(125.0 * Map::pixels_per_ms(suggested - 10.0, 1.0, 100) < 54.0);
+        // a lane gap under the busy parts' average gap is a one-off, spaced like the average
+        let one_off = NoteDensity { busy_nps: 8.0, shortest_lane_gap: Some(20.0) };
+        assert_eq!(Map::suggest_scroll_speed(&one_off, 36.0, 400.0, 1.0, 100), Some(suggested));
+        // kept within the settings there are
+        assert_eq!(suggest(&NoteDensity { busy_nps: 1000.0, shortest_lane_gap: None }), MAX_SCROLL_SPEED);
+        assert_eq!(suggest(&NoteDensity { busy_nps: 0.1, shortest_lane_gap: None }), MIN_SCROLL_SPEED);
+    }
+
+    #[test]
+    fn suggested_scroll_speed_follows_rate_normalization() {
+        let stream = NoteDensity { busy_nps: 10.0, shortest_lane_gap: Some(400.0) };
+        // normalized, a setting moves notes as fast at any rate; without it faster rates need more
+        assert_eq!(Map::suggest_scroll_speed(&stream, 36.0, 1200.0, 1.5, 100), Some(150.0));
+        assert_eq!(Map::suggest_scroll_speed(&stream, 36.0, 1200.0, 1.5, 0), Some(220.0));
+    }
+
+    #[test]
+    fn suggestions_are_offered_only_a_quarter_off_the_current_speed() {
+        use crate::scroll_suggestion::worth_offering;
+        assert!(!worth_offering(250.0, 200.0));
+        assert!(worth_offering(260.0, 200.0));
+        assert!(!worth_offering(150.0, 200.0));
+        assert!(worth_offering(140.0, 200.0));
+        // a stream at 10 notes a second is worth offering at the default 320, not at 160
+        let suggested = Map::suggest_scroll_speed(&stream_chart(100.0, 4, 100).note_density().unwrap(), 36.0, 1200.0, 1.0, 100).unwrap();
+        assert!(worth_offering(suggested, DEFAULT_SKIN.scroll_speed));
+        assert!(!worth_offering(suggested, 160.0));
+    }
+
+    fn valid_chart() -> Map {
+        // an LN in lane 1 with a note right at its end, and a note in lane 2
+        let rows = [(0.0, 1, Some(500.0)), (500.0, 1, None), (0.0, 2, None)];
+        let rows: Vec<SimpleNote> = rows.into_iter().map(|(start_time, lane, end_time)| SimpleNote { start_time, lane, end_time }).collect();
+        let map = Map::from_simple_notes(&rows, 120.0, 4);
+        assert_eq!(map.validate(), Ok(()));
+        map
+    }
+
+    fn validation_messages(map: &Map) -> Vec<String> {
+        map.validate().err().unwrap_or_default().iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn notes_outside_the_lanes_are_invalid() {
+        let mut map = valid_chart();
+        map.hit_objects.push(note(1000.0, 0));
+        map.hit_objects.push(note(1500.0, 5));
+        assert_eq!(
+            map.validate(),
+            Err(vec![
+                MapValidationError::LaneOutOfRange { index: 3, start_time: 1000.0, lane: 0, key_count: 4 },
+                MapValidationError::LaneOutOfRange { index: 4, start_time: 1500.0, lane: 5, key_count: 4 },
+            ])
+        );
+        assert_eq!(validation_messages(&map)[1], "note 4 at 1500 ms is in lane 5, outside 1-4");
+    }
+
+    #[test]
+    fn lns_have_to_end_after_they_start() {
+        let mut map = valid_chart();
+        map.hit_objects.push(long_note(1000.0, 1000.0, 3));
+        map.hit_objects.push(long_note(2000.0, 1500.0, 4));
+        assert_eq!(
+            map.validate(),
+            Err(vec![
+                MapValidationError::LnEndBeforeStart { index: 3, start_time: 1000.0, end_time: 1000.0 },
+                MapValidationError::LnEndBeforeStart { index: 4, start_time: 2000.0, end_time: 1500.0 },
+            ])
+        );
+        assert_eq!(validation_messages(&map)[1], "LN 4 at 2000 ms ends at 1500 ms, not after it starts");
+    }
+
+    #[test]
+    fn notes_in_a_lane_cant_overlap() {
+        let mut map = valid_chart();
+        // inside the LN, and stacked on the note in lane 2
+        map.hit_objects.push(note(250.0, 1));
+        map.hit_objects.push(note(0.0, 2));
+        // a mine on a note is fine
+        map.hit_objects.push(HitObject { start_time: 500.0, lane: 1, object_kind: ObjectKind::Mine, ..HitObject::default() });
+        assert_eq!(
+            map.validate(),
+            Err(vec![
+                MapValidationError::OverlappingNotes { lane: 1, first: 0.0, second: 250.0 },
+                MapValidationError::OverlappingNotes { lane: 2, first: 0.0, second: 0.0 },
+            ])
+        );
+        assert_eq!(validation_messages(&map)[0], "note at 250 ms in lane 1 overlaps the one at 0 ms");
+    }
+
+    #[test]
+    fn sv_multipliers_have_to_be_numbers() {
+        let map: Map = serde_yaml::from_str(
+            "\
+Mode: Keys4
+TimingPoints:
+- StartTime: 0
+  Bpm: 120
+SliderVelocities:
+- StartTime: 100
+  Multiplier: .nan
+TimingGroups:
+  spin:
+    ScrollVelocities:
+    - StartTime: 200
+      Multiplier: 2
+    - StartTime: 300
+      Multiplier: .nan
+",
+        )
+        .unwrap();
+        assert_eq!(
+            map.validate(),
+            Err(vec![
+                MapValidationError::NanSvMultiplier { group: DEFAULT_TIMING_GROUP_ID.to_string(), start_time: 100.0 },
+                MapValidationError::NanSvMultiplier { group: "spin".to_string(), start_time: 300.0 },
+            ])
+        );
+        assert_eq!(validation_messages(&map)[1], "SV at 300 ms (spin) has no number for a multiplier");
+    }
+
+    #[test]
+    fn bpms_of_0_and_nan_are_invalid() {
+        let mut map = valid_chart();
+        for (start_time, bpm) in [(1000.0, 0.0), (2000.0, f64::NAN), (3000.0, -120.0)] {
+            map.timing_points.push(TimingPoint { start_time, bpm, time_signature: None, hidden: false });
+        }
+        let errors = map.validate().unwrap_err();
+        // negative bpms play (counted as positive), so they're left alone
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0], MapValidationError::InvalidBpm { start_time: 1000.0, bpm: 0.0 });
+        assert!(matches!(errors[1], MapValidationError::InvalidBpm { start_time, bpm } if start_time == 2000.0 && bpm.is_nan()));
+        assert_eq!(validation_messages(&map), ["timing point at 1000 ms has a BPM of 0", "timing point at 2000 ms has a BPM of NaN"]);
+    }
+}
@@ -0,0 +1,48 @@
+// chart formats other than quaver's .qua, each read into a Map like a parsed .qua would be
+pub mod osu;
+pub mod stepmania;
+
+use crate::map::Map;
+use anyhow::Result;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChartFormat {
+    Qua,
+    Osu,       // osu!mania
+    StepMania, // .sm and .ssc
+}
+
+impl ChartFormat {
+    pub fn of(path: &Path) -> Option<Self> {
+        // by extension, None for anything that isn't a chart
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "qua" => Some(Self::Qua),
+            "osu" => Some(Self::Osu),
+            "sm" | "ssc" => Some(Self::StepMania),
+            _ => None,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Qua => "Quaver",
+            Self::Osu => "osu!",
+            Self::StepMania => "StepMania",
+        }
+    }
+}
+
+pub fn is_chart_path(path: &Path) -> bool {
+    ChartFormat::of(path).is_some()
+}
+
+pub fn parse_converted(format: ChartFormat, path: &Path, content: &str) -> Result<Map> {
+    // a chart in one of the other formats; .qua files are parsed by Map::from_file itself
+    match format {
+        ChartFormat::Osu => osu::parse_osu(content),
+        ChartFormat::StepMania => stepmania::parse_stepmania(content, path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("ssc"))),
+        ChartFormat::Qua => Ok(serde_yaml::from_str(content)?),
+    }
+}
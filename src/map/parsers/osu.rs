@@ -0,0 +1,163 @@
+use crate::map::{ControlPoint, GameMode, HitObject, Map, TimeSignature, TimingPoint};
+use crate::utils::Time;
+use anyhow::{anyhow, bail, Result};
+
+// osu!mania charts (.osu, file format v14 and the versions close to it). Only what a .qua has is
+// read: metadata, timing points and notes. Inherited timing points become SVs, and every
+// uninherited one resets the SV to 1x like it does in osu!. Hit sounds and storyboards are left out
+
+// width of the osu! playfield in osu!pixels, a note's column is its x across it
+const PLAYFIELD_WIDTH: f64 = 512.0;
+// osu! only honors inherited multipliers within 0.1x-10x
+const MIN_MULTIPLIER: f64 = 0.1;
+const MAX_MULTIPLIER: f64 = 10.0;
+// the type bit of a mania hold note
+const HOLD_NOTE: i64 = 128;
+// "Mode" of osu!mania charts
+const MANIA_MODE: i64 = 3;
+
+fn parse_number<T: std::str::FromStr>(field: Option<&str>, what: &str) -> Result<T> {
+    let field = field.map(str::trim).ok_or_else(|| anyhow!("{what} is missing"))?;
+    field.parse().map_err(|_| anyhow!("{what} '{field}' isn't a number"))
+}
+
+pub fn column_lane(x: f64, key_count: i64) -> i64 {
+    // the 1-indexed lane of a note at x, how osu! splits the playfield into columns
+    let column = (x * key_count as f64 / PLAYFIELD_WIDTH).floor() as i64;
+    column.clamp(0, key_count - 1) + 1
+}
+
+pub fn inherited_multiplier(beat_length: f64) -> f64 {
+    // inherited points store the SV as a negative percentage, -50 is 2x
+    if beat_length < 0.0 {
+        (-100.0 / beat_length).clamp(MIN_MULTIPLIER, MAX_MULTIPLIER)
+    } else {
+        1.0
+    }
+}
+
+fn parse_timing_point(line: &str, timing_points: &mut Vec<TimingPoint>, scroll_velocities: &mut Vec<ControlPoint>) -> Result<()> {
+    let mut fields = line.split(',');
+    let start_time: Time = parse_number(fields.next(), "time")?;
+    let beat_length: f64 = parse_number(fields.next(), "beat length")?;
+    let meter: i64 = fields.next().map_or(Ok(4), |field| parse_number(Some(field), "meter"))?;
+    // sample set, sample index and volume aren't used
+    let uninherited = match fields.nth(3).map(str::trim) {
+        Some("0") => false,
+        Some(_) => true,
+        // files from before the field was added tell them apart by the sign
+        None => beat_length >= 0.0,
+    };
+    let current_multiplier = scroll_velocities.last().map_or(1.0, |sv| sv.multiplier);
+    if uninherited {
+        timing_points.push(TimingPoint {
+            start_time,
+            bpm: 60000.0 / beat_length,
+            time_signature: Some(if meter == 3 { TimeSignature::Triple } else { TimeSignature::Quadruple }),
+            hidden: false,
+        });
+        if current_multiplier != 1.0 {
+            scroll_velocities.push(ControlPoint { start_time, multiplier: 1.0, length: None, cumulative_position: 0 });
+        }
+    } else {
+        let multiplier = inherited_multiplier(beat_length);
+        match scroll_velocities.last_mut() {
+            // replaces the reset of the uninherited point it's with (or an SV at the same time)
+            Some(last) if last.start_time == start_time => last.multiplier = multiplier,
+            _ => scroll_velocities.push(ControlPoint { start_time, multiplier, length: None, cumulative_position: 0 }),
+        }
+    }
+    Ok(())
+}
+
+fn parse_hit_object(line: &str, key_count: i64) -> Result<HitObject> {
+    let mut fields = line.split(',');
+    let x: f64 = parse_number(fields.next(), "x")?;
+    let start_time: Time = parse_number(fields.nth(1), "time")?;
+    let kind: i64 = parse_number(fields.next(), "type")?;
+    // hit sound, then the hold's end time in front of its hit sample
+    let end_time = if kind & HOLD_NOTE != 0 {
+        let end = fields.nth(1).and_then(|params| params.split(':').next());
+        let end_time: Time = parse_number(end, "hold end time")?;
+        // a hold that doesn't end after it starts is a normal note
+        (end_time > start_time).then_some(end_time)
+    } else {
+        None
+    };
+    Ok(HitObject { start_time, end_time, lane: column_lane(x, key_count), ..HitObject::default() })
+}
+
+pub fn parse_osu(content: &str) -> Result<Map> {
+    let mut lines = content.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with("//"));
+    let header = lines.next().unwrap_or_default().trim_start_matches('\u{feff}');
+    if !header.starts_with("osu file format v") {
+        bail!("Not an osu! chart, it doesn't start with 'osu file format'");
+    }
+
+    let mut map = Map { initial_scroll_velocity: 1.0, ..Map::default() };
+    let mut mode = 0;
+    let mut key_count: Option<i64> = None;
+    let mut section = String::new();
+    let mut timing_lines = Vec::new();
+    let mut hit_object_lines = Vec::new();
+    for line in lines {
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            section = name.to_string();
+            continue;
+        }
+        match section.as_str() {
+            "General" | "Metadata" | "Difficulty" => {
+                let Some((key, value)) = line.split_once(':') else {
+                    continue;
+                };
+                let value = value.trim();
+                let text = || (!value.is_empty()).then(|| value.to_string());
+                match key.trim() {
+                    "AudioFilename" => map.audio_file = text(),
+                    "PreviewTime" => map.song_preview_time = value.parse().ok().filter(|&time: &f64| time >= 0.0),
+                    "Mode" => mode = parse_number(Some(value), "Mode")?,
+                    "Title" => map.title = text(),
+                    "Artist" => map.artist = text(),
+                    "Creator" => map.creator = text(),
+                    "Version" => map.difficulty_name = text(),
+                    "Source" => map.source = text(),
+                    "Tags" => map.tags = text(),
+                    "BeatmapID" => map.map_id = value.parse().ok(),
+                    "BeatmapSetID" => map.map_set_id = value.parse().ok(),
+                    "CircleSize" => key_count = Some(parse_number::<f64>(Some(value), "CircleSize")?.round() as i64),
+                    _ => {}
+                }
+            }
+            "Events" => {
+                // the background is the image event at 0: 0,0,"file",x,y
+                let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+                if let ["0", "0", file, ..] = fields.as_slice() {
+                    map.background_file = Some(file.trim_matches('"').to_string());
+                }
+            }
+            "TimingPoints" => timing_lines.push(line),
+            "HitObjects" => hit_object_lines.push(line),
+            _ => {}
+        }
+    }
+
+    if mode != MANIA_MODE {
+        bail!("Only osu!mania charts (Mode: 3) can be played, this one is mode {mode}");
+    }
+    let key_count = key_count.ok_or_else(|| anyhow!("CircleSize (the key count) is missing"))?;
+    map.mode = match key_count {
+        1..=4 => GameMode::Keys4,
+        5..=7 => GameMode::Keys7,
+        _ => bail!("{key_count}K charts can't be played, only up to 7K"),
+    };
+    for (index, line) in timing_lines.iter().enumerate() {
+        parse_timing_point(line, &mut map.timing_points, &mut map.scroll_velocities)
+            .map_err(|e| anyhow!("Timing point {}: {e}", index + 1))?;
+    }
+    map.hit_objects = hit_object_lines
+        .iter()
+        .enumerate()
+        .map(|(index, line)| parse_hit_object(line, key_count).map_err(|e| anyhow!("Hit object {}: {e}", index + 1)))
+        .collect::<Result<_>>()?;
+    Ok(map)
+}
@@ -0,0 +1,279 @@
+use crate::logger;
+use crate::map::{ControlPoint, GameMode, HitObject, Map, TimeSignature, TimingPoint};
+use crate::utils::Time;
+use anyhow::{anyhow, bail, Result};
+
+// StepMania charts (.sm, and .ssc from StepMania 5). Notes are in measures of 4 beats, each split
+// into as many rows as it has lines, and timed from #BPMS and #STOPS with beat 0 at -#OFFSET
+// seconds into the audio (the offset is taken into every time, there's no separate one). Stops
+// become 0x SVs for their length. Only 4-column (dance-single) charts are read, the hardest one if
+// a file has several. #DELAYS, #WARPS and negative BPMs aren't supported
+
+const KEY_COUNT: usize = 4;
+const STEPS_TYPE: &str = "dance-single";
+const BEATS_PER_MEASURE: f64 = 4.0;
+
+// one chart in the file, its own timing (.ssc) replacing the song's
+#[derive(Debug, Default)]
+struct Chart {
+    steps_type: String,
+    difficulty: String,
+    meter: i64,
+    notes: String,
+    bpms: Option<String>,
+    stops: Option<String>,
+}
+
+fn tags(content: &str) -> Vec<(String, String)> {
+    // the #KEY:VALUE; tags in order, keys uppercased; a value runs to its ';' (or the next tag, for
+    // files missing one) and can span lines
+    let content: String = content
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .map(|line| line.split_once("//").map_or(line, |(before, _)| before))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut tags = Vec::new();
+    let mut rest = content.as_str();
+    while let Some(start) = rest.find('#') {
+        rest = &rest[start + 1..];
+        let end = rest.find(';').or_else(|| rest.find("\n#")).unwrap_or(rest.len());
+        let tag = &rest[..end];
+        rest = &rest[end..];
+        if let Some((key, value)) = tag.split_once(':') {
+            tags.push((key.trim().to_ascii_uppercase(), value.trim().to_string()));
+        }
+    }
+    tags
+}
+
+fn parse_pairs(value: &str, what: &str) -> Result<Vec<(f64, f64)>> {
+    // "beat=value,beat=value", sorted by beat
+    let mut pairs = value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (beat, value) = pair.split_once('=').ok_or_else(|| anyhow!("{what} '{pair}' isn't beat=value"))?;
+            let parse = |field: &str| {
+                field.trim().parse::<f64>().ok().filter(|number| number.is_finite()).ok_or_else(|| anyhow!("{what} '{pair}' isn't a number"))
+            };
+            Ok((parse(beat)?, parse(value)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(pairs)
+}
+
+// when each beat is played, from the BPM changes and stops
+struct Timing {
+    offset: Time, // ms of beat 0
+    bpms: Vec<(f64, f64)>,
+    stops: Vec<(f64, f64)>, // beat, seconds
+}
+
+impl Timing {
+    fn new(offset_seconds: f64, bpms: Vec<(f64, f64)>, stops: Vec<(f64, f64)>) -> Result<Self> {
+        match bpms.iter().find(|&&(_, bpm)| bpm <= 0.0) {
+            Some((beat, bpm)) => bail!("BPM {bpm} at beat {beat} isn't positive, negative BPMs (warps) aren't supported"),
+            None if bpms.is_empty() => bail!("#BPMS is empty"),
+            None => Ok(Self { offset: -offset_seconds * 1000.0, bpms, stops }),
+        }
+    }
+
+    fn bpm_at(&self, beat: f64) -> f64 {
+        self.bpms.iter().take_while(|&&(start, _)| start <= beat).last().unwrap_or(&self.bpms[0]).1
+    }
+
+    fn time_at(&self, beat: f64, include_stops_at: bool) -> Time {
+        // ms of a beat; notes on a stop's beat are played before it, include_stops_at is after
+        let mut time = self.offset;
+        let mut previous = 0.0;
+        let mut bpm = self.bpms[0].1;
+        for &(start, next_bpm) in self.bpms.iter().filter(|&&(start, _)| start > 0.0 && start < beat) {
+            time += (start - previous) * 60000.0 / bpm;
+            previous = start;
+            bpm = next_bpm;
+        }
+        time += (beat - previous) * 60000.0 / bpm;
+        let stopped: f64 = self
+            .stops
+            .iter()
+            .filter(|&&(start, _)| start < beat || (include_stops_at && start == beat))
+            .map(|&(_, seconds)| seconds * 1000.0)
+            .sum();
+        time + stopped
+    }
+
+    fn timing_points(&self) -> Vec<TimingPoint> {
+        // one at every BPM change, and at the end of every stop so the beat snaps after it line up
+        let mut beats: Vec<f64> = self.bpms.iter().map(|&(beat, _)| beat.max(0.0)).chain(self.stops.iter().map(|&(beat, _)| beat)).collect();
+        beats.sort_by(f64::total_cmp);
+        beats.dedup();
+        beats
+            .into_iter()
+            .map(|beat| TimingPoint {
+                start_time: self.time_at(beat, true),
+                bpm: self.bpm_at(beat),
+                time_signature: Some(TimeSignature::Quadruple),
+                hidden: false,
+            })
+            .collect()
+    }
+
+    fn stop_velocities(&self) -> Vec<ControlPoint> {
+        // 0x for the length of each stop, back to 1x after it
+        let point = |start_time, multiplier| ControlPoint { start_time, multiplier, length: None, cumulative_position: 0 };
+        self.stops
+            .iter()
+            .filter(|&&(_, seconds)| seconds > 0.0)
+            .flat_map(|&(beat, _)| [point(self.time_at(beat, false), 0.0), point(self.time_at(beat, true), 1.0)])
+            .collect()
+    }
+}
+
+fn strip_keysounds(row: &str) -> String {
+    // .ssc rows can have {attacks} and [keysounds] after a note
+    let mut stripped = String::new();
+    let mut depth = 0;
+    for character in row.chars() {
+        match character {
+            '{' | '[' => depth += 1,
+            '}' | ']' => depth -= 1,
+            _ if depth == 0 => stripped.push(character),
+            _ => {}
+        }
+    }
+    stripped
+}
+
+fn parse_notes(notes: &str, timing: &Timing) -> Result<Vec<HitObject>> {
+    let mut hit_objects = Vec::new();
+    let mut hold_heads: [Option<usize>; KEY_COUNT] = [None; KEY_COUNT]; // index of each lane's unfinished hold
+    for (measure_index, measure) in notes.split(',').enumerate() {
+        let rows: Vec<String> = measure.lines().map(|line| strip_keysounds(line.trim())).filter(|row| !row.is_empty()).collect();
+        for (row_index, row) in rows.iter().enumerate() {
+            if row.chars().count() != KEY_COUNT {
+                bail!("Measure {}: row '{row}' doesn't have {KEY_COUNT} columns", measure_index + 1);
+            }
+            let beat = (measure_index as f64 + row_index as f64 / rows.len() as f64) * BEATS_PER_MEASURE;
+            let time = timing.time_at(beat, false);
+            for (column, note) in row.chars().enumerate() {
+                let lane = column as i64 + 1;
+                match note {
+                    // taps and lifts
+                    '1' | 'L' => hit_objects.push(HitObject { start_time: time, lane, ..HitObject::default() }),
+                    // hold and roll heads, rolls play as holds
+                    '2' | '4' => {
+                        hold_heads[column] = Some(hit_objects.len());
+                        hit_objects.push(HitObject { start_time: time, lane, ..HitObject::default() });
+                    }
+                    '3' => {
+                        // a tail without a head is left out
+                        if let Some(head) = hold_heads[column].take() {
+                            let hit_object = &mut hit_objects[head];
+                            hit_object.end_time = (time > hit_object.start_time).then_some(time);
+                        }
+                    }
+                    // empty, mines, fakes and the rest of what isn't played
+                    _ => {}
+                }
+            }
+        }
+    }
+    // a head that never ends stays a normal note
+    Ok(hit_objects)
+}
+
+fn charts(tags: &[(String, String)], is_ssc: bool) -> Result<Vec<Chart>> {
+    let mut charts = Vec::new();
+    for (key, value) in tags {
+        if !is_ssc {
+            if key == "NOTES" {
+                // steps type:description:difficulty:meter:groove radar:notes
+                let fields: Vec<&str> = value.splitn(6, ':').map(str::trim).collect();
+                let [steps_type, _, difficulty, meter, _, notes] = fields[..] else {
+                    bail!("#NOTES has {} fields instead of 6", fields.len());
+                };
+                charts.push(Chart {
+                    steps_type: steps_type.to_string(),
+                    difficulty: difficulty.to_string(),
+                    meter: meter.parse().unwrap_or(0),
+                    notes: notes.to_string(),
+                    ..Chart::default()
+                });
+            }
+            continue;
+        }
+        if key == "NOTEDATA" {
+            charts.push(Chart::default());
+            continue;
+        }
+        // tags before the first #NOTEDATA are the song's
+        let Some(chart) = charts.last_mut() else {
+            continue;
+        };
+        match key.as_str() {
+            "STEPSTYPE" => chart.steps_type = value.clone(),
+            "DIFFICULTY" => chart.difficulty = value.clone(),
+            "METER" => chart.meter = value.parse().unwrap_or(0),
+            "NOTES" => chart.notes = value.clone(),
+            "BPMS" => chart.bpms = Some(value.clone()),
+            "STOPS" => chart.stops = Some(value.clone()),
+            _ => {}
+        }
+    }
+    Ok(charts)
+}
+
+pub fn parse_stepmania(content: &str, is_ssc: bool) -> Result<Map> {
+    let tags = tags(content);
+    let mut map = Map { initial_scroll_velocity: 1.0, mode: GameMode::Keys4, ..Map::default() };
+    let mut offset = 0.0;
+    let mut bpms = String::new();
+    let mut stops = String::new();
+    for (key, value) in &tags {
+        let text = || (!value.is_empty()).then(|| value.clone());
+        match key.as_str() {
+            // the first of each, a .ssc's charts can repeat them
+            "TITLE" if map.title.is_none() => map.title = text(),
+            "ARTIST" if map.artist.is_none() => map.artist = text(),
+            "CREDIT" if map.creator.is_none() => map.creator = text(),
+            "GENRE" if map.genre.is_none() => map.genre = text(),
+            "MUSIC" if map.audio_file.is_none() => map.audio_file = text(),
+            "BACKGROUND" if map.background_file.is_none() => map.background_file = text(),
+            "BANNER" if map.banner_file.is_none() => map.banner_file = text(),
+            "SAMPLESTART" if map.song_preview_time.is_none() => {
+                map.song_preview_time = value.parse::<f64>().ok().filter(|&seconds| seconds >= 0.0).map(|seconds| seconds * 1000.0);
+            }
+            "OFFSET" => offset = value.parse().map_err(|_| anyhow!("#OFFSET '{value}' isn't a number"))?,
+            "BPMS" if bpms.is_empty() => bpms = value.clone(),
+            "STOPS" | "FREEZES" if stops.is_empty() => stops = value.clone(),
+            _ => {}
+        }
+    }
+
+    let charts = charts(&tags, is_ssc)?;
+    let playable: Vec<&Chart> = charts.iter().filter(|chart| chart.steps_type.eq_ignore_ascii_case(STEPS_TYPE)).collect();
+    let Some(chart) = playable.iter().max_by_key(|chart| chart.meter) else {
+        let mut types: Vec<&str> = charts.iter().map(|chart| chart.steps_type.as_str()).collect();
+        types.dedup();
+        bail!("No {STEPS_TYPE} (4-key) chart, only: {}", if types.is_empty() { "none".to_string() } else { types.join(", ") });
+    };
+    if playable.len() > 1 {
+        let others: Vec<String> =
+            playable.iter().filter(|other| !std::ptr::eq(**other, *chart)).map(|other| format!("{} {}", other.difficulty, other.meter)).collect();
+        logger::info(&format!("Playing the {} {} chart, also in the file: {}", chart.difficulty, chart.meter, others.join(", ")));
+    }
+
+    let timing = Timing::new(
+        offset,
+        parse_pairs(chart.bpms.as_deref().unwrap_or(&bpms), "BPM")?,
+        parse_pairs(chart.stops.as_deref().unwrap_or(&stops), "Stop")?,
+    )?;
+    map.difficulty_name = Some(format!("{} {}", chart.difficulty, chart.meter));
+    map.timing_points = timing.timing_points();
+    map.scroll_velocities = timing.stop_velocities();
+    map.hit_objects = parse_notes(&chart.notes, &timing)?;
+    Ok(map)
+}
@@ -0,0 +1,198 @@
+use crate::logger;
+use crate::utils::{NoteShape, Skin};
+use anyhow::{anyhow, Result};
+use macroquad::color::Color;
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+// optional file in a map's directory with skin overrides for that map
+pub const MAP_SKIN_FILE: &str = "map_skin.toml";
+
+// skin fields a map is allowed to change, anything affecting timing (offset, hit windows) stays the player's
+const MAP_SKIN_FIELDS: &[&str] = &[
+    "note_shape",
+    "note_width",
+    "note_height",
+    "scroll_speed",
+    "wide_timing_lines",
+    "snap_colors",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct SkinOverrides {
+    pub note_shape: Option<NoteShape>,
+    pub note_width: Option<f64>,
+    pub note_height: Option<f64>,
+    pub scroll_speed: Option<f64>,
+    pub wide_timing_lines: Option<bool>,
+    pub snap_colors: Option<Vec<String>>, // "#rrggbb" per beat snap, in BEAT_SNAPS order; extra entries are ignored
+}
+
+fn parse_hex_color(hex: &str) -> Result<Color> {
+    // "#rrggbb" or "#rrggbbaa"
+    let digits = hex.trim_start_matches('#');
+    if !matches!(digits.len(), 6 | 8) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Invalid color '{hex}', expected #rrggbb or #rrggbbaa"));
+    }
+    let channel = |index: usize| {
+        digits
+            .get(index * 2..index * 2 + 2)
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            .map_or(1.0, |value| f32::from(value) / 255.0)
+    };
+    Ok(Color::new(channel(0), channel(1), channel(2), channel(3)))
+}
+
+pub fn parse_map_skin(contents: &str) -> Result<SkinOverrides> {
+    // parses a map skin file, dropping (with a warning) any field maps aren't allowed to override
+    let mut table: toml::Table = toml::from_str(contents)?;
+    table.retain(|key, _| {
+        let allowed = MAP_SKIN_FIELDS.contains(&key);
+        if !allowed {
+            logger::warning(&format!("Ignoring '{key}' in {MAP_SKIN_FILE}: maps can't override it"));
+        }
+        allowed
+    });
+    let overrides: SkinOverrides = table.try_into()?;
+    for hex in overrides.snap_colors.iter().flatten() {
+        parse_hex_color(hex)?;
+    }
+    Ok(overrides)
+}
+
+pub fn merge_skin(user_skin: &Skin, overrides: &SkinOverrides) -> Skin {
+    // the user's skin with the map's overrides on top
+    let mut skin = *user_skin;
+    if let Some(note_shape) = overrides.note_shape {
+        skin.note_shape = note_shape;
+    }
+    if let Some(note_width) = overrides.note_width {
+        skin.note_width = note_width;
+    }
+    if let Some(note_height) = overrides.note_height {
+        skin.note_height = note_height;
+    }
+    if let Some(scroll_speed) = overrides.scroll_speed {
+        skin.scroll_speed = scroll_speed;
+    }
+    if let Some(wide_timing_lines) = overrides.wide_timing_lines {
+        skin.wide_timing_lines = wide_timing_lines;
+    }
+    for (color, hex) in skin.snap_colors.iter_mut().zip(overrides.snap_colors.iter().flatten()) {
+        // already validated when parsed
+        if let Ok(parsed) = parse_hex_color(hex) {
+            *color = parsed;
+        }
+    }
+    skin
+}
+
+pub fn reload_map_skin(user_skin: &Skin, map_dir: &Path) -> Result<Option<Skin>> {
+    // the user's skin merged with the map's overrides as they are on disk now, None without a map skin file
+    Ok(load_map_skin(map_dir)?.map(|overrides| merge_skin(user_skin, &overrides)))
+}
+
+pub fn hot_reload(user_skin: &Skin, map_dir: &Path, map_skin: &mut Option<Skin>, use_map_skin: &mut bool) -> Result<Skin> {
+    // reloads the map skin from disk and returns the skin to draw with; a map skin that just appeared is
+    // used, one switched off with K stays off, and one that fails to load leaves everything as it was
+    let reloaded = reload_map_skin(user_skin, map_dir)?;
+    *use_map_skin = reloaded.is_some() && (*use_map_skin || map_skin.is_none());
+    *map_skin = reloaded;
+    Ok(map_skin.filter(|_| *use_map_skin).unwrap_or(*user_skin))
+}
+
+pub fn load_map_skin(map_dir: &Path) -> Result<Option<SkinOverrides>> {
+    // reads the map skin file from a map directory, if it has one
+    let path = map_dir.join(MAP_SKIN_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| anyhow!("Failed to read '{}': {}", path.display(), e))?;
+    parse_map_skin(&contents)
+        .map(Some)
+        .map_err(|e| anyhow!("Failed to parse '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::DEFAULT_SKIN;
+
+    #[test]
+    fn whitelisted_fields_override_the_user_skin() {
+        let overrides = parse_map_skin("note_shape = \"circles\"\nscroll_speed = 31.5\nsnap_colors = [\"#00ff00\", \"#0000ff80\"]\n").unwrap();
+        let user_skin = Skin { scroll_speed: 20.0, note_width: 120.0, ..DEFAULT_SKIN };
+        let skin = merge_skin(&user_skin, &overrides);
+        assert_eq!(skin.note_shape, NoteShape::Circles);
+        assert_eq!(skin.scroll_speed, 31.5);
+        assert_eq!(skin.snap_colors[0], Color::new(0.0, 1.0, 0.0, 1.0));
+        assert_eq!(skin.snap_colors[1], Color::new(0.0, 0.0, 1.0, 128.0 / 255.0));
+        // anything not overridden is the user's
+        assert_eq!(skin.note_width, 120.0);
+        assert_eq!(skin.snap_colors[2..], user_skin.snap_colors[2..]);
+        assert_eq!(merge_skin(&user_skin, &SkinOverrides::default()), user_skin);
+    }
+
+    #[test]
+    fn fields_maps_cant_override_are_dropped() {
+        let overrides = parse_map_skin("offset = -50.0\ndownscroll = false\nnote_height = 20.0\n").unwrap();
+        assert_eq!(overrides, SkinOverrides { note_height: Some(20.0), ..SkinOverrides::default() });
+        let skin = merge_skin(&DEFAULT_SKIN, &overrides);
+        assert_eq!((skin.offset, skin.downscroll, skin.note_height), (DEFAULT_SKIN.offset, DEFAULT_SKIN.downscroll, 20.0));
+    }
+
+    #[test]
+    fn broken_map_skins_are_errors() {
+        assert!(parse_map_skin("note_width = ").is_err());
+        assert!(parse_map_skin("note_width = \"wide\"").is_err());
+        assert!(parse_map_skin("note_shape = \"triangles\"").is_err());
+        let error = parse_map_skin("snap_colors = [\"#12345\"]").unwrap_err();
+        assert!(error.to_string().contains("Invalid color '#12345'"), "{error}");
+    }
+
+    #[test]
+    fn map_skin_file_is_optional() {
+        let dir = std::env::temp_dir().join(format!("vsrg_map_skin_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(load_map_skin(&dir).unwrap(), None);
+        fs::write(dir.join(MAP_SKIN_FILE), "wide_timing_lines = true\n").unwrap();
+        assert_eq!(load_map_skin(&dir).unwrap().unwrap().wide_timing_lines, Some(true));
+        fs::write(dir.join(MAP_SKIN_FILE), "wide_timing_lines = 3\n").unwrap();
+        assert!(load_map_skin(&dir).unwrap_err().to_string().contains(MAP_SKIN_FILE));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hot_reload_switches_to_the_new_file_and_keeps_it_when_the_next_is_broken() {
+        let dir = std::env::temp_dir().join(format!("vsrg_map_skin_reload_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let user_skin = Skin { note_width: 120.0, ..DEFAULT_SKIN };
+        let (mut map_skin, mut use_map_skin) = (None, false);
+
+        fs::write(dir.join(MAP_SKIN_FILE), "note_width = 90.0\nscroll_speed = 25.0\n").unwrap();
+        let skin = hot_reload(&user_skin, &dir, &mut map_skin, &mut use_map_skin).unwrap();
+        assert_eq!((skin.note_width, skin.scroll_speed), (90.0, 25.0));
+        assert!(use_map_skin);
+
+        fs::write(dir.join(MAP_SKIN_FILE), "note_width = 100.0\n").unwrap();
+        let skin = hot_reload(&user_skin, &dir, &mut map_skin, &mut use_map_skin).unwrap();
+        assert_eq!((skin.note_width, skin.scroll_speed), (100.0, user_skin.scroll_speed));
+
+        // a broken file is an error and the last good one stays
+        fs::write(dir.join(MAP_SKIN_FILE), "note_width = \"wide\"\n").unwrap();
+        assert!(hot_reload(&user_skin, &dir, &mut map_skin, &mut use_map_skin).is_err());
+        assert_eq!(map_skin.map(|skin| skin.note_width), Some(100.0));
+        assert!(use_map_skin);
+
+        // switched off with K it stays off, and without the file it's the user's skin
+        use_map_skin = false;
+        fs::write(dir.join(MAP_SKIN_FILE), "note_width = 80.0\n").unwrap();
+        assert_eq!(hot_reload(&user_skin, &dir, &mut map_skin, &mut use_map_skin).unwrap(), user_skin);
+        assert_eq!(map_skin.map(|skin| skin.note_width), Some(80.0));
+        fs::remove_file(dir.join(MAP_SKIN_FILE)).unwrap();
+        assert_eq!(hot_reload(&user_skin, &dir, &mut map_skin, &mut use_map_skin).unwrap(), user_skin);
+        assert_eq!(map_skin, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+use crate::logger;
+use crate::package::{find_chart_paths, read_metadata, ChartMetadata};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// the mapsets in a songs directory, one per folder, to pick from when no map is given. Only each
+// chart's header is read, so a large library scans quickly. Archives aren't looked into, they're
+// played by name
+
+#[derive(Debug, Clone)]
+pub struct MapsetEntry {
+    pub dir: PathBuf,
+    pub title: String,  // the first chart's that has one, or the folder's name
+    pub artist: String,
+    pub charts: Vec<ChartMetadata>, // sorted by path
+}
+
+fn read_mapset(dir: &Path) -> Option<MapsetEntry> {
+    // None for folders without a chart; charts that can't be read are left out
+    let paths = find_chart_paths(dir)
+        .inspect_err(|e| logger::warning(&format!("Skipping '{}': {e}", dir.display())))
+        .ok()?;
+    let charts: Vec<ChartMetadata> = paths
+        .iter()
+        .filter_map(|path| read_metadata(path).inspect_err(|e| logger::warning(&format!("Skipping chart: {e}"))).ok())
+        .collect();
+    let title = charts
+        .iter()
+        .map(|chart| &chart.title)
+        .find(|title| !title.is_empty())
+        .cloned()
+        .unwrap_or_else(|| dir.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default());
+    let artist = charts.iter().map(|chart| &chart.artist).find(|artist| !artist.is_empty()).cloned().unwrap_or_default();
+    (!charts.is_empty()).then(|| MapsetEntry { dir: dir.to_path_buf(), title, artist, charts })
+}
+
+pub fn scan_songs_dir(path: &Path) -> Vec<MapsetEntry> {
+    // every mapset directly in the songs directory, sorted by artist and title
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            logger::warning(&format!("Failed to read songs directory '{}': {}", path.display(), e));
+            return Vec::new();
+        }
+    };
+    let mut dirs: Vec<PathBuf> = entries.filter_map(Result::ok).map(|entry| entry.path()).filter(|path| path.is_dir()).collect();
+    dirs.sort();
+    let mut mapsets: Vec<MapsetEntry> = dirs.iter().filter_map(|dir| read_mapset(dir)).collect();
+    mapsets.sort_by_cached_key(|mapset| (mapset.artist.to_lowercase(), mapset.title.to_lowercase()));
+    mapsets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_chart(dir: &Path, file_name: &str, title: &str, artist: &str) {
+        fs::create_dir_all(dir).unwrap();
+        let chart = format!(
+            "Mode: Keys4\nTitle: {title}\nArtist: {artist}\nDifficultyName: Easy\nHitObjects:\n- StartTime: 500\n  Lane: 1\n  KeySounds: []\n"
+        );
+        fs::write(dir.join(file_name), chart).unwrap();
+    }
+
+    #[test]
+    fn mapsets_are_sorted_by_artist_then_title() {
+        let songs = std::env::temp_dir().join(format!("vsrg_mapset_scan_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&songs);
+        write_chart(&songs.join("1 zed"), "a.qua", "Alpha", "Zed");
+        write_chart(&songs.join("2 beta"), "a.qua", "beta", "alice");
+        write_chart(&songs.join("3 alpha"), "a.qua", "Alpha", "Alice");
+        // the first chart without a title gives way to the next one's, both are listed; the second's
+        // notes are written flow style and counted all the same
+        write_chart(&songs.join("4 two"), "a.qua", "", "Bob");
+        fs::write(
+            songs.join("4 two").join("b.qua"),
+            "Mode: Keys4\nTitle: Second\nHitObjects: [{StartTime: 500, Lane: 1}, {StartTime: 750, Lane: 2}]\n",
+        )
+        .unwrap();
+        // no title anywhere, the folder's name is used
+        write_chart(&songs.join("5 untitled"), "a.qua", "", "Carol");
+        // nothing playable, or not a folder
+        fs::create_dir_all(songs.join("6 empty")).unwrap();
+        fs::write(songs.join("6 empty").join("notes.txt"), "no chart").unwrap();
+        fs::write(songs.join("loose.qua"), "Mode: Keys4\n").unwrap();
+
+        let mapsets = scan_songs_dir(&songs);
+        let listed: Vec<(&str, &str, usize)> =
+            mapsets.iter().map(|mapset| (mapset.artist.as_str(), mapset.title.as_str(), mapset.charts.len())).collect();
+        assert_eq!(
+            listed,
+            [("Alice", "Alpha", 1), ("alice", "beta", 1), ("Bob", "Second", 2), ("Carol", "5 untitled", 1), ("Zed", "Alpha", 1)]
+        );
+        assert_eq!(mapsets[2].dir, songs.join("4 two"));
+        let note_counts: Vec<usize> = mapsets[2].charts.iter().map(|chart| chart.note_count).collect();
+        assert_eq!(note_counts, [1, 2]);
+        fs::remove_dir_all(&songs).unwrap();
+    }
+
+    #[test]
+    fn missing_songs_dir_has_no_mapsets() {
+        let songs = std::env::temp_dir().join(format!("vsrg_mapset_missing_{}", std::process::id()));
+        assert!(scan_songs_dir(&songs).is_empty());
+    }
+}
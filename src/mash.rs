@@ -0,0 +1,130 @@
+use crate::utils::Time;
+use std::collections::{HashMap, VecDeque};
+
+// default mash thresholds: more than this many presses in one lane...
+pub const DEFAULT_MASH_PRESSES: usize = 6;
+// ...within this many ms, with fewer than half as many notes there
+pub const DEFAULT_MASH_WINDOW: Time = 500.0;
+
+// counts presses per lane over a sliding window to flag mashing; purely informational, never affects judgement
+#[derive(Debug, Clone)]
+pub struct MashDetector {
+    presses: usize,                       // presses in the window needed for a burst
+    window: Time,                         // length of the sliding window in ms
+    recent_presses: HashMap<i64, VecDeque<Time>>, // lane -> press times inside the window
+    in_burst: HashMap<i64, bool>,         // lane -> whether it's currently mashing (one burst is counted once)
+    pub bursts: usize,                    // mash bursts so far
+}
+
+impl Default for MashDetector {
+    fn default() -> Self {
+        Self::new(DEFAULT_MASH_PRESSES, DEFAULT_MASH_WINDOW)
+    }
+}
+
+impl MashDetector {
+    pub fn new(presses: usize, window: Time) -> Self {
+        Self {
+            presses,
+            window,
+            recent_presses: HashMap::new(),
+            in_burst: HashMap::new(),
+            bursts: 0,
+        }
+    }
+
+    pub const fn window(&self) -> Time {
+        self.window
+    }
+
+    pub fn record_press(&mut self, lane: i64, time: Time, notes_in_window: usize) -> bool {
+        // feeds one press, with how many notes the lane has in the window; returns whether a new burst started
+        let presses = self.recent_presses.entry(lane).or_default();
+        presses.push_back(time);
+        while presses.front().is_some_and(|&press| press < time - self.window) {
+            presses.pop_front();
+        }
+
+        let in_burst = self.in_burst.entry(lane).or_default();
+        if presses.len() <= self.presses {
+            *in_burst = false;
+            return false;
+        }
+        // lots of presses, but a jack has about as many notes as presses
+        if notes_in_window * 2 >= self.presses || *in_burst {
+            return false;
+        }
+        *in_burst = true;
+        self.bursts += 1;
+        true
+    }
+
+    pub fn reset(&mut self) {
+        self.recent_presses.clear();
+        self.in_burst.clear();
+        self.bursts = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::initialize_map;
+    use crate::map::{GameMode, HitObject, Map, TimingPoint};
+    use crate::render::set_reference_positions;
+
+    fn presses(detector: &mut MashDetector, lane: i64, times: impl Iterator<Item = Time>) -> Vec<bool> {
+        // fed over empty space, no notes in the window
+        times.map(|time| detector.record_press(lane, time, 0)).collect()
+    }
+
+    #[test]
+    fn mashing_over_nothing_is_one_burst_until_it_stops() {
+        let mut detector = MashDetector::default();
+        let started = presses(&mut detector, 1, (0..40).map(|press| press as Time * 20.0));
+        assert_eq!(started.iter().filter(|&&started| started).count(), 1);
+        // the burst starts on the press after the threshold
+        assert_eq!(started.iter().position(|&started| started), Some(DEFAULT_MASH_PRESSES));
+        // a pause, then mashing again is another burst
+        presses(&mut detector, 1, (0..40).map(|press| 5000.0 + press as Time * 20.0));
+        assert_eq!(detector.bursts, 2);
+    }
+
+    #[test]
+    fn presses_just_under_the_threshold_are_not_a_burst() {
+        let mut detector = MashDetector::new(6, 500.0);
+        // 6 presses in any 500 ms, never more
+        presses(&mut detector, 1, (0..60).map(|press| press as Time * 100.0 + 1.0));
+        assert_eq!(detector.bursts, 0);
+        // lanes are counted apart
+        let mut detector = MashDetector::new(6, 500.0);
+        for press in 0..6 {
+            let time = press as Time * 10.0;
+            detector.record_press(1, time, 0);
+            detector.record_press(2, time, 0);
+        }
+        assert_eq!(detector.bursts, 0);
+    }
+
+    #[test]
+    fn jack_with_a_note_per_press_is_not_mashing() {
+        let mut map = Map::default();
+        map.mode = GameMode::Keys4;
+        map.hit_objects =
+            (0..32).map(|note| HitObject { start_time: 1000.0 + note as Time * 62.5, lane: 1, ..HitObject::default() }).collect();
+        map.timing_points.push(TimingPoint { start_time: 0.0, bpm: 240.0, time_signature: None, hidden: false });
+        map.rate = 1.0;
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        for note in 0..32 {
+            map.handle_gameplay_key_press(1000.0 + note as Time * 62.5, 0);
+        }
+        assert_eq!(map.mash_detector.bursts, 0);
+        assert_eq!(map.hit_stats.len(), 32);
+
+        // the same presses in the empty lane next to it are
+        for press in 0..32 {
+            map.handle_gameplay_key_press(1000.0 + press as Time * 62.5, 1);
+        }
+        assert_eq!(map.mash_detector.bursts, 1);
+    }
+}
@@ -0,0 +1,167 @@
+// keeps count of the decoded audio held in memory and picks what to drop once it's over a cap.
+// The music is always streamed from its file and never counted; what's counted is the one-shot
+// samples (keysounds), each decoded whole on first play. Over the cap, the samples played least
+// recently go first, down to the one played last, which is kept even if it's over the cap on its own
+// (dropping it would only decode it again on the next hit). A dropped sample that's still playing
+// finishes, its memory is freed once it does
+
+pub const DEFAULT_MEMORY_CAP_MB: f64 = 512.0;
+pub const BYTES_PER_MB: f64 = 1024.0 * 1024.0;
+
+fn mb_to_bytes(mb: f64) -> usize {
+    (mb.max(0.0) * BYTES_PER_MB) as usize
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct BudgetEntry<K> {
+    key: K,
+    bytes: usize,
+    last_used: u64, // use counter value when it was last played
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryBudget<K> {
+    cap: usize, // bytes
+    entries: Vec<BudgetEntry<K>>,
+    uses: u64, // counts every use, for the order they were used in
+}
+
+impl<K: PartialEq> MemoryBudget<K> {
+    pub const fn new(cap: usize) -> Self {
+        Self { cap, entries: Vec::new(), uses: 0 }
+    }
+
+    pub fn from_mb(cap_mb: f64) -> Self {
+        Self::new(mb_to_bytes(cap_mb))
+    }
+
+    pub fn set_cap_mb(&mut self, cap_mb: f64) {
+        self.cap = mb_to_bytes(cap_mb);
+    }
+
+    pub const fn cap(&self) -> usize {
+        self.cap
+    }
+
+    pub fn used(&self) -> usize {
+        self.entries.iter().map(|entry| entry.bytes).sum()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn track(&mut self, key: K, bytes: usize) {
+        // counts something newly held (or its new size), as used now
+        self.uses += 1;
+        let last_used = self.uses;
+        match self.entries.iter_mut().find(|entry| entry.key == key) {
+            Some(entry) => {
+                entry.bytes = bytes;
+                entry.last_used = last_used;
+            }
+            None => self.entries.push(BudgetEntry { key, bytes, last_used }),
+        }
+    }
+
+    pub fn touch(&mut self, key: &K) {
+        // marks something as used now, so it's dropped after everything used before it
+        self.uses += 1;
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.key == *key) {
+            entry.last_used = self.uses;
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        self.entries.retain(|entry| entry.key != *key);
+    }
+
+    pub fn evict(&mut self) -> Vec<K> {
+        // takes what has to be dropped to get under the cap off the books and returns it, least
+        // recently used first; the most recently used is never dropped
+        self.entries.sort_by_key(|entry| entry.last_used);
+        let mut used = self.used();
+        let mut evicted = 0;
+        while used > self.cap && evicted + 1 < self.entries.len() {
+            used -= self.entries[evicted].bytes;
+            evicted += 1;
+        }
+        self.entries.drain(..evicted).map(|entry| entry.key).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(cap: usize, entries: &[(&'static str, usize)]) -> MemoryBudget<&'static str> {
+        let mut budget = MemoryBudget::new(cap);
+        for &(key, bytes) in entries {
+            budget.track(key, bytes);
+        }
+        budget
+    }
+
+    #[test]
+    fn nothing_is_evicted_under_the_cap() {
+        let mut budget = budget(100, &[("kick", 40), ("snare", 60)]);
+        assert_eq!(budget.used(), 100);
+        assert!(budget.evict().is_empty());
+        assert_eq!(budget.len(), 2);
+    }
+
+    #[test]
+    fn least_recently_used_go_first_until_under_the_cap() {
+        let mut budget = budget(100, &[("kick", 40), ("snare", 30), ("hat", 20), ("clap", 50)]);
+        // the kick was played again, so the snare and the hat are the oldest
+        budget.touch(&"kick");
+        assert_eq!(budget.evict(), ["snare", "hat"]);
+        assert_eq!(budget.used(), 90);
+        assert_eq!(budget.len(), 2);
+    }
+
+    #[test]
+    fn tracking_again_updates_the_size_and_counts_as_a_use() {
+        let mut budget = budget(100, &[("kick", 40), ("snare", 30)]);
+        budget.track("kick", 80);
+        assert_eq!(budget.used(), 110);
+        assert_eq!(budget.evict(), ["snare"]);
+        assert_eq!(budget.len(), 1);
+    }
+
+    #[test]
+    fn the_last_used_is_kept_even_over_the_cap_on_its_own() {
+        let mut budget = budget(100, &[("kick", 10), ("long_sample", 300)]);
+        assert_eq!(budget.evict(), ["kick"]);
+        assert_eq!(budget.used(), 300);
+        assert!(budget.evict().is_empty());
+    }
+
+    #[test]
+    fn lowering_the_cap_evicts_on_the_next_check() {
+        let mut budget = budget(mb_to_bytes(DEFAULT_MEMORY_CAP_MB), &[("kick", 3 << 20), ("snare", 2 << 20)]);
+        assert!(budget.evict().is_empty());
+        budget.set_cap_mb(4.0);
+        assert_eq!(budget.cap(), 4 << 20);
+        assert_eq!(budget.evict(), ["kick"]);
+        // a negative cap is a cap of nothing
+        budget.set_cap_mb(-1.0);
+        assert_eq!(budget.cap(), 0);
+    }
+
+    #[test]
+    fn removed_and_unknown_keys_are_left_alone() {
+        let mut budget = budget(100, &[("kick", 40), ("snare", 80)]);
+        budget.touch(&"ride");
+        budget.remove(&"snare");
+        budget.remove(&"ride");
+        assert_eq!((budget.len(), budget.used()), (1, 40));
+        assert!(budget.evict().is_empty());
+        budget.remove(&"kick");
+        assert!(budget.is_empty());
+    }
+}
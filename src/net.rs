@@ -0,0 +1,165 @@
+use crate::logger;
+use crate::map::Map;
+use crate::package::extract_archive;
+use anyhow::{anyhow, bail, Result};
+use std::{
+    fs,
+    io::Read as _,
+    path::{Path, PathBuf},
+};
+
+// mapset archives (.qp) are served by id from here
+const MAPSET_DOWNLOAD_URL: &str = "https://api.quavergame.com/d/web/mapset/";
+
+// anything that can fetch a url's body, so the download path can be driven without a network
+pub trait Fetcher {
+    fn fetch(&self, url: &str) -> Result<Vec<u8>>;
+}
+
+pub struct HttpFetcher;
+
+impl Fetcher for HttpFetcher {
+    fn fetch(&self, url: &str) -> Result<Vec<u8>> {
+        let response = match ureq::get(url).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => bail!("Not found: {url}"),
+            Err(ureq::Error::Status(code, _)) => bail!("Request to {url} failed with status {code}"),
+            Err(e) => bail!("Request to {url} failed: {e}"),
+        };
+
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .read_to_end(&mut body)
+            .map_err(|e| anyhow!("Failed to read response from {url}: {e}"))?;
+        Ok(body)
+    }
+}
+
+// downloads a mapset by id into `songs_dir/<mapset_id>/`, returning the extracted directory
+pub fn download_mapset(fetcher: &impl Fetcher, mapset_id: u64, songs_dir: &Path) -> Result<PathBuf> {
+    let target_dir = songs_dir.join(mapset_id.to_string());
+    if target_dir.exists() {
+        bail!("Mapset directory {} already exists", target_dir.display());
+    }
+
+    logger::info(&format!("Downloading mapset {mapset_id}"));
+    let archive = fetcher.fetch(&format!("{MAPSET_DOWNLOAD_URL}{mapset_id}"))?;
+
+    // never leave a half-extracted mapset behind
+    if let Err(e) = extract_mapset(&archive, &target_dir) {
+        if target_dir.exists() {
+            if let Err(cleanup_error) = fs::remove_dir_all(&target_dir) {
+                logger::warning(&format!(
+                    "Failed to clean up {}: {cleanup_error}",
+                    target_dir.display()
+                ));
+            }
+        }
+        return Err(e);
+    }
+
+    Ok(target_dir)
+}
+
+// extracts a mapset archive and checks that every chart in it parses
+fn extract_mapset(archive: &[u8], target_dir: &Path) -> Result<()> {
+    let charts = extract_archive(archive, target_dir)?;
+    for chart in &charts {
+        let map = Map::from_file(chart)?;
+        logger::info(&format!(
+            "Verified chart: {} [{}] ({} notes)",
+            map.title.as_deref().unwrap_or("?"),
+            map.difficulty_name.as_deref().unwrap_or("?"),
+            map.hit_objects.len()
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+
+    const CHART: &str = "Mode: Keys4\nDifficultyName: Easy\nHitObjects:\n- StartTime: 500\n  Lane: 1\n  KeySounds: []\n";
+
+    // serves one body (or error) for every url, and remembers what was asked for
+    struct FakeFetcher {
+        body: Result<Vec<u8>, String>,
+        requested: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl FakeFetcher {
+        fn new(body: Result<Vec<u8>, String>) -> Self {
+            Self { body, requested: std::cell::RefCell::default() }
+        }
+    }
+
+    impl Fetcher for FakeFetcher {
+        fn fetch(&self, url: &str) -> Result<Vec<u8>> {
+            self.requested.borrow_mut().push(url.to_string());
+            self.body.clone().map_err(|e| anyhow!(e))
+        }
+    }
+
+    fn songs_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vsrg_net_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn zip(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in entries {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn mapset_is_downloaded_and_extracted() {
+        let songs = songs_dir("download");
+        let fetcher = FakeFetcher::new(Ok(zip(&[("easy.qua", CHART), ("audio.mp3", "")])));
+        let dir = download_mapset(&fetcher, 123, &songs).unwrap();
+        assert_eq!(dir, songs.join("123"));
+        assert_eq!(fs::read_to_string(dir.join("easy.qua")).unwrap(), CHART);
+        assert_eq!(*fetcher.requested.borrow(), [format!("{MAPSET_DOWNLOAD_URL}123")]);
+
+        // a second download doesn't touch the first
+        let error = download_mapset(&fetcher, 123, &songs).unwrap_err().to_string();
+        assert!(error.contains("already exists"), "{error}");
+        assert_eq!(fetcher.requested.borrow().len(), 1);
+        assert!(dir.join("easy.qua").exists());
+        fs::remove_dir_all(&songs).unwrap();
+    }
+
+    #[test]
+    fn missing_mapset_leaves_nothing_behind() {
+        let songs = songs_dir("missing");
+        let fetcher = FakeFetcher::new(Err("Not found: mapset 404".to_string()));
+        let error = download_mapset(&fetcher, 404, &songs).unwrap_err().to_string();
+        assert_eq!(error, "Not found: mapset 404");
+        assert_eq!(fs::read_dir(&songs).unwrap().count(), 0);
+        fs::remove_dir_all(&songs).unwrap();
+    }
+
+    #[test]
+    fn broken_downloads_are_cleaned_up() {
+        let songs = songs_dir("broken");
+        let mut truncated = zip(&[("easy.qua", CHART)]);
+        truncated.truncate(truncated.len() / 2);
+        let broken_chart = zip(&[("easy.qua", CHART), ("hard.qua", "HitObjects: [\n- StartTime: oops")]);
+        for (mapset_id, archive) in [(1, b"not a zip".to_vec()), (2, truncated), (3, broken_chart)] {
+            let fetcher = FakeFetcher::new(Ok(archive));
+            assert!(download_mapset(&fetcher, mapset_id, &songs).is_err(), "mapset {mapset_id} was downloaded");
+            // not even the chart that did parse is kept
+            assert_eq!(fs::read_dir(&songs).unwrap().count(), 0, "mapset {mapset_id} left files behind");
+        }
+        fs::remove_dir_all(&songs).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+use crate::utils::Time;
+use anyhow::{anyhow, Result};
+use rodio::{Decoder, Source};
+use std::{fs::File, io::BufReader, path::Path};
+
+// how far (ms) around the first timing point an onset is looked for
+pub const ONSET_SEARCH_WINDOW: Time = 150.0;
+// smallest difference (ms) between the audio and the chart worth suggesting an offset for
+pub const OFFSET_SUGGESTION_THRESHOLD: Time = 10.0;
+// length (ms) of the frames energy is measured over, also the detector's resolution
+const ONSET_FRAME: Time = 2.0;
+// an onset has to rise this many times more than the window's average rise
+const ONSET_PROMINENCE: f64 = 4.0;
+// and rise at least this much (mean square of the samples), so noise in near silence isn't an onset
+const ONSET_MIN_RISE: f64 = 1e-4;
+
+pub fn decode_mono(path: &Path, until: Time) -> Result<(Vec<f32>, u32)> {
+    // the audio's first `until` ms, channels averaged, and its sample rate
+    let file = File::open(path).map_err(|e| anyhow!("Failed to open '{}': {}", path.display(), e))?;
+    let decoder = Decoder::new(BufReader::new(file)).map_err(|e| anyhow!("Failed to decode '{}': {}", path.display(), e))?;
+    let channels = usize::from(decoder.channels().max(1));
+    let sample_rate = decoder.sample_rate();
+    let frames = (until.max(0.0) / 1000.0 * f64::from(sample_rate)) as usize;
+    let interleaved: Vec<f32> = decoder.convert_samples().take(frames * channels).collect();
+    let samples = interleaved
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect();
+    Ok((samples, sample_rate))
+}
+
+pub fn detect_onset(samples: &[f32], sample_rate: u32, around: Time, window: Time) -> Option<Time> {
+    // time (ms) of the sharpest energy rise within `window` ms of `around`, if one stands out
+    let frame_length = ((ONSET_FRAME / 1000.0 * f64::from(sample_rate)) as usize).max(1);
+    let frame_time = |frame: usize| (frame * frame_length) as f64 * 1000.0 / f64::from(sample_rate);
+    let energies: Vec<f64> = samples
+        .chunks(frame_length)
+        .map(|frame| frame.iter().map(|&sample| f64::from(sample).powi(2)).sum::<f64>() / frame.len() as f64)
+        .collect();
+
+    // rise in energy into each frame; the first frame rises from silence
+    let rises: Vec<(usize, f64)> = (0..energies.len())
+        .filter(|&frame| (frame_time(frame) - around).abs() <= window)
+        .map(|frame| {
+            let previous = if frame == 0 { 0.0 } else { energies[frame - 1] };
+            (frame, (energies[frame] - previous).max(0.0))
+        })
+        .collect();
+    let (frame, rise) = rises.iter().copied().max_by(|a, b| a.1.total_cmp(&b.1))?;
+    let mean_rise = rises.iter().map(|(_, rise)| rise).sum::<f64>() / rises.len() as f64;
+    (rise >= ONSET_MIN_RISE && rise >= mean_rise * ONSET_PROMINENCE).then(|| frame_time(frame))
+}
+
+pub fn suggest_offset(onset: Time, timing_point: Time, current: Time) -> Option<Time> {
+    // local offset that puts the timing point on the onset, if it's far enough from the current one
+    let suggested = timing_point - onset;
+    ((suggested - current).abs() > OFFSET_SUGGESTION_THRESHOLD).then_some(suggested)
+}
+
+pub fn suggest_local_offset(audio_path: &Path, timing_point: Time, current: Time) -> Result<Option<Time>> {
+    // looks for the first beat in the audio around the chart's first timing point
+    let (samples, sample_rate) = decode_mono(audio_path, timing_point + ONSET_SEARCH_WINDOW + ONSET_FRAME)?;
+    Ok(detect_onset(&samples, sample_rate, timing_point, ONSET_SEARCH_WINDOW)
+        .and_then(|onset| suggest_offset(onset, timing_point, current)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::TAU;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    fn silence_then_click(click: Time, length: Time) -> Vec<f32> {
+        // a 20 ms decaying 1 kHz click at `click` ms, silence everywhere else
+        let rate = f64::from(SAMPLE_RATE);
+        (0..(length / 1000.0 * rate) as usize)
+            .map(|index| {
+                let since = index as f64 / rate - click / 1000.0;
+                if (0.0..0.02).contains(&since) {
+                    ((TAU * 1000.0 * since).sin() * (1.0 - since / 0.02) * 0.8) as f32
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    fn write_wav(path: &Path, samples: &[f32]) {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for &sample in samples {
+            // the same in both channels, so averaging them gives the click back
+            for _ in 0..2 {
+                writer.write_sample((sample * f32::from(i16::MAX)) as i16).unwrap();
+            }
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn click_is_found_to_within_a_frame() {
+        let samples = silence_then_click(1037.0, 1500.0);
+        let onset = detect_onset(&samples, SAMPLE_RATE, 1000.0, ONSET_SEARCH_WINDOW).unwrap();
+        assert!((onset - 1037.0).abs() <= ONSET_FRAME, "onset at {onset}");
+    }
+
+    #[test]
+    fn clicks_outside_the_window_and_silence_have_no_onset() {
+        let samples = silence_then_click(1300.0, 1500.0);
+        assert_eq!(detect_onset(&samples, SAMPLE_RATE, 1000.0, ONSET_SEARCH_WINDOW), None);
+        assert_eq!(detect_onset(&[0.0; 44100], SAMPLE_RATE, 500.0, ONSET_SEARCH_WINDOW), None);
+        // quiet noise doesn't rise enough to count
+        let noise: Vec<f32> = (0..44100).map(|index| if index % 7 == 0 { 0.001 } else { -0.001 }).collect();
+        assert_eq!(detect_onset(&noise, SAMPLE_RATE, 500.0, ONSET_SEARCH_WINDOW), None);
+    }
+
+    #[test]
+    fn offset_is_only_suggested_past_the_threshold() {
+        // audio 30 ms late: the timing point has to be heard 30 ms later
+        assert_eq!(suggest_offset(1030.0, 1000.0, 0.0), Some(-30.0));
+        assert_eq!(suggest_offset(1030.0, 1000.0, -25.0), None);
+        assert_eq!(suggest_offset(1000.0 - OFFSET_SUGGESTION_THRESHOLD, 1000.0, 0.0), None);
+        assert_eq!(suggest_offset(1000.0 - OFFSET_SUGGESTION_THRESHOLD - 1.0, 1000.0, 0.0), Some(11.0));
+    }
+
+    #[test]
+    fn local_offset_is_suggested_from_a_wav() {
+        let path = std::env::temp_dir().join(format!("vsrg_onset_{}.wav", std::process::id()));
+        write_wav(&path, &silence_then_click(540.0, 1000.0));
+        let (samples, sample_rate) = decode_mono(&path, 800.0).unwrap();
+        let suggested = suggest_local_offset(&path, 500.0, 0.0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((samples.len(), sample_rate), (35280, SAMPLE_RATE));
+        let suggested = suggested.unwrap();
+        assert!((suggested + 40.0).abs() <= ONSET_FRAME, "suggested {suggested}");
+    }
+}
@@ -0,0 +1,85 @@
+use crate::map::Map;
+use crate::strings::tr_args;
+
+// the notes left and where the accuracy is headed, only worked out again when something is judged
+#[derive(Debug, Default)]
+pub struct PaceDisplay {
+    judged: Option<usize>, // judgements the text is for
+    text: String,
+}
+
+impl PaceDisplay {
+    pub fn text(&mut self, map: &Map) -> &str {
+        if self.judged != Some(map.hit_stats.len()) {
+            self.judged = Some(map.hit_stats.len());
+            self.text = pace_text(map);
+        }
+        &self.text
+    }
+}
+
+fn pace_text(map: &Map) -> String {
+    let backend = map.ruleset.backend();
+    let remaining = map.remaining_judgements();
+    let percent = |accuracy: f64| format!("{accuracy:.2}%");
+    tr_args(
+        "pace.summary",
+        &[
+            ("remaining", &map.remaining_notes().to_string()),
+            ("pace", &backend.projected_accuracy(&map.hit_stats, remaining).map_or_else(|| "-".to_string(), percent)),
+            ("max", &percent(backend.max_accuracy(&map.hit_stats, remaining))),
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::initialize_map;
+    use crate::map::{GameMode, HitObject, TimingPoint};
+    use crate::render::set_reference_positions;
+
+    fn tap(map: &mut Map, time: f64, key: i64) {
+        map.time = time;
+        map.update_judgements();
+        map.handle_gameplay_key_press(time, key);
+        map.handle_gameplay_key_release(time + 20.0, key);
+    }
+
+    #[test]
+    fn pace_follows_a_scripted_play() {
+        // a note per second in lanes 1 to 4, the third a second-long LN
+        let mut map = Map::default();
+        map.mode = GameMode::Keys4;
+        map.hit_objects = vec![
+            HitObject { start_time: 1000.0, lane: 1, ..HitObject::default() },
+            HitObject { start_time: 2000.0, lane: 2, ..HitObject::default() },
+            HitObject { start_time: 3000.0, end_time: Some(4000.0), lane: 3, ..HitObject::default() },
+            HitObject { start_time: 5000.0, lane: 4, ..HitObject::default() },
+        ];
+        map.timing_points.push(TimingPoint { start_time: 0.0, bpm: 60.0, time_signature: None, hidden: false });
+        map.rate = 1.0;
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        let mut pace = PaceDisplay::default();
+        assert_eq!(pace.text(&map), "4 notes left | Pace: - | Max: 100.00%");
+
+        tap(&mut map, 1000.0, 0);
+        assert_eq!(pace.text(&map), "3 notes left | Pace: 100.00% | Max: 100.00%");
+        // a great (65 points) with the LN's head and end and a note still to come: (100 + 65) / 2 on
+        // average, and (100 + 65 + 3 * 100) / 5 at best
+        tap(&mut map, 2060.0, 1);
+        assert_eq!(pace.text(&map), "2 notes left | Pace: 82.50% | Max: 93.00%");
+
+        // the LN counts as a note until its end is judged
+        map.handle_gameplay_key_press(3000.0, 2);
+        assert_eq!(pace.text(&map), "2 notes left | Pace: 88.33% | Max: 93.00%");
+        map.handle_gameplay_key_release(4000.0, 2);
+        assert_eq!(pace.text(&map), "1 notes left | Pace: 91.25% | Max: 93.00%");
+
+        // missing the last note (-50) leaves nothing to project, both are the final accuracy
+        map.time = 6000.0;
+        map.update_judgements();
+        assert_eq!(pace.text(&map), "0 notes left | Pace: 63.00% | Max: 63.00%");
+        assert_eq!(map.accuracy(), 63.0);
+    }
+}
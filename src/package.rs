@@ -0,0 +1,461 @@
+use crate::logger;
+use crate::map::parsers::{is_chart_path, parse_converted, ChartFormat};
+use crate::map::GameMode;
+use crate::qua_stream;
+use anyhow::{anyhow, bail, Result};
+use macroquad::texture::Image;
+use serde::{de::IgnoredAny, Deserialize};
+use std::{
+    fs,
+    io::Cursor,
+    path::{Component, Path, PathBuf},
+};
+
+// extensions loaded as mapset archives instead of directories
+const ARCHIVE_EXTENSIONS: [&str; 3] = ["qp", "osz", "zip"];
+
+pub fn is_archive(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ARCHIVE_EXTENSIONS.iter().any(|archive_ext| ext.eq_ignore_ascii_case(archive_ext)))
+}
+
+fn archive_hash(archive: &[u8]) -> u64 {
+    // fnv-1a, stable between builds so the cache survives updates
+    archive.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+// extracts a zip archive into target_dir, returning the charts (.qua, .osu, .sm or .ssc) in it
+pub fn extract_archive(archive: &[u8], target_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(archive))
+        .map_err(|e| anyhow!("Not a valid mapset archive: {e}"))?;
+
+    fs::create_dir_all(target_dir)?;
+    let mut charts = Vec::new();
+    for index in 0..zip.len() {
+        let mut entry = zip.by_index(index)?;
+        // skip entries that would escape the target directory
+        let Some(relative_path) = entry.enclosed_name() else {
+            logger::warning(&format!("Skipping unsafe archive entry '{}'", entry.name()));
+            continue;
+        };
+        let path = target_dir.join(relative_path);
+
+        if entry.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = fs::File::create(&path)?;
+        std::io::copy(&mut entry, &mut file)?;
+
+        if is_chart_path(&path) {
+            charts.push(path);
+        }
+    }
+
+    if charts.is_empty() {
+        bail!("Mapset archive contains no charts (.qua, .osu, .sm or .ssc)");
+    }
+    charts.sort();
+    Ok(charts)
+}
+
+// extracts an archive into cache_dir/<hash>/, reusing an earlier extraction of the same archive
+pub fn extract_cached(archive_path: &Path, cache_dir: &Path) -> Result<PathBuf> {
+    let archive = fs::read(archive_path)
+        .map_err(|e| anyhow!("Failed to read archive '{}': {}", archive_path.display(), e))?;
+    let target_dir = cache_dir.join(format!("{:016x}", archive_hash(&archive)));
+    if target_dir.is_dir() {
+        logger::info(&format!("Using cached extraction {}", target_dir.display()));
+        return Ok(target_dir);
+    }
+
+    // extract next to the target and rename, so an interrupted extraction is never reused
+    let partial_dir = target_dir.with_extension("partial");
+    if partial_dir.exists() {
+        fs::remove_dir_all(&partial_dir)?;
+    }
+    if let Err(e) = extract_archive(&archive, &partial_dir) {
+        if let Err(cleanup_error) = fs::remove_dir_all(&partial_dir) {
+            logger::warning(&format!("Failed to clean up {}: {cleanup_error}", partial_dir.display()));
+        }
+        return Err(e);
+    }
+    fs::rename(&partial_dir, &target_dir)?;
+    logger::info(&format!("Extracted {} to {}", archive_path.display(), target_dir.display()));
+    Ok(target_dir)
+}
+
+pub fn find_charts(dir: &Path) -> Result<Vec<ChartMetadata>> {
+    // metadata of every chart (.qua, .osu, .sm or .ssc) under a directory, including nested folders, sorted by path
+    find_chart_paths(dir)?.iter().map(|chart| read_metadata(chart)).collect()
+}
+
+pub fn find_chart_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut charts = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = fs::read_dir(&dir)
+            .map_err(|e| anyhow!("Failed to read map directory {}: {}", dir.display(), e))?;
+        for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+            if path.is_dir() {
+                dirs.push(path);
+            } else if is_chart_path(&path) {
+                charts.push(path);
+            }
+        }
+    }
+    charts.sort();
+    Ok(charts)
+}
+
+// the parts of a chart shown when choosing a difficulty, without building the whole map
+#[derive(Debug, Clone)]
+pub struct ChartMetadata {
+    pub path: PathBuf,
+    pub title: String,
+    pub artist: String,
+    pub creator: String,
+    pub difficulty_name: String,
+    pub key_count: i64,
+    pub note_count: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ChartHeader {
+    title: Option<String>,
+    artist: Option<String>,
+    creator: Option<String>,
+    difficulty_name: Option<String>,
+    #[serde(default)]
+    mode: GameMode,
+    #[serde(default)]
+    has_scratch_key: bool,
+    #[serde(default)]
+    hit_objects: Vec<IgnoredAny>, // only counted, and only there when not written block style
+}
+
+pub fn read_metadata(path: &Path) -> Result<ChartMetadata> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read map file '{}': {}", path.display(), e))?;
+    if let Some(format) = ChartFormat::of(path).filter(|&format| format != ChartFormat::Qua) {
+        // charts in other formats are small enough to convert whole
+        let map = parse_converted(format, path, &content)
+            .map_err(|e| anyhow!("Failed to parse {} chart '{}': {}", format.name(), path.display(), e))?;
+        return Ok(ChartMetadata {
+            path: path.to_path_buf(),
+            title: map.title.unwrap_or_default(),
+            artist: map.artist.unwrap_or_default(),
+            creator: map.creator.unwrap_or_default(),
+            difficulty_name: map.difficulty_name.unwrap_or_default(),
+            key_count: map.mode.key_count(),
+            note_count: map.hit_objects.len(),
+        });
+    }
+    // the notes are only counted, not parsed, so a library scans quickly
+    let parse_error = |e: &dyn std::fmt::Display| anyhow!("Failed to parse map data from '{}': {}", path.display(), e);
+    let (header, note_count) = qua_stream::split_header(&content).map_err(|e| parse_error(&e))?;
+    let header: ChartHeader = serde_yaml::from_str(&header).map_err(|e| parse_error(&e))?;
+    Ok(ChartMetadata {
+        path: path.to_path_buf(),
+        title: header.title.unwrap_or_default(),
+        artist: header.artist.unwrap_or_default(),
+        creator: header.creator.unwrap_or_default(),
+        difficulty_name: header.difficulty_name.unwrap_or_default(),
+        key_count: header.mode.key_count() + i64::from(header.has_scratch_key),
+        note_count: note_count + header.hit_objects.len(),
+    })
+}
+
+pub fn difficulty_names<'a>(charts: impl IntoIterator<Item = &'a ChartMetadata>) -> String {
+    charts
+        .into_iter()
+        .map(|chart| format!("'{}'", chart.difficulty_name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub fn select_difficulty<'a>(charts: &'a [ChartMetadata], query: &str) -> Result<&'a ChartMetadata> {
+    // an exact (case-insensitive) name wins, otherwise the query has to be in exactly one name
+    let lowercase_query = query.to_lowercase();
+    if let Some(chart) = charts
+        .iter()
+        .find(|chart| chart.difficulty_name.to_lowercase() == lowercase_query)
+    {
+        return Ok(chart);
+    }
+    let matches: Vec<&ChartMetadata> = charts
+        .iter()
+        .filter(|chart| chart.difficulty_name.to_lowercase().contains(&lowercase_query))
+        .collect();
+    match matches.as_slice() {
+        [chart] => Ok(chart),
+        [] => bail!("No difficulty matching '{query}', available: {}", difficulty_names(charts)),
+        _ => bail!("Difficulty '{query}' is ambiguous, it matches: {}", difficulty_names(matches)),
+    }
+}
+
+pub fn choose_chart<'a>(charts: &'a [ChartMetadata], difficulty: Option<&str>) -> Result<Option<&'a ChartMetadata>> {
+    // the chart to load, None when there are several and nothing says which (so the user has to pick)
+    match (charts, difficulty) {
+        ([], _) => bail!("No chart (.qua, .osu, .sm or .ssc) found"),
+        (_, Some(query)) => select_difficulty(charts, query).map(Some),
+        ([chart], None) => Ok(Some(chart)),
+        (_, None) => Ok(None),
+    }
+}
+
+fn find_case_insensitive(dir: &Path, relative: &Path) -> Option<PathBuf> {
+    // walks the path one component at a time, matching each name ignoring case
+    let mut path = dir.to_path_buf();
+    for component in relative.components() {
+        match component {
+            Component::Normal(name) => {
+                let name = name.to_str()?;
+                path = fs::read_dir(&path)
+                    .ok()?
+                    .filter_map(Result::ok)
+                    .map(|entry| entry.path())
+                    .find(|candidate| {
+                        candidate
+                            .file_name()
+                            .and_then(|candidate_name| candidate_name.to_str())
+                            .is_some_and(|candidate_name| candidate_name.eq_ignore_ascii_case(name))
+                    })?;
+            }
+            Component::ParentDir => path.push(".."),
+            _ => {}
+        }
+    }
+    path.is_file().then_some(path)
+}
+
+pub fn resolve_map_asset(map_dir: &Path, relative: &str) -> Result<PathBuf> {
+    // finds a file named in a chart inside its map directory: windows separators are converted,
+    // anything resolving outside the directory is rejected, and names are matched ignoring case
+    // if the exact one doesn't exist (charts made on windows)
+    let relative = PathBuf::from(relative.replace('\\', "/"));
+    if relative
+        .components()
+        .any(|component| matches!(component, Component::RootDir | Component::Prefix(_)))
+    {
+        bail!("Asset path '{}' is absolute", relative.display());
+    }
+    let map_dir = map_dir
+        .canonicalize()
+        .map_err(|e| anyhow!("Failed to resolve map directory '{}': {}", map_dir.display(), e))?;
+
+    let exact = map_dir.join(&relative);
+    let path = if exact.is_file() {
+        exact
+    } else {
+        find_case_insensitive(&map_dir, &relative)
+            .ok_or_else(|| anyhow!("Asset '{}' not found in '{}'", relative.display(), map_dir.display()))?
+    };
+    // after canonicalizing, so neither .. nor symlinks can leave the directory
+    let path = path.canonicalize()?;
+    if !path.starts_with(&map_dir) {
+        bail!("Asset path '{}' leaves the map directory", relative.display());
+    }
+    Ok(path)
+}
+
+pub fn find_asset(chart_dir: &Path, root: &Path, file_name: &str) -> Result<PathBuf> {
+    // looks for a file next to the chart, then at the root of the mapset
+    resolve_map_asset(chart_dir, file_name).or_else(|e| resolve_map_asset(root, file_name).map_err(|_| e))
+}
+
+pub fn decode_image(path: &Path, bytes: &[u8]) -> Result<Image> {
+    // a background's bytes as an image, PNG or JPEG; an error instead of load_texture's panic
+    Image::from_file_with_format(bytes, None).map_err(|e| anyhow!("Failed to decode '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    const CHART: &str = "Mode: Keys4\nAudioFile: audio.mp3\nDifficultyName: Easy\nHitObjects:\n- StartTime: 500\n  Lane: 1\n  KeySounds: []\n";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vsrg_package_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn zip(entries: &[(&str, &str)]) -> Vec<u8> {
+        // a zip built in memory, a name ending in / is a folder
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in entries {
+            if name.ends_with('/') {
+                writer.add_directory(*name, SimpleFileOptions::default()).unwrap();
+            } else {
+                writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+                writer.write_all(contents.as_bytes()).unwrap();
+            }
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn archive_charts_are_extracted_from_every_folder() {
+        // two difficulties at the root, one nested, and no audio file even though the charts name one
+        let dir = temp_dir("extract");
+        let archive = zip(&[
+            ("hard.qua", CHART),
+            ("easy.qua", CHART),
+            ("extra/", ""),
+            ("extra/nested/insane.qua", CHART),
+            ("extra/notes.txt", "not a chart"),
+        ]);
+        let charts = extract_archive(&archive, &dir).unwrap();
+        assert_eq!(charts, [dir.join("easy.qua"), dir.join("extra/nested/insane.qua"), dir.join("hard.qua")]);
+        assert_eq!(fs::read_to_string(dir.join("extra/notes.txt")).unwrap(), "not a chart");
+        assert!(!dir.join("audio.mp3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entries_leaving_the_archive_folder_are_skipped() {
+        let dir = temp_dir("escape");
+        let archive = zip(&[("../escaped.qua", CHART), ("map.qua", CHART)]);
+        assert_eq!(zip::ZipArchive::new(Cursor::new(&archive)).unwrap().file_names().filter(|name| name.starts_with("..")).count(), 1);
+        assert_eq!(extract_archive(&archive, &dir.join("inside")).unwrap(), [dir.join("inside/map.qua")]);
+        assert!(!dir.join("escaped.qua").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn archives_without_charts_or_that_arent_zips_are_errors() {
+        let dir = temp_dir("no_chart");
+        let error = extract_archive(&zip(&[("audio.mp3", "")]), &dir).unwrap_err();
+        assert!(error.to_string().contains("contains no charts"), "{error}");
+        let error = extract_archive(b"not a zip", &dir).unwrap_err();
+        assert!(error.to_string().contains("Not a valid mapset archive"), "{error}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cached_extraction_is_reused_for_the_same_archive() {
+        let dir = temp_dir("cache");
+        fs::create_dir_all(&dir).unwrap();
+        let archive_path = dir.join("set.qp");
+        fs::write(&archive_path, zip(&[("map.qua", CHART)])).unwrap();
+        let cache = dir.join("cache");
+        let extracted = extract_cached(&archive_path, &cache).unwrap();
+        assert_eq!(extracted, cache.join(format!("{:016x}", archive_hash(&fs::read(&archive_path).unwrap()))));
+        // a file left in the extraction shows the second call didn't extract again
+        fs::write(extracted.join("marker"), "").unwrap();
+        assert_eq!(extract_cached(&archive_path, &cache).unwrap(), extracted);
+        assert!(extracted.join("marker").exists());
+        // another archive gets its own folder, a broken one leaves nothing behind
+        fs::write(&archive_path, zip(&[("other.qua", CHART)])).unwrap();
+        assert_ne!(extract_cached(&archive_path, &cache).unwrap(), extracted);
+        fs::write(&archive_path, zip(&[("audio.mp3", "")])).unwrap();
+        assert!(extract_cached(&archive_path, &cache).is_err());
+        assert_eq!(fs::read_dir(&cache).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn difficulties(names: &[&str]) -> Vec<ChartMetadata> {
+        names
+            .iter()
+            .map(|name| ChartMetadata {
+                path: PathBuf::from(format!("{name}.qua")),
+                title: String::new(),
+                artist: String::new(),
+                creator: String::new(),
+                difficulty_name: name.to_string(),
+                key_count: 4,
+                note_count: 0,
+            })
+            .collect()
+    }
+
+    fn selected(charts: &[ChartMetadata], query: &str) -> Result<String> {
+        select_difficulty(charts, query).map(|chart| chart.difficulty_name.clone())
+    }
+
+    #[test]
+    fn exact_name_wins_ignoring_case() {
+        // "hard" is also in "Hard+", but the exact name is taken
+        let charts = difficulties(&["Easy", "Hard", "Hard+"]);
+        assert_eq!(selected(&charts, "hard").unwrap(), "Hard");
+        assert_eq!(selected(&charts, "HARD+").unwrap(), "Hard+");
+    }
+
+    #[test]
+    fn unique_part_of_a_name_selects_it() {
+        let charts = difficulties(&["Easy", "Normal", "Insane"]);
+        assert_eq!(selected(&charts, "orm").unwrap(), "Normal");
+        assert_eq!(selected(&charts, "SAN").unwrap(), "Insane");
+    }
+
+    #[test]
+    fn ambiguous_or_unknown_queries_list_the_names() {
+        let charts = difficulties(&["Easy", "Hard", "Hard+", "Insane"]);
+        let error = selected(&charts, "ar").unwrap_err().to_string();
+        assert_eq!(error, "Difficulty 'ar' is ambiguous, it matches: 'Hard', 'Hard+'");
+        let error = selected(&charts, "expert").unwrap_err().to_string();
+        assert_eq!(error, "No difficulty matching 'expert', available: 'Easy', 'Hard', 'Hard+', 'Insane'");
+    }
+
+    #[test]
+    fn chart_is_chosen_when_there_is_no_doubt() {
+        // no charts is an error, one is loaded, several need a query or the user to pick
+        let name = |chart: Option<&ChartMetadata>| chart.map(|chart| chart.difficulty_name.clone());
+        assert!(choose_chart(&[], None).is_err());
+        assert!(choose_chart(&[], Some("easy")).is_err());
+        let one = difficulties(&["Easy"]);
+        assert_eq!(name(choose_chart(&one, None).unwrap()).as_deref(), Some("Easy"));
+        assert!(choose_chart(&one, Some("hard")).is_err());
+        let many = difficulties(&["Easy", "Hard"]);
+        assert_eq!(name(choose_chart(&many, None).unwrap()), None);
+        assert_eq!(name(choose_chart(&many, Some("har")).unwrap()).as_deref(), Some("Hard"));
+    }
+
+    #[test]
+    fn assets_outside_the_map_directory_are_rejected() {
+        let root = temp_dir("escape");
+        let map_dir = root.join("map");
+        fs::create_dir_all(&map_dir).unwrap();
+        fs::write(root.join("x.mp3"), b"").unwrap();
+        fs::write(map_dir.join("inside.mp3"), b"").unwrap();
+        std::os::unix::fs::symlink(root.join("x.mp3"), map_dir.join("link.mp3")).unwrap();
+
+        assert!(resolve_map_asset(&map_dir, "inside.mp3").is_ok());
+        let error = resolve_map_asset(&map_dir, "../x.mp3").unwrap_err().to_string();
+        assert!(error.contains("leaves the map directory"), "{error}");
+        assert!(resolve_map_asset(&map_dir, "../../x.mp3").is_err());
+        assert!(resolve_map_asset(&map_dir, "..\\x.mp3").is_err());
+        let absolute = root.join("x.mp3").display().to_string();
+        assert!(resolve_map_asset(&map_dir, &absolute).unwrap_err().to_string().contains("absolute"));
+        // the link's name is inside, the file it points at isn't
+        let error = resolve_map_asset(&map_dir, "link.mp3").unwrap_err().to_string();
+        assert!(error.contains("leaves the map directory"), "{error}");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn windows_paths_are_found_ignoring_case() {
+        let map_dir = temp_dir("windows_paths");
+        fs::create_dir_all(map_dir.join("Sub")).unwrap();
+        fs::write(map_dir.join("Sub").join("Audio.mp3"), b"").unwrap();
+
+        let expected = map_dir.join("Sub").join("Audio.mp3").canonicalize().unwrap();
+        assert_eq!(resolve_map_asset(&map_dir, "Sub\\Audio.mp3").unwrap(), expected);
+        assert_eq!(resolve_map_asset(&map_dir, "sub\\audio.MP3").unwrap(), expected);
+        assert_eq!(resolve_map_asset(&map_dir, "SUB/AUDIO.mp3").unwrap(), expected);
+        assert!(resolve_map_asset(&map_dir, "sub\\other.mp3").is_err());
+        fs::remove_dir_all(&map_dir).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+use crate::autosave::RecoveredPlay;
+use crate::mapset::MapsetEntry;
+use crate::package::ChartMetadata;
+use crate::strings::{tr, tr_args};
+use crate::utils::{format_time, TimeStyle};
+use anyhow::{anyhow, bail, Result};
+use macroquad::prelude::*;
+
+async fn pick(title: &str, entries: &[String]) -> Result<usize> {
+    // lists the entries until one is picked with up/down and enter, or by its number (the first
+    // nine); the list scrolls to keep the selected one on screen
+    let mut selected = 0;
+    loop {
+        if is_key_pressed(KeyCode::Escape) {
+            bail!("Nothing picked");
+        }
+        if is_key_pressed(KeyCode::Down) {
+            selected = (selected + 1) % entries.len();
+        }
+        if is_key_pressed(KeyCode::Up) {
+            selected = (selected + entries.len() - 1) % entries.len();
+        }
+        let numbered = get_char_pressed()
+            .and_then(|character| character.to_digit(10))
+            .and_then(|digit| (digit as usize).checked_sub(1))
+            .filter(|&index| index < entries.len());
+        if let Some(index) = is_key_pressed(KeyCode::Enter).then_some(selected).or(numbered) {
+            // the key stays pressed until the next frame, which would pick in a picker shown right after
+            next_frame().await;
+            return Ok(index);
+        }
+
+        clear_background(BLACK);
+        draw_text(title, 20.0, 50.0, 40.0, WHITE);
+        let rows = (((screen_height() - 150.0) / 32.0) as usize).max(1);
+        let first = (selected + 1).saturating_sub(rows);
+        for (index, line) in entries.iter().enumerate().skip(first).take(rows) {
+            let color = if index == selected { YELLOW } else { WHITE };
+            draw_text(line, 40.0, 100.0 + (index - first) as f32 * 32.0, 28.0, color);
+        }
+        draw_text(tr("picker.hint"), 20.0, screen_height() - 20.0, 24.0, GRAY);
+        next_frame().await;
+    }
+}
+
+pub async fn pick_difficulty(charts: &[ChartMetadata]) -> Result<&ChartMetadata> {
+    let entries: Vec<String> = charts
+        .iter()
+        .enumerate()
+        .map(|(index, chart)| {
+            tr_args(
+                "picker.entry",
+                &[
+                    ("number", &(index + 1).to_string()),
+                    ("name", &chart.difficulty_name),
+                    ("keys", &chart.key_count.to_string()),
+                    ("notes", &chart.note_count.to_string()),
+                ],
+            )
+        })
+        .collect();
+    let index = pick(tr("picker.title"), &entries).await.map_err(|_| anyhow!("No difficulty picked"))?;
+    Ok(&charts[index])
+}
+
+pub async fn pick_mapset(mapsets: &[MapsetEntry]) -> Result<&MapsetEntry> {
+    let entries: Vec<String> = mapsets
+        .iter()
+        .enumerate()
+        .map(|(index, mapset)| {
+            tr_args(
+                "picker.mapset_entry",
+                &[
+                    ("number", &(index + 1).to_string()),
+                    ("artist", &mapset.artist),
+                    ("title", &mapset.title),
+                    ("charts", &mapset.charts.len().to_string()),
+                ],
+            )
+        })
+        .collect();
+    let index = pick(tr("picker.mapset_title"), &entries).await.map_err(|_| anyhow!("No map picked"))?;
+    Ok(&mapsets[index])
+}
+
+pub async fn ask_recovery(play: &RecoveredPlay) -> bool {
+    // shows what an unfinished play got to, true to export it and false to discard it
+    let details = match &play.score {
+        Some(score) => tr_args(
+            "recovery.details",
+            &[
+                ("judged", &score.judged.to_string()),
+                ("accuracy", &format!("{:.2}", score.accuracy)),
+                ("time", &format_time(score.time, TimeStyle::Precise)),
+            ],
+        ),
+        None => tr_args("recovery.no_score", &[("events", &play.events.len().to_string())]),
+    };
+    loop {
+        if is_key_pressed(KeyCode::E) {
+            return true;
+        }
+        if is_key_pressed(KeyCode::D) || is_key_pressed(KeyCode::Escape) {
+            return false;
+        }
+
+        clear_background(BLACK);
+        draw_text(tr("recovery.title"), 20.0, 50.0, 40.0, WHITE);
+        draw_text(&details, 40.0, 100.0, 28.0, WHITE);
+        draw_text(tr("recovery.hint"), 20.0, screen_height() - 20.0, 24.0, GRAY);
+        next_frame().await;
+    }
+}
@@ -0,0 +1,226 @@
+use crate::map::Map;
+use anyhow::{anyhow, bail, Result};
+use serde::de::DeserializeOwned;
+use std::ops::Range;
+
+// files at least this big (bytes) are parsed in sections, smaller ones are parsed whole
+pub const STREAMING_PARSE_THRESHOLD: usize = 4 * 1024 * 1024;
+// items parsed at once from a long list, which bounds serde_yaml's event buffer
+const CHUNK_ITEMS: usize = 4096;
+// the long lists of a .qua, parsed chunk by chunk; everything else is small and parsed in one go
+const LIST_SECTIONS: [&str; 4] = ["TimingPoints", "SliderVelocities", "ScrollSpeedFactors", "HitObjects"];
+
+// one of the long lists: where its items are in the file and where each one starts
+struct ListSection {
+    key: &'static str,
+    range: Range<usize>, // from the key's line to the end of the last item
+    item_starts: Vec<usize>,
+    items_end: usize,
+}
+
+fn lines(content: &str) -> impl Iterator<Item = (usize, &str)> {
+    // each line with its byte offset, line endings included
+    content.split_inclusive('\n').scan(0, |offset, line| {
+        let start = *offset;
+        *offset += line.len();
+        Some((start, line))
+    })
+}
+
+fn is_top_level_key(line: &str) -> bool {
+    // a line at column 0 that isn't a list item, a comment or blank starts the next top-level entry
+    !line.starts_with(|c: char| c.is_whitespace() || c == '-' || c == '#')
+}
+
+fn find_list_sections(content: &str) -> Result<Vec<ListSection>> {
+    // the long lists written block style ("Key:" on its own line, one "- " item after another);
+    // ones written any other way (e.g. "HitObjects: []") are left for the rest of the document
+    let mut sections: Vec<ListSection> = Vec::new();
+    let mut current: Option<ListSection> = None;
+    let mut item_prefix: Option<String> = None; // indentation and dash of the current list's items
+    let mut seen: Vec<&str> = Vec::new();
+    for (offset, line) in lines(content) {
+        if is_top_level_key(line) {
+            if let Some(section) = current.take() {
+                sections.push(section);
+            }
+            let header = line.trim_end();
+            let Some(key) = LIST_SECTIONS.iter().find(|&&key| header.split(':').next().map(str::trim_end) == Some(key)) else {
+                continue;
+            };
+            // a second one (in any style) would be left in the rest and hide the duplicate from serde_yaml
+            if seen.contains(key) {
+                bail!("'{key}' is in the file twice");
+            }
+            seen.push(key);
+            if header.strip_suffix(':') == Some(key) {
+                let end = offset + line.len();
+                current = Some(ListSection { key, range: offset..end, item_starts: Vec::new(), items_end: end });
+                item_prefix = None;
+            }
+            continue;
+        }
+        let Some(section) = current.as_mut() else {
+            continue;
+        };
+        section.range.end = offset + line.len();
+        let trimmed = line.trim_start();
+        if trimmed.trim_end().is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        section.items_end = offset + line.len();
+        let prefix = item_prefix.get_or_insert_with(|| line[..line.len() - trimmed.len()].to_string() + "-");
+        if line.starts_with(prefix.as_str()) && line[prefix.len()..].starts_with(char::is_whitespace) {
+            section.item_starts.push(offset);
+        } else if section.item_starts.is_empty() {
+            bail!("'{}' doesn't start with a list item", section.key);
+        }
+    }
+    sections.extend(current);
+    Ok(sections)
+}
+
+fn parse_list<T: DeserializeOwned>(content: &str, section: &ListSection) -> Result<Vec<T>> {
+    // parses the items a chunk at a time straight into a list sized for all of them
+    let mut items = Vec::with_capacity(section.item_starts.len());
+    for (index, chunk) in section.item_starts.chunks(CHUNK_ITEMS).enumerate() {
+        let start = chunk[0];
+        let end = section.item_starts.get((index + 1) * CHUNK_ITEMS).copied().unwrap_or(section.items_end);
+        let parsed: Vec<T> = serde_yaml::from_str(&content[start..end])
+            .map_err(|e| anyhow!("{} at byte {start}: {e}", section.key))?;
+        items.extend(parsed);
+    }
+    Ok(items)
+}
+
+fn rest_of(content: &str, sections: &[ListSection]) -> String {
+    // the document with the sections cut out
+    let mut rest = String::new();
+    let mut last_end = 0;
+    for section in sections {
+        rest.push_str(&content[last_end..section.range.start]);
+        last_end = section.range.end;
+    }
+    rest.push_str(&content[last_end..]);
+    rest
+}
+
+pub fn split_header(content: &str) -> Result<(String, usize)> {
+    // the document without its long lists, to read the metadata from without parsing any of them,
+    // and how many items its block style HitObjects list has
+    let mut sections = find_list_sections(content)?;
+    sections.retain(|section| !section.item_starts.is_empty());
+    let note_count = sections
+        .iter()
+        .find(|section| section.key == "HitObjects")
+        .map_or(0, |section| section.item_starts.len());
+    Ok((rest_of(content, &sections), note_count))
+}
+
+pub fn parse_sections(content: &str) -> Result<Map> {
+    // parses a .qua like serde_yaml::from_str does, but the long lists in chunks, so the whole
+    // file never has to be held as yaml events at once
+    let mut sections = find_list_sections(content)?;
+    // a key with no items is null, which is parsed (and rejected) along with the rest
+    sections.retain(|section| !section.item_starts.is_empty());
+    let rest = rest_of(content, &sections);
+
+    let mut map: Map = serde_yaml::from_str(&rest)?;
+    for section in &sections {
+        match section.key {
+            "TimingPoints" => map.timing_points = parse_list(content, section)?,
+            "SliderVelocities" => map.scroll_velocities = parse_list(content, section)?,
+            "ScrollSpeedFactors" => map.scroll_speed_factors = parse_list(content, section)?,
+            "HitObjects" => map.hit_objects = parse_list(content, section)?,
+            key => bail!("No list '{key}'"),
+        }
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCK: &str = "\
+Mode: Keys4
+Title: Block
+TimingPoints:
+- StartTime: 0
+  Bpm: 120
+HitObjects:
+- StartTime: 500
+  Lane: 1
+  KeySounds: []
+
+# a comment and a blank line between items
+- StartTime: 750
+  EndTime: 1000
+  Lane: 2
+  KeySounds:
+  - Sample: 1
+    Volume: 100
+- StartTime: 1000
+  Lane: 3
+  KeySounds: []
+DifficultyName: After
+";
+
+    #[test]
+    fn block_style_notes_are_counted_and_cut_out() {
+        // items are counted, not their lines, nested lists included
+        let (header, note_count) = split_header(BLOCK).unwrap();
+        assert_eq!(note_count, 3);
+        assert!(!header.contains("StartTime"), "{header}");
+        assert!(header.contains("Title: Block") && header.contains("DifficultyName: After"), "{header}");
+    }
+
+    #[test]
+    fn flow_style_notes_are_left_in_the_header() {
+        // not counted here, the header keeps them for whoever parses it
+        let content = "Mode: Keys4\nHitObjects: [{StartTime: 500, Lane: 1}, {StartTime: 750, Lane: 2}]\nTitle: Flow\n";
+        let (header, note_count) = split_header(content).unwrap();
+        assert_eq!(note_count, 0);
+        assert_eq!(header, content);
+        let (header, note_count) = split_header("Mode: Keys4\nHitObjects: []\n").unwrap();
+        assert_eq!((header.as_str(), note_count), ("Mode: Keys4\nHitObjects: []\n", 0));
+    }
+
+    #[test]
+    fn notes_listed_twice_are_an_error() {
+        let content = "HitObjects:\n- StartTime: 500\n  Lane: 1\nHitObjects: []\n";
+        assert!(split_header(content).is_err());
+    }
+
+    #[test]
+    fn sections_parse_like_the_whole_file() {
+        let map = parse_sections(BLOCK).unwrap();
+        let whole: Map = serde_yaml::from_str(BLOCK).unwrap();
+        let notes = |map: &Map| {
+            map.hit_objects.iter().map(|note| (note.start_time, note.end_time, note.lane, note.key_sounds.len())).collect::<Vec<_>>()
+        };
+        assert_eq!(notes(&map), [(500.0, None, 1, 0), (750.0, Some(1000.0), 2, 1), (1000.0, None, 3, 0)]);
+        assert_eq!(notes(&map), notes(&whole));
+        assert_eq!(map.timing_points.len(), 1);
+    }
+
+    #[test]
+    fn every_fixture_chart_parses_like_the_whole_file() {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut checked = 0;
+        for dir in ["golden/charts", "scenarios/charts"] {
+            let mut paths: Vec<_> = std::fs::read_dir(root.join(dir)).unwrap().map(|entry| entry.unwrap().path()).collect();
+            paths.sort();
+            for path in paths.iter().filter(|path| path.extension().is_some_and(|extension| extension == "qua")) {
+                let content = std::fs::read_to_string(path).unwrap();
+                let streamed = parse_sections(&content).unwrap();
+                let whole: Map = serde_yaml::from_str(&content).unwrap();
+                // everything that was read, written back out in a fixed order
+                assert_eq!(streamed.to_qua_string().unwrap(), whole.to_qua_string().unwrap(), "{}", path.display());
+                assert_eq!(streamed.hit_objects.len(), whole.hit_objects.len());
+                checked += 1;
+            }
+        }
+        assert!(checked >= 10, "only {checked} charts");
+    }
+}
@@ -0,0 +1,76 @@
+use crate::strings::tr_args;
+
+// playback rates the audio and charts are played at
+pub const MIN_RATE: f64 = 0.5;
+pub const MAX_RATE: f64 = 2.0;
+// rates are kept to this many decimals, so steps never drift (1.1000000000000001)
+const RATE_DECIMALS: i32 = 2;
+// step with shift held
+pub const FINE_RATE_STEP: f64 = 0.01;
+
+pub fn snap_rate(rate: f64) -> f64 {
+    // rounded to RATE_DECIMALS and kept in the supported range
+    let scale = 10f64.powi(RATE_DECIMALS);
+    ((rate * scale).round() / scale).clamp(MIN_RATE, MAX_RATE)
+}
+
+pub fn step_rate(rate: f64, step: f64) -> f64 {
+    snap_rate(rate + step)
+}
+
+pub fn semitones(rate: f64) -> f64 {
+    // how far the pitch moves when the audio is sped up by rate without keeping its pitch
+    12.0 * rate.log2()
+}
+
+pub fn format_rate(rate: f64) -> String {
+    format!("{rate:.2}")
+}
+
+pub fn pitch_label(rate: f64) -> String {
+    // "+1.65 semitones (+165 cents)"
+    let semitones = semitones(rate);
+    tr_args(
+        "rate.pitch",
+        &[("semitones", &format!("{semitones:+.2}")), ("cents", &format!("{:+.0}", semitones * 100.0))],
+    )
+}
+
+pub fn rate_toast(rate: f64) -> String {
+    tr_args("toast.rate", &[("rate", &format_rate(rate)), ("pitch", &pitch_label(rate))])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_steps_land_on_two_decimals() {
+        // twenty steps of the default 0.05 (which doesn't add up exactly in floats) are 1.05, 1.1, ... 2.0 exactly
+        let mut rate = 1.0;
+        for step in 1..=20 {
+            rate = step_rate(rate, 0.05);
+            assert_eq!(rate, f64::from(20 + step) / 20.0);
+        }
+        // fine steps, and down again
+        assert_eq!(step_rate(step_rate(1.0, FINE_RATE_STEP), FINE_RATE_STEP), 1.02);
+        assert_eq!(step_rate(1.02, -0.1), 0.92);
+        assert_eq!((snap_rate(1.234), snap_rate(1.236)), (1.23, 1.24));
+    }
+
+    #[test]
+    fn rate_stays_in_the_supported_range() {
+        assert_eq!(step_rate(MAX_RATE, 0.1), MAX_RATE);
+        assert_eq!(step_rate(0.55, -0.1), MIN_RATE);
+        assert_eq!((snap_rate(10.0), snap_rate(0.0)), (MAX_RATE, MIN_RATE));
+    }
+
+    #[test]
+    fn pitch_is_shown_in_semitones_and_cents() {
+        assert_eq!(pitch_label(1.0), "+0.00 semitones (+0 cents)");
+        assert_eq!(pitch_label(2.0), "+12.00 semitones (+1200 cents)");
+        assert_eq!(pitch_label(0.5), "-12.00 semitones (-1200 cents)");
+        assert_eq!(rate_toast(1.1), "Rate 1.10x, +1.65 semitones (+165 cents)");
+        assert_eq!(rate_toast(0.95), "Rate 0.95x, -0.89 semitones (-89 cents)");
+    }
+}
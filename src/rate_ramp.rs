@@ -0,0 +1,189 @@
+use crate::results::ResultsSummary;
+use std::str::FromStr;
+
+// accuracy (%) a play needs to count as a clear and move the ramp up
+pub const DEFAULT_RAMP_ACCURACY: f64 = 95.0;
+// how long (s) the results of a clear stay up before the next rate starts
+pub const RAMP_RETRY_DELAY: f64 = 3.0;
+// rates are kept to this many decimals, so adding the step up doesn't drift (0.85 + 3 * 0.05 is 1.0)
+const RATE_DECIMALS: i32 = 3;
+
+// rates to practice at, given as start:max:step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RampSchedule {
+    pub start: f64,
+    pub max: f64,
+    pub step: f64,
+}
+
+impl FromStr for RampSchedule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.split(':');
+        let mut number = |name: &str| -> Result<f64, String> {
+            let part = parts.next().ok_or_else(|| format!("missing {name}, expected start:max:step"))?;
+            part.trim().parse().map_err(|_| format!("{name} '{part}' isn't a number"))
+        };
+        let (start, max, step) = (number("start")?, number("max")?, number("step")?);
+        if parts.next().is_some() {
+            return Err("expected start:max:step".to_string());
+        }
+        // the same range the rate keys allow
+        if !(0.5..=2.0).contains(&start) || !(0.5..=2.0).contains(&max) {
+            return Err("rates must be between 0.5 and 2".to_string());
+        }
+        if start > max {
+            return Err("start can't be above max".to_string());
+        }
+        if step <= 0.0 {
+            return Err("step must be positive".to_string());
+        }
+        Ok(Self { start, max, step })
+    }
+}
+
+// where the ramp goes after a play
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RampOutcome {
+    Next(f64), // cleared, retry at this rate
+    Complete,  // cleared at the max rate
+    Failed,    // not cleared, the ramp stops at the rate it was on
+}
+
+// raises the rate by a step after each clear, until the max is cleared or a play isn't
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateRamp {
+    schedule: RampSchedule,
+    min_accuracy: f64,
+    level: usize,                 // steps taken from the start
+    history: Vec<(f64, f64)>,     // rate and accuracy of every play judged so far
+    outcome: Option<RampOutcome>, // of the last play
+}
+
+impl RateRamp {
+    pub fn new(schedule: RampSchedule, min_accuracy: f64) -> Self {
+        Self { schedule, min_accuracy, level: 0, history: Vec::new(), outcome: None }
+    }
+
+    pub fn rate(&self) -> f64 {
+        // worked out from the start each time rather than added up, and never past the max
+        let scale = 10f64.powi(RATE_DECIMALS);
+        let rate = ((self.schedule.start + self.level as f64 * self.schedule.step) * scale).round() / scale;
+        rate.min(self.schedule.max)
+    }
+
+    pub fn max_rate(&self) -> f64 {
+        self.schedule.max
+    }
+
+    pub fn min_accuracy(&self) -> f64 {
+        self.min_accuracy
+    }
+
+    pub fn history(&self) -> &[(f64, f64)] {
+        &self.history
+    }
+
+    pub fn outcome(&self) -> Option<RampOutcome> {
+        self.outcome
+    }
+
+    pub fn is_over(&self) -> bool {
+        matches!(self.outcome, Some(RampOutcome::Complete | RampOutcome::Failed))
+    }
+
+    pub fn is_clear(&self, summary: &ResultsSummary) -> bool {
+        // only a full play counts, not one that was resumed partway or played by autoplay
+        summary.accuracy >= self.min_accuracy && !summary.resumed && !summary.autoplay_assisted
+    }
+
+    pub fn after_play(&mut self, summary: &ResultsSummary) -> RampOutcome {
+        // records the play and moves to the next rate if it was a clear; plays after the ramp is over don't count
+        if let Some(outcome) = self.outcome.filter(|_| self.is_over()) {
+            return outcome;
+        }
+        self.history.push((self.rate(), summary.accuracy));
+        let outcome = if !self.is_clear(summary) {
+            RampOutcome::Failed
+        } else if self.rate() >= self.schedule.max {
+            RampOutcome::Complete
+        } else {
+            self.level += 1;
+            RampOutcome::Next(self.rate())
+        };
+        self.outcome = Some(outcome);
+        outcome
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Map;
+
+    fn play(accuracy: f64) -> ResultsSummary {
+        ResultsSummary { accuracy, ..ResultsSummary::from_map(&Map::default()) }
+    }
+
+    fn ramp(schedule: &str) -> RateRamp {
+        RateRamp::new(schedule.parse().unwrap(), DEFAULT_RAMP_ACCURACY)
+    }
+
+    #[test]
+    fn schedules_parse_and_bad_ones_are_errors() {
+        assert_eq!("0.85:1.0:0.05".parse(), Ok(RampSchedule { start: 0.85, max: 1.0, step: 0.05 }));
+        for value in ["0.85:1.0", "0.85:1.0:0.05:1", "0.4:1.0:0.05", "1.0:2.5:0.1", "1.1:1.0:0.05", "0.85:1.0:0", "a:1:0.1"] {
+            assert!(value.parse::<RampSchedule>().is_err(), "'{value}' parsed");
+        }
+    }
+
+    #[test]
+    fn clears_step_the_rate_up_to_the_max() {
+        let mut ramp = ramp("0.85:1.0:0.05");
+        assert_eq!(ramp.rate(), 0.85);
+        assert_eq!(ramp.after_play(&play(96.0)), RampOutcome::Next(0.9));
+        assert_eq!(ramp.after_play(&play(95.0)), RampOutcome::Next(0.95));
+        // no drift from adding the step up, the last step lands on the max exactly
+        assert_eq!(ramp.after_play(&play(99.0)), RampOutcome::Next(1.0));
+        assert_eq!(ramp.after_play(&play(97.0)), RampOutcome::Complete);
+        assert!(ramp.is_over());
+        assert_eq!(ramp.history(), [(0.85, 96.0), (0.9, 95.0), (0.95, 99.0), (1.0, 97.0)]);
+    }
+
+    #[test]
+    fn step_past_the_max_stops_at_the_max() {
+        let mut ramp = ramp("0.9:1.0:0.08");
+        assert_eq!(ramp.after_play(&play(100.0)), RampOutcome::Next(0.98));
+        assert_eq!(ramp.after_play(&play(100.0)), RampOutcome::Next(1.0));
+        assert_eq!(ramp.after_play(&play(100.0)), RampOutcome::Complete);
+    }
+
+    #[test]
+    fn a_play_below_the_criterion_ends_the_ramp_at_its_rate() {
+        let mut ramp = ramp("0.85:1.0:0.05");
+        ramp.after_play(&play(98.0));
+        assert_eq!(ramp.after_play(&play(94.9)), RampOutcome::Failed);
+        assert_eq!(ramp.rate(), 0.9);
+        // plays after that aren't counted
+        assert_eq!(ramp.after_play(&play(100.0)), RampOutcome::Failed);
+        assert_eq!(ramp.history().len(), 2);
+    }
+
+    #[test]
+    fn resumed_or_assisted_plays_dont_clear() {
+        let ramp = ramp("0.85:1.0:0.05");
+        assert!(ramp.is_clear(&play(100.0)));
+        assert!(!ramp.is_clear(&ResultsSummary { resumed: true, ..play(100.0) }));
+        assert!(!ramp.is_clear(&ResultsSummary { autoplay_assisted: true, ..play(100.0) }));
+        let strict = RateRamp::new("1:1.2:0.1".parse().unwrap(), 99.0);
+        assert!(!strict.is_clear(&play(98.0)));
+    }
+
+    #[test]
+    fn a_ramp_starting_at_its_max_completes_on_the_first_clear() {
+        let mut ramp = ramp("1.0:1.0:0.05");
+        assert_eq!(ramp.outcome(), None);
+        assert_eq!(ramp.after_play(&play(95.0)), RampOutcome::Complete);
+    }
+}
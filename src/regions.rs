@@ -0,0 +1,129 @@
+use crate::draw::Draw;
+use macroquad::color::Color;
+use std::str::FromStr;
+
+// space kept between moved UI and a reserved region
+const REGION_MARGIN: f64 = 8.0;
+
+// part of the screen kept free for something composited later (e.g. a handcam), given as x,y,w,h[,label]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReservedRegion {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    pub label: Option<String>, // drawn in the placeholder, may contain commas
+}
+
+impl FromStr for ReservedRegion {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.splitn(5, ',');
+        let mut number = |name: &str| -> Result<f64, String> {
+            let part = parts.next().ok_or_else(|| format!("missing {name}, expected x,y,w,h[,label]"))?;
+            part.trim().parse().map_err(|_| format!("{name} '{part}' isn't a number"))
+        };
+        let (x, y, width, height) = (number("x")?, number("y")?, number("w")?, number("h")?);
+        if width <= 0.0 || height <= 0.0 {
+            return Err("w and h must be positive".to_string());
+        }
+        let label = parts.next().map(str::trim).filter(|label| !label.is_empty()).map(ToString::to_string);
+        Ok(Self { x, y, width, height, label })
+    }
+}
+
+impl ReservedRegion {
+    pub fn overlaps(&self, x: f64, y: f64, width: f64, height: f64) -> bool {
+        x < self.x + self.width && self.x < x + width && y < self.y + self.height && self.y < y + height
+    }
+}
+
+pub fn avoid_regions(regions: &[ReservedRegion], x: f64, y: f64, width: f64, height: f64, screen_height: f64) -> f64 {
+    // y for a box that keeps it out of every region, moving it only vertically:
+    // below a region it overlaps if that fits on screen, otherwise above it
+    let mut y = y;
+    // each move clears one region, so more moves than regions means they can't all be avoided
+    for _ in 0..=regions.len() {
+        let Some(region) = regions.iter().find(|region| region.overlaps(x, y, width, height)) else {
+            return y;
+        };
+        let below = region.y + region.height + REGION_MARGIN;
+        y = if below + height <= screen_height {
+            below
+        } else {
+            region.y - height - REGION_MARGIN
+        };
+    }
+    y
+}
+
+pub fn draw_reserved_regions(regions: &[ReservedRegion], draw: &mut impl Draw) {
+    // outlined placeholders, left empty so whatever is composited in later isn't covered
+    let color = Color::new(1.0, 0.4, 0.8, 0.9);
+    for region in regions {
+        draw.draw_rectangle_outline(region.x, region.y, region.width, region.height, 2.0, color);
+        if let Some(label) = &region.label {
+            draw.draw_text(label, region.x + 8.0, region.y + 24.0, 20.0, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(value: &str) -> ReservedRegion {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn regions_parse_with_and_without_a_label() {
+        assert_eq!(
+            region("10, 20,300,200"),
+            ReservedRegion { x: 10.0, y: 20.0, width: 300.0, height: 200.0, label: None }
+        );
+        let labeled = region("0,0,1.5,2,Handcam, left hand");
+        assert_eq!((labeled.width, labeled.label.as_deref()), (1.5, Some("Handcam, left hand")));
+        assert_eq!(region("0,0,1,1, ").label, None);
+    }
+
+    #[test]
+    fn bad_regions_are_errors() {
+        for value in ["", "10,20,300", "10,20,w,200", "10,20,0,200", "10,20,300,-1"] {
+            assert!(value.parse::<ReservedRegion>().is_err(), "'{value}' parsed");
+        }
+    }
+
+    #[test]
+    fn overlaps_excludes_touching_edges() {
+        let handcam = region("100,100,200,100");
+        assert!(handcam.overlaps(150.0, 150.0, 10.0, 10.0));
+        assert!(handcam.overlaps(0.0, 0.0, 101.0, 101.0));
+        assert!(!handcam.overlaps(0.0, 0.0, 100.0, 100.0));
+        assert!(!handcam.overlaps(300.0, 100.0, 50.0, 50.0));
+    }
+
+    #[test]
+    fn boxes_move_below_a_region_when_they_fit() {
+        let regions = [region("100,100,200,100")];
+        // out of the way already
+        assert_eq!(avoid_regions(&regions, 400.0, 120.0, 50.0, 20.0, 1000.0), 120.0);
+        assert_eq!(avoid_regions(&regions, 150.0, 120.0, 50.0, 20.0, 1000.0), 200.0 + REGION_MARGIN);
+    }
+
+    #[test]
+    fn boxes_move_above_a_region_at_the_bottom_of_the_screen() {
+        let regions = [region("100,900,200,100")];
+        assert_eq!(avoid_regions(&regions, 150.0, 950.0, 50.0, 20.0, 1000.0), 900.0 - 20.0 - REGION_MARGIN);
+    }
+
+    #[test]
+    fn boxes_clear_stacked_regions() {
+        // moving below the first lands in the second, so it ends up below both
+        let regions = [region("100,100,200,100"), region("100,210,200,100")];
+        let y = avoid_regions(&regions, 150.0, 120.0, 50.0, 20.0, 1000.0);
+        assert_eq!(y, 310.0 + REGION_MARGIN);
+        assert!(regions.iter().all(|region| !region.overlaps(150.0, y, 50.0, 20.0)));
+    }
+}
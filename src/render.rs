@@ -1,26 +1,133 @@
-use crate::utils::{FieldPositions, BEAT_SNAPS, SKIN, JudgementType};
-use crate::draw::Draw;
-use crate::map::Map;
+use crate::utils::{judgement_color, FieldPositions, JudgementType, BEAT_SNAPS, JUDGEMENTS, NoteShape};
+use crate::utils::{skin, Skin};
+use crate::draw::{Draw, Transformed, Viewport};
+use crate::effects::playfield_transform;
+use crate::map::{DiffEntry, DiffSide, Map, NoteInspection, ObjectKind, OFF_SNAP_TOLERANCE};
+use crate::lerp;
+use crate::lookahead::notes_in_view;
+use crate::regions::{avoid_regions, ReservedRegion};
+use crate::strings::{tr, tr_args};
 // use crate::index_at_time;
 use anyhow::Result;
 use macroquad::{color::Color, prelude::*};
+use std::time::Instant;
 
 pub struct FrameState<'map> {
     pub map: &'map mut Map,
+    pub compare_map: Option<&'map mut Map>, // second chart overlaid with --compare
+    pub chart_diff: &'map [DiffEntry],      // notes only present in one of the two charts
     pub field_positions: &'map FieldPositions<'map>,
+    pub alpha: f64, // interpolation between the last two simulation states (1.0 = latest)
+    pub view_height: f64, // screen height, only timing lines within it are updated
+    pub background: Option<&'map Texture2D>, // the map's background image, drawn dimmed behind the playfield
 }
 
-pub fn set_reference_positions(receptor_texture: &'_ Texture2D) -> FieldPositions<'_> {
+// where the lanes are across the screen; columns are counted left to right as drawn, so with
+// mirror lane 1 is the last column (before the scratch lane, which always stays last)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayfieldLayout {
+    pub x: f64, // left edge of the playfield
+    pub width: f64,
+    columns: Vec<(f64, f64)>, // left edge and width of each column
+    key_count: i64,           // columns mirror flips, all but the scratch lane
+    lane_width: f64,          // the skin's, for columns past the chart's (broken charts with lanes out of range)
+    note_width: f64,          // the skin's, for a lane_width-wide column
+    mirror: bool,
+}
+
+impl PlayfieldLayout {
+    pub fn new(skin: &Skin, key_count: i64, scratch: bool, mirror: bool, window_width: f64) -> Self {
+        // columns side by side, each with its own width if the skin has one and followed by its gap
+        // (a gap after the last column is ignored), the whole playfield centered and then offset;
+        // the scratch lane, if there is one, is the last column
+        let columns_count = usize::try_from(key_count).unwrap_or(0) + usize::from(scratch);
+        let mut columns = Vec::with_capacity(columns_count);
+        let mut width = 0.0;
+        for column in 0..columns_count {
+            if column > 0 && skin.lane_gap_after.get(column - 1).copied().unwrap_or(false) {
+                width += skin.lane_gap_px;
+            }
+            let lane_width = if scratch && column + 1 == columns_count {
+                skin.scratch_lane_width
+            } else {
+                skin.lane_widths.get(column).copied().flatten().unwrap_or(skin.lane_width)
+            };
+            columns.push((width, lane_width));
+            width += lane_width;
+        }
+        let x = (window_width - width) / 2.0 + skin.playfield_x_offset;
+        for column in &mut columns {
+            column.0 += x;
+        }
+        Self { x, width, columns, key_count, lane_width: skin.lane_width, note_width: skin.note_width, mirror }
+    }
+
+    pub fn of(map: &Map, window_width: f64) -> Self {
+        Self::new(&skin(), map.get_key_count(false), map.has_scratch_key, map.mods.mirror, window_width)
+    }
+
+    pub fn column(&self, lane: i64) -> i64 {
+        // column a chart lane (1-based) is drawn in
+        let is_scratch = self.columns.len() as i64 > self.key_count && lane == self.key_count + 1;
+        if self.mirror && !is_scratch { self.key_count - lane } else { lane - 1 }
+    }
+
+    pub fn columns(&self) -> i64 {
+        // every column, the scratch lane's too
+        self.columns.len() as i64
+    }
+
+    pub fn lane_x(&self, lane_index: i64) -> f64 {
+        // left edge of a column; ones outside the playfield continue it at the skin's lane width
+        match usize::try_from(lane_index).ok().and_then(|index| self.columns.get(index)) {
+            Some(&(x, _)) => x,
+            None if lane_index < 0 => self.x + lane_index as f64 * self.lane_width,
+            None => self.x + self.width + (lane_index - self.columns.len() as i64) as f64 * self.lane_width,
+        }
+    }
+
+    pub fn lane_width(&self, lane_index: i64) -> f64 {
+        usize::try_from(lane_index)
+            .ok()
+            .and_then(|index| self.columns.get(index))
+            .map_or(self.lane_width, |&(_, width)| width)
+    }
+
+    pub fn lane_center(&self, lane_index: i64) -> f64 {
+        self.lane_x(lane_index) + self.lane_width(lane_index) / 2.0
+    }
+
+    pub fn note_width(&self, lane_index: i64) -> f64 {
+        // notes keep the skin's note to lane width ratio in narrower or wider columns
+        if self.lane_width <= 0.0 {
+            return self.note_width;
+        }
+        self.note_width * self.lane_width(lane_index) / self.lane_width
+    }
+
+    pub fn note_x(&self, lane_index: i64) -> f64 {
+        // left edge of a note centered in its column
+        self.lane_center(lane_index) - self.note_width(lane_index) / 2.0
+    }
+}
+
+pub fn set_reference_positions(receptor_texture: Option<&'_ Texture2D>) -> FieldPositions<'_> {
+    let skin = skin();
     let mut field_positions = FieldPositions {
         receptor_position_y: 0.0,
         hit_position_y: 0.0,
+        hold_hit_position_y: 0.0,
+        hold_end_hit_position_y: 0.0,
         timing_line_position_y: 0.0,
         receptor_texture,
     };
 
-    if SKIN.downscroll {
-        field_positions.receptor_position_y = -SKIN.receptors_y_position;
+    if skin.downscroll {
+        field_positions.receptor_position_y = -skin.receptors_y_position;
         field_positions.hit_position_y = field_positions.receptor_position_y;
+        field_positions.hold_hit_position_y = field_positions.receptor_position_y - skin.hold_hit_position_offset;
+        field_positions.hold_end_hit_position_y =
+            field_positions.receptor_position_y - skin.hold_end_hit_position_offset;
         field_positions.timing_line_position_y = field_positions.receptor_position_y;
     } else {
         // i dont care about upscroll right now
@@ -29,14 +136,245 @@ pub fn set_reference_positions(receptor_texture: &'_ Texture2D) -> FieldPosition
     field_positions
 }
 
-pub fn render_frame(state: &mut FrameState, draw: &mut impl Draw) -> Result<()> {
-    // calculates the positions of all objects and renders the current frame given the framestate
+// largest gap (ms) the fixed timestep catches up on tick by tick; anything bigger (seeks, restarts) jumps
+const MAX_SIMULATION_CATCHUP: f64 = 250.0;
+
+// runs the simulation at a fixed tick rate independently of the frame rate
+pub struct FixedTimestep {
+    tick_length: f64,     // ms per simulation tick
+    simulation_time: f64, // time of the latest simulation state
+}
+
+impl FixedTimestep {
+    pub fn new(tick_rate: f64) -> Self {
+        Self {
+            tick_length: 1000.0 / tick_rate.max(1.0),
+            simulation_time: f64::NEG_INFINITY,
+        }
+    }
+
+    // returns the simulation times needed to catch up to `time`, and the render alpha between the last two
+    pub fn advance(&mut self, time: f64) -> (Vec<f64>, f64) {
+        if (time - self.simulation_time).abs() > MAX_SIMULATION_CATCHUP {
+            self.simulation_time = time;
+            return (vec![time], 1.0);
+        }
+
+        let mut ticks = Vec::new();
+        while self.simulation_time + self.tick_length <= time {
+            self.simulation_time += self.tick_length;
+            ticks.push(self.simulation_time);
+        }
+        let alpha = ((time - self.simulation_time) / self.tick_length).clamp(0.0, 1.0);
+        (ticks, alpha)
+    }
+
+    pub const fn reset(&mut self) {
+        // the next advance starts fresh instead of catching up from the last state
+        self.simulation_time = f64::NEG_INFINITY;
+    }
+}
+
+// frame intervals (ms) while nothing is moving, so a paused window doesn't burn a full core
+const BACKGROUND_FRAME_INTERVAL: f64 = 100.0; // ~10 fps
+const PAUSED_FRAME_INTERVAL: f64 = 1000.0 / 30.0; // ~30 fps
+// ms without input before the window counts as in the background (macroquad doesn't report focus)
+const IDLE_TIMEOUT: f64 = 10_000.0;
+// ms after the last input that still run at full rate, so scrubbing and menus stay smooth
+const SCRUB_GRACE: f64 = 500.0;
+
+pub fn target_frame_interval(focused: bool, playing: bool, scrubbing: bool) -> Option<f64> {
+    // how long a frame should take at least, None for as fast as possible
+    if playing || scrubbing {
+        None
+    } else if focused {
+        Some(PAUSED_FRAME_INTERVAL)
+    } else {
+        Some(BACKGROUND_FRAME_INTERVAL)
+    }
+}
+
+// sleeps the main loop while paused, using input activity to tell if the window is in use
+pub struct FrameThrottle {
+    enabled: bool,
+    last_input: Instant,
+    last_mouse_position: (f32, f32),
+    frame_start: Instant,
+    throttled: bool, // whether the last frame was slowed down
+}
+
+impl FrameThrottle {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            last_input: Instant::now(),
+            last_mouse_position: mouse_position(),
+            frame_start: Instant::now(),
+            throttled: false,
+        }
+    }
+
+    // call at the start of each frame, returns true on the first full rate frame after throttling
+    pub fn begin_frame(&mut self, playing: bool) -> bool {
+        self.frame_start = Instant::now();
+        let mouse = mouse_position();
+        let has_input = get_last_key_pressed().is_some()
+            || !get_keys_down().is_empty()
+            || mouse != self.last_mouse_position
+            || mouse_wheel() != (0.0, 0.0)
+            || is_mouse_button_down(MouseButton::Left)
+            || is_mouse_button_down(MouseButton::Right);
+        self.last_mouse_position = mouse;
+        if has_input {
+            self.last_input = self.frame_start;
+        }
+
+        let resumed = self.throttled && self.frame_interval(playing).is_none();
+        if resumed {
+            self.throttled = false;
+        }
+        resumed
+    }
+
+    fn frame_interval(&self, playing: bool) -> Option<f64> {
+        if !self.enabled {
+            return None;
+        }
+        let since_input = self.last_input.elapsed().as_secs_f64() * 1000.0;
+        target_frame_interval(since_input < IDLE_TIMEOUT, playing, since_input < SCRUB_GRACE)
+    }
+
+    // whether the last frame was slowed down on purpose
+    pub const fn is_throttled(&self) -> bool {
+        self.throttled
+    }
 
-    // update functions
+    // call right before next_frame, sleeps off the rest of the frame interval
+    pub fn end_frame(&mut self, playing: bool) {
+        let Some(interval) = self.frame_interval(playing) else {
+            return;
+        };
+        self.throttled = true;
+        let remaining = interval - self.frame_start.elapsed().as_secs_f64() * 1000.0;
+        if remaining > 0.0 {
+            std::thread::sleep(std::time::Duration::from_secs_f64(remaining / 1000.0));
+        }
+    }
+}
+
+pub fn update_frame(state: &mut FrameState) -> Result<()> {
+    // calculates the positions of all objects and judges passed notes at the map's current time
     state.map.update_track_position(state.map.time);
     state.map.update_scroll_speed();
-    state.map.update_timing_lines()?;
+    state.map.update_timing_lines(state.view_height)?;
     state.map.update_hit_objects()?;
+    state.map.update_judgements();
+
+    // comparison chart follows the same clock
+    if let Some(compare_map) = state.compare_map.as_deref_mut() {
+        compare_map.time = state.map.time;
+        compare_map.rate = state.map.rate;
+        compare_map.update_track_position(compare_map.time);
+        compare_map.update_scroll_speed();
+        compare_map.update_hit_objects()?;
+    }
+
+    Ok(())
+}
+
+pub fn update_versus(players: &mut [&mut Map], field_positions: &FieldPositions, time: f64, rate: f64, view_height: f64) -> Result<()> {
+    // every player's chart follows the same clock, but is judged on its own
+    for map in players.iter_mut() {
+        map.time = time;
+        map.rate = rate;
+        update_frame(&mut FrameState {
+            map,
+            compare_map: None,
+            chart_diff: &[],
+            field_positions,
+            alpha: 1.0,
+            view_height,
+            background: None,
+        })?;
+    }
+    Ok(())
+}
+
+pub fn render_versus(players: &mut [&mut Map], field_positions: &FieldPositions, alpha: f64, draw: &mut impl Draw) -> Result<()> {
+    // each player's playfield in its own slice of the screen, with their scores compared in the middle
+    let width = draw.screen_width() / players.len().max(1) as f64;
+    for (index, map) in players.iter_mut().enumerate() {
+        let mut viewport = Viewport {
+            target: draw,
+            x: index as f64 * width,
+            width,
+        };
+        render_frame(
+            &mut FrameState {
+                map,
+                compare_map: None,
+                chart_diff: &[],
+                field_positions,
+                alpha,
+                view_height: viewport.screen_height(),
+                background: None,
+            },
+            &mut viewport,
+        )?;
+        render_hit_error_bar(map, &[], &mut viewport);
+    }
+    draw_versus_stats(players, draw);
+    Ok(())
+}
+
+fn draw_versus_stats(players: &[&mut Map], draw: &mut impl Draw) {
+    // accuracy and combo columns side by side, and who's ahead by how much
+    let column_width = 180.0;
+    let x = (draw.screen_width() - column_width * players.len() as f64) / 2.0;
+    draw.draw_rectangle(x, 20.0, column_width * players.len() as f64, 170.0, Color::new(0.0, 0.0, 0.0, 0.7));
+    for (index, map) in players.iter().enumerate() {
+        let column_x = x + index as f64 * column_width + 15.0;
+        let player = tr_args("versus.player", &[("number", &(index + 1).to_string())]);
+        draw.draw_text(&player, column_x, 50.0, 28.0, GRAY);
+        draw.draw_text(&format!("{:.2}%", map.accuracy()), column_x, 95.0, 40.0, WHITE);
+        draw.draw_text(&tr_args("versus.combo", &[("combo", &map.combo.to_string())]), column_x, 130.0, 28.0, WHITE);
+    }
+
+    if let [first, second] = players {
+        let difference = first.accuracy() - second.accuracy();
+        let (text, color) = if difference.abs() < 0.005 {
+            (tr("versus.tied").to_string(), WHITE)
+        } else {
+            let (leader, color) = if difference > 0.0 { (1, SKYBLUE) } else { (2, ORANGE) };
+            let text = tr_args(
+                "versus.ahead",
+                &[("number", &leader.to_string()), ("difference", &format!("{:.2}", difference.abs()))],
+            );
+            (text, color)
+        };
+        draw.draw_text(&text, x + 15.0, 175.0, 28.0, color);
+    }
+}
+
+pub fn render_frame(state: &mut FrameState, draw: &mut impl Draw) -> Result<()> {
+    // renders the current frame given the framestate (positions come from update_frame), moved by
+    // the chart's playfield effects if it has any
+    if let Some(background) = state.background {
+        // stretched over the screen and left out of the effects, which only move the playfield
+        let (width, height) = (draw.screen_width(), draw.screen_height());
+        draw.draw_texture_scaled(background, 0.0, 0.0, width, height, WHITE);
+        draw.draw_rectangle(0.0, 0.0, width, height, Color::new(0.0, 0.0, 0.0, skin().background_dim as f32));
+    }
+    let layout = PlayfieldLayout::of(state.map, draw.screen_width());
+    let pivot = (layout.x + layout.width / 2.0, draw.screen_height() / 2.0);
+    match playfield_transform(&state.map.effects.playfield, state.map.time, pivot) {
+        Some(transform) => render_playfield(state, &mut Transformed { target: draw, transform }),
+        None => render_playfield(state, draw),
+    }
+}
+
+fn render_playfield(state: &mut FrameState, draw: &mut impl Draw) -> Result<()> {
+    let skin = skin();
 
     // reference/base screen size
     // let base_height = 1440.0;
@@ -47,13 +385,11 @@ pub fn render_frame(state: &mut FrameState, draw: &mut impl Draw) -> Result<()>
     let window_width = draw.screen_width();
     // let base_to_virtual_ratio = window_height / base_height;
 
-    let num_lanes = state.map.get_key_count(false);
-    let playfield_width = num_lanes as f64 * SKIN.lane_width;
-    let playfield_x = (window_width - playfield_width) / 2f64;
+    let layout = PlayfieldLayout::of(state.map, window_width);
 
     // receptors (above notes)
-    match SKIN.note_shape {
-        "bars" => {
+    match skin.note_shape {
+        NoteShape::Bars => {
             // draw.draw_line(
             //     0.0,
             //     window_height + state.field_positions.receptor_position_y,
@@ -62,50 +398,52 @@ pub fn render_frame(state: &mut FrameState, draw: &mut impl Draw) -> Result<()>
             //     3.0,
             //     GRAY,
             // );
-            draw.draw_texture(
-                state.field_positions.receptor_texture,
-                0.0,
-                window_height + state.field_positions.receptor_position_y * 1.88,
-                WHITE,
-            )
-        }
-        "circles" => {
-            for i in 0i32..4i32 {
-                // draw receptors
-                let receptor_x = playfield_x
-                    + (f64::from(i) * SKIN.lane_width)
-                    + (SKIN.lane_width / 2f64); // center in lane;
-
+            if let Some(receptor_texture) = state.field_positions.receptor_texture {
+                draw.draw_texture_flipped(
+                    receptor_texture,
+                    0.0,
+                    window_height + state.field_positions.receptor_position_y * 1.88,
+                    WHITE,
+                    !skin.downscroll && skin.flip_receptor_on_upscroll,
+                );
+            }
+        }
+        NoteShape::Circles => {
+            for column in 0..layout.columns() {
+                // draw receptors, centered in their lanes
                 draw.draw_circle_outline(
-                    receptor_x,
+                    layout.lane_center(column),
                     window_height + state.field_positions.receptor_position_y,
-                    SKIN.note_width / 2.2,
+                    layout.note_width(column) / 2.2,
                     2.0,
                     GRAY,
                 );
             }
         }
-        _ => {}
     }
 
     let line_color = GRAY;
     let line_thickness = 1f64;
 
     // timing lines
-    for timing_line in &state.map.timing_lines {
-        let timing_line_y = (timing_line.current_track_position as f64) + window_height;
+    for timing_line in &state.map.timing_lines[state.map.visible_timing_lines.clone()] {
+        let timing_line_y = lerp(
+            timing_line.previous_track_position as f64,
+            timing_line.current_track_position as f64,
+            state.alpha,
+        ) + window_height;
 
         draw.draw_line(
-            if SKIN.wide_timing_lines {
+            if skin.wide_timing_lines {
                 0.0
             } else {
-                playfield_x
+                layout.x
             },
             timing_line_y,
-            if SKIN.wide_timing_lines {
+            if skin.wide_timing_lines {
                 window_width
             } else {
-                playfield_x + (num_lanes as f64 * SKIN.lane_width)
+                layout.x + layout.width
             },
             timing_line_y,
             line_thickness,
@@ -113,64 +451,99 @@ pub fn render_frame(state: &mut FrameState, draw: &mut impl Draw) -> Result<()>
         );
     }
 
-    // notes
-    // let first = index_at_time(&state.map.hit_objects, state.map.time)
-    //     .unwrap_or(0); // first note to render
-    for index in 0..state.map.hit_objects.len() {
-        let note = &state.map.hit_objects[index];
-        // skip note if hit
-        if note.hit {
+    // comparison chart (drawn underneath the main chart's notes)
+    if let Some(compare_map) = state.compare_map.as_deref() {
+        draw_notes(compare_map, state.field_positions, draw, NoteStyle::Comparison, state.alpha);
+    }
+
+    draw_notes(state.map, state.field_positions, draw, NoteStyle::Normal, state.alpha);
+
+    // chart differences
+    for entry in state.chart_diff {
+        let map = match entry.side {
+            DiffSide::Main => &*state.map,
+            DiffSide::Compare => match state.compare_map.as_deref() {
+                Some(compare_map) => compare_map,
+                None => continue,
+            },
+        };
+        let note = &map.hit_objects[entry.index];
+        if note.hit || is_past_receptor(map, entry.index) {
             continue;
         }
-        let is_long_note = note.end_time.is_some();
-        
-        // calculate x position based on lane (1-indexed in quaver)
-        // adjust lane to be 0-indexed for calculation
-        let lane_index = if state.map.mods.mirror {
-            num_lanes - note.lane
-        } else {
-            note.lane - 1
+
+        // the compared chart is mirrored along with the main one
+        let marker_x = layout.lane_x(layout.column(note.lane));
+        let marker_y = note.interpolated_position(state.alpha) + window_height - skin.note_height;
+        let marker_color = match entry.side {
+            DiffSide::Main => RED,
+            DiffSide::Compare => SKYBLUE,
         };
+        draw.draw_rectangle(marker_x, marker_y, 6.0, skin.note_height, marker_color);
+    }
 
-        if state.map.mods.autoplay
-            && (note.start_time <= state.map.time)
-            && (!is_long_note || note.end_time.unwrap() <= state.map.time) {
-            // past receptors in autoplay mode = hit note perfectly
-            state.map.handle_gameplay_key_press(note.start_time, lane_index);
+    Ok(())
+}
+
+// how a chart's notes are drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteStyle {
+    Normal,     // the played chart
+    Comparison, // overlaid chart from --compare, drawn as outlines
+}
+
+// returns whether a note has fully passed the receptors (LNs pass once their end does)
+fn is_past_receptor(map: &Map, index: usize) -> bool {
+    let note = &map.hit_objects[index];
+    note.end_time.unwrap_or(note.start_time) <= map.time
+}
+
+pub fn draw_notes(map: &Map, field_positions: &FieldPositions, draw: &mut impl Draw, style: NoteStyle, alpha: f64) {
+    let skin = skin();
+    // draws all visible notes of a map, interpolated between the last two simulation states
+    let window_height = draw.screen_height();
+    let window_width = draw.screen_width();
+
+    let layout = PlayfieldLayout::of(map, window_width);
+
+    // timing group by timing group, so later groups' notes are drawn over earlier ones'
+    for index in notes_in_view(map, window_height) {
+        let note = &map.hit_objects[index];
+        // skip note once fully judged (LNs stay until their end is)
+        if note.is_finished() {
             continue;
         }
-        if state.map.time - note.start_time >= state.map.judgement_windows[&JudgementType::Miss] {
-            *state.map.judgement_counts.get_mut(&JudgementType::Miss).unwrap() += 1;
-            state.map.hit_objects[index].hit = true;
-            state.map.last_judgement = Some((JudgementType::Miss, state.map.time, 0.0));
-            state.map.combo = 0;
+        // comparison notes are never judged, so hide them once they pass the receptors
+        if style == NoteStyle::Comparison && is_past_receptor(map, index) {
             continue;
         }
+        let is_long_note = note.end_time.is_some();
 
-        let is_held = note.start_time <= state.map.time;
+        // calculate x position based on lane (1-indexed in quaver, mirrored by the layout)
+        let lane_index = layout.column(note.lane);
+        let note_width = layout.note_width(lane_index);
+
+        let is_held = note.start_time <= map.time;
 
         // real hitbox
         let note_y = if is_long_note && is_held {
-            // held notes are rendered at the receptors
-            state.field_positions.receptor_position_y + window_height
+            // held notes are rendered at the hold hit position
+            field_positions.hold_hit_position_y + window_height
         } else {
-            (note.position as f64) + window_height
+            note.interpolated_position(alpha) + window_height
         };
 
-        let note_tail_y = (note.position_tail as f64) + window_height; // long note end position
+        let note_tail_y = note.interpolated_position_tail(alpha) + window_height; // long note end position
 
-        let note_x = playfield_x
-            + (lane_index as f64 * SKIN.lane_width)
-            + (SKIN.lane_width / 2f64) // center in lane
-            - (SKIN.note_width / 2f64);
+        let note_x = layout.note_x(lane_index); // centered in lane
 
-        let half_note_height = SKIN.note_height / 2f64;
+        let half_note_height = skin.note_height / 2f64;
 
         let mut note_top_offset = half_note_height;
         let mut note_bottom_offset = half_note_height;
         let middle_position = note_y - half_note_height;
         let frame_behind = 0;
-        let stretch_limit = SKIN.note_height * 8f64; // max stretch limit
+        let stretch_limit = skin.note_height * 8f64; // max stretch limit
 
         // calculate stretch from previous positions
         for i in 0..note.previous_positions.len() {
@@ -178,7 +551,7 @@ pub fn render_frame(state: &mut FrameState, draw: &mut impl Draw) -> Result<()>
                 break;
             }
             // pos = moving down (top), neg = moving up (bottom)
-            let stretch = (note.position - note.previous_positions[i]) as f64;
+            let stretch = note.position.saturating_sub(note.previous_positions[i]) as f64;
 
             if stretch.abs() > stretch_limit {
                 // stretch is too big, ignore
@@ -192,17 +565,46 @@ pub fn render_frame(state: &mut FrameState, draw: &mut impl Draw) -> Result<()>
             }
         }
 
-        // snap colors
-        let color = BEAT_SNAPS[note.snap_index].color;
+        // snap colors, notes moved out of an unknown timing group stand out in debug mode
+        let color = if map.mods.debug && note.unknown_timing_group.is_some() {
+            MAGENTA
+        } else if note.object_kind == ObjectKind::Mine {
+            skin.mine_color
+        } else {
+            skin.snap_colors[note.snap_index]
+        };
+        // off-snap notes get a warning outline in debug mode
+        let off_snap = map.mods.debug && note.snap_error > OFF_SNAP_TOLERANCE;
 
-        match SKIN.note_shape {
-            "bars" => {
+        match skin.note_shape {
+            NoteShape::Bars => {
+                if style == NoteStyle::Comparison {
+                    if is_long_note {
+                        draw.draw_rectangle_outline(
+                            note_x,
+                            note_y,
+                            note_width,
+                            note_tail_y - note_y,
+                            2.0,
+                            Color { a: 0.4, ..color },
+                        );
+                    }
+                    draw.draw_rectangle_outline(
+                        note_x,
+                        middle_position - note_top_offset,
+                        note_width,
+                        note_top_offset + note_bottom_offset,
+                        2.0,
+                        color,
+                    );
+                    continue;
+                }
                 if is_long_note {
                     let height = note_tail_y - note_y;
                     draw.draw_rectangle(
                         note_x,
                         note_y, // bottom of ln
-                        SKIN.note_width,
+                        note_width,
                         height, // top/height of ln
                         DARKGRAY,
                     );
@@ -210,42 +612,712 @@ pub fn render_frame(state: &mut FrameState, draw: &mut impl Draw) -> Result<()>
                 draw.draw_rectangle(
                     note_x,
                     middle_position - note_top_offset, // bottom of note
-                    SKIN.note_width,
+                    note_width,
                     note_top_offset + note_bottom_offset, // top/height of note
                     color,
                 );
+                if off_snap {
+                    draw.draw_rectangle_outline(
+                        note_x - 3.0,
+                        middle_position - note_top_offset - 3.0,
+                        note_width + 6.0,
+                        note_top_offset + note_bottom_offset + 6.0,
+                        2.0,
+                        ORANGE,
+                    );
+                }
                 // draw.draw_rectangle( // middle of note
                 //     note_x,
                 //     middle_position,
-                //     SKIN.note_width,
+                //     skin.note_width,
                 //     1.0,
                 //     WHITE,
                 // );
                 // draw.draw_rectangle( // hitbox
                 //     note_x,
                 //     note_y,
-                //     SKIN.note_width,
+                //     skin.note_width,
                 //     1.0,
                 //     WHITE,
                 // );
             }
-            "circles" => {
+            NoteShape::Circles => {
                 // if let Some(end_y) = long_end_y {
                 //     let top = note_y.min(end_y);
                 //     let height = (end_y - note_y).abs();
-                //     let center_x = note_x + (SKIN.note_width / 2.0);
+                //     let center_x = note_x + (skin.note_width / 2.0);
                 //     draw.draw_rectangle(center_x - 2.0, top, 4.0, height, color);
                 // }
+                if style == NoteStyle::Comparison {
+                    draw.draw_circle_outline(
+                        note_x + (note_width / 2.0),
+                        note_y,
+                        note_width / 2.4,
+                        2.0,
+                        color,
+                    );
+                    continue;
+                }
                 draw.draw_circle(
-                    note_x + (SKIN.note_width / 2.0),
+                    note_x + (note_width / 2.0),
                     note_y,
-                    SKIN.note_width / 2.4,
+                    note_width / 2.4,
                     color,
                 );
+                if off_snap {
+                    draw.draw_circle_outline(
+                        note_x + (note_width / 2.0),
+                        note_y,
+                        note_width / 2.4 + 3.0,
+                        2.0,
+                        ORANGE,
+                    );
+                }
             }
-            _ => {}
         }
     }
+}
 
-    Ok(())
+pub fn render_progress_bar(map: &Map, draw: &mut impl Draw) {
+    // thin bar at the top of the screen; the chart's playable portion is shaded lighter
+    let bar_height = 6.0;
+    let window_width = draw.screen_width();
+    let total_length = map.length.max(map.playable_length);
+    if total_length <= 0.0 {
+        return;
+    }
+
+    let to_x = |time: f64| (time / total_length).clamp(0.0, 1.0) * window_width;
+    draw.draw_rectangle(0.0, 0.0, window_width, bar_height, Color::new(0.15, 0.15, 0.15, 1.0));
+    draw.draw_rectangle(0.0, 0.0, to_x(map.playable_length), bar_height, Color::new(0.3, 0.3, 0.3, 1.0));
+    draw.draw_rectangle(0.0, 0.0, to_x(map.time), bar_height, WHITE);
+}
+
+// how long (ms) a tick stays on the hit error bar
+const HIT_ERROR_FADE_TIME: f64 = 5000.0;
+
+// pixels per ms on the hit error bar, so the wider miss window spans half the bar's width
+pub fn hit_error_bar_scale(map: &Map) -> f64 {
+    let skin = skin();
+    let miss_window = map.judgement_windows.widest();
+    if miss_window <= 0.0 {
+        return 0.0;
+    }
+    skin.hit_error_bar_width / (2.0 * miss_window)
+}
+
+pub fn render_hit_error_bar(map: &Map, regions: &[ReservedRegion], draw: &mut impl Draw) {
+    let skin = skin();
+    // hit offsets as ticks over the judgement windows, early on the left and late on the right
+    let scale = hit_error_bar_scale(map);
+    let center_x = draw.screen_width() / 2.0;
+    let bar_height = skin.hit_error_bar_height;
+    let bar_y = avoid_regions(
+        regions,
+        center_x - skin.hit_error_bar_width / 2.0,
+        draw.screen_height() - skin.hit_error_bar_y,
+        skin.hit_error_bar_width,
+        bar_height,
+        draw.screen_height(),
+    );
+
+    // window bands, widest first so the narrower ones draw on top
+    for judgement in JUDGEMENTS.iter().rev() {
+        let early_width = map.judgement_windows.early(judgement.kind) * scale;
+        let late_width = map.judgement_windows.late(judgement.kind) * scale;
+        let color = judgement_color(judgement.kind);
+        draw.draw_rectangle(
+            center_x - early_width,
+            bar_y,
+            early_width + late_width,
+            bar_height,
+            Color { a: 0.25, ..color },
+        );
+    }
+    draw.draw_line(center_x, bar_y - 4.0, center_x, bar_y + bar_height + 4.0, 2.0, WHITE);
+
+    // recent hits on top of the bands
+    for hit_stat in map.hit_stats.iter().rev() {
+        let age = map.time - hit_stat.time;
+        if age > HIT_ERROR_FADE_TIME {
+            break;
+        }
+        if hit_stat.judgement == JudgementType::Miss || age < 0.0 {
+            continue;
+        }
+        let tick_x = center_x - hit_stat.offset * scale;
+        let alpha = (1.0 - age / HIT_ERROR_FADE_TIME) as f32;
+        let color = judgement_color(hit_stat.judgement);
+        draw.draw_line(tick_x, bar_y, tick_x, bar_y + bar_height, 2.0, Color { a: alpha, ..color });
+    }
+}
+
+// how far (px) a lane judgement floats up over its lifetime
+const LANE_SPLASH_RISE: f64 = 40.0;
+
+pub fn render_lane_splashes(map: &Map, field_positions: &FieldPositions, draw: &mut impl Draw) {
+    let skin = skin();
+    // each lane's latest judgement, floating up from above its receptor while it fades
+    // (one flashes with every note in dense streams, so not in safe mode)
+    if !skin.lane_splash || map.safe_mode_speed.is_some() {
+        return;
+    }
+    let layout = PlayfieldLayout::of(map, draw.screen_width());
+    let base_y = draw.screen_height() + field_positions.receptor_position_y - skin.lane_splash_offset;
+    // by lane, so the draw order doesn't depend on HashMap iteration order
+    let mut splashes: Vec<_> = map.lane_splashes.iter().collect();
+    splashes.sort_unstable_by_key(|(&lane, _)| lane);
+    for (&lane, &(judgement, time)) in splashes {
+        // song time, so splashes freeze while paused and follow the rate
+        let progress = (map.time - time) / skin.lane_splash_duration;
+        if !(0.0..1.0).contains(&progress) {
+            continue;
+        }
+        let text = judgement.localized();
+        let size = 24.0;
+        // centered in the lane, roughly (the draw target can't measure text)
+        let x = layout.lane_center(layout.column(lane)) - text.len() as f64 * size / 4.0;
+        let y = base_y - progress * LANE_SPLASH_RISE;
+        let color = Color { a: (1.0 - progress) as f32, ..judgement_color(judgement) };
+        draw.draw_text(text, x, y, size, color);
+    }
+}
+
+// judgements given a band on the playfield by the hit window preview, widest first so narrower ones are drawn over it
+const WINDOW_BAND_JUDGEMENTS: [JudgementType; 2] = [JudgementType::Perfect, JudgementType::Marvelous];
+const WINDOW_BAND_ALPHA: f32 = 0.15;
+
+pub fn render_hit_window_bands(map: &Map, field_positions: &FieldPositions, draw: &mut impl Draw) {
+    // faint bands across the playfield where a note has to be right now to get each judgement,
+    // drawn under the notes
+    let skin = skin();
+    let layout = PlayfieldLayout::of(map, draw.screen_width());
+    let window_height = draw.screen_height();
+    // bars are drawn a note height above their position, circles centered on it; bands cover the same span
+    let note_span = match skin.note_shape {
+        NoteShape::Bars => skin.note_height,
+        NoteShape::Circles => 0.0,
+    };
+    for judgement in WINDOW_BAND_JUDGEMENTS {
+        let Some((early_y, late_y)) = map.hit_window_edges(judgement, field_positions.hit_position_y) else {
+            continue;
+        };
+        let top = early_y.min(late_y) + window_height - note_span;
+        let height = (early_y - late_y).abs() + note_span;
+        let color = Color { a: WINDOW_BAND_ALPHA, ..judgement_color(judgement) };
+        draw.draw_rectangle(layout.x, top, layout.width, height, color);
+    }
+}
+
+pub fn note_at_screen_position(map: &Map, field_positions: &FieldPositions, window_width: f64, window_height: f64, x: f64, y: f64) -> Option<usize> {
+    // hit-tests the visible notes (head and LN body) against a screen point, topmost (latest drawn) first;
+    // only the notes draw_notes would look at, not the whole chart
+    let skin = skin();
+    let layout = PlayfieldLayout::of(map, window_width);
+
+    notes_in_view(map, window_height).rev().find(|&index| {
+        let note = &map.hit_objects[index];
+        if note.is_finished() {
+            return false;
+        }
+        let is_long_note = note.end_time.is_some();
+        let note_y = if is_long_note && note.start_time <= map.time {
+            field_positions.hold_hit_position_y + window_height
+        } else {
+            note.position as f64 + window_height
+        };
+        let top = if is_long_note {
+            (note.position_tail as f64 + window_height).min(note_y - skin.note_height)
+        } else {
+            note_y - skin.note_height
+        };
+        if note_y < 0.0 || top > window_height {
+            return false; // culled, off screen
+        }
+
+        let lane_index = layout.column(note.lane);
+        let note_x = layout.note_x(lane_index);
+        (note_x..=note_x + layout.note_width(lane_index)).contains(&x) && (top..=note_y).contains(&y)
+    })
+}
+
+pub fn render_note_inspection(inspection: &NoteInspection, x: f64, y: f64, draw: &mut impl Draw) {
+    // tooltip with a note's computed data, kept on screen
+    let lines = inspection.lines();
+    let line_height = 20.0;
+    let padding = 8.0;
+    let width = 360.0;
+    let height = lines.len() as f64 * line_height + padding * 2.0;
+    let x = x.min(draw.screen_width() - width).max(0.0);
+    let y = y.min(draw.screen_height() - height).max(0.0);
+
+    draw.draw_rectangle(x, y, width, height, Color::new(0.0, 0.0, 0.0, 0.85));
+    draw.draw_rectangle_outline(x, y, width, height, 1.0, GRAY);
+    for (line_index, line) in lines.iter().enumerate() {
+        let line_y = y + padding + (line_index + 1) as f64 * line_height - 5.0;
+        draw.draw_text(line, x + padding, line_y, 20.0, WHITE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw::{DrawCommand, RecordingDraw};
+    use crate::utils::{set_skin, JudgementWindows, Skin, DEFAULT_SKIN};
+    use macroquad::miniquad;
+    use std::path::Path;
+
+    fn chart(name: &str) -> Map {
+        let mut map = Map::from_file(&Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/charts").join(name)).unwrap();
+        map.rate = 1.0;
+        map
+    }
+
+    #[test]
+    fn empty_map_renders_every_frame() {
+        // metadata only: no notes, timing points or groups
+        let mut map = chart("empty.qua");
+        let field_positions = set_reference_positions(None);
+        crate::initialize_map(&mut map, &field_positions).unwrap();
+        for frame in 0..100 {
+            map.time = f64::from(frame) * 1000.0 / 60.0;
+            let mut draw = RecordingDraw::new(1000.0, 1200.0);
+            let mut state = FrameState {
+                map: &mut map,
+                compare_map: None,
+                chart_diff: &[],
+                field_positions: &field_positions,
+                alpha: 1.0,
+                view_height: 1200.0,
+                background: None,
+            };
+            update_frame(&mut state).unwrap_or_else(|e| panic!("frame {frame} didn't update: {e}"));
+            render_frame(&mut state, &mut draw).unwrap_or_else(|e| panic!("frame {frame} didn't render: {e}"));
+        }
+        assert!(map.hit_stats.is_empty());
+    }
+
+    #[test]
+    fn frames_are_only_slowed_down_when_idle() {
+        // (focused, playing, scrubbing) -> ms per frame
+        let cases = [
+            ((false, false, false), Some(BACKGROUND_FRAME_INTERVAL)),
+            ((false, false, true), None),
+            ((false, true, false), None),
+            ((false, true, true), None),
+            ((true, false, false), Some(PAUSED_FRAME_INTERVAL)),
+            ((true, false, true), None),
+            ((true, true, false), None),
+            ((true, true, true), None),
+        ];
+        for ((focused, playing, scrubbing), expected) in cases {
+            assert_eq!(
+                target_frame_interval(focused, playing, scrubbing),
+                expected,
+                "focused {focused}, playing {playing}, scrubbing {scrubbing}"
+            );
+        }
+    }
+
+    fn note_draw_order(z_layer: &str) -> Vec<i64> {
+        // one note per group at the same time, each in its own lane: the lanes in the order the notes were drawn
+        let chart = format!(
+            "\
+Mode: Keys4
+TimingPoints:
+- StartTime: 0
+  Bpm: 120
+TimingGroups:
+  zeta:
+    InitialScrollVelocity: 1
+{z_layer}  alpha:
+    InitialScrollVelocity: 1
+HitObjects:
+- StartTime: 1000
+  Lane: 3
+  KeySounds: []
+  TimingGroup: alpha
+- StartTime: 1000
+  Lane: 2
+  KeySounds: []
+- StartTime: 1000
+  Lane: 1
+  KeySounds: []
+  TimingGroup: zeta
+"
+        );
+        let mut map: Map = serde_yaml::from_str(&chart).unwrap();
+        map.rate = 1.0;
+        map.time = 500.0;
+        let field_positions = set_reference_positions(None);
+        crate::initialize_map(&mut map, &field_positions).unwrap();
+        let mut draw = RecordingDraw::new(1000.0, 1200.0);
+        let mut state = FrameState {
+            map: &mut map,
+            compare_map: None,
+            chart_diff: &[],
+            field_positions: &field_positions,
+            alpha: 1.0,
+            view_height: 1200.0,
+            background: None,
+        };
+        update_frame(&mut state).unwrap();
+        render_frame(&mut state, &mut draw).unwrap();
+        let layout = PlayfieldLayout::new(&DEFAULT_SKIN, 4, false, false, 1000.0);
+        draw.commands
+            .iter()
+            .filter_map(|command| match *command {
+                DrawCommand::Rectangle { x, w, h, .. } if w == DEFAULT_SKIN.note_width && h == DEFAULT_SKIN.note_height => {
+                    (1..=4).find(|&lane| layout.note_x(layout.column(lane)) == x)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn later_defined_groups_are_drawn_on_top() {
+        // the default group, then zeta and alpha in the order the chart defines them (not by name)
+        assert_eq!(note_draw_order(""), [2, 1, 3]);
+        // a higher layer goes over everything on a lower one
+        assert_eq!(note_draw_order("    ZLayer: 1\n"), [2, 3, 1]);
+        assert_eq!(note_draw_order("    ZLayer: -1\n"), [1, 2, 3]);
+    }
+
+    fn column_edges(layout: &PlayfieldLayout) -> Vec<(f64, f64)> {
+        (0..layout.columns()).map(|column| (layout.lane_x(column), layout.lane_width(column))).collect()
+    }
+
+    #[test]
+    fn lanes_are_laid_out_side_by_side_and_centered() {
+        let layout = PlayfieldLayout::new(&DEFAULT_SKIN, 4, false, false, 1000.0);
+        assert_eq!((layout.x, layout.width), (210.0, 580.0));
+        assert_eq!(column_edges(&layout), [(210.0, 145.0), (355.0, 145.0), (500.0, 145.0), (645.0, 145.0)]);
+        assert_eq!(layout.lane_center(1), 427.5);
+        // columns past the playfield carry on at the skin's lane width
+        assert_eq!((layout.lane_x(-1), layout.lane_x(4), layout.lane_x(5)), (65.0, 790.0, 935.0));
+        let offset = PlayfieldLayout::new(&Skin { playfield_x_offset: -50.0, ..DEFAULT_SKIN }, 4, false, false, 1000.0);
+        assert_eq!((offset.x, offset.lane_x(3)), (160.0, 595.0));
+    }
+
+    #[test]
+    fn lane_gaps_widen_the_playfield_between_their_columns() {
+        let mut skin = Skin { lane_gap_px: 40.0, ..DEFAULT_SKIN };
+        skin.lane_gap_after[1] = true;
+        // after the last column it's ignored
+        skin.lane_gap_after[3] = true;
+        let layout = PlayfieldLayout::new(&skin, 4, false, false, 1000.0);
+        assert_eq!((layout.x, layout.width), (190.0, 620.0));
+        assert_eq!(column_edges(&layout), [(190.0, 145.0), (335.0, 145.0), (520.0, 145.0), (665.0, 145.0)]);
+    }
+
+    #[test]
+    fn lane_width_overrides_resize_their_columns_and_notes() {
+        let mut skin = DEFAULT_SKIN;
+        skin.lane_widths[0] = Some(100.0);
+        skin.lane_widths[3] = Some(200.0);
+        let layout = PlayfieldLayout::new(&skin, 4, false, false, 1000.0);
+        assert_eq!((layout.x, layout.width), (205.0, 590.0));
+        assert_eq!(column_edges(&layout), [(205.0, 100.0), (305.0, 145.0), (450.0, 145.0), (595.0, 200.0)]);
+        // notes keep the skin's note to lane width ratio
+        assert_eq!((layout.note_width(0), layout.note_width(1), layout.note_width(3)), (100.0, 145.0, 200.0));
+        assert_eq!(layout.note_x(3), 595.0);
+    }
+
+    #[test]
+    fn mirror_flips_the_lanes_but_not_the_scratch_lane() {
+        let plain = PlayfieldLayout::new(&DEFAULT_SKIN, 4, true, false, 1000.0);
+        let mirrored = PlayfieldLayout::new(&DEFAULT_SKIN, 4, true, true, 1000.0);
+        // the scratch lane is the last column, at its own width
+        assert_eq!((plain.width, plain.lane_width(4)), (760.0, 180.0));
+        assert_eq!(column_edges(&plain), column_edges(&mirrored));
+        let columns = |layout: &PlayfieldLayout| (1..=5).map(|lane| layout.column(lane)).collect::<Vec<_>>();
+        assert_eq!(columns(&plain), [0, 1, 2, 3, 4]);
+        assert_eq!(columns(&mirrored), [3, 2, 1, 0, 4]);
+    }
+
+    fn lane_splash_texts(map: &mut Map, time: f64) -> Vec<(String, f64)> {
+        // the lane splashes drawn at a time, and where
+        map.time = time;
+        let mut draw = RecordingDraw::new(1000.0, 1200.0);
+        render_lane_splashes(map, &set_reference_positions(None), &mut draw);
+        draw.commands
+            .into_iter()
+            .filter_map(|command| match command {
+                DrawCommand::Text { text, x, .. } => Some((text, x)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chord_gets_a_lane_splash_per_lane_until_they_expire() {
+        let chord = "\
+Mode: Keys4
+TimingPoints:
+- StartTime: 0
+  Bpm: 60
+HitObjects:
+- StartTime: 1000
+  Lane: 1
+  KeySounds: []
+- StartTime: 1000
+  Lane: 2
+  KeySounds: []
+- StartTime: 1000
+  Lane: 3
+  KeySounds: []
+- StartTime: 5000
+  Lane: 4
+  KeySounds: []
+";
+        set_skin(Skin { lane_splash_duration: 200.0, ..DEFAULT_SKIN });
+        let mut map: Map = serde_yaml::from_str(chord).unwrap();
+        map.rate = 1.0;
+        crate::initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        for key in 0..3 {
+            map.handle_gameplay_key_press(1000.0, key);
+        }
+        let splashes = lane_splash_texts(&mut map, 1000.0);
+        assert_eq!(splashes.len(), 3);
+        assert!(splashes.iter().all(|(text, _)| text == JudgementType::Marvelous.localized()));
+        // one over each judged lane, left to right
+        assert!(splashes.windows(2).all(|pair| pair[0].1 < pair[1].1), "{splashes:?}");
+
+        assert_eq!(lane_splash_texts(&mut map, 1199.0).len(), 3);
+        assert!(lane_splash_texts(&mut map, 1200.0).is_empty());
+        // nothing before the judgement either (seeking back)
+        assert!(lane_splash_texts(&mut map, 999.0).is_empty());
+        set_skin(DEFAULT_SKIN);
+        assert_eq!(lane_splash_texts(&mut map, 1250.0).len(), 3);
+    }
+
+    #[test]
+    fn notes_are_found_under_the_cursor() {
+        let mut map = chart("plain_4k.qua");
+        let field_positions = set_reference_positions(None);
+        crate::initialize_map(&mut map, &field_positions).unwrap();
+        let (width, height) = (1000.0, 1080.0);
+        map.time = 450.0;
+        map.update_track_position(map.time);
+        map.update_scroll_speed();
+        map.update_hit_objects().unwrap();
+
+        let layout = PlayfieldLayout::of(&map, width);
+        let note_height = skin().note_height;
+        let on_screen: Vec<usize> = (0..map.hit_objects.len())
+            .filter(|&index| {
+                let y = map.hit_objects[index].position as f64 + height;
+                y >= 0.0 && y - note_height <= height
+            })
+            .collect();
+        assert!(!on_screen.is_empty() && on_screen.len() < map.hit_objects.len());
+        for &index in &on_screen {
+            let note = &map.hit_objects[index];
+            let column = layout.column(note.lane);
+            let x = layout.note_x(column) + layout.note_width(column) / 2.0;
+            let y = note.position as f64 + height - note_height / 2.0;
+            assert_eq!(note_at_screen_position(&map, &field_positions, width, height, x, y), Some(index));
+            // the same height in a lane with nothing there
+            let other = layout.note_x(layout.column(note.lane % 4 + 1)) + 1.0;
+            let other_lane_note = note_at_screen_position(&map, &field_positions, width, height, other, y);
+            assert!(other_lane_note.is_none_or(|other_index| map.hit_objects[other_index].lane != note.lane));
+        }
+        // above everything on screen
+        assert_eq!(note_at_screen_position(&map, &field_positions, width, height, width / 2.0, -100.0), None);
+    }
+
+    #[test]
+    fn fixed_timestep_moves_notes_smoothly_at_uneven_frame_times() {
+        let mut map = chart("plain_4k.qua");
+        let field_positions = set_reference_positions(None);
+        crate::initialize_map(&mut map, &field_positions).unwrap();
+        let mut fixed_timestep = FixedTimestep::new(60.0);
+        let last = map.hit_objects.len() - 1;
+        let mut time = 0.0;
+        let mut positions = Vec::new();
+        for frame_length in [3.0, 16.7, 1.0, 40.0, 8.3, 0.5, 33.3, 16.7, 25.0, 2.0].repeat(5) {
+            time += frame_length;
+            let (ticks, alpha) = fixed_timestep.advance(time);
+            for tick in ticks {
+                map.time = tick;
+                let mut state = FrameState {
+                    map: &mut map,
+                    compare_map: None,
+                    chart_diff: &[],
+                    field_positions: &field_positions,
+                    alpha,
+                    view_height: 1080.0,
+                    background: None,
+                };
+                update_frame(&mut state).unwrap();
+            }
+            positions.push(map.hit_objects[last].interpolated_position(alpha));
+        }
+        // downscroll, so notes only ever move down towards the receptors
+        assert!(positions.windows(2).all(|pair| pair[0] <= pair[1]), "{positions:?}");
+        assert!(positions[0] < positions[positions.len() - 1]);
+    }
+
+    fn error_bar_bands(windows: JudgementWindows) -> Vec<(f64, f64)> {
+        // left and right edge of every band drawn, in draw order
+        let mut map = Map::default();
+        map.judgement_windows = windows;
+        map.time = 1000.0;
+        let mut draw = RecordingDraw::new(1000.0, 800.0);
+        render_hit_error_bar(&map, &[], &mut draw);
+        draw.commands
+            .iter()
+            .filter_map(|command| match *command {
+                DrawCommand::Rectangle { x, w, .. } => Some((x, x + w)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn error_bar_bands_are_as_wide_as_their_windows() {
+        let bands = error_bar_bands(JudgementWindows::symmetric(JUDGEMENTS));
+        assert_eq!(bands.len(), JUDGEMENTS.len());
+        // the miss window covers the whole bar, each band is centered on the screen
+        let scale = skin().hit_error_bar_width / (2.0 * 164.0);
+        for ((left, right), judgement) in bands.iter().zip(JUDGEMENTS.iter().rev()) {
+            assert!((500.0 - left - judgement.window * scale).abs() <= 0.1, "{:?} starts at {left}", judgement.kind);
+            assert!((right - 500.0 - judgement.window * scale).abs() <= 0.1, "{:?} ends at {right}", judgement.kind);
+        }
+        assert_eq!(bands[0], (350.0, 650.0));
+        // widest first, so every narrower band is drawn on top
+        assert!(bands.windows(2).all(|pair| pair[1].1 - pair[1].0 < pair[0].1 - pair[0].0));
+    }
+
+    #[test]
+    fn error_bar_bands_follow_asymmetric_windows() {
+        let mut windows = JudgementWindows::symmetric(JUDGEMENTS);
+        windows.early.insert(JudgementType::Marvelous, 10.0);
+        windows.late.insert(JudgementType::Marvelous, 30.0);
+        windows.late.insert(JudgementType::Miss, 328.0);
+        let bands = error_bar_bands(windows);
+        // the late miss window is now the widest, so it spans half the bar
+        let scale = skin().hit_error_bar_width / (2.0 * 328.0);
+        assert_eq!(bands[0], (425.0, 650.0));
+        let (left, right) = bands[bands.len() - 1];
+        assert!((500.0 - left - 10.0 * scale).abs() <= 0.1 && (right - 500.0 - 30.0 * scale).abs() <= 0.1, "{left}..{right}");
+    }
+
+    fn window_bands(windows: JudgementWindows) -> Vec<(f64, f64)> {
+        // top and height of every hit window band drawn a second into a 60 bpm chart without svs, in draw order
+        let mut map: Map = serde_yaml::from_str(
+            "\
+Mode: Keys4
+TimingPoints:
+- StartTime: 0
+  Bpm: 60
+HitObjects:
+- StartTime: 2000
+  Lane: 1
+  KeySounds: []
+",
+        )
+        .unwrap();
+        map.rate = 1.0;
+        let field_positions = set_reference_positions(None);
+        crate::initialize_map(&mut map, &field_positions).unwrap();
+        map.judgement_windows = windows;
+        map.update_track_position(1000.0);
+        map.update_scroll_speed();
+        let mut draw = RecordingDraw::new(1000.0, 1200.0);
+        render_hit_window_bands(&map, &field_positions, &mut draw);
+        draw.commands
+            .iter()
+            .filter_map(|command| match *command {
+                DrawCommand::Rectangle { y, h, .. } => Some((y, h)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn assert_band(band: (f64, f64), early: f64, late: f64) {
+        // a band from `early` ms above the receptors to `late` ms below them, plus a bar's height above
+        let skin = skin();
+        let pixels_per_ms = Map::pixels_per_ms(skin.scroll_speed, 1.0, skin.normalize_scroll_velocity_by_rate_percentage);
+        let top = 1200.0 - skin.receptors_y_position - early * pixels_per_ms - skin.note_height;
+        let height = (early + late) * pixels_per_ms + skin.note_height;
+        assert!((band.0 - top).abs() <= 1.0 && (band.1 - height).abs() <= 1.0, "{band:?} isn't ({top}, {height})");
+    }
+
+    #[test]
+    fn window_bands_cover_their_windows_at_the_scroll_speed() {
+        set_skin(DEFAULT_SKIN);
+        let bands = window_bands(JudgementWindows::symmetric(JUDGEMENTS));
+        // perfect first, so marvelous is drawn over it
+        assert_eq!(bands.len(), 2);
+        assert_band(bands[0], 43.0, 43.0);
+        assert_band(bands[1], 18.0, 18.0);
+        // the default skin's 320 is about 2.25 px per ms
+        assert_eq!(bands[1], (898.0, 117.0));
+
+        // half the scroll speed, half as tall
+        set_skin(Skin { scroll_speed: 160.0, ..DEFAULT_SKIN });
+        let slower = window_bands(JudgementWindows::symmetric(JUDGEMENTS));
+        assert_band(slower[0], 43.0, 43.0);
+        assert_band(slower[1], 18.0, 18.0);
+        assert!(((slower[0].1 - 36.0) * 2.0 - (bands[0].1 - 36.0)).abs() <= 2.0, "{slower:?} {bands:?}");
+        set_skin(DEFAULT_SKIN);
+    }
+
+    #[test]
+    fn window_bands_follow_asymmetric_windows_and_note_shape() {
+        set_skin(DEFAULT_SKIN);
+        let mut windows = JudgementWindows::symmetric(JUDGEMENTS);
+        windows.early.insert(JudgementType::Marvelous, 10.0);
+        windows.late.insert(JudgementType::Marvelous, 30.0);
+        let bands = window_bands(windows.clone());
+        assert_band(bands[1], 10.0, 30.0);
+
+        // circles are centered on their position, so their bands are just the window
+        set_skin(Skin { note_shape: NoteShape::Circles, ..DEFAULT_SKIN });
+        let circles = window_bands(windows);
+        assert_eq!((circles[1].0, circles[1].1), (bands[1].0 + 36.0, bands[1].1 - 36.0));
+        set_skin(DEFAULT_SKIN);
+    }
+
+    fn receptor_flips(skin: Skin) -> Vec<bool> {
+        // which way up the receptor texture is drawn with a skin, once per texture drawn
+        set_skin(skin);
+        // a texture that's never uploaded, the recording only keeps where and which way up it's drawn
+        let texture = Texture2D::from_miniquad_texture(miniquad::TextureId::from_raw_id(miniquad::RawId::OpenGl(0)));
+        let mut map = chart("plain_4k.qua");
+        let field_positions = set_reference_positions(Some(&texture));
+        crate::initialize_map(&mut map, &field_positions).unwrap();
+        map.time = 1000.0;
+        let mut draw = RecordingDraw::new(1000.0, 1200.0);
+        let mut state = FrameState {
+            map: &mut map,
+            compare_map: None,
+            chart_diff: &[],
+            field_positions: &field_positions,
+            alpha: 1.0,
+            view_height: 1200.0,
+            background: None,
+        };
+        update_frame(&mut state).unwrap();
+        render_frame(&mut state, &mut draw).unwrap();
+        set_skin(DEFAULT_SKIN);
+        draw.commands
+            .iter()
+            .filter_map(|command| match *command {
+                DrawCommand::Texture { flip_y, .. } => Some(flip_y),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn receptor_is_flipped_in_upscroll() {
+        assert_eq!(receptor_flips(DEFAULT_SKIN), [false]);
+        assert_eq!(receptor_flips(Skin { downscroll: false, ..DEFAULT_SKIN }), [true]);
+        // turned off in the skin it's drawn as is either way
+        assert_eq!(receptor_flips(Skin { downscroll: false, flip_receptor_on_upscroll: false, ..DEFAULT_SKIN }), [false]);
+        assert_eq!(receptor_flips(Skin { flip_receptor_on_upscroll: false, ..DEFAULT_SKIN }), [false]);
+    }
 }
@@ -0,0 +1,183 @@
+use crate::hit_windows::ActiveWindows;
+use crate::map::{Map, Mods};
+use crate::logger;
+use crate::transform::TransformPipeline;
+use crate::utils::Time;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+// one gameplay key going down or up
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ReplayEvent {
+    pub time: Time, // song time of the press/release
+    pub key: i64,   // 0-indexed gameplay key, before mirror/random
+    pub pressed: bool,
+}
+
+// the key inputs of a play, enough to judge it again on the same chart
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Replay {
+    pub mods: Mods, // only mirror, random and seed change how keys map to lanes
+    #[serde(default)]
+    pub windows: Option<ActiveWindows>, // what it was judged with, none in replays from before they were kept
+    pub events: Vec<ReplayEvent>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read replay '{}': {}", path.display(), e))?;
+        let mut replay: Self = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Failed to parse replay '{}': {}", path.display(), e))?;
+        replay.events.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Ok(replay)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string(self)?;
+        fs::write(path, json).map_err(|e| anyhow!("Failed to write replay '{}': {}", path.display(), e))
+    }
+
+    pub fn apply_mods(&self, map: &mut Map) -> Result<()> {
+        // lays the chart out like it was when the replay was recorded, call before initializing it
+        map.mods.mirror = self.mods.mirror;
+        map.mods.random = self.mods.random;
+        map.mods.seed = self.mods.seed;
+        map.mods.no_ln = self.mods.no_ln;
+        map.mods.autoplay = false;
+        TransformPipeline::from_mods(&map.mods).apply(map)?;
+        Ok(())
+    }
+
+    pub fn check_windows(&self, map: &Map, force: bool) -> Result<()> {
+        // a replay judged with other windows than the map's would come out with other judgements, so
+        // it isn't played unless forced (then it's warned about); call after initializing the map
+        let Some(recorded) = &self.windows else {
+            logger::warning("Replay doesn't say what hit windows it was recorded with, judging it with the current ones");
+            return Ok(());
+        };
+        let differences = recorded.differences(&ActiveWindows::of(map));
+        if differences.is_empty() {
+            return Ok(());
+        }
+        let message = format!("Replay was recorded with other hit windows: {}", differences.join(", "));
+        if !force {
+            bail!("{message} (--force-windows to play it anyway)");
+        }
+        logger::warning(&format!("{message}, its judgements won't match the recorded play"));
+        Ok(())
+    }
+}
+
+// feeds a replay's inputs into a map as the song plays
+pub struct ReplayPlayer {
+    replay: Replay,
+    cursor: usize, // first event not played yet
+}
+
+impl ReplayPlayer {
+    pub const fn new(replay: Replay) -> Self {
+        Self { replay, cursor: 0 }
+    }
+
+    pub fn play_until(&mut self, map: &mut Map, time: Time) {
+        // plays every event up to time, each at its own time
+        while let Some(event) = self.replay.events.get(self.cursor).filter(|event| event.time <= time) {
+            if event.pressed {
+                map.handle_gameplay_key_press(event.time, event.key);
+            } else {
+                map.handle_gameplay_key_release(event.time, event.key);
+            }
+            self.cursor += 1;
+        }
+    }
+
+    pub fn restart(&mut self, map: &mut Map) {
+        // judgements can't be undone, so going back replays from the start
+        map.reset_judgements();
+        self.cursor = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{set_reference_positions, update_versus};
+    use crate::utils::JudgementType;
+
+    fn player_chart(replay: &Replay) -> Map {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/charts/plain_4k.qua");
+        let mut map = Map::from_file(&path).unwrap();
+        map.rate = 1.0;
+        replay.apply_mods(&mut map).unwrap();
+        crate::initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        map
+    }
+
+    fn tapping(offset: f64, every: usize) -> Replay {
+        // taps every nth note of the chart offset ms late, and nothing else
+        let chart = player_chart(&Replay::default());
+        let events = chart
+            .hit_objects
+            .iter()
+            .step_by(every)
+            .flat_map(|note| {
+                let (time, key) = (note.start_time + offset, note.lane - 1);
+                [ReplayEvent { time, key, pressed: true }, ReplayEvent { time: time + 40.0, key, pressed: false }]
+            })
+            .collect();
+        Replay { events, ..Replay::default() }
+    }
+
+    fn judgements(map: &Map) -> Vec<(JudgementType, i64, i64)> {
+        map.hit_stats.iter().map(|stat| (stat.judgement, stat.lane, stat.offset.round() as i64)).collect()
+    }
+
+    fn play(players: &mut [(Map, ReplayPlayer)]) {
+        // the versus frame loop: every replay's inputs, then every chart at the same time
+        let end = players[0].0.hit_objects.last().unwrap().start_time + 1000.0;
+        let field_positions = set_reference_positions(None);
+        let mut time = 0.0;
+        while time <= end {
+            for (map, replay_player) in players.iter_mut() {
+                replay_player.play_until(map, time);
+            }
+            let mut maps: Vec<&mut Map> = players.iter_mut().map(|(map, _)| map).collect();
+            update_versus(&mut maps, &field_positions, time, 1.0, 1200.0).unwrap();
+            time += 1000.0 / 60.0;
+        }
+    }
+
+    fn player(replay: Replay) -> (Map, ReplayPlayer) {
+        (player_chart(&replay), ReplayPlayer::new(replay))
+    }
+
+    #[test]
+    fn versus_replays_are_judged_independently() {
+        let on_time = tapping(0.0, 1);
+        let late = tapping(70.0, 2);
+        let mut versus = vec![player(on_time.clone()), player(late.clone())];
+        play(&mut versus);
+
+        let notes = versus[0].0.hit_objects.len();
+        let first = judgements(&versus[0].0);
+        let second = judgements(&versus[1].0);
+        assert_eq!(first.len(), notes);
+        assert!(first.iter().all(|&(judgement, _, offset)| judgement == JudgementType::Marvelous && offset == 0));
+        assert_eq!(second.len(), notes);
+        let missed = second.iter().filter(|&&(judgement, _, _)| judgement == JudgementType::Miss).count();
+        assert_eq!(missed, notes / 2);
+        assert!(second.iter().all(|&(judgement, _, offset)| judgement == JudgementType::Miss || offset == -70));
+        assert_eq!(versus[0].0.accuracy(), 100.0);
+        assert!(versus[1].0.accuracy() < versus[0].0.accuracy());
+
+        // each comes out the same as when played alone
+        for (replay, (map, _)) in [on_time, late].into_iter().zip(&versus) {
+            let mut solo = vec![player(replay)];
+            play(&mut solo);
+            assert_eq!(judgements(&solo[0].0), judgements(map));
+            assert_eq!(solo[0].0.combo, map.combo);
+        }
+    }
+}
@@ -0,0 +1,666 @@
+use crate::draw::Draw;
+use crate::graph::{decimate, Graph};
+use crate::hit_windows::ActiveWindows;
+use crate::logger;
+use crate::map::Map;
+use crate::rate_ramp::{RampOutcome, RateRamp};
+use crate::scoring::Ruleset;
+use crate::strings::{tr, tr_args};
+use crate::utils::{judgement_color, HitKind, HitStat, HoldEvent, HoldEventKind, JudgementType, JudgementWindows, Time};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::{fs, path::Path};
+use macroquad::prelude::*;
+
+// most points drawn per graph, long plays are decimated down to this
+const MAX_GRAPH_POINTS: usize = 2000;
+const PANEL_WIDTH: f64 = 600.0;
+const PANEL_HEIGHT: f64 = 860.0;
+const GRAPH_HEIGHT: f64 = 170.0;
+const GRAPH_PADDING: f64 = 8.0;
+const LONG_NOTE_PANEL_HEIGHT: f64 = 140.0;
+const RAMP_PANEL_HEIGHT: f64 = 80.0;
+// most recent plays listed in the ramp panel
+const RAMP_HISTORY_SHOWN: usize = 6;
+// length (ms) of the stretch of chart in each column of the lane heatmap
+const HEATMAP_BUCKET_LENGTH: Time = 10_000.0;
+const HEATMAP_ROW_HEIGHT: f64 = 14.0;
+// title above the heatmap's grid and legend below it
+const HEATMAP_TITLE_HEIGHT: f64 = 30.0;
+const HEATMAP_LEGEND_HEIGHT: f64 = 28.0;
+// each line of the hit windows, one when collapsed and three when open
+const WINDOWS_LINE_HEIGHT: f64 = 22.0;
+
+// judgements in display order
+const JUDGEMENT_ORDER: [JudgementType; 6] = [
+    JudgementType::Marvelous,
+    JudgementType::Perfect,
+    JudgementType::Great,
+    JudgementType::Good,
+    JudgementType::Okay,
+    JudgementType::Miss,
+];
+
+#[derive(Debug, Clone)]
+pub struct ResultsSummary {
+    pub judgement_counts: Vec<(JudgementType, usize)>, // counts in display order
+    pub accuracy: f64,
+    pub ruleset: Ruleset, // what the accuracy was scored with
+    pub mash_bursts: usize, // times the player was caught mashing
+    pub unstable_rate: Option<f64>, // spread of the hit offsets, none before anything was hit
+    pub mixed_mods: bool, // mods were toggled partway through
+    pub resumed: bool,    // the play started partway into the map
+    pub autoplay_assisted: bool, // autoplay played part of it
+    pub accuracy_over_time: Vec<(Time, f64)>,             // running accuracy after each judgement
+    pub hit_offsets: Vec<(Time, f64, JudgementType)>,     // every judgement's time and offset
+    pub windows: ActiveWindows,                           // what it was judged with, also the offset graph's reference lines
+    pub long_notes: Option<LongNoteStats>,                // none for charts without LNs
+    pub rate_ramp: Option<RateRamp>,                      // the --rate-ramp, with this play counted
+    pub lane_heatmap: LaneHeatmap,
+}
+
+// how one lane went in one stretch of the chart
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HeatmapCell {
+    pub hits: usize, // judgements other than misses
+    pub misses: usize,
+    pub mean_offset: Option<f64>, // mean absolute offset (ms) of the hits, none without any
+}
+
+// judgements by lane (rows) and stretch of the chart (columns), to see where a hand falls apart
+#[derive(Debug, Clone, PartialEq)]
+pub struct LaneHeatmap {
+    pub bucket_length: Time,
+    pub cells: Vec<Vec<HeatmapCell>>, // by lane (1 first), then by bucket
+}
+
+impl LaneHeatmap {
+    pub fn from_hit_stats(hit_stats: &[HitStat], key_count: usize, length: Time, bucket_length: Time) -> Self {
+        // a judgement at the start of a bucket is in it (10 s is in the second 10 s bucket), ones
+        // before 0 are in the first; there are buckets up to length and for every judgement after it
+        let bucket = |time: Time| (time.max(0.0) / bucket_length).floor() as usize;
+        let last_time = hit_stats.iter().map(|hit_stat| hit_stat.time).fold(0.0, f64::max);
+        let buckets = ((length / bucket_length).ceil() as usize).max(bucket(last_time) + 1);
+
+        let mut cells = vec![vec![HeatmapCell::default(); buckets]; key_count];
+        let mut offset_sums = vec![vec![0.0; buckets]; key_count];
+        for hit_stat in hit_stats {
+            // lanes the chart can't have (broken charts) are left out
+            let Some(lane) = usize::try_from(hit_stat.lane - 1).ok().filter(|&lane| lane < key_count) else {
+                continue;
+            };
+            let bucket = bucket(hit_stat.time);
+            let cell = &mut cells[lane][bucket];
+            if hit_stat.judgement == JudgementType::Miss {
+                cell.misses += 1;
+            } else {
+                cell.hits += 1;
+                offset_sums[lane][bucket] += hit_stat.offset.abs();
+            }
+        }
+        for (lane_cells, lane_sums) in cells.iter_mut().zip(&offset_sums) {
+            for (cell, sum) in lane_cells.iter_mut().zip(lane_sums) {
+                cell.mean_offset = (cell.hits > 0).then(|| sum / cell.hits as f64);
+            }
+        }
+        Self { bucket_length, cells }
+    }
+
+    pub fn buckets(&self) -> usize {
+        self.cells.first().map_or(0, Vec::len)
+    }
+}
+
+// how the LNs in a play went, next to the normal notes
+#[derive(Debug, Clone, PartialEq)]
+pub struct LongNoteStats {
+    pub head_accuracy: Option<f64>,         // LN heads, scored by the play's ruleset
+    pub note_accuracy: Option<f64>,         // normal notes, the same way
+    pub release_offset: Option<(f64, f64)>, // mean and standard deviation (ms) of the releases, misses left out
+    pub breaks: usize,                      // times an LN was let go too early
+    pub regrabs: usize,
+    pub longest_hold: Option<Time>, // longest LN held from its head to its release without a break
+}
+
+impl LongNoteStats {
+    pub fn from_events(hit_stats: &[HitStat], hold_events: &[HoldEvent], ruleset: Ruleset) -> Self {
+        let backend = ruleset.backend();
+        let accuracy = |kind: HitKind| {
+            let hit_stats: Vec<HitStat> = hit_stats.iter().filter(|hit_stat| hit_stat.kind == kind).copied().collect();
+            (!hit_stats.is_empty()).then(|| backend.accuracy(&hit_stats))
+        };
+        let releases: Vec<f64> = hit_stats
+            .iter()
+            .filter(|hit_stat| hit_stat.kind == HitKind::LongNoteEnd && hit_stat.judgement != JudgementType::Miss)
+            .map(|hit_stat| hit_stat.offset)
+            .collect();
+        let release_offset = (!releases.is_empty()).then(|| {
+            let mean = releases.iter().sum::<f64>() / releases.len() as f64;
+            let variance = releases.iter().map(|offset| (offset - mean).powi(2)).sum::<f64>() / releases.len() as f64;
+            (mean, variance.sqrt())
+        });
+
+        // LN index -> when it was grabbed, none once it broke (a regrab doesn't make it a clean hold again)
+        let mut grabbed: HashMap<usize, Option<Time>> = HashMap::new();
+        let mut longest_hold: Option<Time> = None;
+        for event in hold_events {
+            match event.kind {
+                HoldEventKind::Grab => {
+                    grabbed.insert(event.note, Some(event.time));
+                }
+                HoldEventKind::Break => {
+                    grabbed.insert(event.note, None);
+                }
+                HoldEventKind::Regrab => {}
+                HoldEventKind::Release => {
+                    if let Some(Some(grab_time)) = grabbed.remove(&event.note) {
+                        let held = event.time - grab_time;
+                        longest_hold = Some(longest_hold.map_or(held, |longest| longest.max(held)));
+                    }
+                }
+            }
+        }
+
+        let count = |kind: HoldEventKind| hold_events.iter().filter(|event| event.kind == kind).count();
+        Self {
+            head_accuracy: accuracy(HitKind::LongNoteHead),
+            note_accuracy: accuracy(HitKind::Note),
+            release_offset,
+            breaks: count(HoldEventKind::Break),
+            regrabs: count(HoldEventKind::Regrab),
+            longest_hold,
+        }
+    }
+}
+
+impl ResultsSummary {
+    pub fn from_map(map: &Map) -> Self {
+        // snapshot of the map's score once it is finished
+        let backend = map.ruleset.backend();
+        Self {
+            judgement_counts: JUDGEMENT_ORDER
+                .iter()
+                .map(|&judgement| {
+                    (judgement, map.judgement_counts.get(&judgement).copied().unwrap_or(0))
+                })
+                .collect(),
+            accuracy: map.accuracy(),
+            ruleset: map.ruleset,
+            mash_bursts: map.mash_detector.bursts,
+            unstable_rate: unstable_rate(&map.hit_stats),
+            mixed_mods: map.mixed_mods,
+            resumed: map.resumed,
+            autoplay_assisted: map.autoplay_assisted,
+            accuracy_over_time: backend.accuracy_over_time(&map.hit_stats),
+            hit_offsets: map
+                .hit_stats
+                .iter()
+                .map(|hit_stat| (hit_stat.time, hit_stat.offset, hit_stat.judgement))
+                .collect(),
+            windows: ActiveWindows::of(map),
+            long_notes: map
+                .hit_objects
+                .iter()
+                .any(|hit_object| hit_object.end_time.is_some())
+                .then(|| LongNoteStats::from_events(&map.hit_stats, &map.hold_events, map.ruleset)),
+            rate_ramp: None,
+            lane_heatmap: LaneHeatmap::from_hit_stats(
+                &map.hit_stats,
+                usize::try_from(map.get_key_count(true)).unwrap_or(0),
+                map.hit_objects
+                    .iter()
+                    .map(|hit_object| hit_object.end_time.unwrap_or(hit_object.start_time))
+                    .fold(0.0, f64::max),
+                HEATMAP_BUCKET_LENGTH,
+            ),
+        }
+    }
+
+    pub fn log(&self) {
+        let counts = self
+            .judgement_counts
+            .iter()
+            .map(|(judgement, count)| format!("{judgement}: {count}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        logger::info(&format!(
+            "Map finished: {:.2}% {} ({counts}), UR {}, {} mash bursts{}{}{}",
+            self.accuracy,
+            self.ruleset.backend().name(),
+            self.unstable_rate.map_or_else(|| "-".to_string(), |unstable_rate| format!("{unstable_rate:.2}")),
+            self.mash_bursts,
+            if self.mixed_mods { ", mixed mods" } else { "" },
+            if self.resumed { ", resumed" } else { "" },
+            if self.autoplay_assisted { ", autoplay assisted" } else { "" }
+        ));
+        logger::info(&format!(
+            "Hit windows: {} at {:.2}x ({})",
+            self.windows.preset,
+            self.windows.rate,
+            self.windows.describe_windows()
+        ));
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        // the figures of the play (not the graphs), with the windows it was judged with
+        let export = ResultsExport {
+            accuracy: self.accuracy,
+            ruleset: self.ruleset.backend().name(),
+            judgement_counts: self.judgement_counts.iter().copied().collect(),
+            unstable_rate: self.unstable_rate,
+            mash_bursts: self.mash_bursts,
+            mixed_mods: self.mixed_mods,
+            resumed: self.resumed,
+            autoplay_assisted: self.autoplay_assisted,
+            windows: &self.windows,
+        };
+        Ok(serde_json::to_string_pretty(&export)?)
+    }
+
+    pub fn save_json(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_json()?).map_err(|e| anyhow!("Failed to write results '{}': {}", path.display(), e))
+    }
+}
+
+// what --results-json writes
+#[derive(Serialize)]
+struct ResultsExport<'a> {
+    accuracy: f64,
+    ruleset: &'a str,
+    judgement_counts: BTreeMap<JudgementType, usize>,
+    unstable_rate: Option<f64>,
+    mash_bursts: usize,
+    mixed_mods: bool,
+    resumed: bool,
+    autoplay_assisted: bool,
+    windows: &'a ActiveWindows,
+}
+
+pub fn unstable_rate(hit_stats: &[HitStat]) -> Option<f64> {
+    // 10x the standard deviation of the hit offsets, misses left out
+    let offsets: Vec<f64> = hit_stats
+        .iter()
+        .filter(|hit_stat| hit_stat.judgement != JudgementType::Miss)
+        .map(|hit_stat| hit_stat.offset)
+        .collect();
+    if offsets.is_empty() {
+        return None;
+    }
+    let mean = offsets.iter().sum::<f64>() / offsets.len() as f64;
+    let variance = offsets.iter().map(|offset| (offset - mean).powi(2)).sum::<f64>() / offsets.len() as f64;
+    Some(variance.sqrt() * 10.0)
+}
+
+fn draw_accuracy_graph(summary: &ResultsSummary, graph: &Graph, draw: &mut impl Draw) {
+    graph.draw_frame(draw, GRAY);
+    for accuracy in [100.0, 90.0, 80.0] {
+        graph.draw_horizontal_line(draw, accuracy, Color::new(1.0, 1.0, 1.0, 0.2));
+    }
+    let points = decimate(&summary.accuracy_over_time, MAX_GRAPH_POINTS);
+    graph.draw_polyline(draw, &points, 2.0, SKYBLUE);
+    draw.draw_text(&format!("{}%", graph.y_range.0), graph.x + 4.0, graph.y + graph.height - 4.0, 14.0, GRAY);
+    draw.draw_text("100%", graph.x + 4.0, graph.y + 16.0, 14.0, GRAY);
+}
+
+fn draw_offset_graph(summary: &ResultsSummary, graph: &Graph, draw: &mut impl Draw) {
+    graph.draw_frame(draw, GRAY);
+    // window boundaries, early above the center line and late below it
+    for &judgement in JUDGEMENT_ORDER.iter().filter(|&&judgement| judgement != JudgementType::Miss) {
+        let color = Color { a: 0.35, ..judgement_color(judgement) };
+        graph.draw_horizontal_line(draw, summary.windows.windows.early(judgement), color);
+        graph.draw_horizontal_line(draw, -summary.windows.windows.late(judgement), color);
+    }
+    graph.draw_horizontal_line(draw, 0.0, WHITE);
+    let points: Vec<(f64, f64, Color)> = decimate(&summary.hit_offsets, MAX_GRAPH_POINTS)
+        .into_iter()
+        .map(|(time, offset, judgement)| (time, offset, judgement_color(judgement)))
+        .collect();
+    graph.draw_points(draw, &points, 1.5);
+    draw.draw_text(tr("results.early"), graph.x + 4.0, graph.y + 16.0, 14.0, GRAY);
+    draw.draw_text(tr("results.late"), graph.x + 4.0, graph.y + graph.height - 4.0, 14.0, GRAY);
+}
+
+pub fn draw_results(summary: &ResultsSummary, windows_open: bool, draw: &mut impl Draw) {
+    // draws the results overlay on top of the finished map, with the hit windows listed when open
+    let width = PANEL_WIDTH;
+    // the LN panel makes room for itself above the graphs
+    let long_note_height = if summary.long_notes.is_some() { LONG_NOTE_PANEL_HEIGHT + 20.0 } else { 0.0 };
+    let ramp_height = if summary.rate_ramp.is_some() { RAMP_PANEL_HEIGHT + 20.0 } else { 0.0 };
+    let heatmap_height = HEATMAP_TITLE_HEIGHT
+        + summary.lane_heatmap.cells.len() as f64 * HEATMAP_ROW_HEIGHT
+        + HEATMAP_LEGEND_HEIGHT;
+    let windows_lines = if windows_open { 3.0 } else { 1.0 };
+    let height = PANEL_HEIGHT + long_note_height + ramp_height + heatmap_height + windows_lines * WINDOWS_LINE_HEIGHT + 20.0;
+    let x = (draw.screen_width() - width) / 2.0;
+    let y = (draw.screen_height() - height) / 2.0;
+    draw.draw_rectangle(x, y, width, height, Color::new(0.0, 0.0, 0.0, 0.85));
+    draw.draw_rectangle_outline(x, y, width, height, 2.0, GRAY);
+
+    draw.draw_text(tr("results.title"), x + 20.0, y + 50.0, 50.0, WHITE);
+    draw.draw_text(&format!("{:.2}%", summary.accuracy), x + 20.0, y + 110.0, 60.0, WHITE);
+    draw.draw_text(summary.ruleset.backend().name(), x + 260.0, y + 110.0, 24.0, GRAY);
+    // anything that makes the score not a normal full play
+    let flags: Vec<&str> = [
+        (summary.mixed_mods, "results.mixed_mods"),
+        (summary.resumed, "results.resumed"),
+        (summary.autoplay_assisted, "results.autoplay_assisted"),
+    ]
+    .into_iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, key)| tr(key))
+    .collect();
+    draw.draw_text(&flags.join(" / "), x + 20.0, y + 136.0, 24.0, ORANGE);
+
+    let mut line_y = y + 160.0;
+    for (judgement, count) in &summary.judgement_counts {
+        let line = tr_args(
+            "judgement.count",
+            &[("judgement", judgement.localized()), ("count", &count.to_string())],
+        );
+        draw.draw_text(&line, x + 20.0, line_y, 36.0, WHITE);
+        line_y += 36.0;
+    }
+
+    draw.draw_text(
+        &tr_args("results.mash_bursts", &[("count", &summary.mash_bursts.to_string())]),
+        x + 20.0,
+        line_y + 10.0,
+        24.0,
+        GRAY,
+    );
+    if let Some(unstable_rate) = summary.unstable_rate {
+        draw.draw_text(
+            &tr_args("results.unstable_rate", &[("value", &format!("{unstable_rate:.2}"))]),
+            x + 260.0,
+            line_y + 10.0,
+            24.0,
+            GRAY,
+        );
+    }
+
+    if let Some(long_notes) = &summary.long_notes {
+        draw_long_note_panel(long_notes, x + 20.0, line_y + 30.0, width - 40.0, draw);
+    }
+    if let Some(rate_ramp) = &summary.rate_ramp {
+        draw_ramp_panel(rate_ramp, x + 20.0, line_y + 30.0 + long_note_height, width - 40.0, draw);
+    }
+
+    // both graphs share the time axis
+    let end_time = summary.hit_offsets.last().map_or(1.0, |(time, _, _)| *time);
+    let lowest_accuracy = summary
+        .accuracy_over_time
+        .iter()
+        .map(|(_, accuracy)| *accuracy)
+        .fold(90.0, f64::min); // always show at least 90-100%
+    let accuracy_graph = Graph {
+        x: x + 20.0,
+        y: line_y + 40.0 + long_note_height + ramp_height,
+        width: width - 40.0,
+        height: GRAPH_HEIGHT,
+        padding: GRAPH_PADDING,
+        x_range: (0.0, end_time),
+        y_range: ((lowest_accuracy / 10.0).floor() * 10.0, 100.0),
+    };
+    draw_accuracy_graph(summary, &accuracy_graph, draw);
+
+    let widest = summary.windows.windows.widest();
+    let offset_graph = Graph {
+        y: accuracy_graph.y + GRAPH_HEIGHT + 20.0,
+        y_range: (-widest, widest),
+        ..accuracy_graph
+    };
+    draw_offset_graph(summary, &offset_graph, draw);
+
+    draw_lane_heatmap(
+        &summary.lane_heatmap,
+        &summary.windows.windows,
+        x + 20.0,
+        offset_graph.y + GRAPH_HEIGHT + 20.0,
+        width - 40.0,
+        draw,
+    );
+
+    draw_windows(&summary.windows, windows_open, x + 20.0, y + height - 48.0 - windows_lines * WINDOWS_LINE_HEIGHT, draw);
+    draw.draw_text(tr("results.retry_hint"), x + 20.0, y + height - 20.0, 24.0, GRAY);
+
+}
+
+fn draw_windows(windows: &ActiveWindows, open: bool, x: f64, y: f64, draw: &mut impl Draw) {
+    let args = [("preset", windows.preset.as_str()), ("rate", &format!("{:.2}", windows.rate))];
+    let heading = tr_args(if open { "results.windows_open" } else { "results.windows" }, &args);
+    draw.draw_text(&heading, x, y + WINDOWS_LINE_HEIGHT, 20.0, GRAY);
+    if open {
+        draw.draw_text(&windows.describe_windows(), x, y + WINDOWS_LINE_HEIGHT * 2.0, 18.0, GRAY);
+        if windows.scale_with_rate {
+            draw.draw_text(tr("results.windows_scaled"), x, y + WINDOWS_LINE_HEIGHT * 3.0, 18.0, GRAY);
+        }
+    }
+}
+
+fn heatmap_color(cell: &HeatmapCell, windows: &JudgementWindows) -> Color {
+    // the color of the judgement the mean offset is within, blended towards the miss color by the share of misses
+    let judged = cell.hits + cell.misses;
+    if judged == 0 {
+        return Color::new(0.15, 0.15, 0.15, 1.0);
+    }
+    let judgement = cell.mean_offset.map_or(JudgementType::Miss, |offset| {
+        JUDGEMENT_ORDER
+            .into_iter()
+            .find(|&judgement| offset <= windows.early(judgement).max(windows.late(judgement)))
+            .unwrap_or(JudgementType::Miss)
+    });
+    let (color, miss) = (judgement_color(judgement), judgement_color(JudgementType::Miss));
+    let miss_share = cell.misses as f32 / judged as f32;
+    let blend = |from: f32, to: f32| from + (to - from) * miss_share;
+    Color::new(blend(color.r, miss.r), blend(color.g, miss.g), blend(color.b, miss.b), 1.0)
+}
+
+fn draw_lane_heatmap(heatmap: &LaneHeatmap, windows: &JudgementWindows, x: f64, y: f64, width: f64, draw: &mut impl Draw) {
+    let seconds = format!("{:.0}", heatmap.bucket_length / 1000.0);
+    draw.draw_text(&tr_args("results.lane_heatmap", &[("seconds", &seconds)]), x, y + 20.0, 22.0, WHITE);
+
+    // one row per lane, one column per bucket, with a pixel between cells
+    let grid_y = y + HEATMAP_TITLE_HEIGHT;
+    let cell_width = width / heatmap.buckets().max(1) as f64;
+    for (lane, lane_cells) in heatmap.cells.iter().enumerate() {
+        for (bucket, cell) in lane_cells.iter().enumerate() {
+            draw.draw_rectangle(
+                x + bucket as f64 * cell_width,
+                grid_y + lane as f64 * HEATMAP_ROW_HEIGHT,
+                (cell_width - 1.0).max(1.0),
+                HEATMAP_ROW_HEIGHT - 1.0,
+                heatmap_color(cell, windows),
+            );
+        }
+    }
+
+    // the judgement each color means, misses last
+    let legend_y = grid_y + heatmap.cells.len() as f64 * HEATMAP_ROW_HEIGHT + 8.0;
+    let entry_width = width / JUDGEMENT_ORDER.len() as f64;
+    for (index, judgement) in JUDGEMENT_ORDER.into_iter().enumerate() {
+        let entry_x = x + index as f64 * entry_width;
+        draw.draw_rectangle(entry_x, legend_y, 12.0, 12.0, judgement_color(judgement));
+        draw.draw_text(judgement.localized(), entry_x + 16.0, legend_y + 11.0, 14.0, GRAY);
+    }
+}
+
+fn draw_long_note_panel(long_notes: &LongNoteStats, x: f64, y: f64, width: f64, draw: &mut impl Draw) {
+    draw.draw_rectangle_outline(x, y, width, LONG_NOTE_PANEL_HEIGHT, 1.0, GRAY);
+    draw.draw_text(tr("results.long_notes"), x + 10.0, y + 28.0, 28.0, WHITE);
+
+    let not_available = || tr("debug.not_available").to_string();
+    let accuracy = |accuracy: Option<f64>| accuracy.map_or_else(not_available, |accuracy| format!("{accuracy:.2}%"));
+    let (mean, deviation) = long_notes.release_offset.map_or_else(
+        || (not_available(), not_available()),
+        |(mean, deviation)| (format!("{mean:+.1}"), format!("{deviation:.1}")),
+    );
+    let longest_hold = long_notes
+        .longest_hold
+        .map_or_else(not_available, |held| format!("{:.2}s", held / 1000.0));
+    // accuracies on the left, how the holds went on the right
+    let columns = [
+        [
+            tr_args("results.ln_head_accuracy", &[("value", &accuracy(long_notes.head_accuracy))]),
+            tr_args("results.ln_note_accuracy", &[("value", &accuracy(long_notes.note_accuracy))]),
+            tr_args("results.ln_release", &[("mean", &mean), ("deviation", &deviation)]),
+        ],
+        [
+            tr_args("results.ln_breaks", &[("count", &long_notes.breaks.to_string())]),
+            tr_args("results.ln_regrabs", &[("count", &long_notes.regrabs.to_string())]),
+            tr_args("results.ln_longest_hold", &[("value", &longest_hold)]),
+        ],
+    ];
+    for (column, lines) in columns.iter().enumerate() {
+        let line_x = x + 10.0 + column as f64 * width / 2.0;
+        for (row, line) in lines.iter().enumerate() {
+            draw.draw_text(line, line_x, y + 62.0 + row as f64 * 30.0, 22.0, GRAY);
+        }
+    }
+}
+
+fn draw_ramp_panel(rate_ramp: &RateRamp, x: f64, y: f64, width: f64, draw: &mut impl Draw) {
+    draw.draw_rectangle_outline(x, y, width, RAMP_PANEL_HEIGHT, 1.0, GRAY);
+    let rate = format!("{:.2}", rate_ramp.rate());
+    let heading = match rate_ramp.outcome() {
+        Some(RampOutcome::Complete) => tr_args("ramp.complete", &[("rate", &rate)]),
+        Some(RampOutcome::Failed) => tr_args(
+            "ramp.failed",
+            &[("rate", &rate), ("accuracy", &format!("{:.0}", rate_ramp.min_accuracy()))],
+        ),
+        Some(RampOutcome::Next(_)) | None => {
+            tr_args("ramp.progress", &[("rate", &rate), ("max", &format!("{:.2}", rate_ramp.max_rate()))])
+        }
+    };
+    draw.draw_text(&heading, x + 10.0, y + 28.0, 28.0, WHITE);
+
+    // one column per play, oldest on the left, cleared ones in white
+    let history = rate_ramp.history();
+    let shown = &history[history.len().saturating_sub(RAMP_HISTORY_SHOWN)..];
+    let column_width = width / RAMP_HISTORY_SHOWN as f64;
+    for (column, &(rate, accuracy)) in shown.iter().enumerate() {
+        let line = tr_args(
+            "ramp.history_entry",
+            &[("rate", &format!("{rate:.2}")), ("accuracy", &format!("{accuracy:.2}"))],
+        );
+        let color = if accuracy >= rate_ramp.min_accuracy() { WHITE } else { GRAY };
+        draw.draw_text(&line, x + 10.0 + column as f64 * column_width, y + 62.0, 18.0, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::initialize_map;
+    use crate::map::{GameMode, HitObject, TimingPoint};
+    use crate::render::set_reference_positions;
+
+    fn scripted_play() -> Map {
+        // a clean LN, an LN broken and regrabbed, a normal note and an LN never pressed, at 60 BPM
+        let mut map = Map::default();
+        map.mode = GameMode::Keys4;
+        map.hit_objects = vec![
+            HitObject { start_time: 1000.0, end_time: Some(2000.0), lane: 1, ..HitObject::default() },
+            HitObject { start_time: 3000.0, end_time: Some(4000.0), lane: 2, ..HitObject::default() },
+            HitObject { start_time: 5000.0, lane: 3, ..HitObject::default() },
+            HitObject { start_time: 6000.0, end_time: Some(7000.0), lane: 4, ..HitObject::default() },
+        ];
+        map.timing_points.push(TimingPoint { start_time: 0.0, bpm: 60.0, time_signature: None, hidden: false });
+        map.rate = 1.0;
+        initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        map.handle_gameplay_key_press(1000.0, 0);
+        map.handle_gameplay_key_release(2010.0, 0);
+        map.handle_gameplay_key_press(2995.0, 1);
+        map.handle_gameplay_key_release(3300.0, 1);
+        map.handle_gameplay_key_press(3400.0, 1);
+        map.handle_gameplay_key_release(3990.0, 1);
+        map.handle_gameplay_key_press(5020.0, 2);
+        map.time = 7500.0;
+        map.update_judgements();
+        map
+    }
+
+    #[test]
+    fn long_note_stats_of_a_scripted_play() {
+        let map = scripted_play();
+        let stats = ResultsSummary::from_map(&map).long_notes.unwrap();
+        // heads: two marvelous and a miss; the one normal note was a perfect
+        assert_eq!(stats.head_accuracy, Some(50.0));
+        assert_eq!(stats.note_accuracy, Some(98.25));
+        // releases 10 ms late and 10 ms early, the missed LN's end isn't one
+        assert_eq!(stats.release_offset, Some((0.0, 10.0)));
+        assert_eq!((stats.breaks, stats.regrabs), (1, 1));
+        // the broken LN was held longer in total, but only the clean one counts
+        assert_eq!(stats.longest_hold, Some(1010.0));
+    }
+
+    #[test]
+    fn long_note_stats_are_left_out_for_charts_without_lns() {
+        let mut map = scripted_play();
+        for hit_object in &mut map.hit_objects {
+            hit_object.end_time = None;
+        }
+        assert_eq!(ResultsSummary::from_map(&map).long_notes, None);
+    }
+
+    #[test]
+    fn long_note_stats_without_events_are_empty() {
+        let stats = LongNoteStats::from_events(&[], &[], Ruleset::Quaver);
+        assert_eq!(
+            stats,
+            LongNoteStats { head_accuracy: None, note_accuracy: None, release_offset: None, breaks: 0, regrabs: 0, longest_hold: None }
+        );
+    }
+
+    fn judged(time: Time, lane: i64, offset: f64, judgement: JudgementType) -> HitStat {
+        HitStat { time, offset, raw_offset: offset, judgement, kind: HitKind::Note, lane }
+    }
+
+    #[test]
+    fn heatmap_buckets_start_at_their_boundary() {
+        let hit_stats = [
+            judged(-20.0, 1, 5.0, JudgementType::Marvelous),
+            judged(9_999.0, 1, -15.0, JudgementType::Marvelous),
+            judged(10_000.0, 1, 30.0, JudgementType::Perfect),
+            judged(19_999.0, 2, 0.0, JudgementType::Miss),
+            judged(20_000.0, 2, 10.0, JudgementType::Marvelous),
+        ];
+        let heatmap = LaneHeatmap::from_hit_stats(&hit_stats, 4, 25_000.0, 10_000.0);
+        assert_eq!((heatmap.cells.len(), heatmap.buckets()), (4, 3));
+        // early judgements and the last ms of a bucket are in it, its end is in the next one
+        assert_eq!(heatmap.cells[0][0], HeatmapCell { hits: 2, misses: 0, mean_offset: Some(10.0) });
+        assert_eq!(heatmap.cells[0][1], HeatmapCell { hits: 1, misses: 0, mean_offset: Some(30.0) });
+        // misses don't count towards the offset
+        assert_eq!(heatmap.cells[1][1], HeatmapCell { hits: 0, misses: 1, mean_offset: None });
+        assert_eq!(heatmap.cells[1][2], HeatmapCell { hits: 1, misses: 0, mean_offset: Some(10.0) });
+    }
+
+    #[test]
+    fn heatmap_has_empty_buckets_up_to_the_chart_length() {
+        // a judgement past the length (a late miss on the last note) gets its own bucket
+        let heatmap = LaneHeatmap::from_hit_stats(&[judged(30_100.0, 3, 0.0, JudgementType::Miss)], 4, 30_000.0, 10_000.0);
+        assert_eq!(heatmap.buckets(), 4);
+        let filled: Vec<_> = (0..4)
+            .flat_map(|lane| (0..4).map(move |bucket| (lane, bucket)))
+            .filter(|&(lane, bucket)| heatmap.cells[lane][bucket] != HeatmapCell::default())
+            .collect();
+        assert_eq!(filled, [(2, 3)]);
+        // nothing played still has the chart's buckets, all empty
+        let empty = LaneHeatmap::from_hit_stats(&[], 7, 30_000.0, 10_000.0);
+        assert_eq!((empty.cells.len(), empty.buckets()), (7, 3));
+        assert!(empty.cells.iter().flatten().all(|cell| *cell == HeatmapCell::default()));
+    }
+
+    #[test]
+    fn heatmap_leaves_out_lanes_the_chart_cant_have() {
+        let hit_stats = [
+            judged(1000.0, 0, 5.0, JudgementType::Marvelous),
+            judged(1000.0, 5, 5.0, JudgementType::Marvelous),
+            judged(1000.0, 4, 5.0, JudgementType::Marvelous),
+        ];
+        let heatmap = LaneHeatmap::from_hit_stats(&hit_stats, 4, 10_000.0, 10_000.0);
+        assert_eq!(heatmap.cells.len(), 4);
+        let hits: Vec<usize> = heatmap.cells.iter().map(|lane| lane[0].hits).collect();
+        assert_eq!(hits, [0, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::logger;
+use crate::utils::Time;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, path::Path};
+
+// positions earlier than this (ms) aren't worth offering to resume
+pub const RESUME_THRESHOLD: Time = 30000.0;
+
+// where each chart was last left, so long maps can be picked up again
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ResumeStore {
+    positions: HashMap<String, Time>, // chart checksum (hex) -> song time it was quit at
+}
+
+impl ResumeStore {
+    pub fn load(path: &Path) -> Self {
+        // a missing store is empty, an unreadable one is replaced on the next save
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                logger::warning(&format!("Failed to read '{}': {}", path.display(), e));
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            logger::warning(&format!("Ignoring unreadable resume positions '{}': {}", path.display(), e));
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).map_err(|e| anyhow!("Failed to write '{}': {}", path.display(), e))
+    }
+
+    pub fn get(&self, checksum: u64) -> Option<Time> {
+        // the stored position, only if it's far enough in to be worth resuming
+        self.positions
+            .get(&format!("{checksum:016x}"))
+            .copied()
+            .filter(|&time| time > RESUME_THRESHOLD)
+    }
+
+    pub fn set(&mut self, checksum: u64, time: Option<Time>) {
+        // None (or a position before the threshold) forgets the chart
+        let key = format!("{checksum:016x}");
+        match time.filter(|&time| time > RESUME_THRESHOLD) {
+            Some(time) => self.positions.insert(key, time),
+            None => self.positions.remove(&key),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_round_trip_through_the_store() {
+        let path = std::env::temp_dir().join(format!("vsrg_resume_{}.json", std::process::id()));
+        let mut store = ResumeStore::default();
+        store.set(0xabc, Some(754_000.0));
+        store.set(0xdef, Some(31_000.0));
+        store.save(&path).unwrap();
+        let loaded = ResumeStore::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.get(0xabc), Some(754_000.0));
+        assert_eq!(loaded.get(0xdef), Some(31_000.0));
+        assert_eq!(loaded.get(0x123), None);
+    }
+
+    #[test]
+    fn positions_before_the_threshold_are_forgotten() {
+        let mut store = ResumeStore::default();
+        store.set(1, Some(RESUME_THRESHOLD));
+        assert_eq!(store.get(1), None);
+        store.set(1, Some(RESUME_THRESHOLD + 1.0));
+        assert_eq!(store.get(1), Some(RESUME_THRESHOLD + 1.0));
+        // quitting early (or finishing the map) drops what was stored before
+        store.set(1, Some(12_000.0));
+        assert_eq!(store.get(1), None);
+        store.set(1, Some(60_000.0));
+        store.set(1, None);
+        assert_eq!(store.get(1), None);
+    }
+
+    #[test]
+    fn missing_or_unreadable_stores_are_empty() {
+        let path = std::env::temp_dir().join(format!("vsrg_resume_broken_{}.json", std::process::id()));
+        assert_eq!(ResumeStore::load(&path).get(1), None);
+        fs::write(&path, "{ not json").unwrap();
+        let store = ResumeStore::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(store.positions.is_empty());
+    }
+}
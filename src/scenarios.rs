@@ -0,0 +1,415 @@
+use crate::input_gate::{InputGate, DEFAULT_RESUME_GRACE};
+use crate::logger;
+use crate::map::Map;
+use crate::render::{set_reference_positions, update_frame, FrameState};
+use crate::replay::{Replay, ReplayEvent, ReplayPlayer};
+use crate::utils::{set_skin, skin, FieldPositions, JudgementType, Time, DEFAULT_SKIN, JUDGEMENTS};
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs, path::Path};
+
+// scripted plays checked by `scenarios`: each file in the directory (.toml or .json) loads a chart,
+// plays it headless on a clock stepped by hand, does its actions on the way and checks how the play
+// came out. Judging, seeking and pausing go through the same calls the window does, so these cover
+// the interactions between them without a hand-written test for each. The schema, in TOML:
+//
+//     chart = "chords.qua"  # in the charts directory next to the scenarios
+//     rate = 1.0            # optional, like --rate
+//     mirror = false        # optional
+//     autoplay = false      # optional
+//     replay = [{ time = 500, key = 0, pressed = true }, ...]  # optional, played like a versus replay
+//     end = 4000            # optional clock ms to stop at, by default once the chart is over
+//
+//     [[action]]            # in the order they're listed, each once the clock reaches `at`
+//     at = 1503             # clock ms (see below)
+//     press = 2             # one of: press / release a lane (1-indexed, as keys before mirror),
+//                           # seek = chart ms, toggle_pause = true, toggle_autoplay = true
+//
+//     [expect]
+//     counts = { Marvelous = 7, Miss = 1 }  # judgements left out are expected to be 0
+//     accuracy = 93.75                      # optional, within accuracy_tolerance (default 0.01)
+//     combo = 3                             # optional, the combo at the end
+//     judgements = [{ index = 0, judgement = "Marvelous", lane = 1 }]  # optional, lane too
+//
+// The clock is wall time since the scenario started: it keeps running while paused, and chart time
+// moves with it at the rate while playing. So at rate 1 and before any pause or seek, an action at
+// 1503 happens at 1503 ms into the chart. Presses are dropped while paused and for a moment after
+// resuming (InputGate), releases always go through; with autoplay on, or a replay playing, presses
+// and releases are ignored like the window ignores the keys
+
+const CHARTS_DIR: &str = "charts";
+// clock ms between updates, so notes passed without a press are judged on the way like in a play
+const SIMULATION_STEP: f64 = 1000.0 / 240.0;
+const VIEW_HEIGHT: f64 = 1200.0;
+
+fn default_rate() -> f64 {
+    1.0
+}
+
+fn default_tolerance() -> f64 {
+    0.01
+}
+
+// one thing done during the play; exactly one of press, release, seek, toggle_pause and toggle_autoplay
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Action {
+    pub at: f64, // clock ms
+    #[serde(default)]
+    pub press: Option<i64>,
+    #[serde(default)]
+    pub release: Option<i64>,
+    #[serde(default)]
+    pub seek: Option<Time>,
+    #[serde(default)]
+    pub toggle_pause: bool,
+    #[serde(default)]
+    pub toggle_autoplay: bool,
+}
+
+// a judgement expected at an index of the play's judgements, in the order they were made
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExpectedJudgement {
+    pub index: usize,
+    pub judgement: JudgementType,
+    #[serde(default)]
+    pub lane: Option<i64>, // as judged, after mirror
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectations {
+    #[serde(default)]
+    pub counts: BTreeMap<JudgementType, usize>,
+    #[serde(default)]
+    pub accuracy: Option<f64>,
+    #[serde(default = "default_tolerance")]
+    pub accuracy_tolerance: f64,
+    #[serde(default)]
+    pub combo: Option<usize>,
+    #[serde(default)]
+    pub judgements: Vec<ExpectedJudgement>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub chart: String,
+    #[serde(default = "default_rate")]
+    pub rate: f64,
+    #[serde(default)]
+    pub mirror: bool,
+    #[serde(default)]
+    pub autoplay: bool,
+    #[serde(default)]
+    pub replay: Option<Vec<ReplayEvent>>,
+    #[serde(default)]
+    pub end: Option<f64>,
+    #[serde(default, rename = "action")]
+    pub actions: Vec<Action>,
+    pub expect: Expectations,
+}
+
+pub fn load_scenario(path: &Path) -> Result<Scenario> {
+    let content = fs::read_to_string(path).map_err(|e| anyhow!("Failed to read scenario '{}': {}", path.display(), e))?;
+    let scenario: Scenario = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&content).map_err(|e| anyhow!("Failed to parse scenario '{}': {}", path.display(), e))?
+    } else {
+        toml::from_str(&content).map_err(|e| anyhow!("Failed to parse scenario '{}': {}", path.display(), e))?
+    };
+    for (index, action) in scenario.actions.iter().enumerate() {
+        let kinds = [action.press.is_some(), action.release.is_some(), action.seek.is_some(), action.toggle_pause, action.toggle_autoplay];
+        if kinds.into_iter().filter(|&kind| kind).count() != 1 {
+            bail!("Action {} in '{}' has to do exactly one thing", index + 1, path.display());
+        }
+    }
+    Ok(scenario)
+}
+
+// a chart played headless on a clock stepped by hand, the way the window plays one
+pub struct Playback {
+    pub map: Map,
+    field_positions: FieldPositions<'static>,
+    replay_player: Option<ReplayPlayer>,
+    input_gate: InputGate,
+    playing: bool,
+    clock: f64, // ms since the start, paused or not
+}
+
+impl Playback {
+    pub fn new(mut map: Map, replay: Option<Replay>) -> Result<Self> {
+        // starts playing from 0, like a chart that was just loaded
+        let field_positions = set_reference_positions(None);
+        crate::initialize_map(&mut map, &field_positions)?;
+        let mut input_gate = InputGate::new(DEFAULT_RESUME_GRACE);
+        input_gate.set_playing(true, 0.0);
+        Ok(Self { map, field_positions, replay_player: replay.map(ReplayPlayer::new), input_gate, playing: true, clock: 0.0 })
+    }
+
+    pub const fn clock(&self) -> f64 {
+        self.clock
+    }
+
+    pub const fn playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn advance_to(&mut self, clock: f64) -> Result<()> {
+        // moves the clock on (and the chart with it while playing), updating on the way
+        while self.clock < clock {
+            let step = SIMULATION_STEP.min(clock - self.clock);
+            self.clock += step;
+            if self.playing {
+                self.map.time += step * self.map.rate;
+            }
+            self.update()?;
+        }
+        Ok(())
+    }
+
+    fn update(&mut self) -> Result<()> {
+        self.input_gate.set_playing(self.playing, self.clock);
+        if let Some(replay_player) = &mut self.replay_player {
+            let time = self.map.time;
+            replay_player.play_until(&mut self.map, time);
+        }
+        update_frame(&mut FrameState {
+            map: &mut self.map,
+            compare_map: None,
+            chart_diff: &[],
+            field_positions: &self.field_positions,
+            alpha: 1.0,
+            view_height: VIEW_HEIGHT,
+            background: None,
+        })
+    }
+
+    const fn takes_keys(&self) -> bool {
+        !self.map.mods.autoplay && self.replay_player.is_none()
+    }
+
+    pub fn press(&mut self, lane: i64) {
+        if self.takes_keys() && self.input_gate.accepts_press(self.clock) {
+            self.map.handle_gameplay_key_press(self.map.time, lane - 1);
+        }
+    }
+
+    pub fn release(&mut self, lane: i64) {
+        if self.takes_keys() {
+            self.map.handle_gameplay_key_release(self.map.time, lane - 1);
+        }
+    }
+
+    pub fn seek(&mut self, time: Time) -> Result<()> {
+        // judgements stay, a replay is judged again from the start to get back
+        if time < self.map.time {
+            if let Some(replay_player) = &mut self.replay_player {
+                replay_player.restart(&mut self.map);
+            }
+        }
+        self.map.time = time.max(0.0);
+        self.update()
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.playing = !self.playing;
+        self.input_gate.set_playing(self.playing, self.clock);
+    }
+
+    pub fn toggle_autoplay(&mut self) {
+        self.map.toggle_autoplay(self.map.time);
+    }
+}
+
+pub fn play_scenario(dir: &Path, scenario: &Scenario) -> Result<Map> {
+    // plays the scenario and returns the map as it was left
+    let mut map = Map::from_file(&dir.join(CHARTS_DIR).join(&scenario.chart))?;
+    map.rate = scenario.rate;
+    map.mods.mirror = scenario.mirror;
+    map.mods.autoplay = scenario.autoplay;
+    let replay = scenario.replay.clone().map(|mut events| {
+        events.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Replay { events, ..Replay::default() }
+    });
+    let mut playback = Playback::new(map, replay)?;
+    playback.update()?;
+    for action in &scenario.actions {
+        playback.advance_to(action.at)?;
+        if let Some(lane) = action.press {
+            playback.press(lane);
+        } else if let Some(lane) = action.release {
+            playback.release(lane);
+        } else if let Some(time) = action.seek {
+            playback.seek(time)?;
+        } else if action.toggle_pause {
+            playback.toggle_pause();
+        } else {
+            playback.toggle_autoplay();
+        }
+    }
+    match scenario.end {
+        Some(end) => playback.advance_to(end)?,
+        // paused for good, the chart can't get any further
+        None if !playback.playing() => {}
+        None => {
+            let remaining = (playback.map.playable_length - playback.map.time).max(0.0) / playback.map.rate;
+            playback.advance_to(playback.clock() + remaining)?;
+        }
+    }
+    Ok(playback.map)
+}
+
+pub fn check_expectations(map: &Map, expect: &Expectations) -> Vec<String> {
+    // every way the play differs from what was expected, empty if it's as expected
+    let mut failures = Vec::new();
+    for judgement in JUDGEMENTS.iter().map(|judgement| judgement.kind) {
+        let expected = expect.counts.get(&judgement).copied().unwrap_or(0);
+        let actual = map.judgement_counts.get(&judgement).copied().unwrap_or(0);
+        if actual != expected {
+            failures.push(format!("{actual} {judgement}, expected {expected}"));
+        }
+    }
+    if let Some(accuracy) = expect.accuracy {
+        if (map.accuracy() - accuracy).abs() > expect.accuracy_tolerance {
+            failures.push(format!("accuracy {:.4}%, expected {accuracy}% (±{})", map.accuracy(), expect.accuracy_tolerance));
+        }
+    }
+    if let Some(combo) = expect.combo {
+        if map.combo != combo {
+            failures.push(format!("combo {}, expected {combo}", map.combo));
+        }
+    }
+    for expected in &expect.judgements {
+        let Some(actual) = map.hit_stats.get(expected.index) else {
+            failures.push(format!("no judgement {}, only {} were made", expected.index, map.hit_stats.len()));
+            continue;
+        };
+        if actual.judgement != expected.judgement || expected.lane.is_some_and(|lane| lane != actual.lane) {
+            failures.push(format!(
+                "judgement {} is {} in lane {}, expected {}{}",
+                expected.index,
+                actual.judgement,
+                actual.lane,
+                expected.judgement,
+                expected.lane.map(|lane| format!(" in lane {lane}")).unwrap_or_default()
+            ));
+        }
+    }
+    failures
+}
+
+pub fn run(dir: &Path) -> Result<()> {
+    // plays every scenario in the directory with the default skin and checks it
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .map_err(|e| anyhow!("Failed to read scenarios '{}': {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "toml" || ext == "json"))
+        .collect();
+    paths.sort();
+    if paths.is_empty() {
+        bail!("No scenarios (.toml or .json) in '{}'", dir.display());
+    }
+    let previous_skin = skin();
+    set_skin(DEFAULT_SKIN);
+    let mut failed = 0;
+    for path in &paths {
+        let name = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        let result = load_scenario(path).and_then(|scenario| Ok(check_expectations(&play_scenario(dir, &scenario)?, &scenario.expect)));
+        match result {
+            Ok(failures) if failures.is_empty() => logger::info(&format!("{name}: passes")),
+            Ok(failures) => {
+                logger::error(&format!("{name}: {}", failures.join("; ")));
+                failed += 1;
+            }
+            Err(e) => {
+                logger::error(&format!("{name}: {e}"));
+                failed += 1;
+            }
+        }
+    }
+    set_skin(previous_skin);
+    if failed > 0 {
+        bail!("{failed} of {} scenarios failed", paths.len());
+    }
+    logger::info(&format!("All {} scenarios pass", paths.len()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::ResultsSummary;
+    use crate::transform::TransformPipeline;
+
+    fn play_random(seed: u64) -> (String, String) {
+        // the chords chart with --random, two keys mashed on every 1/4; the lanes random picked and
+        // the results json
+        let dir = scenarios_dir();
+        let mut map = Map::from_file(&dir.join(CHARTS_DIR).join("chords.qua")).unwrap();
+        map.rate = 1.0;
+        map.mods.random = true;
+        map.mods.seed = seed;
+        let reports = TransformPipeline::from_mods(&map.mods).apply(&mut map).unwrap();
+        let mut playback = Playback::new(map, None).unwrap();
+        for step in 1..=12 {
+            playback.advance_to(f64::from(step) * 250.0).unwrap();
+            playback.press(1);
+            playback.press(2);
+            playback.advance_to(f64::from(step) * 250.0 + 50.0).unwrap();
+            playback.release(1);
+            playback.release(2);
+        }
+        playback.advance_to(4000.0).unwrap();
+        (crate::transform::describe(&reports), ResultsSummary::from_map(&playback.map).to_json().unwrap())
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_results() {
+        assert_eq!(play_random(42), play_random(42));
+    }
+
+    #[test]
+    fn other_seed_gives_other_lanes() {
+        assert_ne!(play_random(42).0, play_random(43).0);
+    }
+
+    fn scenarios_dir() -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios")
+    }
+
+    #[test]
+    fn shipped_scenarios_pass() {
+        run(&scenarios_dir()).unwrap();
+    }
+
+    #[test]
+    fn unmet_expectations_are_reported() {
+        // the chords scenario expected to go differently in every way it's checked
+        let dir = scenarios_dir();
+        let mut scenario = load_scenario(&dir.join("chords.toml")).unwrap();
+        let map = play_scenario(&dir, &scenario).unwrap();
+        assert!(check_expectations(&map, &scenario.expect).is_empty());
+        scenario.expect = Expectations {
+            counts: BTreeMap::from([(JudgementType::Marvelous, 99)]),
+            accuracy: Some(1.0),
+            accuracy_tolerance: default_tolerance(),
+            combo: Some(99),
+            judgements: vec![ExpectedJudgement { index: 99, judgement: JudgementType::Miss, lane: None }],
+        };
+        let failures = check_expectations(&map, &scenario.expect);
+        assert!(failures.iter().any(|failure| failure.ends_with("Marvelous, expected 99")), "{failures:?}");
+        assert!(failures.iter().any(|failure| failure.starts_with("accuracy")), "{failures:?}");
+        assert!(failures.iter().any(|failure| failure.ends_with("expected 99") && failure.starts_with("combo")), "{failures:?}");
+        assert!(failures.iter().any(|failure| failure.starts_with("no judgement 99")), "{failures:?}");
+    }
+
+    #[test]
+    fn actions_have_to_do_one_thing() {
+        let path = std::env::temp_dir().join(format!("vsrg_scenario_actions_{}.toml", std::process::id()));
+        fs::write(&path, "chart = \"chords.qua\"\n[[action]]\nat = 500\npress = 1\nrelease = 1\n[expect]\n").unwrap();
+        let error = load_scenario(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(error.to_string().contains("exactly one thing"), "{error}");
+    }
+}
@@ -0,0 +1,189 @@
+use crate::utils::{HitStat, JudgementType, Time};
+use clap::ValueEnum;
+
+// wife3 constants, from etterna (judge 4)
+const WIFE3_JUDGE_SCALE: f64 = 1.0;
+const WIFE3_MAX_POINTS: f64 = 2.0;
+const WIFE3_MISS_WEIGHT: f64 = -5.5;
+
+// how accuracy is scored; judgements (and everything drawn from them) are the same for every ruleset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Ruleset {
+    #[default]
+    Quaver, // fixed points per judgement
+    Wife3,  // etterna-style continuous points from the hit offset
+}
+
+impl Ruleset {
+    pub fn backend(self) -> &'static dyn ScoreBackend {
+        match self {
+            Ruleset::Quaver => &QuaverScoring,
+            Ruleset::Wife3 => &Wife3Scoring,
+        }
+    }
+}
+
+pub trait ScoreBackend {
+    fn name(&self) -> &'static str;
+
+    // accuracy points one judgement is worth, 100 for a perfect hit
+    fn hit_points(&self, hit_stat: &HitStat) -> f64;
+
+    fn accuracy(&self, hit_stats: &[HitStat]) -> f64 {
+        // average points over every judgement so far
+        self.accuracy_over_time(hit_stats)
+            .last()
+            .map_or(100.0, |&(_, accuracy)| accuracy)
+    }
+
+    fn max_accuracy(&self, hit_stats: &[HitStat], remaining: usize) -> f64 {
+        // best final accuracy still possible, with full points for every judgement left
+        let total = hit_stats.len() + remaining;
+        if total == 0 {
+            return 100.0;
+        }
+        let points: f64 = hit_stats.iter().map(|hit_stat| self.hit_points(hit_stat)).sum();
+        ((points + 100.0 * remaining as f64) / total as f64).max(0.0)
+    }
+
+    fn projected_accuracy(&self, hit_stats: &[HitStat], remaining: usize) -> Option<f64> {
+        // final accuracy if the judgements left are worth what the ones so far were on average, none before
+        // anything was judged; swings a lot early on and lands on the real accuracy once nothing is left
+        if hit_stats.is_empty() {
+            return None;
+        }
+        let points: f64 = hit_stats.iter().map(|hit_stat| self.hit_points(hit_stat)).sum();
+        let average = points / hit_stats.len() as f64;
+        let projected = (points + average * remaining as f64) / (hit_stats.len() + remaining) as f64;
+        Some(projected.clamp(0.0, self.max_accuracy(hit_stats, remaining)))
+    }
+
+    fn accuracy_over_time(&self, hit_stats: &[HitStat]) -> Vec<(Time, f64)> {
+        // running accuracy after each judgement
+        let mut points = 0.0;
+        hit_stats
+            .iter()
+            .enumerate()
+            .map(|(index, hit_stat)| {
+                points += self.hit_points(hit_stat);
+                (hit_stat.time, (points / (index + 1) as f64).max(0.0))
+            })
+            .collect()
+    }
+}
+
+pub struct QuaverScoring;
+
+impl ScoreBackend for QuaverScoring {
+    fn name(&self) -> &'static str {
+        "Quaver"
+    }
+
+    fn hit_points(&self, hit_stat: &HitStat) -> f64 {
+        hit_stat.judgement.accuracy_weight()
+    }
+}
+
+pub struct Wife3Scoring;
+
+impl ScoreBackend for Wife3Scoring {
+    fn name(&self) -> &'static str {
+        "Wife3"
+    }
+
+    fn hit_points(&self, hit_stat: &HitStat) -> f64 {
+        // wife points are out of 2, scaled to accuracy percent
+        let points = if hit_stat.judgement == JudgementType::Miss {
+            WIFE3_MISS_WEIGHT
+        } else {
+            wife3(hit_stat.offset, WIFE3_JUDGE_SCALE)
+        };
+        points / WIFE3_MAX_POINTS * 100.0
+    }
+}
+
+fn erf(x: f64) -> f64 {
+    // abramowitz and stegun 7.1.26, accurate to 1.5e-7
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let polynomial = t
+        * (0.254_829_592
+            + t * (-0.284_496_736 + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    (1.0 - polynomial * (-x * x).exp()).copysign(x)
+}
+
+fn wife3(offset: f64, judge_scale: f64) -> f64 {
+    // points (out of 2) for a hit off by offset ms: full points near 0, an erf falloff to 0 at ~65 ms,
+    // then linearly down to the miss weight at 180 ms (etterna's wife3 curve)
+    let distance = offset.abs();
+    let ridiculous = 5.0 * judge_scale;
+    let max_boo_weight = 180.0 * judge_scale;
+    let zero = 65.0 * judge_scale.powf(0.75);
+    let deviation = 22.7 * judge_scale.powf(0.75);
+
+    if distance <= ridiculous {
+        WIFE3_MAX_POINTS
+    } else if distance <= zero {
+        WIFE3_MAX_POINTS * erf((zero - distance) / deviation)
+    } else if distance <= max_boo_weight {
+        (distance - zero) * WIFE3_MISS_WEIGHT / (max_boo_weight - zero)
+    } else {
+        WIFE3_MISS_WEIGHT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::HitKind;
+
+    fn hit(offset: f64, judgement: JudgementType) -> HitStat {
+        HitStat { time: 0.0, offset, raw_offset: offset, judgement, kind: HitKind::Note, lane: 1 }
+    }
+
+    #[test]
+    fn wife3_curve_at_canonical_offsets() {
+        // expected values from the exact erf; the approximation is good to ~3e-7 points
+        let expected = [
+            (0.0, 2.0),
+            (18.0, 1.993_179_355_040_913),
+            (43.0, 1.659_002_787_740_819_6),
+            (76.0, -0.526_086_956_521_739_1),
+            (106.0, -1.960_869_565_217_391_3),
+            (127.0, -2.965_217_391_304_348),
+            (180.0, -5.5),
+        ];
+        for (offset, points) in expected {
+            for signed in [offset, -offset] {
+                let actual = wife3(signed, WIFE3_JUDGE_SCALE);
+                assert!((actual - points).abs() < 1e-6, "{signed} ms: {actual}, expected {points}");
+            }
+        }
+    }
+
+    #[test]
+    fn wife3_curve_is_continuous_and_floors_at_the_miss_weight() {
+        assert_eq!(wife3(5.0, WIFE3_JUDGE_SCALE), WIFE3_MAX_POINTS);
+        assert!(wife3(65.0, WIFE3_JUDGE_SCALE).abs() < 1e-6);
+        assert!(wife3(65.0 + 1e-9, WIFE3_JUDGE_SCALE).abs() < 1e-6);
+        assert_eq!(wife3(250.0, WIFE3_JUDGE_SCALE), WIFE3_MISS_WEIGHT);
+    }
+
+    #[test]
+    fn wife3_scores_misses_at_the_miss_weight_whatever_the_offset() {
+        let backend = Ruleset::Wife3.backend();
+        assert_eq!(backend.hit_points(&hit(0.0, JudgementType::Miss)), -275.0);
+        assert_eq!(backend.hit_points(&hit(0.0, JudgementType::Marvelous)), 100.0);
+        // a late hit can take points away, but accuracy never shows below 0
+        assert_eq!(backend.accuracy(&[hit(0.0, JudgementType::Miss)]), 0.0);
+    }
+
+    #[test]
+    fn quaver_scoring_uses_the_judgement_weights() {
+        let backend = Ruleset::Quaver.backend();
+        let hit_stats = [hit(0.0, JudgementType::Marvelous), hit(60.0, JudgementType::Great)];
+        assert_eq!(backend.accuracy(&hit_stats), 82.5);
+        assert_eq!(backend.max_accuracy(&hit_stats, 2), 91.25);
+        assert_eq!(backend.projected_accuracy(&hit_stats, 2), Some(82.5));
+        assert_eq!(backend.projected_accuracy(&[], 2), None);
+    }
+}
@@ -0,0 +1,51 @@
+use crate::logger;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs, path::Path};
+
+// a scroll speed is suggested only the first time a chart is loaded, these are the charts it was
+// suggested for (applied or not)
+
+// a suggestion within this fraction of the current setting isn't worth offering
+pub const SUGGESTION_TOLERANCE: f64 = 0.25;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ScrollSuggestions {
+    offered: HashSet<String>, // chart checksums (hex)
+}
+
+impl ScrollSuggestions {
+    pub fn load(path: &Path) -> Self {
+        // a missing file has nothing offered, an unreadable one is replaced on the next save
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                logger::warning(&format!("Failed to read '{}': {}", path.display(), e));
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            logger::warning(&format!("Ignoring unreadable scroll speed suggestions '{}': {}", path.display(), e));
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json).map_err(|e| anyhow!("Failed to write '{}': {}", path.display(), e))
+    }
+
+    pub fn was_offered(&self, checksum: u64) -> bool {
+        self.offered.contains(&format!("{checksum:016x}"))
+    }
+
+    pub fn mark_offered(&mut self, checksum: u64) {
+        self.offered.insert(format!("{checksum:016x}"));
+    }
+}
+
+pub fn worth_offering(suggested: f64, current: f64) -> bool {
+    // whether the suggestion is far enough from the current setting to bring up
+    (suggested - current).abs() > current * SUGGESTION_TOLERANCE
+}
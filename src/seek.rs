@@ -0,0 +1,141 @@
+use crate::map::Map;
+use crate::utils::Time;
+
+// ms a target has to be past the current time, so seeking again from a line or note moves on from it
+// instead of landing on it again
+const SEEK_TOLERANCE: Time = 5.0;
+
+fn chart_end(map: &Map) -> Time {
+    // the audio's length, or the last object's end if that's later (or the length isn't known)
+    map.hit_objects
+        .iter()
+        .map(|hit_object| hit_object.end_time.unwrap_or(hit_object.start_time))
+        .fold(map.length, f64::max)
+}
+
+fn measure_starts(map: &Map) -> Vec<Time> {
+    // the time every measure starts, counted like beat_phase counts them: every timing point starts
+    // a new one, and its measures run until the next timing point (or the end of the chart)
+    let end = chart_end(map);
+    let mut starts = Vec::new();
+    for (index, timing_point) in map.timing_points.iter().enumerate() {
+        let until = map.timing_points.get(index + 1).map_or(end, |next| next.start_time);
+        let measure_length = timing_point.ms_per_beat() * timing_point.beats_per_measure() as f64;
+        if measure_length.is_nan() || measure_length <= 0.0 {
+            // a timing point with no usable bpm is one measure long
+            if timing_point.start_time < until {
+                starts.push(timing_point.start_time);
+            }
+            continue;
+        }
+        // counted from the timing point, so long sections don't drift
+        for measure in 0u32.. {
+            let start = timing_point.start_time + f64::from(measure) * measure_length;
+            if start >= until {
+                break;
+            }
+            starts.push(start);
+        }
+    }
+    starts
+}
+
+pub fn next_measure_time(map: &Map, time: Time) -> Option<(Time, usize)> {
+    // the first measure line after time and its measure number (from 1); None past the last one
+    let starts = measure_starts(map);
+    let index = starts.partition_point(|&start| start <= time + SEEK_TOLERANCE);
+    starts.get(index).map(|&start| (start, index + 1))
+}
+
+pub fn previous_measure_time(map: &Map, time: Time) -> Option<(Time, usize)> {
+    // the last measure line before time and its measure number; None before the first one
+    let starts = measure_starts(map);
+    let index = starts.partition_point(|&start| start < time - SEEK_TOLERANCE).checked_sub(1)?;
+    Some((starts[index], index + 1))
+}
+
+pub fn next_note_time(map: &Map, time: Time) -> Option<(Time, usize)> {
+    // the first note starting after time, and its index; stacked notes give the first of the stack
+    let index = map.hit_objects.partition_point(|hit_object| hit_object.start_time <= time + SEEK_TOLERANCE);
+    map.hit_objects.get(index).map(|hit_object| (hit_object.start_time, index))
+}
+
+pub fn previous_note_time(map: &Map, time: Time) -> Option<(Time, usize)> {
+    // the last note starting before time, and its index; stacked notes give the first of the stack
+    let last = map
+        .hit_objects
+        .partition_point(|hit_object| hit_object.start_time < time - SEEK_TOLERANCE)
+        .checked_sub(1)?;
+    let start_time = map.hit_objects[last].start_time;
+    let index = map.hit_objects.partition_point(|hit_object| hit_object.start_time < start_time);
+    Some((start_time, index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{HitObject, TimingPoint};
+
+    fn bpm_change_chart() -> Map {
+        // 120 bpm (2 s measures), then 180 (1333 ms) from 5 s, halfway through a measure; two notes
+        // stacked at 3 s
+        let mut map = Map::default();
+        map.timing_points = vec![
+            TimingPoint { start_time: 0.0, bpm: 120.0, time_signature: None, hidden: false },
+            TimingPoint { start_time: 5000.0, bpm: 180.0, time_signature: None, hidden: false },
+        ];
+        map.hit_objects = [(1000.0, 1), (3000.0, 1), (3000.0, 2), (6000.0, 3), (8000.0, 4)]
+            .into_iter()
+            .map(|(start_time, lane)| HitObject { start_time, lane, ..HitObject::default() })
+            .collect();
+        map
+    }
+
+    fn rounded(target: Option<(Time, usize)>) -> Option<(Time, usize)> {
+        target.map(|(time, number)| (time.round(), number))
+    }
+
+    #[test]
+    fn measures_restart_at_the_bpm_change() {
+        let map = bpm_change_chart();
+        let starts: Vec<Time> = measure_starts(&map).iter().map(|start| start.round()).collect();
+        assert_eq!(starts, [0.0, 2000.0, 4000.0, 5000.0, 6333.0, 7667.0]);
+        assert_eq!(next_measure_time(&map, 4500.0), Some((5000.0, 4)));
+        assert_eq!(rounded(next_measure_time(&map, 5000.0)), Some((6333.0, 5)));
+        assert_eq!(previous_measure_time(&map, 6000.0), Some((5000.0, 4)));
+        // from a line it moves on to the next one either way
+        assert_eq!(next_measure_time(&map, 2000.0), Some((4000.0, 3)));
+        assert_eq!(previous_measure_time(&map, 2000.0), Some((0.0, 1)));
+    }
+
+    #[test]
+    fn measures_outside_the_chart() {
+        let map = bpm_change_chart();
+        assert_eq!(next_measure_time(&map, -3000.0), Some((0.0, 1)));
+        assert_eq!(previous_measure_time(&map, -3000.0), None);
+        assert_eq!(previous_measure_time(&map, 0.0), None);
+        assert_eq!(next_measure_time(&map, 7700.0), None);
+        assert_eq!(rounded(previous_measure_time(&map, 60_000.0)), Some((7667.0, 6)));
+        // no timing points, no measures
+        assert_eq!(next_measure_time(&Map::default(), 0.0), None);
+    }
+
+    #[test]
+    fn stacked_notes_are_seeked_to_as_one() {
+        let map = bpm_change_chart();
+        assert_eq!(next_note_time(&map, 1000.0), Some((3000.0, 1)));
+        assert_eq!(next_note_time(&map, 3000.0), Some((6000.0, 3)));
+        assert_eq!(previous_note_time(&map, 6000.0), Some((3000.0, 1)));
+        assert_eq!(previous_note_time(&map, 3002.0), Some((1000.0, 0)));
+    }
+
+    #[test]
+    fn notes_outside_the_chart() {
+        let map = bpm_change_chart();
+        assert_eq!(next_note_time(&map, -500.0), Some((1000.0, 0)));
+        assert_eq!(previous_note_time(&map, 1000.0), None);
+        assert_eq!(next_note_time(&map, 8000.0), None);
+        assert_eq!(previous_note_time(&map, 60_000.0), Some((8000.0, 4)));
+        assert_eq!(next_note_time(&Map::default(), 0.0), None);
+    }
+}
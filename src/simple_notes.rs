@@ -0,0 +1,237 @@
+use crate::logger;
+use crate::map::{Map, SimpleNote};
+use crate::utils::Time;
+use anyhow::{anyhow, bail, Result};
+use std::{fs, path::Path};
+
+// plain note lists (.notes.csv) for charts made by scripts: one `start_ms,lane[,end_ms]` row per note,
+// lanes from 1 and an end for LNs. The first line can instead be a header of `key=value` pairs
+// (`bpm=170,audio=song.mp3,keys=7`), blank lines and lines starting with # are skipped
+
+pub const SIMPLE_NOTES_EXTENSION: &str = ".notes.csv";
+// without a header the chart is 4K at this bpm (it only places the timing lines and snap colors)
+pub const DEFAULT_BPM: f64 = 120.0;
+const DEFAULT_KEY_COUNT: i64 = 4;
+
+// a row that couldn't be used, and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedRow {
+    pub line: usize, // 1-indexed
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimpleNotes {
+    pub bpm: f64,
+    pub audio_file: Option<String>,
+    pub key_count: i64,
+    pub notes: Vec<SimpleNote>,
+    pub skipped: Vec<SkippedRow>,
+}
+
+fn parse_time(field: &str, name: &str) -> Result<Time> {
+    let time: Time = field.parse().map_err(|_| anyhow!("{name} '{field}' isn't a number"))?;
+    if !time.is_finite() {
+        bail!("{name} '{field}' isn't a number");
+    }
+    Ok(time)
+}
+
+fn parse_row(fields: &[&str], key_count: i64) -> Result<SimpleNote> {
+    let (start, lane, end) = match fields {
+        [start, lane] | [start, lane, ""] => (start, lane, None),
+        [start, lane, end] => (start, lane, Some(end)),
+        _ => bail!("has {} columns, expected start_ms,lane[,end_ms]", fields.len()),
+    };
+    let start_time = parse_time(start, "start_ms")?;
+    let lane: i64 = lane.parse().map_err(|_| anyhow!("lane '{lane}' isn't a whole number"))?;
+    if !(1..=key_count).contains(&lane) {
+        bail!("lane {lane} is outside 1-{key_count}");
+    }
+    let end_time = end.map(|end| parse_time(end, "end_ms")).transpose()?;
+    if let Some(end_time) = end_time {
+        if end_time <= start_time {
+            bail!("end_ms {end_time} isn't after start_ms {start_time}");
+        }
+    }
+    Ok(SimpleNote { start_time, lane, end_time })
+}
+
+fn parse_header(line: &str, notes: &mut SimpleNotes) -> Result<()> {
+    for pair in line.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let Some((key, value)) = pair.split_once('=') else {
+            bail!("'{pair}' isn't a key=value pair");
+        };
+        let value = value.trim();
+        match key.trim() {
+            "bpm" => match value.parse::<f64>() {
+                Ok(bpm) if bpm.is_finite() && bpm > 0.0 => notes.bpm = bpm,
+                _ => bail!("bpm '{value}' isn't a positive number"),
+            },
+            "audio" => notes.audio_file = (!value.is_empty()).then(|| value.to_string()),
+            "keys" => match value.parse::<i64>() {
+                Ok(keys) if (1..=7).contains(&keys) => notes.key_count = keys,
+                _ => bail!("keys '{value}' isn't 1-7"),
+            },
+            other => bail!("unknown header key '{other}' (bpm, audio and keys are known)"),
+        }
+    }
+    Ok(())
+}
+
+pub fn parse_simple_notes(contents: &str) -> Result<SimpleNotes> {
+    // rows that can't be used are skipped and listed rather than failing the whole file, a bad
+    // header does fail it (every row after it would be read wrong)
+    let mut notes = SimpleNotes {
+        bpm: DEFAULT_BPM,
+        audio_file: None,
+        key_count: DEFAULT_KEY_COUNT,
+        notes: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if index == 0 && line.contains('=') {
+            parse_header(line, &mut notes).map_err(|e| anyhow!("Header: {e}"))?;
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if index == 0 && fields.first() == Some(&"start_ms") {
+            // column names
+            continue;
+        }
+        match parse_row(&fields, notes.key_count) {
+            Ok(note) => notes.notes.push(note),
+            Err(e) => notes.skipped.push(SkippedRow { line: index + 1, reason: e.to_string() }),
+        }
+    }
+    Ok(notes)
+}
+
+pub fn load_simple_notes(path: &Path) -> Result<Map> {
+    // a note list as a chart named after the file, with a warning for every row skipped
+    let contents =
+        fs::read_to_string(path).map_err(|e| anyhow!("Failed to read note list '{}': {}", path.display(), e))?;
+    let parsed = parse_simple_notes(&contents).map_err(|e| anyhow!("Failed to parse '{}': {}", path.display(), e))?;
+    for row in &parsed.skipped {
+        logger::warning(&format!("Skipping line {} of '{}': {}", row.line, path.display(), row.reason));
+    }
+    if parsed.notes.is_empty() {
+        bail!("'{}' has no notes", path.display());
+    }
+    let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let title = file_name.strip_suffix(SIMPLE_NOTES_EXTENSION).unwrap_or(&file_name).to_string();
+    let mut map = Map::from_simple_notes(&parsed.notes, parsed.bpm, parsed.key_count);
+    map.title = Some(title);
+    map.difficulty_name = Some(format!("{} BPM", parsed.bpm));
+    map.audio_file = parsed.audio_file;
+    map.file_path = path.to_string_lossy().to_string();
+    map.validation_errors = map.validate().err().unwrap_or_default();
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(start_time: Time, lane: i64, end_time: Option<Time>) -> SimpleNote {
+        SimpleNote { start_time, lane, end_time }
+    }
+
+    fn reasons(notes: &SimpleNotes) -> Vec<(usize, &str)> {
+        notes.skipped.iter().map(|row| (row.line, row.reason.as_str())).collect()
+    }
+
+    #[test]
+    fn note_list_with_a_header() {
+        let notes = parse_simple_notes(
+            "bpm=170, audio=song.mp3, keys=7\n\
+             # intro\n\
+             \n\
+             500,1\n\
+             750.5, 7, 1250\n\
+             1000,3,\n",
+        )
+        .unwrap();
+        assert_eq!((notes.bpm, notes.audio_file.as_deref(), notes.key_count), (170.0, Some("song.mp3"), 7));
+        assert_eq!(notes.notes, [note(500.0, 1, None), note(750.5, 7, Some(1250.0)), note(1000.0, 3, None)]);
+        assert!(notes.skipped.is_empty());
+    }
+
+    #[test]
+    fn without_a_header_it_is_4k_at_the_default_bpm() {
+        // column names in the first line are skipped too
+        let notes = parse_simple_notes("start_ms,lane,end_ms\n0,4\n").unwrap();
+        assert_eq!((notes.bpm, notes.audio_file, notes.key_count), (DEFAULT_BPM, None, 4));
+        assert_eq!(notes.notes, [note(0.0, 4, None)]);
+        // the second line can't be a header
+        let notes = parse_simple_notes("0,1\nbpm=200\n").unwrap();
+        assert_eq!((notes.bpm, notes.notes.len()), (DEFAULT_BPM, 1));
+        assert_eq!(notes.skipped.len(), 1);
+    }
+
+    #[test]
+    fn broken_rows_are_skipped_with_their_line() {
+        let notes = parse_simple_notes(
+            "100,1\n\
+             200\n\
+             300,1,400,500\n\
+             abc,1\n\
+             400,1.5\n\
+             500,5\n\
+             600,0\n\
+             700,2,700\n\
+             800,2,nan\n\
+             900,2\n",
+        )
+        .unwrap();
+        assert_eq!(notes.notes, [note(100.0, 1, None), note(900.0, 2, None)]);
+        assert_eq!(
+            reasons(&notes),
+            [
+                (2, "has 1 columns, expected start_ms,lane[,end_ms]"),
+                (3, "has 4 columns, expected start_ms,lane[,end_ms]"),
+                (4, "start_ms 'abc' isn't a number"),
+                (5, "lane '1.5' isn't a whole number"),
+                (6, "lane 5 is outside 1-4"),
+                (7, "lane 0 is outside 1-4"),
+                (8, "end_ms 700 isn't after start_ms 700"),
+                (9, "end_ms 'nan' isn't a number"),
+            ]
+        );
+    }
+
+    #[test]
+    fn broken_headers_fail_the_file() {
+        let error = |header: &str| parse_simple_notes(&format!("{header}\n0,1\n")).unwrap_err().to_string();
+        assert_eq!(error("bpm=0"), "Header: bpm '0' isn't a positive number");
+        assert_eq!(error("bpm=fast"), "Header: bpm 'fast' isn't a positive number");
+        assert_eq!(error("keys=8"), "Header: keys '8' isn't 1-7");
+        assert_eq!(error("bpm=120,offset=30"), "Header: unknown header key 'offset' (bpm, audio and keys are known)");
+        assert_eq!(error("bpm=120,keys"), "Header: 'keys' isn't a key=value pair");
+    }
+
+    #[test]
+    fn note_list_loads_as_a_chart() {
+        let dir = std::env::temp_dir().join(format!("vsrg_simple_notes_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("generated.notes.csv");
+        fs::write(&path, "keys=7,bpm=150\n0,1\n500,7,900\n600,9\n").unwrap();
+        logger::take_warnings();
+        let map = load_simple_notes(&path).unwrap();
+        assert_eq!((map.title.as_deref(), map.difficulty_name.as_deref()), (Some("generated"), Some("150 BPM")));
+        assert_eq!((map.mode.key_count(), map.hit_objects.len(), map.timing_points[0].bpm), (7, 2, 150.0));
+        let warnings = logger::take_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Skipping line 4 of"), "{warnings:?}");
+
+        // nothing usable is an error, as is a missing file
+        fs::write(&path, "# nothing yet\n5,9\n").unwrap();
+        assert!(load_simple_notes(&path).unwrap_err().to_string().ends_with("has no notes"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(load_simple_notes(&path).unwrap_err().to_string().starts_with("Failed to read note list"));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::utils::JudgementType;
+
+// which judgements get the center splash and lane popups; hidden ones still count, score and go on
+// the hit error bar like any other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplashFilter {
+    hidden: [bool; 6],          // by JudgementType, in declaration order
+    pub combo_break_only: bool, // only misses and okays are shown
+}
+
+impl SplashFilter {
+    pub const SHOW_ALL: Self = Self { hidden: [false; 6], combo_break_only: false };
+
+    pub fn new(hidden: &[JudgementType], combo_break_only: bool) -> Self {
+        let mut filter = Self { combo_break_only, ..Self::SHOW_ALL };
+        for &judgement in hidden {
+            filter.hidden[judgement as usize] = true;
+        }
+        filter
+    }
+
+    pub fn shows(&self, judgement: JudgementType) -> bool {
+        if self.combo_break_only && !matches!(judgement, JudgementType::Miss | JudgementType::Okay) {
+            return false;
+        }
+        !self.hidden[judgement as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::map::Map;
+    use crate::render::set_reference_positions;
+    use crate::utils::{set_skin, Skin, DEFAULT_SKIN, JUDGEMENTS};
+
+    fn shown(filter: SplashFilter) -> Vec<JudgementType> {
+        JUDGEMENTS.iter().map(|judgement| judgement.kind).filter(|&judgement| filter.shows(judgement)).collect()
+    }
+
+    #[test]
+    fn hidden_judgements_get_no_splash() {
+        use JudgementType::{Good, Great, Marvelous, Miss, Okay, Perfect};
+        assert_eq!(shown(SplashFilter::SHOW_ALL), [Marvelous, Perfect, Great, Good, Okay, Miss]);
+        assert_eq!(shown(SplashFilter::new(&[Marvelous], false)), [Perfect, Great, Good, Okay, Miss]);
+        assert_eq!(shown(SplashFilter::new(&[Marvelous, Perfect, Miss], false)), [Great, Good, Okay]);
+        // from the config, by name
+        let config = Config::parse("hide_splash_for = [\"Marvelous\", \"Perfect\"]\n").unwrap();
+        assert_eq!(shown(config.apply_to_skin(DEFAULT_SKIN).splash_filter), [Great, Good, Okay, Miss]);
+        assert!(Config::parse("hide_splash_for = [\"Amazing\"]\n").is_err());
+    }
+
+    #[test]
+    fn combo_break_only_shows_misses_and_okays() {
+        use JudgementType::{Marvelous, Miss, Okay};
+        assert_eq!(shown(SplashFilter::new(&[], true)), [Okay, Miss]);
+        // and hiding still applies on top
+        assert_eq!(shown(SplashFilter::new(&[Okay], true)), [Miss]);
+        assert_eq!(shown(SplashFilter::new(&[Marvelous], true)), [Okay, Miss]);
+    }
+
+    #[test]
+    fn hidden_judgements_still_count_but_keep_the_last_splash() {
+        let chart = "Mode: Keys4\nTimingPoints:\n- StartTime: 0\n  Bpm: 60\nHitObjects:\n\
+            - StartTime: 1000\n  Lane: 1\n  KeySounds: []\n- StartTime: 2000\n  Lane: 1\n  KeySounds: []\n";
+        set_skin(Skin { splash_filter: SplashFilter::new(&[JudgementType::Marvelous], false), ..DEFAULT_SKIN });
+        let mut map: Map = serde_yaml::from_str(chart).unwrap();
+        map.rate = 1.0;
+        crate::initialize_map(&mut map, &set_reference_positions(None)).unwrap();
+        // a great, then a marvelous that's hidden
+        map.handle_gameplay_key_press(1060.0, 0);
+        map.handle_gameplay_key_press(2000.0, 0);
+        set_skin(DEFAULT_SKIN);
+        assert_eq!(map.splash.map(|(judgement, time, _)| (judgement, time)), Some((JudgementType::Great, 1060.0)));
+        assert_eq!(map.lane_splashes.get(&1), Some(&(JudgementType::Great, 1060.0)));
+        assert_eq!(map.last_judgement.map(|(judgement, _, _)| judgement), Some(JudgementType::Marvelous));
+        assert_eq!(map.judgement_counts[&JudgementType::Marvelous], 1);
+        assert_eq!((map.hit_stats.len(), map.combo), (2, 2));
+    }
+}
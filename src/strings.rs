@@ -0,0 +1,272 @@
+use crate::logger;
+use std::{collections::HashMap, fs, path::{Path, PathBuf}, sync::OnceLock};
+
+// default english strings, used for any key a translation doesn't have
+const ENGLISH: &[(&str, &str)] = &[
+    ("judgement.marvelous", "Marvelous"),
+    ("judgement.perfect", "Perfect"),
+    ("judgement.great", "Great"),
+    ("judgement.good", "Good"),
+    ("judgement.okay", "Okay"),
+    ("judgement.miss", "Miss"),
+    ("judgement.count", "{judgement}: {count}"),
+    ("state.playing", "Playing"),
+    ("state.paused", "Paused"),
+    ("state.stopped", "Stopped/empty"),
+    ("state.no_notes", "No notes in this chart"),
+    ("debug.map_info", "Map: {title} - {artist} [{difficulty}] by {creator}"),
+    ("debug.map_counts", "{notes} Notes, {svs} SVs, {ssfs} SSFs, {groups} Groups, {timing_points} Timing Points, {timing_lines} Timing Lines"),
+    ("debug.bpm", "BPM: {bpm}"),
+    ("debug.bpm_range", "BPM: {bpm} ({min}–{max})"),
+    ("debug.timing_lines", "Timing lines updated: {updated} / {total}"),
+    ("debug.playback", "Visuals: {visuals} | Audio: {audio} (space, r)"),
+    ("debug.volume_rate", "Volume: {volume} (up/down) | Rate: {rate}x, {pitch} (-/+, shift for fine steps)"),
+    ("debug.audio_latency", "Audio output latency: {latency} ms"),
+    ("debug.time", "Time: {time} ({seconds}) / {total}"),
+    ("progress.time", "{time} / {total}"),
+    ("debug.not_available", "N/A"),
+    ("debug.fps", "FPS: {average} | 1% low: {low} | 0.1% low: {lowest}"),
+    ("debug.audio_status", "Audio status: {status}"),
+    ("debug.audio_memory", "Sample memory: {used} / {cap} MB ({count} samples)"),
+    ("debug.audio_no_path", "Audio status: no path set for '{file}'"),
+    ("results.title", "Results"),
+    ("results.retry_hint", "R to retry, Esc to quit"),
+    ("results.mash_bursts", "Mash bursts: {count}"),
+    ("results.unstable_rate", "UR: {value}"),
+    ("results.early", "Early"),
+    ("results.late", "Late"),
+    ("results.mixed_mods", "Mods changed during play"),
+    ("results.resumed", "Resumed"),
+    ("results.autoplay_assisted", "Autoplay assisted"),
+    ("results.long_notes", "Long notes"),
+    ("results.ln_head_accuracy", "LN head accuracy: {value}"),
+    ("results.ln_note_accuracy", "Note accuracy: {value}"),
+    ("results.ln_release", "Release: {mean} ms (SD {deviation})"),
+    ("results.ln_breaks", "Early breaks: {count}"),
+    ("results.ln_regrabs", "Regrabs: {count}"),
+    ("results.ln_longest_hold", "Longest hold: {value}"),
+    ("results.lane_heatmap", "Lanes, {seconds}s columns (red: misses)"),
+    ("results.windows", "Hit windows: {preset} at {rate}x (W for details)"),
+    ("results.windows_open", "Hit windows: {preset} at {rate}x (W to hide)"),
+    ("results.windows_scaled", "In chart ms, narrower in real time at rates above 1x"),
+    ("ramp.progress", "Ramp: {rate}x → {max}x"),
+    ("pace.summary", "{remaining} notes left | Pace: {pace} | Max: {max}"),
+    ("ramp.complete", "Ramp complete: cleared {rate}x"),
+    ("ramp.failed", "Ramp stopped at {rate}x (under {accuracy}%)"),
+    ("ramp.history_entry", "{rate}x: {accuracy}%"),
+    ("picker.title", "Pick a difficulty"),
+    ("picker.entry", "{number}. {name} ({keys}K, {notes} notes)"),
+    ("picker.mapset_title", "Pick a map"),
+    ("picker.mapset_entry", "{number}. {artist} - {title} ({charts} difficulties)"),
+    ("picker.hint", "Up/down and Enter, or its number. Esc to quit"),
+    ("recovery.title", "Unfinished play found"),
+    ("recovery.details", "{judged} judgements, {accuracy}% at {time}"),
+    ("recovery.no_score", "No score saved yet, {events} key presses"),
+    ("recovery.hint", "E to export it as a replay, D to discard it"),
+    ("versus.player", "P{number}"),
+    ("versus.combo", "{combo}x"),
+    ("versus.ahead", "P{number} ahead by {difference}%"),
+    ("versus.tied", "Tied"),
+    ("sync_test.hint", "Hit the notes on the clicks"),
+    ("sync_test.mean_offset", "Mean offset: {offset} ms ({count} hits)"),
+    ("toast.map_skin_active", "Map skin overrides active (K to toggle)"),
+    ("toast.map_skin_disabled", "Map skin overrides disabled (K to toggle)"),
+    ("toast.mashing", "Mashing detected"),
+    ("toast.clip_marked", "Clip marked (F9, shift for a screenshot)"),
+    ("toast.skin_reloaded", "Skin reloaded (F5)"),
+    ("toast.skin_reload_failed", "Skin not reloaded: {error}"),
+    ("toast.mirror_on", "Mirror on (M to toggle)"),
+    ("toast.mirror_off", "Mirror off (M to toggle)"),
+    ("toast.no_sv_on", "No SV on (V to toggle)"),
+    ("toast.no_sv_off", "No SV off (V to toggle)"),
+    ("toast.window_bands_on", "Hit window preview on (H to toggle)"),
+    ("toast.window_bands_off", "Hit window preview off (H to toggle)"),
+    ("toast.resume_offer", "Resume at {time}? press Y"),
+    ("toast.resumed", "Resumed at {time}"),
+    ("toast.seek_measure", "Measure {measure}"),
+    ("toast.seek_note", "Note {index}"),
+    ("toast.local_offset_offer", "The first beat suggests a local offset of {offset} ms, press O to use it"),
+    ("toast.local_offset_set", "Local offset set to {offset} ms"),
+    ("toast.scroll_speed_offer", "Suggested scroll speed: {speed} — press T to apply"),
+    ("toast.scroll_speed_set", "Scroll speed set to {speed}"),
+    ("toast.autoplay_on", "Autoplay on (F7 to toggle)"),
+    ("toast.autoplay_off", "Autoplay off (F7 to toggle)"),
+    ("toast.mods_locked", "Mods can't change while replays are played or recorded"),
+    ("toast.difficulty_switched", "Switched to {name} (ctrl+left/right)"),
+    ("toast.no_other_difficulties", "No other difficulties in this folder"),
+    ("toast.difficulty_locked", "Difficulties can't change while replays are played or recorded"),
+    ("toast.ramp_next", "Cleared! Retrying at {rate}x"),
+    ("toast.rate_locked", "The rate is set by --rate-ramp"),
+    ("toast.rate", "Rate {rate}x, {pitch}"),
+    ("rate.pitch", "{semitones} semitones ({cents} cents)"),
+];
+
+fn language_dir() -> PathBuf {
+    // next to the songs and config, so it doesn't depend on where the binary is run from
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("lang/")
+}
+
+// strings from the selected translation file, empty when using english
+static TRANSLATION: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+fn flatten_table(prefix: &str, table: toml::Table, strings: &mut HashMap<String, String>) {
+    // turns nested toml tables into dotted keys ([judgement] marvelous = .. -> judgement.marvelous)
+    for (key, value) in table {
+        let key = if prefix.is_empty() { key } else { format!("{prefix}.{key}") };
+        match value {
+            toml::Value::String(text) => {
+                strings.insert(key, text);
+            }
+            toml::Value::Table(inner) => flatten_table(&key, inner, strings),
+            _ => logger::warning(&format!("Ignoring non-string translation '{key}'")),
+        }
+    }
+}
+
+fn load_translation(lang: &str) -> anyhow::Result<HashMap<String, String>> {
+    let path = language_dir().join(format!("{lang}.toml"));
+    let contents = fs::read_to_string(&path)
+        .map_err(|e| anyhow::anyhow!("Failed to read '{}': {}", path.display(), e))?;
+    let table: toml::Table = toml::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("Failed to parse '{}': {}", path.display(), e))?;
+
+    let mut strings = HashMap::new();
+    flatten_table("", table, &mut strings);
+    for key in strings.keys() {
+        if !ENGLISH.iter().any(|(english_key, _)| english_key == key) {
+            logger::warning(&format!("Unknown translation key '{key}' in '{}'", path.display()));
+        }
+    }
+    Ok(strings)
+}
+
+pub fn set_language(lang: &str) {
+    // selects the translation used by tr, only the first call has any effect
+    let strings = if lang == "en" {
+        HashMap::new()
+    } else {
+        load_translation(lang).unwrap_or_else(|e| {
+            logger::warning(&format!("Using English, couldn't load language '{lang}': {e}"));
+            HashMap::new()
+        })
+    };
+    if TRANSLATION.set(strings).is_err() {
+        logger::warning("Language was already set");
+    }
+}
+
+fn lookup<'a>(translation: Option<&'a HashMap<String, String>>, key: &'a str) -> &'a str {
+    // a string from the translation, falling back to english and then to the key itself
+    if let Some(text) = translation.and_then(|strings| strings.get(key)) {
+        return text;
+    }
+    ENGLISH
+        .iter()
+        .find(|(english_key, _)| *english_key == key)
+        .map_or(key, |(_, text)| text)
+}
+
+fn fill(text: &str, args: &[(&str, &str)]) -> String {
+    // replaces {name} placeholders with their values, unknown ones are left as they are
+    args.iter().fold(text.to_string(), |text, (name, value)| {
+        text.replace(&format!("{{{name}}}"), value)
+    })
+}
+
+pub fn tr(key: &'static str) -> &'static str {
+    // looks up a ui string in the selected translation
+    lookup(TRANSLATION.get(), key)
+}
+
+pub fn tr_args(key: &'static str, args: &[(&str, &str)]) -> String {
+    // tr, with {name} placeholders replaced by their values
+    fill(tr(key), args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn placeholders(text: &str) -> BTreeSet<&str> {
+        text.split('{').skip(1).filter_map(|rest| rest.split_once('}')).map(|(name, _)| name).collect()
+    }
+
+    #[test]
+    fn missing_translations_fall_back_to_english_then_the_key() {
+        let translation = HashMap::from([("state.paused".to_string(), "Pausiert".to_string())]);
+        assert_eq!(lookup(Some(&translation), "state.paused"), "Pausiert");
+        assert_eq!(lookup(Some(&translation), "state.playing"), "Playing");
+        assert_eq!(lookup(None, "state.paused"), "Paused");
+        assert_eq!(lookup(Some(&translation), "state.unknown"), "state.unknown");
+        assert_eq!(lookup(None, "state.unknown"), "state.unknown");
+    }
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        let text = lookup(None, "toast.rate");
+        assert_eq!(fill(text, &[("rate", "1.25"), ("pitch", "+3.9 semitones")]), "Rate 1.25x, +3.9 semitones");
+        // every one is replaced, ones without a value are kept so the gap shows
+        assert_eq!(fill("{a} and {a}", &[("a", "x")]), "x and x");
+        assert_eq!(fill("{a} and {b}", &[("a", "x"), ("c", "y")]), "x and {b}");
+        // values aren't filled in again
+        assert_eq!(fill("{a} {b}", &[("a", "{b}"), ("b", "y")]), "y y");
+    }
+
+    #[test]
+    fn english_table_has_each_key_once() {
+        let keys: BTreeSet<&str> = ENGLISH.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys.len(), ENGLISH.len());
+    }
+
+    #[test]
+    fn language_files_translate_every_english_key() {
+        let english: HashMap<&str, &str> = ENGLISH.iter().copied().collect();
+        let mut languages = 0;
+        for entry in fs::read_dir(language_dir()).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "toml") {
+                continue;
+            }
+            let lang = path.file_stem().and_then(|stem| stem.to_str()).unwrap();
+            let translation = load_translation(lang).unwrap();
+            let keys: BTreeSet<&str> = translation.keys().map(String::as_str).collect();
+            assert_eq!(keys, english.keys().copied().collect(), "{lang} has different keys");
+            for (key, text) in &translation {
+                assert_eq!(placeholders(text), placeholders(english[key.as_str()]), "{lang}: {key}");
+            }
+            languages += 1;
+        }
+        assert!(languages > 0);
+    }
+
+    #[test]
+    fn every_key_used_in_code_is_in_the_english_table() {
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let mut used = BTreeSet::new();
+        let mut dirs = vec![src];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if path.extension().is_some_and(|ext| ext == "rs") {
+                    let code = fs::read_to_string(&path).unwrap();
+                    for function in ["tr(\"", "tr_args(\""] {
+                        for (start, _) in code.match_indices(function) {
+                            // not write_str( and the like
+                            if code[..start].ends_with(|c: char| c.is_alphanumeric() || c == '_') {
+                                continue;
+                            }
+                            let call = &code[start + function.len()..];
+                            used.extend(call.split_once('"').map(|(key, _)| key.to_string()));
+                        }
+                    }
+                }
+            }
+        }
+        let missing: Vec<&String> = used.iter().filter(|key| !ENGLISH.iter().any(|(english_key, _)| english_key == key)).collect();
+        assert!(missing.is_empty(), "not in the English table: {missing:?}");
+        assert!(used.len() > 10);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::utils::{HitStat, JudgementType, Time};
+use anyhow::{anyhow, Result};
+use std::{f64::consts::TAU, path::Path};
+
+pub const SYNC_TEST_BPM: f64 = 120.0;
+pub const SYNC_TEST_DURATION: Time = 120_000.0;
+pub const SYNC_TEST_LANES: i64 = 4;
+
+const SAMPLE_RATE: u32 = 44100;
+const CLICK_LENGTH: f64 = 0.03; // s
+const CLICK_FREQUENCY: f64 = 1000.0; // hz, the first beat of each bar is an octave higher
+
+pub fn write_click_track(path: &Path, bpm: f64, duration: Time) -> Result<()> {
+    // a mono wav with a short decaying click exactly on every beat
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)
+        .map_err(|e| anyhow!("Failed to create click track '{}': {}", path.display(), e))?;
+
+    let sample_rate = f64::from(SAMPLE_RATE);
+    let beat_length = 60.0 / bpm; // s
+    let total_samples = (duration / 1000.0 * sample_rate) as u64;
+    for index in 0..total_samples {
+        let time = index as f64 / sample_rate;
+        let beat = (time / beat_length).floor();
+        let since_beat = time - beat * beat_length;
+        let sample = if since_beat < CLICK_LENGTH {
+            let frequency = if (beat as u64).is_multiple_of(4) { CLICK_FREQUENCY * 2.0 } else { CLICK_FREQUENCY };
+            let envelope = 1.0 - since_beat / CLICK_LENGTH;
+            (TAU * frequency * since_beat).sin() * envelope * envelope * 0.8
+        } else {
+            0.0
+        };
+        writer.write_sample((sample * f64::from(i16::MAX)) as i16)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+pub fn mean_offset(hit_stats: &[HitStat]) -> Option<(f64, usize)> {
+    // mean offset of every hit (misses left out) and how many there were
+    let offsets: Vec<f64> = hit_stats
+        .iter()
+        .filter(|hit_stat| hit_stat.judgement != JudgementType::Miss)
+        .map(|hit_stat| hit_stat.offset)
+        .collect();
+    if offsets.is_empty() {
+        return None;
+    }
+    Some((offsets.iter().sum::<f64>() / offsets.len() as f64, offsets.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::HitKind;
+
+    fn hit(offset: f64, judgement: JudgementType) -> HitStat {
+        HitStat { time: 0.0, offset, raw_offset: offset, judgement, kind: HitKind::Note, lane: 1 }
+    }
+
+    #[test]
+    fn mean_offset_leaves_out_misses() {
+        let hit_stats = [
+            hit(10.0, JudgementType::Marvelous),
+            hit(-4.0, JudgementType::Perfect),
+            hit(150.0, JudgementType::Miss),
+        ];
+        assert_eq!(mean_offset(&hit_stats), Some((3.0, 2)));
+        assert_eq!(mean_offset(&[hit(150.0, JudgementType::Miss)]), None);
+    }
+
+    #[test]
+    fn click_track_clicks_on_every_beat() {
+        let path = std::env::temp_dir().join(format!("vsrg_click_track_{}.wav", std::process::id()));
+        write_click_track(&path, SYNC_TEST_BPM, 2000.0).unwrap();
+        let samples: Vec<i16> = hound::WavReader::open(&path).unwrap().samples().map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(samples.len(), 88200);
+        let at = |ms: f64| samples[(ms / 1000.0 * f64::from(SAMPLE_RATE)) as usize];
+        for beat in [0.0, 500.0, 1000.0, 1500.0] {
+            // loud right after the beat, silent from the end of the click to the next one
+            let first = (beat / 1000.0 * f64::from(SAMPLE_RATE)) as usize;
+            let loudest = samples[first..first + 441].iter().map(|sample| sample.unsigned_abs()).max().unwrap();
+            assert!(loudest > 10000, "no click at {beat} ms");
+            assert_eq!(at(beat + CLICK_LENGTH * 1000.0 + 1.0), 0);
+            assert_eq!(at(beat + 499.0), 0);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use crate::draw::{Draw, SoftwareDraw};
+use crate::map::Map;
+use crate::utils::{format_time, Time, TimeStyle, BEAT_SNAPS, DEFAULT_TIMING_GROUP_ID};
+use macroquad::color::Color;
+
+const LANE_WIDTH: f64 = 6.0;
+const NOTE_HEIGHT: f64 = 2.0;
+const COLUMN_PADDING: f64 = 10.0;
+const COLUMN_HEIGHT: f64 = 900.0;
+const HEADER_HEIGHT: f64 = 48.0;
+const MIN_WIDTH: f64 = 360.0; // room for the header text on charts with few columns
+const TEXT_SIZE: f64 = 14.0;
+const BACKGROUND_COLOR: Color = Color::new(0.06, 0.06, 0.08, 1.0);
+const COLUMN_COLOR: Color = Color::new(0.12, 0.12, 0.15, 1.0);
+const LN_BODY_ALPHA: f32 = 0.45;
+
+pub struct ThumbnailLayout {
+    pub columns: usize,
+    pub duration: Time, // time covered by the whole image
+    pub column_height: f64,
+}
+
+impl ThumbnailLayout {
+    pub fn column_duration(&self) -> Time {
+        self.duration / self.columns as f64
+    }
+
+    pub fn time_to_column_y(&self, time: Time) -> (usize, f64) {
+        // maps a time to (column, y within the column), time runs bottom to top like gameplay
+        let column_duration = self.column_duration();
+        let time = time.clamp(0.0, self.duration);
+        let column = ((time / column_duration) as usize).min(self.columns - 1);
+        let progress = (time - column as f64 * column_duration) / column_duration;
+        (column, self.column_height * (1.0 - progress))
+    }
+
+    fn column_segments(&self, start_time: Time, end_time: Time) -> Vec<(usize, f64, f64)> {
+        // splits a time range into (column, top y, bottom y) pieces, one per column it crosses
+        let (start_column, start_y) = self.time_to_column_y(start_time);
+        let (end_column, end_y) = self.time_to_column_y(end_time);
+        (start_column..=end_column)
+            .map(|column| {
+                let bottom = if column == start_column { start_y } else { self.column_height };
+                let top = if column == end_column { end_y } else { 0.0 };
+                (column, top, bottom)
+            })
+            .collect()
+    }
+}
+
+fn chart_duration(map: &Map) -> Time {
+    // time of the last object end, so the chart fills the image regardless of audio length
+    map.hit_objects
+        .iter()
+        .map(|hit_object| hit_object.end_time.unwrap_or(hit_object.start_time))
+        .fold(0.0, f64::max)
+        .max(1.0)
+}
+
+fn sv_heat_color(multiplier: f64) -> Option<Color> {
+    // red for faster than 1x, blue for slower, stronger the further from 1x
+    let intensity = (multiplier.abs().max(0.01).ln().abs() / 2.0).min(1.0) as f32;
+    if intensity < 0.02 {
+        return None;
+    }
+    let alpha = 0.1 + 0.4 * intensity;
+    if multiplier.abs() > 1.0 {
+        Some(Color::new(1.0, 0.25, 0.2, alpha))
+    } else {
+        Some(Color::new(0.2, 0.4, 1.0, alpha))
+    }
+}
+
+pub fn render_thumbnail(map: &Map, columns: usize, sv_heat: bool) -> SoftwareDraw {
+    // draws the whole chart as side-by-side columns of equal time
+    // the map needs its default timing group, sort and beat snaps initialized
+    let columns = columns.max(1);
+    let key_count = map.get_key_count(true);
+    let column_width = key_count as f64 * LANE_WIDTH;
+    let layout = ThumbnailLayout {
+        columns,
+        duration: chart_duration(map),
+        column_height: COLUMN_HEIGHT,
+    };
+
+    let width = (COLUMN_PADDING + columns as f64 * (column_width + COLUMN_PADDING)).max(MIN_WIDTH);
+    let height = HEADER_HEIGHT + COLUMN_HEIGHT + COLUMN_PADDING;
+    let mut draw = SoftwareDraw::new(width as u32, height as u32, BACKGROUND_COLOR);
+
+    let column_x = |column: usize| COLUMN_PADDING + column as f64 * (column_width + COLUMN_PADDING);
+
+    for column in 0..columns {
+        draw.draw_rectangle(column_x(column), HEADER_HEIGHT, column_width, COLUMN_HEIGHT, COLUMN_COLOR);
+    }
+
+    // sv heat tint behind the notes, each sv lasts until the next one
+    if sv_heat {
+        if let Some(timing_group) = map.timing_groups.get(DEFAULT_TIMING_GROUP_ID) {
+            let svs = &timing_group.scroll_velocities;
+            for (index, sv) in svs.iter().enumerate() {
+                let end_time = svs.get(index + 1).map_or(layout.duration, |next| next.start_time);
+                let Some(color) = sv_heat_color(sv.multiplier) else {
+                    continue;
+                };
+                if end_time <= sv.start_time {
+                    continue;
+                }
+                for (column, top, bottom) in layout.column_segments(sv.start_time, end_time) {
+                    draw.draw_rectangle(column_x(column), HEADER_HEIGHT + top, column_width, bottom - top, color);
+                }
+            }
+        }
+    }
+
+    for hit_object in &map.hit_objects {
+        let snap_color = BEAT_SNAPS[hit_object.snap_index].color;
+        let lane_x = (hit_object.lane - 1) as f64 * LANE_WIDTH;
+
+        if let Some(end_time) = hit_object.end_time {
+            let body_color = Color { a: LN_BODY_ALPHA, ..snap_color };
+            for (column, top, bottom) in layout.column_segments(hit_object.start_time, end_time) {
+                draw.draw_rectangle(
+                    column_x(column) + lane_x + 1.0,
+                    HEADER_HEIGHT + top,
+                    LANE_WIDTH - 2.0,
+                    bottom - top,
+                    body_color,
+                );
+            }
+        }
+
+        let (column, y) = layout.time_to_column_y(hit_object.start_time);
+        draw.draw_rectangle(
+            column_x(column) + lane_x,
+            HEADER_HEIGHT + y - NOTE_HEIGHT,
+            LANE_WIDTH,
+            NOTE_HEIGHT,
+            snap_color,
+        );
+    }
+
+    // metadata header
+    let title = format!(
+        "{} - {} [{}]",
+        map.artist.as_deref().unwrap_or("Unknown"),
+        map.title.as_deref().unwrap_or("Unknown"),
+        map.difficulty_name.as_deref().unwrap_or("Unknown"),
+    );
+    let details = format!(
+        "{}K  {} notes  {}  by {}",
+        key_count,
+        map.hit_objects.len(),
+        format_time(layout.duration, TimeStyle::Clock),
+        map.creator.as_deref().unwrap_or("Unknown"),
+    );
+    draw.draw_text(&title, COLUMN_PADDING, 20.0, TEXT_SIZE, Color::new(1.0, 1.0, 1.0, 1.0));
+    draw.draw_text(&details, COLUMN_PADDING, 38.0, TEXT_SIZE, Color::new(0.7, 0.7, 0.7, 1.0));
+
+    draw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn layout() -> ThumbnailLayout {
+        ThumbnailLayout { columns: 4, duration: 4000.0, column_height: 900.0 }
+    }
+
+    #[test]
+    fn times_fill_columns_bottom_to_top() {
+        let layout = layout();
+        assert_eq!(layout.time_to_column_y(0.0), (0, 900.0));
+        assert_eq!(layout.time_to_column_y(500.0), (0, 450.0));
+        // the end of a column is the bottom of the next one
+        assert_eq!(layout.time_to_column_y(1000.0), (1, 900.0));
+        assert_eq!(layout.time_to_column_y(3750.0), (3, 225.0));
+        // the very end stays in the last column, times outside the chart are clamped
+        assert_eq!(layout.time_to_column_y(4000.0), (3, 0.0));
+        assert_eq!(layout.time_to_column_y(-100.0), (0, 900.0));
+        assert_eq!(layout.time_to_column_y(9000.0), (3, 0.0));
+    }
+
+    #[test]
+    fn long_notes_are_split_where_they_cross_columns() {
+        let layout = layout();
+        assert_eq!(layout.column_segments(250.0, 750.0), [(0, 225.0, 675.0)]);
+        assert_eq!(layout.column_segments(500.0, 2500.0), [(0, 0.0, 450.0), (1, 0.0, 900.0), (2, 450.0, 900.0)]);
+    }
+
+    fn thumbnail_hash(chart: &str, columns: usize, sv_heat: bool) -> u64 {
+        // fnv-1a over the size and pixels
+        let mut map = Map::from_file(&Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/charts").join(chart)).unwrap();
+        map.initialize_default_timing_group();
+        map.sort();
+        map.normalize_svs();
+        map.initialize_beat_snaps().unwrap();
+        let image = render_thumbnail(&map, columns, sv_heat).image;
+        let bytes = image.width().to_le_bytes().into_iter().chain(image.height().to_le_bytes()).chain(image.into_raw());
+        bytes.fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3))
+    }
+
+    #[test]
+    fn thumbnails_match_their_golden_hashes() {
+        // when a change to the thumbnail is meant, check the new image (the thumbnail command) and update these
+        assert_eq!(thumbnail_hash("long_notes.qua", 3, false), 11_259_467_711_267_779_373);
+        assert_eq!(thumbnail_hash("sv_reversal.qua", 2, true), 6_563_205_940_872_493_949);
+    }
+}
@@ -0,0 +1,292 @@
+use crate::map::Map;
+use crate::render::{update_frame, FrameState};
+use crate::utils::{FieldPositions, Time};
+use anyhow::{anyhow, bail, Result};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+// first bytes of a binary trace, bumped if the layout changes
+const BINARY_MAGIC: &[u8; 8] = b"VSRGTRC1";
+
+// how a trace is written, picked by the output's extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Csv,    // one "time,index,lane,y,held" row per note per frame, easy to inspect
+    Binary, // per frame: time (f64), note count (u32), then index (u32), lane (u8), y (f32), held (u8) per note
+}
+
+impl TraceFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => Ok(Self::Csv),
+            Some("bin") => Ok(Self::Binary),
+            _ => bail!("Unknown trace format for '{}', use .csv or .bin", path.display()),
+        }
+    }
+}
+
+// where one note was drawn in a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceNote {
+    pub index: u32, // index in the map's hit objects
+    pub lane: u8,   // 0-indexed column it's drawn in (after mirror/random)
+    pub y: f32,     // screen y of the note's head, from the top of the view
+    pub held: bool, // an LN currently being held
+}
+
+// one frame read back from a trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub time: Time,
+    pub notes: Vec<TraceNote>,
+}
+
+// buffered, so tracing every frame doesn't slow the simulation down
+pub struct TraceWriter {
+    out: BufWriter<File>,
+    format: TraceFormat,
+    pub frames: usize,
+    pub notes: usize,
+}
+
+impl TraceWriter {
+    pub fn create(path: &Path) -> Result<Self> {
+        let format = TraceFormat::from_path(path)?;
+        let file = File::create(path).map_err(|e| anyhow!("Failed to create '{}': {}", path.display(), e))?;
+        let mut out = BufWriter::new(file);
+        match format {
+            TraceFormat::Csv => writeln!(out, "time,index,lane,y,held")?,
+            TraceFormat::Binary => out.write_all(BINARY_MAGIC)?,
+        }
+        Ok(Self { out, format, frames: 0, notes: 0 })
+    }
+
+    pub fn write_frame(&mut self, time: Time, notes: &[TraceNote]) -> Result<()> {
+        match self.format {
+            TraceFormat::Csv => {
+                for note in notes {
+                    writeln!(self.out, "{time},{},{},{},{}", note.index, note.lane, note.y, u8::from(note.held))?;
+                }
+            }
+            TraceFormat::Binary => {
+                self.out.write_all(&time.to_le_bytes())?;
+                self.out.write_all(&(notes.len() as u32).to_le_bytes())?;
+                for note in notes {
+                    self.out.write_all(&note.index.to_le_bytes())?;
+                    self.out.write_all(&[note.lane])?;
+                    self.out.write_all(&note.y.to_le_bytes())?;
+                    self.out.write_all(&[u8::from(note.held)])?;
+                }
+            }
+        }
+        self.frames += 1;
+        self.notes += notes.len();
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+pub fn trace_map(map: &mut Map, field_positions: &FieldPositions, frame_ms: f64, view_height: f64, writer: &mut TraceWriter) -> Result<()> {
+    // steps through the whole (initialized) map a frame at a time, writing where its notes are in each
+    let mut notes = Vec::new();
+    for frame in 0.. {
+        let time = f64::from(frame) * frame_ms;
+        if time > map.playable_length {
+            break;
+        }
+        map.time = time;
+        update_frame(&mut FrameState {
+            map,
+            compare_map: None,
+            chart_diff: &[],
+            field_positions,
+            alpha: 1.0,
+            view_height,
+            background: None,
+        })?;
+        visible_notes(map, field_positions.hold_hit_position_y, view_height, &mut notes);
+        writer.write_frame(time, &notes)?;
+    }
+    Ok(())
+}
+
+fn read_csv(contents: &str) -> Result<Vec<TraceFrame>> {
+    // rows of the same time make up a frame; frames without notes have no rows, so they're not read back
+    let mut frames: Vec<TraceFrame> = Vec::new();
+    for (number, line) in contents.lines().enumerate().skip(1) {
+        let fields: Vec<&str> = line.split(',').collect();
+        let [time, index, lane, y, held] = fields[..] else {
+            bail!("Line {} has {} fields, expected 5", number + 1, fields.len());
+        };
+        let time: Time = time.parse()?;
+        let note = TraceNote { index: index.parse()?, lane: lane.parse()?, y: y.parse()?, held: held == "1" };
+        match frames.last_mut() {
+            Some(frame) if frame.time == time => frame.notes.push(note),
+            _ => frames.push(TraceFrame { time, notes: vec![note] }),
+        }
+    }
+    Ok(frames)
+}
+
+fn take<'a>(bytes: &'a [u8], position: &mut usize, length: usize) -> Result<&'a [u8]> {
+    // the next length bytes of a binary trace
+    let taken = bytes.get(*position..*position + length).ok_or_else(|| anyhow!("Trace ends in the middle of a frame"))?;
+    *position += length;
+    Ok(taken)
+}
+
+fn read_binary(bytes: &[u8]) -> Result<Vec<TraceFrame>> {
+    if !bytes.starts_with(BINARY_MAGIC) {
+        bail!("Not a binary trace");
+    }
+    let mut position = BINARY_MAGIC.len();
+    let mut frames = Vec::new();
+    while position < bytes.len() {
+        let time = f64::from_le_bytes(take(bytes, &mut position, 8)?.try_into()?);
+        let count = u32::from_le_bytes(take(bytes, &mut position, 4)?.try_into()?);
+        let mut notes = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let index = u32::from_le_bytes(take(bytes, &mut position, 4)?.try_into()?);
+            let lane = take(bytes, &mut position, 1)?[0];
+            let y = f32::from_le_bytes(take(bytes, &mut position, 4)?.try_into()?);
+            let held = take(bytes, &mut position, 1)?[0] != 0;
+            notes.push(TraceNote { index, lane, y, held });
+        }
+        frames.push(TraceFrame { time, notes });
+    }
+    Ok(frames)
+}
+
+pub fn read_trace(path: &Path) -> Result<Vec<TraceFrame>> {
+    // reads a trace written by TraceWriter back, in the format its extension says
+    let bytes = fs::read(path).map_err(|e| anyhow!("Failed to read '{}': {}", path.display(), e))?;
+    match TraceFormat::from_path(path)? {
+        TraceFormat::Csv => read_csv(std::str::from_utf8(&bytes)?),
+        TraceFormat::Binary => read_binary(&bytes),
+    }
+}
+
+pub fn visible_notes(map: &Map, hold_hit_position_y: f64, view_height: f64, notes: &mut Vec<TraceNote>) {
+    // the notes on screen at the map's current time, placed like draw_notes places them
+    notes.clear();
+    let num_lanes = map.get_key_count(false);
+    for (index, note) in map.hit_objects.iter().enumerate() {
+        if note.is_finished() {
+            continue;
+        }
+        let held = map.held_notes.get(&note.lane) == Some(&index);
+        let y = if note.end_time.is_some() && note.start_time <= map.time {
+            hold_hit_position_y + view_height
+        } else {
+            note.position as f64 + view_height
+        };
+        let tail_y = note.position_tail as f64 + view_height;
+        if y.max(tail_y) < 0.0 || y.min(tail_y) > view_height {
+            continue;
+        }
+        let lane = if map.mods.mirror && !map.is_scratch_lane(note.lane) { num_lanes - note.lane } else { note.lane - 1 };
+        notes.push(TraceNote {
+            index: index as u32,
+            lane: lane as u8,
+            y: y as f32,
+            held,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::initialize_map;
+    use crate::render::set_reference_positions;
+
+    const VIEW_HEIGHT: f64 = 1200.0;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("vsrg_trace_{}_{name}", std::process::id()))
+    }
+
+    fn traced(path: &Path) -> Vec<TraceFrame> {
+        // a short chart traced at 60 fps and read back
+        let mut map = Map::from_file(Path::new("scenarios/charts/long_notes.qua")).unwrap();
+        map.rate = 1.0;
+        map.mods.autoplay = true;
+        let field_positions = set_reference_positions(None);
+        initialize_map(&mut map, &field_positions).unwrap();
+        let mut writer = TraceWriter::create(path).unwrap();
+        trace_map(&mut map, &field_positions, 1000.0 / 60.0, VIEW_HEIGHT, &mut writer).unwrap();
+        let frames = writer.frames;
+        writer.finish().unwrap();
+        let read = read_trace(path).unwrap();
+        fs::remove_file(path).unwrap();
+        if path.extension().is_some_and(|extension| extension == "bin") {
+            assert_eq!(read.len(), frames);
+        }
+        read
+    }
+
+    #[test]
+    fn frames_round_trip_through_both_formats() {
+        let frames = [
+            TraceFrame { time: 0.0, notes: vec![TraceNote { index: 0, lane: 2, y: 512.5, held: false }] },
+            TraceFrame {
+                time: 16.5,
+                notes: vec![
+                    TraceNote { index: 0, lane: 2, y: 540.25, held: true },
+                    TraceNote { index: 7, lane: 0, y: -3.0, held: false },
+                ],
+            },
+        ];
+        for name in ["frames.csv", "frames.bin"] {
+            let path = temp_path(name);
+            let mut writer = TraceWriter::create(&path).unwrap();
+            for frame in &frames {
+                writer.write_frame(frame.time, &frame.notes).unwrap();
+            }
+            writer.finish().unwrap();
+            let read = read_trace(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            assert_eq!(read, frames, "{name}");
+        }
+    }
+
+    #[test]
+    fn traced_run_moves_forward_smoothly() {
+        let binary = traced(&temp_path("run.bin"));
+        assert!(binary.windows(2).all(|pair| pair[0].time < pair[1].time));
+        assert!(binary.iter().any(|frame| frame.notes.iter().any(|note| note.held)));
+        for pair in binary.windows(2) {
+            for note in &pair[1].notes {
+                let Some(before) = pair[0].notes.iter().find(|before| before.index == note.index) else {
+                    continue;
+                };
+                // notes only come down the screen (no SVs here), and not by more than a frame's worth
+                let delta = note.y - before.y;
+                assert!((0.0..100.0).contains(&delta), "note {} moved {delta} px", note.index);
+            }
+        }
+        // the csv has the same frames, apart from the ones without notes
+        let csv = traced(&temp_path("run.csv"));
+        let with_notes: Vec<&TraceFrame> = binary.iter().filter(|frame| !frame.notes.is_empty()).collect();
+        assert_eq!(csv.iter().collect::<Vec<_>>(), with_notes);
+    }
+
+    #[test]
+    fn truncated_binary_trace_is_an_error() {
+        let path = temp_path("truncated.bin");
+        let mut writer = TraceWriter::create(&path).unwrap();
+        writer.write_frame(0.0, &[TraceNote { index: 0, lane: 0, y: 1.0, held: false }]).unwrap();
+        writer.finish().unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+        assert!(read_trace(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
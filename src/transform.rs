@@ -0,0 +1,262 @@
+use crate::map::{Map, Mods};
+use crate::utils::{sort_by_start_time, Rng, Time};
+use anyhow::Result;
+use std::fmt;
+
+// changes to a chart's data, applied before it's initialized; the mirror mod isn't one of these, it only
+// changes how the chart is drawn and played so it can be toggled mid-song (Mirror here is for headless output)
+
+// what a transform did, with the parameters it was given and anything it decided (like random's lanes)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransformReport {
+    pub name: &'static str,
+    pub parameters: Vec<(&'static str, String)>,
+    pub changes_times: bool, // the chart has to be sorted again afterwards
+}
+
+impl TransformReport {
+    fn new(name: &'static str) -> Self {
+        Self { name, parameters: Vec::new(), changes_times: false }
+    }
+
+    fn parameter(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.parameters.push((name, value.to_string()));
+        self
+    }
+}
+
+impl fmt::Display for TransformReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // "random(seed=5, lanes=[2, 4, 1, 3])", or just the name without parameters
+        write!(f, "{}", self.name)?;
+        if !self.parameters.is_empty() {
+            let parameters: Vec<String> = self.parameters.iter().map(|(name, value)| format!("{name}={value}")).collect();
+            write!(f, "({})", parameters.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+pub trait MapTransform {
+    fn apply(&self, map: &mut Map) -> Result<TransformReport>;
+}
+
+// flips the lanes (1 <-> key count); done before random, lane l ends up in random's lanes[k - l],
+// done after it, in k + 1 - lanes[l - 1]
+pub struct Mirror;
+
+impl MapTransform for Mirror {
+    fn apply(&self, map: &mut Map) -> Result<TransformReport> {
+        map.mirror_chart_lanes();
+        Ok(TransformReport::new("mirror"))
+    }
+}
+
+// shuffles the lanes with a fresh generator from the seed, so the same seed always gives the same lanes
+pub struct Random {
+    pub seed: u64,
+}
+
+impl MapTransform for Random {
+    fn apply(&self, map: &mut Map) -> Result<TransformReport> {
+        let lanes = map.randomize_lanes(&mut Rng::new(self.seed));
+        Ok(TransformReport::new("random")
+            .parameter("seed", self.seed)
+            .parameter("lanes", format!("{lanes:?}")))
+    }
+}
+
+pub struct NoLongNotes;
+
+impl MapTransform for NoLongNotes {
+    fn apply(&self, map: &mut Map) -> Result<TransformReport> {
+        let removed = map.remove_long_notes();
+        Ok(TransformReport::new("no_ln").parameter("removed", removed))
+    }
+}
+
+// times as if played at a rate, for dumps that line up with rate-changed audio
+pub struct ScaleTimes {
+    pub rate: f64,
+}
+
+impl MapTransform for ScaleTimes {
+    fn apply(&self, map: &mut Map) -> Result<TransformReport> {
+        map.scale_times(self.rate)?;
+        Ok(TransformReport {
+            changes_times: true,
+            ..TransformReport::new("rate").parameter("rate", self.rate)
+        })
+    }
+}
+
+pub struct ShiftTimes {
+    pub delta: Time,
+    pub range: Option<(Time, Time)>, // only objects starting within it are moved
+    pub clamp: bool,                 // clamp times shifted before 0 instead of failing
+}
+
+impl MapTransform for ShiftTimes {
+    fn apply(&self, map: &mut Map) -> Result<TransformReport> {
+        map.shift_times(self.delta, self.range, self.clamp)?;
+        let mut report = TransformReport::new("shift").parameter("ms", self.delta);
+        if let Some((from, to)) = self.range {
+            report = report.parameter("from", from).parameter("to", to);
+        }
+        Ok(TransformReport {
+            changes_times: true,
+            ..report.parameter("clamp", self.clamp)
+        })
+    }
+}
+
+// transforms applied one after another in the order they were added; what they invalidate is
+// redone once at the end
+#[derive(Default)]
+pub struct TransformPipeline {
+    transforms: Vec<Box<dyn MapTransform>>,
+}
+
+impl TransformPipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_mods(mods: &Mods) -> Self {
+        // the chart changes a play's mods make, in the order every play (and replay) applies them
+        Self::new()
+            .then_if(mods.no_ln, NoLongNotes)
+            .then_if(mods.random, Random { seed: mods.seed })
+    }
+
+    pub fn then(mut self, transform: impl MapTransform + 'static) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    pub fn then_if(self, condition: bool, transform: impl MapTransform + 'static) -> Self {
+        if condition {
+            self.then(transform)
+        } else {
+            self
+        }
+    }
+
+    pub fn apply(&self, map: &mut Map) -> Result<Vec<TransformReport>> {
+        // stops at the first failing transform; the map may be partly transformed then
+        let mut reports = Vec::with_capacity(self.transforms.len());
+        for transform in &self.transforms {
+            reports.push(transform.apply(map)?);
+        }
+        if reports.iter().any(|report| report.changes_times) {
+            sort_by_start_time(&mut map.scroll_velocities);
+            sort_by_start_time(&mut map.scroll_speed_factors);
+            map.sort();
+        }
+        Ok(reports)
+    }
+}
+
+pub fn describe(reports: &[TransformReport]) -> String {
+    reports.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{GameMode, HitObject};
+
+    fn chart() -> Map {
+        // one note per lane in lane order, and an LN in lane 2
+        let mut map = Map::default();
+        map.mode = GameMode::Keys4;
+        map.hit_objects = (1..=4).map(|lane| HitObject { start_time: lane as f64 * 100.0, lane, ..HitObject::default() }).collect();
+        map.hit_objects.push(HitObject { start_time: 500.0, end_time: Some(700.0), lane: 2, ..HitObject::default() });
+        map
+    }
+
+    fn lanes(map: &Map) -> Vec<i64> {
+        map.hit_objects.iter().map(|note| note.lane).collect()
+    }
+
+    fn random_lanes(report: &TransformReport) -> Vec<i64> {
+        // the lanes random reported, "[2, 4, 1, 3]"
+        let (_, lanes) = report.parameters.iter().find(|(name, _)| *name == "lanes").unwrap();
+        lanes.trim_matches(['[', ']']).split(", ").map(|lane| lane.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn mirror_then_random_and_random_then_mirror_give_the_documented_lanes() {
+        let seed = 5;
+        let mut mirrored_first = chart();
+        let reports = TransformPipeline::new().then(Mirror).then(Random { seed }).apply(&mut mirrored_first).unwrap();
+        let shuffle = random_lanes(&reports[1]);
+        let expected: Vec<i64> = lanes(&chart()).iter().map(|&lane| shuffle[(4 - lane) as usize]).collect();
+        assert_eq!(lanes(&mirrored_first), expected);
+
+        let mut random_first = chart();
+        let reports = TransformPipeline::new().then(Random { seed }).then(Mirror).apply(&mut random_first).unwrap();
+        assert_eq!(random_lanes(&reports[0]), shuffle);
+        let expected: Vec<i64> = lanes(&chart()).iter().map(|&lane| 5 - shuffle[(lane - 1) as usize]).collect();
+        assert_eq!(lanes(&random_first), expected);
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_lanes() {
+        let (mut first, mut second) = (chart(), chart());
+        Random { seed: 9 }.apply(&mut first).unwrap();
+        Random { seed: 9 }.apply(&mut second).unwrap();
+        assert_eq!(lanes(&first), lanes(&second));
+        let mut sorted = lanes(&first)[..4].to_vec();
+        sorted.sort_unstable();
+        assert_eq!(sorted, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn reports_list_every_transform_with_its_parameters() {
+        let mut map = chart();
+        let reports = TransformPipeline::new()
+            .then(NoLongNotes)
+            .then_if(false, Mirror)
+            .then(ScaleTimes { rate: 2.0 })
+            .then(ShiftTimes { delta: -20.0, range: Some((0.0, 150.0)), clamp: false })
+            .apply(&mut map)
+            .unwrap();
+        assert_eq!(describe(&reports), "no_ln(removed=1), rate(rate=2), shift(ms=-20, from=0, to=150, clamp=false)");
+        assert_eq!(TransformReport::new("mirror").to_string(), "mirror");
+    }
+
+    #[test]
+    fn pipeline_sorts_once_times_have_changed() {
+        // the first note is moved past the second, so it has to be sorted again
+        let mut map = chart();
+        TransformPipeline::new()
+            .then(ShiftTimes { delta: 150.0, range: Some((100.0, 100.0)), clamp: false })
+            .then(ScaleTimes { rate: 0.5 })
+            .apply(&mut map)
+            .unwrap();
+        let times: Vec<Time> = map.hit_objects.iter().map(|note| note.start_time).collect();
+        assert_eq!(times, [400.0, 500.0, 600.0, 800.0, 1000.0]);
+        assert_eq!(lanes(&map), [2, 1, 3, 4, 2]);
+    }
+
+    #[test]
+    fn failing_transform_stops_the_pipeline() {
+        let mut map = chart();
+        let shift = ShiftTimes { delta: -150.0, range: None, clamp: false };
+        assert!(TransformPipeline::new().then(shift).then(NoLongNotes).apply(&mut map).is_err());
+        assert_eq!(map.hit_objects[4].end_time, Some(700.0));
+        let clamped = ShiftTimes { delta: -150.0, range: None, clamp: true };
+        TransformPipeline::new().then(clamped).apply(&mut map).unwrap();
+        assert_eq!(map.hit_objects[0].start_time, 0.0);
+    }
+
+    #[test]
+    fn mods_build_no_ln_before_random() {
+        let mods = Mods { no_ln: true, random: true, seed: 3, ..Mods::default() };
+        let reports = TransformPipeline::from_mods(&mods).apply(&mut chart()).unwrap();
+        let names: Vec<&str> = reports.iter().map(|report| report.name).collect();
+        assert_eq!(names, ["no_ln", "random"]);
+        assert!(TransformPipeline::from_mods(&Mods::default()).apply(&mut chart()).unwrap().is_empty());
+    }
+}
@@ -1,5 +1,10 @@
 use macroquad::{color::Color, prelude::*};
-use std::fmt;
+use crate::strings::tr;
+use crate::splash::SplashFilter;
+use serde::{Deserialize, Serialize};
+use std::{collections::{BTreeMap, HashMap}, fmt};
+#[cfg(not(test))]
+use std::sync::RwLock;
 // use serde::{Deserialize, Serialize};
 
 pub const DEFAULT_TIMING_GROUP_ID: &str = "$Default";
@@ -8,13 +13,43 @@ pub const DEFAULT_TIMING_GROUP_ID: &str = "$Default";
 // rounding for track positions, for int/float conversion - 100.0 for Quaver compatibility
 pub const TRACK_ROUNDING: f64 = 100.0;
 
+// small seeded PRNG (splitmix64); every randomized feature draws from one of these so a run replays from its seed
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub const fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub const fn below(&mut self, bound: u64) -> u64 {
+        // number in 0..bound
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+}
+
+// time (ms) after the last judgeable event before the map counts as finished
+pub const LEAD_OUT_TIME: f64 = 1500.0;
+
 #[derive(Debug, Clone)]
 pub struct FieldPositions<'a> {
     // positions from top of screen
     pub receptor_position_y: f64,    // receptors position
     pub hit_position_y: f64,         // hit object target position
+    pub hold_hit_position_y: f64,    // held hit object target position
+    pub hold_end_hit_position_y: f64, // LN end target position
     pub timing_line_position_y: f64, // timing line position
-    pub receptor_texture: &'a Texture2D, // receptor texture
+    pub receptor_texture: Option<&'a Texture2D>, // receptor texture, None when running headless
 }
 
 pub struct BeatSnap {
@@ -35,36 +70,131 @@ pub const BEAT_SNAPS: &[BeatSnap] = &[
     BeatSnap { divisor: 1,  color: Color::new(200.0 / 255.0, 200.0 / 255.0, 200.0 / 255.0, 1.0) }, // 48th (gray) + fallback
 ];
 
-#[derive(Debug, Clone)]
+// how notes and receptors are drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoteShape {
+    Bars,
+    Circles,
+}
+
+// most lanes a chart can have (7K)
+pub const MAX_LANES: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Skin {
     // skin settings
-    pub note_shape: &'static str,  // shape of the notes ("circles", "bars")
+    pub note_shape: NoteShape,     // shape of the notes
     pub lane_width: f64,           // width of each lane/column
+    pub lane_widths: [Option<f64>; MAX_LANES], // width of single columns (left to right), instead of lane_width
+    pub lane_gap_after: [bool; MAX_LANES], // columns (left to right) followed by an extra gap, e.g. between the hands
+    pub lane_gap_px: f64,          // width of that gap
+    pub scratch_lane_width: f64,   // width of the scratch lane (+1 key charts), drawn after the others
+    pub playfield_x_offset: f64,   // how far the playfield is moved right of the center (negative for left)
     pub note_width: f64,           // width of each note
     pub note_height: f64,          // height of each note
     pub receptors_y_position: f64, // y position of the receptors/hit line
+    pub hold_hit_position_offset: f64,     // how far above the hit line held LN bodies anchor
+    pub hold_end_hit_position_offset: f64, // how far above the hit line LN ends are judged
     pub scroll_speed: f64,         // scroll speed of the notes
     pub wide_timing_lines: bool,   // whether to draw timing lines to the sides of the screen
     pub downscroll: bool,          // downscroll (true) or upscroll (false)
     pub normalize_scroll_velocity_by_rate_percentage: usize, // percentage of scaling applied when changing rates
     pub offset: f64,               // audio offset in milliseconds
+    pub hit_error_bar_width: f64,  // width of the hit error bar (covers the full miss window)
+    pub hit_error_bar_height: f64, // height of the hit error bar
+    pub hit_error_bar_y: f64,      // y position of the hit error bar from the bottom of the screen
+    pub snap_colors: [Color; 9],   // note color for each entry in BEAT_SNAPS
+    pub watermark_x: f64,          // x position of the rate/mods watermark's right edge from the right of the screen
+    pub watermark_y: f64,          // y position of the rate/mods watermark from the bottom of the screen
+    pub lane_splash: bool,         // show each lane's judgement above its receptor
+    pub lane_splash_offset: f64,   // how far above the receptors lane judgements appear
+    pub lane_splash_duration: f64, // how long (ms) a lane judgement floats up before it's gone
+    pub splash_filter: SplashFilter, // judgements that don't get the center splash or a lane judgement
+    pub frozen_sv_warning: f64,    // shortest (ms) 0x SV stop loading a chart warns about, 0 for none
+    pub flip_receptor_on_upscroll: bool, // turn the receptor texture (drawn for downscroll) upside down in upscroll
+    pub mine_color: Color,         // color of mines, which aren't snap colored
+    pub lookahead: f64,            // how far ahead (ms) notes are drawn, 0 to work it out from the screen, scroll speed and SV
+    pub background_dim: f64,       // how much the map's background is darkened, 0 (as it is) to 1 (black)
 }
 
 
-pub const SKIN: Skin = Skin {
-    note_shape: "bars",
+pub const DEFAULT_SKIN: Skin = Skin {
+    note_shape: NoteShape::Bars,
     lane_width: 145.0,           // 136
+    lane_widths: [None; MAX_LANES],
+    lane_gap_after: [false; MAX_LANES],
+    lane_gap_px: 40.0,
+    scratch_lane_width: 180.0,
+    playfield_x_offset: 0.0,
     note_width: 145.0,           // 136
     note_height: 36.0,           // 36
     receptors_y_position: 226.0, // 226
+    hold_hit_position_offset: 0.0,
+    hold_end_hit_position_offset: 0.0,
     scroll_speed: 320.0,         // 200 = 20 in quaver
     wide_timing_lines: true,
     downscroll: true,
     normalize_scroll_velocity_by_rate_percentage: 100,
     offset: -50.0,
+    hit_error_bar_width: 300.0,
+    hit_error_bar_height: 12.0,
+    hit_error_bar_y: 180.0,
+    snap_colors: [
+        BEAT_SNAPS[0].color,
+        BEAT_SNAPS[1].color,
+        BEAT_SNAPS[2].color,
+        BEAT_SNAPS[3].color,
+        BEAT_SNAPS[4].color,
+        BEAT_SNAPS[5].color,
+        BEAT_SNAPS[6].color,
+        BEAT_SNAPS[7].color,
+        BEAT_SNAPS[8].color,
+    ],
+    watermark_x: 20.0,
+    watermark_y: 20.0,
+    lane_splash: true,
+    lane_splash_offset: 60.0,
+    lane_splash_duration: 300.0,
+    splash_filter: SplashFilter::SHOW_ALL,
+    frozen_sv_warning: 5000.0,
+    flip_receptor_on_upscroll: true,
+    mine_color: Color::new(0.55, 0.55, 0.6, 1.0),
+    lookahead: 0.0,
+    background_dim: 0.8,
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+// skin in use; starts as the default and can be swapped at runtime (e.g. map skin overrides)
+#[cfg(not(test))]
+static ACTIVE_SKIN: RwLock<Skin> = RwLock::new(DEFAULT_SKIN);
+// unit tests run in parallel threads, each with its own skin so one that sets one doesn't change
+// another's frames
+#[cfg(test)]
+thread_local! {
+    static ACTIVE_SKIN: std::cell::Cell<Skin> = const { std::cell::Cell::new(DEFAULT_SKIN) };
+}
+
+#[cfg(not(test))]
+pub fn skin() -> Skin {
+    *ACTIVE_SKIN.read().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(not(test))]
+pub fn set_skin(skin: Skin) {
+    *ACTIVE_SKIN.write().unwrap_or_else(std::sync::PoisonError::into_inner) = skin;
+}
+
+#[cfg(test)]
+pub fn skin() -> Skin {
+    ACTIVE_SKIN.get()
+}
+
+#[cfg(test)]
+pub fn set_skin(skin: Skin) {
+    ACTIVE_SKIN.set(skin);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum JudgementType {
     Marvelous,
     Perfect,
@@ -95,6 +225,79 @@ impl fmt::Display for JudgementType {
     }
 }
 
+impl JudgementType {
+    pub fn localized(self) -> &'static str {
+        // name shown in the ui; Display stays english for logs
+        tr(match self {
+            JudgementType::Marvelous => "judgement.marvelous",
+            JudgementType::Perfect => "judgement.perfect",
+            JudgementType::Great => "judgement.great",
+            JudgementType::Good => "judgement.good",
+            JudgementType::Okay => "judgement.okay",
+            JudgementType::Miss => "judgement.miss",
+        })
+    }
+    pub const fn accuracy_weight(self) -> f64 {
+        // quaver-style accuracy points for one judgement
+        match self {
+            JudgementType::Marvelous => 100.0,
+            JudgementType::Perfect => 98.25,
+            JudgementType::Great => 65.0,
+            JudgementType::Good => 25.0,
+            JudgementType::Okay => -100.0,
+            JudgementType::Miss => -50.0,
+        }
+    }
+}
+
+// display color for each judgement
+pub const fn judgement_color(judgement: JudgementType) -> Color {
+    match judgement {
+        JudgementType::Marvelous => WHITE,
+        JudgementType::Perfect => GOLD,
+        JudgementType::Great => GREEN,
+        JudgementType::Good => BLUE,
+        JudgementType::Okay => DARKGRAY,
+        JudgementType::Miss => RED,
+    }
+}
+
+// a single judged note, kept for the hit error bar and results
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitStat {
+    pub time: Time,   // song time the judgement happened at
+    pub offset: f64,  // note time - press time (positive = early), after the lane's offset
+    pub raw_offset: f64, // the same for the press as it came in, before the lane's offset
+    pub judgement: JudgementType,
+    pub kind: HitKind,
+    pub lane: i64,    // the note's lane as played (after mirror or random)
+}
+
+// what part of a note a judgement was for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HitKind {
+    Note,         // a normal note (rice)
+    LongNoteHead, // an LN's start
+    LongNoteEnd,  // an LN's release, or the miss for one broken and never regrabbed
+    Mine,         // a press on a mine, always a miss
+}
+
+// something that happened while holding an LN, apart from its judgements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HoldEventKind {
+    Grab,    // head hit, the LN is being held
+    Break,   // let go too early
+    Regrab,  // pressed again after a break
+    Release, // let go at the end, its end was judged
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HoldEvent {
+    pub time: Time,
+    pub note: usize, // index of the LN
+    pub kind: HoldEventKind,
+}
+
 pub const JUDGEMENTS: &[Judgement] = &[
     Judgement { kind: JudgementType::Marvelous, window: 18.0 },
     Judgement { kind: JudgementType::Perfect,   window: 43.0 },
@@ -104,6 +307,57 @@ pub const JUDGEMENTS: &[Judgement] = &[
     Judgement { kind: JudgementType::Miss,      window: 164.0 },
 ];
 
+// hit windows (ms) on either side of a note; early and late can differ
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct JudgementWindows {
+    pub early: BTreeMap<JudgementType, f64>, // how early a press can be for each judgement
+    pub late: BTreeMap<JudgementType, f64>,  // how late a press can be for each judgement
+}
+
+impl JudgementWindows {
+    pub fn symmetric(judgements: &[Judgement]) -> Self {
+        let windows: BTreeMap<JudgementType, f64> = judgements.iter().map(|j| (j.kind, j.window)).collect();
+        Self {
+            early: windows.clone(),
+            late: windows,
+        }
+    }
+
+    pub fn early(&self, judgement: JudgementType) -> f64 {
+        self.early.get(&judgement).copied().unwrap_or(0.0)
+    }
+
+    pub fn late(&self, judgement: JudgementType) -> f64 {
+        self.late.get(&judgement).copied().unwrap_or(0.0)
+    }
+
+    pub fn widest(&self) -> f64 {
+        // the furthest from a note anything is judged, on either side
+        self.early(JudgementType::Miss).max(self.late(JudgementType::Miss))
+    }
+
+    pub fn judge(&self, distance: Time) -> Option<JudgementType> {
+        // judges a press `distance` ms before its target (negative = late)
+        // early: past the miss window is a ghost tap, between okay and miss is an early miss
+        // late: past the okay window nothing is judged, the note is left to be auto missed
+        let (distance, windows) = if distance >= 0.0 {
+            if distance > self.early(JudgementType::Miss) {
+                return None;
+            }
+            (distance, &self.early)
+        } else {
+            if -distance > self.late(JudgementType::Okay) {
+                return None;
+            }
+            (-distance, &self.late)
+        };
+        JUDGEMENTS
+            .iter()
+            .map(|judgement| judgement.kind)
+            .find(|kind| windows.get(kind).is_some_and(|&window| distance <= window))
+    }
+}
+
 
 // anything representing a time in milliseconds
 pub type Time = f64;
@@ -118,7 +372,71 @@ pub fn lerp(a: f64, b: f64, t: f64) -> f64 {
     a + (b - a) * t
 }
 
-// returns index of currently active item (start_time <= time)
+// how format_time writes a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeStyle {
+    Clock,   // 1:23, 1:02:03 past an hour
+    Seconds, // 83.21s
+    Precise, // 1:23.210, 1:02:03.210 past an hour
+}
+
+pub fn format_time(ms: Time, style: TimeStyle) -> String {
+    let unsigned = format_unsigned_time(ms.abs(), style);
+    // times before the chart starts (lead-in) get a minus sign, unless they round to zero
+    if ms < 0.0 && unsigned.chars().any(|c| matches!(c, '1'..='9')) {
+        format!("-{unsigned}")
+    } else {
+        unsigned
+    }
+}
+
+fn format_unsigned_time(ms: Time, style: TimeStyle) -> String {
+    if style == TimeStyle::Seconds {
+        return format!("{:.2}s", ms / 1000.0);
+    }
+    // rounded once to the shown precision, so 59999.6 ms is 1:00.000 and not 0:60.000
+    let (total_seconds, millis) = match style {
+        TimeStyle::Precise => {
+            let total_ms = ms.round() as u64;
+            (total_ms / 1000, Some(total_ms % 1000))
+        }
+        _ => ((ms / 1000.0).floor() as u64, None),
+    };
+    let (hours, minutes, seconds) = (total_seconds / 3600, total_seconds / 60 % 60, total_seconds % 60);
+    let clock = if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    };
+    match millis {
+        Some(millis) => format!("{clock}.{millis:03}"),
+        None => clock,
+    }
+}
+
+/// Returns the index of the item active at `time`: the last one with `start_time <= time`, or None
+/// before the first. Items starting at the same time resolve to the last of them, like later points
+/// at the same time override earlier ones in a chart.
+///
+/// ```
+/// use vsrg_renderer::{index_at_time, HasStartTime, Time};
+///
+/// struct Point(Time);
+///
+/// impl HasStartTime for Point {
+///     fn start_time(&self) -> Time {
+///         self.0
+///     }
+/// }
+///
+/// let points = [Point(0.0), Point(100.0), Point(100.0), Point(250.0)];
+/// assert_eq!(index_at_time(&points, -5.0), None);
+/// assert_eq!(index_at_time(&points, 50.0), Some(0));
+/// // two points at 100 ms, the second one wins
+/// assert_eq!(index_at_time(&points, 100.0), Some(2));
+/// assert_eq!(index_at_time(&points, 249.9), Some(2));
+/// assert_eq!(index_at_time(&points, 1000.0), Some(3));
+/// ```
 pub fn index_at_time<T: HasStartTime>(list: &[T], time: Time) -> Option<usize> {
     match list.binary_search_by(|item| item.start_time().partial_cmp(&time).unwrap()) {
         Ok(mut idx) => {
@@ -132,12 +450,93 @@ pub fn index_at_time<T: HasStartTime>(list: &[T], time: Time) -> Option<usize> {
     }
 }
 
-// returns currently active item (start_time <= time)
+/// Returns the item active at `time`, see [`index_at_time`].
+///
+/// ```
+/// use vsrg_renderer::{map::TimingPoint, object_at_time};
+///
+/// let points = [
+///     TimingPoint { start_time: 0.0, bpm: 120.0, time_signature: None, hidden: false },
+///     TimingPoint { start_time: 1000.0, bpm: 180.0, time_signature: None, hidden: false },
+/// ];
+/// assert!(object_at_time(&points, -1.0).is_none());
+/// assert_eq!(object_at_time(&points, 999.0).map(|point| point.bpm), Some(120.0));
+/// assert_eq!(object_at_time(&points, 1000.0).map(|point| point.bpm), Some(180.0));
+/// ```
 pub fn object_at_time<T: HasStartTime>(list: &[T], time: Time) -> Option<&T> {
     index_at_time(list, time).map(|i| &list[i])
 }
 
 // sorts a vector of items by their start time
 pub fn sort_by_start_time<T: HasStartTime>(items: &mut [T]) {
-    items.sort_by(|a, b| a.start_time().partial_cmp(&b.start_time()).unwrap());
+    // stable, so items at the same time keep their chart order
+    items.sort_by(|a, b| a.start_time().total_cmp(&b.start_time()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_boundary_judges_inside_and_not_one_ms_past() {
+        let windows = JudgementWindows::symmetric(JUDGEMENTS);
+        for (index, judgement) in JUDGEMENTS.iter().enumerate() {
+            let next = JUDGEMENTS.get(index + 1).map(|judgement| judgement.kind);
+            // early: past the miss window is a ghost tap
+            assert_eq!(windows.judge(judgement.window - 1.0), Some(judgement.kind));
+            assert_eq!(windows.judge(judgement.window), Some(judgement.kind));
+            assert_eq!(windows.judge(judgement.window + 1.0), next);
+            // late: past the okay window is left to be auto missed
+            let late_past = next.filter(|&kind| kind != JudgementType::Miss);
+            if judgement.kind != JudgementType::Miss {
+                assert_eq!(windows.judge(-(judgement.window - 1.0)), Some(judgement.kind));
+                assert_eq!(windows.judge(-judgement.window), Some(judgement.kind));
+                assert_eq!(windows.judge(-(judgement.window + 1.0)), late_past);
+            }
+        }
+        assert_eq!(windows.judge(0.0), Some(JudgementType::Marvelous));
+    }
+
+    #[test]
+    fn early_and_late_windows_can_differ() {
+        let mut windows = JudgementWindows::symmetric(JUDGEMENTS);
+        windows.early.insert(JudgementType::Miss, 200.0);
+        windows.late.insert(JudgementType::Okay, 100.0);
+        assert_eq!(windows.judge(199.0), Some(JudgementType::Miss));
+        assert_eq!(windows.judge(201.0), None);
+        assert_eq!(windows.judge(-99.0), Some(JudgementType::Good));
+        assert_eq!(windows.judge(-101.0), None);
+        assert_eq!(windows.widest(), 200.0);
+    }
+
+    #[test]
+    fn times_round_once_to_what_is_shown() {
+        // 59.9995 s is a minute to the ms, not 0:60.000
+        assert_eq!(format_time(59_999.5, TimeStyle::Precise), "1:00.000");
+        assert_eq!(format_time(59_999.4, TimeStyle::Precise), "0:59.999");
+        assert_eq!(format_time(59_999.5, TimeStyle::Seconds), "60.00s");
+        // whole seconds are never rounded up
+        assert_eq!(format_time(59_999.9, TimeStyle::Clock), "0:59");
+        assert_eq!(format_time(0.0, TimeStyle::Precise), "0:00.000");
+    }
+
+    #[test]
+    fn negative_times_get_a_minus_unless_they_show_as_zero() {
+        assert_eq!(format_time(-1500.0, TimeStyle::Precise), "-0:01.500");
+        assert_eq!(format_time(-1500.0, TimeStyle::Clock), "-0:01");
+        assert_eq!(format_time(-1500.0, TimeStyle::Seconds), "-1.50s");
+        assert_eq!(format_time(-0.4, TimeStyle::Precise), "0:00.000");
+        assert_eq!(format_time(-500.0, TimeStyle::Clock), "0:00");
+        assert_eq!(format_time(-61_000.0, TimeStyle::Clock), "-1:01");
+    }
+
+    #[test]
+    fn hours_are_shown_past_an_hour() {
+        assert_eq!(format_time(3_723_456.0, TimeStyle::Precise), "1:02:03.456");
+        assert_eq!(format_time(3_723_456.0, TimeStyle::Clock), "1:02:03");
+        assert_eq!(format_time(3_723_456.0, TimeStyle::Seconds), "3723.46s");
+        assert_eq!(format_time(3_599_999.6, TimeStyle::Precise), "1:00:00.000");
+        assert_eq!(format_time(3_599_999.6, TimeStyle::Clock), "59:59");
+        assert_eq!(format_time(36_000_000.0, TimeStyle::Clock), "10:00:00");
+    }
 }
@@ -0,0 +1,70 @@
+// the C interface driven the way a host engine would: load, update, read the notes on screen,
+// send keys and free
+#![cfg(feature = "ffi")]
+
+use std::ffi::CString;
+use vsrg_renderer::ffi::{vsrg_free, vsrg_key_event, vsrg_load_map, vsrg_update, vsrg_visible_notes, VsrgNote};
+
+const MARVELOUS: i32 = 0;
+const MISS: i32 = 5;
+const NO_JUDGEMENT: i32 = -1;
+
+fn chart(name: &str) -> CString {
+    CString::new(format!("{}/golden/charts/{name}", env!("CARGO_MANIFEST_DIR"))).unwrap()
+}
+
+#[test]
+fn a_play_through_the_c_interface() {
+    // plain_4k: notes at 500 (lane 1), 750, 875, 1000, 1166.667, 1333.333 and a chord at 1500
+    unsafe {
+        let handle = vsrg_load_map(chart("plain_4k.qua").as_ptr());
+        assert!(!handle.is_null());
+        // nothing's come down into view yet
+        assert_eq!(vsrg_update(handle, 0.0), 0);
+        assert_eq!(vsrg_visible_notes(handle, std::ptr::null_mut(), 0), 0);
+
+        // the first two are on screen; asking for fewer than there are still says how many there are
+        assert_eq!(vsrg_update(handle, 450.0), 0);
+        let mut notes = [VsrgNote::default(); 8];
+        assert_eq!(vsrg_visible_notes(handle, notes.as_mut_ptr(), 1), 2);
+        assert_eq!((notes[0].index, notes[0].lane, notes[0].held, notes[0].color_index), (0, 0, 0, 0));
+        assert_eq!(notes[1].index, 0, "only one was written");
+        assert_eq!(vsrg_visible_notes(handle, notes.as_mut_ptr(), notes.len()), 2);
+        assert_eq!((notes[1].index, notes[1].lane, notes[1].color_index), (1, 1, 1));
+        assert!(notes[0].y > notes[1].y, "the later note is further up");
+
+        assert_eq!(vsrg_update(handle, 500.0), 0);
+        assert_eq!(vsrg_key_event(handle, 0, true, 500.0), MARVELOUS);
+        assert_eq!(vsrg_key_event(handle, 0, false, 550.0), NO_JUDGEMENT);
+        // a ghost tap and keys the chart doesn't have
+        assert_eq!(vsrg_key_event(handle, 3, true, 600.0), NO_JUDGEMENT);
+        assert_eq!(vsrg_key_event(handle, 4, true, 750.0), NO_JUDGEMENT);
+        assert_eq!(vsrg_key_event(handle, -1, true, 750.0), NO_JUDGEMENT);
+        assert_eq!(vsrg_key_event(handle, 1, true, 750.0), MARVELOUS);
+
+        // the rest go by unpressed, and the judged ones leave the screen
+        assert_eq!(vsrg_update(handle, 3000.0), 0);
+        assert_eq!(vsrg_visible_notes(handle, notes.as_mut_ptr(), notes.len()), 0);
+        assert_eq!(vsrg_key_event(handle, 2, true, 3000.0), NO_JUDGEMENT);
+        vsrg_free(handle);
+    }
+}
+
+#[test]
+fn late_press_is_judged_a_miss_or_worse() {
+    unsafe {
+        let handle = vsrg_load_map(chart("plain_4k.qua").as_ptr());
+        assert_eq!(vsrg_update(handle, 600.0), 0);
+        let judgement = vsrg_key_event(handle, 0, true, 600.0);
+        assert!((1..=MISS).contains(&judgement), "{judgement}");
+        vsrg_free(handle);
+    }
+}
+
+#[test]
+fn charts_that_cant_be_loaded_give_null() {
+    unsafe {
+        assert!(vsrg_load_map(chart("missing.qua").as_ptr()).is_null());
+        assert!(vsrg_load_map(chart("background.jpg").as_ptr()).is_null());
+    }
+}